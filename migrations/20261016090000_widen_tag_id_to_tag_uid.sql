@@ -0,0 +1,7 @@
+CREATE DOMAIN "tag_uid" AS varchar(20)
+CHECK ("value" ~ '^([0-9A-F]{14}|[0-9A-F]{20})$');
+
+ALTER TABLE "twag_tags"
+ALTER COLUMN "id" TYPE tag_uid;
+
+DROP DOMAIN "hex_14";
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS "twag_tag_daily" (
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "day" date NOT NULL,
+   "taps" integer NOT NULL DEFAULT 0,
+   "visitor_sketch" bytea,
+   PRIMARY KEY ("tag_id", "day")
+);
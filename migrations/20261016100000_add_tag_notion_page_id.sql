@@ -0,0 +1,5 @@
+CREATE DOMAIN "notion_page_id" AS varchar(36)
+CHECK ("value" ~ '^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$');
+
+ALTER TABLE "twag_tags"
+ADD COLUMN "notion_page_id" notion_page_id;
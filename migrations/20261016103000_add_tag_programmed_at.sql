@@ -0,0 +1,2 @@
+ALTER TABLE "twag_tags"
+ADD COLUMN "programmed_at" timestamp with time zone;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS "twag_outbox" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   "kind" text NOT NULL,
+   "payload" jsonb NOT NULL,
+   "attempts" integer NOT NULL DEFAULT 0,
+   "next_attempt_at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+   "last_error" text,
+   "created_at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+   "done_at" timestamp with time zone,
+   "dead_at" timestamp with time zone
+);
+
+CREATE INDEX IF NOT EXISTS "twag_outbox_due_idx"
+ON "twag_outbox" ("next_attempt_at")
+WHERE "done_at" IS NULL AND "dead_at" IS NULL;
@@ -0,0 +1,17 @@
+CREATE DOMAIN "lang_tag" AS varchar(35)
+CHECK ("value" ~ '^[a-z]{2,3}(-[A-Za-z0-9]{1,8})*$');
+
+CREATE TABLE IF NOT EXISTS "twag_tag_targets_i18n" (
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "lang" lang_tag NOT NULL,
+   "target_url" text NOT NULL,
+   PRIMARY KEY ("tag_id", "lang")
+);
+
+CREATE TABLE IF NOT EXISTS "twag_tag_daily_lang" (
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "day" date NOT NULL,
+   "lang" lang_tag NOT NULL,
+   "taps" integer NOT NULL DEFAULT 0,
+   PRIMARY KEY ("tag_id", "day", "lang")
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS "twag_tag_vcards" (
+   "tag_id" tag_uid NOT NULL PRIMARY KEY REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "name" text NOT NULL,
+   "org" text,
+   "phone" text,
+   "email" text,
+   "url" text,
+   "note" text
+);
@@ -0,0 +1,3 @@
+ALTER TABLE "twag_tags"
+ADD COLUMN "permanent_redirect" boolean NOT NULL DEFAULT true,
+ADD COLUMN "served_permanent_until" timestamp with time zone;
@@ -0,0 +1,21 @@
+-- Raw create submissions, kept so a mangled target can be traced to the client or to twag.
+-- "tag_id" is the id as submitted, which may not be a valid tag_uid.
+CREATE TABLE IF NOT EXISTS "twag_request_log" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   "received_at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+   "tag_id" text,
+   "method" text NOT NULL,
+   "path" text NOT NULL,
+   "content_type" text,
+   "user_agent" text,
+   "query" text NOT NULL,
+   "body" text NOT NULL,
+   "truncated" boolean NOT NULL DEFAULT false,
+   "status" smallint NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "twag_request_log_tag_idx"
+ON "twag_request_log" ("tag_id", "received_at");
+
+CREATE INDEX IF NOT EXISTS "twag_request_log_received_idx"
+ON "twag_request_log" ("received_at");
@@ -0,0 +1,7 @@
+ALTER TABLE "twag_tags"
+ADD COLUMN "label" text,
+ADD COLUMN "kit" text;
+
+CREATE INDEX IF NOT EXISTS "twag_tags_kit_idx"
+ON "twag_tags" ("kit")
+WHERE "kit" IS NOT NULL;
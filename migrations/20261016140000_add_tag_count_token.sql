@@ -0,0 +1,7 @@
+-- When set, a tap only counts if the scan URL carries it as `?ct=`; other hits still redirect
+-- but are tallied separately as "uncounted".
+ALTER TABLE "twag_tags"
+ADD COLUMN "count_token" text;
+
+ALTER TABLE "twag_tag_daily"
+ADD COLUMN "uncounted" integer NOT NULL DEFAULT 0;
@@ -0,0 +1,19 @@
+-- A stateful tag toggles between checked out and checked in on each scan, instead of only
+-- redirecting. NULL until the first scan, which checks the item out.
+ALTER TABLE "twag_tags"
+ADD COLUMN "stateful" boolean NOT NULL DEFAULT false;
+
+ALTER TABLE "twag_tags"
+ADD COLUMN "current_state" text CHECK ("current_state" IN ('checked_in', 'checked_out'));
+
+CREATE TABLE IF NOT EXISTS "twag_state_transitions" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "state" text NOT NULL CHECK ("state" IN ('checked_in', 'checked_out')),
+   "at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+   -- The salted, day-scoped visitor fingerprint, when visitor hashing is enabled
+   "actor_fingerprint" bigint
+);
+
+CREATE INDEX IF NOT EXISTS "twag_state_transitions_tag_idx"
+ON "twag_state_transitions" ("tag_id", "at" DESC);
@@ -0,0 +1,7 @@
+-- A tag in maintenance is sent to the global maintenance URL, or a built-in page when there's
+-- none. Taps during maintenance still count, and are also tallied separately.
+ALTER TABLE "twag_tags"
+ADD COLUMN "maintenance" boolean NOT NULL DEFAULT false;
+
+ALTER TABLE "twag_tag_daily"
+ADD COLUMN "during_maintenance" integer NOT NULL DEFAULT 0;
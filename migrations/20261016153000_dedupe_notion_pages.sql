@@ -0,0 +1,13 @@
+-- Keep the link on the lowest tag id wherever a page was linked more than once
+UPDATE "twag_tags" AS "t"
+SET "notion_page_id" = NULL
+WHERE EXISTS (
+   SELECT 1 FROM "twag_tags" AS "o"
+   WHERE "o"."notion_page_id" = "t"."notion_page_id" AND "o"."id" < "t"."id"
+);
+
+ALTER TABLE "twag_tags"
+ADD CONSTRAINT "twag_tags_notion_page_id_key" UNIQUE ("notion_page_id");
+
+ALTER TABLE "twag_tags"
+ADD COLUMN "notion_sync_claimed_at" timestamp with time zone;
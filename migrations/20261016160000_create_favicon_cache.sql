@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS "twag_favicons" (
+   "host" text PRIMARY KEY,
+   -- NULL when the last fetch failed
+   "png" bytea,
+   "error" text,
+   "fetched_at" timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
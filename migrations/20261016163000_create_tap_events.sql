@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS "twag_tap_events" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "tapped_at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+   "tap_count" integer,
+   "lang" text,
+   "counted" boolean NOT NULL,
+   "resolution_path" text NOT NULL CHECK ("resolution_path" IN (
+      'direct', 'language', 'maintenance', 'cache_stale', 'stateful', 'contact', 'flush'
+   )),
+   -- NULL when the client was sent to the tag's base target_url, or wasn't redirected at all
+   -- (see "response_status"); only a differing URL is stored, to keep rows small.
+   "served_target_url" text,
+   "response_status" smallint NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "twag_tap_events_tag_idx"
+ON "twag_tap_events" ("tag_id", "tapped_at" DESC);
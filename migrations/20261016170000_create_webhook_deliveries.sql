@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS "twag_webhook_deliveries" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   -- Webhooks are configured by environment rather than stored, so the endpoint identifies one.
+   "webhook_url" text NOT NULL,
+   "event" text NOT NULL,
+   "payload" jsonb NOT NULL,
+   "attempts" integer NOT NULL DEFAULT 0,
+   "next_attempt_at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+   "last_status" smallint,
+   "last_error" text,
+   "created_at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+   "delivered_at" timestamp with time zone,
+   "failed_at" timestamp with time zone
+);
+
+CREATE INDEX IF NOT EXISTS "twag_webhook_deliveries_due_idx"
+ON "twag_webhook_deliveries" ("next_attempt_at")
+WHERE "delivered_at" IS NULL AND "failed_at" IS NULL;
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS "twag_tag_targets_geo" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   -- ISO 3166-1 alpha-2 codes, uppercase; the first row (by id) listing the client's country wins
+   "countries" text[] NOT NULL CHECK (array_to_string("countries", ',') ~ '^[A-Z]{2}(,[A-Z]{2})*$'),
+   "target_url" text NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "twag_tag_targets_geo_tag_idx"
+ON "twag_tag_targets_geo" ("tag_id");
+
+-- Only recorded while TWAG_GEOIP_DB is set
+ALTER TABLE "twag_tap_events" ADD COLUMN IF NOT EXISTS "country" char(2);
+
+ALTER TABLE "twag_tap_events" DROP CONSTRAINT IF EXISTS "twag_tap_events_resolution_path_check";
+ALTER TABLE "twag_tap_events" ADD CONSTRAINT "twag_tap_events_resolution_path_check"
+CHECK ("resolution_path" IN (
+   'direct', 'language', 'geo', 'maintenance', 'cache_stale', 'stateful', 'contact', 'flush'
+));
@@ -0,0 +1,18 @@
+-- Tags past their expiry date, or soft-deleted, answer scans with 410 Gone. Soft-deleted tags keep
+-- their row, so their taps and audit history stay put.
+ALTER TABLE "twag_tags"
+ADD COLUMN "expires_on" date,
+ADD COLUMN "deleted_at" timestamp with time zone;
+
+-- One row per tag per edit, e.g. each tag touched by a bulk action.
+CREATE TABLE IF NOT EXISTS "twag_tag_audit" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "action" text NOT NULL CHECK ("action" IN ('retarget', 'set_kit', 'set_expiry', 'delete')),
+   "before" text,
+   "after" text,
+   "at" timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS "twag_tag_audit_tag_idx"
+ON "twag_tag_audit" ("tag_id", "at" DESC);
@@ -0,0 +1,17 @@
+-- Every short code a tag has ever had, so that regenerating one leaves links already shared
+-- working. "twag_tags"."short_code" is the current one, and is always also listed here.
+CREATE TABLE IF NOT EXISTS "twag_tag_aliases" (
+   "alias" text PRIMARY KEY CHECK ("alias" ~ '^[A-Za-z0-9]{1,32}$'),
+   "tag_id" tag_uid NOT NULL REFERENCES "twag_tags" ("id") ON DELETE CASCADE,
+   "created_at" timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS "twag_tag_aliases_tag_idx"
+ON "twag_tag_aliases" ("tag_id");
+
+ALTER TABLE "twag_tags"
+ADD COLUMN "short_code" text UNIQUE;
+
+-- How a hit reached the tag, when it wasn't a scan of the tag itself; e.g. 'shortlink'.
+ALTER TABLE "twag_tap_events"
+ADD COLUMN "channel" text;
@@ -0,0 +1,9 @@
+-- Edits made by something other than the operator, e.g. "notion-sync", say so.
+ALTER TABLE "twag_tag_audit"
+ADD COLUMN "actor" text NOT NULL DEFAULT 'operator';
+
+-- How far each background sync has read, so that it only looks at what changed since.
+CREATE TABLE IF NOT EXISTS "twag_sync_cursors" (
+   "name" text PRIMARY KEY,
+   "cursor" timestamp with time zone NOT NULL
+);
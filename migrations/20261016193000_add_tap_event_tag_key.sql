@@ -0,0 +1,20 @@
+-- With TWAG_REDACT_TAG_IDS, a keyed HMAC of "tag_id", so exported taps can still be grouped by tag
+-- without naming it. NULL for taps recorded while redaction was off.
+ALTER TABLE "twag_tap_events"
+ADD COLUMN "tag_key" text;
+
+-- What analytics exports read: taps keyed by "tag_key" instead of "tag_id", and without the served
+-- target, which can identify a tag as plainly as its id.
+CREATE OR REPLACE VIEW "twag_tap_events_analytics" AS
+SELECT
+   "id",
+   "tag_key",
+   "tapped_at",
+   "tap_count",
+   "lang",
+   "counted",
+   "resolution_path",
+   "response_status",
+   "country",
+   "channel"
+FROM "twag_tap_events";
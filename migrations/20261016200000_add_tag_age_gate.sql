@@ -0,0 +1,4 @@
+-- Tags whose scans first ask for an age confirmation; see `age_gate`.
+ALTER TABLE "twag_tags"
+ADD COLUMN "age_gate" boolean NOT NULL DEFAULT false,
+ADD COLUMN "age_gate_text" text;
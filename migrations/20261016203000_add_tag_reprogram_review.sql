@@ -0,0 +1,23 @@
+-- A tag whose counter dropped back near zero waits here for an admin; see `reprogram`.
+ALTER TABLE "twag_tags"
+ADD COLUMN "review_state" text NOT NULL DEFAULT 'ok'
+CHECK ("review_state" IN ('ok', 'needs_review', 'suspicious')),
+-- The highest counter seen since the drop, which accepting makes the new baseline
+ADD COLUMN "review_tap_count" integer,
+-- Whether scans get the quarantine page while the tag awaits review, rather than only once it's
+-- marked suspicious
+ADD COLUMN "quarantine_on_review" boolean NOT NULL DEFAULT false;
+
+ALTER TABLE "twag_tag_audit" DROP CONSTRAINT IF EXISTS "twag_tag_audit_action_check";
+ALTER TABLE "twag_tag_audit" ADD CONSTRAINT "twag_tag_audit_action_check"
+CHECK ("action" IN (
+   'retarget', 'set_kit', 'set_expiry', 'delete', 'reprogram_detected', 'reprogram_accepted',
+   'reprogram_rejected'
+));
+
+ALTER TABLE "twag_tap_events" DROP CONSTRAINT IF EXISTS "twag_tap_events_resolution_path_check";
+ALTER TABLE "twag_tap_events" ADD CONSTRAINT "twag_tap_events_resolution_path_check"
+CHECK ("resolution_path" IN (
+   'direct', 'language', 'geo', 'maintenance', 'cache_stale', 'stateful', 'contact', 'flush',
+   'quarantine'
+));
@@ -0,0 +1,19 @@
+-- A deleted tag waits in the trash until then, and can be restored; see `trash`.
+ALTER TABLE "twag_tags"
+ADD COLUMN "purge_after" timestamp with time zone;
+
+UPDATE "twag_tags" SET "purge_after" = current_timestamp + interval '30 days'
+WHERE "deleted_at" IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS "twag_tags_purge_idx"
+ON "twag_tags" ("purge_after") WHERE "deleted_at" IS NOT NULL;
+
+-- The record of a purge has to outlive the tag it describes
+ALTER TABLE "twag_tag_audit" DROP CONSTRAINT IF EXISTS "twag_tag_audit_tag_id_fkey";
+
+ALTER TABLE "twag_tag_audit" DROP CONSTRAINT IF EXISTS "twag_tag_audit_action_check";
+ALTER TABLE "twag_tag_audit" ADD CONSTRAINT "twag_tag_audit_action_check"
+CHECK ("action" IN (
+   'retarget', 'set_kit', 'set_expiry', 'delete', 'reprogram_detected', 'reprogram_accepted',
+   'reprogram_rejected', 'restore', 'purge'
+));
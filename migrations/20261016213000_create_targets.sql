@@ -0,0 +1,44 @@
+-- Every distinct destination, shared by the tags pointing at it; see `targets`. The tag's own
+-- "target_url" stays as a copy of its target's, so scans don't need the join.
+CREATE TABLE IF NOT EXISTS "twag_targets" (
+   "id" bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   "url" text NOT NULL,
+   -- `targets::normalize` of the URL; two URLs the same once normalized are one target
+   "normalized" text NOT NULL UNIQUE,
+   "created_at" timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
+
+-- Approximates `targets::normalize` for what's already stored: scheme and host lowercased, default
+-- ports dropped, and an empty path made '/'. Anything it misses stays a separate target until one
+-- is edited to match the other, which merges them.
+CREATE TEMPORARY TABLE "normalized_targets" AS
+SELECT "id", "target_url",
+   CASE WHEN "origin" IS NULL THEN btrim("target_url")
+   ELSE regexp_replace(regexp_replace("origin", '^(http://.*):80$', '\1'), '^(https://.*):443$', '\1')
+      || CASE WHEN "rest" LIKE '/%' THEN "rest" ELSE '/' || "rest" END
+   END AS "normalized"
+FROM (
+   SELECT "id", "target_url",
+      lower(substring(btrim("target_url") FROM '^[A-Za-z][A-Za-z0-9+.-]*://[^/?#]*')) AS "origin",
+      substring(btrim("target_url") FROM '^[A-Za-z][A-Za-z0-9+.-]*://[^/?#]*(.*)$') AS "rest"
+   FROM "twag_tags"
+) AS "split";
+
+INSERT INTO "twag_targets" ("url", "normalized")
+SELECT DISTINCT ON ("normalized") "target_url", "normalized"
+FROM "normalized_targets"
+ORDER BY "normalized", "target_url" <> "normalized", "target_url";
+
+ALTER TABLE "twag_tags"
+ADD COLUMN "target_id" bigint REFERENCES "twag_targets" ("id");
+
+UPDATE "twag_tags" AS t SET "target_id" = targets."id", "target_url" = targets."url"
+FROM "normalized_targets" AS n JOIN "twag_targets" AS targets USING ("normalized")
+WHERE t."id" = n."id";
+
+DROP TABLE "normalized_targets";
+
+ALTER TABLE "twag_tags" ALTER COLUMN "target_id" SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS "twag_tags_target_idx"
+ON "twag_tags" ("target_id");
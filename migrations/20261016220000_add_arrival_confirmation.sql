@@ -0,0 +1,27 @@
+-- Tags whose scans go through a page that reports the browser got there; see `arrival`.
+ALTER TABLE "twag_tags"
+ADD COLUMN "confirm_arrival" boolean NOT NULL DEFAULT false;
+
+-- Both NULL for taps answered without that page. Otherwise "arrival_confirmed" is false until the
+-- page's beacon lands, and stays false if it never does.
+ALTER TABLE "twag_tap_events"
+ADD COLUMN "arrival_nonce" text,
+ADD COLUMN "arrival_confirmed" boolean;
+
+CREATE INDEX IF NOT EXISTS "twag_tap_events_arrival_nonce_idx"
+ON "twag_tap_events" ("arrival_nonce") WHERE "arrival_nonce" IS NOT NULL;
+
+CREATE OR REPLACE VIEW "twag_tap_events_analytics" AS
+SELECT
+   "id",
+   "tag_key",
+   "tapped_at",
+   "tap_count",
+   "lang",
+   "counted",
+   "resolution_path",
+   "response_status",
+   "country",
+   "channel",
+   "arrival_confirmed"
+FROM "twag_tap_events";
@@ -0,0 +1,6 @@
+-- Ids whose pre-signed creation link has been used; see `provision`. Kept after the tag is purged,
+-- so the link can't create it again.
+CREATE TABLE IF NOT EXISTS "twag_provisioning_consumed" (
+   "tag_id" tag_uid PRIMARY KEY,
+   "consumed_at" timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
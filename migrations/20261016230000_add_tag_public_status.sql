@@ -0,0 +1,9 @@
+-- Tags listed on the public `/status` page, with how recently they were scanned; see
+-- `public_status`. Their tap counts are only shown with `public_show_count` set too.
+ALTER TABLE "twag_tags"
+ADD COLUMN "public_status" boolean NOT NULL DEFAULT false,
+ADD COLUMN "public_show_count" boolean NOT NULL DEFAULT false;
+
+CREATE INDEX IF NOT EXISTS "twag_tags_public_status_idx"
+ON "twag_tags" ("label")
+WHERE "public_status" AND "deleted_at" IS NULL;
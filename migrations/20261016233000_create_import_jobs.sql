@@ -0,0 +1,28 @@
+-- CSV imports of tags, worked through a batch at a time by a background task; see `import`. The
+-- file is kept on the job until it's pruned, and `next_row` is only advanced in the transaction
+-- that created that batch's tags, so a restarted instance picks up where the last commit left off.
+CREATE TABLE IF NOT EXISTS "twag_import_jobs" (
+   "id" bigserial PRIMARY KEY,
+   "created_at" timestamp with time zone NOT NULL DEFAULT now(),
+   "body" text NOT NULL,
+   "total_rows" integer NOT NULL,
+   "next_row" integer NOT NULL DEFAULT 0,
+   "created" integer NOT NULL DEFAULT 0,
+   "skipped" integer NOT NULL DEFAULT 0,
+   "failed" integer NOT NULL DEFAULT 0,
+   "finished_at" timestamp with time zone
+);
+
+CREATE INDEX IF NOT EXISTS "twag_import_jobs_unfinished_idx"
+ON "twag_import_jobs" ("id")
+WHERE "finished_at" IS NULL;
+
+-- Rows of an import that weren't created, for its error report.
+CREATE TABLE IF NOT EXISTS "twag_import_problems" (
+   "job_id" bigint NOT NULL REFERENCES "twag_import_jobs" ("id") ON DELETE CASCADE,
+   "row_number" integer NOT NULL,
+   "tag_id" text NOT NULL,
+   "outcome" text NOT NULL,
+   "reason" text NOT NULL,
+   PRIMARY KEY ("job_id", "row_number")
+);
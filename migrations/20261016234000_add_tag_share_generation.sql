@@ -0,0 +1,3 @@
+-- Signed into every stats share link made for the tag; bumping it revokes them all. See `share`.
+ALTER TABLE "twag_tags"
+ADD COLUMN "share_generation" integer NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- The physical tag a row stands for; see `tag_models`. Existing tags were made without saying.
+ALTER TABLE "twag_tags"
+ADD COLUMN "tag_model" text NOT NULL DEFAULT 'unknown'
+   CHECK ("tag_model" IN ('ntag213', 'ntag215', 'ntag216', 'ntag424', 'qr_only', 'unknown'));
@@ -0,0 +1,20 @@
+-- Taps whose client sent Global Privacy Control or Do Not Track, when `TWAG_PRIVACY_SIGNALS` honors
+-- it; see `privacy`. Their rows keep no fingerprint, country or channel, only that they happened.
+ALTER TABLE "twag_tap_events"
+ADD COLUMN "privacy_limited" boolean NOT NULL DEFAULT false;
+
+CREATE OR REPLACE VIEW "twag_tap_events_analytics" AS
+SELECT
+   "id",
+   "tag_key",
+   "tapped_at",
+   "tap_count",
+   "lang",
+   "counted",
+   "resolution_path",
+   "response_status",
+   "country",
+   "channel",
+   "arrival_confirmed",
+   "privacy_limited"
+FROM "twag_tap_events";
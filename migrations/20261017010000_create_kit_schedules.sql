@@ -0,0 +1,25 @@
+-- Windows in which a kit points all of its tags elsewhere, as for an event; see `schedule`.
+CREATE TABLE IF NOT EXISTS "twag_kit_schedules" (
+   "id" bigserial PRIMARY KEY,
+   "kit" text NOT NULL,
+   "starts_at" timestamptz NOT NULL,
+   -- The first instant the window is closed again
+   "ends_at" timestamptz NOT NULL,
+   "target_url" text NOT NULL,
+   "created_at" timestamptz NOT NULL DEFAULT now(),
+   CONSTRAINT "twag_kit_schedules_window_check" CHECK ("starts_at" < "ends_at")
+);
+
+CREATE INDEX IF NOT EXISTS "twag_kit_schedules_kit_idx"
+ON "twag_kit_schedules" ("kit", "ends_at");
+
+-- A tag that keeps to its own targets whatever its kit schedules
+ALTER TABLE "twag_tags"
+ADD COLUMN "ignore_kit_schedule" boolean NOT NULL DEFAULT false;
+
+ALTER TABLE "twag_tap_events" DROP CONSTRAINT IF EXISTS "twag_tap_events_resolution_path_check";
+ALTER TABLE "twag_tap_events" ADD CONSTRAINT "twag_tap_events_resolution_path_check"
+CHECK ("resolution_path" IN (
+   'direct', 'language', 'geo', 'maintenance', 'cache_stale', 'stateful', 'contact', 'flush',
+   'quarantine', 'schedule'
+));
@@ -0,0 +1,4 @@
+-- A decommissioned tag answers 410 Gone but keeps its counts and history, and, unlike a deleted
+-- one, is never purged
+ALTER TABLE "twag_tags"
+ADD COLUMN "enabled" boolean NOT NULL DEFAULT true;
@@ -0,0 +1,11 @@
+-- An archived tag is retired from the listing and answers 410 Gone, but keeps its row, and so its
+-- id, aliases and history, until restored; unlike a deleted one, it's never purged
+ALTER TABLE "twag_tags"
+ADD COLUMN "archived_at" timestamp with time zone;
+
+ALTER TABLE "twag_tag_audit" DROP CONSTRAINT IF EXISTS "twag_tag_audit_action_check";
+ALTER TABLE "twag_tag_audit" ADD CONSTRAINT "twag_tag_audit_action_check"
+CHECK ("action" IN (
+   'retarget', 'set_kit', 'set_expiry', 'delete', 'reprogram_detected', 'reprogram_accepted',
+   'reprogram_rejected', 'restore', 'purge', 'archive', 'unarchive'
+));
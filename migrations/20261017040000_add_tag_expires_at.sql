@@ -0,0 +1,4 @@
+-- A tag stops redirecting at this instant, for tags that should lapse at a set time rather than
+-- at the start of a day as `expires_on` does. Past it, the tag is still listed and can be edited
+ALTER TABLE "twag_tags"
+ADD COLUMN "expires_at" timestamp with time zone;
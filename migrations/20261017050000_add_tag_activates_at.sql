@@ -0,0 +1,11 @@
+-- A tag programmed ahead of time answers a "not yet active" page until this instant, counting its
+-- scans meanwhile
+ALTER TABLE "twag_tags"
+ADD COLUMN "activates_at" timestamp with time zone;
+
+ALTER TABLE "twag_tap_events" DROP CONSTRAINT IF EXISTS "twag_tap_events_resolution_path_check";
+ALTER TABLE "twag_tap_events" ADD CONSTRAINT "twag_tap_events_resolution_path_check"
+CHECK ("resolution_path" IN (
+   'direct', 'language', 'geo', 'maintenance', 'cache_stale', 'stateful', 'contact', 'flush',
+   'quarantine', 'schedule', 'pending'
+));
@@ -0,0 +1,7 @@
+-- Which redirect a tag answers with, in place of the permanent flag. Every tag starts out temporary,
+-- existing ones included: a 308 is cached for good, and clients holding one are flushed on their
+-- next revisit by way of "served_permanent_until"
+ALTER TABLE "twag_tags"
+ADD COLUMN "redirect_kind" text NOT NULL DEFAULT 'temporary'
+   CHECK ("redirect_kind" IN ('permanent', 'temporary', 'see_other')),
+DROP COLUMN "permanent_redirect";
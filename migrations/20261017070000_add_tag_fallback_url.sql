@@ -0,0 +1,12 @@
+-- Where a scan goes instead when the tag's own target is down. The target is only probed, before
+-- each redirect and for at most 800ms, for tags that opt in with "check_target"
+ALTER TABLE "twag_tags"
+ADD COLUMN "fallback_url" text,
+ADD COLUMN "check_target" boolean NOT NULL DEFAULT false;
+
+ALTER TABLE "twag_tap_events" DROP CONSTRAINT IF EXISTS "twag_tap_events_resolution_path_check";
+ALTER TABLE "twag_tap_events" ADD CONSTRAINT "twag_tap_events_resolution_path_check"
+CHECK ("resolution_path" IN (
+   'direct', 'language', 'geo', 'maintenance', 'cache_stale', 'stateful', 'contact', 'flush',
+   'quarantine', 'schedule', 'pending', 'fallback'
+));
@@ -0,0 +1,7 @@
+-- One expiry per tag: a date becomes its midnight, UTC, which is when `expires_on` took effect.
+-- A tag with both keeps whichever came first, as that's when it stopped redirecting
+UPDATE "twag_tags"
+SET "expires_at" = least("expires_at", "expires_on"::timestamp AT TIME ZONE 'UTC')
+WHERE "expires_on" IS NOT NULL;
+
+ALTER TABLE "twag_tags" DROP COLUMN "expires_on";
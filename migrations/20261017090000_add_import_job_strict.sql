@@ -0,0 +1,5 @@
+-- A strict import creates nothing from a batch with a failing row, and stops there; "stopped" marks
+-- a job finished that way rather than by reaching its last row.
+ALTER TABLE "twag_import_jobs"
+ADD COLUMN "strict" boolean NOT NULL DEFAULT false,
+ADD COLUMN "stopped" boolean NOT NULL DEFAULT false;
@@ -0,0 +1,34 @@
+-- Archived tags move out of "twag_tags" into a table of their own, with the same columns and when
+-- they were archived, and back when restored. Rows are moved by column name, so a column added to
+-- "twag_tags" must be added here too. Moving a tag out deletes what hangs off it in other tables,
+-- its aliases, translations and history, as deleting it would; a restored tag keeps only its row
+CREATE TABLE "twag_tags_archive" (LIKE "twag_tags" INCLUDING DEFAULTS INCLUDING CONSTRAINTS);
+ALTER TABLE "twag_tags_archive" DROP COLUMN "archived_at";
+ALTER TABLE "twag_tags_archive"
+ADD COLUMN "archived_at" timestamp with time zone NOT NULL DEFAULT current_timestamp,
+ADD PRIMARY KEY ("id");
+
+INSERT INTO "twag_tags_archive"
+SELECT (jsonb_populate_record(NULL::"twag_tags_archive", to_jsonb("t") - 'short_code')).*
+FROM "twag_tags" AS "t"
+WHERE "t"."archived_at" IS NOT NULL;
+
+DELETE FROM "twag_tags" WHERE "archived_at" IS NOT NULL;
+
+ALTER TABLE "twag_tags" DROP COLUMN "archived_at";
+
+-- An archived id stays taken, so that a retired tag's scans can't be claimed by a new one. No
+-- constraint spans two tables, so a trigger stands in for one, failing as a unique violation would
+CREATE FUNCTION "twag_tags_refuse_archived_id"() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+   IF EXISTS (SELECT FROM "twag_tags_archive" WHERE "id" = NEW."id") THEN
+      RAISE EXCEPTION 'Tag % is archived', NEW."id"
+      USING ERRCODE = 'unique_violation', CONSTRAINT = 'twag_tags_archived_id';
+   END IF;
+   RETURN NEW;
+END
+$$;
+
+CREATE TRIGGER "twag_tags_archived_id"
+BEFORE INSERT ON "twag_tags"
+FOR EACH ROW EXECUTE FUNCTION "twag_tags_refuse_archived_id"();
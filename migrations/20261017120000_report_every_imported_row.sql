@@ -0,0 +1,5 @@
+-- An import's report lists every row it reached, created ones too, not only those that weren't.
+ALTER TABLE "twag_import_problems" RENAME TO "twag_import_rows";
+ALTER TABLE "twag_import_rows" RENAME CONSTRAINT "twag_import_problems_pkey" TO "twag_import_rows_pkey";
+ALTER TABLE "twag_import_rows"
+RENAME CONSTRAINT "twag_import_problems_job_id_fkey" TO "twag_import_rows_job_id_fkey";
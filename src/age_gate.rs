@@ -0,0 +1,100 @@
+//! An age confirmation page in front of chosen tags. A scan of a gated tag gets the page instead of
+//! its redirect, and is only recorded once confirmed through `POST /tag/{slug}/go`. Confirming also
+//! sets one signed cookie, good for every gated tag until it expires, so later scans skip the page.
+
+use serde::Deserialize;
+
+use crate::security::CookieKey;
+use crate::stale_redirect::find_cookie;
+
+pub const COOKIE_NAME: &str = "twag_age_ok";
+/// Shown for gated tags without text of their own.
+pub const DEFAULT_TEXT: &str = "You must be 18 or older to continue.";
+pub const DEFAULT_DAYS: u32 = 30;
+/// Where declining goes, unless `TWAG_AGE_GATE_DECLINE_URL` says otherwise.
+pub const DEFAULT_DECLINE_URL: &str = "/";
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Answer {
+   Yes,
+   No,
+}
+
+/// Whether any of the `Cookie` headers holds an unexpired confirmation. Without a key nothing can
+/// have been signed, so nothing is confirmed.
+pub fn confirmed<'a>(key: Option<&CookieKey>, cookies: impl IntoIterator<Item = &'a str>, now: i64) -> bool {
+   let Some(key) = key else {
+      return false;
+   };
+   cookies
+      .into_iter()
+      .filter_map(|header| find_cookie(header, COOKIE_NAME))
+      .filter_map(|signed| key.verify(COOKIE_NAME, signed))
+      .filter_map(|expires| expires.parse::<i64>().ok())
+      .any(|expires| expires > now)
+}
+
+/// The confirmation, carrying its own expiry so that an old cookie can't be kept alive past
+/// `Max-Age` by a client that ignores it. Scoped to the whole site, as gated tags are reached
+/// through both `/tag` and `/t`.
+pub fn set_cookie(key: &CookieKey, now: i64, days: u32) -> String {
+   let max_age = i64::from(days) * SECS_PER_DAY;
+   format!(
+      "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+      COOKIE_NAME,
+      key.sign(COOKIE_NAME, &(now + max_age).to_string()),
+      max_age
+   )
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const NOW: i64 = 1_792_152_000;
+
+   fn key() -> CookieKey {
+      CookieKey::from_vars(|_| Some("0123456789abcdef".to_string()))
+         .unwrap()
+         .unwrap()
+   }
+
+   /// The `name=value` a client would send back for a `Set-Cookie`.
+   fn sent_back(set_cookie: &str) -> String { set_cookie.split(';').next().unwrap().to_string() }
+
+   #[test]
+   fn test_confirm_sets_a_cookie_good_for_every_gated_tag() {
+      let set_cookie = set_cookie(&key(), NOW, DEFAULT_DAYS);
+      assert!(set_cookie.contains("; Path=/; Max-Age=2592000; HttpOnly; SameSite=Lax"));
+      let cookie = format!("session=abc; {}", sent_back(&set_cookie));
+      assert!(confirmed(Some(&key()), [cookie.as_str()], NOW));
+      assert!(confirmed(Some(&key()), ["a=b", cookie.as_str()], NOW + 60));
+   }
+
+   #[test]
+   fn test_declining_or_never_answering_stays_unconfirmed() {
+      assert!(!confirmed(Some(&key()), [], NOW));
+      assert!(!confirmed(Some(&key()), ["session=abc"], NOW));
+      let cookie = sent_back(&set_cookie(&key(), NOW, DEFAULT_DAYS));
+      // No key to check it with
+      assert!(!confirmed(None, [cookie.as_str()], NOW));
+   }
+
+   #[test]
+   fn test_cookie_expires() {
+      let cookie = sent_back(&set_cookie(&key(), NOW, 1));
+      assert!(confirmed(Some(&key()), [cookie.as_str()], NOW + SECS_PER_DAY - 1));
+      assert!(!confirmed(Some(&key()), [cookie.as_str()], NOW + SECS_PER_DAY));
+
+      // Pushing the expiry out breaks the signature
+      let extended = cookie.replace(
+         &(NOW + SECS_PER_DAY).to_string(),
+         &(NOW + 400 * SECS_PER_DAY).to_string(),
+      );
+      assert_ne!(extended, cookie);
+      assert!(!confirmed(Some(&key()), [extended.as_str()], NOW + SECS_PER_DAY));
+   }
+}
@@ -0,0 +1,219 @@
+//! The files iOS and Android fetch to decide that the companion app, rather than the browser,
+//! opens scan URLs. Only `/tag/*` is claimed; every other page stays in the browser.
+
+use lazy_regex::regex_is_match;
+use serde_json::{json, Value};
+
+/// Paths handed to the app. Android reads these from the app's own manifest instead.
+const TAG_PATHS: &str = "/tag/*";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppleApp {
+   pub team_id: String,
+   pub bundle_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndroidApp {
+   pub package: String,
+   /// SHA-256 fingerprints of the signing certificates, as `AB:CD:…`.
+   pub fingerprints: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppLinks {
+   pub apple: Option<AppleApp>,
+   pub android: Option<AndroidApp>,
+}
+
+/// Uppercases a certificate fingerprint and checks it's 32 colon-separated bytes, as `keytool`
+/// and the Play Console print them.
+pub fn parse_fingerprint(raw: &str) -> Result<String, String> {
+   let fingerprint = raw.trim().to_ascii_uppercase();
+   match regex_is_match!(r"^[0-9A-F]{2}(?::[0-9A-F]{2}){31}$", &fingerprint) {
+      true => Ok(fingerprint),
+      false => Err(format!(
+         "Invalid certificate fingerprint '{}': expected 32 colon-separated hex bytes",
+         raw
+      )),
+   }
+}
+
+impl AppLinks {
+   /// Each platform is enabled by setting all of its variables: `TWAG_APPLE_TEAM_ID` and
+   /// `TWAG_APPLE_BUNDLE_ID`, or `TWAG_ANDROID_PACKAGE` and `TWAG_ANDROID_CERT_FINGERPRINTS`
+   /// (comma-separated).
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+      let var = |name: &str| var(name).map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+
+      let apple = match (var("TWAG_APPLE_TEAM_ID"), var("TWAG_APPLE_BUNDLE_ID")) {
+         (Some(team_id), Some(bundle_id)) => {
+            if !regex_is_match!(r"^[A-Z0-9]{10}$", &team_id) {
+               return Err(format!(
+                  "Invalid TWAG_APPLE_TEAM_ID '{}': expected 10 uppercase letters and digits",
+                  team_id
+               ));
+            }
+            if !regex_is_match!(r"^[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)+$", &bundle_id) {
+               return Err(format!("Invalid TWAG_APPLE_BUNDLE_ID '{}'", bundle_id));
+            }
+            Some(AppleApp { team_id, bundle_id })
+         }
+         (None, None) => None,
+         _ => return Err("TWAG_APPLE_TEAM_ID and TWAG_APPLE_BUNDLE_ID must be set together".to_string()),
+      };
+
+      let android = match (var("TWAG_ANDROID_PACKAGE"), var("TWAG_ANDROID_CERT_FINGERPRINTS")) {
+         (Some(package), Some(fingerprints)) => {
+            if !regex_is_match!(r"^[A-Za-z][A-Za-z0-9_]*(?:\.[A-Za-z][A-Za-z0-9_]*)+$", &package) {
+               return Err(format!("Invalid TWAG_ANDROID_PACKAGE '{}'", package));
+            }
+            let fingerprints = fingerprints
+               .split(',')
+               .map(parse_fingerprint)
+               .collect::<Result<Vec<_>, _>>()
+               .map_err(|e| format!("TWAG_ANDROID_CERT_FINGERPRINTS: {}", e))?;
+            Some(AndroidApp { package, fingerprints })
+         }
+         (None, None) => None,
+         _ => return Err("TWAG_ANDROID_PACKAGE and TWAG_ANDROID_CERT_FINGERPRINTS must be set together".to_string()),
+      };
+
+      Ok(AppLinks { apple, android })
+   }
+}
+
+/// `/.well-known/apple-app-site-association`, in both the iOS 13+ form (`appIDs`, `components`)
+/// and the older one (`appID`, `paths`) that earlier versions still read.
+pub fn apple_app_site_association(app: &AppleApp) -> Value {
+   let app_id = format!("{}.{}", app.team_id, app.bundle_id);
+   json!({
+      "applinks": {
+         "apps": [],
+         "details": [{
+            "appIDs": [app_id],
+            "components": [{ "/": TAG_PATHS }],
+            "appID": app_id,
+            "paths": [TAG_PATHS],
+         }],
+      },
+   })
+}
+
+/// `/.well-known/assetlinks.json`.
+pub fn asset_links(app: &AndroidApp) -> Value {
+   json!([{
+      "relation": ["delegate_permission/common.handle_all_urls"],
+      "target": {
+         "namespace": "android_app",
+         "package_name": app.package,
+         "sha256_cert_fingerprints": app.fingerprints,
+      },
+   }])
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const FINGERPRINT: &str =
+      "14:6D:E9:83:C5:73:06:50:D8:EE:B9:95:2F:34:FC:64:16:A0:83:42:E6:1D:BE:A8:8A:04:96:B2:3F:CF:44:E5";
+
+   fn vars(pairs: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+      move |name| pairs.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+   }
+
+   #[test]
+   fn test_disabled_without_vars() {
+      assert_eq!(AppLinks::from_vars(vars(&[])).unwrap(), AppLinks::default());
+   }
+
+   #[test]
+   fn test_apple_association_structure() {
+      let links = AppLinks::from_vars(vars(&[
+         ("TWAG_APPLE_TEAM_ID", "ABCDE12345"),
+         ("TWAG_APPLE_BUNDLE_ID", "ws.xz.twag"),
+      ]))
+      .unwrap();
+      assert_eq!(links.android, None);
+      assert_eq!(
+         apple_app_site_association(&links.apple.unwrap()),
+         serde_json::from_str::<Value>(
+            r#"{
+               "applinks": {
+                  "apps": [],
+                  "details": [{
+                     "appIDs": ["ABCDE12345.ws.xz.twag"],
+                     "components": [{"/": "/tag/*"}],
+                     "appID": "ABCDE12345.ws.xz.twag",
+                     "paths": ["/tag/*"]
+                  }]
+               }
+            }"#
+         )
+         .unwrap()
+      );
+   }
+
+   #[test]
+   fn test_asset_links_structure() {
+      let links = AppLinks::from_vars(vars(&[
+         ("TWAG_ANDROID_PACKAGE", "ws.xz.twag"),
+         (
+            "TWAG_ANDROID_CERT_FINGERPRINTS",
+            "146de983c5730650d8eeb9952f34fc6416a08342e61dbea88a0496b23fcf44e5, \
+             14:6d:e9:83:c5:73:06:50:d8:ee:b9:95:2f:34:fc:64:16:a0:83:42:e6:1d:be:a8:8a:04:96:b2:3f:cf:44:e5",
+         ),
+      ]));
+      assert!(links.is_err(), "fingerprints without colons are refused");
+
+      let links = AppLinks::from_vars(vars(&[
+         ("TWAG_ANDROID_PACKAGE", "ws.xz.twag"),
+         (
+            "TWAG_ANDROID_CERT_FINGERPRINTS",
+            "14:6d:e9:83:c5:73:06:50:d8:ee:b9:95:2f:34:fc:64:16:a0:83:42:e6:1d:be:a8:8a:04:96:b2:3f:cf:44:e5",
+         ),
+      ]))
+      .unwrap();
+      assert_eq!(
+         asset_links(&links.android.unwrap()),
+         serde_json::from_str::<Value>(&format!(
+            r#"[{{
+               "relation": ["delegate_permission/common.handle_all_urls"],
+               "target": {{
+                  "namespace": "android_app",
+                  "package_name": "ws.xz.twag",
+                  "sha256_cert_fingerprints": ["{FINGERPRINT}"]
+               }}
+            }}]"#
+         ))
+         .unwrap()
+      );
+   }
+
+   #[test]
+   fn test_fingerprint_format() {
+      assert_eq!(parse_fingerprint(&FINGERPRINT.to_lowercase()).unwrap(), FINGERPRINT);
+      for raw in [
+         "",
+         "14:6D:E9",
+         &FINGERPRINT[3..],
+         FINGERPRINT.replace(':', "-").as_str(),
+         format!("{FINGERPRINT}:00").as_str(),
+         FINGERPRINT.replace("14:", "1G:").as_str(),
+      ] {
+         assert!(parse_fingerprint(raw).is_err(), "{raw} should be refused");
+      }
+   }
+
+   #[test]
+   fn test_partial_config_is_refused() {
+      assert!(AppLinks::from_vars(vars(&[("TWAG_APPLE_TEAM_ID", "ABCDE12345")])).is_err());
+      assert!(AppLinks::from_vars(vars(&[("TWAG_ANDROID_PACKAGE", "ws.xz.twag")])).is_err());
+      assert!(AppLinks::from_vars(vars(&[
+         ("TWAG_APPLE_TEAM_ID", "abcde12345"),
+         ("TWAG_APPLE_BUNDLE_ID", "ws.xz.twag"),
+      ]))
+      .is_err());
+   }
+}
@@ -0,0 +1,191 @@
+//! Confirmed arrivals, for tags where a redirect that goes nowhere needs noticing. A 302 says
+//! nothing about whether the scanner's browser got anywhere, so scans of an opted-in tag get a
+//! page instead, which refreshes straight on to the target and loads a tiny beacon on the way.
+//! The beacon marks the tap as arrived; taps whose beacon never lands are the interrupted ones.
+//!
+//! The page costs an extra round trip, so it's only served for tags with `confirm_arrival` set,
+//! and only with `TWAG_COOKIE_SECRET` set to sign the beacon's token with.
+
+use sqlx::PgConnection;
+
+use crate::models::TagUid;
+use crate::security::CookieKey;
+use crate::short_code::CodeRng;
+
+/// How long after the scan a beacon still counts. A browser that took longer than this to load a
+/// one-line page didn't get on to the target promptly either.
+pub const WINDOW_SECS: i64 = 60;
+/// Signed along with the token, so a token can't be passed off as a cookie or the reverse.
+const TOKEN_PURPOSE: &str = "twag_arrival";
+/// How far the clocks of two instances may disagree about when a token was issued.
+const CLOCK_SKEW_SECS: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Refused {
+   #[error("Token is malformed or not signed by this server")]
+   Invalid,
+   #[error("Token was issued for another tag")]
+   OtherTag,
+   #[error("Token arrived after the window closed")]
+   Expired,
+}
+
+/// Identifies one tap among its tag's, as `twag_tap_events.arrival_nonce`. Only unique, not
+/// secret: the token around it is what can't be forged.
+pub fn nonce() -> String { format!("{:016x}", CodeRng::from_entropy().next_u64()) }
+
+/// `{id}.{nonce}.{issued}.{signature}`, for `/tag/{id}/arrived?t=`.
+pub fn token(key: &CookieKey, id: &TagUid, nonce: &str, now: i64) -> String {
+   key.sign(TOKEN_PURPOSE, &format!("{}.{}.{}", id, nonce, now))
+}
+
+/// The nonce of the tap a beacon for tag `id` is confirming.
+pub fn check<'a>(key: &CookieKey, id: &TagUid, token: &'a str, now: i64) -> Result<&'a str, Refused> {
+   let value = key.verify(TOKEN_PURPOSE, token).ok_or(Refused::Invalid)?;
+   let mut parts = value.split('.');
+   let (Some(tag), Some(nonce), Some(issued), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+      return Err(Refused::Invalid);
+   };
+   let issued: i64 = issued.parse().map_err(|_| Refused::Invalid)?;
+   if tag != id.to_string() {
+      return Err(Refused::OtherTag);
+   }
+   if issued > now + CLOCK_SKEW_SECS {
+      return Err(Refused::Invalid);
+   }
+   if now - issued >= WINDOW_SECS {
+      return Err(Refused::Expired);
+   }
+   Ok(nonce)
+}
+
+/// Marks the tap as arrived, returning whether it was waiting to be. A tap is only confirmed once,
+/// so replaying a beacon changes nothing.
+pub async fn confirm(conn: &mut PgConnection, id: &TagUid, nonce: &str) -> Result<bool, sqlx::Error> {
+   let result = sqlx::query!(
+      r#"UPDATE twag_tap_events SET arrival_confirmed = true
+         WHERE tag_id = $1 AND arrival_nonce = $2 AND arrival_confirmed = false
+            AND tapped_at > current_timestamp - make_interval(secs => $3)"#,
+      id as &TagUid,
+      nonce,
+      WINDOW_SECS as f64,
+   )
+   .execute(conn)
+   .await?;
+   Ok(result.rows_affected() > 0)
+}
+
+/// Of a tag's taps answered with the arrival page, how many were confirmed. Taps still inside
+/// their window are left out, as their beacon may yet land.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+   pub confirmed: i64,
+   pub settled: i64,
+}
+
+impl Rate {
+   pub fn interrupted(&self) -> i64 { self.settled - self.confirmed }
+
+   /// Rounded down, so 199 of 200 doesn't read as all of them; `None` before any tap settles.
+   pub fn percent(&self) -> Option<i64> { (self.settled > 0).then(|| self.confirmed * 100 / self.settled) }
+}
+
+/// `None` if none of the tag's taps were answered with the arrival page.
+pub async fn rate(conn: &mut PgConnection, id: &TagUid) -> Result<Option<Rate>, sqlx::Error> {
+   let row = sqlx::query!(
+      r#"SELECT count(*) FILTER (WHERE arrival_confirmed) AS "confirmed!",
+            count(*) FILTER (WHERE arrival_confirmed
+               OR tapped_at <= current_timestamp - make_interval(secs => $2)) AS "settled!",
+            count(*) AS "tracked!"
+         FROM twag_tap_events WHERE tag_id = $1 AND arrival_confirmed IS NOT NULL"#,
+      id as &TagUid,
+      WINDOW_SECS as f64,
+   )
+   .fetch_one(conn)
+   .await?;
+   Ok((row.tracked > 0).then_some(Rate {
+      confirmed: row.confirmed,
+      settled: row.settled,
+   }))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const NOW: i64 = 1_792_152_000;
+
+   fn key(secret: &str) -> CookieKey { CookieKey::from_vars(|_| Some(secret.to_string())).unwrap().unwrap() }
+
+   fn uid(id: &str) -> TagUid { id.parse().unwrap() }
+
+   #[test]
+   fn test_token_round_trips() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let token = token(&key, &id, "00c0ffee00c0ffee", NOW);
+      assert!(token.starts_with("055B88A23C1250.00c0ffee00c0ffee.1792152000."));
+      assert_eq!(check(&key, &id, &token, NOW), Ok("00c0ffee00c0ffee"));
+      assert_eq!(check(&key, &id, &token, NOW + WINDOW_SECS - 1), Ok("00c0ffee00c0ffee"));
+   }
+
+   #[test]
+   fn test_forged_tokens_are_refused() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let token = token(&key, &id, "00c0ffee00c0ffee", NOW);
+
+      let other_key = super::key("fedcba9876543210");
+      assert_eq!(check(&other_key, &id, &token, NOW), Err(Refused::Invalid));
+      let postdated = token.replace("1792152000", "1792159999");
+      assert_eq!(check(&key, &id, &postdated, NOW), Err(Refused::Invalid));
+      // The same value signed as a cookie
+      let cookie = key.sign("twag_age_ok", "055B88A23C1250.00c0ffee00c0ffee.1792152000");
+      assert_eq!(check(&key, &id, &cookie, NOW), Err(Refused::Invalid));
+      for garbage in ["", ".", "...", "%00", "055B88A23C1250", &"a".repeat(10_000)] {
+         assert_eq!(check(&key, &id, garbage, NOW), Err(Refused::Invalid), "{garbage:?}");
+      }
+
+      assert_eq!(check(&key, &uid("04A1B2C3D4E5F6"), &token, NOW), Err(Refused::OtherTag));
+   }
+
+   #[test]
+   fn test_tokens_from_the_future_are_refused() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let token = token(&key, &id, "00c0ffee00c0ffee", NOW + CLOCK_SKEW_SECS);
+      assert!(check(&key, &id, &token, NOW).is_ok());
+      let token = super::token(&key, &id, "00c0ffee00c0ffee", NOW + 3600);
+      assert_eq!(check(&key, &id, &token, NOW), Err(Refused::Invalid));
+   }
+
+   #[test]
+   fn test_beacons_after_the_window_leave_the_tap_unconfirmed() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let token = token(&key, &id, "00c0ffee00c0ffee", NOW);
+      assert_eq!(check(&key, &id, &token, NOW + WINDOW_SECS), Err(Refused::Expired));
+      assert_eq!(check(&key, &id, &token, NOW + 86_400), Err(Refused::Expired));
+   }
+
+   #[test]
+   fn test_rate() {
+      let rate = Rate {
+         confirmed: 199,
+         settled: 200,
+      };
+      assert_eq!(rate.percent(), Some(99));
+      assert_eq!(rate.interrupted(), 1);
+      // Every tap still inside its window
+      let pending = Rate {
+         confirmed: 0,
+         settled: 0,
+      };
+      assert_eq!(pending.percent(), None);
+   }
+
+   #[test]
+   fn test_nonces_differ() {
+      assert_ne!(nonce(), nonce());
+   }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use tracing::{info, warn};
+
+use crate::models::{TagUid, TagUidError};
+use crate::pool::ScalingPool;
+
+/// The result of checking stored tag ids against the `TagUid` invariants. The `tag_uid` domain
+/// should make violations impossible, but rows written while it was looser (or with constraints
+/// bypassed) are never matched by lookups.
+#[derive(Debug, Default)]
+pub struct IdAudit {
+   /// Rows that only need their case normalized, mapped to their normalized id.
+   pub fixable: Vec<(String, TagUid)>,
+   /// Rows that would normalize to the same id as another row, grouped by that id. These need
+   /// merging by hand rather than an in-place rename.
+   pub collisions: BTreeMap<TagUid, Vec<String>>,
+   /// Rows that aren't a valid tag UID in any case.
+   pub invalid: Vec<(String, TagUidError)>,
+}
+
+impl IdAudit {
+   pub fn is_clean(&self) -> bool { self.fixable.is_empty() && self.collisions.is_empty() && self.invalid.is_empty() }
+}
+
+pub fn audit_ids(stored: &[String]) -> IdAudit {
+   let mut by_normalized: BTreeMap<TagUid, Vec<&String>> = BTreeMap::new();
+   let mut audit = IdAudit::default();
+
+   for raw in stored {
+      match TagUid::new(raw.to_ascii_uppercase()) {
+         Ok(normalized) => by_normalized.entry(normalized).or_default().push(raw),
+         Err(e) => audit.invalid.push((raw.clone(), e)),
+      }
+   }
+
+   for (normalized, raws) in by_normalized {
+      match raws.as_slice() {
+         [raw] if normalized == **raw => {}
+         [raw] => audit.fixable.push(((*raw).clone(), normalized)),
+         _ => {
+            audit.collisions.insert(normalized, raws.into_iter().cloned().collect());
+         }
+      }
+   }
+   audit
+}
+
+impl fmt::Display for IdAudit {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      if self.is_clean() {
+         return writeln!(f, "All tag ids are valid.");
+      }
+      for (raw, normalized) in &self.fixable {
+         writeln!(f, "fixable: '{}' -> '{}'", raw, normalized)?;
+      }
+      for (normalized, raws) in &self.collisions {
+         writeln!(f, "collision: {} <- {}", normalized, raws.join(", "))?;
+      }
+      for (raw, e) in &self.invalid {
+         writeln!(f, "invalid: '{}': {}", raw, e)?;
+      }
+      Ok(())
+   }
+}
+
+pub async fn load_audit(pool: &ScalingPool) -> Result<IdAudit, sqlx::Error> {
+   let stored = sqlx::query_scalar!(r#"SELECT id::text AS "id!" FROM twag_tags ORDER BY id"#)
+      .fetch_all(&pool.get())
+      .await?;
+   Ok(audit_ids(&stored))
+}
+
+/// Normalizes the case of every fixable row. Collisions and invalid rows are left untouched.
+pub async fn apply_fixes(pool: &ScalingPool, audit: &IdAudit) -> Result<(), sqlx::Error> {
+   let mut tx = pool.get().begin().await?;
+   for (raw, normalized) in &audit.fixable {
+      sqlx::query!(
+         "UPDATE twag_tags SET id = $2::tag_uid WHERE id::text = $1",
+         raw,
+         normalized as &TagUid,
+      )
+      .execute(&mut *tx)
+      .await?;
+      info!(from = raw, to = %normalized, "Normalized tag id");
+   }
+   tx.commit().await
+}
+
+/// The `TWAG_MODE=audit-ids` entry point: prints the audit and, with `TWAG_AUDIT_FIX=true`,
+/// applies the fixable renames.
+pub async fn run(pool: &ScalingPool, fix: bool) -> Result<(), sqlx::Error> {
+   let audit = load_audit(pool).await?;
+   print!("{}", audit);
+   if !audit.collisions.is_empty() {
+      warn!(count = audit.collisions.len(), "Colliding tag ids need merging by hand");
+   }
+   if fix && !audit.fixable.is_empty() {
+      apply_fixes(pool, &audit).await?;
+      println!("Normalized {} tag id(s).", audit.fixable.len());
+   }
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn uid(s: &str) -> TagUid { s.parse().unwrap() }
+
+   #[test]
+   fn test_audit_clean() {
+      let stored = vec!["055B88A23C1250".to_string(), "055B88A23C1250AA01BB".to_string()];
+      let audit = audit_ids(&stored);
+      assert!(audit.is_clean());
+      assert_eq!(audit.to_string(), "All tag ids are valid.\n");
+   }
+
+   #[test]
+   fn test_audit_finds_lowercase_rows() {
+      let stored = vec!["055b88a23c1250".to_string(), "04A1B2C3D4E5F6".to_string()];
+      let audit = audit_ids(&stored);
+      assert_eq!(audit.fixable, [("055b88a23c1250".to_string(), uid("055B88A23C1250"))]);
+      assert!(audit.collisions.is_empty());
+      assert!(audit.invalid.is_empty());
+   }
+
+   #[test]
+   fn test_audit_flags_case_collisions_instead_of_fixing() {
+      let stored = vec![
+         "055B88A23C1250".to_string(),
+         "055b88a23c1250".to_string(),
+         "04a1b2c3d4e5f6".to_string(),
+         "04A1b2C3d4E5f6".to_string(),
+      ];
+      let audit = audit_ids(&stored);
+      assert!(audit.fixable.is_empty());
+      assert_eq!(audit.collisions.len(), 2);
+      assert_eq!(
+         audit.collisions[&uid("055B88A23C1250")],
+         ["055B88A23C1250", "055b88a23c1250"]
+      );
+   }
+
+   #[test]
+   fn test_audit_reports_invalid_rows() {
+      let stored = vec![
+         "055B88A23C125".to_string(),
+         "055B88A23C12ZZ".to_string(),
+         "".to_string(),
+      ];
+      let audit = audit_ids(&stored);
+      assert!(matches!(audit.invalid[0].1, TagUidError::InvalidLength(13)));
+      assert!(matches!(audit.invalid[1].1, TagUidError::InvalidCharacter('Z')));
+      assert!(matches!(audit.invalid[2].1, TagUidError::InvalidLength(0)));
+      assert!(audit.to_string().contains("invalid: '055B88A23C12ZZ'"));
+   }
+}
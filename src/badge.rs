@@ -0,0 +1,61 @@
+use crate::branding::HexColor;
+
+const CHAR_WIDTH: usize = 7;
+const PADDING: usize = 10;
+const LABEL_COLOR: &str = "#555";
+
+fn escape_xml(s: &str) -> String {
+   s.replace('&', "&amp;")
+      .replace('<', "&lt;")
+      .replace('>', "&gt;")
+      .replace('"', "&quot;")
+      .replace('\'', "&apos;")
+}
+
+fn text_width(s: &str) -> usize { s.chars().count() * CHAR_WIDTH + PADDING }
+
+/// A flat, shields.io-style two-part badge. Widths are estimated from character counts, which is
+/// close enough for short labels in Verdana.
+pub fn badge_svg(label: &str, value: &str, value_color: &HexColor) -> String {
+   let label_width = text_width(label);
+   let value_width = text_width(value);
+   let width = label_width + value_width;
+   let label_x = label_width / 2;
+   let value_x = label_width + value_width / 2;
+   let label = escape_xml(label);
+   let value = escape_xml(value);
+   format!(
+      r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {value}"><title>{label}: {value}</title><rect width="{label_width}" height="20" fill="{LABEL_COLOR}"/><rect x="{label_width}" width="{value_width}" height="20" fill="{value_color}"/><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{label_x}" y="14">{label}</text><text x="{value_x}" y="14">{value}</text></g></svg>"##
+   )
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_badge_snapshot() {
+      let svg = badge_svg("scanned", "42", &HexColor::new("#3b6ea5").unwrap());
+      assert_eq!(
+         svg,
+         r##"<svg xmlns="http://www.w3.org/2000/svg" width="83" height="20" role="img" aria-label="scanned: 42"><title>scanned: 42</title><rect width="59" height="20" fill="#555"/><rect x="59" width="24" height="20" fill="#3b6ea5"/><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="29" y="14">scanned</text><text x="71" y="14">42</text></g></svg>"##
+      );
+   }
+
+   #[test]
+   fn test_badge_escapes_text() {
+      let svg = badge_svg("<b>&", "\"1\"", &HexColor::new("#000").unwrap());
+      assert!(svg.contains("<text x=\"19\" y=\"14\">&lt;b&gt;&amp;</text>"));
+      assert!(svg.contains("&quot;1&quot;"));
+      assert!(!svg.contains("<b>"));
+   }
+
+   #[test]
+   fn test_badge_width_grows_with_value() {
+      let color = HexColor::new("#000").unwrap();
+      let short = badge_svg("scanned", "9", &color);
+      let long = badge_svg("scanned", "1234567", &color);
+      assert!(short.contains(r#"width="76""#));
+      assert!(long.contains(r#"width="118""#));
+   }
+}
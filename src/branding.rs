@@ -0,0 +1,82 @@
+/// A CSS hex color, `#rgb` or `#rrggbb`, normalized to lowercase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HexColor(String);
+
+#[derive(Debug, thiserror::Error)]
+pub enum HexColorError {
+   #[error("Invalid color '{0}': expected '#rgb' or '#rrggbb'")]
+   InvalidFormat(String),
+}
+
+impl HexColor {
+   pub fn new(s: impl AsRef<str>) -> Result<Self, HexColorError> {
+      let s = s.as_ref();
+      let valid = s
+         .strip_prefix('#')
+         .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()));
+      if !valid {
+         return Err(HexColorError::InvalidFormat(s.to_string()));
+      }
+      Ok(HexColor(s.to_ascii_lowercase()))
+   }
+
+   pub fn as_str(&self) -> &str { &self.0 }
+}
+
+impl std::fmt::Display for HexColor {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "{}", self.0) }
+}
+
+/// Per-deployment values rendered into every HTML page via `base.html`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branding {
+   pub site_name: String,
+   pub accent_color: HexColor,
+   pub logo_url: Option<String>,
+   pub footer_text: Option<String>,
+}
+
+impl Default for Branding {
+   fn default() -> Self {
+      Branding {
+         site_name: "twag".to_string(),
+         accent_color: HexColor("#3b6ea5".to_string()),
+         logo_url: None,
+         footer_text: None,
+      }
+   }
+}
+
+impl Branding {
+   pub fn from_env() -> Result<Self, HexColorError> {
+      let var = |name: &str| dotenvy::var(name).ok().filter(|s| !s.is_empty());
+      let default = Branding::default();
+      Ok(Branding {
+         site_name: var("TWAG_SITE_NAME").unwrap_or(default.site_name),
+         accent_color: var("TWAG_ACCENT_COLOR")
+            .map(HexColor::new)
+            .transpose()?
+            .unwrap_or(default.accent_color),
+         logo_url: var("TWAG_LOGO_URL"),
+         footer_text: var("TWAG_FOOTER_TEXT"),
+      })
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_hex_color_validation() {
+      assert_eq!(HexColor::new("#AABBCC").unwrap().as_str(), "#aabbcc");
+      assert_eq!(HexColor::new("#fc0").unwrap().as_str(), "#fc0");
+
+      assert!(HexColor::new("aabbcc").is_err());
+      assert!(HexColor::new("#aabbc").is_err());
+      assert!(HexColor::new("#aabbccdd").is_err());
+      assert!(HexColor::new("#ggg").is_err());
+      assert!(HexColor::new("red").is_err());
+      assert!(HexColor::new("#fff;}body{").is_err());
+   }
+}
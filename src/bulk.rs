@@ -0,0 +1,468 @@
+//! Actions on a selection of tags from the listing. A change is applied to every selected tag in
+//! one transaction, or to none of them; each affected tag gets its own `twag_tag_audit` row.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use chrono::NaiveDate;
+use serde::de::{value::StrDeserializer, IntoDeserializer};
+use serde::Deserialize;
+
+use crate::kit::{self, RowResult};
+use crate::listing::TagRow;
+use crate::models::TagUid;
+use crate::taps::csv_field;
+
+/// Ids accepted per request.
+pub const MAX_BULK_IDS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionName {
+   Retarget,
+   #[serde(alias = "set_group")]
+   SetKit,
+   SetExpiry,
+   Delete,
+   Export,
+}
+
+/// `POST /api/tags/bulk` as JSON, or `POST /tags/bulk` as a form (see `from_pairs`). Only the
+/// field the action needs is read.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BulkRequest {
+   pub action: ActionName,
+   #[serde(default)]
+   pub ids: Vec<String>,
+   pub target_url: Option<String>,
+   pub kit: Option<String>,
+   /// `YYYY-MM-DD`, expiring at its midnight, UTC; blank clears the expiry.
+   pub expires_on: Option<String>,
+   #[serde(default)]
+   pub confirm: bool,
+}
+
+impl BulkRequest {
+   /// The listing's form submits one `ids` per checked tag, `confirm=yes` from the confirmation
+   /// page, and the action as the value of whichever button was pressed.
+   pub fn from_pairs(pairs: &[(String, String)]) -> Result<Self, Rejected> {
+      let mut action = None;
+      let mut request = BulkRequest {
+         action: ActionName::Export,
+         ids: Vec::new(),
+         target_url: None,
+         kit: None,
+         expires_on: None,
+         confirm: false,
+      };
+      for (key, value) in pairs {
+         match key.as_str() {
+            "action" => {
+               let de: StrDeserializer<'_, serde::de::value::Error> = value.as_str().into_deserializer();
+               action = Some(ActionName::deserialize(de).map_err(|_| Rejected::UnknownAction(value.clone()))?);
+            }
+            "ids" => request.ids.push(value.clone()),
+            "target_url" => request.target_url = Some(value.clone()),
+            "kit" => request.kit = Some(value.clone()),
+            "expires_on" => request.expires_on = Some(value.clone()),
+            "confirm" => request.confirm = value == "yes",
+            _ => {}
+         }
+      }
+      request.action = action.ok_or_else(|| Rejected::UnknownAction(String::new()))?;
+      Ok(request)
+   }
+}
+
+/// A validated action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+   Retarget(String),
+   /// `None` takes the tags out of any kit.
+   SetKit(Option<String>),
+   /// `None` clears the expiry.
+   SetExpiry(Option<NaiveDate>),
+   /// Soft-deletes; the rows, taps and audit history are kept.
+   Delete,
+   Export,
+}
+
+impl Action {
+   /// As recorded in `twag_tag_audit.action`.
+   pub fn name(&self) -> &'static str {
+      match self {
+         Action::Retarget(_) => "retarget",
+         Action::SetKit(_) => "set_kit",
+         Action::SetExpiry(_) => "set_expiry",
+         Action::Delete => "delete",
+         Action::Export => "export",
+      }
+   }
+
+   /// Overwriting targets and deleting can't be undone from the listing, so both are only carried
+   /// out once confirmed.
+   pub fn needs_confirmation(&self) -> bool { matches!(self, Action::Retarget(_) | Action::Delete) }
+
+   /// The form field carrying the new value, and that value, for resubmitting after confirmation.
+   pub fn field(&self) -> Option<(&'static str, String)> {
+      match self {
+         Action::Retarget(target_url) => Some(("target_url", target_url.clone())),
+         Action::SetKit(kit) => kit.clone().map(|kit| ("kit", kit)),
+         Action::SetExpiry(expires_on) => expires_on.map(|date| ("expires_on", date.to_string())),
+         Action::Delete | Action::Export => None,
+      }
+   }
+
+   /// The new value, for the audit log. An expiry is written as `expires_at` is read for `before`.
+   pub fn after(&self) -> Option<String> {
+      match self {
+         Action::SetExpiry(expires_on) => expires_on.map(|date| format!("{date} 00:00")),
+         _ => self.field().map(|(_, value)| value),
+      }
+   }
+
+   pub fn describe(&self) -> String {
+      match self {
+         Action::Retarget(target_url) => format!("Redirect to {}", target_url),
+         Action::SetKit(Some(kit)) => format!("Move to kit {}", kit),
+         Action::SetKit(None) => "Remove from their kit".to_string(),
+         Action::SetExpiry(Some(date)) => format!("Expire on {}", date),
+         Action::SetExpiry(None) => "Never expire".to_string(),
+         Action::Delete => "Delete".to_string(),
+         Action::Export => "Export as CSV".to_string(),
+      }
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+   pub action: Action,
+   /// Deduplicated, in the order submitted.
+   pub ids: Vec<TagUid>,
+}
+
+/// Why nothing was attempted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejected {
+   UnknownAction(String),
+   NoIds,
+   TooMany(usize),
+   Invalid(String),
+   /// Some ids don't parse; the rest are reported as not attempted.
+   Ids(Vec<RowResult>),
+}
+
+impl fmt::Display for Rejected {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         Rejected::UnknownAction(action) if action.is_empty() => write!(f, "No action was chosen"),
+         Rejected::UnknownAction(action) => write!(f, "Unknown action '{}'", action),
+         Rejected::NoIds => write!(f, "No tags were selected"),
+         Rejected::TooMany(count) => write!(f, "{} tags selected; at most {} at once", count, MAX_BULK_IDS),
+         Rejected::Invalid(error) => write!(f, "{}", error),
+         Rejected::Ids(_) => write!(f, "Some tag ids are invalid; nothing was changed"),
+      }
+   }
+}
+
+fn non_empty(s: &Option<String>) -> Option<&str> { s.as_deref().map(str::trim).filter(|s| !s.is_empty()) }
+
+pub fn plan(request: &BulkRequest, strict_idn: bool) -> Result<Plan, Rejected> {
+   let action = match request.action {
+      ActionName::Retarget => {
+         let raw = non_empty(&request.target_url).ok_or_else(|| Rejected::Invalid("Enter a target URL".to_string()))?;
+         Action::Retarget(kit::validate_target_url(raw, strict_idn).map_err(Rejected::Invalid)?)
+      }
+      ActionName::SetKit => Action::SetKit(non_empty(&request.kit).map(str::to_string)),
+      ActionName::SetExpiry => Action::SetExpiry(
+         non_empty(&request.expires_on)
+            .map(|date| {
+               NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                  .map_err(|_| Rejected::Invalid(format!("Invalid expiry date '{}'; use YYYY-MM-DD", date)))
+            })
+            .transpose()?,
+      ),
+      ActionName::Delete => Action::Delete,
+      ActionName::Export => Action::Export,
+   };
+
+   Ok(Plan {
+      action,
+      ids: parse_ids(&request.ids)?,
+   })
+}
+
+/// Parses and deduplicates submitted ids, keeping their order; also used by `/tags/trash`.
+pub fn parse_ids(ids: &[String]) -> Result<Vec<TagUid>, Rejected> {
+   let raw: Vec<&str> = ids.iter().map(|id| id.trim()).filter(|id| !id.is_empty()).collect();
+   if raw.is_empty() {
+      return Err(Rejected::NoIds);
+   }
+   if raw.len() > MAX_BULK_IDS {
+      return Err(Rejected::TooMany(raw.len()));
+   }
+   let parsed: Vec<Result<TagUid, String>> = raw
+      .iter()
+      .map(|id| id.parse::<TagUid>().map_err(|e| format!("Invalid tag id: {}", e)))
+      .collect();
+   if parsed.iter().any(Result::is_err) {
+      return Err(Rejected::Ids(
+         raw.iter()
+            .zip(parsed)
+            .map(|(raw, result)| match result {
+               Ok(id) => RowResult::not_attempted(&id.to_string()),
+               Err(error) => RowResult::rejected(raw, error),
+            })
+            .collect(),
+      ));
+   }
+
+   let mut seen = HashSet::new();
+   Ok(parsed
+      .into_iter()
+      .map(Result::unwrap)
+      .filter(|id| seen.insert(*id))
+      .collect())
+}
+
+/// A selected tag as locked at the start of the transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+   pub id: TagUid,
+   pub deleted: bool,
+   /// The value the action replaces, for the audit log.
+   pub before: Option<String>,
+}
+
+/// Every tag must exist and not be deleted for the change to go ahead; otherwise each failing id
+/// is reported and the rest are marked as not attempted, since the transaction is rolled back.
+pub fn check_found(ids: &[TagUid], found: &[Found]) -> Result<(), Vec<RowResult>> {
+   let failures: Vec<Option<RowResult>> = ids
+      .iter()
+      .map(|id| match found.iter().find(|found| found.id == *id) {
+         None => Some(RowResult::not_found(id)),
+         Some(found) if found.deleted => Some(RowResult::gone(id)),
+         Some(_) => None,
+      })
+      .collect();
+   if failures.iter().all(Option::is_none) {
+      return Ok(());
+   }
+   Err(
+      ids.iter()
+         .zip(failures)
+         .map(|(id, failure)| failure.unwrap_or_else(|| RowResult::not_attempted(&id.to_string())))
+         .collect(),
+   )
+}
+
+/// The selected tags, as `POST /tags/bulk` with `action=export` downloads them.
+pub fn csv(tags: &[TagRow]) -> String {
+   let mut csv = String::from("id,label,kit,target_url,access_count,maintenance,stateful,expires_at\r\n");
+   for tag in tags {
+      let fields = [
+         tag.id.clone(),
+         tag.label.clone().unwrap_or_default(),
+         tag.kit.clone().unwrap_or_default(),
+         tag.target_url.clone(),
+         tag.access_count.map(|c| c.to_string()).unwrap_or_default(),
+         tag.maintenance.to_string(),
+         tag.stateful.to_string(),
+         tag.expires_at.clone().unwrap_or_default(),
+      ];
+      let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+      csv.push_str(&fields.join(","));
+      csv.push_str("\r\n");
+   }
+   csv
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn uid(s: &str) -> TagUid { s.parse().unwrap() }
+
+   fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+      pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+   }
+
+   fn request(action: ActionName, ids: &[&str]) -> BulkRequest {
+      BulkRequest {
+         action,
+         ids: ids.iter().map(|id| id.to_string()).collect(),
+         target_url: None,
+         kit: None,
+         expires_on: None,
+         confirm: false,
+      }
+   }
+
+   #[test]
+   fn test_form_and_json_requests_agree() {
+      let form = BulkRequest::from_pairs(&pairs(&[
+         ("ids", "055B88A23C1250"),
+         ("ids", "04A1B2C3D4E5F6"),
+         ("target_url", "https://example.com/new"),
+         ("kit", ""),
+         ("expires_on", ""),
+         ("confirm", "yes"),
+         ("action", "retarget"),
+      ]))
+      .unwrap();
+      let json: BulkRequest = serde_json::from_str(
+         r#"{"action": "retarget", "ids": ["055B88A23C1250", "04A1B2C3D4E5F6"],
+             "target_url": "https://example.com/new", "confirm": true}"#,
+      )
+      .unwrap();
+      assert_eq!(plan(&form, false), plan(&json, false));
+      assert!(form.confirm);
+
+      let group = BulkRequest::from_pairs(&pairs(&[("action", "set_group"), ("kit", "Camera bag")])).unwrap();
+      assert_eq!(group.action, ActionName::SetKit);
+      assert_eq!(
+         BulkRequest::from_pairs(&pairs(&[("action", "drop")])),
+         Err(Rejected::UnknownAction("drop".to_string()))
+      );
+      assert_eq!(
+         BulkRequest::from_pairs(&pairs(&[("ids", "055B88A23C1250")])),
+         Err(Rejected::UnknownAction(String::new()))
+      );
+   }
+
+   #[test]
+   fn test_plan_validates_the_action() {
+      let mut retarget = request(ActionName::Retarget, &["055B88A23C1250"]);
+      assert_eq!(
+         plan(&retarget, false),
+         Err(Rejected::Invalid("Enter a target URL".to_string()))
+      );
+      retarget.target_url = Some("javascript:alert(1)".to_string());
+      assert_eq!(
+         plan(&retarget, false),
+         Err(Rejected::Invalid(
+            "Target URL must be http or https, not javascript".to_string()
+         ))
+      );
+
+      let mut expiry = request(ActionName::SetExpiry, &["055B88A23C1250"]);
+      expiry.expires_on = Some("2027-02-30".to_string());
+      assert!(matches!(plan(&expiry, false), Err(Rejected::Invalid(_))));
+      expiry.expires_on = Some("2027-02-28".to_string());
+      assert_eq!(
+         plan(&expiry, false).unwrap().action,
+         Action::SetExpiry(NaiveDate::from_ymd_opt(2027, 2, 28))
+      );
+      expiry.expires_on = Some(" ".to_string());
+      assert_eq!(plan(&expiry, false).unwrap().action, Action::SetExpiry(None));
+
+      let mut kit = request(ActionName::SetKit, &["055B88A23C1250"]);
+      kit.kit = Some("  ".to_string());
+      assert_eq!(plan(&kit, false).unwrap().action, Action::SetKit(None));
+   }
+
+   #[test]
+   fn test_plan_limits_ids() {
+      assert_eq!(plan(&request(ActionName::Delete, &[]), false), Err(Rejected::NoIds));
+      assert_eq!(
+         plan(&request(ActionName::Delete, &["", " "]), false),
+         Err(Rejected::NoIds)
+      );
+
+      let ids: Vec<String> = (0..=MAX_BULK_IDS).map(|i| format!("055B88A23C{:04X}", i)).collect();
+      let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+      assert_eq!(
+         plan(&request(ActionName::Delete, &ids), false),
+         Err(Rejected::TooMany(MAX_BULK_IDS + 1))
+      );
+      assert_eq!(
+         plan(&request(ActionName::Delete, &ids[..MAX_BULK_IDS]), false)
+            .unwrap()
+            .ids
+            .len(),
+         MAX_BULK_IDS
+      );
+   }
+
+   #[test]
+   fn test_plan_rejects_every_id_when_one_is_invalid() {
+      let Err(Rejected::Ids(results)) = plan(&request(ActionName::Delete, &["055B88A23C1250", "055B88A23C12"]), false)
+      else {
+         panic!("expected per-id results");
+      };
+      assert_eq!(results.iter().map(|r| r.status).collect::<Vec<_>>(), [424, 422]);
+   }
+
+   #[test]
+   fn test_plan_dedupes_ids() {
+      let plan = plan(
+         &request(
+            ActionName::Export,
+            &["055b88a23c1250", "04A1B2C3D4E5F6", "055B88A23C1250"],
+         ),
+         false,
+      )
+      .unwrap();
+      assert_eq!(plan.ids, [uid("055B88A23C1250"), uid("04A1B2C3D4E5F6")]);
+   }
+
+   #[test]
+   fn test_only_destructive_actions_need_confirmation() {
+      assert!(Action::Retarget("https://example.com".to_string()).needs_confirmation());
+      assert!(Action::Delete.needs_confirmation());
+      assert!(!Action::SetKit(None).needs_confirmation());
+      assert!(!Action::SetExpiry(None).needs_confirmation());
+      assert!(!Action::Export.needs_confirmation());
+   }
+
+   #[test]
+   fn test_one_missing_tag_rolls_back_the_rest() {
+      let ids = [uid("055B88A23C1250"), uid("04A1B2C3D4E5F6"), uid("04A1B2C3D4E5F7")];
+      let found = |id: &str, deleted: bool| Found {
+         id: uid(id),
+         deleted,
+         before: None,
+      };
+
+      assert_eq!(
+         check_found(
+            &ids,
+            &[
+               found("04A1B2C3D4E5F7", false),
+               found("055B88A23C1250", false),
+               found("04A1B2C3D4E5F6", false)
+            ]
+         ),
+         Ok(())
+      );
+
+      let results = check_found(&ids, &[found("055B88A23C1250", false), found("04A1B2C3D4E5F7", true)]).unwrap_err();
+      assert_eq!(results.iter().map(|r| r.status).collect::<Vec<_>>(), [424, 404, 410]);
+      assert_eq!(results[0].error, None);
+      assert_eq!(results[1].id, "04A1B2C3D4E5F6");
+   }
+
+   #[test]
+   fn test_csv_escapes_fields() {
+      let tag = TagRow {
+         id: "055B88A23C1250".to_string(),
+         label: Some("Bag, \"big\"".to_string()),
+         target_url: "https://example.com/".to_string(),
+         kit: None,
+         access_count: Some(3),
+         maintenance: false,
+         stateful: true,
+         enabled: true,
+         expires_at: Some("2027-01-01 00:00".to_string()),
+         activates_at: None,
+         last_accessed: None,
+         count_token: None,
+         review_state: "ok".to_string(),
+      };
+      assert_eq!(
+         csv(&[tag]),
+         "id,label,kit,target_url,access_count,maintenance,stateful,expires_at\r\n\
+          055B88A23C1250,\"Bag, \"\"big\"\"\",,https://example.com/,3,false,true,2027-01-01 00:00\r\n"
+      );
+   }
+}
@@ -0,0 +1,150 @@
+use axum::{
+   extract::{Request, State},
+   http::{header, Method, StatusCode},
+   middleware::Next,
+   response::{IntoResponse, Redirect, Response},
+};
+use tracing::debug;
+
+use crate::settings::SharedSettings;
+
+const EXEMPT_PATHS: &[&str] = &["/healthz"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalHost {
+   pub host: String,
+   pub allow_writes: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum HostDecision {
+   Pass,
+   Redirect(String),
+   Misdirected,
+}
+
+fn decide(canonical: &CanonicalHost, method: &Method, host: Option<&str>, path_and_query: &str) -> HostDecision {
+   let path = path_and_query.split('?').next().unwrap_or_default();
+   if EXEMPT_PATHS.contains(&path) {
+      return HostDecision::Pass;
+   }
+   match host {
+      None => HostDecision::Pass,
+      Some(host) if host.eq_ignore_ascii_case(&canonical.host) => HostDecision::Pass,
+      Some(_) if *method == Method::GET || *method == Method::HEAD => {
+         HostDecision::Redirect(format!("https://{}{}", canonical.host, path_and_query))
+      }
+      Some(_) if canonical.allow_writes => HostDecision::Pass,
+      Some(_) => HostDecision::Misdirected,
+   }
+}
+
+pub async fn enforce_canonical_host(State(settings): State<SharedSettings>, req: Request, next: Next) -> Response {
+   let settings = settings.load();
+   let Some(canonical) = settings.canonical_host.as_ref() else {
+      return next.run(req).await;
+   };
+
+   let host = req
+      .headers()
+      .get("x-forwarded-host")
+      .or_else(|| req.headers().get(header::HOST))
+      .and_then(|v| v.to_str().ok())
+      .or_else(|| req.uri().host());
+   let path_and_query = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+
+   match decide(canonical, req.method(), host, path_and_query) {
+      HostDecision::Pass => next.run(req).await,
+      HostDecision::Redirect(url) => {
+         debug!(?host, canonical_host = %canonical.host, "Redirecting to canonical host");
+         Redirect::permanent(&url).into_response()
+      }
+      HostDecision::Misdirected => StatusCode::MISDIRECTED_REQUEST.into_response(),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn canonical(allow_writes: bool) -> CanonicalHost {
+      CanonicalHost {
+         host: "xz.ws".to_string(),
+         allow_writes,
+      }
+   }
+
+   #[test]
+   fn test_canonical_host_passes_through() {
+      let c = canonical(false);
+      assert_eq!(
+         decide(&c, &Method::GET, Some("xz.ws"), "/tag/055B88A23C1250"),
+         HostDecision::Pass
+      );
+      assert_eq!(
+         decide(&c, &Method::GET, Some("XZ.WS"), "/tag/055B88A23C1250"),
+         HostDecision::Pass
+      );
+      assert_eq!(
+         decide(&c, &Method::POST, Some("xz.ws"), "/tag/create?id=055B88A23C1250"),
+         HostDecision::Pass
+      );
+      assert_eq!(
+         decide(&c, &Method::GET, None, "/tag/055B88A23C1250"),
+         HostDecision::Pass
+      );
+   }
+
+   #[test]
+   fn test_other_host_redirects_preserving_counter_and_query() {
+      let c = canonical(false);
+      assert_eq!(
+         decide(&c, &Method::GET, Some("twag.fly.dev"), "/tag/055B88A23C1250x00000F"),
+         HostDecision::Redirect("https://xz.ws/tag/055B88A23C1250x00000F".to_string())
+      );
+      assert_eq!(
+         decide(
+            &c,
+            &Method::HEAD,
+            Some("twag.fly.dev"),
+            "/tag/create?id=055B88A23C1250&tap_count=00000F"
+         ),
+         HostDecision::Redirect("https://xz.ws/tag/create?id=055B88A23C1250&tap_count=00000F".to_string())
+      );
+   }
+
+   #[test]
+   fn test_other_host_writes_are_misdirected_unless_allowed() {
+      assert_eq!(
+         decide(
+            &canonical(false),
+            &Method::POST,
+            Some("twag.fly.dev"),
+            "/tag/create?id=055B88A23C1250"
+         ),
+         HostDecision::Misdirected
+      );
+      assert_eq!(
+         decide(
+            &canonical(true),
+            &Method::POST,
+            Some("twag.fly.dev"),
+            "/tag/create?id=055B88A23C1250"
+         ),
+         HostDecision::Pass
+      );
+   }
+
+   #[test]
+   fn test_health_check_is_exempt() {
+      let c = canonical(false);
+      assert_eq!(
+         decide(&c, &Method::GET, Some("10.0.0.5:3000"), "/healthz"),
+         HostDecision::Pass
+      );
+      assert_eq!(
+         decide(&c, &Method::GET, Some("10.0.0.5:3000"), "/healthz?verbose"),
+         HostDecision::Pass
+      );
+   }
+}
@@ -0,0 +1,243 @@
+//! Failure injection, for watching the degraded paths (the redirect cache, deferred taps, outbox
+//! retries) actually engage. Only compiled with `--features chaos`, and even then inert until
+//! `TWAG_CHAOS=1`; faults are then set at runtime through `/debug/chaos`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use axum::extract::{Form, Request};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+use crate::checkout::CheckoutState;
+use crate::models::{NotionPageId, TagUid};
+use crate::notion::TagPages;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Faults {
+   /// Added to every `ScalingPool::acquire`, and counted as pool wait.
+   pub acquire_delay_ms: u64,
+   /// Acquires failing with `PoolTimedOut`, as though Postgres were unreachable.
+   pub acquire_fail_percent: u8,
+   /// Calls through `Flaky` failing before they reach Notion.
+   pub notion_fail_percent: u8,
+   /// Deferred taps dropped as though the queue were full.
+   pub tap_drop_percent: u8,
+   /// Added to every HTML response, after the handler has rendered it.
+   pub render_delay_ms: u64,
+}
+
+impl Faults {
+   fn validate(&self) -> Result<(), String> {
+      for (name, percent) in [
+         ("acquire_fail_percent", self.acquire_fail_percent),
+         ("notion_fail_percent", self.notion_fail_percent),
+         ("tap_drop_percent", self.tap_drop_percent),
+      ] {
+         if percent > 100 {
+            return Err(format!("{} must be at most 100", name));
+         }
+      }
+      Ok(())
+   }
+
+   fn describe(&self) -> String {
+      format!(
+         "acquire_delay_ms={}\nacquire_fail_percent={}\nnotion_fail_percent={}\ntap_drop_percent={}\n\
+          render_delay_ms={}\n",
+         self.acquire_delay_ms,
+         self.acquire_fail_percent,
+         self.notion_fail_percent,
+         self.tap_drop_percent,
+         self.render_delay_ms
+      )
+   }
+}
+
+// `None` until `enable`: every hook is a no-op and `/debug/chaos` is a 404.
+#[cfg(not(test))]
+static FAULTS: std::sync::RwLock<Option<Faults>> = std::sync::RwLock::new(None);
+
+#[cfg(test)]
+thread_local! {
+   // Per thread, so tests injecting faults can't trip up tests running alongside them
+   static FAULTS: std::cell::Cell<Option<Faults>> = const { std::cell::Cell::new(None) };
+}
+
+#[cfg(not(test))]
+fn current() -> Option<Faults> { *FAULTS.read().unwrap() }
+
+#[cfg(not(test))]
+fn set(faults: Option<Faults>) { *FAULTS.write().unwrap() = faults }
+
+#[cfg(test)]
+fn current() -> Option<Faults> { FAULTS.with(|faults| faults.get()) }
+
+#[cfg(test)]
+fn set(faults: Option<Faults>) { FAULTS.with(|cell| cell.set(faults)) }
+
+/// Turns on the hooks, with no faults set yet.
+pub fn enable() { set(Some(Faults::default())) }
+
+/// Sets the faults directly, enabling the hooks if need be.
+#[cfg(test)]
+pub fn inject(faults: Faults) { set(Some(faults)) }
+
+fn roll(percent: u8) -> bool {
+   percent >= 100 || (percent > 0 && RandomState::new().hash_one(()) % 100 < u64::from(percent))
+}
+
+pub async fn before_acquire() -> Result<(), sqlx::Error> {
+   let Some(faults) = current() else {
+      return Ok(());
+   };
+   if faults.acquire_delay_ms > 0 {
+      tokio::time::sleep(Duration::from_millis(faults.acquire_delay_ms)).await;
+   }
+   if roll(faults.acquire_fail_percent) {
+      return Err(sqlx::Error::PoolTimedOut);
+   }
+   Ok(())
+}
+
+pub fn drop_tap() -> bool { current().is_some_and(|faults| roll(faults.tap_drop_percent)) }
+
+fn notion_error() -> Result<(), String> {
+   match current() {
+      Some(faults) if roll(faults.notion_fail_percent) => Err("Injected Notion failure".to_string()),
+      _ => Ok(()),
+   }
+}
+
+/// Wraps the Notion gateway, failing a share of its calls.
+#[derive(Clone)]
+pub struct Flaky<P>(pub P);
+
+impl<P: TagPages + Sync> TagPages for Flaky<P> {
+   async fn find_page_for_tag(&self, id: &TagUid) -> Result<Option<NotionPageId>, String> {
+      notion_error()?;
+      self.0.find_page_for_tag(id).await
+   }
+
+   async fn create_page_for_tag(&self, id: &TagUid) -> Result<NotionPageId, String> {
+      notion_error()?;
+      self.0.create_page_for_tag(id).await
+   }
+
+   async fn set_checkout_state(&self, page: &NotionPageId, state: CheckoutState) -> Result<(), String> {
+      notion_error()?;
+      self.0.set_checkout_state(page, state).await
+   }
+}
+
+/// Holds back HTML responses, as a stand-in for slow template rendering.
+pub async fn slow_html(req: Request, next: Next) -> Response {
+   let response = next.run(req).await;
+   let delay = current().map_or(0, |faults| faults.render_delay_ms);
+   let html = response
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .is_some_and(|v| v.as_bytes().starts_with(b"text/html"));
+   if delay > 0 && html {
+      tokio::time::sleep(Duration::from_millis(delay)).await;
+   }
+   response
+}
+
+/// GET /debug/chaos
+pub async fn show() -> Response {
+   match current() {
+      Some(faults) => faults.describe().into_response(),
+      None => StatusCode::NOT_FOUND.into_response(),
+   }
+}
+
+/// POST /debug/chaos; omitted fields are reset to zero.
+pub async fn update(Form(faults): Form<Faults>) -> Response {
+   if current().is_none() {
+      return StatusCode::NOT_FOUND.into_response();
+   }
+   if let Err(e) = faults.validate() {
+      return (StatusCode::UNPROCESSABLE_ENTITY, format!("{}\n", e)).into_response();
+   }
+   tracing::warn!(?faults, "Injecting faults");
+   set(Some(faults));
+   faults.describe().into_response()
+}
+
+#[cfg(test)]
+mod tests {
+   use std::time::Instant;
+
+   use axum::body::Body;
+   use axum::response::Html;
+   use axum::routing::get;
+   use axum::Router;
+   use tower::Service;
+
+   use super::*;
+
+   #[test]
+   fn test_roll_extremes() {
+      assert!((0..1000).all(|_| !roll(0)));
+      assert!((0..1000).all(|_| roll(100)));
+      let hits = (0..1000).filter(|_| roll(50)).count();
+      assert!((300..700).contains(&hits), "{hits}");
+   }
+
+   #[tokio::test]
+   async fn test_hooks_are_inert_until_enabled() {
+      set(None);
+      assert!(before_acquire().await.is_ok());
+      assert!(!drop_tap());
+      assert!(notion_error().is_ok());
+      assert_eq!(show().await.status(), StatusCode::NOT_FOUND);
+      let faults = Faults {
+         acquire_fail_percent: 100,
+         ..Faults::default()
+      };
+      assert_eq!(update(Form(faults)).await.status(), StatusCode::NOT_FOUND);
+      assert_eq!(current(), None);
+   }
+
+   #[tokio::test]
+   async fn test_update_sets_and_validates() {
+      enable();
+      let faults = Faults {
+         tap_drop_percent: 101,
+         ..Faults::default()
+      };
+      assert_eq!(update(Form(faults)).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+      assert_eq!(current(), Some(Faults::default()));
+
+      let faults = Faults {
+         acquire_fail_percent: 100,
+         notion_fail_percent: 100,
+         ..Faults::default()
+      };
+      assert_eq!(update(Form(faults)).await.status(), StatusCode::OK);
+      assert!(matches!(before_acquire().await, Err(sqlx::Error::PoolTimedOut)));
+      assert!(notion_error().is_err());
+      assert!(!drop_tap());
+   }
+
+   #[tokio::test]
+   async fn test_render_delay_holds_back_html() {
+      inject(Faults {
+         render_delay_ms: 50,
+         ..Faults::default()
+      });
+      let mut app = Router::new()
+         .route("/page", get(|| async { Html("<p>hi</p>") }))
+         .layer(axum::middleware::from_fn(slow_html));
+
+      let started = Instant::now();
+      let request = axum::http::Request::builder().uri("/page").body(Body::empty()).unwrap();
+      assert_eq!(app.call(request).await.unwrap().status(), StatusCode::OK);
+      assert!(started.elapsed() >= Duration::from_millis(50));
+   }
+}
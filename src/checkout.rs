@@ -0,0 +1,94 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::TimeDelta;
+
+/// Where a stateful ("scan to check out / check in") tag's item is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckoutState {
+   CheckedIn,
+   CheckedOut,
+}
+
+impl CheckoutState {
+   /// The state a scan moves to. A tag that has never been scanned is checked in.
+   pub fn after_scan(current: Option<CheckoutState>) -> CheckoutState {
+      match current {
+         Some(CheckoutState::CheckedOut) => CheckoutState::CheckedIn,
+         Some(CheckoutState::CheckedIn) | None => CheckoutState::CheckedOut,
+      }
+   }
+
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         CheckoutState::CheckedIn => "checked_in",
+         CheckoutState::CheckedOut => "checked_out",
+      }
+   }
+
+   /// The label shown on the confirmation page, and used as the Notion select option.
+   pub fn label(&self) -> &'static str {
+      match self {
+         CheckoutState::CheckedIn => "Checked in",
+         CheckoutState::CheckedOut => "Checked out",
+      }
+   }
+}
+
+impl fmt::Display for CheckoutState {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for CheckoutState {
+   type Err = String;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      match s {
+         "checked_in" => Ok(CheckoutState::CheckedIn),
+         "checked_out" => Ok(CheckoutState::CheckedOut),
+         other => Err(format!("Unknown checkout state '{}'", other)),
+      }
+   }
+}
+
+/// A rough, human duration such as "3 days" or "less than a minute".
+pub fn humanize(duration: TimeDelta) -> String {
+   let plural = |n: i64, unit: &str| format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" });
+   match duration {
+      d if d < TimeDelta::minutes(1) => "less than a minute".to_string(),
+      d if d < TimeDelta::hours(1) => plural(d.num_minutes(), "minute"),
+      d if d < TimeDelta::days(1) => plural(d.num_hours(), "hour"),
+      d => plural(d.num_days(), "day"),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_scans_alternate() {
+      let first = CheckoutState::after_scan(None);
+      assert_eq!(first, CheckoutState::CheckedOut);
+      let second = CheckoutState::after_scan(Some(first));
+      assert_eq!(second, CheckoutState::CheckedIn);
+      assert_eq!(CheckoutState::after_scan(Some(second)), CheckoutState::CheckedOut);
+   }
+
+   #[test]
+   fn test_round_trips_through_text() {
+      for state in [CheckoutState::CheckedIn, CheckoutState::CheckedOut] {
+         assert_eq!(state.to_string().parse::<CheckoutState>(), Ok(state));
+      }
+      assert!("lost".parse::<CheckoutState>().is_err());
+   }
+
+   #[test]
+   fn test_humanize() {
+      assert_eq!(humanize(TimeDelta::seconds(5)), "less than a minute");
+      assert_eq!(humanize(TimeDelta::minutes(1)), "1 minute");
+      assert_eq!(humanize(TimeDelta::minutes(59)), "59 minutes");
+      assert_eq!(humanize(TimeDelta::hours(23)), "23 hours");
+      assert_eq!(humanize(TimeDelta::days(3) + TimeDelta::hours(20)), "3 days");
+   }
+}
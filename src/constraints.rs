@@ -0,0 +1,142 @@
+//! What to tell a client when a write breaks a database constraint, rather than a bare 500. The
+//! table is `migrations/constraints.tsv`, beside the migrations that declare the constraints it
+//! names; a test checks that each of them still exists. Violations of constraints it doesn't name
+//! are still 500s.
+
+use std::sync::OnceLock;
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+static TABLE: &str = include_str!("../migrations/constraints.tsv");
+
+/// Unique, foreign key and check violations; the only errors a constraint name is looked up for.
+const VIOLATION_CODES: [&str; 3] = ["23505", "23503", "23514"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+   pub constraint: &'static str,
+   pub status: StatusCode,
+   pub message: &'static str,
+}
+
+impl IntoResponse for &Violation {
+   fn into_response(self) -> Response { (self.status, format!("{}\n", self.message)).into_response() }
+}
+
+fn parse(source: &'static str) -> Result<Vec<Violation>, String> {
+   source
+      .lines()
+      .enumerate()
+      .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+      .map(|(n, line)| {
+         let invalid = |why: &str| format!("constraints.tsv line {}: {}", n + 1, why);
+         let [constraint, status, message] = line.split('\t').collect::<Vec<_>>()[..] else {
+            return Err(invalid("expected a constraint, a status and a message"));
+         };
+         let status = status
+            .parse()
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .filter(StatusCode::is_client_error)
+            .ok_or_else(|| invalid("the status must be a 4xx"))?;
+         Ok(Violation {
+            constraint,
+            status,
+            message,
+         })
+      })
+      .collect()
+}
+
+fn table() -> &'static [Violation] {
+   static PARSED: OnceLock<Vec<Violation>> = OnceLock::new();
+   PARSED.get_or_init(|| parse(TABLE).unwrap())
+}
+
+/// The violation an error with SQLSTATE `code`, naming `constraint`, is, if the table knows it.
+pub fn classify(code: &str, constraint: &str) -> Option<&'static Violation> {
+   if !VIOLATION_CODES.contains(&code) {
+      return None;
+   }
+   table().iter().find(|violation| violation.constraint == constraint)
+}
+
+/// The violation `err` is, if it broke a constraint the table knows.
+pub fn violation(err: &sqlx::Error) -> Option<&'static Violation> {
+   let db = err.as_database_error()?;
+   classify(&db.code()?, db.constraint()?)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_known_constraints() {
+      let taken = classify("23505", "twag_tags_pkey").unwrap();
+      assert_eq!(taken.status, StatusCode::CONFLICT);
+      assert_eq!(taken.message, "A tag with this id already exists.");
+      let linked = classify("23505", "twag_tags_notion_page_id_key").unwrap();
+      assert_eq!(linked.status, StatusCode::CONFLICT);
+      let malformed = classify("23514", "tag_uid_check").unwrap();
+      assert_eq!(malformed.status, StatusCode::UNPROCESSABLE_ENTITY);
+      assert_eq!(
+         classify("23503", "twag_tag_aliases_tag_id_fkey").unwrap().message,
+         "The tag no longer exists."
+      );
+
+      assert_eq!(classify("23505", "twag_tag_daily_pkey"), None);
+      // Not a violation, whatever it names
+      assert_eq!(classify("40001", "twag_tags_pkey"), None);
+   }
+
+   #[test]
+   fn test_malformed_tables() {
+      assert!(parse("# nothing yet\n\n").unwrap().is_empty());
+      assert!(parse("twag_tags_pkey\t409").unwrap_err().contains("line 1"));
+      assert!(parse("# x\ntwag_tags_pkey\t500\tOops.")
+         .unwrap_err()
+         .contains("line 2: the status"));
+      assert!(parse("twag_tags_pkey\tconflict\tTaken.").is_err());
+   }
+
+   /// Every constraint in the table is declared by a migration, by name or by the name Postgres
+   /// gives it: `{domain}_check`, `{table}_pkey`, or `{table}_{column}_key|fkey|check`.
+   #[test]
+   fn test_table_names_declared_constraints() {
+      let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+      let mut migrations = String::new();
+      for entry in std::fs::read_dir(dir).unwrap() {
+         let path = entry.unwrap().path();
+         if path.extension().is_some_and(|ext| ext == "sql") {
+            migrations.push_str(&std::fs::read_to_string(&path).unwrap());
+         }
+      }
+      let declares = |sql: String| migrations.contains(&sql);
+      let has_table = |table: &str| {
+         declares(format!("CREATE TABLE IF NOT EXISTS \"{table}\"")) || declares(format!("CREATE TABLE \"{table}\""))
+      };
+      let table_has = |table: &str, column: &str| has_table(table) && declares(format!("\"{column}\""));
+
+      let violations = table();
+      assert!(!violations.is_empty());
+      for violation in violations {
+         let name = violation.constraint;
+         let declared = declares(format!("\"{name}\""))
+            || name
+               .strip_suffix("_check")
+               .is_some_and(|domain| declares(format!("CREATE DOMAIN \"{domain}\"")))
+            || name.strip_suffix("_pkey").is_some_and(has_table)
+            || ["_key", "_fkey", "_check"].iter().any(|suffix| {
+               name.strip_suffix(suffix).is_some_and(|rest| {
+                  rest
+                     .match_indices('_')
+                     .any(|(at, _)| table_has(&rest[..at], &rest[at + 1..]))
+               })
+            });
+         assert!(declared, "{name} isn't declared by any migration");
+         assert!(violation.message.ends_with('.'), "{name}");
+      }
+   }
+}
@@ -0,0 +1,60 @@
+/// Compares without short-circuiting on the first differing byte, so response timing doesn't
+/// reveal how much of a guessed token was right. Only the length leaks.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+   if a.len() != b.len() {
+      return false;
+   }
+   a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Whether a hit counts as a tap. Tags without a token count every hit.
+pub fn should_count(required: Option<&str>, presented: Option<&str>) -> bool {
+   match (required, presented) {
+      (None, _) => true,
+      (Some(required), Some(presented)) => constant_time_eq(required.as_bytes(), presented.as_bytes()),
+      (Some(_), None) => false,
+   }
+}
+
+/// Appends the token a scan URL must carry to be counted.
+pub fn with_token(uri: &str, token: Option<&str>) -> String {
+   match token {
+      Some(token) => format!("{}?ct={}", uri, token),
+      None => uri.to_string(),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_constant_time_eq() {
+      assert!(constant_time_eq(b"3f9a1c0e", b"3f9a1c0e"));
+      assert!(!constant_time_eq(b"3f9a1c0e", b"3f9a1c0f"));
+      assert!(!constant_time_eq(b"3f9a1c0e", b"3f9a1c0"));
+      assert!(constant_time_eq(b"", b""));
+   }
+
+   #[test]
+   fn test_should_count() {
+      assert!(should_count(None, None));
+      assert!(should_count(None, Some("anything")));
+      assert!(should_count(Some("3f9a1c0e"), Some("3f9a1c0e")));
+      assert!(!should_count(Some("3f9a1c0e"), None));
+      assert!(!should_count(Some("3f9a1c0e"), Some("")));
+      assert!(!should_count(Some("3f9a1c0e"), Some("3F9A1C0E")));
+   }
+
+   #[test]
+   fn test_with_token() {
+      assert_eq!(
+         with_token("https://xz.ws/tag/055B88A23C1250", Some("3f9a1c0e")),
+         "https://xz.ws/tag/055B88A23C1250?ct=3f9a1c0e"
+      );
+      assert_eq!(
+         with_token("https://xz.ws/tag/055B88A23C1250", None),
+         "https://xz.ws/tag/055B88A23C1250"
+      );
+   }
+}
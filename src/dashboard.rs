@@ -0,0 +1,382 @@
+//! Tap stats over a set of tags, for a period and the one just before it: `/stats` for every tag,
+//! `/kits/{kit}/stats` for one kit's.
+//!
+//! Taps come from the daily rollups, `twag_tag_daily`, filtered by `Scope` in the query itself;
+//! `Scope::includes` mirrors that filter for tests. Never-scanned counts come from each tag's
+//! `access_count` instead, as retention prunes old rollups, and per-model counts from each tag's
+//! `tag_model`.
+
+use chrono::{NaiveDate, TimeDelta};
+use serde::Serialize;
+use sqlx::PgConnection;
+
+use crate::models::TagUid;
+use crate::tag_models::TagModel;
+
+/// Without `from`, periods end `to` and are this long.
+pub const DEFAULT_DAYS: i64 = 30;
+/// Longer periods are refused.
+pub const MAX_DAYS: i64 = 366;
+/// How many of the most-tapped tags are listed.
+pub const TOP: usize = 10;
+
+/// Which tags are counted. Deleted tags never are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+   All,
+   Kit(String),
+}
+
+impl Scope {
+   /// As bound to queries, where `NULL` is every tag.
+   pub fn kit(&self) -> Option<&str> {
+      match self {
+         Scope::All => None,
+         Scope::Kit(kit) => Some(kit),
+      }
+   }
+
+   /// Whether a tag in `kit` is counted.
+   pub fn includes(&self, kit: Option<&str>) -> bool { self.kit().is_none_or(|scope| kit == Some(scope)) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+   pub from: NaiveDate,
+   pub to: NaiveDate,
+}
+
+impl Period {
+   /// From `?from=&to=`, as `YYYY-MM-DD`, both inclusive. `to` defaults to `today`, and `from` to
+   /// `DEFAULT_DAYS` before `to`.
+   pub fn parse(from: Option<&str>, to: Option<&str>, today: NaiveDate) -> Result<Period, String> {
+      let date = |name: &str, s: &str| {
+         NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("{name} must be a date, as YYYY-MM-DD"))
+      };
+      let to = match to.filter(|s| !s.is_empty()) {
+         Some(s) => date("to", s)?,
+         None => today,
+      };
+      let from = match from.filter(|s| !s.is_empty()) {
+         Some(s) => date("from", s)?,
+         None => to - TimeDelta::days(DEFAULT_DAYS - 1),
+      };
+      let period = Period { from, to };
+      match period.days() {
+         ..=0 => Err("from must not be after to".to_string()),
+         days if days > MAX_DAYS => Err(format!("Periods are at most {MAX_DAYS} days")),
+         _ => Ok(period),
+      }
+   }
+
+   pub fn days(&self) -> i64 { (self.to - self.from).num_days() + 1 }
+
+   /// As long as this one, and ending the day before it starts.
+   pub fn previous(&self) -> Period {
+      let to = self.from - TimeDelta::days(1);
+      Period {
+         from: to - TimeDelta::days(self.days() - 1),
+         to,
+      }
+   }
+
+   pub fn contains(&self, day: NaiveDate) -> bool { self.from <= day && day <= self.to }
+}
+
+/// One tag's taps on one day, from the rollup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagDay {
+   pub id: TagUid,
+   pub label: Option<String>,
+   pub day: NaiveDate,
+   pub taps: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DayTaps {
+   pub day: String,
+   pub taps: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TopTag {
+   pub id: String,
+   pub label: Option<String>,
+   pub taps: i64,
+}
+
+/// How many of the scope's tags are of one model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelCount {
+   pub tag_model: &'static str,
+   pub name: &'static str,
+   pub tags: i64,
+}
+
+/// From `(tag_model, tags)` rows, in `TagModel::ALL` order; models without tags are left out.
+pub fn model_counts(rows: &[(String, i64)]) -> Vec<ModelCount> {
+   TagModel::ALL
+      .iter()
+      .filter_map(|model| {
+         let tags = rows
+            .iter()
+            .filter(|(key, _)| key == model.as_str())
+            .map(|(_, tags)| tags)
+            .sum();
+         (tags > 0).then(|| ModelCount {
+            tag_model: model.as_str(),
+            name: model.name(),
+            tags,
+         })
+      })
+      .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Dashboard {
+   pub from: String,
+   pub to: String,
+   /// Every day of the period, those without taps too.
+   pub days: Vec<DayTaps>,
+   pub taps: i64,
+   /// Over `previous_from` to `previous_to`, the period before.
+   pub previous_taps: i64,
+   pub previous_from: String,
+   pub previous_to: String,
+   pub change: i64,
+   /// `None` when there were no taps the period before.
+   pub change_percent: Option<i64>,
+   pub top: Vec<TopTag>,
+   pub tags: i64,
+   pub never_scanned: i64,
+   pub models: Vec<ModelCount>,
+   /// Taps over the period that kept no detail, their client having asked not to be tracked; see
+   /// `privacy`. Only `load` counts them.
+   pub privacy_limited: i64,
+}
+
+/// `rows` are the scope's taps over `period` and the one before; rows outside both are ignored.
+pub fn summarize(
+   period: &Period,
+   rows: &[TagDay],
+   tags: i64,
+   never_scanned: i64,
+   models: Vec<ModelCount>,
+) -> Dashboard {
+   let previous = period.previous();
+   let mut days = vec![0; period.days() as usize];
+   let mut by_tag: Vec<TopTag> = Vec::new();
+   let mut previous_taps = 0;
+   for row in rows {
+      if previous.contains(row.day) {
+         previous_taps += row.taps;
+      }
+      if !period.contains(row.day) {
+         continue;
+      }
+      days[(row.day - period.from).num_days() as usize] += row.taps;
+      let id = row.id.to_string();
+      match by_tag.iter_mut().find(|tag| tag.id == id) {
+         Some(tag) => tag.taps += row.taps,
+         None => by_tag.push(TopTag {
+            id,
+            label: row.label.clone(),
+            taps: row.taps,
+         }),
+      }
+   }
+   by_tag.sort_by(|a, b| b.taps.cmp(&a.taps).then_with(|| a.id.cmp(&b.id)));
+   by_tag.truncate(TOP);
+
+   let taps = days.iter().sum();
+   Dashboard {
+      from: period.from.to_string(),
+      to: period.to.to_string(),
+      days: days
+         .into_iter()
+         .enumerate()
+         .map(|(n, taps)| DayTaps {
+            day: (period.from + TimeDelta::days(n as i64)).to_string(),
+            taps,
+         })
+         .collect(),
+      taps,
+      previous_taps,
+      previous_from: previous.from.to_string(),
+      previous_to: previous.to.to_string(),
+      change: taps - previous_taps,
+      change_percent: (previous_taps != 0).then(|| (taps - previous_taps) * 100 / previous_taps),
+      top: by_tag,
+      tags,
+      never_scanned,
+      models,
+      privacy_limited: 0,
+   }
+}
+
+/// `None` for a kit without tags.
+pub async fn load(conn: &mut PgConnection, scope: &Scope, period: &Period) -> Result<Option<Dashboard>, sqlx::Error> {
+   let counts = sqlx::query!(
+      r#"SELECT count(*) AS "tags!", count(*) FILTER (WHERE COALESCE(access_count, 0) = 0) AS "never_scanned!"
+         FROM twag_tags WHERE ($1::text IS NULL OR kit = $1) AND deleted_at IS NULL"#,
+      scope.kit(),
+   )
+   .fetch_one(&mut *conn)
+   .await?;
+   if counts.tags == 0 && scope != &Scope::All {
+      return Ok(None);
+   }
+   let models = sqlx::query!(
+      r#"SELECT tag_model, count(*) AS "tags!" FROM twag_tags
+         WHERE ($1::text IS NULL OR kit = $1) AND deleted_at IS NULL GROUP BY tag_model"#,
+      scope.kit(),
+   )
+   .fetch_all(&mut *conn)
+   .await?
+   .into_iter()
+   .map(|row| (row.tag_model, row.tags))
+   .collect::<Vec<_>>();
+
+   let rows = sqlx::query!(
+      r#"SELECT d.tag_id AS "id: TagUid", t.label, to_char(d.day, 'YYYY-MM-DD') AS "day!", d.taps
+         FROM twag_tag_daily d JOIN twag_tags t ON t.id = d.tag_id
+         WHERE ($1::text IS NULL OR t.kit = $1) AND t.deleted_at IS NULL AND d.taps > 0
+            AND d.day BETWEEN $2::text::date AND $3::text::date"#,
+      scope.kit(),
+      period.previous().from.to_string(),
+      period.to.to_string(),
+   )
+   .fetch_all(&mut *conn)
+   .await?
+   .into_iter()
+   .filter_map(|row| {
+      Some(TagDay {
+         id: row.id,
+         label: row.label,
+         day: row.day.parse().ok()?,
+         taps: row.taps.into(),
+      })
+   })
+   .collect::<Vec<_>>();
+   let privacy_limited = sqlx::query_scalar!(
+      r#"SELECT count(*) AS "privacy_limited!"
+         FROM twag_tap_events e JOIN twag_tags t ON t.id = e.tag_id
+         WHERE ($1::text IS NULL OR t.kit = $1) AND t.deleted_at IS NULL AND e.privacy_limited
+            AND (e.tapped_at AT TIME ZONE 'UTC')::date BETWEEN $2::text::date AND $3::text::date"#,
+      scope.kit(),
+      period.from.to_string(),
+      period.to.to_string(),
+   )
+   .fetch_one(&mut *conn)
+   .await?;
+   Ok(Some(Dashboard {
+      privacy_limited,
+      ..summarize(period, &rows, counts.tags, counts.never_scanned, model_counts(&models))
+   }))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn day(s: &str) -> NaiveDate { s.parse().unwrap() }
+
+   fn tag_day(id: &str, kit: &str, on: &str, taps: i64) -> (Option<String>, TagDay) {
+      let row = TagDay {
+         id: id.parse().unwrap(),
+         label: Some(format!("{kit} tag")),
+         day: day(on),
+         taps,
+      };
+      (Some(kit.to_string()), row)
+   }
+
+   #[test]
+   fn test_periods() {
+      let today = day("2026-10-16");
+      let default = Period::parse(None, None, today).unwrap();
+      assert_eq!((default.from, default.to), (day("2026-09-17"), today));
+      assert_eq!(default.days(), DEFAULT_DAYS);
+
+      let october = Period::parse(Some("2026-10-01"), Some("2026-10-31"), today).unwrap();
+      assert_eq!(
+         october.previous(),
+         Period {
+            from: day("2026-08-31"),
+            to: day("2026-09-30"),
+         }
+      );
+
+      assert!(Period::parse(Some("2026-10-02"), Some("2026-10-01"), today).is_err());
+      assert!(Period::parse(Some("2025-01-01"), None, today).is_err());
+      assert!(Period::parse(Some("10/01/2026"), None, today).is_err());
+      assert!(Period::parse(Some(""), Some(""), today).is_ok());
+   }
+
+   #[test]
+   fn test_scopes_dont_bleed() {
+      let period = Period::parse(Some("2026-10-01"), Some("2026-10-03"), day("2026-10-16")).unwrap();
+      let seeded = [
+         tag_day("04000000000001", "Kitchen", "2026-10-01", 3),
+         tag_day("04000000000001", "Kitchen", "2026-10-03", 2),
+         tag_day("04000000000002", "Kitchen", "2026-09-29", 4),
+         tag_day("04000000000003", "Garage", "2026-10-02", 7),
+         tag_day("04000000000003", "Garage", "2026-09-30", 1),
+      ];
+      // As the query filters
+      let rows = |scope: &Scope| -> Vec<TagDay> {
+         seeded
+            .iter()
+            .filter(|(kit, _)| scope.includes(kit.as_deref()))
+            .map(|(_, row)| row.clone())
+            .collect()
+      };
+
+      let kitchen = summarize(&period, &rows(&Scope::Kit("Kitchen".to_string())), 2, 0, Vec::new());
+      assert_eq!(kitchen.days.iter().map(|d| d.taps).collect::<Vec<_>>(), [3, 0, 2]);
+      assert_eq!((kitchen.taps, kitchen.previous_taps, kitchen.change), (5, 4, 1));
+      assert_eq!(kitchen.change_percent, Some(25));
+      assert_eq!(kitchen.top.len(), 1);
+      assert_eq!(kitchen.top[0].id, "04000000000001");
+
+      let garage = summarize(&period, &rows(&Scope::Kit("Garage".to_string())), 1, 0, Vec::new());
+      assert_eq!((garage.taps, garage.previous_taps), (7, 1));
+      assert!(garage.top.iter().all(|tag| tag.id == "04000000000003"));
+
+      let all = summarize(&period, &rows(&Scope::All), 3, 0, Vec::new());
+      assert_eq!((all.taps, all.previous_taps), (12, 5));
+      assert_eq!(
+         all.top.iter().map(|tag| tag.id.as_str()).collect::<Vec<_>>(),
+         ["04000000000003", "04000000000001"]
+      );
+
+      assert!(!Scope::Kit("Kitchen".to_string()).includes(None));
+      assert!(Scope::All.includes(None));
+   }
+
+   #[test]
+   fn test_no_previous_taps_has_no_percentage() {
+      let period = Period::parse(None, None, day("2026-10-16")).unwrap();
+      let rows = [tag_day("04000000000001", "Kitchen", "2026-10-16", 1).1];
+      let dashboard = summarize(&period, &rows, 1, 0, Vec::new());
+      assert_eq!((dashboard.change, dashboard.change_percent), (1, None));
+      assert_eq!(dashboard.days.last().unwrap().day, "2026-10-16");
+   }
+
+   #[test]
+   fn test_model_counts() {
+      let rows = [
+         ("unknown".to_string(), 40),
+         ("ntag215".to_string(), 3),
+         ("qr_only".to_string(), 0),
+         ("ntag213".to_string(), 12),
+      ];
+      let counts = model_counts(&rows);
+      assert_eq!(
+         counts.iter().map(|c| (c.tag_model, c.tags)).collect::<Vec<_>>(),
+         [("ntag213", 12), ("ntag215", 3), ("unknown", 40)]
+      );
+      assert_eq!(counts[0].name, "NTAG213");
+      assert!(model_counts(&[]).is_empty());
+   }
+}
@@ -0,0 +1,406 @@
+//! `GET /admin/db-report`: which indexes the hot queries use, and which they're missing. Each
+//! catalogued query is explained, never executed, inside a read-only transaction; suggestions are
+//! only ever shown for the operator to apply.
+//!
+//! The catalogue lives beside the code owning each query (`planned_queries` in `main`,
+//! `listing::planned`, `retention::planned`), as a copy of the SQL that's checked against the
+//! `.sqlx` cache so it can't drift from the query it describes.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use lazy_regex::regex;
+use serde_json::Value;
+use sqlx::{Connection, Row};
+
+use crate::pool::ScalingPool;
+
+/// Sequential scans of tables estimated at fewer rows than this are cheap enough to ignore.
+pub const LARGE_TABLE_ROWS: i64 = 10_000;
+
+pub struct Planned {
+   pub name: &'static str,
+   pub sql: Cow<'static, str>,
+   /// SQL literals for `$1`, `$2`, ..., as a typical call would bind them.
+   pub args: Vec<String>,
+   /// Also written out in a `query!` macro, and so checked against the schema at build time.
+   pub macro_checked: bool,
+}
+
+impl Planned {
+   pub fn checked(name: &'static str, sql: &'static str, args: &[&str]) -> Self {
+      Planned {
+         name,
+         sql: Cow::Borrowed(sql),
+         args: args.iter().map(|arg| arg.to_string()).collect(),
+         macro_checked: true,
+      }
+   }
+
+   /// Assembled at runtime, e.g. by a `QueryBuilder`.
+   pub fn built(name: &'static str, sql: String, args: Vec<String>) -> Self {
+      Planned {
+         name,
+         sql: Cow::Owned(sql),
+         args,
+         macro_checked: false,
+      }
+   }
+
+   /// The SQL with every placeholder replaced by its sample argument, since Postgres 15 can't plan
+   /// a statement with unbound parameters.
+   pub fn bound(&self) -> Result<String, String> {
+      let mut missing = None;
+      let sql = regex!(r"\$(\d+)").replace_all(&self.sql, |captures: &lazy_regex::Captures| {
+         let n: usize = captures[1].parse().unwrap_or(0);
+         match n.checked_sub(1).and_then(|i| self.args.get(i)) {
+            Some(arg) => arg.clone(),
+            None => {
+               missing.get_or_insert(n);
+               String::new()
+            }
+         }
+      });
+      match missing {
+         Some(n) => Err(format!("{} has no sample argument for ${}", self.name, n)),
+         None => Ok(sql.into_owned()),
+      }
+   }
+}
+
+/// A plan node that reads a table or an index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scan {
+   pub node: String,
+   pub relation: Option<String>,
+   pub index: Option<String>,
+   pub filter: Option<String>,
+   pub rows: f64,
+}
+
+/// Every node of an `EXPLAIN (FORMAT JSON)` result that touches a table or an index, outermost
+/// first.
+pub fn scans(explained: &Value) -> Vec<Scan> {
+   fn walk(node: &Value, found: &mut Vec<Scan>) {
+      let text = |key: &str| node[key].as_str().map(str::to_string);
+      let relation = text("Relation Name");
+      let index = text("Index Name");
+      if relation.is_some() || index.is_some() {
+         found.push(Scan {
+            node: text("Node Type").unwrap_or_default(),
+            relation,
+            index,
+            filter: text("Filter"),
+            rows: node["Plan Rows"].as_f64().unwrap_or_default(),
+         });
+      }
+      for child in node["Plans"].as_array().into_iter().flatten() {
+         walk(child, found);
+      }
+   }
+   let mut found = Vec::new();
+   for plan in explained.as_array().into_iter().flatten() {
+      walk(&plan["Plan"], &mut found);
+   }
+   found
+}
+
+/// Columns a filter compares against a value, which an index could have answered instead.
+pub fn filter_columns(filter: &str) -> Vec<String> {
+   let mut columns: Vec<String> = Vec::new();
+   for captures in regex!(r"\(([a-z_][a-z0-9_]*) (?:=|<|>|<=|>=) ").captures_iter(filter) {
+      if !columns.iter().any(|c| c == &captures[1]) {
+         columns.push(captures[1].to_string());
+      }
+   }
+   columns
+}
+
+/// A `CREATE INDEX` for the columns, unless some index already leads with them.
+pub fn suggest(table: &str, columns: &[String], existing: &[Vec<String>]) -> Option<String> {
+   if columns.is_empty() || existing.iter().any(|index| index.starts_with(columns)) {
+      return None;
+   }
+   let quoted: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c)).collect();
+   Some(format!(
+      "CREATE INDEX CONCURRENTLY \"{}_{}_idx\" ON \"{}\" ({});",
+      table,
+      columns.join("_"),
+      table,
+      quoted.join(", ")
+   ))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+   pub relation: String,
+   pub estimated_rows: i64,
+   pub filter: Option<String>,
+   pub suggestion: Option<String>,
+}
+
+/// Sequential scans of large tables in a plan.
+pub fn findings(
+   scans: &[Scan],
+   table_rows: &HashMap<String, i64>,
+   indexes: &HashMap<String, Vec<Vec<String>>>,
+) -> Vec<Finding> {
+   scans
+      .iter()
+      .filter(|scan| scan.node == "Seq Scan")
+      .filter_map(|scan| {
+         let relation = scan.relation.clone()?;
+         let estimated_rows = table_rows.get(&relation).copied().unwrap_or_default();
+         if estimated_rows < LARGE_TABLE_ROWS {
+            return None;
+         }
+         let columns = scan.filter.as_deref().map(filter_columns).unwrap_or_default();
+         let existing = indexes.get(&relation).map(Vec::as_slice).unwrap_or_default();
+         Some(Finding {
+            suggestion: suggest(&relation, &columns, existing),
+            relation,
+            estimated_rows,
+            filter: scan.filter.clone(),
+         })
+      })
+      .collect()
+}
+
+pub struct QueryReport {
+   pub name: &'static str,
+   /// Pretty-printed JSON plan.
+   pub plan: String,
+   pub scans: Vec<Scan>,
+   pub findings: Vec<Finding>,
+   pub error: Option<String>,
+}
+
+pub struct UnusedIndex {
+   pub table: String,
+   pub index: String,
+   pub size: String,
+}
+
+pub struct Report {
+   pub queries: Vec<QueryReport>,
+   /// Every distinct suggestion across the queries.
+   pub suggestions: Vec<String>,
+   /// Never scanned since statistics were last reset, leaving out unique and primary key indexes.
+   pub unused: Vec<UnusedIndex>,
+}
+
+async fn explain(conn: &mut sqlx::PgConnection, planned: &Planned) -> Result<Value, String> {
+   let sql = format!("EXPLAIN (FORMAT JSON) {}", planned.bound()?);
+   let row = sqlx::query(&sql)
+      .persistent(false)
+      .fetch_one(&mut *conn)
+      .await
+      .map_err(|e| format!("{}", e))?;
+   // `json` arrives as its text, which sqlx won't decode as a `String` without the `json` feature
+   let text: String = row.try_get_unchecked(0).map_err(|e| format!("{}", e))?;
+   serde_json::from_str(&text).map_err(|e| format!("Unreadable plan: {}", e))
+}
+
+/// Explains each query against `pool`, whose statistics also decide what counts as large or unused.
+pub async fn run(pool: &ScalingPool, catalogue: &[Planned]) -> Result<Report, sqlx::Error> {
+   let mut tx = pool.get().begin().await?;
+   sqlx::query("SET TRANSACTION READ ONLY").execute(&mut *tx).await?;
+
+   let table_rows: HashMap<String, i64> = sqlx::query!(
+      r#"SELECT c.relname::text AS "relname!", greatest(c.reltuples, 0)::bigint AS "rows!"
+         FROM pg_class c WHERE c.relkind = 'r' AND pg_table_is_visible(c.oid)"#
+   )
+   .fetch_all(&mut *tx)
+   .await?
+   .into_iter()
+   .map(|row| (row.relname, row.rows))
+   .collect();
+
+   let mut indexes: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+   for row in sqlx::query!(
+      r#"SELECT t.relname::text AS "table!",
+            ARRAY(SELECT a.attname::text FROM unnest(x.indkey::int2[]) WITH ORDINALITY AS k(attnum, n)
+               JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = k.attnum ORDER BY k.n) AS "columns!"
+         FROM pg_index x JOIN pg_class t ON t.oid = x.indrelid
+         WHERE pg_table_is_visible(t.oid)"#
+   )
+   .fetch_all(&mut *tx)
+   .await?
+   {
+      indexes.entry(row.table).or_default().push(row.columns);
+   }
+
+   let mut queries = Vec::new();
+   for planned in catalogue {
+      // Each in its own savepoint, so that one failing doesn't abort the rest
+      let mut savepoint = Connection::begin(&mut *tx).await?;
+      let explained = explain(&mut savepoint, planned).await;
+      savepoint.rollback().await?;
+      let report = match explained {
+         Ok(explained) => {
+            let scans = scans(&explained);
+            QueryReport {
+               name: planned.name,
+               plan: serde_json::to_string_pretty(&explained).unwrap_or_default(),
+               findings: findings(&scans, &table_rows, &indexes),
+               scans,
+               error: None,
+            }
+         }
+         Err(error) => QueryReport {
+            name: planned.name,
+            plan: String::new(),
+            scans: Vec::new(),
+            findings: Vec::new(),
+            error: Some(error),
+         },
+      };
+      queries.push(report);
+   }
+
+   let unused = sqlx::query!(
+      r#"SELECT s.relname::text AS "table!", s.indexrelname::text AS "index!",
+            pg_size_pretty(pg_relation_size(s.indexrelid)) AS "size!"
+         FROM pg_stat_user_indexes s JOIN pg_index x ON x.indexrelid = s.indexrelid
+         WHERE s.idx_scan = 0 AND NOT x.indisunique AND NOT x.indisprimary
+         ORDER BY pg_relation_size(s.indexrelid) DESC"#
+   )
+   .fetch_all(&mut *tx)
+   .await?
+   .into_iter()
+   .map(|row| UnusedIndex {
+      table: row.table,
+      index: row.index,
+      size: row.size,
+   })
+   .collect();
+   tx.rollback().await?;
+
+   let mut seen = HashSet::new();
+   let suggestions = queries
+      .iter()
+      .flat_map(|query| &query.findings)
+      .filter_map(|finding| finding.suggestion.clone())
+      .filter(|suggestion| seen.insert(suggestion.clone()))
+      .collect();
+   Ok(Report {
+      queries,
+      suggestions,
+      unused,
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Shaped like Postgres 15's output for a batch delete from a table keyed on (tag_id, day)
+   const FIXTURE_PLAN: &str = r#"[{"Plan": {
+      "Node Type": "ModifyTable", "Operation": "Delete", "Relation Name": "twag_tag_daily",
+      "Alias": "twag_tag_daily", "Plan Rows": 0,
+      "Plans": [{"Node Type": "Nested Loop", "Parent Relationship": "Outer", "Plan Rows": 1000,
+         "Plans": [
+            {"Node Type": "Limit", "Parent Relationship": "Outer", "Plan Rows": 1000,
+             "Plans": [{"Node Type": "Seq Scan", "Parent Relationship": "Outer", "Relation Name": "twag_tag_daily",
+                "Alias": "twag_tag_daily_1", "Plan Rows": 1000,
+                "Filter": "(day < ('2026-01-01'::text)::date)"}]},
+            {"Node Type": "Tid Scan", "Parent Relationship": "Inner", "Relation Name": "twag_tag_daily",
+             "Alias": "twag_tag_daily", "Plan Rows": 1}
+         ]}]
+   }}]"#;
+
+   #[test]
+   fn test_bound_replaces_every_placeholder() {
+      let planned = Planned::checked(
+         "test",
+         "SELECT 1 FROM t WHERE a = $1 AND b = $10 AND c = $1::text",
+         &["'x'", "2", "3", "4", "5", "6", "7", "8", "9", "10"],
+      );
+      assert_eq!(
+         planned.bound().unwrap(),
+         "SELECT 1 FROM t WHERE a = 'x' AND b = 10 AND c = 'x'::text"
+      );
+      let short = Planned::checked("short", "SELECT $1, $2", &["1"]);
+      assert_eq!(short.bound(), Err("short has no sample argument for $2".to_string()));
+   }
+
+   #[test]
+   fn test_scans_walks_nested_plans() {
+      let scans = scans(&serde_json::from_str(FIXTURE_PLAN).unwrap());
+      let nodes: Vec<&str> = scans.iter().map(|scan| scan.node.as_str()).collect();
+      assert_eq!(nodes, ["ModifyTable", "Seq Scan", "Tid Scan"]);
+      assert_eq!(scans[1].filter.as_deref(), Some("(day < ('2026-01-01'::text)::date)"));
+   }
+
+   #[test]
+   fn test_filter_columns() {
+      assert_eq!(
+         filter_columns("((kit = 'Kit'::text) AND (deleted_at IS NULL))"),
+         ["kit"]
+      );
+      assert_eq!(
+         filter_columns("((tag_id = '055B88A23C1250'::tag_uid) AND (day >= '2026-01-01'::date))"),
+         ["tag_id", "day"]
+      );
+      // Neither an index on the column nor a plain comparison
+      assert!(filter_columns("((label)::text ~~* '%lamp%'::text)").is_empty());
+   }
+
+   #[test]
+   fn test_findings_only_flag_large_tables_without_a_usable_index() {
+      let scans = scans(&serde_json::from_str(FIXTURE_PLAN).unwrap());
+      let rows = |n: i64| HashMap::from([("twag_tag_daily".to_string(), n)]);
+      let keyed_on_tag = HashMap::from([(
+         "twag_tag_daily".to_string(),
+         vec![vec!["tag_id".to_string(), "day".to_string()]],
+      )]);
+
+      assert!(findings(&scans, &rows(LARGE_TABLE_ROWS - 1), &keyed_on_tag).is_empty());
+
+      let found = findings(&scans, &rows(250_000), &keyed_on_tag);
+      assert_eq!(found.len(), 1);
+      assert_eq!(
+         found[0].suggestion.as_deref(),
+         Some(r#"CREATE INDEX CONCURRENTLY "twag_tag_daily_day_idx" ON "twag_tag_daily" ("day");"#)
+      );
+
+      let mut with_day = keyed_on_tag.clone();
+      with_day
+         .get_mut("twag_tag_daily")
+         .unwrap()
+         .push(vec!["day".to_string()]);
+      let found = findings(&scans, &rows(250_000), &with_day);
+      assert_eq!((found.len(), found[0].suggestion.as_ref()), (1, None));
+   }
+
+   /// Every query written out in a `query!` macro is in the `.sqlx` cache, which `cargo sqlx
+   /// prepare` only fills with queries that parse and check against the migrated schema, and
+   /// prunes of ones no longer in the code.
+   #[test]
+   fn test_catalogued_queries_still_parse() {
+      let cache = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(".sqlx");
+      let prepared: HashSet<String> = std::fs::read_dir(&cache)
+         .unwrap()
+         .map(|entry| {
+            let json: Value = serde_json::from_slice(&std::fs::read(entry.unwrap().path()).unwrap()).unwrap();
+            json["query"].as_str().unwrap().to_string()
+         })
+         .collect();
+
+      let mut catalogue = crate::planned_queries();
+      catalogue.extend(crate::listing::planned());
+      catalogue.extend(crate::retention::planned());
+      let mut names = HashSet::new();
+      for planned in &catalogue {
+         assert!(names.insert(planned.name), "{} catalogued twice", planned.name);
+         assert!(planned.bound().is_ok(), "{}", planned.name);
+         if planned.macro_checked {
+            assert!(
+               prepared.contains(planned.sql.as_ref()),
+               "{} no longer matches any query! in the code",
+               planned.name
+            );
+         }
+      }
+   }
+}
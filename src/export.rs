@@ -0,0 +1,291 @@
+//! `GET /tags/export`: every row of `twag_tags` and its archive as CSV or JSON, for a backup
+//! without psql. Tags in the trash are included, with their `deleted_at`, and archived tags with
+//! their `archived_at`. Rows are read and sent `streaming::BATCH` at a time, as the listing's are,
+//! so an export is never held in memory whole.
+//!
+//! The CSV's columns are named as `import` reads them, so an export can be imported elsewhere, its
+//! tags starting at their exported `access_count`. Counts are decimal, and times RFC 3339 in UTC to
+//! the second, in both formats.
+
+use axum::BoxError;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::streaming::Batches;
+use crate::taps::csv_field;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+   #[default]
+   Csv,
+   Json,
+}
+
+impl Format {
+   pub fn content_type(self) -> &'static str {
+      match self {
+         Format::Csv => "text/csv; charset=utf-8",
+         Format::Json => "application/json",
+      }
+   }
+
+   /// `twag-tags-2026-10-16.csv`, for the day it's taken.
+   pub fn filename(self, day: chrono::NaiveDate) -> String {
+      let extension = match self {
+         Format::Csv => "csv",
+         Format::Json => "json",
+      };
+      format!("twag-tags-{day}.{extension}")
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct ExportRow {
+   pub id: String,
+   pub label: Option<String>,
+   pub kit: Option<String>,
+   pub tag_model: String,
+   pub target_url: String,
+   pub notion_page: Option<String>,
+   pub access_count: Option<i32>,
+   pub last_seen_tap_count: Option<i32>,
+   pub enabled: bool,
+   pub maintenance: bool,
+   pub stateful: bool,
+   pub redirect_kind: String,
+   pub fallback_url: Option<String>,
+   pub check_target: bool,
+   pub expires_at: Option<String>,
+   pub activates_at: Option<String>,
+   pub created_at: Option<String>,
+   pub updated_at: Option<String>,
+   pub last_accessed: Option<String>,
+   pub programmed_at: Option<String>,
+   pub archived_at: Option<String>,
+   pub deleted_at: Option<String>,
+}
+
+const HEADER: &str = "id,label,kit,tag_model,target_url,notion_page,access_count,last_seen_tap_count,enabled,\
+                      maintenance,stateful,redirect_kind,fallback_url,check_target,expires_at,activates_at,created_at,\
+                      updated_at,last_accessed,programmed_at,archived_at,deleted_at\r\n";
+
+/// `column` as RFC 3339, e.g. `2026-10-16T21:04:05Z`.
+fn rfc3339(column: &str) -> String {
+   format!(r#"to_char({column} AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS {column}"#)
+}
+
+/// The columns `twag_tags` and its archive have in common, for the one to be exported after the
+/// other.
+const COLUMNS: &str = "id, label, kit, tag_model, target_url, notion_page_id, access_count, last_seen_tap_count, \
+                       enabled, maintenance, stateful, redirect_kind, fallback_url, check_target, expires_at, \
+                       activates_at, created_at, updated_at, last_accessed, programmed_at, deleted_at";
+
+/// Every tag, archived or not, in id order.
+pub fn query() -> QueryBuilder<'static, Postgres> {
+   let times = [
+      "expires_at",
+      "activates_at",
+      "created_at",
+      "updated_at",
+      "last_accessed",
+      "programmed_at",
+      "archived_at",
+      "deleted_at",
+   ]
+   .map(rfc3339)
+   .join(", ");
+   QueryBuilder::new(format!(
+      "SELECT id::text AS id, label, kit, tag_model, target_url, notion_page_id::text AS notion_page, \
+       access_count, last_seen_tap_count, enabled, maintenance, stateful, redirect_kind, \
+       fallback_url, check_target, {times} \
+       FROM (SELECT {COLUMNS}, NULL::timestamptz AS archived_at FROM twag_tags \
+       UNION ALL SELECT {COLUMNS}, archived_at FROM twag_tags_archive) AS tags ORDER BY id"
+   ))
+}
+
+fn csv_line(row: &ExportRow) -> String {
+   let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+   let count = |value: Option<i32>| value.map(|c| c.to_string()).unwrap_or_default();
+   let fields = [
+      row.id.clone(),
+      optional(&row.label),
+      optional(&row.kit),
+      row.tag_model.clone(),
+      row.target_url.clone(),
+      optional(&row.notion_page),
+      count(row.access_count),
+      count(row.last_seen_tap_count),
+      row.enabled.to_string(),
+      row.maintenance.to_string(),
+      row.stateful.to_string(),
+      row.redirect_kind.clone(),
+      optional(&row.fallback_url),
+      row.check_target.to_string(),
+      optional(&row.expires_at),
+      optional(&row.activates_at),
+      optional(&row.created_at),
+      optional(&row.updated_at),
+      optional(&row.last_accessed),
+      optional(&row.programmed_at),
+      optional(&row.archived_at),
+      optional(&row.deleted_at),
+   ];
+   let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+   fields.join(",") + "\r\n"
+}
+
+/// The export's body: the CSV header or JSON array's opening, `first`, then a chunk per batch of
+/// rows as it's read, then the closing. An error partway through ends the body early, as
+/// `streaming` does; the client sees a truncated file, which for JSON won't parse.
+pub fn body(
+   format: Format,
+   first: Vec<ExportRow>,
+   batches: Batches<ExportRow>,
+) -> impl Stream<Item = Result<String, BoxError>> + Send {
+   let (open, close) = match format {
+      Format::Csv => (HEADER, ""),
+      Format::Json => ("[", "]\n"),
+   };
+   let rest = stream::unfold(batches, |mut batches| async move {
+      let batch = batches.recv().await?;
+      Some((batch, batches))
+   });
+   let rows = stream::once(async { Ok(first) }).chain(rest);
+   let mut leading = true;
+   let rendered = rows.map(move |batch| -> Result<String, BoxError> {
+      let mut chunk = String::new();
+      for row in batch? {
+         match format {
+            Format::Csv => chunk.push_str(&csv_line(&row)),
+            Format::Json => {
+               if !std::mem::take(&mut leading) {
+                  chunk.push(',');
+               }
+               chunk.push_str(&serde_json::to_string(&row)?);
+               chunk.push('\n');
+            }
+         }
+      }
+      Ok(chunk)
+   });
+   stream::once(async move { Ok(open.to_string()) })
+      .chain(rendered)
+      .chain(stream::once(async move { Ok(close.to_string()) }))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use futures_util::TryStreamExt;
+   use tokio::sync::mpsc;
+
+   fn row(id: &str) -> ExportRow {
+      ExportRow {
+         id: id.to_string(),
+         label: Some("Lens, wide".to_string()),
+         kit: None,
+         tag_model: "ntag215".to_string(),
+         target_url: "https://example.com/?a=1&b=2".to_string(),
+         notion_page: None,
+         access_count: Some(15),
+         last_seen_tap_count: None,
+         enabled: true,
+         maintenance: false,
+         stateful: false,
+         redirect_kind: "temporary".to_string(),
+         fallback_url: None,
+         check_target: false,
+         expires_at: None,
+         activates_at: None,
+         created_at: Some("2026-10-16T21:04:05Z".to_string()),
+         updated_at: None,
+         last_accessed: None,
+         programmed_at: None,
+         archived_at: None,
+         deleted_at: None,
+      }
+   }
+
+   async fn export(format: Format, mut batches: Vec<Vec<ExportRow>>) -> String {
+      let first = if batches.is_empty() {
+         Vec::new()
+      } else {
+         batches.remove(0)
+      };
+      let (sender, receiver) = mpsc::channel(batches.len().max(1));
+      for batch in batches {
+         sender.send(Ok(batch)).await.unwrap();
+      }
+      drop(sender);
+      let chunks: Vec<String> = body(format, first, receiver).try_collect().await.unwrap();
+      chunks.concat()
+   }
+
+   #[tokio::test]
+   async fn test_csv_has_a_line_per_row_under_its_header() {
+      let csv = export(
+         Format::Csv,
+         vec![vec![row("04A1B2C3D4E5F6")], vec![row("04A1B2C3D4E5F7")]],
+      )
+      .await;
+      let lines: Vec<&str> = csv.lines().collect();
+      assert_eq!(lines.len(), 3);
+      assert_eq!(lines[0].split(',').count(), 22);
+      assert_eq!(
+         lines[1],
+         "04A1B2C3D4E5F6,\"Lens, wide\",,ntag215,https://example.com/?a=1&b=2,,15,,true,false,false,temporary,,false,,,\
+          2026-10-16T21:04:05Z,,,,,"
+      );
+      assert_eq!(export(Format::Csv, vec![]).await, HEADER);
+   }
+
+   #[tokio::test]
+   async fn test_csv_imports_with_its_counts() {
+      // Past what a tag's counter holds
+      let tag = ExportRow {
+         access_count: Some(0x100_0000),
+         ..row("04A1B2C3D4E5F6")
+      };
+      let csv = export(Format::Csv, vec![vec![tag]]).await;
+      let records = crate::import::parse(&csv).unwrap();
+      assert_eq!(records.len(), 1);
+      assert_eq!(records[0].row.id, "04A1B2C3D4E5F6");
+      assert_eq!(records[0].row.target_url, "https://example.com/?a=1&b=2");
+      assert_eq!(crate::import::start_count(&records[0]), Ok(0x100_0000));
+   }
+
+   #[tokio::test]
+   async fn test_json_is_one_array_across_batches() {
+      let json = export(
+         Format::Json,
+         vec![
+            vec![row("04A1B2C3D4E5F6"), row("04A1B2C3D4E5F7")],
+            vec![row("04A1B2C3D4E5F8")],
+         ],
+      )
+      .await;
+      let rows: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+      assert_eq!(rows.len(), 3);
+      assert_eq!(rows[2]["id"], "04A1B2C3D4E5F8");
+      assert_eq!(rows[0]["created_at"], "2026-10-16T21:04:05Z");
+      assert_eq!(rows[0]["last_accessed"], serde_json::Value::Null);
+      assert_eq!(export(Format::Json, vec![]).await, "[]\n");
+   }
+
+   #[test]
+   fn test_times_are_formatted_in_postgres() {
+      let query = query();
+      let sql = query.sql();
+      assert!(
+         sql.contains(r#"to_char(last_accessed AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS last_accessed"#)
+      );
+      assert!(sql.contains("FROM twag_tags UNION ALL SELECT"), "{sql}");
+      assert!(sql.ends_with("FROM twag_tags_archive) AS tags ORDER BY id"), "{sql}");
+      assert_eq!(
+         Format::Json.filename("2026-10-16".parse().unwrap()),
+         "twag-tags-2026-10-16.json"
+      );
+   }
+}
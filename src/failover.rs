@@ -0,0 +1,417 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::{header::HeaderName, HeaderValue};
+use sqlx::PgPool;
+
+use crate::models::TagUid;
+
+pub const X_TWAG_STALE: HeaderName = HeaderName::from_static("x-twag-stale");
+pub const STALE: HeaderValue = HeaderValue::from_static("true");
+/// Seconds a client is asked to wait before retrying a tag that couldn't be served at all.
+pub const RETRY_AFTER_SECS: u32 = 30;
+
+/// Last-known redirects kept for at most this many tags.
+const CACHE_CAPACITY: usize = 10_000;
+/// Taps recorded while the database is down are kept for at most this many; later ones are
+/// dropped and counted.
+const PENDING_CAPACITY: usize = 10_000;
+/// Tags preloaded into the cache at startup, unless `TWAG_PRELOAD_TAGS` says otherwise.
+const DEFAULT_PRELOAD: usize = 500;
+/// However many tags are asked for, a preload gives up after this long rather than hold up startup.
+const PRELOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether an error means Postgres couldn't be reached at all, as opposed to a query failing.
+pub fn is_unavailable(err: &sqlx::Error) -> bool {
+   match err {
+      sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => true,
+      // Class 08 is "connection exception"; 57P01..57P03 are shutdown and "cannot connect now"
+      sqlx::Error::Database(db) => db
+         .code()
+         .is_some_and(|code| code.starts_with("08") || matches!(&*code, "57P01" | "57P02" | "57P03")),
+      _ => false,
+   }
+}
+
+/// What a tag last redirected to, for serving when the database can't be reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedRedirect {
+   /// The default target; per-language targets aren't kept.
+   pub target_url: String,
+   pub count_token: Option<String>,
+}
+
+/// How many of the most-scanned tags `Failover::preload` loads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preload {
+   /// 0 turns preloading off.
+   pub tags: usize,
+}
+
+impl Default for Preload {
+   fn default() -> Self { Preload { tags: DEFAULT_PRELOAD } }
+}
+
+impl Preload {
+   /// From `TWAG_PRELOAD_TAGS`, at most the cache's capacity.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+      match var("TWAG_PRELOAD_TAGS") {
+         Some(tags) => tags
+            .trim()
+            .parse()
+            .ok()
+            .filter(|tags| *tags <= CACHE_CAPACITY)
+            .map(|tags| Preload { tags })
+            .ok_or_else(|| format!("TWAG_PRELOAD_TAGS must be a number of tags up to {CACHE_CAPACITY}, not '{tags}'")),
+         None => Ok(Preload::default()),
+      }
+   }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreloadError {
+   #[error("gave up after {}s", .0.as_secs())]
+   TimedOut(Duration),
+   #[error(transparent)]
+   Query(#[from] sqlx::Error),
+}
+
+/// What a preload did, for the log and the admin endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preloaded {
+   pub warmed: usize,
+   pub elapsed: Duration,
+}
+
+impl std::fmt::Display for Preloaded {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      write!(f, "Warmed {} tag(s) in {} ms", self.warmed, self.elapsed.as_millis())
+   }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolved<T> {
+   Fresh(T),
+   /// The database is unreachable; this is the last target seen for the tag, however old.
+   Stale(CachedRedirect),
+   /// The database is unreachable and the tag isn't cached.
+   Unavailable,
+}
+
+#[derive(Default)]
+struct Cache {
+   entries: HashMap<TagUid, CachedRedirect>,
+   /// Insertion order, oldest first, for eviction.
+   order: VecDeque<TagUid>,
+}
+
+/// State shared by the read path's fallbacks: the redirect cache and taps waiting for the database.
+#[derive(Clone, Default)]
+pub struct Failover {
+   cache: Arc<Mutex<Cache>>,
+   pending: Arc<Mutex<VecDeque<PendingTap>>>,
+   dropped_taps: Arc<AtomicU64>,
+}
+
+/// A tap that couldn't be recorded, held until the database returns.
+pub type PendingTap = crate::Tap;
+
+impl Failover {
+   pub fn remember(&self, id: TagUid, redirect: CachedRedirect) {
+      let mut cache = self.cache.lock().unwrap();
+      if cache.entries.insert(id, redirect).is_none() {
+         cache.order.push_back(id);
+         if cache.order.len() > CACHE_CAPACITY {
+            if let Some(oldest) = cache.order.pop_front() {
+               cache.entries.remove(&oldest);
+            }
+         }
+      }
+   }
+
+   pub fn forget(&self, id: &TagUid) {
+      let mut cache = self.cache.lock().unwrap();
+      if cache.entries.remove(id).is_some() {
+         cache.order.retain(|cached| cached != id);
+      }
+   }
+
+   pub fn cached(&self, id: &TagUid) -> Option<CachedRedirect> { self.cache.lock().unwrap().entries.get(id).cloned() }
+
+   pub fn cached_len(&self) -> usize { self.cache.lock().unwrap().entries.len() }
+
+   /// Caches `redirects`, hottest first. They're remembered coldest first, so the hottest are the
+   /// last to be evicted as scans of other tags fill the cache.
+   pub fn warm(&self, redirects: Vec<(TagUid, CachedRedirect)>) -> usize {
+      let warmed = redirects.len();
+      for (id, redirect) in redirects.into_iter().rev() {
+         self.remember(id, redirect);
+      }
+      warmed
+   }
+
+   /// Caches the `preload.tags` most-scanned tags in one query, so each can be served while
+   /// Postgres is unreachable without having been scanned since startup. Only tags a scan would
+   /// cache are loaded; see `resolve::Decision::cacheable`. Nothing is cached if the query takes
+   /// longer than `PRELOAD_TIMEOUT`.
+   pub async fn preload(&self, pool: &PgPool, preload: Preload) -> Result<Preloaded, PreloadError> {
+      let started = Instant::now();
+      let query = sqlx::query!(
+         r#"SELECT t.id AS "id: TagUid", t.target_url, t.count_token FROM twag_tags t
+            WHERE t.deleted_at IS NULL AND t.enabled
+               AND (t.expires_at IS NULL OR t.expires_at > now())
+               AND (t.activates_at IS NULL OR t.activates_at <= now())
+               AND NOT t.stateful AND NOT t.age_gate
+               AND (t.review_state = 'ok' OR (t.review_state = 'needs_review' AND NOT t.quarantine_on_review))
+               AND NOT EXISTS (SELECT 1 FROM twag_tag_vcards v WHERE v.tag_id = t.id)
+               AND (t.ignore_kit_schedule OR NOT EXISTS (SELECT 1 FROM twag_kit_schedules s
+                  WHERE s.kit = t.kit AND s.starts_at <= now() AND s.ends_at > now()))
+            ORDER BY t.access_count DESC NULLS LAST, t.id
+            LIMIT $1"#,
+         preload.tags as i64,
+      )
+      .fetch_all(pool);
+      let rows = tokio::time::timeout(PRELOAD_TIMEOUT, query)
+         .await
+         .map_err(|_| PreloadError::TimedOut(PRELOAD_TIMEOUT))??;
+      let warmed = self.warm(
+         rows
+            .into_iter()
+            .map(|row| {
+               let redirect = CachedRedirect {
+                  target_url: row.target_url,
+                  count_token: row.count_token,
+               };
+               (row.id, redirect)
+            })
+            .collect(),
+      );
+      Ok(Preloaded {
+         warmed,
+         elapsed: started.elapsed(),
+      })
+   }
+
+   /// Runs a lookup, falling back to the cache only when the database is unreachable. Other
+   /// errors are returned as they are.
+   pub async fn resolve<T>(
+      &self,
+      id: &TagUid,
+      lookup: impl Future<Output = Result<T, sqlx::Error>>,
+   ) -> Result<Resolved<T>, sqlx::Error> {
+      match lookup.await {
+         Ok(found) => Ok(Resolved::Fresh(found)),
+         Err(e) if is_unavailable(&e) => Ok(self.cached(id).map_or(Resolved::Unavailable, Resolved::Stale)),
+         Err(e) => Err(e),
+      }
+   }
+
+   pub fn defer_tap(&self, tap: PendingTap) {
+      #[cfg(feature = "chaos")]
+      if crate::chaos::drop_tap() {
+         self.dropped_taps.fetch_add(1, Ordering::Relaxed);
+         return;
+      }
+      let mut pending = self.pending.lock().unwrap();
+      if pending.len() >= PENDING_CAPACITY {
+         self.dropped_taps.fetch_add(1, Ordering::Relaxed);
+         return;
+      }
+      pending.push_back(tap);
+   }
+
+   pub fn next_pending(&self) -> Option<PendingTap> { self.pending.lock().unwrap().pop_front() }
+
+   /// Puts back a tap that still couldn't be recorded, ahead of the rest.
+   pub fn requeue(&self, tap: PendingTap) { self.pending.lock().unwrap().push_front(tap) }
+
+   pub fn pending_len(&self) -> usize { self.pending.lock().unwrap().len() }
+
+   pub fn dropped_taps(&self) -> u64 { self.dropped_taps.load(Ordering::Relaxed) }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn id() -> TagUid { "055B88A23C1250".parse().unwrap() }
+
+   fn redirect(url: &str) -> CachedRedirect {
+      CachedRedirect {
+         target_url: url.to_string(),
+         count_token: None,
+      }
+   }
+
+   /// Stands in for a pool whose connections all fail.
+   async fn failing_pool() -> Result<Option<String>, sqlx::Error> { Err(sqlx::Error::PoolTimedOut) }
+
+   #[tokio::test]
+   async fn test_cached_tags_keep_redirecting_when_the_pool_fails() {
+      let failover = Failover::default();
+      failover.remember(id(), redirect("https://example.com/"));
+
+      let resolved = failover.resolve(&id(), failing_pool()).await.unwrap();
+      assert_eq!(resolved, Resolved::Stale(redirect("https://example.com/")));
+
+      let other: TagUid = "04A1B2C3D4E5F6".parse().unwrap();
+      assert_eq!(
+         failover.resolve(&other, failing_pool()).await.unwrap(),
+         Resolved::Unavailable
+      );
+   }
+
+   #[tokio::test]
+   async fn test_fresh_lookups_win_over_the_cache() {
+      let failover = Failover::default();
+      failover.remember(id(), redirect("https://example.org/old"));
+      let resolved = failover
+         .resolve(&id(), async { Ok(Some("https://example.com/".to_string())) })
+         .await
+         .unwrap();
+      assert_eq!(resolved, Resolved::Fresh(Some("https://example.com/".to_string())));
+   }
+
+   #[tokio::test]
+   async fn test_query_errors_are_not_masked() {
+      let failover = Failover::default();
+      failover.remember(id(), redirect("https://example.com/"));
+      let result = failover
+         .resolve(&id(), async { Err::<(), _>(sqlx::Error::RowNotFound) })
+         .await;
+      assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+   }
+
+   #[test]
+   fn test_cache_is_bounded() {
+      let failover = Failover::default();
+      for i in 0..=CACHE_CAPACITY {
+         let id: TagUid = format!("04{:012X}", i).parse().unwrap();
+         failover.remember(id, redirect("https://example.com/"));
+      }
+      assert_eq!(failover.cached_len(), CACHE_CAPACITY);
+      assert_eq!(failover.cached(&"04000000000000".parse().unwrap()), None);
+
+      // Refreshing an entry doesn't grow the cache
+      failover.remember("04000000000001".parse().unwrap(), redirect("https://example.org/"));
+      assert_eq!(failover.cached_len(), CACHE_CAPACITY);
+   }
+
+   #[tokio::test]
+   async fn test_warmed_tags_serve_without_the_pool() {
+      let failover = Failover::default();
+      let hot: TagUid = "04A1B2C3D4E5F6".parse().unwrap();
+      let warmed = failover.warm(vec![
+         (hot, redirect("https://example.com/hot")),
+         (id(), redirect("https://example.com/")),
+      ]);
+      assert_eq!(warmed, 2);
+      assert_eq!(
+         failover.resolve(&hot, failing_pool()).await.unwrap(),
+         Resolved::Stale(redirect("https://example.com/hot"))
+      );
+      assert_eq!(
+         failover.resolve(&id(), failing_pool()).await.unwrap(),
+         Resolved::Stale(redirect("https://example.com/"))
+      );
+   }
+
+   #[test]
+   fn test_hottest_warmed_tags_are_evicted_last() {
+      let failover = Failover::default();
+      let ids: Vec<TagUid> = (0..CACHE_CAPACITY)
+         .map(|i| format!("04{:012X}", i).parse().unwrap())
+         .collect();
+      failover.warm(ids.iter().map(|id| (*id, redirect("https://example.com/"))).collect());
+      // A scan of a tag that wasn't preloaded evicts the coldest
+      failover.remember(id(), redirect("https://example.org/"));
+      assert!(failover.cached(&ids[0]).is_some());
+      assert_eq!(failover.cached(&ids[CACHE_CAPACITY - 1]), None);
+   }
+
+   #[test]
+   fn test_preload_settings() {
+      fn vars(tags: Option<&'static str>) -> impl Fn(&str) -> Option<String> { move |_| tags.map(str::to_string) }
+      assert_eq!(Preload::from_vars(vars(None)), Ok(Preload { tags: DEFAULT_PRELOAD }));
+      assert_eq!(Preload::from_vars(vars(Some("0"))), Ok(Preload { tags: 0 }));
+      assert!(Preload::from_vars(vars(Some("all"))).is_err());
+      assert!(Preload::from_vars(vars(Some("1000000"))).is_err());
+   }
+
+   #[test]
+   fn test_forget() {
+      let failover = Failover::default();
+      failover.remember(id(), redirect("https://example.com/"));
+      failover.forget(&id());
+      assert_eq!(failover.cached(&id()), None);
+      assert_eq!(failover.cached_len(), 0);
+   }
+
+   #[test]
+   fn test_unavailable_errors() {
+      assert!(is_unavailable(&sqlx::Error::PoolTimedOut));
+      assert!(is_unavailable(&sqlx::Error::Io(
+         std::io::ErrorKind::ConnectionRefused.into()
+      )));
+      assert!(!is_unavailable(&sqlx::Error::RowNotFound));
+   }
+
+   #[cfg(feature = "chaos")]
+   #[tokio::test]
+   async fn test_injected_pool_failures_fall_back_to_the_cache() {
+      use crate::chaos::{self, Faults};
+      use crate::pool::ScalingPool;
+
+      chaos::inject(Faults {
+         acquire_fail_percent: 100,
+         ..Faults::default()
+      });
+      let pool = ScalingPool::lazy();
+      let failover = Failover::default();
+      failover.remember(id(), redirect("https://example.com/"));
+
+      let lookup = async { pool.acquire().await.map(|_| ()) };
+      assert_eq!(
+         failover.resolve(&id(), lookup).await.unwrap(),
+         Resolved::Stale(redirect("https://example.com/"))
+      );
+      let other: TagUid = "04A1B2C3D4E5F6".parse().unwrap();
+      let lookup = async { pool.acquire().await.map(|_| ()) };
+      assert_eq!(failover.resolve(&other, lookup).await.unwrap(), Resolved::Unavailable);
+   }
+
+   #[cfg(feature = "chaos")]
+   #[test]
+   fn test_injected_tap_drops_are_counted() {
+      use crate::chaos::{self, Faults};
+
+      let tap = PendingTap {
+         id: id(),
+         at: chrono::Utc::now(),
+         tap_count: None,
+         detail: None,
+         lang: None,
+         served_permanent: false,
+         counted: true,
+         during_maintenance: false,
+         served: crate::taps::served_stale(),
+         tag_key: None,
+         arrival_nonce: None,
+      };
+      let failover = Failover::default();
+      chaos::inject(Faults {
+         tap_drop_percent: 100,
+         ..Faults::default()
+      });
+      failover.defer_tap(tap.clone());
+      failover.defer_tap(tap.clone());
+      assert_eq!((failover.pending_len(), failover.dropped_taps()), (0, 2));
+
+      chaos::inject(Faults::default());
+      failover.defer_tap(tap);
+      assert_eq!((failover.pending_len(), failover.dropped_taps()), (1, 2));
+   }
+}
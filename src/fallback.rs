@@ -0,0 +1,97 @@
+//! A tag's fallback target, for when its own is down: a Notion share link that's been revoked, or
+//! a site that's gone away. Tags with `check_target` have their target probed before each redirect,
+//! and a scan goes to `fallback_url` instead when the probe can't connect or gets a 5xx. A tag
+//! without a fallback isn't probed at all, since there'd be nowhere else to send it.
+//!
+//! The probe is bounded by `PROBE_TIMEOUT`, lookup included, so a target that hangs delays the scan
+//! by that much and no more. Probes share one `Prober`, so a target probed again reuses its
+//! connection.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tracing::info;
+
+use crate::net::{FetchError, FetchPolicy, Prober};
+
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// The prober scans share, built for the fetch policy of the first probe and again when it's
+/// reloaded. Clones share it.
+#[derive(Clone, Default)]
+pub struct SharedProber(Arc<Mutex<Option<Prober>>>);
+
+impl SharedProber {
+   /// The prober for `policy`, built if the last one was for another.
+   fn get(&self, policy: &FetchPolicy) -> Result<Prober, FetchError> {
+      let mut prober = self.0.lock().unwrap();
+      match &*prober {
+         Some(prober) if prober.policy() == policy => Ok(prober.clone()),
+         _ => Ok(prober.insert(Prober::new(policy.clone())?).clone()),
+      }
+   }
+}
+
+/// Whether a probe's result means the target is down. A 4xx is the target answering, and an
+/// address the fetch policy refuses can't be probed, so neither is reason to fall back.
+fn is_down(probed: &Result<StatusCode, FetchError>) -> bool {
+   match probed {
+      Ok(status) => status.is_server_error(),
+      Err(FetchError::Http(_) | FetchError::Resolve(_) | FetchError::NoAddresses) => true,
+      Err(_) => false,
+   }
+}
+
+/// Probes `url`, giving up after `PROBE_TIMEOUT`, which counts as down.
+pub async fn target_down(prober: &SharedProber, url: &str, policy: &FetchPolicy) -> bool {
+   let probe = async { prober.get(policy)?.probe(url).await };
+   match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+      Ok(probed) => {
+         if let Err(e) = &probed {
+            info!("Probe of '{}' failed: {}", url, e);
+         }
+         is_down(&probed)
+      }
+      Err(_) => {
+         info!("Probe of '{}' timed out", url);
+         true
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_only_failures_to_answer_are_down() {
+      assert!(is_down(&Ok(StatusCode::BAD_GATEWAY)));
+      assert!(is_down(&Ok(StatusCode::INTERNAL_SERVER_ERROR)));
+      assert!(!is_down(&Ok(StatusCode::OK)));
+      assert!(!is_down(&Ok(StatusCode::FOUND)));
+      assert!(!is_down(&Ok(StatusCode::NOT_FOUND)));
+      assert!(is_down(&Err(FetchError::NoAddresses)));
+      assert!(!is_down(&Err(FetchError::Blocked("10.0.0.1".parse().unwrap()))));
+      assert!(!is_down(&Err(FetchError::UnsupportedScheme("ftp".to_string()))));
+   }
+
+   #[tokio::test]
+   async fn test_internal_targets_are_left_alone() {
+      let prober = SharedProber::default();
+      assert!(!target_down(&prober, "http://127.0.0.1/", &FetchPolicy::default()).await);
+   }
+
+   #[test]
+   fn test_the_prober_follows_the_policy() {
+      let prober = SharedProber::default();
+      let policy = FetchPolicy {
+         extra_blocklist: vec!["192.0.2.0/24".parse().unwrap()],
+      };
+      assert_eq!(
+         *prober.get(&FetchPolicy::default()).unwrap().policy(),
+         FetchPolicy::default()
+      );
+      assert_eq!(*prober.get(&policy).unwrap().policy(), policy);
+   }
+}
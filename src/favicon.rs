@@ -0,0 +1,219 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, ImageFormat};
+use lazy_regex::{regex, regex_captures};
+use url::Url;
+
+use crate::net::{self, FetchError, FetchPolicy};
+
+/// Icons larger than this are refused rather than decoded.
+pub const MAX_ICON_BYTES: usize = 32 * 1024;
+/// Icons are shrunk to at most this many pixels square.
+const ICON_SIZE: u32 = 32;
+/// How long a fetched icon is served before it's fetched again.
+pub const TTL_SECS: i64 = 7 * 24 * 60 * 60;
+/// How long a failed fetch is remembered before the host is tried again.
+pub const NEGATIVE_TTL_SECS: i64 = 24 * 60 * 60;
+
+pub const CACHE_CONTROL: &str = "public, max-age=604800";
+pub const MISSING_CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Debug, thiserror::Error)]
+pub enum FaviconError {
+   #[error("Not a bare host name: '{0}'")]
+   InvalidHost(String),
+   #[error(transparent)]
+   Fetch(#[from] FetchError),
+   #[error("Host answered {0}")]
+   Status(reqwest::StatusCode),
+   #[error("Icon larger than {} bytes", MAX_ICON_BYTES)]
+   TooLarge,
+   #[error("Failed to decode icon: {0}")]
+   Decode(#[from] image::ImageError),
+   #[error("Host has no icon")]
+   NoIcon,
+}
+
+/// Accepts a bare, lowercased host name or IP address: no scheme, port, path, or credentials.
+pub fn parse_host(raw: &str) -> Result<String, FaviconError> {
+   let invalid = || FaviconError::InvalidHost(raw.to_string());
+   let url = Url::parse(&format!("https://{}/", raw.trim())).map_err(|_| invalid())?;
+   let host = url.host().ok_or_else(invalid)?.to_string();
+   let bare = url.username().is_empty() && url.password().is_none() && url.port().is_none() && url.path() == "/";
+   match bare && host.eq_ignore_ascii_case(raw.trim()) {
+      true => Ok(host),
+      false => Err(invalid()),
+   }
+}
+
+/// The host an http(s) target URL points at, as `parse_host` would normalize it.
+pub fn host_of(target_url: &str) -> Option<String> {
+   let url = Url::parse(target_url).ok()?;
+   match url.scheme() {
+      "http" | "https" => url.host().map(|host| host.to_string()),
+      _ => None,
+   }
+}
+
+/// A row of the icon cache. `png` is `None` for a remembered failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedIcon {
+   pub png: Option<Vec<u8>>,
+   /// Unix time of the fetch.
+   pub fetched_at: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup {
+   Serve(Vec<u8>),
+   /// A recent fetch failed; don't try again yet.
+   Missing,
+   Refetch,
+}
+
+pub fn lookup(cached: Option<CachedIcon>, now: i64) -> Lookup {
+   match cached {
+      Some(CachedIcon {
+         png: Some(png),
+         fetched_at,
+      }) if now - fetched_at < TTL_SECS => Lookup::Serve(png),
+      Some(CachedIcon { png: None, fetched_at }) if now - fetched_at < NEGATIVE_TTL_SECS => Lookup::Missing,
+      _ => Lookup::Refetch,
+   }
+}
+
+/// The `href` of the first `<link rel="icon">` (or `shortcut icon`, `apple-touch-icon`, …) in a
+/// page.
+fn icon_href(html: &str) -> Option<String> {
+   regex!(r#"(?is)<link\b[^>]*>"#).find_iter(html).find_map(|tag| {
+      let tag = tag.as_str();
+      let (_, rel) = regex_captures!(r#"(?i)\brel\s*=\s*["']?([^"'>]*)"#, tag)?;
+      if !rel
+         .split_ascii_whitespace()
+         .any(|rel| rel.to_ascii_lowercase().ends_with("icon"))
+      {
+         return None;
+      }
+      let (_, quoted, bare) = regex_captures!(r#"(?i)\bhref\s*=\s*(?:["']([^"']*)["']|([^\s"'>]+))"#, tag)?;
+      Some([quoted, bare].concat().replace("&amp;", "&"))
+   })
+}
+
+async fn fetch_image(url: &str, policy: &FetchPolicy) -> Result<Vec<u8>, FaviconError> {
+   let response = net::safe_fetch(url, policy).await?;
+   if !response.status.is_success() {
+      return Err(FaviconError::Status(response.status));
+   }
+   if response.body.len() > MAX_ICON_BYTES {
+      return Err(FaviconError::TooLarge);
+   }
+   Ok(response.body)
+}
+
+/// Re-encodes an icon as a PNG no larger than `ICON_SIZE` square.
+fn to_png(icon: &[u8]) -> Result<Vec<u8>, FaviconError> {
+   let mut image = image::load_from_memory(icon)?;
+   if image.width() > ICON_SIZE || image.height() > ICON_SIZE {
+      image = image.resize(ICON_SIZE, ICON_SIZE, FilterType::Lanczos3);
+   }
+   let mut png = Vec::new();
+   image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+   Ok(png)
+}
+
+/// Fetches a host's `/favicon.ico`, falling back to the icon its home page links to.
+pub async fn fetch_icon(host: &str, policy: &FetchPolicy) -> Result<Vec<u8>, FaviconError> {
+   let host = parse_host(host)?;
+   let icon = match fetch_image(&format!("https://{}/favicon.ico", host), policy).await {
+      Ok(icon) => icon,
+      // Never fall back to the page of a host that's refused outright
+      Err(FaviconError::Fetch(e @ (FetchError::Blocked(_) | FetchError::NoAddresses))) => return Err(e.into()),
+      Err(_) => {
+         let page = net::safe_fetch(&format!("https://{}/", host), policy).await?;
+         let is_html = page.content_type.as_deref().is_some_and(|ct| ct.contains("html"));
+         let href = is_html
+            .then(|| icon_href(&String::from_utf8_lossy(&page.body)))
+            .flatten()
+            .ok_or(FaviconError::NoIcon)?;
+         let url = page.final_url.join(&href).map_err(FetchError::from)?;
+         fetch_image(url.as_str(), policy).await?
+      }
+   };
+   to_png(&icon)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const NOW: i64 = 1_800_000_000;
+
+   fn icon(png: Option<&[u8]>, age: i64) -> Option<CachedIcon> {
+      Some(CachedIcon {
+         png: png.map(<[u8]>::to_vec),
+         fetched_at: NOW - age,
+      })
+   }
+
+   #[test]
+   fn test_failures_are_not_refetched_until_they_expire() {
+      assert_eq!(lookup(icon(None, 60), NOW), Lookup::Missing);
+      assert_eq!(lookup(icon(None, NEGATIVE_TTL_SECS - 1), NOW), Lookup::Missing);
+      assert_eq!(lookup(icon(None, NEGATIVE_TTL_SECS), NOW), Lookup::Refetch);
+   }
+
+   #[test]
+   fn test_icons_are_served_until_they_expire() {
+      assert_eq!(lookup(icon(Some(b"png"), 60), NOW), Lookup::Serve(b"png".to_vec()));
+      assert_eq!(lookup(icon(Some(b"png"), TTL_SECS), NOW), Lookup::Refetch);
+      assert_eq!(lookup(None, NOW), Lookup::Refetch);
+   }
+
+   #[test]
+   fn test_parse_host() {
+      assert_eq!(parse_host("Example.COM").unwrap(), "example.com");
+      assert_eq!(parse_host("93.184.216.34").unwrap(), "93.184.216.34");
+      for raw in [
+         "",
+         "example.com:8080",
+         "example.com/admin",
+         "user@example.com",
+         "https://example.com",
+         "example.com?x=1",
+      ] {
+         assert!(parse_host(raw).is_err(), "{raw} should be refused");
+      }
+   }
+
+   #[test]
+   fn test_host_of() {
+      assert_eq!(host_of("https://Example.com:8443/a?b").as_deref(), Some("example.com"));
+      assert_eq!(host_of("mailto:someone@example.com"), None);
+   }
+
+   #[tokio::test]
+   async fn test_internal_hosts_are_refused() {
+      let policy = FetchPolicy::default();
+      for host in ["127.0.0.1", "169.254.169.254", "[::1]", "10.0.0.1"] {
+         assert!(
+            matches!(
+               fetch_icon(host, &policy).await,
+               Err(FaviconError::Fetch(FetchError::Blocked(_)))
+            ),
+            "{host} should be refused"
+         );
+      }
+   }
+
+   #[test]
+   fn test_icon_href() {
+      let html = r#"<head><link rel="stylesheet" href="/a.css">
+         <LINK href='/icons/fav.png?v=1&amp;s=2' REL="shortcut icon"></head>"#;
+      assert_eq!(icon_href(html).as_deref(), Some("/icons/fav.png?v=1&s=2"));
+      assert_eq!(
+         icon_href(r#"<link rel=apple-touch-icon href=/touch.png>"#).as_deref(),
+         Some("/touch.png")
+      );
+      assert_eq!(icon_href(r#"<link rel="preload" href="/font.woff2">"#), None);
+   }
+}
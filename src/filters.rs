@@ -0,0 +1,136 @@
+//! Template filters for the escaping contexts askama's default HTML escaping doesn't cover.
+//!
+//! - Plain text and ordinary attribute values: rely on askama's default escaping.
+//! - `href`, `src` and refresh URLs: `safe_href`, which re-validates the URL first.
+//! - Anything inside a `<script>` element: `script_json`.
+//! - QR codes: `qr_svg`, whose markup is generated entirely from the encoded modules.
+//! - Target URLs shown as text on admin pages: `display_url`, then default escaping.
+//! - Tag ids shown as text: `with_checksum`, then default escaping.
+
+use std::fmt::Display;
+
+use askama::filters::Safe;
+use serde::Serialize;
+
+/// Used in place of a URL that isn't safe to follow.
+const INERT_HREF: &str = "#";
+
+/// Schemes a user-supplied link may use. Anything else (`javascript:`, `data:`, `vbscript:`, ...)
+/// is replaced with an inert fragment.
+const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+/// Re-validates a URL for use in a URL attribute and returns it in its serialized (percent-encoded)
+/// form. Root-relative paths are allowed; scheme-relative (`//host`) and unparseable URLs aren't.
+/// The result still goes through askama's HTML escaping, so `&` in queries is encoded correctly.
+pub fn href(raw: &str) -> String {
+   let raw = raw.trim();
+   if raw.starts_with('/') && !raw.starts_with("//") && !raw.starts_with("/\\") {
+      let base = url::Url::parse("http://localhost").unwrap();
+      return match base.join(raw) {
+         Ok(url) => url[url::Position::BeforePath..].to_string(),
+         Err(_) => INERT_HREF.to_string(),
+      };
+   }
+   match url::Url::parse(raw) {
+      Ok(url) if ALLOWED_SCHEMES.contains(&url.scheme()) => url.to_string(),
+      _ => INERT_HREF.to_string(),
+   }
+}
+
+/// Serializes a value as JSON that is safe to place inside a `<script>` element: `<`, `>` and `&`
+/// are escaped so the data can't close the element or open a comment, and U+2028/U+2029 so it
+/// stays valid as JavaScript.
+pub fn to_script_json<T: Serialize + ?Sized>(value: &T) -> Result<String, serde_json::Error> {
+   let json = serde_json::to_string(value)?;
+   Ok(json
+      .replace('<', "\\u003c")
+      .replace('>', "\\u003e")
+      .replace('&', "\\u0026")
+      .replace('\u{2028}', "\\u2028")
+      .replace('\u{2029}', "\\u2029"))
+}
+
+pub fn safe_href<T: Display>(value: T, _: &dyn askama::Values) -> askama::Result<String> {
+   Ok(href(&value.to_string()))
+}
+
+/// Named to stay clear of askama's optional built-in `json` filter.
+pub fn script_json<T: Serialize>(value: T, _: &dyn askama::Values) -> askama::Result<Safe<String>> {
+   to_script_json(&value)
+      .map(Safe)
+      .map_err(|e| askama::Error::Custom(Box::new(e)))
+}
+
+/// Renders a value as an inline SVG QR code. The input only decides which modules are dark; none
+/// of it reaches the markup.
+pub fn qr_svg<T: Display>(value: T, _: &dyn askama::Values) -> askama::Result<Safe<String>> {
+   crate::qr::Matrix::encode(&value.to_string())
+      .map(|matrix| Safe(matrix.to_svg()))
+      .map_err(|e| askama::Error::Custom(Box::new(e)))
+}
+
+/// A stored target URL in its readable form; see `target_url::display_url`. Never for `href`s.
+pub fn display_url<T: Display>(value: T, _: &dyn askama::Values) -> askama::Result<String> {
+   Ok(crate::target_url::display_url(&value.to_string()).into_owned())
+}
+
+/// A tag id followed by its checksum, `055B88A23C1250 · XQ`; see `TagUid::checksum`. Anything
+/// that isn't an id is shown as it is. Never for `href`s.
+pub fn with_checksum<T: Display>(value: T, _: &dyn askama::Values) -> askama::Result<String> {
+   let value = value.to_string();
+   Ok(match crate::models::TagUid::new(&value) {
+      Ok(id) => format!("{value} · {}", id.checksum()),
+      Err(_) => value,
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_href_keeps_ordinary_links() {
+      assert_eq!(href("https://example.com/a?b=1&c=2"), "https://example.com/a?b=1&c=2");
+      assert_eq!(href("  http://example.com  "), "http://example.com/");
+      assert_eq!(href("mailto:ada@example.com"), "mailto:ada@example.com");
+      assert_eq!(href("tel:+442079460000"), "tel:+442079460000");
+      assert_eq!(href("/tag/055B88A23C1250.vcf"), "/tag/055B88A23C1250.vcf");
+   }
+
+   #[test]
+   fn test_href_rejects_script_urls() {
+      assert_eq!(href("javascript:alert(1)"), "#");
+      assert_eq!(href("JaVaScRiPt:alert(1)"), "#");
+      assert_eq!(href(" \tjavascript:alert(1)"), "#");
+      assert_eq!(href("java\nscript:alert(1)"), "#");
+      assert_eq!(href("data:text/html,<script>alert(1)</script>"), "#");
+      assert_eq!(href("vbscript:msgbox"), "#");
+   }
+
+   #[test]
+   fn test_href_rejects_scheme_relative_and_garbage() {
+      assert_eq!(href("//evil.example/"), "#");
+      assert_eq!(href("/\\evil.example/"), "#");
+      assert_eq!(href("not a url"), "#");
+      assert_eq!(href(""), "#");
+   }
+
+   #[test]
+   fn test_href_percent_encodes() {
+      assert_eq!(
+         href("https://example.com/\"><script>"),
+         "https://example.com/%22%3E%3Cscript%3E"
+      );
+      assert_eq!(
+         href("https://example.com/\u{202E}gpj.exe"),
+         "https://example.com/%E2%80%AEgpj.exe"
+      );
+   }
+
+   #[test]
+   fn test_script_json_cannot_break_out() {
+      let json = to_script_json("</script><!--&\u{2028}").unwrap();
+      assert_eq!(json, r#""\u003c/script\u003e\u003c!--\u0026\u2028""#);
+      assert_eq!(serde_json::from_str::<String>(&json).unwrap(), "</script><!--&\u{2028}");
+   }
+}
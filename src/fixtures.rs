@@ -0,0 +1,495 @@
+//! Demo data: tags with labels, kits, aliases, fake Notion pages and a few months of taps, for
+//! evaluating twag and developing its pages without a real collection. Everything comes from one
+//! seed, so the same seed and day always give the same data, for screenshots and tests alike.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Timelike, Utc};
+use sqlx::{PgConnection, PgPool};
+
+use crate::models::{NotionPageId, TagUid};
+use crate::pool::ScalingPool;
+use crate::short_code::{self, CodeRng};
+use crate::targets;
+
+pub const DEFAULT_SEED: u64 = 1;
+pub const DEFAULT_TAGS: u32 = 40;
+pub const DEFAULT_DAYS: u32 = 90;
+const MAX_TAGS: u32 = 10_000;
+const MAX_DAYS: u32 = 366;
+
+/// Relative tap volume by hour (UTC): quiet overnight, busy around lunch and in the evening.
+const HOURLY: [u64; 24] = [
+   1, 1, 1, 1, 1, 2, 4, 7, 9, 8, 8, 10, 12, 10, 8, 8, 9, 11, 14, 15, 13, 9, 5, 2,
+];
+/// Mean taps per day; most tags see a handful, a few see many, some none at all.
+const POPULARITY: [u64; 8] = [0, 1, 1, 2, 3, 5, 8, 20];
+
+const THINGS: &[&str] = &[
+   "Pantry shelf",
+   "Garage bin",
+   "Camping gear",
+   "Winter coats",
+   "Holiday decorations",
+   "Tool chest",
+   "Craft supplies",
+   "Board games",
+   "Spare cables",
+   "Kitchen drawer",
+   "Attic box",
+   "First aid kit",
+   "Seed packets",
+   "Bike repair kit",
+];
+const KITS: &[Option<&str>] = &[None, Some("kitchen"), Some("garage"), Some("attic"), Some("office")];
+const LANGS: &[&str] = &["en", "en", "en", "en", "de", "fr", "es", "pt-BR"];
+const COUNTRIES: &[&str] = &["US", "US", "US", "GB", "DE", "FR", "CA", "BR"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spec {
+   pub seed: u64,
+   pub tags: u32,
+   /// How many days of taps, ending yesterday.
+   pub days: u32,
+}
+
+impl Default for Spec {
+   fn default() -> Self {
+      Spec {
+         seed: DEFAULT_SEED,
+         tags: DEFAULT_TAGS,
+         days: DEFAULT_DAYS,
+      }
+   }
+}
+
+impl Spec {
+   /// From `TWAG_SEED`, `TWAG_SEED_TAGS` and `TWAG_SEED_DAYS`.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+      fn parse<T: std::str::FromStr>(
+         var: &impl Fn(&str) -> Option<String>,
+         name: &str,
+         default: T,
+      ) -> Result<T, String> {
+         match var(name).filter(|s| !s.is_empty()) {
+            Some(s) => s.parse().map_err(|_| format!("Invalid {}: '{}'", name, s)),
+            None => Ok(default),
+         }
+      }
+      Spec {
+         seed: parse(&var, "TWAG_SEED", DEFAULT_SEED)?,
+         tags: parse(&var, "TWAG_SEED_TAGS", DEFAULT_TAGS)?,
+         days: parse(&var, "TWAG_SEED_DAYS", DEFAULT_DAYS)?,
+      }
+      .validated()
+   }
+
+   pub fn validated(self) -> Result<Self, String> {
+      if self.tags > MAX_TAGS {
+         return Err(format!("At most {} tags can be seeded", MAX_TAGS));
+      }
+      if self.days > MAX_DAYS {
+         return Err(format!("At most {} days of taps can be seeded", MAX_DAYS));
+      }
+      Ok(self)
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeTap {
+   pub at: DateTime<Utc>,
+   pub tap_count: i32,
+   pub lang: &'static str,
+   pub country: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeTag {
+   pub id: TagUid,
+   pub label: String,
+   pub kit: Option<&'static str>,
+   pub target_url: String,
+   pub notion_page_id: Option<NotionPageId>,
+   pub created_at: DateTime<Utc>,
+   pub expires_on: Option<NaiveDate>,
+   pub maintenance: bool,
+   pub short_code: Option<String>,
+   /// Codes the tag had before `short_code`, still listed as aliases.
+   pub old_codes: Vec<String>,
+   /// Oldest first.
+   pub taps: Vec<FakeTap>,
+}
+
+fn below(rng: &mut CodeRng, n: u64) -> u64 { rng.next_u64() % n }
+
+fn percent(rng: &mut CodeRng, p: u64) -> bool { below(rng, 100) < p }
+
+fn pick<T: Copy>(rng: &mut CodeRng, items: &[T]) -> T { items[below(rng, items.len() as u64) as usize] }
+
+fn weighted(rng: &mut CodeRng, weights: &[u64]) -> usize {
+   let mut roll = below(rng, weights.iter().sum());
+   weights
+      .iter()
+      .position(|&weight| {
+         let hit = roll < weight;
+         roll = roll.saturating_sub(weight);
+         hit
+      })
+      .unwrap_or(0)
+}
+
+fn midnight(day: NaiveDate) -> DateTime<Utc> { day.and_hms_opt(0, 0, 0).unwrap().and_utc() }
+
+/// A 7-byte UID with NXP's manufacturer byte, as on most stickers.
+fn fake_uid(rng: &mut CodeRng) -> TagUid {
+   let mut bytes = [0x04; 7];
+   bytes[1..].copy_from_slice(&rng.next_u64().to_be_bytes()[..6]);
+   TagUid::Seven(bytes)
+}
+
+fn fake_page_id(rng: &mut CodeRng) -> NotionPageId {
+   NotionPageId::new(format!("{:016x}{:016x}", rng.next_u64(), rng.next_u64())).expect("32 hex digits")
+}
+
+/// The demo tags for `spec`, with taps on the days before `today`.
+pub fn generate(spec: &Spec, today: NaiveDate) -> Vec<FakeTag> {
+   let mut rng = CodeRng::seeded(spec.seed);
+   let first_day = today - TimeDelta::days(i64::from(spec.days));
+   let mut ids = HashSet::new();
+   let mut codes = HashSet::new();
+   let mut code = |rng: &mut CodeRng| loop {
+      let code = rng.code(short_code::LEN);
+      if codes.insert(code.clone()) {
+         break code;
+      }
+   };
+
+   (0..spec.tags)
+      .map(|i| {
+         let id = loop {
+            let id = fake_uid(&mut rng);
+            if ids.insert(id) {
+               break id;
+            }
+         };
+         let notion_page_id = percent(&mut rng, 80).then(|| fake_page_id(&mut rng));
+         let target_url = match &notion_page_id {
+            Some(page) => page.notion_url(),
+            None => format!("https://example.com/items/{}", i + 1),
+         };
+         let created_at = midnight(first_day) - TimeDelta::days(below(&mut rng, 60) as i64)
+            + TimeDelta::hours(below(&mut rng, 24) as i64);
+
+         // Every few tags is expired, expiring soon, or in maintenance, so each shows up in any
+         // demo big enough to page through
+         let expires_on = match i % 10 {
+            3 => Some(today - TimeDelta::days(1 + below(&mut rng, 30) as i64)),
+            6 => Some(today + TimeDelta::days(below(&mut rng, 14) as i64)),
+            _ => None,
+         };
+         let maintenance = i % 15 == 7;
+
+         let (short_code, old_codes) = if percent(&mut rng, 25) {
+            let old_codes = (0..below(&mut rng, 3)).map(|_| code(&mut rng)).collect();
+            (Some(code(&mut rng)), old_codes)
+         } else {
+            (None, Vec::new())
+         };
+
+         let mean = pick(&mut rng, &POPULARITY);
+         let last_day = expires_on.filter(|&expiry| expiry < today).unwrap_or(today);
+         let mut tap_count = 1 + below(&mut rng, 20) as i32;
+         let mut taps = Vec::new();
+         let mut day = first_day;
+         while day < last_day {
+            let mut times: Vec<_> = (0..below(&mut rng, 2 * mean + 1))
+               .map(|_| {
+                  midnight(day)
+                     + TimeDelta::hours(weighted(&mut rng, &HOURLY) as i64)
+                     + TimeDelta::seconds(below(&mut rng, 3600) as i64)
+               })
+               .collect();
+            times.sort();
+            for at in times {
+               taps.push(FakeTap {
+                  at,
+                  tap_count,
+                  lang: pick(&mut rng, LANGS),
+                  country: pick(&mut rng, COUNTRIES),
+               });
+               // Now and then a phone reads the tag twice for one tap
+               tap_count += if percent(&mut rng, 5) { 2 } else { 1 };
+            }
+            day += TimeDelta::days(1);
+         }
+
+         FakeTag {
+            id,
+            label: format!("{} {}", pick(&mut rng, THINGS), i + 1),
+            kit: pick(&mut rng, KITS),
+            target_url,
+            notion_page_id,
+            created_at,
+            expires_on,
+            maintenance,
+            short_code,
+            old_codes,
+            taps,
+         }
+      })
+      .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+   pub tags: usize,
+   pub aliases: usize,
+   pub taps: usize,
+}
+
+impl fmt::Display for Summary {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      writeln!(
+         f,
+         "Seeded {} tag(s), {} alias(es) and {} tap(s).",
+         self.tags, self.aliases, self.taps
+      )
+   }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SeedError {
+   #[error("The database already has {0} tag(s); demo data is only added to it when forced")]
+   NotEmpty(i64),
+   #[error(transparent)]
+   Database(#[from] sqlx::Error),
+}
+
+/// Inserts `tags` along with their aliases, taps, and daily rollups.
+pub async fn insert(conn: &mut PgConnection, tags: &[FakeTag]) -> Result<Summary, sqlx::Error> {
+   // Optional columns travel as '' for NULL, as arrays of them can't
+   let text = |f: fn(&FakeTag) -> Option<String>| tags.iter().map(|tag| f(tag).unwrap_or_default()).collect::<Vec<_>>();
+   let ids = text(|tag| Some(tag.id.to_string()));
+   let target_urls = text(|tag| Some(tag.target_url.clone()));
+   let target_ids = targets::ensure(conn, &target_urls).await?;
+   sqlx::query!(
+      r#"INSERT INTO twag_tags
+            (id, target_url, target_id, label, kit, access_count, last_seen_tap_count, created_at, last_accessed,
+             notion_page_id, expires_at, maintenance, short_code)
+         SELECT id::tag_uid, target_url, target_id, label, NULLIF(kit, ''), access_count, NULLIF(last_seen, 0),
+               created_at::timestamptz, NULLIF(last_accessed, '')::timestamptz,
+               NULLIF(notion_page_id, '')::notion_page_id,
+               NULLIF(expires_on, '')::date::timestamp AT TIME ZONE 'UTC', maintenance, NULLIF(short_code, '')
+         FROM unnest($1::text[], $2::text[], $3::text[], $4::text[], $5::int4[], $6::int4[], $7::text[],
+               $8::text[], $9::text[], $10::text[], $11::bool[], $12::text[], $13::int8[])
+            AS t (id, target_url, label, kit, access_count, last_seen, created_at, last_accessed, notion_page_id,
+               expires_on, maintenance, short_code, target_id)"#,
+      &ids,
+      &target_urls,
+      &text(|tag| Some(tag.label.clone())),
+      &text(|tag| tag.kit.map(str::to_string)),
+      &tags.iter().map(|tag| tag.taps.len() as i32).collect::<Vec<_>>(),
+      &tags
+         .iter()
+         .map(|tag| tag.taps.last().map_or(0, |tap| tap.tap_count))
+         .collect::<Vec<_>>(),
+      &text(|tag| Some(tag.created_at.to_rfc3339())),
+      &text(|tag| tag.taps.last().map(|tap| tap.at.to_rfc3339())),
+      &text(|tag| tag.notion_page_id.as_ref().map(|page| page.to_string())),
+      &text(|tag| tag.expires_on.map(|date| date.to_string())),
+      &tags.iter().map(|tag| tag.maintenance).collect::<Vec<_>>(),
+      &text(|tag| tag.short_code.clone()),
+      &target_ids,
+   )
+   .execute(&mut *conn)
+   .await?;
+
+   let (aliases, alias_ids): (Vec<String>, Vec<String>) = tags
+      .iter()
+      .flat_map(|tag| {
+         tag.old_codes
+            .iter()
+            .chain(&tag.short_code)
+            .map(|code| (code.clone(), tag.id.to_string()))
+      })
+      .unzip();
+   sqlx::query!(
+      r#"INSERT INTO twag_tag_aliases (alias, tag_id)
+         SELECT alias, tag_id::tag_uid FROM unnest($1::text[], $2::text[]) AS t (alias, tag_id)"#,
+      &aliases,
+      &alias_ids,
+   )
+   .execute(&mut *conn)
+   .await?;
+
+   let taps: Vec<(String, &FakeTap)> = tags
+      .iter()
+      .flat_map(|tag| tag.taps.iter().map(|tap| (tag.id.to_string(), tap)))
+      .collect();
+   sqlx::query!(
+      r#"INSERT INTO twag_tap_events (tag_id, tapped_at, tap_count, lang, country, counted, resolution_path,
+            response_status)
+         SELECT tag_id::tag_uid, tapped_at::timestamptz, tap_count, lang, country, true, 'direct', 307
+         FROM unnest($1::text[], $2::text[], $3::int4[], $4::text[], $5::text[])
+            AS t (tag_id, tapped_at, tap_count, lang, country)"#,
+      &taps.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>(),
+      &taps.iter().map(|(_, tap)| tap.at.to_rfc3339()).collect::<Vec<_>>(),
+      &taps.iter().map(|(_, tap)| tap.tap_count).collect::<Vec<_>>(),
+      &taps.iter().map(|(_, tap)| tap.lang.to_string()).collect::<Vec<_>>(),
+      &taps.iter().map(|(_, tap)| tap.country.to_string()).collect::<Vec<_>>(),
+   )
+   .execute(&mut *conn)
+   .await?;
+
+   sqlx::query!(
+      r#"INSERT INTO twag_tag_daily (tag_id, day, taps)
+         SELECT tag_id, (tapped_at AT TIME ZONE 'UTC')::date, count(*)
+         FROM twag_tap_events WHERE tag_id::text = ANY($1)
+         GROUP BY 1, 2"#,
+      &ids,
+   )
+   .execute(&mut *conn)
+   .await?;
+   sqlx::query!(
+      r#"INSERT INTO twag_tag_daily_lang (tag_id, day, lang, taps)
+         SELECT tag_id, (tapped_at AT TIME ZONE 'UTC')::date, lang::lang_tag, count(*)
+         FROM twag_tap_events WHERE tag_id::text = ANY($1) AND lang IS NOT NULL
+         GROUP BY 1, 2, 3"#,
+      &ids,
+   )
+   .execute(&mut *conn)
+   .await?;
+
+   Ok(Summary {
+      tags: tags.len(),
+      aliases: aliases.len(),
+      taps: taps.len(),
+   })
+}
+
+/// Seeds an empty database, or any database when `force`d.
+pub async fn seed(pool: &PgPool, spec: &Spec, today: NaiveDate, force: bool) -> Result<Summary, SeedError> {
+   let mut tx = pool.begin().await?;
+   let existing = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM twag_tags"#)
+      .fetch_one(&mut *tx)
+      .await?;
+   if existing > 0 && !force {
+      return Err(SeedError::NotEmpty(existing));
+   }
+   let summary = insert(&mut *tx, &generate(spec, today)).await?;
+   tx.commit().await?;
+   Ok(summary)
+}
+
+/// The `TWAG_MODE=seed` entry point; `--force` seeds a database that already has tags.
+pub async fn run(pool: &ScalingPool, spec: &Spec, force: bool) -> Result<(), SeedError> {
+   let summary = seed(&pool.get(), spec, Utc::now().date_naive(), force).await?;
+   print!("{}", summary);
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn today() -> NaiveDate { NaiveDate::from_ymd_opt(2026, 10, 16).unwrap() }
+
+   fn spec(seed: u64) -> Spec {
+      Spec {
+         seed,
+         ..Spec::default()
+      }
+   }
+
+   #[test]
+   fn test_same_seed_same_data() {
+      assert_eq!(generate(&spec(7), today()), generate(&spec(7), today()));
+      assert_ne!(generate(&spec(7), today()), generate(&spec(8), today()));
+   }
+
+   #[test]
+   fn test_ids_and_codes_are_unique_and_valid() {
+      let tags = generate(&Spec { tags: 2000, ..spec(3) }, today());
+      assert_eq!(tags.len(), 2000);
+      let ids: HashSet<_> = tags.iter().map(|tag| tag.id).collect();
+      assert_eq!(ids.len(), tags.len());
+      for tag in &tags {
+         assert_eq!(tag.id.to_string().parse::<TagUid>().unwrap(), tag.id);
+      }
+
+      let codes: Vec<_> = tags
+         .iter()
+         .flat_map(|tag| tag.old_codes.iter().chain(&tag.short_code))
+         .collect();
+      assert!(!codes.is_empty());
+      assert_eq!(codes.iter().collect::<HashSet<_>>().len(), codes.len());
+      assert!(codes.iter().all(|code| short_code::is_plausible(code)));
+   }
+
+   #[test]
+   fn test_taps_stay_in_the_window_and_count_up() {
+      let first = midnight(today() - TimeDelta::days(i64::from(DEFAULT_DAYS)));
+      for tag in generate(&spec(1), today()) {
+         let end = midnight(tag.expires_on.filter(|&expiry| expiry < today()).unwrap_or(today()));
+         for tap in &tag.taps {
+            assert!(first <= tap.at && tap.at < end, "{:?} tapped at {}", tag.id, tap.at);
+         }
+         for pair in tag.taps.windows(2) {
+            assert!(pair[0].at <= pair[1].at);
+            assert!(pair[0].tap_count < pair[1].tap_count);
+         }
+      }
+   }
+
+   #[test]
+   fn test_includes_expired_expiring_and_maintenance_tags() {
+      let tags = generate(&spec(1), today());
+      assert!(tags
+         .iter()
+         .any(|tag| tag.expires_on.is_some_and(|expiry| expiry < today())));
+      assert!(tags
+         .iter()
+         .any(|tag| tag.expires_on.is_some_and(|expiry| expiry >= today())));
+      assert!(tags.iter().any(|tag| tag.maintenance));
+      assert!(tags.iter().any(|tag| tag.notion_page_id.is_some()));
+      assert!(tags.iter().any(|tag| tag.notion_page_id.is_none()));
+   }
+
+   #[test]
+   fn test_taps_follow_the_day() {
+      let mut by_hour = [0; 24];
+      for tag in generate(&spec(1), today()) {
+         for tap in tag.taps {
+            by_hour[tap.at.hour() as usize] += 1;
+         }
+      }
+      assert!(by_hour[3] * 5 < by_hour[19], "{:?}", by_hour);
+   }
+
+   #[test]
+   fn test_no_days_no_taps() {
+      let tags = generate(&Spec { days: 0, ..spec(1) }, today());
+      assert!(tags.iter().all(|tag| tag.taps.is_empty()));
+   }
+
+   #[test]
+   fn test_config() {
+      assert_eq!(Spec::from_vars(|_| None), Ok(Spec::default()));
+      let vars = |name: &str| match name {
+         "TWAG_SEED" => Some("42".to_string()),
+         "TWAG_SEED_TAGS" => Some("5".to_string()),
+         _ => None,
+      };
+      assert_eq!(
+         Spec::from_vars(vars),
+         Ok(Spec {
+            seed: 42,
+            tags: 5,
+            days: DEFAULT_DAYS,
+         })
+      );
+      assert!(Spec::from_vars(|name| (name == "TWAG_SEED_DAYS").then(|| "ninety".to_string())).is_err());
+      assert!(Spec::from_vars(|name| (name == "TWAG_SEED_TAGS").then(|| "10001".to_string())).is_err());
+   }
+}
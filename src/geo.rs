@@ -0,0 +1,141 @@
+//! Per-country targets, from a local MaxMind-format database (`TWAG_GEOIP_DB`). The database is
+//! re-read on SIGHUP, so it can be updated in place.
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use maxminddb::{geoip2, MaxMindDbError, Reader};
+use tracing::{info, warn};
+
+pub struct GeoIp {
+   path: PathBuf,
+   reader: RwLock<Arc<Reader<Vec<u8>>>>,
+}
+
+impl GeoIp {
+   pub fn open(path: impl Into<PathBuf>) -> Result<Self, MaxMindDbError> {
+      let path = path.into();
+      let reader = Reader::open_readfile(&path)?;
+      Ok(GeoIp {
+         path,
+         reader: RwLock::new(Arc::new(reader)),
+      })
+   }
+
+   /// The uppercase ISO 3166-1 code of the country `ip` is registered in, if the database knows.
+   pub fn country(&self, ip: IpAddr) -> Option<String> {
+      let reader = self.reader.read().unwrap().clone();
+      let found = reader.lookup::<geoip2::Country>(ip).unwrap_or_else(|e| {
+         warn!(%ip, "GeoIP lookup failed: {}", e);
+         None
+      })?;
+      found.country?.iso_code.map(str::to_ascii_uppercase)
+   }
+
+   /// Swaps in a fresh copy of the database file. On failure the old one stays in use.
+   pub fn reload(&self) -> Result<(), MaxMindDbError> {
+      let reader = Reader::open_readfile(&self.path)?;
+      *self.reader.write().unwrap() = Arc::new(reader);
+      Ok(())
+   }
+}
+
+pub fn spawn_reload_on_sighup(geoip: Arc<GeoIp>) {
+   use tokio::signal::unix::{signal, SignalKind};
+   let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+   tokio::spawn(async move {
+      while sighup.recv().await.is_some() {
+         match geoip.reload() {
+            Ok(()) => info!(path = %geoip.path.display(), "Reloaded GeoIP database"),
+            Err(e) => {
+               warn!(path = %geoip.path.display(), "Failed to reload GeoIP database, keeping the old one: {}", e)
+            }
+         }
+      }
+   });
+}
+
+/// One row of `twag_tag_targets_geo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeoTarget<'a> {
+   /// Comma-separated country codes.
+   pub countries: &'a str,
+   pub target_url: &'a str,
+}
+
+/// The client's country, looked up with `lookup`, and the first of `targets` listing it. Tags
+/// without geo targets still get the country, for the tap record.
+pub fn pick<'a>(
+   targets: &[GeoTarget<'a>],
+   ip: IpAddr,
+   lookup: impl Fn(IpAddr) -> Option<String>,
+) -> (Option<String>, Option<&'a str>) {
+   let Some(country) = lookup(ip) else {
+      return (None, None);
+   };
+   let target = target_for(targets, &country);
+   (Some(country), target)
+}
+
+/// The first of `targets` listing `country`.
+pub fn target_for<'a>(targets: &[GeoTarget<'a>], country: &str) -> Option<&'a str> {
+   targets
+      .iter()
+      .find(|target| target.countries.split(',').any(|c| c == country))
+      .map(|target| target.target_url)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const TARGETS: &[GeoTarget] = &[
+      GeoTarget {
+         countries: "US,CA",
+         target_url: "https://store.example.com/na",
+      },
+      GeoTarget {
+         countries: "DE,AT,CH",
+         target_url: "https://store.example.com/dach",
+      },
+      GeoTarget {
+         countries: "CA",
+         target_url: "https://store.example.com/ca",
+      },
+   ];
+
+   fn stub(ip: IpAddr) -> Option<String> {
+      match ip.to_string().as_str() {
+         "203.0.113.1" => Some("CA".to_string()),
+         "203.0.113.2" => Some("AT".to_string()),
+         "203.0.113.3" => Some("FR".to_string()),
+         _ => None,
+      }
+   }
+
+   fn ip(s: &str) -> IpAddr { s.parse().unwrap() }
+
+   #[test]
+   fn test_first_listing_row_wins() {
+      assert_eq!(
+         pick(TARGETS, ip("203.0.113.1"), stub),
+         (Some("CA".to_string()), Some("https://store.example.com/na"))
+      );
+      assert_eq!(
+         pick(TARGETS, ip("203.0.113.2"), stub),
+         (Some("AT".to_string()), Some("https://store.example.com/dach"))
+      );
+   }
+
+   #[test]
+   fn test_unlisted_country_falls_back() {
+      assert_eq!(pick(TARGETS, ip("203.0.113.3"), stub), (Some("FR".to_string()), None));
+      assert_eq!(pick(&[], ip("203.0.113.1"), stub), (Some("CA".to_string()), None));
+   }
+
+   #[test]
+   fn test_failed_lookup_falls_back() {
+      assert_eq!(pick(TARGETS, ip("2001:db8::1"), stub), (None, None));
+   }
+}
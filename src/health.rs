@@ -0,0 +1,240 @@
+//! `/healthz?deep=true`: checks that a freshly deployed instance can actually serve, beyond
+//! reaching Postgres. Each check is timed and reported on its own line, and the whole run shares
+//! one time budget, so a load balancer's probe gets an answer before it gives up.
+//!
+//! Deep checks need `TWAG_HEALTH_TOKEN`, passed as `token=`; they render pages and query
+//! Postgres, which `/healthz` proper is kept too cheap to do. A failed run marks the instance
+//! unready, and plain `/healthz` answers 503 too, until a deep run passes again.
+
+use std::future::Future;
+use std::time::Duration;
+
+use sqlx::PgConnection;
+use tokio::time::Instant;
+
+use crate::count_token::constant_time_eq;
+use crate::models::TagUid;
+use crate::provision::{self, ProvisioningKey};
+use crate::security::{CookieKey, MIN_SECRET_LEN};
+
+/// For every check together.
+pub const BUDGET: Duration = Duration::from_secs(2);
+
+#[derive(Clone)]
+pub struct HealthToken(String);
+
+impl HealthToken {
+   /// Deep checks are off unless `TWAG_HEALTH_TOKEN` is set.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+      let Some(token) = var("TWAG_HEALTH_TOKEN").filter(|s| !s.is_empty()) else {
+         return Ok(None);
+      };
+      if token.len() < MIN_SECRET_LEN {
+         return Err(format!("TWAG_HEALTH_TOKEN must be at least {} bytes", MIN_SECRET_LEN));
+      }
+      Ok(Some(HealthToken(token)))
+   }
+
+   pub fn accepts(&self, presented: &str) -> bool { constant_time_eq(self.0.as_bytes(), presented.as_bytes()) }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+   pub name: &'static str,
+   /// What was found, or why the check failed.
+   pub result: Result<String, String>,
+   pub took: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+   pub checks: Vec<Check>,
+}
+
+impl Report {
+   pub fn passed(&self) -> bool { self.checks.iter().all(|check| check.result.is_ok()) }
+}
+
+impl std::fmt::Display for Report {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      for check in &self.checks {
+         let (outcome, detail) = match &check.result {
+            Ok(detail) => ("ok", detail),
+            Err(error) => ("FAILED", error),
+         };
+         writeln!(
+            f,
+            "{}: {} in {}ms, {}",
+            check.name,
+            outcome,
+            check.took.as_millis(),
+            detail
+         )?;
+      }
+      Ok(())
+   }
+}
+
+/// Runs `check` if there's time left before `deadline`, failing it if it runs past.
+pub async fn timed<F>(name: &'static str, deadline: Instant, check: F) -> Check
+where
+   F: Future<Output = Result<String, String>>,
+{
+   let started = Instant::now();
+   // Even a check that would finish at once isn't started once time's up
+   let result = match started < deadline {
+      true => tokio::time::timeout_at(deadline, check)
+         .await
+         .unwrap_or_else(|_| Err("out of time".to_string())),
+      false => Err("out of time".to_string()),
+   };
+   Check {
+      name,
+      result,
+      took: started.elapsed(),
+   }
+}
+
+/// Whether links twag builds, always `https://` and on the canonical host if one is set, suit how
+/// this request reached it.
+pub fn check_origin(canonical_host: Option<&str>, forwarded_proto: Option<&str>) -> Result<String, String> {
+   let origin = match canonical_host {
+      Some(host) => {
+         let url = url::Url::parse(&format!("https://{host}"))
+            .map_err(|e| format!("TWAG_CANONICAL_HOST {host:?} isn't a host: {e}"))?;
+         if url.host_str().is_none() || url.path() != "/" || url.query().is_some() {
+            return Err(format!("TWAG_CANONICAL_HOST {host:?} isn't a bare host"));
+         }
+         format!("https://{host}")
+      }
+      None => "https://<request host>".to_string(),
+   };
+   match forwarded_proto.map(str::trim) {
+      Some(proto) if !proto.eq_ignore_ascii_case("https") => {
+         Err(format!("served over {proto}, but links are built as {origin}"))
+      }
+      Some(_) => Ok(origin),
+      None => Ok(format!("{origin}, scheme not forwarded")),
+   }
+}
+
+/// Whether the configured keys sign and verify a test value; keys that aren't configured pass.
+pub fn check_keys(
+   cookie_key: Option<&CookieKey>,
+   provisioning_key: Option<&ProvisioningKey>,
+) -> Result<String, String> {
+   let mut checked = Vec::new();
+   if let Some(key) = cookie_key {
+      let signed = key.sign("twag_health", "round trip");
+      if key.verify("twag_health", &signed) != Some("round trip") {
+         return Err("TWAG_COOKIE_SECRET doesn't verify its own signature".to_string());
+      }
+      checked.push("TWAG_COOKIE_SECRET");
+   }
+   if let Some(key) = provisioning_key {
+      let id: TagUid = "04000000000000".parse().unwrap();
+      let link = provision::Link::new(key, &id, i64::MAX);
+      if link.check(key, &id, 0).is_err() {
+         return Err("TWAG_PROVISIONING_SECRET doesn't verify its own signature".to_string());
+      }
+      checked.push("TWAG_PROVISIONING_SECRET");
+   }
+   match checked.is_empty() {
+      true => Ok("no keys configured".to_string()),
+      false => Ok(checked.join(", ")),
+   }
+}
+
+/// Whether the database has every migration this build expects. A database further along passes,
+/// as it is during a rolling deploy before the old instances are gone.
+pub fn check_migrations(applied: Option<i64>, expected: i64) -> Result<String, String> {
+   match applied {
+      None => Err(format!("no migrations applied; expected {expected}")),
+      Some(applied) if applied < expected => Err(format!("database at {applied}, this build expects {expected}")),
+      Some(applied) if applied > expected => Ok(format!("database at {applied}, ahead of this build's {expected}")),
+      Some(applied) => Ok(applied.to_string()),
+   }
+}
+
+/// The migrations this build was compiled with, only read for the latest version.
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+pub async fn migrations(conn: &mut PgConnection) -> Result<String, String> {
+   let expected = MIGRATOR.iter().map(|migration| migration.version).max().unwrap_or(0);
+   let applied = sqlx::query_scalar!(r#"SELECT max(version) AS version FROM _sqlx_migrations WHERE success"#)
+      .fetch_one(conn)
+      .await
+      .map_err(|e| e.to_string())?;
+   check_migrations(applied, expected)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_origin() {
+      assert_eq!(
+         check_origin(Some("xz.ws"), Some("https")),
+         Ok("https://xz.ws".to_string())
+      );
+      assert_eq!(
+         check_origin(None, None),
+         Ok("https://<request host>, scheme not forwarded".to_string())
+      );
+      assert!(check_origin(Some("xz.ws"), Some("http")).is_err());
+      assert!(check_origin(Some("xz.ws/tags"), None).is_err());
+      assert!(check_origin(Some("exa mple.com"), None).is_err());
+   }
+
+   #[test]
+   fn test_keys() {
+      let cookie_key = CookieKey::from_vars(|_| Some("0123456789abcdef".to_string())).unwrap();
+      let provisioning_key = ProvisioningKey::from_vars(|_| Some("0123456789abcdef".to_string())).unwrap();
+      assert_eq!(
+         check_keys(cookie_key.as_ref(), provisioning_key.as_ref()),
+         Ok("TWAG_COOKIE_SECRET, TWAG_PROVISIONING_SECRET".to_string())
+      );
+      assert_eq!(check_keys(None, None), Ok("no keys configured".to_string()));
+   }
+
+   #[test]
+   fn test_migrations() {
+      let expected = 20261016223000;
+      assert!(check_migrations(Some(expected), expected).is_ok());
+      assert!(check_migrations(Some(expected + 1), expected).is_ok());
+      assert!(check_migrations(Some(expected - 1), expected).is_err());
+      assert!(check_migrations(None, expected).is_err());
+   }
+
+   #[tokio::test]
+   async fn test_checks_share_one_budget() {
+      let deadline = Instant::now() + Duration::from_millis(50);
+      let slow = timed("slow", deadline, async {
+         tokio::time::sleep(Duration::from_secs(10)).await;
+         Ok(String::new())
+      })
+      .await;
+      assert_eq!(slow.result, Err("out of time".to_string()));
+      // Nothing left for the next one
+      let quick = timed("quick", deadline, async { Ok("fine".to_string()) }).await;
+      assert_eq!(quick.result, Err("out of time".to_string()));
+
+      let report = Report {
+         checks: vec![slow, quick],
+      };
+      assert!(!report.passed());
+      assert!(report.to_string().starts_with("slow: FAILED in "));
+   }
+
+   #[test]
+   fn test_token() {
+      let token = HealthToken::from_vars(|_| Some("0123456789abcdef".to_string()))
+         .unwrap()
+         .unwrap();
+      assert!(token.accepts("0123456789abcdef"));
+      assert!(!token.accepts("0123456789abcde"));
+      assert!(HealthToken::from_vars(|_| Some(String::new())).unwrap().is_none());
+      assert!(HealthToken::from_vars(|_| Some("short".to_string())).is_err());
+   }
+}
@@ -0,0 +1,105 @@
+use crate::models::LanguageTag;
+
+/// Language ranges from an `Accept-Language` header, most preferred first. Ranges with `q=0` are
+/// explicitly unacceptable and dropped; malformed entries are ignored.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+   let mut ranges: Vec<(String, f32)> = header
+      .split(',')
+      .filter_map(|entry| {
+         let mut parts = entry.split(';');
+         let range = parts.next()?.trim();
+         let valid_range = range == "*"
+            || range
+               .split('-')
+               .all(|subtag| (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric()));
+         if !valid_range {
+            return None;
+         }
+
+         let mut quality = 1.0;
+         for param in parts {
+            let (key, value) = param.split_once('=')?;
+            if key.trim().eq_ignore_ascii_case("q") {
+               quality = value.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))?;
+            }
+         }
+         (quality > 0.0).then(|| (range.to_ascii_lowercase(), quality))
+      })
+      .collect();
+   // Stable, so equal-quality ranges keep the order the client sent them in.
+   ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+   ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+/// Picks the best of `available` for the client's preferences: for each range in order, an exact
+/// match, then RFC 4647 basic filtering (`pt` matches `pt-BR`), then a shared primary subtag
+/// (`de-AT` matches `de`). The wildcard matches nothing, leaving the tag's base target as the
+/// default.
+pub fn negotiate<'a>(ranges: &[String], available: &'a [LanguageTag]) -> Option<&'a LanguageTag> {
+   ranges.iter().filter(|range| range.as_str() != "*").find_map(|range| {
+      let primary = range.split('-').next().unwrap_or_default();
+      available
+         .iter()
+         .find(|tag| tag.as_str().eq_ignore_ascii_case(range))
+         .or_else(|| {
+            available.iter().find(|tag| {
+               let tag = tag.as_str().to_ascii_lowercase();
+               tag.strip_prefix(range.as_str())
+                  .is_some_and(|rest| rest.starts_with('-'))
+            })
+         })
+         .or_else(|| available.iter().find(|tag| tag.primary() == primary))
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn tags(tags: &[&str]) -> Vec<LanguageTag> { tags.iter().map(|t| LanguageTag::new(t).unwrap()).collect() }
+
+   #[test]
+   fn test_parse_accept_language_orders_by_quality() {
+      assert_eq!(
+         parse_accept_language("fr;q=0.5, de-DE, en;q=0.8, *;q=0.1"),
+         ["de-de", "en", "fr", "*"]
+      );
+      assert_eq!(parse_accept_language("en, de"), ["en", "de"]);
+      assert_eq!(
+         parse_accept_language("en;q=0.5, de;q=0.5, fr;q=0.9"),
+         ["fr", "en", "de"]
+      );
+   }
+
+   #[test]
+   fn test_parse_accept_language_drops_unacceptable_and_malformed() {
+      assert_eq!(parse_accept_language("de;q=0, en"), ["en"]);
+      assert_eq!(parse_accept_language("de;q=2, en;q=abc, fr"), ["fr"]);
+      assert_eq!(parse_accept_language("de_DE, ,en-"), Vec::<String>::new());
+      assert!(parse_accept_language("").is_empty());
+   }
+
+   #[test]
+   fn test_negotiate_exact_before_prefix_before_primary() {
+      let available = tags(&["de", "de-AT", "pt-BR"]);
+      assert_eq!(negotiate(&["de-at".into()], &available).unwrap().as_str(), "de-AT");
+      assert_eq!(negotiate(&["de-ch".into()], &available).unwrap().as_str(), "de");
+      assert_eq!(negotiate(&["pt".into()], &available).unwrap().as_str(), "pt-BR");
+      assert_eq!(negotiate(&["pt-pt".into()], &available).unwrap().as_str(), "pt-BR");
+   }
+
+   #[test]
+   fn test_negotiate_respects_preference_order() {
+      let available = tags(&["de", "fr"]);
+      let ranges = parse_accept_language("es, fr;q=0.9, de;q=0.8");
+      assert_eq!(negotiate(&ranges, &available).unwrap().as_str(), "fr");
+   }
+
+   #[test]
+   fn test_negotiate_falls_back_to_none() {
+      let available = tags(&["de"]);
+      assert_eq!(negotiate(&["en-us".into(), "en".into()], &available), None);
+      assert_eq!(negotiate(&["*".into()], &available), None);
+      assert_eq!(negotiate(&["de".into()], &[]), None);
+   }
+}
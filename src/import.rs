@@ -0,0 +1,835 @@
+//! Importing tags from a CSV file too large to create in one request: the upload only stores the
+//! file as a job in `twag_import_jobs`, and a background task creates its tags `BATCH_ROWS` at a
+//! time, each batch in one transaction with the job's progress. A crash mid-batch rolls back that
+//! batch alone; the next worker to claim the job starts again from its last committed row.
+//!
+//! Columns are found by header, as `id`, `label`, `kit`, `tag_model`, `target_url`, `notion_page`
+//! and `tap_count`, or an export's `access_count` in its place; others are ignored. Rows are
+//! checked as a kit's are, and ids that already exist are skipped rather than changed. Every row
+//! goes in the job's report, with its row number and what became of it.
+//!
+//! A strict import is checked whole at upload, and refused with that report if any row fails, so
+//! nothing is created from a file with a mistake in it; see `refusal`. Rows only skipped, as
+//! already taken, don't count against it. The worker takes a strict job as one batch, however long,
+//! so a row that fails by the time it's reached, over a quota say, leaves the whole file uncreated.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use sqlx::Connection;
+use tracing::{info, warn};
+
+use crate::kit::{self, KitRow, ValidKitRow};
+use crate::models::{NotionPageId, TagUid};
+use crate::negative_cache::NegativeCache;
+use crate::outbox;
+use crate::pool::ScalingPool;
+use crate::quota;
+use crate::settings::SharedSettings;
+use crate::tag_models::TagModel;
+use crate::taps::csv_field;
+use crate::targets;
+
+/// Rows created per transaction.
+pub const BATCH_ROWS: usize = 1000;
+/// Larger files are refused at upload.
+pub const MAX_UPLOAD_BYTES: usize = 16 * 1024 * 1024;
+pub const MAX_ROWS: usize = 100_000;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a running job's page reloads itself.
+pub const REFRESH_SECS: u32 = 2;
+/// Report lines shown on a job's page; the rest are in its CSV report.
+pub const SHOWN_ROWS: i64 = 100;
+
+/// One row of the file, with the row number a spreadsheet would show for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+   pub row_number: i32,
+   pub kit: Option<String>,
+   /// As written; see `TagModel::from_input`.
+   pub tag_model: String,
+   /// As written; see `tap_count`.
+   pub tap_count: String,
+   /// As written, in decimal as an export has it; read when `tap_count` is blank.
+   pub access_count: String,
+   pub row: KitRow,
+}
+
+/// The file's rows, blank ones left out. Refuses files without an `id` column, or that don't
+/// parse as CSV.
+pub fn parse(text: &str) -> Result<Vec<Record>, String> {
+   let mut fields = fields(text.strip_prefix('\u{feff}').unwrap_or(text))?.into_iter();
+   let Some(header) = fields.next() else {
+      return Err("The file is empty".to_string());
+   };
+   let column = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+   let Some(id) = column("id") else {
+      return Err("The first row must name the columns, one of them id".to_string());
+   };
+   let (label, kit, tag_model, target_url, notion_page, tap_count, access_count) = (
+      column("label"),
+      column("kit"),
+      column("tag_model"),
+      column("target_url"),
+      column("notion_page"),
+      column("tap_count"),
+      column("access_count"),
+   );
+
+   let mut records = Vec::new();
+   for (n, values) in fields.enumerate() {
+      let value = |i: Option<usize>| i.and_then(|i| values.get(i)).cloned().unwrap_or_default();
+      let row = KitRow {
+         id: value(Some(id)),
+         label: value(label),
+         target_url: value(target_url),
+         notion_page: value(notion_page),
+      };
+      if values.iter().all(|v| v.trim().is_empty()) {
+         continue;
+      }
+      if records.len() == MAX_ROWS {
+         return Err(format!("Imports are at most {} rows", MAX_ROWS));
+      }
+      records.push(Record {
+         // After the header, counting from one
+         row_number: n as i32 + 2,
+         kit: Some(value(kit).trim().to_string()).filter(|s| !s.is_empty()),
+         tag_model: value(tag_model),
+         tap_count: value(tap_count),
+         access_count: value(access_count),
+         row,
+      });
+   }
+   Ok(records)
+}
+
+/// Splits RFC 4180 CSV into records of fields; quoted fields may hold commas, quotes as `""`, and
+/// line breaks.
+fn fields(text: &str) -> Result<Vec<Vec<String>>, String> {
+   let mut records = Vec::new();
+   let mut record = Vec::new();
+   let mut field = String::new();
+   let mut quoted = false;
+   let mut chars = text.chars().peekable();
+   while let Some(c) = chars.next() {
+      match (quoted, c) {
+         (true, '"') if chars.peek() == Some(&'"') => {
+            chars.next();
+            field.push('"');
+         }
+         (true, '"') => quoted = false,
+         (true, c) => field.push(c),
+         (false, '"') if field.is_empty() => quoted = true,
+         (false, ',') => record.push(std::mem::take(&mut field)),
+         (false, '\r') if chars.peek() == Some(&'\n') => {}
+         (false, '\n') => {
+            record.push(std::mem::take(&mut field));
+            records.push(std::mem::take(&mut record));
+         }
+         (false, c) => field.push(c),
+      }
+   }
+   if quoted {
+      return Err("The file ends inside a quoted field".to_string());
+   }
+   if !field.is_empty() || !record.is_empty() {
+      record.push(field);
+      records.push(record);
+   }
+   Ok(records)
+}
+
+/// The tag's mirrored counter when it was programmed, as hex the way scan URLs carry it
+/// (`00000F`); the created tag's access count starts there. Blank for 1, as the create form does.
+pub fn tap_count(raw: &str) -> Result<i32, String> {
+   let raw = raw.trim();
+   if raw.is_empty() {
+      return Ok(1);
+   }
+   if raw.len() > 6 || !raw.chars().all(|c| c.is_ascii_hexdigit()) {
+      return Err(format!("Invalid tap count '{raw}': expected at most 6 hex digits"));
+   }
+   Ok(i32::from_str_radix(raw, 16).expect("at most 6 hex digits"))
+}
+
+/// The access count `record`'s tag starts at: its `tap_count`, or failing that the `access_count`
+/// an export wrote, which can be past what a tag's counter holds.
+pub fn start_count(record: &Record) -> Result<i32, String> {
+   let access_count = record.access_count.trim();
+   if !record.tap_count.trim().is_empty() || access_count.is_empty() {
+      return tap_count(&record.tap_count);
+   }
+   access_count
+      .parse::<i32>()
+      .ok()
+      .filter(|count| *count >= 0)
+      .ok_or_else(|| format!("Invalid access count '{access_count}': expected a whole number"))
+}
+
+/// What becomes of one row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+   /// With the access count to start at.
+   Create(ValidKitRow, TagModel, i32),
+   Skip(String),
+   Fail(String),
+}
+
+/// The steps for `records`, in order. `existing` are the ids among them already taken; a repeated
+/// id is skipped after its first row.
+pub fn steps(records: &[Record], existing: &HashSet<TagUid>, notion_enabled: bool, strict_idn: bool) -> Vec<Step> {
+   let mut seen = HashSet::new();
+   records
+      .iter()
+      .map(|record| {
+         let valid = kit::validate_row(&record.row, notion_enabled, strict_idn)
+            .and_then(|row| Ok((row, TagModel::from_input(&record.tag_model)?, start_count(record)?)));
+         match valid {
+            Err(error) => Step::Fail(error),
+            Ok((row, ..)) if existing.contains(&row.id) => Step::Skip("Already exists".to_string()),
+            Ok((row, ..)) if !seen.insert(row.id) => Step::Skip("Appears earlier in the file".to_string()),
+            Ok((row, tag_model, access_count)) => Step::Create(row, tag_model, access_count),
+         }
+      })
+      .collect()
+}
+
+/// The report refusing a strict import of `records`, if any row fails. Whether ids are taken isn't
+/// known until the worker reaches them, so only the rows themselves are checked.
+pub fn refusal(records: &[Record], notion_enabled: bool, strict_idn: bool) -> Option<Vec<ReportRow>> {
+   let steps = steps(records, &HashSet::new(), notion_enabled, strict_idn);
+   let refused = steps.iter().any(|step| matches!(step, Step::Fail(_)));
+   refused.then(|| Progress::default().stop(records, &steps))
+}
+
+/// What became of one row, as the report lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportRow {
+   pub row_number: i32,
+   /// As given in the file.
+   pub tag_id: String,
+   /// `created`, `skipped`, `failed`, or `not imported` when a strict import's other rows failed.
+   pub outcome: String,
+   /// Empty for a created row.
+   pub reason: String,
+}
+
+/// The report lines for `records`, that went as `steps` did. `stopped` is whether nothing was
+/// created from them after all.
+fn report(records: &[Record], steps: &[Step], stopped: bool) -> Vec<ReportRow> {
+   records
+      .iter()
+      .zip(steps)
+      .map(|(record, step)| {
+         let (outcome, reason) = match step {
+            Step::Create(..) if stopped => ("not imported", "Another row failed"),
+            Step::Create(..) => ("created", ""),
+            Step::Skip(reason) => ("skipped", reason.as_str()),
+            Step::Fail(reason) => ("failed", reason.as_str()),
+         };
+         ReportRow {
+            row_number: record.row_number,
+            tag_id: record.row.id.clone(),
+            outcome: outcome.to_string(),
+            reason: reason.to_string(),
+         }
+      })
+      .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+   /// Rows before this one are done.
+   pub next_row: i32,
+   pub created: i32,
+   pub skipped: i32,
+   pub failed: i32,
+}
+
+impl Progress {
+   /// Counts a batch of `records`, the next ones, that went as `steps` did. Returns the report
+   /// lines for those rows.
+   pub fn settle(&mut self, records: &[Record], steps: &[Step]) -> Vec<ReportRow> {
+      for step in steps {
+         match step {
+            Step::Create(..) => self.created += 1,
+            Step::Skip(_) => self.skipped += 1,
+            Step::Fail(_) => self.failed += 1,
+         }
+      }
+      self.next_row += records.len() as i32;
+      report(records, steps, false)
+   }
+
+   /// Counts a strict job that had failing rows, and so created nothing: the rows it would have
+   /// created are reported as not imported, and the job doesn't move past them.
+   pub fn stop(&mut self, records: &[Record], steps: &[Step]) -> Vec<ReportRow> {
+      for step in steps {
+         match step {
+            Step::Create(..) => {}
+            Step::Skip(_) => self.skipped += 1,
+            Step::Fail(_) => self.failed += 1,
+         }
+      }
+      report(records, steps, true)
+   }
+}
+
+/// Every row a job has reached, and what became of it.
+pub fn report_csv(rows: &[ReportRow]) -> String {
+   let mut csv = String::from("row,id,outcome,reason\r\n");
+   for row in rows {
+      csv.push_str(&format!(
+         "{},{},{},{}\r\n",
+         row.row_number,
+         csv_field(&row.tag_id),
+         row.outcome,
+         csv_field(&row.reason)
+      ));
+   }
+   csv
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+   pub id: i64,
+   pub total_rows: i32,
+   pub progress: Progress,
+   pub finished: bool,
+   /// Finished by a strict job's failing row, having created nothing.
+   pub stopped: bool,
+}
+
+impl Job {
+   pub fn percent(&self) -> i32 {
+      match self.total_rows {
+         0 => 100,
+         total => (self.progress.next_row as i64 * 100 / total as i64) as i32,
+      }
+   }
+}
+
+/// Stores the file as a job for the worker. `total_rows` is from `parse`.
+pub async fn create(
+   conn: &mut sqlx::PgConnection,
+   body: &str,
+   total_rows: i32,
+   strict: bool,
+) -> Result<i64, sqlx::Error> {
+   sqlx::query_scalar!(
+      r#"INSERT INTO twag_import_jobs (body, total_rows, strict, finished_at)
+         VALUES ($1, $2, $3, CASE WHEN $2 = 0 THEN now() END) RETURNING id"#,
+      body,
+      total_rows,
+      strict,
+   )
+   .fetch_one(conn)
+   .await
+}
+
+pub async fn load(conn: &mut sqlx::PgConnection, id: i64) -> Result<Option<Job>, sqlx::Error> {
+   Ok(sqlx::query!(
+      r#"SELECT id, total_rows, next_row, created, skipped, failed, finished_at IS NOT NULL AS "finished!", stopped
+         FROM twag_import_jobs WHERE id = $1"#,
+      id
+   )
+   .fetch_optional(conn)
+   .await?
+   .map(|row| Job {
+      id: row.id,
+      total_rows: row.total_rows,
+      progress: Progress {
+         next_row: row.next_row,
+         created: row.created,
+         skipped: row.skipped,
+         failed: row.failed,
+      },
+      finished: row.finished,
+      stopped: row.stopped,
+   }))
+}
+
+pub async fn recent(conn: &mut sqlx::PgConnection) -> Result<Vec<Job>, sqlx::Error> {
+   Ok(sqlx::query!(
+      r#"SELECT id, total_rows, next_row, created, skipped, failed, finished_at IS NOT NULL AS "finished!", stopped
+         FROM twag_import_jobs ORDER BY id DESC LIMIT 20"#
+   )
+   .fetch_all(conn)
+   .await?
+   .into_iter()
+   .map(|row| Job {
+      id: row.id,
+      total_rows: row.total_rows,
+      progress: Progress {
+         next_row: row.next_row,
+         created: row.created,
+         skipped: row.skipped,
+         failed: row.failed,
+      },
+      finished: row.finished,
+      stopped: row.stopped,
+   })
+   .collect())
+}
+
+/// The job's report lines in row order, the first `limit` of them if given.
+pub async fn report_rows(
+   conn: &mut sqlx::PgConnection,
+   job_id: i64,
+   limit: Option<i64>,
+) -> Result<Vec<ReportRow>, sqlx::Error> {
+   Ok(sqlx::query!(
+      r#"SELECT row_number, tag_id, outcome, reason FROM twag_import_rows
+         WHERE job_id = $1 ORDER BY row_number LIMIT $2"#,
+      job_id,
+      limit,
+   )
+   .fetch_all(conn)
+   .await?
+   .into_iter()
+   .map(|row| ReportRow {
+      row_number: row.row_number,
+      tag_id: row.tag_id,
+      outcome: row.outcome,
+      reason: row.reason,
+   })
+   .collect())
+}
+
+/// What the worker needs besides the pool; see `AppState` for each.
+#[derive(Clone)]
+pub(crate) struct Worker {
+   pub pool: ScalingPool,
+   pub settings: SharedSettings,
+   pub notion_enabled: bool,
+   pub notion_outbox: bool,
+   pub negative_cache: NegativeCache,
+}
+
+/// Claims the oldest unfinished job and creates its next batch, committing the tags, the report
+/// lines and the job's progress together. Returns whether there was a job to work on.
+pub(crate) async fn process_batch(worker: &Worker) -> Result<bool, sqlx::Error> {
+   let mut tx = worker.pool.get().begin().await?;
+   // Another instance's worker skips a job this one holds, rather than redoing its batch
+   let Some(job) = sqlx::query!(
+      r#"SELECT id, body, next_row, created, skipped, failed, strict FROM twag_import_jobs
+         WHERE finished_at IS NULL ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED"#
+   )
+   .fetch_optional(&mut *tx)
+   .await?
+   else {
+      return Ok(false);
+   };
+
+   let records = match parse(&job.body) {
+      Ok(records) => records,
+      Err(error) => {
+         // Checked at upload, so only a file changed since could get here
+         warn!(job = job.id, "Import no longer parses: {}", error);
+         Vec::new()
+      }
+   };
+   let start = (job.next_row as usize).min(records.len());
+   // A strict job is one batch, so that it's created whole or not at all
+   let batch_rows = if job.strict { records.len() } else { BATCH_ROWS };
+   let batch = &records[start..(start + batch_rows).min(records.len())];
+
+   let ids: Vec<String> = batch
+      .iter()
+      .filter_map(|record| record.row.id.trim().parse::<TagUid>().ok())
+      .map(|id| id.to_string())
+      .collect();
+   // Archived ids stay taken, so they're skipped as existing ones are
+   let existing: HashSet<TagUid> = sqlx::query_scalar!(
+      r#"SELECT id::text AS "id!" FROM (SELECT id FROM twag_tags WHERE id::text = ANY($1) FOR UPDATE) AS t
+         UNION ALL SELECT id::text FROM twag_tags_archive WHERE id::text = ANY($1)"#,
+      &ids,
+   )
+   .fetch_all(&mut *tx)
+   .await?
+   .into_iter()
+   .filter_map(|id| id.parse().ok())
+   .collect();
+   let strict_idn = worker.settings.load().strict_idn;
+   let mut steps = steps(batch, &existing, worker.notion_enabled, strict_idn);
+
+   // Rolled back alone when a strict job stops, keeping the job locked to record that
+   let mut savepoint = tx.begin().await?;
+
+   let creating: Vec<String> = steps
+      .iter()
+      .filter_map(|step| match step {
+         Step::Create(row, ..) => Some(row.target_url.clone()),
+         _ => None,
+      })
+      .collect();
+   let mut target_ids = targets::ensure(&mut savepoint, &creating).await?.into_iter();
+   let quotas = worker.settings.load().quotas;
+   let mut held = quota::hold(&mut savepoint, &quotas, quota::Resource::Tags).await?;
+   for (record, step) in batch.iter().zip(steps.iter_mut()) {
+      let Step::Create(row, tag_model, access_count) = step else {
+         continue;
+      };
+      let target_id = target_ids.next().expect("a target per created row");
+      if let Some(Err(exceeded)) = held.map(|usage| quota::admit(quota::Resource::Tags, usage.limit, usage.used, 1)) {
+         *step = Step::Fail(exceeded.to_string());
+         continue;
+      }
+      let inserted = sqlx::query!(
+         r#"INSERT INTO twag_tags (id, target_url, target_id, access_count, notion_page_id, label, kit, tag_model)
+            VALUES ($1::tag_uid, $2, $3, $4, $5::notion_page_id, $6, $7, $8) ON CONFLICT (id) DO NOTHING"#,
+         row.id as TagUid,
+         row.target_url,
+         target_id,
+         *access_count,
+         row.notion_page_id.clone() as Option<NotionPageId>,
+         row.label,
+         record.kit.as_deref(),
+         tag_model.as_str(),
+      )
+      .execute(&mut *savepoint)
+      .await?
+      .rows_affected();
+      // Created since the check above, by someone else
+      if inserted == 0 {
+         *step = Step::Skip("Already exists".to_string());
+         continue;
+      }
+      if let Some(usage) = &mut held {
+         usage.used += 1;
+      }
+      outbox::enqueue_created(
+         &mut savepoint,
+         worker.notion_outbox,
+         &row.id,
+         row.notion_page_id.as_ref(),
+      )
+      .await?;
+   }
+
+   let mut progress = Progress {
+      next_row: start as i32,
+      created: job.created,
+      skipped: job.skipped,
+      failed: job.failed,
+   };
+   let stopped = job.strict && steps.iter().any(|step| matches!(step, Step::Fail(_)));
+   let report = if stopped {
+      savepoint.rollback().await?;
+      warn!(job = job.id, "Stopping a strict import, as a row failed");
+      progress.stop(batch, &steps)
+   } else {
+      savepoint.commit().await?;
+      progress.settle(batch, &steps)
+   };
+   let (mut row_numbers, mut tag_ids, mut outcomes, mut reasons) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+   for row in report {
+      row_numbers.push(row.row_number);
+      tag_ids.push(row.tag_id);
+      outcomes.push(row.outcome);
+      reasons.push(row.reason);
+   }
+   sqlx::query!(
+      r#"INSERT INTO twag_import_rows (job_id, row_number, tag_id, outcome, reason)
+         SELECT $1, * FROM unnest($2::int[], $3::text[], $4::text[], $5::text[])"#,
+      job.id,
+      &row_numbers,
+      &tag_ids,
+      &outcomes,
+      &reasons,
+   )
+   .execute(&mut *tx)
+   .await?;
+   let finished = stopped || progress.next_row as usize >= records.len();
+   sqlx::query!(
+      r#"UPDATE twag_import_jobs
+         SET next_row = $2, created = $3, skipped = $4, failed = $5, finished_at = CASE WHEN $6 THEN now() END,
+            stopped = $7
+         WHERE id = $1"#,
+      job.id,
+      progress.next_row,
+      progress.created,
+      progress.skipped,
+      progress.failed,
+      finished,
+      stopped,
+   )
+   .execute(&mut *tx)
+   .await?;
+   tx.commit().await?;
+   if stopped {
+      return Ok(true);
+   }
+   worker
+      .negative_cache
+      .invalidate(steps.iter().filter_map(|step| match step {
+         Step::Create(row, ..) => Some(&row.id),
+         _ => None,
+      }));
+
+   info!(
+      job = job.id,
+      rows = progress.next_row,
+      total = records.len(),
+      "Imported a batch of tags"
+   );
+   Ok(true)
+}
+
+pub(crate) fn spawn_worker(worker: Worker) {
+   tokio::spawn(async move {
+      let mut interval = tokio::time::interval(POLL_INTERVAL);
+      loop {
+         interval.tick().await;
+         loop {
+            match process_batch(&worker).await {
+               Ok(true) => continue,
+               Ok(false) => break,
+               Err(e) => {
+                  warn!("Import batch failed: {:?}", e);
+                  break;
+               }
+            }
+         }
+      }
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn csv(rows: usize) -> String {
+      let mut csv = String::from("id,label,target_url\n");
+      for n in 0..rows {
+         csv.push_str(&format!("04{n:012X},Tag {n},https://example.com/{n}\n"));
+         if n == 1500 {
+            csv.push_str("not a tag,Bad,https://example.com\n");
+         }
+      }
+      csv
+   }
+
+   #[test]
+   fn test_parse() {
+      let records = parse(
+         "\u{feff}ID,kit,label,target_url,access_count\r\n\
+          04A1B2C3D4E5F6,Camera bag,\"Lens, wide\",https://example.com/?a=1&b=2,3\r\n\
+          ,,,,\r\n\
+          04A1B2C3D4E5F7,,\"Says \"\"hi\"\"\nand more\",https://example.com\r\n",
+      )
+      .unwrap();
+      assert_eq!(records.len(), 2);
+      assert_eq!(records[0].row_number, 2);
+      assert_eq!(records[0].kit.as_deref(), Some("Camera bag"));
+      assert_eq!(records[0].row.label, "Lens, wide");
+      assert_eq!(records[0].row.target_url, "https://example.com/?a=1&b=2");
+      assert_eq!(records[0].access_count, "3");
+      // The blank row still counts
+      assert_eq!(records[1].row_number, 4);
+      assert_eq!(records[1].kit, None);
+      assert_eq!(records[1].row.label, "Says \"hi\"\nand more");
+
+      assert!(parse("").is_err());
+      assert!(parse("label,target_url\nx,https://example.com\n").is_err());
+      assert!(parse("id,label\n04A1B2C3D4E5F6,\"unterminated\n").is_err());
+      assert_eq!(parse("id").unwrap(), Vec::new());
+   }
+
+   /// Postgres as `process_batch` uses it: every batch's tags, report lines and progress are
+   /// committed together, or not at all.
+   #[derive(Default, Clone)]
+   struct Committed {
+      tags: HashSet<TagUid>,
+      progress: Progress,
+      report: Vec<ReportRow>,
+   }
+
+   /// One batch, as `process_batch` does it; a crash before the commit leaves `db` as it was.
+   fn batch(db: &mut Committed, records: &[Record], crash: bool) -> bool {
+      let start = db.progress.next_row as usize;
+      if start >= records.len() {
+         return false;
+      }
+      let batch = &records[start..(start + BATCH_ROWS).min(records.len())];
+      let mut tx = db.clone();
+      let steps = steps(batch, &tx.tags, false, false);
+      for step in &steps {
+         if let Step::Create(row, ..) = step {
+            tx.tags.insert(row.id);
+         }
+      }
+      let report = tx.progress.settle(batch, &steps);
+      tx.report.extend(report);
+      if !crash {
+         *db = tx;
+      }
+      true
+   }
+
+   #[test]
+   fn test_resumes_after_a_crash() {
+      let mut text = csv(2500);
+      // Besides the bad row in the batch that crashes
+      text.push_str("04000000000005,Again,https://example.com\n");
+      text.push_str("also not a tag,Bad,https://example.com\n");
+      let records = parse(&text).unwrap();
+
+      let mut uninterrupted = Committed::default();
+      while batch(&mut uninterrupted, &records, false) {}
+
+      let mut resumed = Committed::default();
+      assert!(batch(&mut resumed, &records, false));
+      // Mid-way through the second batch
+      assert!(batch(&mut resumed, &records, true));
+      assert_eq!(resumed.progress.next_row, BATCH_ROWS as i32);
+      assert_eq!(resumed.tags.len(), BATCH_ROWS);
+      while batch(&mut resumed, &records, false) {}
+
+      assert_eq!(resumed.progress, uninterrupted.progress);
+      assert_eq!(resumed.report, uninterrupted.report);
+      assert_eq!(resumed.report.len(), records.len());
+      assert_eq!(resumed.tags, uninterrupted.tags);
+      assert_eq!(
+         resumed.progress,
+         Progress {
+            next_row: 2503,
+            created: 2500,
+            skipped: 1,
+            failed: 2,
+         }
+      );
+   }
+
+   #[test]
+   fn test_stopped_jobs_create_nothing() {
+      let records = parse(&csv(1600)).unwrap();
+      let steps = steps(&records, &HashSet::new(), false, false);
+      let mut progress = Progress::default();
+      let report = progress.stop(&records, &steps);
+      assert_eq!(
+         progress,
+         Progress {
+            next_row: 0,
+            created: 0,
+            skipped: 0,
+            failed: 1,
+         }
+      );
+      assert_eq!(report.len(), records.len());
+      assert_eq!(report[0].outcome, "not imported");
+      assert_eq!(report[0].reason, "Another row failed");
+      let failed: Vec<_> = report.iter().filter(|row| row.outcome == "failed").collect();
+      assert_eq!(failed.len(), 1);
+      assert_eq!((failed[0].row_number, failed[0].tag_id.as_str()), (1503, "not a tag"));
+   }
+
+   #[test]
+   fn test_report() {
+      let records = parse(
+         "id,label,target_url\n\
+          04A1B2C3D4E5F6,Taken,https://example.com\n\
+          04A1B2C3D4E5F7,New,https://example.com\n\
+          04A1B2C3D4E5F7,Twice,https://example.com\n\
+          04A1B2C3D4E5F8,No target,\n\
+          \"nope, really\",Bad id,https://example.com\n",
+      )
+      .unwrap();
+      let existing = HashSet::from(["04A1B2C3D4E5F6".parse().unwrap()]);
+      let steps = steps(&records, &existing, false, false);
+      let mut progress = Progress::default();
+      let rows = progress.settle(&records, &steps);
+      assert_eq!((progress.created, progress.skipped, progress.failed), (1, 2, 2));
+
+      let report = report_csv(&rows);
+      let lines: Vec<&str> = report.lines().collect();
+      assert_eq!(lines[0], "row,id,outcome,reason");
+      assert_eq!(lines[1], "2,04A1B2C3D4E5F6,skipped,Already exists");
+      assert_eq!(lines[2], "3,04A1B2C3D4E5F7,created,");
+      assert_eq!(lines[3], "4,04A1B2C3D4E5F7,skipped,Appears earlier in the file");
+      assert_eq!(lines[4], "5,04A1B2C3D4E5F8,failed,Needs a target URL or a Notion page");
+      assert!(lines[5].starts_with("6,\"nope, really\",failed,"));
+      assert_eq!(lines.len(), 6);
+   }
+
+   #[test]
+   fn test_tap_counts() {
+      let records = parse(
+         "id,target_url,tap_count
+          04A1B2C3D4E5F6,https://example.com,00000f
+          04A1B2C3D4E5F7,https://example.com,
+          04A1B2C3D4E5F8,https://example.com,1000000
+          04A1B2C3D4E5F9,https://example.com,-1
+",
+      )
+      .unwrap();
+      let steps = steps(&records, &HashSet::new(), false, false);
+      assert!(matches!(steps[0], Step::Create(_, _, 15)));
+      assert!(matches!(steps[1], Step::Create(_, _, 1)));
+      assert!(matches!(&steps[2], Step::Fail(e) if e.starts_with("Invalid tap count '1000000'")));
+      assert!(matches!(&steps[3], Step::Fail(e) if e.starts_with("Invalid tap count '-1'")));
+      assert_eq!(tap_count("FFFFFF"), Ok(0xFF_FFFF));
+   }
+
+   #[test]
+   fn test_exported_access_counts() {
+      let records = parse(
+         "id,target_url,tap_count,access_count
+          04A1B2C3D4E5F6,https://example.com,,20000000
+          04A1B2C3D4E5F7,https://example.com,00000F,3
+          04A1B2C3D4E5F8,https://example.com,,
+          04A1B2C3D4E5F9,https://example.com,,0F
+",
+      )
+      .unwrap();
+      let steps = steps(&records, &HashSet::new(), false, false);
+      assert!(matches!(steps[0], Step::Create(_, _, 20_000_000)));
+      // A tap count, as the tag has it, wins
+      assert!(matches!(steps[1], Step::Create(_, _, 15)));
+      assert!(matches!(steps[2], Step::Create(_, _, 1)));
+      assert!(matches!(&steps[3], Step::Fail(e) if e.starts_with("Invalid access count '0F'")));
+   }
+
+   #[test]
+   fn test_strict_imports_are_refused_by_any_failing_row() {
+      let records = parse(
+         "id,target_url
+          04A1B2C3D4E5F6,https://example.com
+          04A1B2C3D4E5F6,https://example.com
+          04A1B2C3D4E5F7,
+          not a tag,https://example.com
+",
+      )
+      .unwrap();
+      let refused = refusal(&records, false, false).unwrap();
+      let outcomes: Vec<_> = refused
+         .iter()
+         .map(|row| (row.row_number, row.outcome.as_str()))
+         .collect();
+      // The repeated id is only skipped
+      assert_eq!(
+         outcomes,
+         [(2, "not imported"), (3, "skipped"), (4, "failed"), (5, "failed")]
+      );
+      assert_eq!(refused[2].reason, "Needs a target URL or a Notion page");
+      assert!(refused[3].reason.starts_with("Invalid tag id"), "{refused:?}");
+      assert_eq!(refusal(&records[..2], false, false), None);
+   }
+
+   #[test]
+   fn test_models() {
+      let records = parse(
+         "id,tag_model,target_url
+          04A1B2C3D4E5F6,NTAG215,https://example.com
+          04A1B2C3D4E5F7,,https://example.com
+          04A1B2C3D4E5F8,ntag210,https://example.com
+",
+      )
+      .unwrap();
+      let steps = steps(&records, &HashSet::new(), false, false);
+      assert!(matches!(steps[0], Step::Create(_, TagModel::Ntag215, _)));
+      assert!(matches!(steps[1], Step::Create(_, TagModel::Unknown, _)));
+      assert!(matches!(&steps[2], Step::Fail(e) if e.starts_with("Unknown tag model 'ntag210'")));
+   }
+}
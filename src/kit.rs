@@ -0,0 +1,396 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{NotionPageId, TagUid};
+use crate::target_url;
+
+/// Rows accepted per kit; the form renders this many.
+pub const MAX_KIT_ROWS: usize = 8;
+
+/// The fields of a row, as JSON keys or form names.
+pub const ROW_FIELDS: &[&str] = &["id", "label", "target_url", "notion_page"];
+/// Everything the kit form submits: each row's fields, and the shared kit name.
+pub const FORM_FIELDS: &[&str] = &["kit", "id", "label", "target_url", "notion_page"];
+
+/// One row of a kit as submitted, before validation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct KitRow {
+   #[serde(default)]
+   pub id: String,
+   #[serde(default)]
+   pub label: String,
+   #[serde(default)]
+   pub target_url: String,
+   #[serde(default)]
+   pub notion_page: String,
+}
+
+impl KitRow {
+   fn is_blank(&self) -> bool {
+      [&self.id, &self.label, &self.target_url, &self.notion_page]
+         .iter()
+         .all(|s| s.trim().is_empty())
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidKitRow {
+   pub id: TagUid,
+   pub label: Option<String>,
+   pub target_url: String,
+   pub notion_page_id: Option<NotionPageId>,
+}
+
+/// Collects form pairs into rows. Each row's fields are submitted in order starting with `id`, so
+/// every `id` begins a new row; `kit` is the shared group name.
+pub fn rows_from_pairs(pairs: &[(String, String)]) -> (Option<String>, Vec<KitRow>) {
+   let mut kit = None;
+   let mut rows: Vec<KitRow> = Vec::new();
+   for (key, value) in pairs {
+      if key == "kit" {
+         kit = Some(value.trim().to_string()).filter(|s| !s.is_empty());
+         continue;
+      }
+      if key == "id" || rows.is_empty() {
+         rows.push(KitRow::default());
+      }
+      let row = rows.last_mut().unwrap();
+      match key.as_str() {
+         "id" => row.id = value.clone(),
+         "label" => row.label = value.clone(),
+         "target_url" => row.target_url = value.clone(),
+         "notion_page" => row.notion_page = value.clone(),
+         _ => {}
+      }
+   }
+   (kit, rows)
+}
+
+/// Checks a target URL and returns it in the form it's stored in; see `target_url::normalize`.
+/// With `strict_idn`, a host mixing look-alike scripts is refused rather than stored.
+pub fn validate_target_url(raw: &str, strict_idn: bool) -> Result<String, String> {
+   let url = match url::Url::parse(raw) {
+      Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+      Ok(url) => return Err(format!("Target URL must be http or https, not {}", url.scheme())),
+      Err(e) => return Err(format!("Invalid target URL: {}", e)),
+   };
+   if strict_idn {
+      if let Some(label) = url.host_str().and_then(target_url::mixed_script_label) {
+         return Err(format!("Target URL host mixes scripts in '{}'", label));
+      }
+   }
+   Ok(target_url::normalize(raw).into_owned())
+}
+
+/// With no explicit target, a row with a Notion page redirects to that page.
+pub fn validate_row(row: &KitRow, notion_enabled: bool, strict_idn: bool) -> Result<ValidKitRow, String> {
+   let id: TagUid = row.id.trim().parse().map_err(|e| format!("Invalid tag id: {}", e))?;
+   let notion_page_id = Some(row.notion_page.trim())
+      .filter(|s| !s.is_empty())
+      .map(|page| {
+         if !notion_enabled {
+            return Err("Notion integration is disabled".to_string());
+         }
+         NotionPageId::new(page).map_err(|e| e.to_string())
+      })
+      .transpose()?;
+   let target_url = match (row.target_url.trim(), &notion_page_id) {
+      ("", Some(page)) => page.notion_url(),
+      ("", None) => return Err("Needs a target URL or a Notion page".to_string()),
+      (raw, _) => validate_target_url(raw, strict_idn)?,
+   };
+   Ok(ValidKitRow {
+      id,
+      label: Some(row.label.trim().to_string()).filter(|s| !s.is_empty()),
+      target_url,
+      notion_page_id,
+   })
+}
+
+/// Validates every non-blank row. The kit is only created if all of them pass; otherwise the
+/// per-row results are returned so each error can be shown beside its row.
+pub fn plan_kit(rows: &[KitRow], notion_enabled: bool, strict_idn: bool) -> Result<Vec<ValidKitRow>, Vec<RowResult>> {
+   let rows: Vec<&KitRow> = rows.iter().filter(|row| !row.is_blank()).collect();
+   if rows.is_empty() {
+      return Err(Vec::new());
+   }
+   if rows.len() > MAX_KIT_ROWS {
+      let error = format!("A kit has at most {} tags", MAX_KIT_ROWS);
+      return Err(
+         rows
+            .iter()
+            .map(|row| RowResult::rejected(&row.id, error.clone()))
+            .collect(),
+      );
+   }
+
+   let mut seen = HashSet::new();
+   let results: Vec<Result<ValidKitRow, String>> = rows
+      .iter()
+      .map(|row| {
+         let valid = validate_row(row, notion_enabled, strict_idn)?;
+         if !seen.insert(valid.id) {
+            return Err(format!("{} appears more than once in this kit", valid.id));
+         }
+         Ok(valid)
+      })
+      .collect();
+
+   if results.iter().all(Result::is_ok) {
+      return Ok(results.into_iter().map(Result::unwrap).collect());
+   }
+   Err(
+      rows
+         .iter()
+         .zip(results)
+         .map(|(row, result)| match result {
+            Ok(valid) => RowResult::not_attempted(&valid.id.to_string()),
+            Err(error) => RowResult::rejected(&row.id, error),
+         })
+         .collect(),
+   )
+}
+
+/// Pairs each submitted row with its error for re-rendering the form, padded with blank rows.
+/// `results` only covers non-blank rows, in order, as returned by `plan_kit`.
+pub fn annotate(rows: Vec<KitRow>, results: Vec<RowResult>) -> Vec<(KitRow, Option<String>)> {
+   let mut results = results.into_iter();
+   let len = rows.len().max(MAX_KIT_ROWS);
+   rows
+      .into_iter()
+      .chain(std::iter::repeat(KitRow::default()))
+      .take(len)
+      .map(|row| {
+         let error = match row.is_blank() {
+            true => None,
+            false => results.next().and_then(|result| result.error),
+         };
+         (row, error)
+      })
+      .collect()
+}
+
+/// A per-row outcome, in the style of a 207 Multi-Status body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowResult {
+   pub id: String,
+   pub status: u16,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   pub error: Option<String>,
+}
+
+impl RowResult {
+   pub fn created(id: &TagUid) -> Self {
+      RowResult {
+         id: id.to_string(),
+         status: 201,
+         error: None,
+      }
+   }
+
+   pub fn rejected(id: &str, error: String) -> Self {
+      RowResult {
+         id: id.trim().to_string(),
+         status: 422,
+         error: Some(error),
+      }
+   }
+
+   /// Valid, but not created (or changed) because another row in the submission failed.
+   pub fn not_attempted(id: &str) -> Self {
+      RowResult {
+         id: id.to_string(),
+         status: 424,
+         error: None,
+      }
+   }
+
+   pub fn updated(id: &TagUid) -> Self {
+      RowResult {
+         id: id.to_string(),
+         status: 200,
+         error: None,
+      }
+   }
+
+   pub fn not_found(id: &TagUid) -> Self {
+      RowResult {
+         id: id.to_string(),
+         status: 404,
+         error: Some("No such tag".to_string()),
+      }
+   }
+
+   pub fn gone(id: &TagUid) -> Self {
+      RowResult {
+         id: id.to_string(),
+         status: 410,
+         error: Some("This tag has been deleted".to_string()),
+      }
+   }
+
+   pub fn conflict(id: &TagUid) -> Self {
+      RowResult {
+         id: id.to_string(),
+         status: 409,
+         error: Some("A tag with this id already exists".to_string()),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn row(id: &str, target_url: &str) -> KitRow {
+      KitRow {
+         id: id.to_string(),
+         target_url: target_url.to_string(),
+         ..Default::default()
+      }
+   }
+
+   fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+      pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+   }
+
+   #[test]
+   fn test_rows_from_pairs() {
+      let (kit, rows) = rows_from_pairs(&pairs(&[
+         ("kit", "Camera bag"),
+         ("id", "055B88A23C1250"),
+         ("label", "Bag"),
+         ("target_url", "https://example.com/bag"),
+         ("notion_page", ""),
+         ("id", "04A1B2C3D4E5F6"),
+         ("label", ""),
+         ("target_url", ""),
+         ("notion_page", "a1b2c3d4e5f67890abcdef1234567890"),
+      ]));
+      assert_eq!(kit.as_deref(), Some("Camera bag"));
+      assert_eq!(rows.len(), 2);
+      assert_eq!(rows[0].label, "Bag");
+      assert_eq!(rows[1].notion_page, "a1b2c3d4e5f67890abcdef1234567890");
+   }
+
+   #[test]
+   fn test_plan_kit_skips_blank_rows() {
+      let rows = vec![
+         row("055B88A23C1250", "https://example.com/bag"),
+         KitRow::default(),
+         row("  ", " "),
+      ];
+      let plan = plan_kit(&rows, true, false).unwrap();
+      assert_eq!(plan.len(), 1);
+      assert_eq!(plan[0].label, None);
+   }
+
+   #[test]
+   fn test_plan_kit_derives_target_from_notion() {
+      let rows = vec![KitRow {
+         id: "055B88A23C1250".to_string(),
+         notion_page: "a1b2c3d4e5f67890abcdef1234567890".to_string(),
+         ..Default::default()
+      }];
+      let plan = plan_kit(&rows, true, false).unwrap();
+      assert_eq!(
+         plan[0].target_url,
+         "https://www.notion.so/a1b2c3d4e5f67890abcdef1234567890"
+      );
+
+      let rejected = plan_kit(&rows, false, false).unwrap_err();
+      assert_eq!(rejected[0].error.as_deref(), Some("Notion integration is disabled"));
+   }
+
+   #[test]
+   fn test_partial_validation_failure_plans_nothing() {
+      let rows = vec![
+         row("055B88A23C1250", "https://example.com/bag"),
+         row("055B88A23C12", "https://example.com/body"),
+         row("04A1B2C3D4E5F6", "javascript:alert(1)"),
+         row("04A1B2C3D4E5F7", "https://example.com/charger"),
+      ];
+      let results = plan_kit(&rows, true, false).unwrap_err();
+      assert_eq!(
+         results.iter().map(|r| r.status).collect::<Vec<_>>(),
+         [424, 422, 422, 424]
+      );
+      assert!(results[1].error.as_ref().unwrap().starts_with("Invalid tag id"));
+      assert_eq!(
+         results[2].error.as_deref(),
+         Some("Target URL must be http or https, not javascript")
+      );
+   }
+
+   #[test]
+   fn test_target_urls_are_stored_normalized() {
+      assert_eq!(
+         validate_target_url("https://münchen.example/🎉", false).unwrap(),
+         "https://xn--mnchen-3ya.example/%F0%9F%8E%89"
+      );
+
+      let confusable = "https://\u{430}pple.com/";
+      assert_eq!(
+         validate_target_url(confusable, false).unwrap(),
+         "https://xn--pple-43d.com/"
+      );
+      assert_eq!(
+         validate_target_url(confusable, true),
+         Err("Target URL host mixes scripts in '\u{430}pple'".to_string())
+      );
+      assert!(validate_target_url("https://münchen.example/", true).is_ok());
+   }
+
+   #[test]
+   fn test_plan_kit_rejects_duplicate_ids() {
+      let rows = vec![
+         row("055B88A23C1250", "https://example.com/a"),
+         row("055b88a23c1250", "https://example.com/b"),
+      ];
+      let results = plan_kit(&rows, true, false).unwrap_err();
+      assert_eq!(results[0].status, 424);
+      assert_eq!(
+         results[1].error.as_deref(),
+         Some("055B88A23C1250 appears more than once in this kit")
+      );
+   }
+
+   #[test]
+   fn test_plan_kit_caps_rows() {
+      let rows: Vec<KitRow> = (0..=MAX_KIT_ROWS)
+         .map(|i| row(&format!("055B88A23C12{:02X}", i), "https://example.com"))
+         .collect();
+      let results = plan_kit(&rows, true, false).unwrap_err();
+      assert!(results.iter().all(|r| r.status == 422));
+   }
+
+   #[test]
+   fn test_annotate_lines_errors_up_with_rows() {
+      let rows = vec![
+         row("055B88A23C12", "https://example.com/a"),
+         KitRow::default(),
+         row("04A1B2C3D4E5F6", "https://example.com/b"),
+      ];
+      let results = plan_kit(&rows, true, false).unwrap_err();
+      let annotated = annotate(rows, results);
+      assert_eq!(annotated.len(), MAX_KIT_ROWS);
+      assert!(annotated[0].1.as_ref().unwrap().starts_with("Invalid tag id"));
+      assert_eq!(annotated[1].1, None);
+      assert_eq!(annotated[2].1, None);
+      assert_eq!(annotated[2].0.id, "04A1B2C3D4E5F6");
+   }
+
+   #[test]
+   fn test_row_result_json() {
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      assert_eq!(
+         serde_json::to_string(&RowResult::created(&id)).unwrap(),
+         r#"{"id":"055B88A23C1250","status":201}"#
+      );
+      assert_eq!(
+         serde_json::to_string(&RowResult::conflict(&id)).unwrap(),
+         r#"{"id":"055B88A23C1250","status":409,"error":"A tag with this id already exists"}"#
+      );
+   }
+}
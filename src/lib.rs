@@ -0,0 +1,56 @@
+//! twag's tag resolution, for deciding how a scan is answered without running its server: say, in a
+//! kiosk that keeps its own copy of the tags.
+//!
+//! ```
+//! use std::collections::HashMap;
+//!
+//! use twag::{resolve, ResolveContext, ResolveOutcome, StoredTag, TagUid};
+//!
+//! let id: TagUid = "04A1B2C3D4E5F6".parse().unwrap();
+//! let mut tag = StoredTag::new("https://example.com/");
+//! tag.lang_targets =
+//!    vec![("de".parse().unwrap(), "https://example.com/de".to_string())];
+//! let tags = HashMap::from([(id, tag)]);
+//!
+//! let mut ctx = ResolveContext::new(chrono::Utc::now());
+//! ctx.accept_language = Some("de-AT,de;q=0.9");
+//! assert_eq!(
+//!    resolve(&tags, "04A1B2C3D4E5F6", ctx),
+//!    ResolveOutcome::Redirect {
+//!       url: "https://example.com/de".to_string(),
+//!       status: 307,
+//!    }
+//! );
+//! assert!(matches!(
+//!    resolve(&tags, "04A1B2C3D4E5F7", ctx),
+//!    ResolveOutcome::NotFound { .. }
+//! ));
+//! ```
+//!
+//! Only what's exported from the crate root is covered by semver. `StoredTag`, `ResolveContext`,
+//! `Policy` and the outcomes are `#[non_exhaustive]`, so fields and outcomes can be added in minor
+//! releases; start from `new` or `default` and set fields from there. The modules are public for
+//! twag's own server, and change whenever it does.
+
+#[doc(hidden)]
+pub mod geo;
+#[doc(hidden)]
+pub mod i18n;
+#[doc(hidden)]
+pub mod maintenance;
+#[doc(hidden)]
+pub mod models;
+#[doc(hidden)]
+pub mod reprogram;
+#[doc(hidden)]
+pub mod resolve;
+#[doc(hidden)]
+pub mod schedule;
+#[doc(hidden)]
+pub mod stale_redirect;
+#[doc(hidden)]
+pub mod taps;
+
+pub use models::{LanguageTag, TagUid};
+pub use reprogram::{Review, ReviewState, Thresholds};
+pub use resolve::{resolve, Gate, Page, Policy, RedirectKind, ResolveContext, ResolveOutcome, StoredTag, TagStore};
@@ -0,0 +1,567 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::db_report::Planned;
+use crate::models::{self, TwagTag};
+
+/// Rows returned when no limit, or too large a one, is asked for.
+pub const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sort {
+   Id,
+   Label,
+   Target,
+   Created,
+   LastTapped,
+   Taps,
+   Updated,
+}
+
+impl Sort {
+   /// The only identifiers ever interpolated into a listing query.
+   fn column(&self) -> &'static str {
+      match self {
+         Sort::Id => "id",
+         Sort::Label => "label",
+         Sort::Target => "target_url",
+         Sort::Created => "created_at",
+         Sort::LastTapped => "last_accessed",
+         Sort::Taps => "access_count",
+         Sort::Updated => "updated_at",
+      }
+   }
+
+   /// As the query string has it.
+   fn name(&self) -> &'static str {
+      match self {
+         Sort::Id => "id",
+         Sort::Label => "label",
+         Sort::Target => "target",
+         Sort::Created => "created",
+         Sort::LastTapped => "last_tapped",
+         Sort::Taps => "taps",
+         Sort::Updated => "updated",
+      }
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+   Asc,
+   Desc,
+}
+
+impl Direction {
+   /// As the query string has it.
+   fn name(&self) -> &'static str {
+      match self {
+         Direction::Asc => "asc",
+         Direction::Desc => "desc",
+      }
+   }
+}
+
+/// Filters for the tag listings, as taken from the query string. Every value is bound; only the
+/// sort column and direction are written into the SQL, and both come from fixed lists.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TagFilter {
+   /// Substring of the id, label, or target URL, case-insensitively, or an id in any form an id
+   /// field takes; see `searched_id`.
+   pub q: Option<String>,
+   pub kit: Option<String>,
+   /// As `twag_tags.tag_model` stores it; see `TagModel`.
+   pub tag_model: Option<String>,
+   pub maintenance: Option<bool>,
+   pub stateful: Option<bool>,
+   /// Archived tags instead of those in use; the two are never listed together.
+   #[serde(default)]
+   pub archived: bool,
+   /// Unset, `default_order`'s, or by id.
+   pub sort: Option<Sort>,
+   /// Unset, ascending, but for `default_order`'s own.
+   pub dir: Option<Direction>,
+   /// The order when no `sort` is asked for, where a listing has its own. Not read from the query
+   /// string.
+   #[serde(skip)]
+   pub default_order: Option<(Sort, Direction)>,
+   /// Rows per page.
+   #[serde(alias = "per_page")]
+   pub limit: Option<i64>,
+   /// Counted from 1, of `limit` rows each.
+   pub page: Option<i64>,
+   /// Only these tags, e.g. a selection for printing. Not read from the query string.
+   #[serde(skip)]
+   pub ids: Option<Vec<String>>,
+}
+
+/// A row of either listing, as shown: a `TwagTag` with its times in UTC to the minute.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagRow {
+   pub id: String,
+   pub label: Option<String>,
+   pub target_url: String,
+   pub kit: Option<String>,
+   pub access_count: Option<i32>,
+   pub maintenance: bool,
+   pub stateful: bool,
+   /// Off for decommissioned tags, which answer 410 Gone.
+   pub enabled: bool,
+   /// `YYYY-MM-DD HH:MM`, in UTC, if the tag stops redirecting at some point.
+   pub expires_at: Option<String>,
+   /// `YYYY-MM-DD HH:MM`, in UTC, while the tag is yet to activate.
+   pub activates_at: Option<String>,
+   /// `YYYY-MM-DD HH:MM`, in UTC.
+   pub last_accessed: Option<String>,
+   /// Needed to build scan URLs; never listed.
+   #[serde(skip_serializing)]
+   pub count_token: Option<String>,
+   /// `ok` unless the counter dropped back; see `reprogram`.
+   pub review_state: String,
+}
+
+impl TagRow {
+   /// `tag` as listed at `now`, which hides an activation time already past.
+   pub fn new(tag: TwagTag, now: DateTime<Utc>) -> Self {
+      let minutes = |at: Option<DateTime<Utc>>| at.map(|at| at.format("%Y-%m-%d %H:%M").to_string());
+      TagRow {
+         id: tag.id.to_string(),
+         label: tag.label,
+         target_url: tag.target_url,
+         kit: tag.kit,
+         access_count: tag.access_count,
+         maintenance: tag.maintenance,
+         stateful: tag.stateful,
+         enabled: tag.enabled,
+         expires_at: minutes(tag.expires_at),
+         activates_at: minutes(tag.activates_at.filter(|at| *at > now)),
+         last_accessed: minutes(tag.last_accessed),
+         count_token: tag.count_token,
+         review_state: tag.review_state,
+      }
+   }
+}
+
+/// Escapes `LIKE` wildcards so user input only ever matches literally.
+fn like_pattern(s: &str) -> String {
+   let escaped = s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+   format!("%{}%", escaped)
+}
+
+fn non_empty(s: &Option<String>) -> Option<&str> { s.as_deref().map(str::trim).filter(|s| !s.is_empty()) }
+
+/// The id a search names, if it's one as an id field takes it: with colons, or with the checksum
+/// copied from a page, neither of which a substring match would find.
+fn searched_id(q: &str) -> Option<String> { models::parse_tag_input(q, &[]).ok().map(|slug| slug.id.to_string()) }
+
+impl TagFilter {
+   /// Compiles to a query selecting `TwagTag`s, a page of them.
+   pub fn query(&self) -> QueryBuilder<'static, Postgres> {
+      let mut query = QueryBuilder::new(format!(
+         "SELECT id::text AS id, target_url, extract(epoch FROM created_at)::bigint AS created_at, \
+          extract(epoch FROM updated_at)::bigint AS updated_at, \
+          extract(epoch FROM last_accessed)::bigint AS last_accessed, access_count, last_seen_tap_count, \
+          notion_page_id::text AS notion_page_id, label, kit, maintenance, stateful, enabled, \
+          extract(epoch FROM expires_at)::bigint AS expires_at, \
+          extract(epoch FROM activates_at)::bigint AS activates_at, count_token, review_state \
+          FROM {} WHERE deleted_at IS NULL",
+         self.table()
+      ));
+      self.push_conditions(&mut query);
+      let (sort, dir) = self.order();
+      let dir = match dir {
+         Direction::Asc => "ASC",
+         Direction::Desc => "DESC",
+      };
+      query.push(format!(" ORDER BY {} {} NULLS LAST, id LIMIT ", sort.column(), dir));
+      query.push_bind(self.limit());
+      if self.page() > 1 {
+         query.push(" OFFSET ");
+         query.push_bind((self.page() - 1) * self.limit());
+      }
+      query
+   }
+
+   /// Compiles to a query counting the tags on every page, for whether there's another.
+   pub fn count(&self) -> QueryBuilder<'static, Postgres> {
+      let mut query = QueryBuilder::new(format!(
+         "SELECT count(*) FROM {} WHERE deleted_at IS NULL",
+         self.table()
+      ));
+      self.push_conditions(&mut query);
+      query
+   }
+
+   /// The sort column and direction: as asked for, else `default_order`, else by id.
+   pub fn order(&self) -> (Sort, Direction) {
+      match (self.sort, self.default_order) {
+         (Some(sort), _) => (sort, self.dir.unwrap_or(Direction::Asc)),
+         (None, Some((sort, dir))) => (sort, self.dir.unwrap_or(dir)),
+         (None, None) => (Sort::Id, self.dir.unwrap_or(Direction::Asc)),
+      }
+   }
+
+   /// Archived tags are kept apart from those in use, in a table of their own.
+   fn table(&self) -> &'static str {
+      if self.archived {
+         "twag_tags_archive"
+      } else {
+         "twag_tags"
+      }
+   }
+
+   fn push_conditions(&self, query: &mut QueryBuilder<'static, Postgres>) {
+      if let Some(ids) = &self.ids {
+         query.push(" AND id::text = ANY(");
+         query.push_bind(ids.clone());
+         query.push(")");
+      }
+      if let Some(q) = non_empty(&self.q) {
+         let pattern = like_pattern(q);
+         query.push(" AND (id::text ILIKE ");
+         query.push_bind(pattern.clone());
+         query.push(" OR label ILIKE ");
+         query.push_bind(pattern.clone());
+         query.push(" OR target_url ILIKE ");
+         query.push_bind(pattern);
+         if let Some(id) = searched_id(q) {
+            query.push(" OR id::text = ");
+            query.push_bind(id);
+         }
+         query.push(")");
+      }
+      if let Some(kit) = non_empty(&self.kit) {
+         query.push(" AND kit = ");
+         query.push_bind(kit.to_string());
+      }
+      if let Some(tag_model) = non_empty(&self.tag_model) {
+         query.push(" AND tag_model = ");
+         query.push_bind(tag_model.to_string());
+      }
+      if let Some(maintenance) = self.maintenance {
+         query.push(" AND maintenance = ");
+         query.push_bind(maintenance);
+      }
+      if let Some(stateful) = self.stateful {
+         query.push(" AND stateful = ");
+         query.push_bind(stateful);
+      }
+   }
+
+   pub fn limit(&self) -> i64 { self.limit.unwrap_or(MAX_LIMIT).clamp(1, MAX_LIMIT) }
+
+   pub fn page(&self) -> i64 { self.page.unwrap_or(1).max(1) }
+
+   /// `/tags` with these filters, at `page`.
+   pub fn page_href(&self, page: i64) -> String {
+      let mut query = url::form_urlencoded::Serializer::new(String::new());
+      for (name, value) in [("q", &self.q), ("kit", &self.kit), ("tag_model", &self.tag_model)] {
+         if let Some(value) = non_empty(value) {
+            query.append_pair(name, value);
+         }
+      }
+      for (name, value) in [("maintenance", self.maintenance), ("stateful", self.stateful)] {
+         if let Some(value) = value {
+            query.append_pair(name, if value { "true" } else { "false" });
+         }
+      }
+      if self.archived {
+         query.append_pair("archived", "true");
+      }
+      if let Some(sort) = self.sort {
+         query.append_pair("sort", sort.name());
+      }
+      if let Some(dir) = self.dir {
+         query.append_pair("dir", dir.name());
+      }
+      if let Some(limit) = self.limit {
+         query.append_pair("limit", &limit.to_string());
+      }
+      query.append_pair("page", &page.to_string());
+      format!("/tags?{}", query.finish())
+   }
+}
+
+/// The listings' common shapes, for `/admin/db-report`; see `db_report`.
+pub fn planned() -> Vec<Planned> {
+   let limit = MAX_LIMIT.to_string();
+   let filtered = |name, filter: TagFilter, args: &[&str]| {
+      let args = args.iter().map(|arg| arg.to_string()).chain([limit.clone()]).collect();
+      Planned::built(name, filter.query().sql().to_string(), args)
+   };
+   vec![
+      filtered("Listing", TagFilter::default(), &[]),
+      filtered(
+         "Listing by kit",
+         TagFilter {
+            kit: Some("Workshop".to_string()),
+            ..TagFilter::default()
+         },
+         &["'Workshop'"],
+      ),
+      filtered(
+         "Listing search",
+         TagFilter {
+            q: Some("lamp".to_string()),
+            ..TagFilter::default()
+         },
+         &["'%lamp%'", "'%lamp%'", "'%lamp%'"],
+      ),
+      filtered(
+         "Listing by recent taps",
+         TagFilter {
+            sort: Some(Sort::LastTapped),
+            dir: Some(Direction::Desc),
+            ..TagFilter::default()
+         },
+         &[],
+      ),
+   ]
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const SELECT: &str = "SELECT id::text AS id, target_url, extract(epoch FROM created_at)::bigint AS created_at, \
+                         extract(epoch FROM updated_at)::bigint AS updated_at, \
+                         extract(epoch FROM last_accessed)::bigint AS last_accessed, access_count, \
+                         last_seen_tap_count, notion_page_id::text AS notion_page_id, label, kit, maintenance, \
+                         stateful, enabled, extract(epoch FROM expires_at)::bigint AS expires_at, \
+                         extract(epoch FROM activates_at)::bigint AS activates_at, count_token, review_state \
+                         FROM twag_tags WHERE deleted_at IS NULL";
+
+   fn sql(filter: &TagFilter) -> String { filter.query().sql().strip_prefix(SELECT).unwrap().to_string() }
+
+   #[test]
+   fn test_no_filters() {
+      assert_eq!(sql(&TagFilter::default()), " ORDER BY id ASC NULLS LAST, id LIMIT $1");
+   }
+
+   #[test]
+   fn test_the_listing_can_have_its_own_default_order() {
+      let listing = |query: &str| TagFilter {
+         default_order: Some((Sort::Updated, Direction::Desc)),
+         ..serde_json::from_str(query).unwrap()
+      };
+      assert_eq!(sql(&listing("{}")), " ORDER BY updated_at DESC NULLS LAST, id LIMIT $1");
+      let ascending = listing(r#"{"dir": "asc"}"#);
+      assert_eq!(sql(&ascending), " ORDER BY updated_at ASC NULLS LAST, id LIMIT $1");
+      assert_eq!(ascending.page_href(2), "/tags?dir=asc&page=2");
+      assert_eq!(
+         sql(&listing(r#"{"sort": "label"}"#)),
+         " ORDER BY label ASC NULLS LAST, id LIMIT $1"
+      );
+      assert_eq!(
+         sql(&listing(r#"{"sort": "id"}"#)),
+         " ORDER BY id ASC NULLS LAST, id LIMIT $1"
+      );
+      assert_eq!(listing("{}").page_href(2), "/tags?page=2");
+   }
+
+   #[test]
+   fn test_counts_every_page_with_the_same_filters() {
+      let filter = TagFilter {
+         kit: Some("Camera bag".to_string()),
+         page: Some(3),
+         ..Default::default()
+      };
+      assert_eq!(
+         filter.count().sql(),
+         "SELECT count(*) FROM twag_tags WHERE deleted_at IS NULL AND kit = $1"
+      );
+   }
+
+   #[test]
+   fn test_rows_show_times_to_the_minute() {
+      let now: DateTime<Utc> = "2026-10-16T12:00:00Z".parse().unwrap();
+      let tag = TwagTag {
+         id: "055B88A23C1250".parse().unwrap(),
+         target_url: "https://example.com/".to_string(),
+         created_at: None,
+         updated_at: None,
+         last_accessed: Some("2026-10-16T09:05:59Z".parse().unwrap()),
+         access_count: Some(3),
+         last_seen_tap_count: None,
+         notion_page_id: None,
+         label: None,
+         kit: None,
+         maintenance: false,
+         stateful: false,
+         enabled: true,
+         expires_at: Some("2027-01-01T00:00:00Z".parse().unwrap()),
+         activates_at: Some("2026-10-16T11:00:00Z".parse().unwrap()),
+         count_token: None,
+         review_state: "ok".to_string(),
+      };
+      let row = TagRow::new(tag.clone(), now);
+      assert_eq!(row.id, "055B88A23C1250");
+      assert_eq!(row.last_accessed.as_deref(), Some("2026-10-16 09:05"));
+      assert_eq!(row.expires_at.as_deref(), Some("2027-01-01 00:00"));
+      assert_eq!(row.activates_at, None);
+
+      let pending = TagRow::new(tag, "2026-10-16T10:00:00Z".parse().unwrap());
+      assert_eq!(pending.activates_at.as_deref(), Some("2026-10-16 11:00"));
+   }
+
+   #[test]
+   fn test_archived_tags_are_listed_apart() {
+      let archived = TagFilter {
+         archived: true,
+         kit: Some("Camera bag".to_string()),
+         ..Default::default()
+      };
+      let sql = archived.query().sql().to_string();
+      assert!(
+         sql.ends_with(
+            "FROM twag_tags_archive WHERE deleted_at IS NULL AND kit = $1 ORDER BY id ASC NULLS LAST, id LIMIT $2"
+         ),
+         "{sql}"
+      );
+      assert_eq!(archived.page_href(2), "/tags?kit=Camera+bag&archived=true&page=2");
+   }
+
+   #[test]
+   fn test_filter_matrix_binds_in_order() {
+      let cases = [
+         (
+            TagFilter {
+               kit: Some("Camera bag".to_string()),
+               ..Default::default()
+            },
+            " AND kit = $1 ORDER BY id ASC NULLS LAST, id LIMIT $2",
+         ),
+         (
+            TagFilter {
+               ids: Some(vec!["055B88A23C1250".to_string()]),
+               kit: Some("Camera bag".to_string()),
+               ..Default::default()
+            },
+            " AND id::text = ANY($1) AND kit = $2 ORDER BY id ASC NULLS LAST, id LIMIT $3",
+         ),
+         (
+            TagFilter {
+               maintenance: Some(true),
+               stateful: Some(false),
+               ..Default::default()
+            },
+            " AND maintenance = $1 AND stateful = $2 ORDER BY id ASC NULLS LAST, id LIMIT $3",
+         ),
+         (
+            TagFilter {
+               kit: Some("Camera bag".to_string()),
+               tag_model: Some("ntag215".to_string()),
+               ..Default::default()
+            },
+            " AND kit = $1 AND tag_model = $2 ORDER BY id ASC NULLS LAST, id LIMIT $3",
+         ),
+         (
+            TagFilter {
+               q: Some("bag".to_string()),
+               kit: Some("Camera bag".to_string()),
+               ..Default::default()
+            },
+            " AND (id::text ILIKE $1 OR label ILIKE $2 OR target_url ILIKE $3) AND kit = $4 \
+             ORDER BY id ASC NULLS LAST, id LIMIT $5",
+         ),
+         (
+            TagFilter {
+               q: Some("bag".to_string()),
+               kit: Some("Camera bag".to_string()),
+               maintenance: Some(false),
+               stateful: Some(true),
+               sort: Some(Sort::Taps),
+               dir: Some(Direction::Desc),
+               limit: Some(20),
+               ..Default::default()
+            },
+            " AND (id::text ILIKE $1 OR label ILIKE $2 OR target_url ILIKE $3) AND kit = $4 \
+             AND maintenance = $5 AND stateful = $6 ORDER BY access_count DESC NULLS LAST, id LIMIT $7",
+         ),
+      ];
+      for (filter, expected) in cases {
+         assert_eq!(sql(&filter), expected, "{filter:?}");
+      }
+   }
+
+   #[test]
+   fn test_blank_text_filters_are_ignored() {
+      let blank = TagFilter {
+         q: Some("  ".to_string()),
+         kit: Some(String::new()),
+         tag_model: Some(" ".to_string()),
+         ..Default::default()
+      };
+      assert_eq!(sql(&blank), sql(&TagFilter::default()));
+   }
+
+   #[test]
+   fn test_sort_comes_from_the_whitelist() {
+      let parsed: Result<TagFilter, _> = serde_json::from_str(r#"{"sort": "id; DROP TABLE twag_tags"}"#);
+      assert!(parsed.is_err());
+
+      let parsed: TagFilter = serde_json::from_str(r#"{"sort": "last_tapped", "dir": "desc"}"#).unwrap();
+      assert_eq!(sql(&parsed), " ORDER BY last_accessed DESC NULLS LAST, id LIMIT $1");
+   }
+
+   #[test]
+   fn test_pages_past_the_first_are_offset() {
+      let parsed: TagFilter = serde_json::from_str(r#"{"sort": "updated", "dir": "desc", "per_page": 50}"#).unwrap();
+      assert_eq!(parsed.limit, Some(50));
+      assert_eq!(sql(&parsed), " ORDER BY updated_at DESC NULLS LAST, id LIMIT $1");
+
+      let third = TagFilter {
+         page: Some(3),
+         ..parsed
+      };
+      assert_eq!(
+         sql(&third),
+         " ORDER BY updated_at DESC NULLS LAST, id LIMIT $1 OFFSET $2"
+      );
+      assert_eq!(third.page_href(4), "/tags?sort=updated&dir=desc&limit=50&page=4");
+
+      let zeroth = TagFilter {
+         page: Some(0),
+         ..Default::default()
+      };
+      assert_eq!(zeroth.page(), 1);
+      assert_eq!(sql(&zeroth), sql(&TagFilter::default()));
+   }
+
+   #[test]
+   fn test_page_links_keep_the_filters() {
+      let filter = TagFilter {
+         q: Some("lamp & shade".to_string()),
+         kit: Some(" ".to_string()),
+         stateful: Some(false),
+         ..Default::default()
+      };
+      assert_eq!(filter.page_href(2), "/tags?q=lamp+%26+shade&stateful=false&page=2");
+   }
+
+   #[test]
+   fn test_searches_for_an_id_match_it_exactly() {
+      let search = |q: &str| TagFilter {
+         q: Some(q.to_string()),
+         ..Default::default()
+      };
+      let exact = " AND (id::text ILIKE $1 OR label ILIKE $2 OR target_url ILIKE $3 OR id::text = $4) \
+                   ORDER BY id ASC NULLS LAST, id LIMIT $5";
+      assert_eq!(sql(&search("05:5B:88:A2:3C:12:50")), exact);
+      assert_eq!(searched_id("055B88A23C1250 · XQ").as_deref(), Some("055B88A23C1250"));
+      assert_eq!(searched_id("055b88a23c1250").as_deref(), Some("055B88A23C1250"));
+      assert_eq!(searched_id("example.com/menu"), None);
+      assert_eq!(
+         sql(&search("example.com/menu")),
+         " AND (id::text ILIKE $1 OR label ILIKE $2 OR target_url ILIKE $3) ORDER BY id ASC NULLS LAST, id LIMIT $4"
+      );
+   }
+
+   #[test]
+   fn test_like_pattern_escapes_wildcards() {
+      assert_eq!(like_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+   }
+}
@@ -1,237 +1,1398 @@
 use askama::Template;
 use axum::{
    extract,
-   http::{header, StatusCode},
+   http::{header, HeaderMap, StatusCode},
+   middleware,
    response::{IntoResponse, Response},
-   routing::{get, post},
-   Router,
+   routing::post,
 };
-use lazy_regex::regex_captures;
-use notion_client::{
-   endpoints::Client as Notion, objects::data_source::DataSource, objects::database::DatabaseProperty,
-};
-use serde::Deserialize;
+use notion_client::endpoints::Client as Notion;
+use serde::{Deserialize, Serialize};
 use serde_hex::{Compact, SerHexOpt};
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use std::io::IsTerminal;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock, RwLock};
 use tower_http::{
    trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
    LatencyUnit,
 };
-use tracing::{debug, info, trace, warn, Level};
+use tracing::{info, trace, warn, Level};
+
+mod age_gate;
+mod app_links;
+mod arrival;
+mod audit;
+mod badge;
+mod branding;
+mod bulk;
+mod canonical;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod checkout;
+mod constraints;
+mod count_token;
+mod dashboard;
+mod db_report;
+mod export;
+mod failover;
+mod fallback;
+mod favicon;
+mod filters;
+mod fixtures;
+mod health;
+mod import;
+mod kit;
+mod listing;
+mod mqtt;
+mod ndef;
+mod negative_cache;
+mod notion;
+mod notion_sync;
+mod outbox;
+mod params;
+// Anything that fetches user-supplied URLs must go through `net::safe_fetch`.
+mod net;
+mod pool;
+mod privacy;
+mod provision;
+mod public_status;
+mod qr;
+mod quota;
+mod rate_limit;
+mod redact;
+mod replica;
+mod report;
+mod request_log;
+mod retention;
+mod routes;
+mod security;
+mod settings;
+mod setup;
+mod share;
+mod short_code;
+mod state;
+mod stats;
+mod streaming;
+mod tag_lock;
+mod tag_models;
+mod target_url;
+mod targets;
+mod telemetry;
+mod timing;
+mod trash;
+mod upcoming;
+mod vcard;
+mod visitors;
+mod webhook;
 
-mod models;
-use models::{Hex14, NotionPageId};
+use app_links::AppLinks;
+use branding::Branding;
+use bulk::{Action, BulkRequest, Rejected};
+use canonical::enforce_canonical_host;
+use checkout::CheckoutState;
+use failover::{CachedRedirect, Failover, Preload, Resolved};
+use geo::GeoIp;
+use health::HealthToken;
+use kit::{KitRow, RowResult, ValidKitRow};
+use listing::{Direction, Sort, TagFilter, TagRow};
+use models::{LanguageTag, NotionPageId, TagSlug, TagUid, TwagTag};
+use mqtt::{MqttConfig, MqttPublisher, TapEvent};
+use negative_cache::NegativeCache;
+use notion::edits::NotionEdits;
+use notion::limiter::NotionLimiter;
+use notion::picker::{self, NotionPicker, PickError, Searchable};
+use notion::schema::{self, ContainersDb, ContainersRelationColumn, DatabaseRef, ThingsDb, ThingsRelationColumn};
+use notion::NotionTagPages;
+use params::{CheckedForm, CheckedQuery, KnownParams, Unexpected};
+use pool::{PoolSizing, ScalingPool};
+use privacy::Preference;
+use provision::ProvisioningKey;
+use rate_limit::RateLimiter;
+use redact::Redaction;
+use replica::ReadPools;
+use reprogram::ReviewState;
+use resolve::{Gate, Page, RedirectKind, ResolveContext, ResolveOutcome, StoredTag};
+use retention::{RetentionPolicy, RetentionReport};
+use routes::{Access, Doc, Method, RouteDoc, Routes};
+use security::CookieKey;
+use settings::{Settings, SharedSettings};
+use state::{CacheState, DbState, HealthState, NotionState, ScanState, SettingsState};
+use tag_lock::TagLocks;
+use tag_models::TagModel;
+use telemetry::{Telemetry, TelemetryConfig};
+use timing::Timings;
+use twag::{geo, maintenance, models, reprogram, resolve, schedule, stale_redirect, taps};
+use vcard::Contact;
+use visitors::{Sketch, VisitorHasher};
+use webhook::WebhookConfig;
 
-async fn initialize_connection(postgres_url: &str) -> Result<Pool<Postgres>, sqlx::Error> {
+async fn initialize_connection(postgres_url: &str, sizing: PoolSizing) -> Result<ScalingPool, sqlx::Error> {
    let options: sqlx::postgres::PgConnectOptions = postgres_url.parse()?;
    info!(
       host = options.get_host(),
       database = ?options.get_database(),
       username = options.get_username(),
+      pool_max = sizing.max_connections,
+      pool_hard_cap = sizing.hard_cap,
+      autoscale = sizing.autoscale,
       "Connecting to Postgres"
    );
 
-   let pool = PgPoolOptions::new()
-      .min_connections(1)
-      .max_connections(5)
-      .idle_timeout(std::time::Duration::from_secs(300))
-      .connect_with(options)
-      .await?;
+   let pool = ScalingPool::connect(options, sizing).await?;
 
-   sqlx::query("SELECT 1").fetch_one(&pool).await?;
+   sqlx::query("SELECT 1").fetch_one(&pool.get()).await?;
 
    info!("Postgres connection established");
    Ok(pool)
 }
 
-/// Retrieve the primary data-source for a Notion Database.
-///
-/// Since API version 2025-09-03, Database properties live on data-sources
-/// rather than on the Database object itself. This retrieves the Database to
-/// find its data-source IDs, then fetches the first data-source.
-async fn retrieve_data_source(
-   client: &Notion,
-   database_id: &NotionPageId,
-   data_source_id: &str,
-) -> Result<DataSource, String> {
-   // FIXME: once `notion-client` fixes Database deserialization for API >=
-   //    2025-09-03, discover the data_source_id from the Database object
-   //    instead of requiring it as a parameter:
-   //
-   //     let db = client
-   //        .databases
-   //        .retrieve_a_database(database_id)
-   //        .await
-   //        .map_err(|err| format!("Failed to retrieve Database {}: {:?}", database_id, err))?;
-   //     let ds_ref = db
-   //        .data_sources
-   //        .first()
-   //        .ok_or_else(|| format!("Database {} has no DataSource", database_id))?;
-   //     let data_source_id = &ds_ref.id;
-
-   let ds = client
-      .data_sources
-      .retrieve_a_data_source(data_source_id)
-      .await
-      .map_err(|err| {
-         format!(
-            "Failed to retrieve DataSource {} for Database {}: {:?}",
-            data_source_id, database_id, err
-         )
-      })?;
+/// The Notion settings, only demanded when the integration is enabled.
+#[derive(Debug, PartialEq)]
+struct NotionConfig {
+   token: String,
+   things_db: ThingsDb,
+   things_column: ThingsRelationColumn,
+   containers_db: ContainersDb,
+   containers_column: ContainersRelationColumn,
+   // Data source IDs (required until notion-client supports the 2025-09-03 Database schema)
+   things_ds: String,
+   containers_ds: String,
+}
+
+impl NotionConfig {
+   /// Disabled by `TWAG_NOTION_ENABLED=false`, or implicitly when `NOTION_TOKEN` is absent.
+   fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+      let var = |name: &str| var(name).filter(|s| !s.is_empty());
+      if var("TWAG_NOTION_ENABLED").is_some_and(|s| s == "false") {
+         return Ok(None);
+      }
+      let Some(token) = var("NOTION_TOKEN") else {
+         return Ok(None);
+      };
 
-   debug!(?ds, "Retrieved DataSource for Database {}", database_id);
-   Ok(ds)
+      let required = |name: &str| var(name).ok_or_else(|| format!("{} must be set", name));
+      let page_id =
+         |name: &str| NotionPageId::new(required(name)?).map_err(|e| format!("Invalid {} format: {}", name, e));
+      Ok(Some(NotionConfig {
+         token,
+         things_db: ThingsDb(page_id("NOTION_THINGS_DB")?),
+         things_column: ThingsRelationColumn(required("NOTION_THINGS_COLUMN_NAME")?),
+         containers_db: ContainersDb(page_id("NOTION_CONTAINERS_DB")?),
+         containers_column: ContainersRelationColumn(required("NOTION_CONTAINERS_COLUMN_NAME")?),
+         things_ds: required("NOTION_THINGS_DS").map_err(|e| format!("{} (pending notion-client fix)", e))?,
+         containers_ds: required("NOTION_CONTAINERS_DS").map_err(|e| format!("{} (pending notion-client fix)", e))?,
+      }))
+   }
 }
 
-fn validate_relation_property(
-   data_source: &DataSource,
-   property_name: &str,
-   expected_target_db: &NotionPageId,
-) -> Result<(), String> {
-   let property = data_source
-      .properties
-      .get(property_name)
-      .ok_or_else(|| format!("Missing required property '{}' in DataSource", property_name))?;
+async fn initialize_notion(
+   config: &NotionConfig,
+) -> (Notion, Option<NotionTagPages>, Option<NotionEdits>, NotionPicker) {
+   let client = Notion::new(config.token.clone(), None).expect("Failed to create Notion client");
+   // Shared by every call below that runs once twag is serving
+   let limiter = NotionLimiter::default();
 
-   match property {
-      DatabaseProperty::Relation { relation, .. } => {
-         let actual_db_id = relation
-            .database_id
-            .as_ref()
-            .ok_or_else(|| format!("'{}' relation has no database_id", property_name))?;
+   trace!(things_ndb = %config.things_db, containers_ndb = %config.containers_db, "Parsed Database IDs");
+   let things = DatabaseRef {
+      name: "Things",
+      db: &config.things_db,
+      ds: &config.things_ds,
+      column: &config.things_column,
+   };
+   let containers = DatabaseRef {
+      name: "Containers",
+      db: &config.containers_db,
+      ds: &config.containers_ds,
+      column: &config.containers_column,
+   };
+   let report = schema::validate_databases(
+      |name, db, ds| {
+         let client = client.clone();
+         async move { schema::retrieve_schema(&client, name, &db, &ds).await }
+      },
+      things,
+      containers,
+   )
+   .await;
+   if let Err(report) = report.into_result() {
+      panic!("{}", report);
+   }
+   trace!(
+      things_column = %config.things_column,
+      containers_column = %config.containers_column,
+      "Validated Database relations"
+   );
 
-         if actual_db_id != expected_target_db {
-            return Err(format!(
-               "'{}' property points to wrong Database: expected {}, got {}",
-               property_name, expected_target_db, actual_db_id
-            ));
+   let tag_pages = match dotenvy::var("NOTION_THINGS_TAG_COLUMN_NAME")
+      .ok()
+      .filter(|s| !s.is_empty())
+   {
+      Some(tag_property) => {
+         let things_schema = schema::retrieve_schema(&client, "Things", &config.things_db, &config.things_ds)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+         schema::validate_rich_text(&things_schema, "Things", &tag_property).unwrap_or_else(|e| panic!("{}", e));
+         let state_property = dotenvy::var("NOTION_THINGS_STATE_COLUMN_NAME")
+            .ok()
+            .filter(|s| !s.is_empty());
+         if let Some(state_property) = &state_property {
+            schema::validate_select(&things_schema, "Things", state_property).unwrap_or_else(|e| panic!("{}", e));
          }
-
-         trace!("Validated '{}' property points to target Database", property_name);
+         Some(NotionTagPages {
+            client: client.clone(),
+            things_db: config.things_db.clone(),
+            things_ds: config.things_ds.clone(),
+            title_property: schema::find_title_property(&things_schema, "Things").unwrap_or_else(|e| panic!("{}", e)),
+            tag_property,
+            state_property,
+            limiter: limiter.clone(),
+         })
       }
-      _ => {
-         return Err(format!(
-            "'{}' property must be a relation type, found: {:?}",
-            property_name, property
-         ));
+      None => {
+         info!("NOTION_THINGS_TAG_COLUMN_NAME unset, tags will not get Notion pages automatically");
+         None
       }
-   }
-   Ok(())
-}
-
-async fn validate_notion_databases(
-   client: &Notion,
-   things_db: &NotionPageId,
-   things_ds: &str,
-   containers_db: &NotionPageId,
-   containers_ds: &str,
-   things_column_name: &str,
-   containers_column_name: &str,
-) -> Result<(), String> {
-   let things_ds = retrieve_data_source(client, things_db, things_ds).await?;
-   let containers_ds = retrieve_data_source(client, containers_db, containers_ds).await?;
+   };
 
-   validate_relation_property(&things_ds, things_column_name, containers_db)?;
-   validate_relation_property(&containers_ds, containers_column_name, things_db)?;
+   let url_edits = match dotenvy::var("NOTION_THINGS_URL_COLUMN_NAME")
+      .ok()
+      .filter(|s| !s.is_empty())
+   {
+      Some(url_property) => {
+         let things_schema = schema::retrieve_schema(&client, "Things", &config.things_db, &config.things_ds)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+         schema::validate_url(&things_schema, "Things", &url_property).unwrap_or_else(|e| panic!("{}", e));
+         Some(NotionEdits {
+            http: reqwest::Client::new(),
+            token: config.token.clone(),
+            things_ds: config.things_ds.clone(),
+            url_property,
+            limiter: limiter.clone(),
+            property_ids: OnceLock::new(),
+         })
+      }
+      None => {
+         info!("NOTION_THINGS_URL_COLUMN_NAME unset, target URLs edited in Notion will not be synced");
+         None
+      }
+   };
 
-   Ok(())
+   let title_property = |name: &'static str, db: NotionPageId, ds: String| {
+      let client = client.clone();
+      async move {
+         let schema = schema::retrieve_schema(&client, name, &db, &ds)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+         Searchable {
+            title_property: schema::find_title_property(&schema, name).unwrap_or_else(|e| panic!("{}", e)),
+            ds,
+         }
+      }
+   };
+   let picker = NotionPicker::new(
+      config.token.clone(),
+      title_property("Things", config.things_db.0.clone(), config.things_ds.clone()).await,
+      title_property(
+         "Containers",
+         config.containers_db.0.clone(),
+         config.containers_ds.clone(),
+      )
+      .await,
+      limiter,
+   );
+   (client, tag_pages, url_edits, picker)
 }
 
-fn init_tracing() {
-   use tracing_subscriber::{fmt, EnvFilter};
+#[allow(dead_code)]
+#[derive(Clone)]
+struct AppState {
+   pool: ScalingPool,
+   client: Option<Notion>,
+   notion_picker: Option<Arc<NotionPicker>>,
+   visitor_hasher: Option<VisitorHasher>,
+   settings: SharedSettings,
+   notion_outbox: bool,
+   retention: Arc<RwLock<RetentionReport>>,
+   mqtt: Option<MqttPublisher>,
+   webhook: Option<WebhookConfig>,
+   failover: Failover,
+   /// How many tags to load into the failover cache, at startup and on request.
+   preload: Preload,
+   lookup_limiter: Arc<RateLimiter>,
+   /// Ids recently scanned and not found; see `negative_cache`.
+   negative_cache: NegativeCache,
+   /// The client fallback tags' targets are probed with; see `fallback`.
+   prober: fallback::SharedProber,
+   app_links: Arc<AppLinks>,
+   /// Taken by every multi-step mutation of a tag; see `tag_lock`.
+   tag_locks: Arc<TagLocks>,
+   geoip: Option<Arc<GeoIp>>,
+   redaction: Option<Redaction>,
+   /// Changes what's logged while running; see `telemetry`.
+   telemetry: Telemetry,
+   /// Signs the age gate's confirmations; without it, every scan of a gated tag asks again.
+   cookie_key: Option<CookieKey>,
+   /// Checks pre-signed creation links; without it, creating tags always needs admin access.
+   provisioning_key: Option<ProvisioningKey>,
+   /// Allows `/healthz?deep=true`; see `health`.
+   health_token: Option<HealthToken>,
+   /// The last deep health check, while it's failing; the instance is unready until one passes.
+   unready: Arc<RwLock<Option<health::Report>>>,
+   /// The replica, if any, for reads; `pool` is always the primary. See `replica`.
+   reads: ReadPools,
+   /// Everything `build_router` registered, for `/help`.
+   route_docs: Arc<Vec<RouteDoc>>,
+}
 
-   let filter = EnvFilter::builder()
-      .with_default_directive(match dotenvy::var("RUST_FMT").as_deref() {
-         Ok("json") => Level::INFO.into(),
-         Ok("pretty") => Level::DEBUG.into(),
-         _ => Level::WARN.into(),
-      })
-      .parse_lossy(dotenvy::var("RUST_LOG").unwrap_or_else(|_| "info".into()))
-      .add_directive("hyper::client=info".parse().unwrap())
-      .add_directive("hyper::proto=warn".parse().unwrap());
+const SLUG: routes::Param = routes::path("slug", "tag id", "14 or 20 hex digits");
+const KIT: routes::Param = routes::path("kit", "string", "Kit name");
+const ADMIN_SLUG: routes::Param = routes::path(
+   "slug",
+   "tag slug",
+   "Tag id, optionally followed by x and a 6 digit counter",
+);
 
-   let format = fmt::format().with_timer(fmt::time::ChronoUtc::rfc_3339());
+/// Every route twag serves. Routes are only ever added here, through `Routes`, so that `/help`
+/// documents exactly what's served.
+fn build_router(pool: &ScalingPool) -> Routes<AppState> {
+   let log_submission = || middleware::from_fn_with_state(pool.clone(), request_log::log_submission);
+   let routes = Routes::default()
+      .get("/", index_page, Doc::public("Tag lookup box"))
+      // GET https://xz.ws/lookup?q=05:5B:88:A2:3C:12:50
+      .get(
+         "/lookup",
+         lookup_tag,
+         Doc::public("Redirects to an existing tag; rate-limited").param(
+            routes::query(
+               "q",
+               "tag id or URL",
+               "Id in any case, with or without colons, or a scan URL",
+            )
+            .required(),
+         ),
+      )
+      .get(
+         "/healthz",
+         health_check,
+         Doc::public("Postgres, Notion, MQTT and retention status")
+            .param(routes::query("deep", "true", "Also check pages, config and migrations"))
+            .param(routes::query("token", "string", "TWAG_HEALTH_TOKEN, for deep checks")),
+      )
+      .get("/help", help_page, Doc::admin("This page"))
+      .get(
+         "/status",
+         public_status_page,
+         Doc::public("When the tags marked public were last scanned, if TWAG_STATUS_PAGE is set"),
+      )
+      .get("/help.json", help_json, Doc::admin("This page, as JSON"))
+      .get(
+         "/.well-known/apple-app-site-association",
+         apple_app_site_association,
+         Doc::public("iOS universal links for /tag/*, when configured"),
+      )
+      .get(
+         "/.well-known/assetlinks.json",
+         asset_links,
+         Doc::public("Android app links, when configured"),
+      )
+      // GET https://xz.ws/tag/create?id=055B88A23C1250&tap_count=00000F
+      .get(
+         "/tag/create",
+         create_tag_page,
+         Doc::admin("Form for a new tag; redirects to its edit page if it exists")
+            .param(routes::query("id", "tag id or URL", "Bare id or scan URL").required())
+            .param(routes::query("tap_count", "hex", "Counter mirrored by the tag"))
+            .param(routes::query("target_url", "url", "Prefilled destination"))
+            .param(routes::query("exp", "unix time", "Creation link expiry"))
+            .param(routes::query("sig", "hex", "Creation link; for this id only")),
+      )
+      // POST https://xz.ws/tag/create?id=055B88A23C1250&tap_count=00000F: target_url=https://example.com
+      .add(
+         Method::Post,
+         "/tag/create",
+         post(create_tag).layer(log_submission()),
+         Doc::admin("Creates a tag")
+            .param(routes::query("id", "tag id or URL", "Used unless the form has an id").required())
+            .param(routes::query("tap_count", "hex", "Starting tap count"))
+            .param(routes::query("exp", "unix time", "Creation link expiry"))
+            .param(routes::query("sig", "hex", "Creation link; makes one tag"))
+            .param(routes::form("id", "tag id or URL", "Replaces the query's id"))
+            .param(routes::form("tap_count", "hex", "Starting tap count"))
+            .param(routes::form(
+               "target_url",
+               "url",
+               "Destination; required here or in the query",
+            ))
+            .param(routes::form(
+               "notion_page",
+               "Notion page id or URL",
+               "Links an existing page",
+            ))
+            .param(routes::form(
+               "tag_model",
+               "ntag213|ntag215|ntag216|ntag424|qr_only|unknown",
+               "Unknown when blank",
+            ))
+            .param(routes::form(
+               "redirect_kind",
+               "permanent|temporary|see_other",
+               "308, 307 or 303; temporary when blank",
+            ))
+            .param(routes::form(
+               "activates_at",
+               "UTC time",
+               "Until then, scans get a not-yet-active page; blank is active at once",
+            ))
+            .param(routes::form(
+               "expires_at",
+               "UTC time",
+               "Past it, scans get an expired page; blank never expires",
+            ))
+            .param(routes::form(
+               "fallback_url",
+               "url",
+               "Where scans go while the URL is down",
+            ))
+            .param(routes::form(
+               "check_target",
+               "bool",
+               "Probes the URL before each redirect, for at most 800ms, falling back if it's down; false when absent",
+            ))
+            .param(routes::form(
+               "mode",
+               "create|upsert",
+               "upsert retargets the tag if it exists, rather than refusing",
+            )),
+      )
+      // GET https://xz.ws/tags?q=bag&kit=Camera+bag&sort=taps&dir=desc
+      .get("/tags", tags_page, tag_filter_doc(Doc::admin("Tag listing")))
+      .get(
+         "/tags.json",
+         tags_json,
+         tag_filter_doc(Doc::admin("Tag listing, as JSON")),
+      )
+      // GET https://xz.ws/tags/export?format=json
+      .get(
+         "/tags/export",
+         export_all_tags,
+         Doc::admin("Every tag, trashed ones too, as a CSV or JSON download").param(routes::query(
+            "format",
+            "csv|json",
+            "Defaults to csv",
+         )),
+      )
+      // POST https://xz.ws/tags/bulk: action=set_kit&kit=Camera+bag&ids=055B88A23C1250&ids=04A1B2C3D4E5F6
+      .post(
+         "/tags/bulk",
+         bulk_tags,
+         Doc::admin("Changes, deletes or exports the selected tags; destructive actions ask to confirm")
+            .param(routes::form(
+               "action",
+               "string",
+               "retarget, set_kit, set_expiry, delete, or export",
+            ))
+            .param(routes::form("ids", "tag id", "Once per tag, at most 500"))
+            .param(routes::form("target_url", "url", "For retarget"))
+            .param(routes::form("kit", "string", "For set_kit; blank removes the kit"))
+            .param(routes::form(
+               "expires_on",
+               "date",
+               "For set_expiry, at its midnight UTC; blank never expires",
+            ))
+            .param(routes::form(
+               "confirm",
+               "string",
+               "`yes` to carry out retarget or delete",
+            )),
+      )
+      .post(
+         "/api/tags/bulk",
+         bulk_tags_json,
+         Doc::admin("As /tags/bulk, for a JSON object of the same fields; answers 207 per tag")
+            .param(routes::form("ids", "array", "Tag ids, at most 500"))
+            .param(routes::form("confirm", "bool", "Must be true for retarget or delete")),
+      )
+      .get(
+         "/tags/trash",
+         tags_trash_page,
+         Doc::admin("Deleted tags awaiting their purge, with restore buttons"),
+      )
+      .get("/tags/trash.json", tags_trash_json, Doc::admin("Deleted tags, as JSON"))
+      // GET https://xz.ws/stats?from=2026-10-01&to=2026-10-31
+      .get(
+         "/stats",
+         stats_page,
+         period_doc(Doc::admin("Taps of every tag, against the period before")),
+      )
+      .get("/stats.json", stats_json, period_doc(Doc::admin("As /stats, as JSON")))
+      .get(
+         "/usage",
+         usage_json,
+         Doc::admin("How many tags and aliases there are, against TWAG_MAX_TAGS and TWAG_MAX_ALIASES"),
+      )
+      // GET https://xz.ws/kits/Kitchen/stats?from=2026-10-01&to=2026-10-31
+      .get(
+         "/kits/{kit}/stats",
+         kit_stats_page,
+         period_doc(Doc::admin("Taps of one kit's tags, against the period before")).param(KIT),
+      )
+      .get(
+         "/kits/{kit}/stats.json",
+         kit_stats_json,
+         period_doc(Doc::admin("As /kits/{kit}/stats, as JSON")).param(KIT),
+      )
+      .get(
+         "/kits/{kit}/schedule",
+         kit_schedule_page,
+         Doc::admin("Windows in which the kit's tags all point elsewhere, with a form for another").param(KIT),
+      )
+      // POST https://xz.ws/kits/Kitchen/schedule: starts_at=2026-10-20T18:00&ends_at=2026-10-20T22:00&target_url=https://live.example.com/
+      .post(
+         "/kits/{kit}/schedule",
+         add_kit_window,
+         Doc::admin("Points every tag in the kit at a target for a while")
+            .param(KIT)
+            .param(routes::form("starts_at", "datetime", "UTC, or with an offset").required())
+            .param(routes::form("ends_at", "datetime", "UTC, or with an offset").required())
+            .param(routes::form("target_url", "url", "").required()),
+      )
+      .post(
+         "/kits/{kit}/schedule/{id}/remove",
+         remove_kit_window,
+         Doc::admin("Ends a window early, or cancels it")
+            .param(KIT)
+            .param(routes::path("id", "int", "Window id")),
+      )
+      .post(
+         "/tags/trash/restore",
+         restore_trashed,
+         Doc::admin("Takes deleted tags out of the trash").param(routes::form(
+            "ids",
+            "tag id",
+            "Once per tag, at most 500",
+         )),
+      )
+      .post(
+         "/tags/trash/purge",
+         purge_trashed,
+         Doc::admin("Deletes trashed tags for good now; asks to confirm")
+            .param(routes::form("ids", "tag id", "Once per tag, at most 500"))
+            .param(routes::form("confirm", "string", "`yes` to carry it out")),
+      )
+      .post(
+         "/api/tags/trash/restore",
+         restore_trashed_json,
+         Doc::admin("As /tags/trash/restore, for a JSON object; answers 207 per tag").param(routes::form(
+            "ids",
+            "array",
+            "Tag ids, at most 500",
+         )),
+      )
+      .post(
+         "/api/tags/trash/purge",
+         purge_trashed_json,
+         Doc::admin("As /tags/trash/purge, for a JSON object; answers 207 per tag")
+            .param(routes::form("ids", "array", "Tag ids, at most 500"))
+            .param(routes::form("confirm", "bool", "Must be true")),
+      )
+      .get(
+         "/targets",
+         targets_page,
+         Doc::admin("Tags grouped by destination, the most shared first"),
+      )
+      .get(
+         "/targets/{id}",
+         target_page,
+         Doc::admin("One destination and the tags using it").param(routes::path("id", "int", "Target id")),
+      )
+      // POST https://xz.ws/targets/12: url=https://example.com/new&confirm=yes
+      .post(
+         "/targets/{id}",
+         edit_target,
+         Doc::admin("Moves every tag using a destination to a new URL; asks to confirm")
+            .param(routes::path("id", "int", "Target id"))
+            .param(routes::form("url", "url", "The new destination").required())
+            .param(routes::form("confirm", "string", "`yes` to carry it out")),
+      )
+      // GET https://xz.ws/notion/things?q=camera
+      .get(
+         "/notion/things",
+         search_notion_things,
+         Doc::admin("Things pages by title, for picking one to link; at most 10").param(routes::query(
+            "q",
+            "string",
+            "Part of the title; blank lists recent pages",
+         )),
+      )
+      .get(
+         "/notion/containers",
+         search_notion_containers,
+         Doc::admin("Containers pages by title, as /notion/things").param(routes::query(
+            "q",
+            "string",
+            "Part of the title; blank lists recent pages",
+         )),
+      )
+      // GET https://xz.ws/tags/print?ids=055B88A23C1250,04A1B2C3D4E5F6&cols=4&label=on
+      .get(
+         "/tags/print",
+         tags_print_page,
+         Doc::admin("Printable sheet of QR codes; needs ids, kit, or q")
+            .param(routes::query("ids", "tag ids", "Comma-separated"))
+            .param(routes::query("kit", "string", "Every tag in a kit; also `group`"))
+            .param(routes::query("q", "string", "Substring of id, label, or target"))
+            .param(routes::query("cols", "int", "Codes per row"))
+            .param(routes::query("label", "checkbox", "Print labels under the codes")),
+      )
+      // GET https://xz.ws/favicon-proxy?host=example.com
+      .get(
+         "/favicon-proxy",
+         favicon_proxy,
+         Doc::public("A tag destination's favicon, as PNG")
+            .param(routes::query("host", "host", "Must be the host of some tag's target").required()),
+      )
+      .get(
+         "/tags/create-kit",
+         create_kit_page,
+         Doc::admin("Form for a kit of tags"),
+      )
+      .add(
+         Method::Post,
+         "/tags/create-kit",
+         post(create_kit).layer(log_submission()),
+         Doc::admin("Creates every tag of a kit, or none; a JSON array of rows gets a 207")
+            .param(routes::query("kit", "string", "Kit name for JSON submissions"))
+            .param(routes::form("kit", "string", "Kit name"))
+            .param(routes::form("id", "tag id", "Per row"))
+            .param(routes::form("label", "string", "Per row"))
+            .param(routes::form("target_url", "url", "Per row"))
+            .param(routes::form("notion_page", "Notion page id or URL", "Per row")),
+      )
+      .get(
+         "/tags/import",
+         import_page,
+         Doc::admin("Form for importing tags from a CSV file"),
+      )
+      // POST https://xz.ws/tags/import: multipart/form-data, the CSV as its file field
+      .add(
+         Method::Post,
+         "/tags/import",
+         post(start_import).layer(extract::DefaultBodyLimit::max(import::MAX_UPLOAD_BYTES)),
+         Doc::admin("Queues the uploaded CSV's tags to be created a batch at a time; redirects to its progress")
+            .param(routes::form("file", "CSV file", "Required"))
+            .param(routes::query(
+               "strict",
+               "bool",
+               "Refuses the whole file, with a 422 and a report of every row, if any row fails",
+            )),
+      )
+      .get(
+         "/tags/import/{id}",
+         import_job_page,
+         Doc::admin("An import's progress, reloading until it's done").param(routes::path("id", "int", "Import id")),
+      )
+      .get(
+         "/tags/import/{id}/report.csv",
+         import_report,
+         Doc::admin("What became of each row an import reached").param(routes::path("id", "int", "Import id")),
+      )
+      // GET https://xz.ws/tag/055B88A23C1250
+      // GET https://xz.ws/tag/055B88A23C1250x00000F
+      // GET https://xz.ws/tag/055B88A23C1250AA01BBx00000F
+      .get(
+         "/tag/{slug}",
+         get_tag_by_id,
+         Doc::public("A scan: redirects, or serves the tag's page")
+            .param(routes::path(
+               "slug",
+               "tag slug",
+               "Tag id, optionally followed by x and a 6 digit counter, or .vcf",
+            ))
+            .param(routes::query("lang", "language tag", "Overrides Accept-Language"))
+            .param(routes::query(
+               "ct",
+               "string",
+               "Count token; only tokened taps are counted",
+            )),
+      )
+      // GET https://xz.ws/t/x7Qp2
+      .get(
+         "/t/{code}",
+         short_link,
+         Doc::public("A short link; answered like a scan of its tag")
+            .param(routes::path("code", "short code", "Current or earlier"))
+            .param(routes::query("lang", "language tag", "Overrides Accept-Language"))
+            .param(routes::query("ct", "string", "Count token")),
+      )
+      // DELETE https://xz.ws/tag/055B88A23C1250
+      .delete(
+         "/tag/{slug}",
+         delete_tag,
+         Doc::admin("Moves a tag to the trash; 204, or 404 if there's no such tag").param(ADMIN_SLUG),
+      )
+      .get(
+         "/tag/{slug}/delete",
+         delete_tag_page,
+         Doc::admin("Asks to confirm moving a tag to the trash").param(ADMIN_SLUG),
+      )
+      .post(
+         "/tag/{slug}/delete",
+         delete_tag_confirmed,
+         Doc::admin("Moves a tag to the trash, then redirects there").param(ADMIN_SLUG),
+      )
+      // POST https://xz.ws/tag/055B88A23C1250/clone: new_id=04A1B2C3D4E5F6
+      .post(
+         "/tag/{slug}/clone",
+         clone_tag,
+         Doc::admin("Creates a tag set up as this one is, then redirects to its edit page; 409 if it exists")
+            .param(ADMIN_SLUG)
+            .param(routes::form("new_id", "tag id or scan URL", "The tag to create")),
+      )
+      // GET https://xz.ws/tag/055B88A23C1250x00000F/edit
+      .get(
+         "/tag/{slug}/edit",
+         edit_tag_page,
+         Doc::admin("Form for a tag's target and count; redirects to /tag/create if there's no such tag")
+            .param(ADMIN_SLUG)
+            .param(routes::query(
+               "tap_count",
+               "hex",
+               "Counter a scan reported; filled in for the count",
+            )),
+      )
+      // POST https://xz.ws/tag/055B88A23C1250/edit: target_url=https://example.com&access_count=15
+      .post(
+         "/tag/{slug}/edit",
+         edit_tag,
+         Doc::admin("Changes a tag's target, count, redirect kind, activation, expiry and fallback")
+            .param(ADMIN_SLUG)
+            .param(routes::form("target_url", "url", "").required())
+            .param(routes::form("access_count", "int", "Scans counted so far").required())
+            .param(routes::form(
+               "redirect_kind",
+               "permanent|temporary|see_other",
+               "Unchanged when absent",
+            ))
+            .param(routes::form(
+               "activates_at",
+               "UTC time",
+               "Blank is active at once; unchanged when absent",
+            ))
+            .param(routes::form(
+               "expires_at",
+               "UTC time",
+               "Blank never expires; unchanged when absent",
+            ))
+            .param(routes::form(
+               "fallback_url",
+               "url",
+               "Blank for none; unchanged when absent",
+            ))
+            .param(routes::form("check_target", "bool", "Unchanged when absent")),
+      )
+      // GET https://xz.ws/tag/055B88A23C1250/stats
+      .get(
+         "/tag/{slug}/stats",
+         tag_stats_page,
+         Doc::admin("Daily tap stats").param(SLUG),
+      )
+      // GET https://xz.ws/tag/055B88A23C1250/taps
+      .get("/tag/{slug}/taps", tag_taps_page, Doc::admin("Recent taps").param(SLUG))
+      .get(
+         "/tag/{slug}/taps.csv",
+         tag_taps_csv,
+         Doc::admin("Every logged tap, as CSV").param(SLUG),
+      )
+      .get(
+         "/tag/{slug}/report",
+         tag_report_page,
+         Doc::admin("Printable history of the tag, for handing it over").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/report/share",
+         share_tag_report,
+         Doc::admin("Makes a link to the report that works without logging in, for a while")
+            .param(SLUG)
+            .param(routes::form(
+               "days",
+               "int",
+               "How long the link works; 7 by default, at most 90",
+            )),
+      )
+      .get(
+         "/tag/{slug}/report/shared",
+         shared_tag_report,
+         Doc::public("The report, through a share link")
+            .param(SLUG)
+            .param(routes::query("t", "string", "From the share link").required()),
+      )
+      .post(
+         "/tag/{slug}/stats/share",
+         share_tag_stats,
+         Doc::admin("Makes a revocable link to the tag's tap counts that works without logging in")
+            .param(SLUG)
+            .param(routes::form(
+               "days",
+               "int",
+               "How long the link works; TWAG_STATS_SHARE_DAYS by default, at most 90",
+            )),
+      )
+      .post(
+         "/tag/{slug}/stats/share/revoke",
+         revoke_tag_stats_shares,
+         Doc::admin("Stops every stats link shared for the tag so far").param(SLUG),
+      )
+      // GET https://xz.ws/share/055B88A23C1250.stats.0.1792756800.9f86d081884c7d65...
+      .get(
+         "/share/{token}",
+         shared_tag_stats,
+         Doc::public("A tag's tap counts, through a share link").param(routes::path(
+            "token",
+            "string",
+            "From the share link",
+         )),
+      )
+      // GET https://xz.ws/tag/055B88A23C1250/ndef.json
+      .get(
+         "/tag/{slug}/ndef.json",
+         tag_ndef_json,
+         Doc::admin("The NDEF record to write").param(SLUG),
+      )
+      // GET https://xz.ws/tag/055B88A23C1250/badge.svg
+      .get(
+         "/tag/{slug}/badge.svg",
+         tag_badge_svg,
+         Doc::public("Tap count badge").param(SLUG),
+      )
+      // GET https://xz.ws/tag/055B88A23C1250/count.json
+      .get(
+         "/tag/{slug}/count.json",
+         tag_count_json,
+         Doc::public("Tap count").param(SLUG),
+      )
+      .get(
+         "/tag/{slug}/qr.svg",
+         tag_qr_svg,
+         Doc::admin("Scan URL as a QR code").param(SLUG),
+      )
+      .get(
+         "/tag/{slug}/qr.png",
+         tag_qr_png,
+         Doc::admin("Scan URL as a QR code").param(SLUG),
+      )
+      .get(
+         "/tag/{slug}/write",
+         tag_write_page,
+         Doc::admin("Writes the tag with Web NFC").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/write-confirm",
+         tag_write_confirm,
+         Doc::admin("Records that the tag was written").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/shortcode",
+         mint_short_code,
+         Doc::admin("Issues a new short link; earlier ones keep working").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/stateful",
+         enable_stateful,
+         Doc::admin("Makes scans toggle check-out/in").param(SLUG),
+      )
+      .delete(
+         "/tag/{slug}/stateful",
+         disable_stateful,
+         Doc::admin("Back to plain redirects").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/maintenance",
+         enable_maintenance,
+         Doc::admin("Sends scans to maintenance").param(SLUG),
+      )
+      .delete(
+         "/tag/{slug}/maintenance",
+         disable_maintenance,
+         Doc::admin("Ends maintenance").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/go",
+         age_gate_go,
+         Doc::public("Answers a tag's age gate; a yes then continues the scan")
+            .param(routes::path("slug", "tag slug", "As scanned"))
+            .param(routes::form("answer", "string", "`yes` or `no`").required())
+            .param(routes::query("lang", "language tag", "Carried from the scan"))
+            .param(routes::query("ct", "string", "Carried from the scan")),
+      )
+      .post(
+         "/tag/{slug}/age-gate",
+         enable_age_gate,
+         Doc::admin("Asks scans for an age confirmation first")
+            .param(SLUG)
+            .param(routes::form(
+               "text",
+               "string",
+               "Shown on the gate, e.g. the minimum age",
+            )),
+      )
+      .delete(
+         "/tag/{slug}/age-gate",
+         disable_age_gate,
+         Doc::admin("Removes the age gate").param(SLUG),
+      )
+      .get(
+         "/tag/{slug}/arrived",
+         arrival_beacon,
+         Doc::public("Confirms a scan reached its target; loaded by the arrival page")
+            .param(SLUG)
+            .param(routes::query("t", "string", "Token from the arrival page").required()),
+      )
+      .post(
+         "/tag/{slug}/confirm-arrival",
+         enable_confirm_arrival,
+         Doc::admin("Answers scans with a page that confirms they arrived").param(SLUG),
+      )
+      .delete(
+         "/tag/{slug}/confirm-arrival",
+         disable_confirm_arrival,
+         Doc::admin("Back to plain redirects").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/public-status",
+         enable_public_status,
+         Doc::admin("Lists the tag on /status, by label")
+            .param(SLUG)
+            .param(routes::form("show_count", "bool", "Also show its tap count")),
+      )
+      .delete(
+         "/tag/{slug}/public-status",
+         disable_public_status,
+         Doc::admin("Takes the tag off /status").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/review",
+         review_tag,
+         Doc::admin("Settles a tag whose counter dropped back near zero")
+            .param(SLUG)
+            .param(routes::form("decision", "string", "`accept` the new counter, or `suspicious`").required()),
+      )
+      .post(
+         "/tag/{slug}/quarantine-on-review",
+         enable_quarantine_on_review,
+         Doc::admin("Holds scans as soon as the tag needs review").param(SLUG),
+      )
+      .delete(
+         "/tag/{slug}/quarantine-on-review",
+         disable_quarantine_on_review,
+         Doc::admin("Only holds scans once marked suspicious").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/disable",
+         disable_tag,
+         Doc::admin("Decommissions the tag: scans answer 410 Gone, and its history is kept").param(SLUG),
+      )
+      .delete(
+         "/tag/{slug}/disable",
+         enable_tag,
+         Doc::admin("Puts a disabled tag back in use").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/archive",
+         archive_tag,
+         Doc::admin("Moves the tag to the archive: scans answer 410 Gone, and its id stays taken").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/restore",
+         restore_tag,
+         Doc::admin("Moves an archived tag back; 204, or 404 if it isn't archived").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/ignore-kit-schedule",
+         enable_ignore_kit_schedule,
+         Doc::admin("Keeps the tag on its own targets whatever its kit schedules").param(SLUG),
+      )
+      .delete(
+         "/tag/{slug}/ignore-kit-schedule",
+         disable_ignore_kit_schedule,
+         Doc::admin("Follows its kit's schedule again").param(SLUG),
+      )
+      .post(
+         "/tag/{slug}/count-token",
+         rotate_count_token,
+         Doc::admin("Issues a new count token").param(SLUG),
+      )
+      .delete(
+         "/tag/{slug}/count-token",
+         clear_count_token,
+         Doc::admin("Counts every tap again").param(SLUG),
+      )
+      .get("/admin/outbox", admin_outbox_page, Doc::admin("Pending Notion work"))
+      .get("/admin/webhooks", admin_webhooks_page, Doc::admin("Webhook deliveries"))
+      .post(
+         "/admin/webhooks/{id}/redeliver",
+         admin_redeliver_webhook,
+         Doc::admin("Sends a delivery again").param(routes::path("id", "int", "Delivery id")),
+      )
+      .get(
+         "/admin/db-report",
+         admin_db_report,
+         Doc::admin("Query plans of the hot queries, with index suggestions; runs nothing"),
+      )
+      .get(
+         "/admin/audit-ids",
+         admin_audit_ids,
+         Doc::admin("Tag ids stored in the wrong case"),
+      )
+      .post(
+         "/admin/audit-ids/fix",
+         admin_fix_ids,
+         Doc::admin("Normalizes those ids"),
+      )
+      .post(
+         "/admin/cache/preload",
+         admin_preload_cache,
+         Doc::admin("Loads the most-scanned tags into the failover cache, as at startup"),
+      )
+      .put(
+         "/admin/log-level",
+         admin_set_log_level,
+         Doc::admin("Changes what's logged, until the timeout or the next restart")
+            .param(routes::form("filter", "string", "An EnvFilter, as in RUST_LOG").required())
+            .param(routes::form(
+               "revert_secs",
+               "int",
+               "Puts RUST_LOG's filter back after this long",
+            )),
+      )
+      .get(
+         "/admin/provisioning",
+         admin_provisioning_page,
+         Doc::admin("Form for minting creation links"),
+      )
+      .post(
+         "/admin/provisioning",
+         admin_mint_provisioning,
+         Doc::admin("Creation links for a vendor to program tags with")
+            .param(routes::form("ids", "tag ids", "One per line, bare or as scan URLs").required())
+            .param(routes::form("days", "int", "How long the links work; defaults to 30")),
+      )
+      // GET https://xz.ws/admin/upcoming?days=30
+      .get(
+         "/admin/upcoming",
+         admin_upcoming_page,
+         Doc::admin("Where tags will stop pointing where they do now, soonest first").param(routes::query(
+            "days",
+            "int",
+            "How far ahead; defaults to 7, at most 90",
+         )),
+      )
+      .get(
+         "/admin/upcoming.ics",
+         admin_upcoming_ics,
+         Doc::admin("As /admin/upcoming, as a calendar to subscribe to").param(routes::query(
+            "days",
+            "int",
+            "How far ahead; defaults to 7, at most 90",
+         )),
+      )
+      .post(
+         "/admin/seed",
+         admin_seed,
+         Doc::admin("Fills the database with demo tags and taps")
+            .param(routes::query("danger", "bool", "Required, as true"))
+            .param(routes::query("force", "bool", "Seed even though tags already exist"))
+            .param(routes::query("seed", "int", "Same seed, same data"))
+            .param(routes::query("tags", "int", "Defaults to 40"))
+            .param(routes::query("days", "int", "Of taps; defaults to 90")),
+      )
+      // GET https://xz.ws/admin/requests?tag=055B88A23C1250
+      .get(
+         "/admin/requests",
+         admin_requests_page,
+         Doc::admin("Logged create submissions").param(routes::query("tag", "tag id", "Only this tag's")),
+      );
+   #[cfg(feature = "chaos")]
+   let routes = routes
+      .get(
+         "/debug/chaos",
+         chaos::show,
+         Doc::admin("Injected faults; 404 unless TWAG_CHAOS=1"),
+      )
+      .post(
+         "/debug/chaos",
+         chaos::update,
+         Doc::admin("Sets the injected faults; omitted ones are cleared")
+            .param(routes::form("acquire_delay_ms", "int", "Added to every pool acquire"))
+            .param(routes::form(
+               "acquire_fail_percent",
+               "0-100",
+               "Acquires failing as if Postgres were down",
+            ))
+            .param(routes::form(
+               "notion_fail_percent",
+               "0-100",
+               "Notion outbox calls failing",
+            ))
+            .param(routes::form("tap_drop_percent", "0-100", "Deferred taps dropped"))
+            .param(routes::form("render_delay_ms", "int", "Added to every HTML response")),
+      );
+   routes
+}
 
-   match dotenvy::var("RUST_FMT").as_deref() {
-      Ok("json") => fmt()
-         .with_env_filter(filter)
-         .event_format(format.json().with_target(false).with_source_location(true))
-         .init(),
-      Ok("pretty") => fmt()
-         .with_env_filter(filter)
-         .event_format(format.pretty().with_source_location(true))
-         .init(),
-      _ => fmt().with_env_filter(filter).event_format(format).init(),
-   };
+/// The parameters `TagFilter` reads.
+fn tag_filter_doc(doc: Doc) -> Doc {
+   doc.param(routes::query("q", "string", "Substring of id, label, or target"))
+      .param(routes::query("kit", "string", "Only this kit"))
+      .param(routes::query(
+         "tag_model",
+         "ntag213|ntag215|ntag216|ntag424|qr_only|unknown",
+         "Only this model",
+      ))
+      .param(routes::query("maintenance", "bool", ""))
+      .param(routes::query("stateful", "bool", ""))
+      .param(routes::query(
+         "archived",
+         "bool",
+         "Archived tags instead of those in use",
+      ))
+      .param(routes::query(
+         "sort",
+         "id|label|target|created|last_tapped|taps|updated",
+         "Defaults to id; on /tags, to updated, newest first",
+      ))
+      .param(routes::query(
+         "dir",
+         "asc|desc",
+         "Defaults to asc, but for the default sort",
+      ))
+      .param(routes::query("limit", "int", "At most 1000; also per_page"))
+      .param(routes::query("page", "int", "Of `limit` rows each, from 1"))
 }
 
-#[allow(dead_code)]
-#[derive(Clone)]
-struct AppState {
-   pool: sqlx::PgPool,
-   client: Notion,
+/// The parameters `PeriodQuery` reads.
+fn period_doc(doc: Doc) -> Doc {
+   doc.param(routes::query("from", "date", "30 days before `to` by default"))
+      .param(routes::query("to", "date", "Today by default"))
 }
 
 #[tokio::main]
 async fn main() {
    dotenvy::dotenv().ok();
 
-   init_tracing();
+   let redaction = Redaction::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let telemetry = Telemetry::install(TelemetryConfig {
+      redact_tag_ids: redaction.is_some(),
+      ..TelemetryConfig::from_vars(|name| dotenvy::var(name).ok())
+   });
+
+   // On a first run from a terminal, ask rather than panic over the missing DATABASE_URL
+   let first_run =
+      dotenvy::var("TWAG_MODE").is_err() && dotenvy::var("DATABASE_URL").is_err() && std::io::stdin().is_terminal();
+   if dotenvy::var("TWAG_MODE").is_ok_and(|mode| mode == "setup") || first_run {
+      if let Err(e) = setup::run().await {
+         eprintln!("{}", e);
+         std::process::exit(1);
+      }
+      return;
+   }
 
    // TODO: Use a config library
    // `DATABASE_URL` is the cross-ecosystem env-var convention (sqlx, Dokku, etc.);
    // elsewhere in twag, "database" refers to a Notion database (see `NOTION_*_DB`).
    let postgres_url = dotenvy::var("DATABASE_URL").expect("DATABASE_URL must be set");
-   let notion_token = dotenvy::var("NOTION_TOKEN").expect("NOTION_TOKEN must be set");
-   let things_ndb = NotionPageId::new(dotenvy::var("NOTION_THINGS_DB").expect("NOTION_THINGS_DB must be set"))
-      .expect("Invalid NOTION_THINGS_DB format");
-   let things_column = dotenvy::var("NOTION_THINGS_COLUMN_NAME").expect("NOTION_THINGS_COLUMN_NAME must be set");
-   let containers_ndb =
-      NotionPageId::new(dotenvy::var("NOTION_CONTAINERS_DB").expect("NOTION_CONTAINERS_DB must be set"))
-         .expect("Invalid NOTION_CONTAINERS_DB format");
-   let containers_column =
-      dotenvy::var("NOTION_CONTAINERS_COLUMN_NAME").expect("NOTION_CONTAINERS_COLUMN_NAME must be set");
-   // Data source IDs (required until notion-client supports the 2025-09-03 Database schema)
-   let things_nds = dotenvy::var("NOTION_THINGS_DS").expect("NOTION_THINGS_DS must be set (pending notion-client fix)");
-   let containers_nds =
-      dotenvy::var("NOTION_CONTAINERS_DS").expect("NOTION_CONTAINERS_DS must be set (pending notion-client fix)");
+   let notion_config = NotionConfig::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let mqtt_config = MqttConfig::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let webhook_config = WebhookConfig::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let app_links = AppLinks::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let cookie_key = CookieKey::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let provisioning_key = ProvisioningKey::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let health_token = HealthToken::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let negative_cache = NegativeCache::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+   let preload = Preload::from_vars(|name| dotenvy::var(name).ok()).unwrap();
 
-   let pool = initialize_connection(&postgres_url)
+   let pool_max = dotenvy::var("TWAG_PG_MAX_CONNECTIONS")
+      .ok()
+      .map(|s| s.parse::<u32>().expect("Invalid TWAG_PG_MAX_CONNECTIONS"))
+      .unwrap_or(5);
+   let pool_sizing = PoolSizing {
+      max_connections: pool_max,
+      hard_cap: dotenvy::var("TWAG_PG_MAX_CONNECTIONS_CAP")
+         .ok()
+         .map(|s| s.parse::<u32>().expect("Invalid TWAG_PG_MAX_CONNECTIONS_CAP"))
+         .unwrap_or(pool_max * 4)
+         .max(pool_max),
+      autoscale: dotenvy::var("TWAG_PG_AUTOSCALE").is_ok_and(|s| s == "true"),
+   };
+
+   let pool = initialize_connection(&postgres_url, pool_sizing)
       .await
       .expect("Failed to connect to Postgres");
+   pool.spawn_monitor();
+
+   // Optional; without it, reads share the primary's pool
+   let replica = match dotenvy::var("DATABASE_READ_URL").ok().filter(|s| !s.is_empty()) {
+      Some(url) => {
+         let replica = initialize_connection(&url, pool_sizing)
+            .await
+            .expect("Failed to connect to the Postgres replica");
+         replica.spawn_monitor();
+         Some(replica)
+      }
+      None => None,
+   };
 
-   let client = Notion::new(notion_token.clone(), None).expect("Failed to create Notion client");
+   if dotenvy::var("TWAG_MODE").is_ok_and(|mode| mode == "audit-ids") {
+      let fix = dotenvy::var("TWAG_AUDIT_FIX").is_ok_and(|s| s == "true");
+      audit::run(&pool, fix).await.expect("Failed to audit tag ids");
+      pool.close().await;
+      return;
+   }
 
-   trace!(%things_ndb, %containers_ndb, "Parsed Database IDs");
-   validate_notion_databases(
-      &client,
-      &things_ndb,
-      &things_nds,
-      &containers_ndb,
-      &containers_nds,
-      &things_column,
-      &containers_column,
-   )
-   .await
-   .unwrap();
-   trace!(things_column, containers_column, "Validated Database relations");
+   if dotenvy::var("TWAG_MODE").is_ok_and(|mode| mode == "audit-urls") {
+      let max_len = match Settings::from_env() {
+         Ok(settings) => settings.max_location_len,
+         Err(errors) => panic!("Invalid settings: {}", errors.join("; ")),
+      };
+      target_url::run(&pool, max_len)
+         .await
+         .expect("Failed to audit target URLs");
+      pool.close().await;
+      return;
+   }
 
+   if dotenvy::var("TWAG_MODE").is_ok_and(|mode| mode == "seed") {
+      let spec = fixtures::Spec::from_vars(|name| dotenvy::var(name).ok()).unwrap();
+      let force = std::env::args().any(|arg| arg == "--force");
+      if let Err(e) = fixtures::run(&pool, &spec, force).await {
+         eprintln!("{}", e);
+         std::process::exit(1);
+      }
+      pool.close().await;
+      return;
+   }
+
+   let (client, tag_pages, url_edits, notion_picker) = match &notion_config {
+      Some(config) => {
+         let (client, tag_pages, url_edits, picker) = initialize_notion(config).await;
+         (Some(client), tag_pages, url_edits, Some(Arc::new(picker)))
+      }
+      None => {
+         info!("Notion integration disabled");
+         (None, None, None, None)
+      }
+   };
+
+   let visitor_hasher = dotenvy::var("TWAG_VISITOR_SALT")
+      .ok()
+      .filter(|s| !s.is_empty())
+      .map(|salt| VisitorHasher::new(&salt));
+   if visitor_hasher.is_none() {
+      info!("TWAG_VISITOR_SALT unset, unique-scanner estimation disabled");
+   }
+
+   let settings = match Settings::from_env() {
+      Ok(settings) => SharedSettings::new(settings),
+      Err(errors) => panic!("Invalid settings: {}", errors.join("; ")),
+   };
+   settings::spawn_reload_on_sighup(settings.clone());
+
+   if dotenvy::var("TWAG_CHAOS").is_ok_and(|s| s == "1") {
+      #[cfg(feature = "chaos")]
+      {
+         chaos::enable();
+         warn!("TWAG_CHAOS=1, failure injection enabled; faults are set at /debug/chaos");
+      }
+      #[cfg(not(feature = "chaos"))]
+      warn!("Ignoring TWAG_CHAOS, this build doesn't include the chaos feature");
+   }
+
+   let retention = Arc::new(RwLock::new(RetentionReport::default()));
+   retention::spawn_nightly(pool.clone(), RetentionPolicy::from_env(), retention.clone());
+
+   let mqtt = mqtt_config.map(|config| mqtt::spawn(&config).expect("Failed to configure MQTT"));
+
+   let geoip = dotenvy::var("TWAG_GEOIP_DB")
+      .ok()
+      .filter(|s| !s.is_empty())
+      .map(|path| {
+         let geoip = Arc::new(GeoIp::open(&path).expect("Failed to open TWAG_GEOIP_DB"));
+         info!(path, "Loaded GeoIP database");
+         geo::spawn_reload_on_sighup(geoip.clone());
+         geoip
+      });
+
+   let (router, route_docs) = build_router(&pool).into_parts();
    let app_state = AppState {
       pool: pool.clone(),
       client,
+      notion_picker,
+      visitor_hasher,
+      settings: settings.clone(),
+      notion_outbox: tag_pages.is_some(),
+      retention,
+      mqtt,
+      webhook: webhook_config.clone(),
+      failover: Failover::default(),
+      preload,
+      lookup_limiter: Arc::new(RateLimiter::new(LOOKUPS_PER_WINDOW, LOOKUP_WINDOW)),
+      negative_cache,
+      prober: fallback::SharedProber::default(),
+      app_links: Arc::new(app_links),
+      tag_locks: Arc::default(),
+      geoip,
+      redaction,
+      telemetry,
+      cookie_key,
+      provisioning_key,
+      health_token,
+      unready: Arc::default(),
+      reads: ReadPools::new(pool.clone(), replica),
+      route_docs: Arc::new(route_docs),
    };
-   let app = Router::new()
-      .route("/", get(|| async { "Hello, World!" }))
-      .route("/healthz", get(health_check))
-      // GET https://xz.ws/tag/create?id=055B88A23C1250&tap_count=00000F
-      .route("/tag/create", get(create_tag_page))
-      // POST https://xz.ws/tag/create?id=055B88A23C1250&tap_count=00000F: target_url=https://example.com
-      .route("/tag/create", post(create_tag))
-      // GET https://xz.ws/tag/055B88A23C1250
-      // GET https://xz.ws/tag/055B88A23C1250x00000F
-      .route("/tag/{slug}", get(get_tag_by_id))
-      .with_state(app_state)
+   spawn_tap_flusher(pool.clone(), app_state.failover.clone());
+   // Before the listener is bound, so the first scans after a deploy find the hottest tags cached
+   if preload.tags > 0 {
+      match app_state.failover.preload(&pool.get(), preload).await {
+         Ok(preloaded) => info!(
+            warmed = preloaded.warmed,
+            elapsed_ms = preloaded.elapsed.as_millis() as u64,
+            "Preloaded the redirect cache"
+         ),
+         Err(e) => warn!("Failed to preload the redirect cache: {}", e),
+      }
+   }
+   #[cfg(feature = "chaos")]
+   let tag_pages = tag_pages.map(chaos::Flaky);
+   if let Some(config) = webhook_config {
+      webhook::spawn_dispatcher(pool.clone(), config);
+   }
+   if let Some(tag_pages) = tag_pages {
+      outbox::spawn_backfill(pool.clone(), tag_pages.clone());
+      outbox::spawn_dispatcher(pool.clone(), tag_pages);
+   }
+   import::spawn_worker(import::Worker {
+      pool: pool.clone(),
+      settings: app_state.settings.clone(),
+      notion_enabled: app_state.client.is_some(),
+      notion_outbox: app_state.notion_outbox,
+      negative_cache: app_state.negative_cache.clone(),
+   });
+   if let Some(source) = url_edits {
+      notion_sync::spawn(notion_sync::UrlSync {
+         pool: pool.clone(),
+         source,
+         failover: app_state.failover.clone(),
+         reads: app_state.reads.clone(),
+         tag_locks: app_state.tag_locks.clone(),
+         settings: app_state.settings.clone(),
+      });
+   }
+   let app = router.with_state(app_state);
+   #[cfg(feature = "chaos")]
+   let app = app.layer(middleware::from_fn(chaos::slow_html));
+   let app = app
+      .layer(middleware::from_fn_with_state(settings, enforce_canonical_host))
       .layer(
          TraceLayer::new_for_http()
             .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
@@ -262,12 +1423,27 @@ async fn main() {
       pool.close().await;
    };
 
-   axum::serve(listener, app)
+   axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
       .with_graceful_shutdown(shutdown_signal)
       .await
       .unwrap();
 }
 
+/// Renders a page around `streaming::ROWS`, to be split there unless it has no rows.
+fn render_shell(page: &impl Template, empty: bool) -> Result<streaming::Shell, StatusCode> {
+   let rendered = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   if empty {
+      return Ok(streaming::Shell::whole(rendered));
+   }
+   streaming::Shell::split(&rendered).ok_or_else(|| {
+      warn!("Rendered page has no single place for its rows");
+      StatusCode::INTERNAL_SERVER_ERROR
+   })
+}
+
 fn as_html(mut resp: Response) -> Response {
    resp
       .headers_mut()
@@ -275,68 +1451,603 @@ fn as_html(mut resp: Response) -> Response {
    resp
 }
 
-async fn health_check(extract::State(state): extract::State<AppState>) -> StatusCode {
-   match sqlx::query("SELECT 1").fetch_one(&state.pool).await {
-      Ok(_) => StatusCode::OK,
-      Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+/// Lookups allowed per client in each window, so the form can't be used to enumerate tag ids.
+const LOOKUPS_PER_WINDOW: u32 = 10;
+const LOOKUP_WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexTemplate<'a> {
+   branding: &'a Branding,
+   q: &'a str,
+   error: Option<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "tag_not_found.html")]
+struct TagNotFoundTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a TagUid,
+}
+
+fn render_index(state: &AppState, status: StatusCode, q: &str, error: Option<&str>) -> Result<Response, StatusCode> {
+   let page = IndexTemplate {
+      branding: &state.settings.load().branding,
+      q,
+      error,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html((status, response).into_response()))
+}
+
+async fn index_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   render_index(&state, StatusCode::OK, "", None)
+}
+
+#[derive(Deserialize)]
+struct LookupQuery {
+   #[serde(default)]
+   q: String,
+}
+
+/// The root page's lookup box. Only ever redirects to a tag that exists, and never records a tap
+/// itself; that happens when the client follows the redirect.
+async fn lookup_tag(
+   extract::State(state): extract::State<AppState>,
+   extract::ConnectInfo(remote): extract::ConnectInfo<SocketAddr>,
+   headers: HeaderMap,
+   extract::Query(query): extract::Query<LookupQuery>,
+) -> Result<Response, StatusCode> {
+   let ip = client_ip(&headers, remote);
+   if !state.lookup_limiter.check(ip, std::time::Instant::now()) {
+      info!(%ip, "Rate-limiting tag lookups");
+      let mut response = render_index(
+         &state,
+         StatusCode::TOO_MANY_REQUESTS,
+         &query.q,
+         Some("Too many lookups; please wait a minute and try again."),
+      )?;
+      response
+         .headers_mut()
+         .insert(header::RETRY_AFTER, state.lookup_limiter.window().as_secs().into());
+      return Ok(response);
+   }
+
+   let id = match models::parse_tag_input(&query.q, &own_hosts(&state, &headers)) {
+      Ok(slug) => slug.id,
+      Err(e) => {
+         info!("Rejecting lookup '{}': {}", query.q, e);
+         return render_index(&state, StatusCode::BAD_REQUEST, &query.q, Some(&e.to_string()));
+      }
+   };
+
+   let exists = sqlx::query_scalar!(
+      r#"SELECT EXISTS (SELECT 1 FROM twag_tags WHERE id = $1 AND deleted_at IS NULL) AS "exists!""#,
+      id as TagUid
+   )
+   .fetch_one(&state.reads.for_tag(&id).get())
+   .await
+   .map_err(|e| {
+      warn!(tag_id = %id, "Failed to look up tag: {:?}", e);
+      StatusCode::SERVICE_UNAVAILABLE
+   })?;
+   if !exists {
+      let page = TagNotFoundTemplate {
+         branding: &state.settings.load().branding,
+         id: &id,
+      };
+      let response = page.render().map_err(|e| {
+         warn!("Failed to render template: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      return Ok(as_html((StatusCode::NOT_FOUND, response).into_response()));
+   }
+   Ok(axum::response::Redirect::to(&format!("/tag/{}", id)).into_response())
+}
+
+/// Served without an extension, but must still be `application/json`.
+async fn apple_app_site_association(extract::State(state): extract::State<AppState>) -> Response {
+   match &state.app_links.apple {
+      Some(app) => axum::Json(app_links::apple_app_site_association(app)).into_response(),
+      None => StatusCode::NOT_FOUND.into_response(),
+   }
+}
+
+async fn asset_links(extract::State(state): extract::State<AppState>) -> Response {
+   match &state.app_links.android {
+      Some(app) => axum::Json(app_links::asset_links(app)).into_response(),
+      None => StatusCode::NOT_FOUND.into_response(),
+   }
+}
+
+async fn ping(pool: &ScalingPool) -> bool { sqlx::query("SELECT 1").fetch_one(&pool.get()).await.is_ok() }
+
+#[derive(Deserialize)]
+struct HealthQuery {
+   #[serde(default)]
+   deep: bool,
+   token: Option<String>,
+}
+
+async fn health_check(
+   extract::State(db): extract::State<DbState>,
+   extract::State(cache): extract::State<CacheState>,
+   extract::State(SettingsState(settings)): extract::State<SettingsState>,
+   extract::State(notion): extract::State<NotionState>,
+   extract::State(health): extract::State<HealthState>,
+   extract::Query(query): extract::Query<HealthQuery>,
+   headers: HeaderMap,
+) -> (StatusCode, String) {
+   let deep = match (query.deep, &health.health_token, &query.token) {
+      (false, _, _) => None,
+      (true, Some(token), Some(presented)) if token.accepts(presented) => {
+         Some(deep_health(&db, &settings, &health, &headers).await)
+      }
+      (true, _, _) => {
+         return (
+            StatusCode::FORBIDDEN,
+            "Deep checks need TWAG_HEALTH_TOKEN.\n".to_string(),
+         )
+      }
+   };
+   if let Some(report) = &deep {
+      *health.unready.write().unwrap() = (!report.passed()).then(|| report.clone());
+   }
+   let unready = health.unready.read().unwrap().clone();
+   let primary = ping(&db.pool).await;
+   let replica = match db.reads.replica() {
+      Some(replica) => Some(ping(replica).await),
+      None => None,
+   };
+   // With a replica, lookups need it and writes need the primary
+   let reachable = primary && replica.unwrap_or(true);
+   // Unreachable but still redirecting cached tags is degraded, not down
+   let (status, summary) = match (reachable, cache.failover.cached_len()) {
+      (true, _) => (StatusCode::OK, "ok"),
+      (false, 0) => (StatusCode::SERVICE_UNAVAILABLE, "down"),
+      (false, _) => (StatusCode::OK, "degraded (serving stale)"),
+   };
+   let (status, summary) = match unready {
+      Some(_) => (StatusCode::SERVICE_UNAVAILABLE, "unready (deep checks failing)"),
+      None => (status, summary),
+   };
+   let mut body = format!("status: {}\n{}\n", summary, health.retention.read().unwrap());
+   if let Some(replica) = replica {
+      let up = |reachable: bool| if reachable { "ok" } else { "unreachable" };
+      body.push_str(&format!(
+         "postgres primary: {}\npostgres replica: {}\n",
+         up(primary),
+         up(replica)
+      ));
+   }
+   let (pending, dropped) = (cache.failover.pending_len(), cache.failover.dropped_taps());
+   if pending > 0 || dropped > 0 {
+      body.push_str(&format!("deferred taps: {} pending, {} dropped\n", pending, dropped));
+   }
+   body.push_str(&format!("{}\n", cache.negative_cache.stats()));
+   if let Some(mqtt) = &health.mqtt {
+      body.push_str(&format!("{}\n", mqtt.status()));
+   }
+   if let Some(picker) = &notion.picker {
+      body.push_str(&format!("{}\n", picker.limiter.stats()));
+   }
+   if let Some(report) = deep.as_ref().or(unready.as_ref()) {
+      body.push_str(&report.to_string());
+   }
+   (status, body)
+}
+
+/// `/healthz?deep=true`: what a bad deploy would otherwise only show to its first visitors.
+async fn deep_health(
+   db: &DbState,
+   settings: &SharedSettings,
+   health: &HealthState,
+   headers: &HeaderMap,
+) -> health::Report {
+   let deadline = tokio::time::Instant::now() + health::BUDGET;
+   let settings = settings.load();
+   let canonical_host = settings.canonical_host.as_ref().map(|c| c.host.as_str());
+   let forwarded_proto = headers.get("x-forwarded-proto").and_then(|v| v.to_str().ok());
+   let checks = vec![
+      health::timed("pages", deadline, async { render_critical_pages(&settings.branding) }).await,
+      health::timed("origin", deadline, async {
+         health::check_origin(canonical_host, forwarded_proto)
+      })
+      .await,
+      health::timed("keys", deadline, async {
+         health::check_keys(health.cookie_key.as_ref(), health.provisioning_key.as_ref())
+      })
+      .await,
+      health::timed("migrations", deadline, async {
+         let mut conn = db.pool.get().acquire().await.map_err(|e| e.to_string())?;
+         health::migrations(&mut conn).await
+      })
+      .await,
+   ];
+   health::Report { checks }
+}
+
+/// Renders the pages scans and tag creation end on, with made-up data, so that a template that no
+/// longer renders fails a deep health check rather than the first visitor to need it.
+fn render_critical_pages(branding: &Branding) -> Result<String, String> {
+   let id: TagUid = "055B88A23C1250".parse().unwrap();
+   let target_url = "https://example.com/";
+   let pages = [
+      ("tag_not_found", TagNotFoundTemplate { branding, id: &id }.render()),
+      ("unavailable", UnavailableTemplate { branding }.render()),
+      ("maintenance", MaintenanceTemplate { branding }.render()),
+      ("tag_disabled", TagDisabledTemplate { branding }.render()),
+      ("tag_expired", TagExpiredTemplate { branding }.render()),
+      (
+         "tag_pending",
+         TagPendingTemplate {
+            branding,
+            activates_at: "2026-10-20 18:00 UTC",
+            datetime: "2026-10-20T18:00:00Z",
+         }
+         .render(),
+      ),
+      ("tag_quarantine", TagQuarantineTemplate { branding }.render()),
+      (
+         "tag_age_gate",
+         TagAgeGateTemplate {
+            branding,
+            text: age_gate::DEFAULT_TEXT,
+            action: &format!("/tag/{}/go", id),
+         }
+         .render(),
+      ),
+      (
+         "tag_arrival",
+         TagArrivalTemplate {
+            branding,
+            target_url,
+            beacon_url: &format!("/tag/{}/arrived?t=0", id),
+         }
+         .render(),
+      ),
+      (
+         "tag_create",
+         TagCreateTemplate {
+            branding,
+            action: &create_action(&id.to_string(), None, None),
+            id: &id.to_string(),
+            tap_count: &None,
+            target_url: &Some(target_url.to_string()),
+            notion_page: &None,
+            tag_model: TagModel::Unknown,
+            redirect_kind: RedirectKind::Temporary,
+            times: &FormTimes::default(),
+            fallback: &FormFallback::default(),
+            notion_enabled: true,
+            error: Some("Example error".to_string()),
+            capacity_warnings: &["NTAG213".to_string()],
+         }
+         .render(),
+      ),
+   ];
+   for (name, page) in &pages {
+      if let Err(e) = page {
+         return Err(format!("{}.html: {}", name, e));
+      }
    }
+   Ok(format!("{} pages", pages.len()))
 }
 
 #[derive(Deserialize)]
 struct TagCreateQuery {
-   id: Hex14,
+   /// A bare id, or a scan URL pasted whole; see `models::parse_tag_input`.
+   id: String,
    #[serde(with = "SerHexOpt::<Compact>")]
    #[serde(default)]
    tap_count: Option<u32>,
    target_url: Option<String>,
+   /// With `sig`, from a pre-signed creation link; see `provision`.
+   exp: Option<i64>,
+   sig: Option<String>,
+}
+
+impl TagCreateQuery {
+   fn link(&self) -> Option<provision::Link> {
+      Some(provision::Link {
+         exp: self.exp?,
+         sig: self.sig.clone()?,
+      })
+   }
+}
+
+impl KnownParams for TagCreateQuery {
+   const NAMES: &'static [&'static str] = &["id", "tap_count", "target_url", "exp", "sig"];
 }
 
 #[derive(Deserialize)]
 struct TagCreateForm {
+   /// When present, replaces the id in the query string.
+   #[serde(default)]
+   id: Option<String>,
    #[serde(with = "SerHexOpt::<Compact>")]
    #[serde(default)]
    tap_count: Option<u32>,
    target_url: Option<String>,
+   notion_page: Option<String>,
+   /// Blank for unknown; see `TagModel::from_input`.
+   tag_model: Option<String>,
+   /// Blank for temporary; see `RedirectKind::from_input`.
+   redirect_kind: Option<String>,
+   /// Blank for at once; see `parse_form_time`.
+   activates_at: Option<String>,
+   /// Blank for never.
+   expires_at: Option<String>,
+   /// Blank for none.
+   fallback_url: Option<String>,
+   #[serde(default)]
+   check_target: bool,
+   #[serde(default)]
+   mode: CreateMode,
+}
+
+impl KnownParams for TagCreateForm {
+   const NAMES: &'static [&'static str] = &[
+      "id",
+      "tap_count",
+      "target_url",
+      "notion_page",
+      "tag_model",
+      "redirect_kind",
+      "activates_at",
+      "expires_at",
+      "fallback_url",
+      "check_target",
+      "mode",
+   ];
+}
+
+/// What a creation does with an id that's already a tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CreateMode {
+   /// Refuses, with a 409.
+   #[default]
+   Create,
+   /// Points the existing tag at the new target instead, as a resubmitted form means to. Only the
+   /// target changes; the count, model and Notion page stay as they were.
+   Upsert,
+}
+
+#[derive(Template)]
+#[template(path = "tag_exists.html")]
+struct TagExistsTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a TagUid,
+   /// Where it redirects now.
+   target_url: &'a str,
+   /// Deleted, but not yet purged.
+   trashed: bool,
 }
 
 #[derive(Template)]
 #[template(path = "tag_create.html")]
 struct TagCreateTemplate<'a> {
+   branding: &'a Branding,
+   /// See `create_action`.
+   action: &'a str,
    id: &'a str,
    tap_count: &'a Option<String>,
    target_url: &'a Option<String>,
+   notion_page: &'a Option<String>,
+   tag_model: TagModel,
+   redirect_kind: RedirectKind,
+   times: &'a FormTimes,
+   fallback: &'a FormFallback,
+   notion_enabled: bool,
+   error: Option<String>,
+   /// Tag types the programmed URL won't fit on. Informational only; creation still proceeds.
+   capacity_warnings: &'a [String],
 }
 
 async fn create_tag_page(
-   extract::State(_state): extract::State<AppState>,
-   extract::Query(param): extract::Query<TagCreateQuery>,
+   extract::State(state): extract::State<AppState>,
+   CheckedQuery(param, unexpected): CheckedQuery<TagCreateQuery>,
+   headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
-   let id = &param.id;
-   let tap_count = param.tap_count;
    let target_url = &param.target_url;
+   unexpected.log_unknown();
 
-   // TODO: Redirect to edit if exists
+   let link = param.link();
+   let (id, tap_count, error, capacity_warnings) =
+      match models::parse_tag_input(&param.id, &own_hosts(&state, &headers)) {
+         Ok(slug) => {
+            let tap_count = param.tap_count.or(slug.tap_count);
+            if let Some(link) = &link {
+               let mut conn = state.pool.get().acquire().await.map_err(|e| {
+                  warn!("Failed to acquire a Postgres connection: {:?}", e);
+                  StatusCode::INTERNAL_SERVER_ERROR
+               })?;
+               let now = chrono::Utc::now().timestamp();
+               match provision::admit(&mut conn, state.provisioning_key.as_ref(), link, &slug.id, now).await {
+                  Ok(Ok(())) => {}
+                  // Scanned again once programmed; the link has nothing more to do
+                  Ok(Err(provision::Refused::Exists)) => {
+                     return Ok(axum::response::Redirect::temporary(&format!("/tag/{}", slug.id)).into_response());
+                  }
+                  Ok(Err(refused)) => {
+                     info!(tag_id = %slug.id, "Creation link refused: {}", refused);
+                     return Ok((StatusCode::FORBIDDEN, format!("{refused}.\n")).into_response());
+                  }
+                  Err(e) => {
+                     warn!("Failed to check creation link for tag '{}': {:?}", slug.id, e);
+                     return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                  }
+               }
+            } else {
+               // Rescanned once programmed, or opened twice; creating it again could only conflict
+               let exists = sqlx::query_scalar!(
+                  r#"SELECT EXISTS (SELECT 1 FROM twag_tags WHERE id = $1 AND deleted_at IS NULL) AS "exists!""#,
+                  slug.id as TagUid
+               )
+               .fetch_one(&state.reads.for_tag(&slug.id).get())
+               .await
+               .map_err(|e| {
+                  warn!("Failed to check whether tag '{}' exists: {:?}", slug.id, e);
+                  StatusCode::INTERNAL_SERVER_ERROR
+               })?;
+               if exists {
+                  return Ok(axum::response::Redirect::temporary(&edit_path(&slug.id, tap_count)).into_response());
+               }
+            }
+            let programmed = ndef::programmed_uri(&public_origin(&state, &headers), &slug.id);
+            (
+               slug.id.to_string(),
+               tap_count,
+               None,
+               ndef::capacity_warnings(&programmed),
+            )
+         }
+         Err(e) => {
+            info!("Rejecting tag id '{}': {}", param.id, e);
+            (param.id.clone(), param.tap_count, Some(e.to_string()), Vec::new())
+         }
+      };
+   let error = unexpected.error().or(error);
+   let status = match error {
+      Some(_) => StatusCode::BAD_REQUEST,
+      None => StatusCode::OK,
+   };
+   let tap_count = tap_count.map(|c| format!("{:06X}", c));
+   let page = TagCreateTemplate {
+      branding: &state.settings.load().branding,
+      action: &create_action(&id, tap_count.as_deref(), link.as_ref()),
+      id: &id,
+      tap_count: &tap_count,
+      target_url,
+      notion_page: &None,
+      tag_model: TagModel::Unknown,
+      redirect_kind: RedirectKind::Temporary,
+      times: &FormTimes::default(),
+      fallback: &FormFallback::default(),
+      notion_enabled: state.client.is_some(),
+      error,
+      capacity_warnings: &capacity_warnings,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html((status, response).into_response()))
+}
+
+/// Where the create form posts to: back to `/tag/create`, keeping the id, counter and any creation
+/// link it was opened with.
+fn create_action(id: &str, tap_count: Option<&str>, link: Option<&provision::Link>) -> String {
+   let mut query = url::form_urlencoded::Serializer::new(String::new());
+   query.append_pair("id", id);
+   if let Some(tap_count) = tap_count {
+      query.append_pair("tap_count", tap_count);
+   }
+   if let Some(link) = link {
+      query.append_pair("exp", &link.exp.to_string());
+      query.append_pair("sig", &link.sig);
+   }
+   format!("/tag/create?{}", query.finish())
+}
+
+/// Hosts a pasted scan URL may point at: the canonical host, and whichever host this request
+/// came in on.
+fn own_hosts(state: &AppState, headers: &HeaderMap) -> Vec<String> {
+   let canonical = state.settings.load().canonical_host.as_ref().map(|c| c.host.clone());
+   let requested = headers
+      .get(header::HOST)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|host| url::Url::parse(&format!("http://{host}")).ok())
+      .and_then(|url| url.host_str().map(str::to_string));
+   canonical.into_iter().chain(requested).collect()
+}
 
+/// Re-renders the create form with an error beside what was submitted.
+#[allow(clippy::too_many_arguments)]
+fn reject_create(
+   state: &AppState,
+   id: &str,
+   tap_count: Option<u32>,
+   target_url: &str,
+   notion_page: &Option<String>,
+   tag_model: TagModel,
+   redirect_kind: RedirectKind,
+   times: &FormTimes,
+   fallback: &FormFallback,
+   link: Option<&provision::Link>,
+   error: String,
+) -> Result<Response, StatusCode> {
+   let tap_count = tap_count.map(|c| format!("{:06X}", c));
    let page = TagCreateTemplate {
+      branding: &state.settings.load().branding,
+      action: &create_action(id, tap_count.as_deref(), link),
       id,
-      tap_count: &tap_count.map(|c| format!("{:06X}", c)),
-      target_url,
+      tap_count: &tap_count,
+      target_url: &Some(target_url.to_string()),
+      notion_page,
+      tag_model,
+      redirect_kind,
+      times,
+      fallback,
+      notion_enabled: state.client.is_some(),
+      error: Some(error),
+      capacity_warnings: &[],
    };
    let response = page.render().map_err(|e| {
       warn!("Failed to render template: {:?}", e);
       StatusCode::INTERNAL_SERVER_ERROR
    })?;
-   Ok(as_html(response.into_response()))
+   Ok(as_html((StatusCode::UNPROCESSABLE_ENTITY, response).into_response()))
 }
 
 async fn create_tag(
    extract::State(state): extract::State<AppState>,
-   extract::Query(param): extract::Query<TagCreateQuery>,
-   extract::Form(form): extract::Form<TagCreateForm>,
+   CheckedQuery(param, query_unexpected): CheckedQuery<TagCreateQuery>,
+   headers: HeaderMap,
+   CheckedForm(form, form_unexpected): CheckedForm<TagCreateForm>,
 ) -> Result<Response, StatusCode> {
-   let id = &param.id;
-   let tap_count = form.tap_count.or(param.tap_count).unwrap_or(1);
    let target_url = &form.target_url.or(param.target_url);
+   let link = param.link();
+   let tag_model = TagModel::from_input(form.tag_model.as_deref().unwrap_or_default());
+   let redirect_kind = RedirectKind::from_input(form.redirect_kind.as_deref().unwrap_or_default());
+   // Selected again when anything else is rejected
+   let shown_model = tag_model.clone().unwrap_or_default();
+   let shown_kind = redirect_kind.clone().unwrap_or_default();
+   let times = FormTimes {
+      activates_at: form.activates_at.clone().unwrap_or_default(),
+      expires_at: form.expires_at.clone().unwrap_or_default(),
+   };
+   let fallback = FormFallback {
+      url: form.fallback_url.clone().unwrap_or_default(),
+      check_target: form.check_target,
+   };
+
+   // A misspelled field would otherwise be dropped, leaving a tag without the value it meant
+   let unexpected = query_unexpected.merge(form_unexpected);
+   unexpected.log_unknown();
+   if let Some(error) = unexpected.error() {
+      info!("Rejecting tag creation: {error}");
+      let raw_id = form.id.as_deref().filter(|s| !s.trim().is_empty()).unwrap_or(&param.id);
+      let tap_count = form.tap_count.or(param.tap_count);
+      let target_url = target_url.as_deref().unwrap_or_default();
+      return reject_create(
+         &state,
+         raw_id,
+         tap_count,
+         target_url,
+         &form.notion_page,
+         shown_model,
+         shown_kind,
+         &times,
+         &fallback,
+         link.as_ref(),
+         error,
+      );
+   }
 
    if target_url.is_none() {
       warn!("Target URL is missing");
@@ -344,72 +2055,7170 @@ async fn create_tag(
    }
    let target_url = target_url.as_ref().unwrap();
 
-   info!(
-      "Creating tag with ID: {id}, tap_count: {tap_count}, target_url: {:?}",
-      target_url
-   );
+   let notion_page = form
+      .notion_page
+      .filter(|s| !s.trim().is_empty())
+      .filter(|_| state.client.is_some());
 
-   let Ok(mut conn) = state.pool.acquire().await else {
-      warn!("Failed to acquire Postgres connection");
-      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   let raw_id = form.id.as_deref().filter(|s| !s.trim().is_empty()).unwrap_or(&param.id);
+   let slug = match models::parse_tag_input(raw_id, &own_hosts(&state, &headers)) {
+      Ok(slug) => slug,
+      Err(e) => {
+         info!("Rejecting tag id '{raw_id}': {e}");
+         let tap_count = form.tap_count.or(param.tap_count);
+         return reject_create(
+            &state,
+            raw_id,
+            tap_count,
+            target_url,
+            &notion_page,
+            shown_model,
+            shown_kind,
+            &times,
+            &fallback,
+            link.as_ref(),
+            e.to_string(),
+         );
+      }
    };
+   let id = &slug.id;
+   let has_counter = form.tap_count.or(param.tap_count).or(slug.tap_count).is_some();
+   let tap_count = form.tap_count.or(param.tap_count).or(slug.tap_count).unwrap_or(1);
 
-   sqlx::query!(
-      r#"INSERT INTO twag_tags (id, target_url, access_count) VALUES ($1::hex_14, $2, $3)"#,
-      id as &Hex14,
-      target_url,
-      tap_count as i32,
-   )
-   .execute(&mut *conn)
-   .await
-   .map_err(|e| {
-      warn!("Failed to create tag in Postgres: {:?}", e);
-      StatusCode::INTERNAL_SERVER_ERROR
-   })?;
+   let tag_model = match tag_model {
+      Ok(tag_model) => tag_model,
+      Err(e) => {
+         info!("Rejecting tag model for tag '{id}': {e}");
+         let tap_count = form.tap_count.or(param.tap_count).or(slug.tap_count);
+         return reject_create(
+            &state,
+            &id.to_string(),
+            tap_count,
+            target_url,
+            &notion_page,
+            shown_model,
+            shown_kind,
+            &times,
+            &fallback,
+            link.as_ref(),
+            e,
+         );
+      }
+   };
 
-   Ok("Created!".into_response())
-}
+   let redirect_kind = match redirect_kind {
+      Ok(redirect_kind) => redirect_kind,
+      Err(e) => {
+         info!("Rejecting redirect kind for tag '{id}': {e}");
+         let tap_count = form.tap_count.or(param.tap_count).or(slug.tap_count);
+         return reject_create(
+            &state,
+            &id.to_string(),
+            tap_count,
+            target_url,
+            &notion_page,
+            shown_model,
+            shown_kind,
+            &times,
+            &fallback,
+            link.as_ref(),
+            e,
+         );
+      }
+   };
 
-async fn get_tag_by_id(
-   extract::State(state): extract::State<AppState>,
-   extract::Path(param): extract::Path<String>,
-) -> Result<Response, StatusCode> {
-   let Some((_, id_str, tap_count_str)) = regex_captures!(r"^([0-9A-F]{14})(?:x([0-9A-F]{6}))?$", &param) else {
-      warn!("Invalid tag ID format");
-      return Err(StatusCode::BAD_REQUEST);
+   let notion_page_id = match notion_page.as_deref().map(NotionPageId::new).transpose() {
+      Ok(notion_page_id) => notion_page_id,
+      Err(e) => {
+         info!("Rejecting Notion page for tag '{id}': {e}");
+         let tap_count = form.tap_count.or(param.tap_count).or(slug.tap_count);
+         return reject_create(
+            &state,
+            &id.to_string(),
+            tap_count,
+            target_url,
+            &notion_page,
+            shown_model,
+            shown_kind,
+            &times,
+            &fallback,
+            link.as_ref(),
+            e.to_string(),
+         );
+      }
    };
 
-   let id: Hex14 = id_str.try_into().map_err(|e| {
-      warn!("Failed to parse tag ID: {:?}", e);
-      StatusCode::BAD_REQUEST
-   })?;
+   let target_url = match kit::validate_target_url(target_url, state.settings.load().strict_idn) {
+      Ok(target_url) => target_url,
+      Err(e) => {
+         info!("Rejecting target URL for tag '{id}': {e}");
+         let tap_count = form.tap_count.or(param.tap_count).or(slug.tap_count);
+         return reject_create(
+            &state,
+            &id.to_string(),
+            tap_count,
+            target_url,
+            &notion_page,
+            shown_model,
+            shown_kind,
+            &times,
+            &fallback,
+            link.as_ref(),
+            e,
+         );
+      }
+   };
 
-   let tap_count = (!tap_count_str.is_empty())
-      .then_some(tap_count_str)
-      .and_then(|s| i32::from_str_radix(s, 16).ok());
+   let activates_at = parse_form_time(&times.activates_at, "The activation time");
+   let expires_at = parse_form_time(&times.expires_at, "The expiry");
+   let (activates_at, expires_at) = match (activates_at, expires_at) {
+      (Ok(activates_at), Ok(expires_at)) => (activates_at, expires_at),
+      (Err(e), _) | (_, Err(e)) => {
+         info!("Rejecting times for tag '{id}': {e}");
+         let tap_count = form.tap_count.or(param.tap_count).or(slug.tap_count);
+         return reject_create(
+            &state,
+            &id.to_string(),
+            tap_count,
+            &target_url,
+            &notion_page,
+            shown_model,
+            shown_kind,
+            &times,
+            &fallback,
+            link.as_ref(),
+            e,
+         );
+      }
+   };
 
-   let Ok(mut conn) = state.pool.acquire().await else {
-      warn!("Failed to acquire Postgres connection");
-      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   let fallback_url = match fallback.url.trim() {
+      "" => None,
+      url => match kit::validate_target_url(url, state.settings.load().strict_idn) {
+         Ok(url) => Some(url),
+         Err(e) => {
+            info!("Rejecting fallback URL for tag '{id}': {e}");
+            let tap_count = form.tap_count.or(param.tap_count).or(slug.tap_count);
+            return reject_create(
+               &state,
+               &id.to_string(),
+               tap_count,
+               &target_url,
+               &notion_page,
+               shown_model,
+               shown_kind,
+               &times,
+               &fallback,
+               link.as_ref(),
+               format!("Fallback: {e}"),
+            );
+         }
+      },
    };
 
-   let tag = sqlx::query!("SELECT * FROM twag_tags WHERE id = $1", &id)
-      .fetch_optional(&mut *conn)
-      .await
-      .map_err(|e| {
+   if form.mode == CreateMode::Upsert && link.is_some() {
+      info!(tag_id = %id, "Refusing to upsert with a creation link");
+      return Ok((StatusCode::FORBIDDEN, "A creation link can only create a tag.\n").into_response());
+   }
+
+   let _lock = match state.tag_locks.acquire(&[*id], tag_lock::TIMEOUT).await {
+      Ok(lock) => lock,
+      Err(locked) => return Ok(locked.into_response()),
+   };
+   info!(
+      "Creating tag with ID: {id}, tap_count: {tap_count}, target_url: {:?}",
+      target_url
+   );
+
+   let Ok(mut tx) = state.pool.get().begin().await else {
+      warn!("Failed to begin Postgres transaction");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   };
+
+   // Checked under the lock and marked used in the same transaction, so a link makes one tag even
+   // when it's submitted twice at once
+   if let Some(link) = &link {
+      let now = chrono::Utc::now().timestamp();
+      let admitted = provision::admit(&mut tx, state.provisioning_key.as_ref(), link, id, now)
+         .await
+         .map_err(|e| {
+            warn!("Failed to check creation link for tag '{id}': {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+      let admitted = match admitted {
+         // Not the link's to create; same as an unsigned request for an existing tag
+         Err(provision::Refused::Exists) => Ok(()),
+         Ok(()) => match provision::consume(&mut tx, id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(provision::Refused::Used),
+            Err(e) => {
+               warn!("Failed to mark creation link for tag '{id}' used: {:?}", e);
+               return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+         },
+         refused => refused,
+      };
+      if let Err(refused) = admitted {
+         info!(tag_id = %id, "Creation link refused: {}", refused);
+         return Ok((StatusCode::FORBIDDEN, format!("{refused}.\n")).into_response());
+      }
+   }
+
+   if form.mode == CreateMode::Upsert {
+      let before = sqlx::query_scalar!(
+         "SELECT target_url FROM twag_tags WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+         *id as TagUid
+      )
+      .fetch_optional(&mut *tx)
+      .await
+      .map_err(|e| {
          warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
          StatusCode::INTERNAL_SERVER_ERROR
       })?;
+      if let Some(before) = before {
+         return retarget_instead(&state, tx, *id, &before, &target_url).await;
+      }
+   }
 
-   if tag.is_none() {
-      info!("Tag '{id}' not found, redirecting to /tag/create");
-      let create_url = tap_count
-         .map(|tap_count| format!("/tag/create?id={id}&tap_count={:06X}", tap_count))
-         .unwrap_or_else(|| format!("/tag/create?id={id}"));
-      return Ok(axum::response::Redirect::temporary(&create_url).into_response());
+   let quotas = state.settings.load().quotas;
+   let reserved = quota::reserve(&mut tx, &quotas, quota::Resource::Tags, 1)
+      .await
+      .map_err(|e| {
+         warn!("Failed to check the tag quota in Postgres: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+   if let Err(exceeded) = reserved {
+      info!(tag_id = %id, "Not creating tag: {}", exceeded);
+      return Ok(exceeded.into_response());
+   }
+
+   let target_id = targets::find_or_insert(&mut tx, &target_url).await.map_err(|e| {
+      warn!("Failed to find the target for a new tag in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   // Claimed with ON CONFLICT rather than by catching the unique violation: a retried submission
+   // racing the first, on another instance, finds the id taken, and rolls back all it did
+   let inserted = sqlx::query!(
+      r#"INSERT INTO twag_tags
+            (id, target_url, target_id, access_count, notion_page_id, tag_model, expires_at, activates_at,
+             redirect_kind, fallback_url, check_target)
+         VALUES ($1::tag_uid, $2, $3, $4, $5::notion_page_id, $6, $7::text::timestamptz, $8::text::timestamptz, $9,
+            $10, $11)
+         ON CONFLICT (id) DO NOTHING"#,
+      id as &TagUid,
+      target_url,
+      target_id,
+      tap_count as i32,
+      notion_page_id as Option<NotionPageId>,
+      tag_model.as_str(),
+      expires_at.map(|at| at.to_rfc3339()),
+      activates_at.map(|at| at.to_rfc3339()),
+      redirect_kind.as_str(),
+      fallback_url,
+      form.check_target,
+   )
+   .execute(&mut *tx)
+   .await;
+   let inserted = match inserted {
+      Ok(inserted) => inserted,
+      Err(e) => return write_failed("Failed to create tag in Postgres", e),
+   };
+   if inserted.rows_affected() == 0 {
+      info!(tag_id = %id, "Not creating tag, it already exists");
+      return render_tag_exists(&state, &mut tx, id).await;
+   }
+
+   outbox::enqueue_created(&mut tx, state.notion_outbox, id, notion_page_id.as_ref())
+      .await
+      .map_err(|e| {
+         warn!("Failed to enqueue Notion page creation: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+
+   tx.commit().await.map_err(|e| {
+      warn!("Failed to commit tag creation: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   state.reads.wrote(id);
+   state.negative_cache.invalidate([id]);
+
+   let mut body = "Created!\n".to_string();
+   let programmed = ndef::programmed_uri(&public_origin(&state, &headers), id);
+   let warnings = tag_model.counter_warning(has_counter).into_iter();
+   for warning in warnings.chain(tag_model.capacity_warnings(&programmed)) {
+      body.push_str(&format!("Warning: {}\n", warning));
+   }
+   Ok(body.into_response())
+}
+
+/// The upsert of a tag that exists: points it at `target_url`, audited as any retarget is.
+async fn retarget_instead(
+   state: &AppState,
+   mut tx: sqlx::Transaction<'_, sqlx::Postgres>,
+   id: TagUid,
+   before: &str,
+   target_url: &str,
+) -> Result<Response, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to update tag '{id}' in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let target_id = targets::find_or_insert(&mut tx, target_url).await.map_err(failed)?;
+   sqlx::query!(
+      "UPDATE twag_tags SET target_url = $2, target_id = $3, updated_at = current_timestamp WHERE id = $1",
+      id as TagUid,
+      target_url,
+      target_id,
+   )
+   .execute(&mut *tx)
+   .await
+   .map_err(failed)?;
+   if before != target_url {
+      sqlx::query!(
+         "INSERT INTO twag_tag_audit (tag_id, action, before, after) VALUES ($1, 'retarget', $2, $3)",
+         id as TagUid,
+         before,
+         target_url,
+      )
+      .execute(&mut *tx)
+      .await
+      .map_err(failed)?;
+   }
+   tx.commit().await.map_err(failed)?;
+   state.failover.forget(&id);
+   state.reads.wrote(&id);
+   info!(tag_id = %id, "Tag updated instead of created");
+   Ok("Updated!\n".into_response())
+}
+
+/// The 409 for creating a tag that exists, with where to go instead.
+async fn render_tag_exists(
+   state: &AppState,
+   conn: &mut sqlx::PgConnection,
+   id: &TagUid,
+) -> Result<Response, StatusCode> {
+   let existing = sqlx::query!(
+      r#"SELECT target_url, deleted_at IS NOT NULL AS "trashed!" FROM twag_tags WHERE id = $1"#,
+      id as &TagUid
+   )
+   .fetch_optional(conn)
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   // Purged between the insert and now; only a retry can tell what it would find
+   let Some(existing) = existing else {
+      return Ok((
+         StatusCode::CONFLICT,
+         "A tag with this id was just removed; try again.\n",
+      )
+         .into_response());
+   };
+   let page = TagExistsTemplate {
+      branding: &state.settings.load().branding,
+      id,
+      target_url: &existing.target_url,
+      trashed: existing.trashed,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html((StatusCode::CONFLICT, response).into_response()))
+}
+
+#[derive(Template)]
+#[template(path = "tag_edit.html")]
+struct TagEditTemplate<'a> {
+   branding: &'a Branding,
+   id: TagUid,
+   target_url: &'a str,
+   access_count: &'a str,
+   enabled: bool,
+   redirect_kind: RedirectKind,
+   times: &'a FormTimes,
+   fallback: &'a FormFallback,
+   error: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct TagEditForm {
+   target_url: String,
+   access_count: String,
+   /// Left as it is when absent.
+   enabled: Option<bool>,
+   /// Left as it is when absent; see `RedirectKind::from_input`.
+   redirect_kind: Option<String>,
+   /// Left as it is when absent, and cleared when blank.
+   activates_at: Option<String>,
+   /// Left as it is when absent, and cleared when blank.
+   expires_at: Option<String>,
+   /// Left as it is when absent, and cleared when blank.
+   fallback_url: Option<String>,
+   /// Left as it is when absent.
+   check_target: Option<bool>,
+}
+
+/// A time as typed into a tag form; see `schedule::parse_time`. Blank for none; `what` names the
+/// field in the error.
+fn parse_form_time(raw: &str, what: &str) -> Result<Option<chrono::DateTime<chrono::Utc>>, String> {
+   if raw.trim().is_empty() {
+      return Ok(None);
+   }
+   schedule::parse_time(raw)
+      .map(Some)
+      .ok_or_else(|| format!("{what} must be a date and time in UTC, like 2026-10-20T18:00."))
+}
+
+/// The times a tag form sets, as `datetime-local` inputs take them, in UTC; blank for none.
+#[derive(Debug, Clone, Default)]
+struct FormTimes {
+   activates_at: String,
+   expires_at: String,
+}
+
+/// A tag form's fallback and whether the target is probed for it, as typed; see `fallback`.
+#[derive(Debug, Clone, Default)]
+struct FormFallback {
+   url: String,
+   check_target: bool,
+}
+
+/// The tag an admin URL names. Slugs parse as on a scan, so a scanned `...x00000F` URL with `/edit`
+/// added works, its counter ignored unless the handler has a use for it; `.vcf` slugs aren't tags.
+fn admin_slug(param: &str) -> Result<models::TagSlug, StatusCode> {
+   match param.parse::<models::TagSlug>() {
+      Ok(slug) if !slug.vcf => Ok(slug),
+      _ => Err(StatusCode::NOT_FOUND),
+   }
+}
+
+/// The edit page for `id`, with the counter a scan reported, if any, to fill in.
+fn edit_path(id: &TagUid, tap_count: Option<u32>) -> String {
+   match tap_count {
+      Some(tap_count) => format!("/tag/{}/edit?tap_count={:06X}", id, tap_count),
+      None => format!("/tag/{}/edit", id),
+   }
+}
+
+#[derive(Deserialize)]
+struct TagEditQuery {
+   #[serde(with = "SerHexOpt::<Compact>")]
+   #[serde(default)]
+   tap_count: Option<u32>,
+}
+
+/// Where an edit of a tag that doesn't exist goes instead, keeping the counter the slug had.
+fn create_instead(slug: &models::TagSlug) -> Response {
+   let tap_count = slug.tap_count.map(|c| format!("{:06X}", c));
+   axum::response::Redirect::to(&create_action(&slug.id.to_string(), tap_count.as_deref(), None)).into_response()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_tag_edit(
+   state: &AppState,
+   status: StatusCode,
+   id: TagUid,
+   target_url: &str,
+   access_count: &str,
+   enabled: bool,
+   redirect_kind: RedirectKind,
+   times: &FormTimes,
+   fallback: &FormFallback,
+   error: Option<&str>,
+) -> Result<Response, StatusCode> {
+   let page = TagEditTemplate {
+      branding: &state.settings.load().branding,
+      id,
+      target_url,
+      access_count,
+      enabled,
+      redirect_kind,
+      times,
+      fallback,
+      error,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html((status, response).into_response()))
+}
+
+/// `GET /tag/{slug}/edit`: the tag's target, count, redirect kind, activation, expiry and fallback,
+/// to change. A counter, in the slug or as `tap_count`, is what a scan just reported, and is filled
+/// in for the count. Expired tags can be edited, to move their expiry later.
+async fn edit_tag_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+   extract::Query(query): extract::Query<TagEditQuery>,
+) -> Result<Response, StatusCode> {
+   let mut slug = admin_slug(&param)?;
+   slug.tap_count = query.tap_count.or(slug.tap_count);
+   let id = slug.id;
+   let tag = sqlx::query!(
+      r#"SELECT target_url, access_count, enabled, redirect_kind, fallback_url, check_target,
+            to_char(activates_at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI') AS activates_at,
+            to_char(expires_at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI') AS expires_at
+         FROM twag_tags WHERE id = $1 AND deleted_at IS NULL"#,
+      id as TagUid
+   )
+   .fetch_optional(&state.reads.for_tag(&id).get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   let Some(tag) = tag else {
+      return Ok(create_instead(&slug));
+   };
+   let access_count = match slug.tap_count {
+      Some(tap_count) => tap_count.to_string(),
+      None => tag.access_count.unwrap_or(0).to_string(),
+   };
+   let times = FormTimes {
+      activates_at: tag.activates_at.unwrap_or_default(),
+      expires_at: tag.expires_at.unwrap_or_default(),
+   };
+   let fallback = FormFallback {
+      url: tag.fallback_url.unwrap_or_default(),
+      check_target: tag.check_target,
+   };
+   render_tag_edit(
+      &state,
+      StatusCode::OK,
+      id,
+      &tag.target_url,
+      &access_count,
+      tag.enabled,
+      tag.redirect_kind.parse().unwrap_or_default(),
+      &times,
+      &fallback,
+      None,
+   )
+}
+
+/// `POST /tag/{slug}/edit`: changes the target, count, redirect kind, activation, expiry and
+/// fallback, and stamps `updated_at`.
+async fn edit_tag(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+   extract::Form(form): extract::Form<TagEditForm>,
+) -> Result<Response, StatusCode> {
+   let slug = admin_slug(&param)?;
+   let id = slug.id;
+   let shown_enabled = form.enabled.unwrap_or(true);
+   let redirect_kind = form.redirect_kind.as_deref().map(RedirectKind::from_input);
+   let shown_kind = redirect_kind.clone().and_then(Result::ok).unwrap_or_default();
+   let shown_times = FormTimes {
+      activates_at: form.activates_at.clone().unwrap_or_default(),
+      expires_at: form.expires_at.clone().unwrap_or_default(),
+   };
+   let shown_fallback = FormFallback {
+      url: form.fallback_url.clone().unwrap_or_default(),
+      check_target: form.check_target.unwrap_or(false),
+   };
+   let reject = |error: &str| {
+      let (target_url, access_count) = (&form.target_url, &form.access_count);
+      let status = StatusCode::UNPROCESSABLE_ENTITY;
+      render_tag_edit(
+         &state,
+         status,
+         id,
+         target_url,
+         access_count,
+         shown_enabled,
+         shown_kind,
+         &shown_times,
+         &shown_fallback,
+         Some(error),
+      )
+   };
+   let target_url = kit::validate_target_url(form.target_url.trim(), state.settings.load().strict_idn);
+   let access_count = form.access_count.trim().parse::<i32>().ok().filter(|count| *count >= 0);
+   let (target_url, access_count) = match (target_url, access_count) {
+      (Ok(target_url), Some(access_count)) => (target_url, access_count),
+      (Err(e), _) => {
+         info!("Rejecting target URL for tag '{id}': {e}");
+         return reject(&e);
+      }
+      (Ok(_), None) => return reject("The count must be a whole number, 0 or more."),
+   };
+   let redirect_kind = match redirect_kind.transpose() {
+      Ok(redirect_kind) => redirect_kind,
+      Err(error) => return reject(&error),
+   };
+   let activates_at = form
+      .activates_at
+      .as_deref()
+      .map(|raw| parse_form_time(raw, "The activation time"));
+   let activates_at = match activates_at.transpose() {
+      Ok(activates_at) => activates_at,
+      Err(error) => return reject(&error),
+   };
+   let expires_at = form.expires_at.as_deref().map(|raw| parse_form_time(raw, "The expiry"));
+   let expires_at = match expires_at.transpose() {
+      Ok(expires_at) => expires_at,
+      Err(error) => return reject(&error),
+   };
+   let strict_idn = state.settings.load().strict_idn;
+   let fallback_url = form.fallback_url.as_deref().map(|url| match url.trim() {
+      "" => Ok(None),
+      url => kit::validate_target_url(url, strict_idn).map(Some),
+   });
+   let fallback_url = match fallback_url.transpose() {
+      Ok(fallback_url) => fallback_url,
+      Err(e) => return reject(&format!("Fallback: {e}")),
+   };
+
+   let _lock = match state.tag_locks.acquire(&[id], tag_lock::TIMEOUT).await {
+      Ok(lock) => lock,
+      Err(locked) => return Ok(locked.into_response()),
+   };
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to edit tag '{id}' in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut tx = state.pool.get().begin().await.map_err(failed)?;
+   let before = sqlx::query_scalar!(
+      "SELECT target_url FROM twag_tags WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+      id as TagUid
+   )
+   .fetch_optional(&mut *tx)
+   .await
+   .map_err(failed)?;
+   let Some(before) = before else {
+      return Ok(create_instead(&slug));
+   };
+   let target_id = targets::find_or_insert(&mut tx, &target_url).await.map_err(failed)?;
+   sqlx::query!(
+      "UPDATE twag_tags SET target_url = $2, target_id = $3, access_count = $4, enabled = coalesce($5, enabled),
+            expires_at = CASE WHEN $6 THEN $7::text::timestamptz ELSE expires_at END,
+            activates_at = CASE WHEN $8 THEN $9::text::timestamptz ELSE activates_at END,
+            redirect_kind = coalesce($10, redirect_kind),
+            fallback_url = CASE WHEN $11 THEN $12 ELSE fallback_url END,
+            check_target = coalesce($13, check_target), updated_at = current_timestamp
+         WHERE id = $1",
+      id as TagUid,
+      target_url,
+      target_id,
+      access_count,
+      form.enabled,
+      expires_at.is_some(),
+      expires_at.flatten().map(|at| at.to_rfc3339()),
+      activates_at.is_some(),
+      activates_at.flatten().map(|at| at.to_rfc3339()),
+      redirect_kind.map(|kind| kind.as_str()),
+      fallback_url.is_some(),
+      fallback_url.flatten(),
+      form.check_target,
+   )
+   .execute(&mut *tx)
+   .await
+   .map_err(failed)?;
+   if before != target_url {
+      sqlx::query!(
+         "INSERT INTO twag_tag_audit (tag_id, action, before, after) VALUES ($1, 'retarget', $2, $3)",
+         id as TagUid,
+         before,
+         target_url,
+      )
+      .execute(&mut *tx)
+      .await
+      .map_err(failed)?;
+   }
+   tx.commit().await.map_err(failed)?;
+   state.failover.forget(&id);
+   state.reads.wrote(&id);
+   info!(tag_id = %id, access_count, "Tag edited");
+   Ok(axum::response::Redirect::to(&format!("/tag/{}/stats", id)).into_response())
+}
+
+#[derive(Template)]
+#[template(path = "tag_delete.html")]
+struct TagDeleteTemplate<'a> {
+   branding: &'a Branding,
+   id: TagUid,
+   target_url: &'a str,
+   trash_days: u32,
+}
+
+/// Moves the tag to the trash, as a bulk delete does, and logs what it pointed at. `false` if
+/// there's no such tag, or it's in the trash already.
+async fn trash_tag(state: &AppState, id: TagUid) -> Result<bool, Response> {
+   let _lock = state
+      .tag_locks
+      .acquire(&[id], tag_lock::TIMEOUT)
+      .await
+      .map_err(IntoResponse::into_response)?;
+   let deleted = sqlx::query!(
+      r#"WITH deleted AS (
+            UPDATE twag_tags SET deleted_at = current_timestamp,
+               purge_after = current_timestamp + make_interval(days => $2)
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING id, target_url, access_count
+         ), audited AS (
+            INSERT INTO twag_tag_audit (tag_id, action) SELECT id, 'delete' FROM deleted
+         )
+         SELECT target_url AS "target_url!", access_count FROM deleted"#,
+      id as TagUid,
+      state.settings.load().trash_days as i32,
+   )
+   .fetch_optional(&state.pool.get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to delete tag '{id}' in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+   })?;
+   let Some(deleted) = deleted else {
+      return Ok(false);
+   };
+   state.failover.forget(&id);
+   state.reads.wrote(&id);
+   info!(
+      tag_id = %id,
+      target_url = %deleted.target_url,
+      access_count = deleted.access_count.unwrap_or(0),
+      "Tag deleted"
+   );
+   Ok(true)
+}
+
+/// `DELETE /tag/{slug}`: moves the tag to the trash; 204, or 404 if there's no such tag.
+async fn delete_tag(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+) -> Result<Response, StatusCode> {
+   let id = admin_slug(&param)?.id;
+   match trash_tag(&state, id).await {
+      Ok(true) => Ok(StatusCode::NO_CONTENT.into_response()),
+      Ok(false) => Err(StatusCode::NOT_FOUND),
+      Err(response) => Ok(response),
+   }
+}
+
+/// `GET /tag/{slug}/delete`: asks to confirm, for browsers, whose forms can't send a DELETE.
+async fn delete_tag_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+) -> Result<Response, StatusCode> {
+   let id = admin_slug(&param)?.id;
+   let target_url = sqlx::query_scalar!(
+      "SELECT target_url FROM twag_tags WHERE id = $1 AND deleted_at IS NULL",
+      id as TagUid
+   )
+   .fetch_optional(&state.reads.for_tag(&id).get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   .ok_or(StatusCode::NOT_FOUND)?;
+   let settings = state.settings.load();
+   let page = TagDeleteTemplate {
+      branding: &settings.branding,
+      id,
+      target_url: &target_url,
+      trash_days: settings.trash_days,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+/// `POST /tag/{slug}/delete`: the confirmed delete, then on to the trash, where it can be undone.
+async fn delete_tag_confirmed(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+) -> Result<Response, StatusCode> {
+   let id = admin_slug(&param)?.id;
+   match trash_tag(&state, id).await {
+      Ok(true) => Ok(axum::response::Redirect::to("/tags/trash").into_response()),
+      Ok(false) => Err(StatusCode::NOT_FOUND),
+      Err(response) => Ok(response),
+   }
+}
+
+#[derive(Deserialize)]
+struct TagCloneForm {
+   new_id: String,
+}
+
+/// `POST /tag/{slug}/clone`: creates `new_id` with the tag's target and settings, its localized and
+/// per-country targets and its contact card, then redirects to the new tag's edit page. Its label,
+/// Notion page, short link and counters are its own, so start empty, as do its history and stats.
+async fn clone_tag(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+   headers: HeaderMap,
+   extract::Form(form): extract::Form<TagCloneForm>,
+) -> Result<Response, StatusCode> {
+   let from = admin_slug(&param)?.id;
+   let new = match models::parse_tag_input(&form.new_id, &own_hosts(&state, &headers)) {
+      Ok(new) => new,
+      Err(e) => {
+         info!(tag_id = %from, "Rejecting clone: {e}");
+         return Ok((StatusCode::BAD_REQUEST, format!("{e}\n")).into_response());
+      }
+   };
+   let id = new.id;
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to clone tag '{from}' to '{id}' in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut tx = state.pool.get().begin().await.map_err(failed)?;
+   let exists = sqlx::query_scalar!(
+      r#"SELECT EXISTS (SELECT 1 FROM twag_tags WHERE id = $1 AND deleted_at IS NULL) AS "exists!""#,
+      from as TagUid
+   )
+   .fetch_one(&mut *tx)
+   .await
+   .map_err(failed)?;
+   if !exists {
+      return Err(StatusCode::NOT_FOUND);
+   }
+
+   let quotas = state.settings.load().quotas;
+   if let Err(exceeded) = quota::reserve(&mut tx, &quotas, quota::Resource::Tags, 1)
+      .await
+      .map_err(failed)?
+   {
+      info!(tag_id = %id, "Not cloning tag: {}", exceeded);
+      return Ok(exceeded.into_response());
+   }
+   // Claimed with ON CONFLICT, as a create is; the copies only ever follow a row just inserted
+   let cloned = sqlx::query_scalar!(
+      r#"WITH cloned AS (
+            INSERT INTO twag_tags (id, target_url, target_id, access_count, kit, tag_model, redirect_kind,
+               maintenance, stateful, age_gate, age_gate_text, quarantine_on_review, ignore_kit_schedule, enabled,
+               expires_at, confirm_arrival, public_status, public_show_count, fallback_url, check_target,
+               activates_at)
+            SELECT $2::tag_uid, target_url, target_id, 0, kit, tag_model, redirect_kind,
+               maintenance, stateful, age_gate, age_gate_text, quarantine_on_review, ignore_kit_schedule, enabled,
+               expires_at, confirm_arrival, public_status, public_show_count, fallback_url, check_target,
+               activates_at
+            FROM twag_tags WHERE id = $1
+            ON CONFLICT (id) DO NOTHING
+            RETURNING id
+         ), langs AS (
+            INSERT INTO twag_tag_targets_i18n (tag_id, lang, target_url)
+            SELECT cloned.id, l.lang, l.target_url FROM cloned, twag_tag_targets_i18n l WHERE l.tag_id = $1
+         ), geo AS (
+            INSERT INTO twag_tag_targets_geo (tag_id, countries, target_url)
+            SELECT cloned.id, g.countries, g.target_url FROM cloned, twag_tag_targets_geo g
+            WHERE g.tag_id = $1 ORDER BY g.id
+         ), vcard AS (
+            INSERT INTO twag_tag_vcards (tag_id, name, org, phone, email, url, note)
+            SELECT cloned.id, v.name, v.org, v.phone, v.email, v.url, v.note FROM cloned, twag_tag_vcards v
+            WHERE v.tag_id = $1
+         )
+         SELECT count(*) AS "cloned!" FROM cloned"#,
+      from as TagUid,
+      id as TagUid,
+   )
+   .fetch_one(&mut *tx)
+   .await;
+   match cloned {
+      Ok(0) => {
+         info!(tag_id = %id, "Not cloning tag, it already exists");
+         return render_tag_exists(&state, &mut tx, &id).await;
+      }
+      Ok(_) => {}
+      Err(e) => return write_failed("Failed to clone tag in Postgres", e),
+   }
+   outbox::enqueue_created(&mut tx, state.notion_outbox, &id, None)
+      .await
+      .map_err(failed)?;
+   tx.commit().await.map_err(failed)?;
+   state.reads.wrote(&id);
+   state.negative_cache.invalidate([&id]);
+   info!(tag_id = %id, from = %from, "Tag cloned");
+   Ok(axum::response::Redirect::to(&edit_path(&id, new.tap_count)).into_response())
+}
+
+#[derive(Template)]
+#[template(path = "tag_kit.html")]
+struct TagKitTemplate<'a> {
+   branding: &'a Branding,
+   kit: &'a Option<String>,
+   /// Each row as submitted, with its validation error if it had one.
+   rows: &'a [(KitRow, Option<String>)],
+   notion_enabled: bool,
+   error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "tag_kit_created.html")]
+struct TagKitCreatedTemplate<'a> {
+   branding: &'a Branding,
+   kit: &'a Option<String>,
+   tags: &'a [ValidKitRow],
+}
+
+async fn create_kit_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   let rows = vec![(KitRow::default(), None); kit::MAX_KIT_ROWS];
+   let page = TagKitTemplate {
+      branding: &state.settings.load().branding,
+      kit: &None,
+      rows: &rows,
+      notion_enabled: state.client.is_some(),
+      error: None,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+/// Why `insert_kit` created nothing.
+enum KitRefused {
+   /// Ids already taken, with a result per row.
+   Conflicts(Vec<RowResult>),
+   Quota(quota::Exceeded),
+}
+
+/// Creates every tag in the kit in one transaction, or none of them. Ids that already exist are
+/// reported per row rather than failing the whole request with a 500.
+async fn insert_kit(
+   state: &AppState,
+   kit: &Option<String>,
+   rows: &[ValidKitRow],
+) -> Result<Result<(), KitRefused>, sqlx::Error> {
+   let mut tx = state.pool.get().begin().await?;
+
+   let ids: Vec<String> = rows.iter().map(|row| row.id.to_string()).collect();
+   let existing = sqlx::query_scalar!(
+      r#"SELECT id::text AS "id!" FROM (SELECT id FROM twag_tags WHERE id::text = ANY($1) FOR UPDATE) AS t
+         UNION ALL SELECT id::text FROM twag_tags_archive WHERE id::text = ANY($1)"#,
+      &ids,
+   )
+   .fetch_all(&mut *tx)
+   .await?;
+   if !existing.is_empty() {
+      return Ok(Err(KitRefused::Conflicts(
+         rows
+            .iter()
+            .map(|row| match existing.contains(&row.id.to_string()) {
+               true => RowResult::conflict(&row.id),
+               false => RowResult::not_attempted(&row.id.to_string()),
+            })
+            .collect(),
+      )));
+   }
+   let quotas = state.settings.load().quotas;
+   if let Err(exceeded) = quota::reserve(&mut tx, &quotas, quota::Resource::Tags, rows.len() as i64).await? {
+      return Ok(Err(KitRefused::Quota(exceeded)));
+   }
+
+   let urls: Vec<String> = rows.iter().map(|row| row.target_url.clone()).collect();
+   let target_ids = targets::ensure(&mut tx, &urls).await?;
+   for (row, target_id) in rows.iter().zip(target_ids) {
+      sqlx::query!(
+         r#"INSERT INTO twag_tags (id, target_url, target_id, access_count, notion_page_id, label, kit)
+            VALUES ($1::tag_uid, $2, $3, 1, $4::notion_page_id, $5, $6)"#,
+         row.id as TagUid,
+         row.target_url,
+         target_id,
+         row.notion_page_id.clone() as Option<NotionPageId>,
+         row.label,
+         kit.as_deref(),
+      )
+      .execute(&mut *tx)
+      .await?;
+
+      outbox::enqueue_created(&mut tx, state.notion_outbox, &row.id, row.notion_page_id.as_ref()).await?;
+   }
+
+   tx.commit().await?;
+   state.negative_cache.invalidate(rows.iter().map(|row| &row.id));
+   Ok(Ok(()))
+}
+
+#[derive(Deserialize)]
+struct KitQuery {
+   kit: Option<String>,
+}
+
+impl KnownParams for KitQuery {
+   const NAMES: &'static [&'static str] = &["kit"];
+}
+
+/// `POST /tags/create-kit` takes either the HTML form or, with a JSON content type, an array of
+/// rows (the shared kit name then comes from `?kit=`). JSON callers get a 207 with one result per
+/// row.
+async fn create_kit(
+   extract::State(state): extract::State<AppState>,
+   CheckedQuery(query, query_unexpected): CheckedQuery<KitQuery>,
+   headers: HeaderMap,
+   body: axum::body::Bytes,
+) -> Result<Response, StatusCode> {
+   let wants_json = headers
+      .get(header::CONTENT_TYPE)
+      .and_then(|v| v.to_str().ok())
+      .is_some_and(|ct| ct.starts_with("application/json"));
+   let (kit, rows, unexpected) = if wants_json {
+      let malformed = |e: serde_json::Error| {
+         info!("Rejecting malformed kit JSON: {e}");
+         StatusCode::BAD_REQUEST
+      };
+      let value: serde_json::Value = serde_json::from_slice(&body).map_err(malformed)?;
+      let names: Vec<String> = value
+         .as_array()
+         .into_iter()
+         .flatten()
+         .filter_map(serde_json::Value::as_object)
+         .flat_map(|row| row.keys().cloned())
+         .collect();
+      let unexpected = Unexpected::check(names.iter().map(String::as_str), kit::ROW_FIELDS);
+      let rows: Vec<KitRow> = serde_json::from_value(value).map_err(malformed)?;
+      (query.kit.filter(|k| !k.trim().is_empty()), rows, unexpected)
+   } else {
+      let pairs: Vec<(String, String)> = url::form_urlencoded::parse(&body).into_owned().collect();
+      let unexpected = Unexpected::check(pairs.iter().map(|(name, _)| name.as_str()), kit::FORM_FIELDS);
+      let (kit, rows) = kit::rows_from_pairs(&pairs);
+      (kit, rows, unexpected)
+   };
+   let notion_enabled = state.client.is_some();
+
+   let unexpected = query_unexpected.merge(unexpected);
+   unexpected.log_unknown();
+   if let Some(error) = unexpected.error() {
+      info!("Rejecting kit: {error}");
+      if wants_json {
+         return Ok((StatusCode::BAD_REQUEST, format!("{}\n", error)).into_response());
+      }
+      let page = TagKitTemplate {
+         branding: &state.settings.load().branding,
+         kit: &kit,
+         rows: &kit::annotate(rows, Vec::new()),
+         notion_enabled,
+         error: Some(error),
+      };
+      let response = page.render().map_err(|e| {
+         warn!("Failed to render template: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      return Ok(as_html((StatusCode::UNPROCESSABLE_ENTITY, response).into_response()));
    }
-   let tag = tag.unwrap();
 
-   trace!(tag = ?tag, "Tag found, redirecting to '{}'", tag.target_url);
-   Ok(axum::response::Redirect::permanent(&tag.target_url).into_response())
+   let plan = kit::plan_kit(&rows, notion_enabled, state.settings.load().strict_idn);
+   let ids: Vec<TagUid> = plan.iter().flatten().map(|row| row.id).collect();
+   let _lock = match state.tag_locks.acquire(&ids, tag_lock::TIMEOUT).await {
+      Ok(lock) => lock,
+      Err(locked) => return Ok(locked.into_response()),
+   };
+   let outcome = match plan {
+      Ok(plan) => match insert_kit(&state, &kit, &plan).await {
+         Ok(Ok(())) => {
+            ids.iter().for_each(|id| state.reads.wrote(id));
+            info!(kit = ?kit, count = plan.len(), "Created kit");
+            Ok(plan)
+         }
+         Ok(Err(KitRefused::Conflicts(results))) => Err(results),
+         Ok(Err(KitRefused::Quota(exceeded))) => {
+            info!(kit = ?kit, count = plan.len(), "Not creating kit: {}", exceeded);
+            if wants_json {
+               return Ok(exceeded.into_response());
+            }
+            let page = TagKitTemplate {
+               branding: &state.settings.load().branding,
+               kit: &kit,
+               rows: &kit::annotate(rows, Vec::new()),
+               notion_enabled,
+               error: Some(format!("Nothing was created. {}.", exceeded)),
+            };
+            let response = page.render().map_err(|e| {
+               warn!("Failed to render template: {:?}", e);
+               StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            return Ok(as_html((StatusCode::UNPROCESSABLE_ENTITY, response).into_response()));
+         }
+         Err(e) => return write_failed("Failed to create kit in Postgres", e),
+      },
+      Err(results) => Err(results),
+   };
+
+   if wants_json {
+      let results: Vec<RowResult> = match &outcome {
+         Ok(plan) => plan.iter().map(|row| RowResult::created(&row.id)).collect(),
+         Err(results) if results.is_empty() => return Err(StatusCode::BAD_REQUEST),
+         Err(results) => results.clone(),
+      };
+      return Ok((StatusCode::MULTI_STATUS, axum::Json(results)).into_response());
+   }
+
+   let branding = &state.settings.load().branding;
+   let (status, rendered) = match outcome {
+      Ok(tags) => (
+         StatusCode::OK,
+         TagKitCreatedTemplate {
+            branding,
+            kit: &kit,
+            tags: &tags,
+         }
+         .render(),
+      ),
+      Err(results) => {
+         let rows = kit::annotate(rows, results);
+         let error = match rows.iter().any(|(_, error)| error.is_some()) {
+            true => "Nothing was created; fix the rows below and resubmit.",
+            false => "Enter at least one tag.",
+         };
+         (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            TagKitTemplate {
+               branding,
+               kit: &kit,
+               rows: &rows,
+               notion_enabled,
+               error: Some(error.to_string()),
+            }
+            .render(),
+         )
+      }
+   };
+   let response = rendered.map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html((status, response).into_response()))
+}
+
+#[derive(Template)]
+#[template(path = "tags_import.html")]
+struct TagsImportTemplate<'a> {
+   branding: &'a Branding,
+   jobs: &'a [import::Job],
+   max_megabytes: usize,
+}
+
+#[derive(Template)]
+#[template(path = "tags_import_job.html")]
+struct TagsImportJobTemplate<'a> {
+   branding: &'a Branding,
+   job: &'a import::Job,
+   /// The first of the report's lines.
+   rows: &'a [import::ReportRow],
+   refresh_secs: u32,
+}
+
+#[derive(Template)]
+#[template(path = "tags_import_refused.html")]
+struct TagsImportRefusedTemplate<'a> {
+   branding: &'a Branding,
+   /// A line for every row of the file.
+   rows: &'a [import::ReportRow],
+   failed: usize,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+   #[serde(default)]
+   strict: bool,
+}
+
+async fn import_page(
+   extract::State(db): extract::State<DbState>,
+   extract::State(SettingsState(settings)): extract::State<SettingsState>,
+) -> Result<Response, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch imports from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = db.pool.get().acquire().await.map_err(failed)?;
+   let jobs = import::recent(&mut conn).await.map_err(failed)?;
+   let page = TagsImportTemplate {
+      branding: &settings.load().branding,
+      jobs: &jobs,
+      max_megabytes: import::MAX_UPLOAD_BYTES / (1024 * 1024),
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+/// The upload's `file` field, skipping any others.
+async fn uploaded_file(
+   upload: &mut extract::Multipart,
+) -> Result<Option<axum::body::Bytes>, extract::multipart::MultipartError> {
+   while let Some(field) = upload.next_field().await? {
+      if field.name() == Some("file") {
+         return field.bytes().await.map(Some);
+      }
+   }
+   Ok(None)
+}
+
+/// `POST /tags/import`: a multipart upload with the CSV as its `file` field, as
+/// `curl -F file=@tags.csv` and the import page's script send it. Only its header and size are
+/// checked here, unless it's strict, when a file with a failing row is refused with a 422 and its
+/// report; rows are otherwise checked as the worker reaches them. Redirects to the job's progress.
+async fn start_import(
+   extract::State(db): extract::State<DbState>,
+   extract::State(SettingsState(settings)): extract::State<SettingsState>,
+   extract::State(notion): extract::State<NotionState>,
+   extract::Query(query): extract::Query<ImportQuery>,
+   mut upload: extract::Multipart,
+) -> Result<Response, StatusCode> {
+   let refused = |error: String| {
+      info!("Refusing import: {error}");
+      Ok((StatusCode::BAD_REQUEST, format!("{error}\n")).into_response())
+   };
+   let body = match uploaded_file(&mut upload).await {
+      Ok(Some(body)) => body,
+      Ok(None) => return refused("The upload has no file field".to_string()),
+      Err(e) => {
+         info!("Refusing import: {}", e.body_text());
+         return Ok((e.status(), format!("{}\n", e.body_text())).into_response());
+      }
+   };
+   let Ok(text) = std::str::from_utf8(&body) else {
+      return refused("The file must be UTF-8 text".to_string());
+   };
+   let records = match import::parse(text) {
+      Ok(records) => records,
+      Err(error) => return refused(error),
+   };
+   let refusal = match query.strict {
+      true => import::refusal(&records, notion.picker.is_some(), settings.load().strict_idn),
+      false => None,
+   };
+   if let Some(rows) = refusal {
+      let failed = rows.iter().filter(|row| row.outcome == "failed").count();
+      info!(rows = failed, "Refusing strict import");
+      let page = TagsImportRefusedTemplate {
+         branding: &settings.load().branding,
+         rows: &rows,
+         failed,
+      };
+      let response = page.render().map_err(|e| {
+         warn!("Failed to render template: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      return Ok(as_html((StatusCode::UNPROCESSABLE_ENTITY, response).into_response()));
+   }
+   let rows = records.len();
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to store import in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = db.pool.get().acquire().await.map_err(failed)?;
+   let id = import::create(&mut conn, text, rows as i32, query.strict)
+      .await
+      .map_err(failed)?;
+   info!(job = id, rows, "Queued an import of tags");
+   Ok(axum::response::Redirect::to(&format!("/tags/import/{}", id)).into_response())
+}
+
+async fn fetch_import(state: &AppState, id: i64) -> Result<import::Job, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch import {id} from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = state.pool.get().acquire().await.map_err(failed)?;
+   import::load(&mut conn, id)
+      .await
+      .map_err(failed)?
+      .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// `GET /tags/import/{id}`: reloads itself until the job is finished, listing the rows reached so
+/// far and what became of them.
+async fn import_job_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<i64>,
+) -> Result<Response, StatusCode> {
+   let job = fetch_import(&state, id).await?;
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch import {id}'s report from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = state.pool.get().acquire().await.map_err(failed)?;
+   let rows = import::report_rows(&mut conn, id, Some(import::SHOWN_ROWS))
+      .await
+      .map_err(failed)?;
+   let page = TagsImportJobTemplate {
+      branding: &state.settings.load().branding,
+      job: &job,
+      rows: &rows,
+      refresh_secs: import::REFRESH_SECS,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+/// `GET /tags/import/{id}/report.csv`: every row reached so far, and what became of it.
+async fn import_report(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<i64>,
+) -> Result<Response, StatusCode> {
+   fetch_import(&state, id).await?;
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch import {id}'s report from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = state.pool.get().acquire().await.map_err(failed)?;
+   let rows = import::report_rows(&mut conn, id, None).await.map_err(failed)?;
+   Ok((
+      [
+         (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+         (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"import-{}-report.csv\"", id),
+         ),
+      ],
+      import::report_csv(&rows),
+   )
+      .into_response())
+}
+
+fn client_ip(headers: &HeaderMap, remote: SocketAddr) -> IpAddr {
+   headers
+      .get("x-forwarded-for")
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.split(',').next())
+      .and_then(|v| v.trim().parse().ok())
+      .unwrap_or(remote.ip())
+}
+
+#[derive(Clone)]
+struct Tap {
+   id: TagUid,
+   /// When the scan was answered, which may be well before the tap is recorded; see `stats`.
+   at: chrono::DateTime<chrono::Utc>,
+   tap_count: Option<i32>,
+   /// `None` for a client that asked not to be tracked; only ever set through `Preference::keep`.
+   detail: Option<TapDetail>,
+   lang: Option<LanguageTag>,
+   served_permanent: bool,
+   /// False for hits without the tag's count token; those are only tallied as uncounted.
+   counted: bool,
+   during_maintenance: bool,
+   served: taps::Served,
+   /// Only with `TWAG_REDACT_TAG_IDS`; see `redact`.
+   tag_key: Option<String>,
+   /// Set when the tap was answered with the arrival page; see `arrival`.
+   arrival_nonce: Option<String>,
+}
+
+/// What a tap records about who made it, beyond that they did; none of it is kept for a client
+/// that asked not to be tracked. See `privacy`.
+#[derive(Clone, Default)]
+struct TapDetail {
+   fingerprint: Option<u64>,
+   /// Only looked up with `TWAG_GEOIP_DB` set.
+   country: Option<String>,
+   /// `None` for a scan of the tag itself; see `taps::CHANNEL_SHORTLINK`.
+   channel: Option<&'static str>,
+}
+
+/// Records taps that were deferred while Postgres was unreachable, once it's back. They're counted
+/// on the day they're recorded, though their events keep when they were answered and they can't
+/// move the tag's aggregates back; see `stats`.
+fn spawn_tap_flusher(pool: ScalingPool, failover: Failover) {
+   tokio::spawn(async move {
+      let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
+      loop {
+         interval.tick().await;
+         let mut flushed = 0;
+         while let Some(tap) = failover.next_pending() {
+            match record_daily_tap(pool.clone(), tap.clone()).await {
+               Ok(()) => flushed += 1,
+               Err(e) if failover::is_unavailable(&e) => {
+                  failover.requeue(tap);
+                  break;
+               }
+               Err(e) => warn!(tag_id = %tap.id, "Failed to record deferred tap: {:?}", e),
+            }
+         }
+         if flushed > 0 {
+            info!(flushed, remaining = failover.pending_len(), "Recorded deferred taps");
+         }
+      }
+   });
+}
+
+#[derive(Template)]
+#[template(path = "unavailable.html")]
+struct UnavailableTemplate<'a> {
+   branding: &'a Branding,
+}
+
+/// The response to a write that failed: what the broken constraint means, if it's one
+/// `constraints` knows, and otherwise a 500.
+fn write_failed(context: &str, e: sqlx::Error) -> Result<Response, StatusCode> {
+   match constraints::violation(&e) {
+      Some(violation) => {
+         info!(constraint = violation.constraint, "{context}: {}", violation.message);
+         Ok(violation.into_response())
+      }
+      None => {
+         warn!("{context}: {:?}", e);
+         Err(StatusCode::INTERNAL_SERVER_ERROR)
+      }
+   }
+}
+
+/// Where a scan of an unknown tag goes: creating it, keeping the scan's counter.
+fn create_redirect(id: &TagUid, tap_count: Option<i32>) -> Response {
+   let create_url = tap_count
+      .map(|tap_count| format!("/tag/create?id={id}&tap_count={:06X}", tap_count))
+      .unwrap_or_else(|| format!("/tag/create?id={id}"));
+   axum::response::Redirect::temporary(&create_url).into_response()
+}
+
+/// The page a disabled or archived tag answers with, never cached, so that enabling or restoring
+/// the tag takes effect on the next scan.
+fn retired_page(settings: &SharedSettings) -> Result<Response, StatusCode> {
+   let page = TagDisabledTemplate {
+      branding: &settings.load().branding,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(
+      (StatusCode::GONE, [(header::CACHE_CONTROL, "no-store")], response).into_response(),
+   ))
+}
+
+fn unavailable_page(settings: &SharedSettings) -> Result<Response, StatusCode> {
+   let page = UnavailableTemplate {
+      branding: &settings.load().branding,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   let retry_after = failover::RETRY_AFTER_SECS.to_string();
+   Ok(as_html(
+      (
+         StatusCode::SERVICE_UNAVAILABLE,
+         [(header::RETRY_AFTER, retry_after)],
+         response,
+      )
+         .into_response(),
+   ))
+}
+
+async fn record_daily_tap(pool: ScalingPool, tap: Tap) -> Result<(), sqlx::Error> {
+   let Tap {
+      id,
+      at,
+      tap_count,
+      detail,
+      lang,
+      served_permanent,
+      counted,
+      during_maintenance,
+      served,
+      tag_key,
+      arrival_nonce,
+   } = tap;
+   let privacy_limited = detail.is_none();
+   let TapDetail {
+      fingerprint,
+      country,
+      channel,
+   } = detail.unwrap_or_default();
+   let mut tx = pool.get().begin().await?;
+
+   sqlx::query!(
+      r#"INSERT INTO twag_tap_events
+            (tag_id, tap_count, lang, counted, resolution_path, served_target_url, response_status, country,
+             channel, tag_key, arrival_nonce, arrival_confirmed, tapped_at, privacy_limited)
+         VALUES ($1::tag_uid, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, CASE WHEN $11 IS NOT NULL THEN false END,
+            $12::text::timestamptz, $13)"#,
+      id as TagUid,
+      tap_count,
+      lang.as_ref().map(|lang| lang.to_string()),
+      counted,
+      served.path.as_str(),
+      served.target_url,
+      served.status as i16,
+      country,
+      channel,
+      tag_key,
+      arrival_nonce,
+      at.to_rfc3339(),
+      privacy_limited,
+   )
+   .execute(&mut *tx)
+   .await?;
+
+   let batch = stats::Batch::of(&[stats::TapRecord {
+      at,
+      tap_count,
+      counted,
+      served_permanent,
+   }]);
+   if !counted {
+      sqlx::query!(
+         r#"INSERT INTO twag_tag_daily (tag_id, day, uncounted)
+            VALUES ($1::tag_uid, (current_timestamp AT TIME ZONE 'UTC')::date, 1)
+            ON CONFLICT (tag_id, day) DO UPDATE SET uncounted = twag_tag_daily.uncounted + 1"#,
+         id as TagUid,
+      )
+      .execute(&mut *tx)
+      .await?;
+      // The client may still cache a permanent redirect, counted or not.
+      stats::write(&mut tx, &id, &batch).await?;
+      return tx.commit().await;
+   }
+
+   // The upsert takes the row lock, so concurrent taps serialize on the sketch read-modify-write.
+   let stored = sqlx::query_scalar!(
+      r#"INSERT INTO twag_tag_daily (tag_id, day, taps, during_maintenance)
+         VALUES ($1::tag_uid, (current_timestamp AT TIME ZONE 'UTC')::date, 1, CASE WHEN $2 THEN 1 ELSE 0 END)
+         ON CONFLICT (tag_id, day) DO UPDATE SET
+            taps = twag_tag_daily.taps + 1,
+            during_maintenance = twag_tag_daily.during_maintenance + excluded.during_maintenance
+         RETURNING visitor_sketch"#,
+      id as TagUid,
+      during_maintenance,
+   )
+   .fetch_one(&mut *tx)
+   .await?;
+
+   if let Some(fingerprint) = fingerprint {
+      let mut sketch = stored.and_then(Sketch::from_bytes).unwrap_or_default();
+      sketch.insert(fingerprint);
+      sqlx::query!(
+         r#"UPDATE twag_tag_daily SET visitor_sketch = $2
+            WHERE tag_id = $1 AND day = (current_timestamp AT TIME ZONE 'UTC')::date"#,
+         id as TagUid,
+         sketch.as_bytes(),
+      )
+      .execute(&mut *tx)
+      .await?;
+   }
+
+   stats::write(&mut tx, &id, &batch).await?;
+
+   if let Some(lang) = lang {
+      sqlx::query!(
+         r#"INSERT INTO twag_tag_daily_lang (tag_id, day, lang, taps)
+            VALUES ($1::tag_uid, (current_timestamp AT TIME ZONE 'UTC')::date, $2::lang_tag, 1)
+            ON CONFLICT (tag_id, day, lang) DO UPDATE SET taps = twag_tag_daily_lang.taps + 1"#,
+         id as TagUid,
+         lang as LanguageTag,
+      )
+      .execute(&mut *tx)
+      .await?;
+   }
+
+   tx.commit().await
+}
+
+#[derive(Deserialize)]
+struct TagTapQuery {
+   lang: Option<String>,
+   ct: Option<String>,
+}
+
+/// Adds the `Server-Timing` header when enabled, and traces each phase either way.
+fn with_server_timing(settings: &SharedSettings, timings: &Timings, mut response: Response) -> Response {
+   for (phase, duration) in timings.phases() {
+      trace!(phase, ?duration, "Request phase timing");
+   }
+   if settings.load().server_timing {
+      response.headers_mut().insert(timing::SERVER_TIMING, timings.finish());
+   }
+   response
+}
+
+async fn get_tag_by_id(
+   extract::State(db): extract::State<DbState>,
+   extract::State(cache): extract::State<CacheState>,
+   extract::State(scan): extract::State<ScanState>,
+   extract::ConnectInfo(remote): extract::ConnectInfo<SocketAddr>,
+   headers: HeaderMap,
+   extract::Path(param): extract::Path<String>,
+   extract::Query(query): extract::Query<TagTapQuery>,
+) -> Result<Response, StatusCode> {
+   let mut timings = Timings::new();
+   let response = resolve_tag(
+      &db,
+      &cache,
+      &scan,
+      remote,
+      headers,
+      param,
+      query,
+      None,
+      false,
+      &mut timings,
+   )
+   .await?;
+   Ok(with_server_timing(&scan.settings, &timings, response))
+}
+
+/// `GET /t/{code}`: a tag's short link, or one it had before regenerating, answered as a scan of
+/// the tag and recorded with the `shortlink` channel.
+async fn short_link(
+   extract::State(db): extract::State<DbState>,
+   extract::State(cache): extract::State<CacheState>,
+   extract::State(scan): extract::State<ScanState>,
+   extract::ConnectInfo(remote): extract::ConnectInfo<SocketAddr>,
+   headers: HeaderMap,
+   extract::Path(code): extract::Path<String>,
+   extract::Query(query): extract::Query<TagTapQuery>,
+) -> Result<Response, StatusCode> {
+   if !short_code::is_plausible(&code) {
+      return Err(StatusCode::NOT_FOUND);
+   }
+   let mut timings = Timings::new();
+   let lookup = sqlx::query_scalar!(
+      r#"SELECT tag_id AS "tag_id: TagUid" FROM twag_tag_aliases WHERE alias = $1"#,
+      code
+   )
+   .fetch_optional(&db.reads.any().get());
+   let id = timings
+      .time("db", lookup)
+      .await
+      .map_err(|e| {
+         warn!("Failed to look up short code '{code}': {:?}", e);
+         StatusCode::SERVICE_UNAVAILABLE
+      })?
+      .ok_or(StatusCode::NOT_FOUND)?;
+   let response = resolve_tag(
+      &db,
+      &cache,
+      &scan,
+      remote,
+      headers,
+      id.to_string(),
+      query,
+      Some(taps::CHANNEL_SHORTLINK),
+      false,
+      &mut timings,
+   )
+   .await?;
+   Ok(with_server_timing(&scan.settings, &timings, response))
+}
+
+/// Gives the tag a new short code. Any earlier code keeps working, as an alias.
+async fn mint_short_code(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+   let _lock = match state.tag_locks.acquire(&[id], tag_lock::TIMEOUT).await {
+      Ok(lock) => lock,
+      Err(locked) => return Ok(locked.into_response()),
+   };
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to mint a short code for tag '{id}': {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut tx = state.pool.get().begin().await.map_err(failed)?;
+   let exists = sqlx::query_scalar!(
+      r#"SELECT EXISTS (SELECT 1 FROM twag_tags WHERE id = $1 FOR UPDATE) AS "exists!""#,
+      id as TagUid
+   )
+   .fetch_one(&mut *tx)
+   .await
+   .map_err(failed)?;
+   if !exists {
+      return Err(StatusCode::NOT_FOUND);
+   }
+
+   let quotas = state.settings.load().quotas;
+   if let Err(exceeded) = quota::reserve(&mut tx, &quotas, quota::Resource::Aliases, 1)
+      .await
+      .map_err(failed)?
+   {
+      info!(tag_id = %id, "Not minting a short code: {}", exceeded);
+      return Ok(exceeded.into_response());
+   }
+
+   // Claimed with ON CONFLICT rather than by catching the unique violation, which would abort
+   // the transaction
+   let mut rng = short_code::CodeRng::from_entropy();
+   let mut claimed = None;
+   for code in short_code::candidates(&mut rng) {
+      let inserted = sqlx::query!(
+         "INSERT INTO twag_tag_aliases (alias, tag_id) VALUES ($1, $2::tag_uid) ON CONFLICT DO NOTHING",
+         code,
+         id as TagUid,
+      )
+      .execute(&mut *tx)
+      .await
+      .map_err(failed)?;
+      if inserted.rows_affected() == 1 {
+         claimed = Some(code);
+         break;
+      }
+      info!(tag_id = %id, code = %code, "Short code taken, retrying");
+   }
+   let Some(code) = claimed else {
+      warn!(tag_id = %id, "Every short code candidate was taken");
+      return Err(StatusCode::SERVICE_UNAVAILABLE);
+   };
+   sqlx::query!("UPDATE twag_tags SET short_code = $2 WHERE id = $1", id as TagUid, code)
+      .execute(&mut *tx)
+      .await
+      .map_err(failed)?;
+   tx.commit().await.map_err(failed)?;
+   state.reads.wrote(&id);
+   info!(tag_id = %id, code = %code, "Minted short code");
+
+   let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+   if vcard::prefers_html(accept) {
+      return Ok(axum::response::Redirect::to(&format!("/tag/{}/stats", id)).into_response());
+   }
+   Ok(format!("{}/t/{}\n", public_origin(&state, &headers), code).into_response())
+}
+
+/// The scan path's queries, for `/admin/db-report`; see `db_report`.
+fn planned_queries() -> Vec<db_report::Planned> {
+   use db_report::Planned;
+   const ID: &str = "'055B88A23C1250'";
+   vec![
+      Planned::checked(
+         "Redirect lookup",
+         r#"SELECT t.*,
+            ARRAY(SELECT lang::text FROM twag_tag_targets_i18n WHERE tag_id = t.id ORDER BY lang) AS "langs!",
+            ARRAY(SELECT target_url FROM twag_tag_targets_i18n WHERE tag_id = t.id ORDER BY lang) AS "lang_targets!",
+            ARRAY(SELECT array_to_string(countries, ',') FROM twag_tag_targets_geo WHERE tag_id = t.id ORDER BY id)
+               AS "geo_countries!",
+            ARRAY(SELECT target_url FROM twag_tag_targets_geo WHERE tag_id = t.id ORDER BY id) AS "geo_targets!",
+            ARRAY(SELECT extract(epoch FROM s.starts_at)::bigint FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_starts!",
+            ARRAY(SELECT extract(epoch FROM s.ends_at)::bigint FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_ends!",
+            ARRAY(SELECT s.target_url FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_targets!",
+            v.name AS "vcard_name?", v.org AS vcard_org, v.phone AS vcard_phone, v.email AS vcard_email,
+            v.url AS vcard_url, v.note AS vcard_note,
+            extract(epoch FROM t.served_permanent_until)::bigint AS served_permanent_epoch,
+            extract(epoch FROM t.expires_at)::bigint AS expires_at_epoch,
+            extract(epoch FROM t.activates_at)::bigint AS activates_at_epoch
+         FROM twag_tags t LEFT JOIN twag_tag_vcards v ON v.tag_id = t.id
+         WHERE t.id = $1"#,
+         &[ID],
+      ),
+      Planned::checked(
+         "Tap event",
+         r#"INSERT INTO twag_tap_events
+            (tag_id, tap_count, lang, counted, resolution_path, served_target_url, response_status, country,
+             channel, tag_key, arrival_nonce, arrival_confirmed, tapped_at, privacy_limited)
+         VALUES ($1::tag_uid, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, CASE WHEN $11 IS NOT NULL THEN false END,
+            $12::text::timestamptz, $13)"#,
+         &[
+            ID,
+            "15",
+            "'en'",
+            "true",
+            "'redirect'",
+            "'https://example.com/'",
+            "307",
+            "'DE'",
+            "NULL",
+            "NULL",
+            "NULL::text",
+            "'2026-10-16T12:00:00+00:00'",
+            "false",
+         ],
+      ),
+      Planned::checked(
+         "Daily rollup, counted tap",
+         r#"INSERT INTO twag_tag_daily (tag_id, day, taps, during_maintenance)
+         VALUES ($1::tag_uid, (current_timestamp AT TIME ZONE 'UTC')::date, 1, CASE WHEN $2 THEN 1 ELSE 0 END)
+         ON CONFLICT (tag_id, day) DO UPDATE SET
+            taps = twag_tag_daily.taps + 1,
+            during_maintenance = twag_tag_daily.during_maintenance + excluded.during_maintenance
+         RETURNING visitor_sketch"#,
+         &[ID, "false"],
+      ),
+      Planned::checked(
+         "Daily rollup, uncounted tap",
+         r#"INSERT INTO twag_tag_daily (tag_id, day, uncounted)
+            VALUES ($1::tag_uid, (current_timestamp AT TIME ZONE 'UTC')::date, 1)
+            ON CONFLICT (tag_id, day) DO UPDATE SET uncounted = twag_tag_daily.uncounted + 1"#,
+         &[ID],
+      ),
+      Planned::checked(
+         "Daily stats",
+         r#"SELECT to_char(day, 'YYYY-MM-DD') AS "day!", taps, uncounted, visitor_sketch
+         FROM twag_tag_daily WHERE tag_id = $1 ORDER BY day DESC LIMIT 30"#,
+         &[ID],
+      ),
+   ]
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn resolve_tag(
+   db: &DbState,
+   cache: &CacheState,
+   scan: &ScanState,
+   remote: SocketAddr,
+   headers: HeaderMap,
+   param: String,
+   query: TagTapQuery,
+   channel: Option<&'static str>,
+   age_confirmed: bool,
+   timings: &mut Timings,
+) -> Result<Response, StatusCode> {
+   let slug: TagSlug = param.parse().map_err(|e| {
+      warn!("Invalid tag ID format: {}", e);
+      StatusCode::BAD_REQUEST
+   })?;
+   let TagSlug { id, tap_count, vcf } = slug;
+   // At most six hex digits, so this always fits
+   let tap_count = tap_count.map(|c| c as i32);
+
+   let preference = Preference::of(scan.settings.load().privacy_signals, &headers);
+   // Not even worked out for a client that asked not to be tracked, so stateful tags don't keep it
+   // as the actor either
+   let fingerprint = scan
+      .visitor_hasher
+      .as_ref()
+      .filter(|_| !preference.limited())
+      .map(|hasher| {
+         let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+         hasher.fingerprint(client_ip(&headers, remote), user_agent, chrono::Utc::now().date_naive())
+      });
+
+   if cache.negative_cache.is_missing(&id, std::time::Instant::now()) {
+      timings.describe("cache", "unknown id");
+      return Ok(create_redirect(&id, tap_count));
+   }
+   // Taken before the lookup, so a tag created while it runs can't be cached as missing
+   let ticket = cache.negative_cache.ticket();
+   let lookup = async {
+      let mut conn = timings.time("pool", db.reads.for_tag(&id).acquire()).await?;
+      let query = sqlx::query!(
+         r#"SELECT t.*,
+            ARRAY(SELECT lang::text FROM twag_tag_targets_i18n WHERE tag_id = t.id ORDER BY lang) AS "langs!",
+            ARRAY(SELECT target_url FROM twag_tag_targets_i18n WHERE tag_id = t.id ORDER BY lang) AS "lang_targets!",
+            ARRAY(SELECT array_to_string(countries, ',') FROM twag_tag_targets_geo WHERE tag_id = t.id ORDER BY id)
+               AS "geo_countries!",
+            ARRAY(SELECT target_url FROM twag_tag_targets_geo WHERE tag_id = t.id ORDER BY id) AS "geo_targets!",
+            ARRAY(SELECT extract(epoch FROM s.starts_at)::bigint FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_starts!",
+            ARRAY(SELECT extract(epoch FROM s.ends_at)::bigint FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_ends!",
+            ARRAY(SELECT s.target_url FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_targets!",
+            v.name AS "vcard_name?", v.org AS vcard_org, v.phone AS vcard_phone, v.email AS vcard_email,
+            v.url AS vcard_url, v.note AS vcard_note,
+            extract(epoch FROM t.served_permanent_until)::bigint AS served_permanent_epoch,
+            extract(epoch FROM t.expires_at)::bigint AS expires_at_epoch,
+            extract(epoch FROM t.activates_at)::bigint AS activates_at_epoch
+         FROM twag_tags t LEFT JOIN twag_tag_vcards v ON v.tag_id = t.id
+         WHERE t.id = $1"#,
+         id as TagUid
+      )
+      .fetch_optional(&mut *conn);
+      let tag = timings.time("db", query).await?;
+      Ok::<_, sqlx::Error>((conn, tag))
+   };
+   let (mut conn, tag) = match cache.failover.resolve(&id, lookup).await {
+      Ok(Resolved::Fresh(found)) => found,
+      Ok(Resolved::Stale(cached)) => {
+         warn!(tag_id = %id, "Postgres unreachable, serving a cached redirect");
+         cache.failover.defer_tap(Tap {
+            id,
+            at: chrono::Utc::now(),
+            tap_count,
+            detail: preference.keep(|| TapDetail {
+               fingerprint,
+               country: None,
+               channel,
+            }),
+            lang: None,
+            served_permanent: false,
+            counted: count_token::should_count(cached.count_token.as_deref(), query.ct.as_deref()),
+            during_maintenance: false,
+            served: taps::served_stale(),
+            tag_key: scan.redaction.as_ref().map(|redaction| redaction.tag_key(&id)),
+            arrival_nonce: None,
+         });
+         timings.describe("cache", "stale");
+         let redirect = redirect_to_target(&scan.settings, &id, &cached.target_url, StatusCode::TEMPORARY_REDIRECT)?;
+         return Ok((
+            [
+               (failover::X_TWAG_STALE, failover::STALE),
+               (header::CACHE_CONTROL, header::HeaderValue::from_static("no-store")),
+            ],
+            redirect,
+         )
+            .into_response());
+      }
+      Ok(Resolved::Unavailable) => {
+         warn!(tag_id = %id, "Postgres unreachable and tag not cached");
+         return unavailable_page(&scan.settings);
+      }
+      Err(e) => {
+         warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+         return Err(StatusCode::INTERNAL_SERVER_ERROR);
+      }
+   };
+
+   if tag.is_none() && scan.settings.load().lookup_normalize_retry {
+      let retry = sqlx::query_scalar!(
+         "SELECT target_url FROM twag_tags WHERE upper(id::text) = $1 LIMIT 1",
+         id.to_string()
+      )
+      .fetch_optional(&mut *conn);
+      let misfiled = timings.time("db", retry).await.map_err(|e| {
+         warn!("Failed to retry tag '{id}' lookup in Postgres: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      if let Some(target_url) = misfiled {
+         warn!(tag_id = %id, "Tag stored with a non-normalized id; run the id audit");
+         return redirect_to_target(&scan.settings, &id, &target_url, StatusCode::TEMPORARY_REDIRECT);
+      }
+   }
+
+   // Answered as retired rather than as unknown, so a stray scan can't lead to the id being created
+   // anew
+   if tag.is_none() {
+      let archived = sqlx::query_scalar!(
+         r#"SELECT EXISTS (SELECT 1 FROM twag_tags_archive WHERE id = $1) AS "archived!""#,
+         id as TagUid
+      )
+      .fetch_one(&mut *conn);
+      let archived = timings.time("db", archived).await.map_err(|e| {
+         warn!("Failed to look up tag '{id}' in the archive: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      if archived {
+         info!(tag_id = %id, "Tag archived");
+         return retired_page(&scan.settings);
+      }
+   }
+
+   let settings = scan.settings.load();
+   let stored = tag.as_ref().map(|tag| {
+      let mut stored = StoredTag::new(tag.target_url.clone());
+      stored.deleted = tag.deleted_at.is_some();
+      stored.expires_at = tag
+         .expires_at_epoch
+         .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0));
+      stored.activates_at = tag
+         .activates_at_epoch
+         .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0));
+      stored.redirect_kind = tag.redirect_kind.parse().unwrap_or_default();
+      stored.served_permanent_until = tag.served_permanent_epoch;
+      stored.last_seen_tap_count = tag.last_seen_tap_count;
+      stored.maintenance = tag.maintenance;
+      stored.stateful = tag.stateful;
+      stored.contact = tag.vcard_name.is_some();
+      stored.age_gate = tag.age_gate;
+      stored.age_gate_text = tag.age_gate_text.clone();
+      stored.review = reprogram::Review {
+         state: tag.review_state.parse().unwrap_or(ReviewState::Ok),
+         since_drop: tag.review_tap_count,
+      };
+      stored.quarantine_on_review = tag.quarantine_on_review;
+      stored.lang_targets = tag
+         .langs
+         .iter()
+         .zip(&tag.lang_targets)
+         .filter_map(|(lang, target_url)| Some((LanguageTag::new(lang).ok()?, target_url.clone())))
+         .collect();
+      stored.geo_targets = tag
+         .geo_countries
+         .iter()
+         .cloned()
+         .zip(tag.geo_targets.iter().cloned())
+         .collect();
+      stored.kit_schedule = schedule::from_columns(&tag.schedule_starts, &tag.schedule_ends, &tag.schedule_targets);
+      stored.ignore_kit_schedule = tag.ignore_kit_schedule;
+      stored.enabled = tag.enabled;
+      stored
+   });
+
+   let now = chrono::Utc::now();
+   let cookies: Vec<&str> = headers
+      .get_all(header::COOKIE)
+      .iter()
+      .filter_map(|v| v.to_str().ok())
+      .collect();
+   let country = scan
+      .geoip
+      .as_ref()
+      .and_then(|geoip| geoip.country(client_ip(&headers, remote)));
+   let mut ctx = ResolveContext::new(now);
+   ctx.lang = query.lang.as_deref();
+   ctx.accept_language = headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok());
+   ctx.country = country.as_deref();
+   ctx.cookies = &cookies;
+   ctx.age_confirmed = age_confirmed
+      || (stored.as_ref().is_some_and(|stored| stored.age_gate)
+         && age_gate::confirmed(scan.cookie_key.as_ref(), cookies.iter().copied(), now.timestamp()));
+   ctx.policy.maintenance_target_url = settings.maintenance_target_url.as_deref();
+   ctx.policy.flush_stale_redirects = settings.flush_stale_redirects;
+   ctx.policy.reprogram = settings.reprogram;
+   let mut decision = resolve::decide(stored.as_ref(), &slug, &ctx);
+
+   let missing = tag.is_none();
+   let tag = match (&decision.outcome, tag) {
+      (ResolveOutcome::NotFound { .. }, _) | (_, None) => {
+         cache.failover.forget(&id);
+         if missing {
+            cache.negative_cache.record_miss(id, ticket, std::time::Instant::now());
+         }
+         info!("Tag '{id}' not found, redirecting to /tag/create");
+         return Ok(create_redirect(&id, tap_count));
+      }
+      (ResolveOutcome::Expired, Some(tag)) if tag.deleted_at.is_some() => {
+         cache.failover.forget(&id);
+         info!(tag_id = %id, "Tag deleted");
+         return Ok((StatusCode::GONE, "This tag is no longer in use.\n").into_response());
+      }
+      (ResolveOutcome::Expired, _) => {
+         cache.failover.forget(&id);
+         info!(tag_id = %id, "Tag expired");
+         // Never cached, so moving the expiry later takes effect on the next scan
+         if let Some(url) = &settings.expired_target_url {
+            return Ok((
+               [(header::CACHE_CONTROL, "no-store")],
+               axum::response::Redirect::temporary(url),
+            )
+               .into_response());
+         }
+         let page = TagExpiredTemplate {
+            branding: &settings.branding,
+         };
+         let response = page.render().map_err(|e| {
+            warn!("Failed to render template: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+         return Ok(as_html(
+            (StatusCode::GONE, [(header::CACHE_CONTROL, "no-store")], response).into_response(),
+         ));
+      }
+      (ResolveOutcome::Disabled, _) => {
+         cache.failover.forget(&id);
+         info!(tag_id = %id, "Tag disabled");
+         return retired_page(&scan.settings);
+      }
+      (_, Some(tag)) => tag,
+   };
+
+   let review = stored.as_ref().map(|stored| stored.review);
+   if let (Some(step), Some(review)) = (decision.review, review) {
+      if step.review != review {
+         if step.audit.is_some() {
+            warn!(tag_id = %id, ?tap_count, last_seen = ?tag.last_seen_tap_count, "Counter dropped back, tag needs review");
+         }
+         let pool = db.pool.clone();
+         tokio::spawn(async move {
+            let recorded = async {
+               reprogram::record(
+                  &mut *pool.get().acquire().await?,
+                  id,
+                  review.state,
+                  &step,
+                  reprogram::ACTOR_SCAN,
+               )
+               .await
+            };
+            if let Err(e) = recorded.await {
+               warn!(tag_id = %id, "Failed to record review state: {:?}", e);
+            }
+         });
+      }
+   }
+
+   if let ResolveOutcome::Gated {
+      kind: Gate::Age { text },
+   } = &decision.outcome
+   {
+      cache.failover.forget(&id);
+      info!(tag_id = %id, "Tag is age-gated, asking for confirmation");
+      // Carries the scan's own parameters through to the confirmation
+      let mut carried = url::form_urlencoded::Serializer::new(String::new());
+      for (name, value) in [("lang", &query.lang), ("ct", &query.ct)] {
+         if let Some(value) = value {
+            carried.append_pair(name, value);
+         }
+      }
+      let carried = carried.finish();
+      let action = if carried.is_empty() {
+         format!("/tag/{}/go", param)
+      } else {
+         format!("/tag/{}/go?{}", param, carried)
+      };
+      let page = TagAgeGateTemplate {
+         branding: &settings.branding,
+         text: text.as_deref().unwrap_or(age_gate::DEFAULT_TEXT),
+         action: &action,
+      };
+      let response = page.render().map_err(|e| {
+         warn!("Failed to render template: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      return Ok(as_html(
+         ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+      ));
+   }
+   if decision.outcome == ResolveOutcome::NoContact {
+      return Err(StatusCode::NOT_FOUND);
+   }
+
+   // Probed only with somewhere else to send the scan, so other tags are redirected as quickly as
+   // ever
+   let probed = match &decision.outcome {
+      ResolveOutcome::Redirect { url, .. } if tag.check_target && !decision.during_maintenance => {
+         tag.fallback_url.clone().map(|fallback_url| (url.clone(), fallback_url))
+      }
+      _ => None,
+   };
+   if let Some((url, fallback_url)) = probed {
+      if timings
+         .time(
+            "probe",
+            fallback::target_down(&scan.prober, &url, &settings.fetch_policy),
+         )
+         .await
+      {
+         info!(tag_id = %id, "Target is down, redirecting to the fallback");
+         decision.fall_back(&fallback_url);
+      }
+   }
+
+   if decision.cacheable {
+      let cached = CachedRedirect {
+         target_url: tag.target_url.clone(),
+         count_token: tag.count_token.clone(),
+      };
+      cache.failover.remember(id, cached);
+   } else {
+      cache.failover.forget(&id);
+   }
+
+   // Only redirects to the tag's own targets are worth confirming, and only ones that could be
+   // sent as a `Location` at all
+   let arrival = match (&decision.outcome, &scan.cookie_key) {
+      (ResolveOutcome::Redirect { url, .. }, Some(key))
+         if tag.confirm_arrival
+            && !decision.during_maintenance
+            && target_url::location(url, settings.max_location_len).is_ok() =>
+      {
+         let nonce = arrival::nonce();
+         let token = arrival::token(key, &id, &nonce, now.timestamp());
+         Some((nonce, token))
+      }
+      _ => None,
+   };
+
+   let mut recorded = None;
+   if let Some(served) = decision.served.clone() {
+      let pool = db.pool.clone();
+      let mqtt = scan.mqtt.clone();
+      let webhook_config = scan.webhook.clone();
+      let tap = Tap {
+         id,
+         at: now,
+         tap_count,
+         detail: preference.keep(|| TapDetail {
+            fingerprint,
+            country: country.clone(),
+            channel,
+         }),
+         lang: decision.lang.clone(),
+         served_permanent: decision.served_permanent && arrival.is_none(),
+         counted: count_token::should_count(tag.count_token.as_deref(), query.ct.as_deref()),
+         during_maintenance: decision.during_maintenance,
+         served,
+         tag_key: scan.redaction.as_ref().map(|redaction| redaction.tag_key(&id)),
+         arrival_nonce: arrival.as_ref().map(|(nonce, _)| nonce.clone()),
+      };
+      let event = ((mqtt.is_some() || webhook_config.is_some()) && tap.counted).then(|| TapEvent {
+         tag_id: id,
+         tap_count,
+         lang: decision.lang.clone(),
+         during_maintenance: decision.during_maintenance,
+         at: now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+      });
+      let pending = cache.failover.clone();
+      let (done, was_recorded) = tokio::sync::oneshot::channel();
+      recorded = Some(was_recorded);
+      tokio::spawn(async move {
+         match record_daily_tap(pool.clone(), tap.clone()).await {
+            Err(e) if failover::is_unavailable(&e) => pending.defer_tap(tap),
+            Err(e) => warn!(tag_id = %id, "Failed to record tap: {:?}", e),
+            Ok(()) => {}
+         }
+         let _ = done.send(());
+         if let (Some(config), Some(event)) = (&webhook_config, &event) {
+            if let Err(e) = webhook::enqueue(&pool, config, webhook::EVENT_TAP, event).await {
+               warn!(tag_id = %id, "Failed to queue tap webhook: {:?}", e);
+            }
+         }
+         if let (Some(mqtt), Some(event)) = (mqtt, event) {
+            mqtt.publish_tap(&event);
+         }
+      });
+   }
+
+   if let (Some((_, token)), ResolveOutcome::Redirect { url, .. }) = (&arrival, &decision.outcome) {
+      // The beacon can land as soon as the page does, so the tap it confirms must be there first
+      if let Some(recorded) = recorded {
+         let _ = timings.time("db", recorded).await;
+      }
+      trace!(tag = ?tag, "Tag found, serving the arrival page for '{}'", url);
+      let beacon_url = format!("/tag/{}/arrived?t={}", id, token);
+      let page = TagArrivalTemplate {
+         branding: &settings.branding,
+         target_url: &target_url::normalize(url),
+         beacon_url: &beacon_url,
+      };
+      let response = page.render().map_err(|e| {
+         warn!("Failed to render template: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      return Ok(as_html(
+         ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+      ));
+   }
+
+   match decision.outcome {
+      ResolveOutcome::Gated { kind: Gate::Quarantine } => {
+         info!(tag_id = %id, "Tag quarantined pending review");
+         let page = TagQuarantineTemplate {
+            branding: &settings.branding,
+         };
+         let response = page.render().map_err(|e| {
+            warn!("Failed to render template: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+         Ok(as_html(
+            ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+         ))
+      }
+      ResolveOutcome::Redirect { url, .. } if decision.during_maintenance => {
+         info!(tag_id = %id, "Maintenance active, redirecting to the maintenance URL");
+         let redirect = [(header::LOCATION, url), (header::CACHE_CONTROL, "no-store".to_string())];
+         Ok((StatusCode::FOUND, redirect).into_response())
+      }
+      ResolveOutcome::Page {
+         page: Page::Maintenance,
+         ..
+      } => {
+         info!(tag_id = %id, "Tag in maintenance, serving the maintenance page");
+         let page = MaintenanceTemplate {
+            branding: &settings.branding,
+         };
+         let response = page.render().map_err(|e| {
+            warn!("Failed to render template: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+         Ok(as_html(
+            (
+               StatusCode::SERVICE_UNAVAILABLE,
+               [(header::CACHE_CONTROL, "no-store")],
+               response,
+            )
+               .into_response(),
+         ))
+      }
+      ResolveOutcome::Page {
+         page: Page::Stateful { url },
+         ..
+      } => {
+         let mirror = scan.notion_outbox && tag.notion_page_id.is_some();
+         let _lock = match scan.tag_locks.acquire(&[id], tag_lock::TIMEOUT).await {
+            Ok(lock) => lock,
+            Err(locked) => return Ok(locked.into_response()),
+         };
+         let (new_state, held_for) = timings
+            .time("db", scan_stateful_tag(&db.pool, id, fingerprint, mirror))
+            .await
+            .map_err(|e| {
+               warn!("Failed to record state transition for tag '{id}': {:?}", e);
+               StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+         db.reads.wrote(&id);
+         info!(tag_id = %id, state = %new_state, "Stateful tag scanned");
+         let page = TagStateTemplate {
+            branding: &settings.branding,
+            label: tag.label.as_deref(),
+            state: new_state,
+            held_for: held_for.map(checkout::humanize),
+            target_url: Some(url.as_str()).filter(|url| !url.is_empty()),
+         };
+         let response = page.render().map_err(|e| {
+            warn!("Failed to render template: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+         // Every scan must reach the server, or the state would stop flipping
+         Ok(as_html(
+            ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+         ))
+      }
+      ResolveOutcome::Page {
+         page: Page::Contact, ..
+      } => {
+         let Some(name) = tag.vcard_name.clone() else {
+            return Err(StatusCode::NOT_FOUND);
+         };
+         let contact = Contact {
+            name,
+            org: tag.vcard_org.clone(),
+            phone: tag.vcard_phone.clone(),
+            email: tag.vcard_email.clone(),
+            url: tag.vcard_url.clone(),
+            note: tag.vcard_note.clone(),
+         };
+         let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+         if !vcf && vcard::prefers_html(accept) {
+            let page = TagVcardTemplate {
+               branding: &settings.branding,
+               id: &id.to_string(),
+               contact: &contact,
+            };
+            let response = page.render().map_err(|e| {
+               warn!("Failed to render template: {:?}", e);
+               StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            return Ok(as_html(response.into_response()));
+         }
+
+         trace!(tag = ?tag, "Tag found, serving vCard");
+         let disposition = format!("attachment; filename=\"{}\"", contact.file_name());
+         Ok((
+            [
+               (header::CONTENT_TYPE, "text/vcard; charset=utf-8".to_string()),
+               (header::CONTENT_DISPOSITION, disposition),
+            ],
+            contact.to_vcard(),
+         )
+            .into_response())
+      }
+      ResolveOutcome::Page {
+         page: Page::Flush { url },
+         ..
+      } => {
+         info!(tag_id = %id, "Flushing a possibly cached permanent redirect");
+         let page = TagFlushTemplate {
+            branding: &settings.branding,
+            target_url: &url,
+         };
+         let response = page.render().map_err(|e| {
+            warn!("Failed to render template: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+         let set_cookie = stale_redirect::set_cookie(&id, tag.served_permanent_epoch.unwrap_or_default());
+         Ok(as_html(
+            (
+               [
+                  (
+                     header::HeaderName::from_static("clear-site-data"),
+                     "\"cache\"".to_string(),
+                  ),
+                  (header::SET_COOKIE, set_cookie),
+                  (header::CACHE_CONTROL, "no-store".to_string()),
+               ],
+               response,
+            )
+               .into_response(),
+         ))
+      }
+      ResolveOutcome::Page {
+         page: Page::Pending { activates_at },
+         ..
+      } => {
+         info!(tag_id = %id, %activates_at, "Tag not yet active");
+         let page = TagPendingTemplate {
+            branding: &settings.branding,
+            activates_at: &activates_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            datetime: &activates_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+         };
+         let response = page.render().map_err(|e| {
+            warn!("Failed to render template: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+         // Never cached, so the tag goes live on the first scan after it activates
+         Ok(as_html(
+            ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+         ))
+      }
+      ResolveOutcome::Redirect { url, status } => {
+         trace!(tag = ?tag, lang = ?decision.lang, "Tag found, redirecting to '{}'", url);
+         let status = StatusCode::from_u16(status).unwrap_or(StatusCode::TEMPORARY_REDIRECT);
+         let mut response = redirect_to_target(&scan.settings, &id, &url, status)?;
+         if decision.vary_language {
+            response
+               .headers_mut()
+               .insert(header::VARY, header::HeaderValue::from_static("accept-language"));
+         }
+         Ok(response)
+      }
+      outcome => {
+         warn!(tag_id = %id, ?outcome, "Resolved to an outcome the server can't answer");
+         Err(StatusCode::INTERNAL_SERVER_ERROR)
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "tag_link.html")]
+struct TagLinkTemplate<'a> {
+   branding: &'a Branding,
+   target_url: &'a str,
+}
+
+/// Redirects to a stored target URL, or serves a page linking to it when it can't be a
+/// `Location` header; see `target_url`.
+fn redirect_to_target(
+   settings: &SharedSettings,
+   id: &TagUid,
+   target_url: &str,
+   status: StatusCode,
+) -> Result<Response, StatusCode> {
+   let settings = settings.load();
+   match target_url::location(target_url, settings.max_location_len) {
+      Ok(location) => Ok((status, [(header::LOCATION, location)]).into_response()),
+      Err(problem) => {
+         warn!(tag_id = %id, "Target URL is {}, serving a link instead; run the URL audit", problem);
+         let page = TagLinkTemplate {
+            branding: &settings.branding,
+            target_url: &target_url::normalize(target_url),
+         };
+         let response = page.render().map_err(|e| {
+            warn!("Failed to render template: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+         })?;
+         Ok(as_html(
+            ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+         ))
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "tag_age_gate.html")]
+struct TagAgeGateTemplate<'a> {
+   branding: &'a Branding,
+   text: &'a str,
+   action: &'a str,
+}
+
+#[derive(Deserialize)]
+struct AgeGateForm {
+   answer: age_gate::Answer,
+}
+
+/// `POST /tag/{slug}/go`: the age gate's answer. A yes is remembered in a cookie and then
+/// answered as the scan it was for, counting the tap; a no leaves for the decline URL.
+async fn age_gate_go(
+   extract::State(db): extract::State<DbState>,
+   extract::State(cache): extract::State<CacheState>,
+   extract::State(scan): extract::State<ScanState>,
+   extract::ConnectInfo(remote): extract::ConnectInfo<SocketAddr>,
+   headers: HeaderMap,
+   extract::Path(param): extract::Path<String>,
+   extract::Query(query): extract::Query<TagTapQuery>,
+   extract::Form(form): extract::Form<AgeGateForm>,
+) -> Result<Response, StatusCode> {
+   let settings = scan.settings.load();
+   if form.answer == age_gate::Answer::No {
+      return Ok(axum::response::Redirect::to(&settings.age_gate_decline_url).into_response());
+   }
+   let mut timings = Timings::new();
+   let mut response = resolve_tag(
+      &db,
+      &cache,
+      &scan,
+      remote,
+      headers,
+      param,
+      query,
+      None,
+      true,
+      &mut timings,
+   )
+   .await?;
+   // A 307 or 308 would repeat this POST against the target
+   if response.status().is_redirection() {
+      *response.status_mut() = StatusCode::SEE_OTHER;
+   }
+   if let Some(key) = &scan.cookie_key {
+      let set_cookie = age_gate::set_cookie(key, chrono::Utc::now().timestamp(), settings.age_gate_days);
+      let set_cookie = header::HeaderValue::from_str(&set_cookie).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+      response.headers_mut().append(header::SET_COOKIE, set_cookie);
+   }
+   Ok(with_server_timing(&scan.settings, &timings, response))
+}
+
+#[derive(Deserialize)]
+struct AgeGateSettingsForm {
+   /// Falls back to `age_gate::DEFAULT_TEXT` when empty.
+   #[serde(default)]
+   text: String,
+}
+
+/// Puts a tag behind the age gate, with its own text if given.
+async fn enable_age_gate(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   extract::Form(form): extract::Form<AgeGateSettingsForm>,
+) -> StatusCode {
+   let text = Some(form.text.trim().to_string()).filter(|text| !text.is_empty());
+   set_age_gate(&state, id, true, text).await
+}
+
+async fn disable_age_gate(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_age_gate(&state, id, false, None).await
+}
+
+async fn set_age_gate(state: &AppState, id: TagUid, age_gate: bool, text: Option<String>) -> StatusCode {
+   match sqlx::query!(
+      "UPDATE twag_tags SET age_gate = $2, age_gate_text = $3 WHERE id = $1",
+      id as TagUid,
+      age_gate,
+      text,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.failover.forget(&id);
+         state.reads.wrote(&id);
+         info!(tag_id = %id, age_gate, "Tag age gate changed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to change age gate of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "tag_flush.html")]
+struct TagFlushTemplate<'a> {
+   branding: &'a Branding,
+   target_url: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ReviewForm {
+   decision: reprogram::Decision,
+}
+
+/// An admin's answer to a tag flagged as reprogrammed; see `reprogram`.
+async fn review_tag(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   headers: HeaderMap,
+   extract::Form(form): extract::Form<ReviewForm>,
+) -> Result<Response, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to record review of tag '{id}': {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut tx = state.pool.get().begin().await.map_err(failed)?;
+   let Some(row) = sqlx::query!(
+      "SELECT review_state, review_tap_count FROM twag_tags WHERE id = $1 FOR UPDATE",
+      id as TagUid
+   )
+   .fetch_optional(&mut *tx)
+   .await
+   .map_err(failed)?
+   else {
+      return Err(StatusCode::NOT_FOUND);
+   };
+   let review = reprogram::Review {
+      state: row.review_state.parse().unwrap_or(ReviewState::Ok),
+      since_drop: row.review_tap_count,
+   };
+   let Ok(step) = reprogram::decide(review, form.decision) else {
+      return Ok((StatusCode::CONFLICT, "This tag isn't awaiting review.\n").into_response());
+   };
+   reprogram::record(&mut *tx, id, review.state, &step, "operator")
+      .await
+      .map_err(failed)?;
+   tx.commit().await.map_err(failed)?;
+   state.failover.forget(&id);
+   state.reads.wrote(&id);
+   info!(tag_id = %id, decision = ?form.decision, state = %step.review.state, "Tag reviewed");
+
+   let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+   if vcard::prefers_html(accept) {
+      return Ok(axum::response::Redirect::to(&format!("/tag/{}/stats", id)).into_response());
+   }
+   Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Quarantines the tag as soon as it needs review, rather than once it's marked suspicious.
+async fn enable_quarantine_on_review(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_quarantine_on_review(&state, id, true).await
+}
+
+async fn disable_quarantine_on_review(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_quarantine_on_review(&state, id, false).await
+}
+
+async fn set_quarantine_on_review(state: &AppState, id: TagUid, quarantine_on_review: bool) -> StatusCode {
+   match sqlx::query!(
+      "UPDATE twag_tags SET quarantine_on_review = $2 WHERE id = $1",
+      id as TagUid,
+      quarantine_on_review,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.failover.forget(&id);
+         state.reads.wrote(&id);
+         info!(tag_id = %id, quarantine_on_review, "Tag quarantine on review changed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to change quarantine on review of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+async fn disable_tag(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_enabled(&state, id, false).await
+}
+
+async fn enable_tag(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_enabled(&state, id, true).await
+}
+
+/// Only ever flips `enabled`, so a tag put back in use counts on from where it was.
+async fn set_enabled(state: &AppState, id: TagUid, enabled: bool) -> StatusCode {
+   match sqlx::query!(
+      "UPDATE twag_tags SET enabled = $2 WHERE id = $1 AND deleted_at IS NULL",
+      id as TagUid,
+      enabled,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.failover.forget(&id);
+         state.reads.wrote(&id);
+         info!(tag_id = %id, enabled, "Tag enabled or disabled");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to enable or disable tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+/// Moves the tag into the archive; 404 if it's in the trash or already archived. What hangs off it
+/// in other tables is deleted with its row, as the archive migration explains.
+async fn archive_tag(
+   extract::State(db): extract::State<DbState>,
+   extract::State(cache): extract::State<CacheState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to archive tag '{id}': {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut tx = db.pool.get().begin().await.map_err(failed)?;
+   // Moved by column name, and without its short code, whose alias is deleted with the row
+   let archived = sqlx::query!(
+      r#"INSERT INTO twag_tags_archive
+         SELECT (jsonb_populate_record(
+            NULL::twag_tags_archive,
+            to_jsonb(t) - 'short_code' || jsonb_build_object('archived_at', current_timestamp)
+         )).*
+         FROM twag_tags t WHERE t.id = $1 AND t.deleted_at IS NULL
+         ON CONFLICT (id) DO NOTHING"#,
+      id as TagUid
+   )
+   .execute(&mut *tx)
+   .await
+   .map_err(failed)?;
+   if archived.rows_affected() == 0 {
+      return Err(StatusCode::NOT_FOUND);
+   }
+   sqlx::query!("DELETE FROM twag_tags WHERE id = $1", id as TagUid)
+      .execute(&mut *tx)
+      .await
+      .map_err(failed)?;
+   tx.commit().await.map_err(failed)?;
+   cache.failover.forget(&id);
+   db.reads.wrote(&id);
+   info!(tag_id = %id, "Tag archived");
+   Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Moves the tag back out of the archive, with an audit entry; 404 if it isn't archived.
+async fn restore_tag(
+   extract::State(db): extract::State<DbState>,
+   extract::State(cache): extract::State<CacheState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to restore tag '{id}': {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut tx = db.pool.get().begin().await.map_err(failed)?;
+   // Out of the archive first, or the insert would be refused as taking an archived id
+   let archived = sqlx::query_scalar!(
+      r#"DELETE FROM twag_tags_archive a WHERE a.id = $1 RETURNING to_jsonb(a)::text AS "row!""#,
+      id as TagUid
+   )
+   .fetch_optional(&mut *tx)
+   .await
+   .map_err(failed)?;
+   let Some(row) = archived else {
+      return Err(StatusCode::NOT_FOUND);
+   };
+   let restored = sqlx::query!(
+      r#"WITH restored AS (
+            INSERT INTO twag_tags SELECT (jsonb_populate_record(NULL::twag_tags, $2::text::jsonb)).*
+            RETURNING id
+         )
+         INSERT INTO twag_tag_audit (tag_id, action) SELECT id, 'unarchive' FROM restored"#,
+      id as TagUid,
+      row,
+   )
+   .execute(&mut *tx)
+   .await;
+   if let Err(e) = restored {
+      return write_failed(&format!("Failed to restore tag '{id}'"), e);
+   }
+   tx.commit().await.map_err(failed)?;
+   cache.failover.forget(&id);
+   cache.negative_cache.invalidate([&id]);
+   db.reads.wrote(&id);
+   info!(tag_id = %id, "Tag restored");
+   Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn enable_ignore_kit_schedule(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_ignore_kit_schedule(&state, id, true).await
+}
+
+async fn disable_ignore_kit_schedule(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_ignore_kit_schedule(&state, id, false).await
+}
+
+async fn set_ignore_kit_schedule(state: &AppState, id: TagUid, ignore_kit_schedule: bool) -> StatusCode {
+   match sqlx::query!(
+      "UPDATE twag_tags SET ignore_kit_schedule = $2 WHERE id = $1",
+      id as TagUid,
+      ignore_kit_schedule,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.failover.forget(&id);
+         state.reads.wrote(&id);
+         info!(tag_id = %id, ignore_kit_schedule, "Tag kit schedule opt-out changed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to change kit schedule opt-out of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "tag_arrival.html")]
+struct TagArrivalTemplate<'a> {
+   branding: &'a Branding,
+   target_url: &'a str,
+   beacon_url: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ArrivedQuery {
+   t: Option<String>,
+}
+
+/// `GET /tag/{slug}/arrived`: the arrival page's beacon. Answers every request with the same empty
+/// 204, so neither a bad token nor a failing database shows on the scanner's way out.
+async fn arrival_beacon(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+   extract::Query(query): extract::Query<ArrivedQuery>,
+) -> Response {
+   let answered = (StatusCode::NO_CONTENT, [(header::CACHE_CONTROL, "no-store")]).into_response();
+   let (Some(key), Ok(id), Some(token)) = (&state.cookie_key, param.parse::<TagUid>(), query.t.as_deref()) else {
+      return answered;
+   };
+   let nonce = match arrival::check(key, &id, token, chrono::Utc::now().timestamp()) {
+      Ok(nonce) => nonce,
+      Err(refused) => {
+         info!(tag_id = %id, "Arrival beacon refused: {}", refused);
+         return answered;
+      }
+   };
+   let confirmed = async { arrival::confirm(&mut *state.pool.get().acquire().await?, &id, nonce).await };
+   match confirmed.await {
+      Ok(true) => trace!(tag_id = %id, "Arrival confirmed"),
+      Ok(false) => info!(tag_id = %id, "Arrival beacon for a tap already confirmed or gone"),
+      Err(e) => warn!(tag_id = %id, "Failed to confirm arrival: {:?}", e),
+   }
+   answered
+}
+
+async fn enable_confirm_arrival(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_confirm_arrival(&state, id, true).await
+}
+
+async fn disable_confirm_arrival(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_confirm_arrival(&state, id, false).await
+}
+
+async fn set_confirm_arrival(state: &AppState, id: TagUid, confirm_arrival: bool) -> StatusCode {
+   if confirm_arrival && state.cookie_key.is_none() {
+      warn!(tag_id = %id, "TWAG_COOKIE_SECRET is unset, so scans will still get plain redirects");
+   }
+   match sqlx::query!(
+      "UPDATE twag_tags SET confirm_arrival = $2 WHERE id = $1",
+      id as TagUid,
+      confirm_arrival,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.reads.wrote(&id);
+         info!(tag_id = %id, confirm_arrival, "Tag arrival confirmation changed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to change arrival confirmation of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+#[derive(Deserialize)]
+struct PublicStatusForm {
+   #[serde(default)]
+   show_count: bool,
+}
+
+async fn enable_public_status(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   extract::Form(form): extract::Form<PublicStatusForm>,
+) -> StatusCode {
+   set_public_status(&state, id, true, form.show_count).await
+}
+
+async fn disable_public_status(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_public_status(&state, id, false, false).await
+}
+
+async fn set_public_status(state: &AppState, id: TagUid, public_status: bool, show_count: bool) -> StatusCode {
+   match sqlx::query!(
+      "UPDATE twag_tags SET public_status = $2, public_show_count = $3 WHERE id = $1",
+      id as TagUid,
+      public_status,
+      show_count,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.reads.wrote(&id);
+         info!(tag_id = %id, public_status, show_count, "Tag public status changed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to change public status of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "status.html")]
+struct PublicStatusTemplate<'a> {
+   branding: &'a Branding,
+   entries: &'a [public_status::Entry],
+   refresh_secs: u32,
+}
+
+/// `GET /status`; see `public_status`. A 404 while `TWAG_STATUS_PAGE` is off, as if it didn't
+/// exist.
+async fn public_status_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   let settings = state.settings.load();
+   if !settings.status_page {
+      return Err(StatusCode::NOT_FOUND);
+   }
+   let Ok(mut conn) = state.reads.any().acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::SERVICE_UNAVAILABLE);
+   };
+   let rows = public_status::load(&mut conn).await.map_err(|e| {
+      warn!("Failed to fetch public status from Postgres: {:?}", e);
+      StatusCode::SERVICE_UNAVAILABLE
+   })?;
+   let now = chrono::Utc::now();
+   let entries: Vec<_> = rows
+      .into_iter()
+      .map(|row| public_status::Entry::new(row, now))
+      .collect();
+   let page = PublicStatusTemplate {
+      branding: &settings.branding,
+      entries: &entries,
+      refresh_secs: public_status::MAX_AGE_SECS,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   let cache_control = format!("public, max-age={}", public_status::MAX_AGE_SECS);
+   Ok(as_html(
+      ([(header::CACHE_CONTROL, cache_control)], response).into_response(),
+   ))
+}
+
+#[derive(Template)]
+#[template(path = "tag_quarantine.html")]
+struct TagQuarantineTemplate<'a> {
+   branding: &'a Branding,
+}
+
+#[derive(Template)]
+#[template(path = "maintenance.html")]
+struct MaintenanceTemplate<'a> {
+   branding: &'a Branding,
+}
+
+#[derive(Template)]
+#[template(path = "tag_disabled.html")]
+struct TagDisabledTemplate<'a> {
+   branding: &'a Branding,
+}
+
+#[derive(Template)]
+#[template(path = "tag_expired.html")]
+struct TagExpiredTemplate<'a> {
+   branding: &'a Branding,
+}
+
+#[derive(Template)]
+#[template(path = "tag_pending.html")]
+struct TagPendingTemplate<'a> {
+   branding: &'a Branding,
+   /// As shown, in UTC.
+   activates_at: &'a str,
+   /// RFC 3339, for the `<time>` element.
+   datetime: &'a str,
+}
+
+/// Puts one tag into maintenance. Its scans go to the global maintenance URL if set, or the
+/// built-in maintenance page.
+async fn enable_maintenance(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_maintenance(&state, id, true).await
+}
+
+async fn disable_maintenance(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_maintenance(&state, id, false).await
+}
+
+async fn set_maintenance(state: &AppState, id: TagUid, maintenance: bool) -> StatusCode {
+   match sqlx::query!(
+      "UPDATE twag_tags SET maintenance = $2 WHERE id = $1",
+      id as TagUid,
+      maintenance,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.failover.forget(&id);
+         state.reads.wrote(&id);
+         info!(tag_id = %id, maintenance, "Tag maintenance changed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to change maintenance of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+/// The admin pages' maintenance banner. A failed count only hides the per-tag part.
+async fn maintenance_banner(state: &AppState) -> maintenance::Banner {
+   let tags = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM twag_tags WHERE maintenance"#)
+      .fetch_one(&state.reads.any().get())
+      .await
+      .unwrap_or_else(|e| {
+         warn!("Failed to count tags in maintenance: {:?}", e);
+         0
+      });
+   maintenance::Banner {
+      global_url: state.settings.load().maintenance_target_url.clone(),
+      tags,
+   }
+}
+
+#[derive(Template)]
+#[template(path = "tag_state.html")]
+struct TagStateTemplate<'a> {
+   branding: &'a Branding,
+   label: Option<&'a str>,
+   state: CheckoutState,
+   /// How long the previous state lasted.
+   held_for: Option<String>,
+   target_url: Option<&'a str>,
+}
+
+/// Flips a stateful tag and records the transition. Returns the new state and how long the old one
+/// lasted, if there was one.
+async fn scan_stateful_tag(
+   pool: &ScalingPool,
+   id: TagUid,
+   fingerprint: Option<u64>,
+   mirror_to_notion: bool,
+) -> Result<(CheckoutState, Option<chrono::TimeDelta>), sqlx::Error> {
+   let mut tx = pool.get().begin().await?;
+
+   // The row lock serializes concurrent scans, so two at once flip twice rather than both
+   // reading the same state and flipping once.
+   let current = sqlx::query!(
+      r#"SELECT current_state,
+            (SELECT extract(epoch FROM current_timestamp - max("at"))::bigint
+               FROM twag_state_transitions WHERE tag_id = $1) AS held_secs
+         FROM twag_tags WHERE id = $1 FOR UPDATE"#,
+      id as TagUid,
+   )
+   .fetch_one(&mut *tx)
+   .await?;
+   let new_state = CheckoutState::after_scan(current.current_state.and_then(|s| s.parse().ok()));
+
+   sqlx::query!(
+      "INSERT INTO twag_state_transitions (tag_id, state, actor_fingerprint) VALUES ($1, $2, $3)",
+      id as TagUid,
+      new_state.as_str(),
+      fingerprint.map(|f| f as i64),
+   )
+   .execute(&mut *tx)
+   .await?;
+   sqlx::query!(
+      "UPDATE twag_tags SET current_state = $2 WHERE id = $1",
+      id as TagUid,
+      new_state.as_str(),
+   )
+   .execute(&mut *tx)
+   .await?;
+
+   if mirror_to_notion {
+      let payload = serde_json::to_string(&outbox::MirrorState { tag_id: id }).expect("MirrorState serializes");
+      sqlx::query!(
+         "INSERT INTO twag_outbox (kind, payload) VALUES ($1, $2::text::jsonb)",
+         outbox::KIND_MIRROR_STATE,
+         payload,
+      )
+      .execute(&mut *tx)
+      .await?;
+   }
+
+   tx.commit().await?;
+   Ok((new_state, current.held_secs.map(chrono::TimeDelta::seconds)))
+}
+
+/// Makes a tag toggle between checked out and checked in on each scan. Its state starts over.
+async fn enable_stateful(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_stateful(&state, id, true).await
+}
+
+/// Goes back to plain redirects. The transition history is kept.
+async fn disable_stateful(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   set_stateful(&state, id, false).await
+}
+
+async fn set_stateful(state: &AppState, id: TagUid, stateful: bool) -> StatusCode {
+   match sqlx::query!(
+      "UPDATE twag_tags SET stateful = $2, current_state = NULL WHERE id = $1",
+      id as TagUid,
+      stateful,
+   )
+   .execute(&state.pool.get())
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         state.reads.wrote(&id);
+         info!(tag_id = %id, stateful, "Tag mode changed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to change mode of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "tag_vcard.html")]
+struct TagVcardTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a str,
+   contact: &'a Contact,
+}
+
+#[derive(Serialize)]
+struct DailyStats {
+   day: String,
+   taps: i32,
+   /// Hits without the tag's count token, e.g. link-expansion bots.
+   uncounted: i32,
+   approx_unique: Option<u64>,
+}
+
+#[derive(Template)]
+#[template(path = "tag_stats.html")]
+struct TagStatsTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a str,
+   target_url: &'a str,
+   notion_url: Option<String>,
+   short_code: Option<String>,
+   days: &'a [DailyStats],
+   review_state: ReviewState,
+   /// The highest counter since the drop, while under review.
+   review_tap_count: Option<i32>,
+   last_seen_tap_count: Option<i32>,
+   /// Only for tags whose scans were ever answered with the arrival page.
+   arrival: Option<arrival::Rate>,
+   /// The share form's default.
+   share_days: u32,
+   /// Taps, ever, whose client asked not to be tracked; see `privacy`.
+   privacy_limited: i64,
+}
+
+/// The last 30 days with taps, most recent first.
+async fn fetch_daily(conn: &mut sqlx::PgConnection, id: &TagUid) -> Result<Vec<DailyStats>, sqlx::Error> {
+   Ok(sqlx::query!(
+      r#"SELECT to_char(day, 'YYYY-MM-DD') AS "day!", taps, uncounted, visitor_sketch
+         FROM twag_tag_daily WHERE tag_id = $1 ORDER BY day DESC LIMIT 30"#,
+      id as &TagUid,
+   )
+   .fetch_all(conn)
+   .await?
+   .into_iter()
+   .map(|row| DailyStats {
+      day: row.day,
+      taps: row.taps,
+      uncounted: row.uncounted,
+      approx_unique: row.visitor_sketch.and_then(Sketch::from_bytes).map(|s| s.estimate()),
+   })
+   .collect())
+}
+
+async fn tag_stats_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let Ok(mut conn) = state.reads.for_tag(&id).acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   };
+
+   let Some(tag) = sqlx::query!(
+      r#"SELECT target_url, notion_page_id AS "notion_page_id: NotionPageId", short_code, review_state,
+            review_tap_count, last_seen_tap_count
+         FROM twag_tags WHERE id = $1"#,
+      id as TagUid
+   )
+   .fetch_optional(&mut *conn)
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   else {
+      return Err(StatusCode::NOT_FOUND);
+   };
+
+   let days = fetch_daily(&mut conn, &id).await.map_err(|e| {
+      warn!("Failed to fetch daily stats for '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+
+   let arrival = arrival::rate(&mut conn, &id).await.map_err(|e| {
+      warn!("Failed to fetch arrival rate for '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+
+   let privacy_limited = privacy::limited_taps(&mut conn, &id).await.map_err(|e| {
+      warn!("Failed to count privacy-limited taps for '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+
+   let page = TagStatsTemplate {
+      branding: &state.settings.load().branding,
+      id: &id.to_string(),
+      target_url: &tag.target_url,
+      notion_url: tag
+         .notion_page_id
+         .filter(|_| state.client.is_some())
+         .map(|id| id.notion_url()),
+      short_code: tag.short_code,
+      days: &days,
+      review_state: tag.review_state.parse().unwrap_or(ReviewState::Ok),
+      review_tap_count: tag.review_tap_count,
+      last_seen_tap_count: tag.last_seen_tap_count,
+      arrival,
+      share_days: state.settings.load().stats_share_days,
+      privacy_limited,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+#[derive(Template)]
+#[template(path = "tag_report.html")]
+struct TagReportTemplate<'a> {
+   branding: &'a Branding,
+   report: &'a report::Report,
+   /// Fetched from Notion as the report is rendered; `None` if it couldn't be.
+   notion_title: Option<String>,
+   /// Set on the shared view, which has no share form.
+   shared_until: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "tag_report_share.html")]
+struct TagReportShareTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a str,
+   share_url: &'a str,
+   expires: &'a str,
+}
+
+/// `YYYY-MM-DD HH:MM UTC`, for times shown on report pages.
+fn report_time(unix: i64) -> String {
+   chrono::DateTime::from_timestamp(unix, 0)
+      .unwrap_or_default()
+      .format("%Y-%m-%d %H:%M UTC")
+      .to_string()
+}
+
+async fn render_tag_report(
+   state: &AppState,
+   id: &TagUid,
+   shared_until: Option<String>,
+) -> Result<Response, StatusCode> {
+   let report = async { report::load(&mut *state.reads.for_tag(id).acquire().await?, id).await };
+   let Some(report) = report.await.map_err(|e| {
+      warn!("Failed to fetch report for '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   else {
+      return Err(StatusCode::NOT_FOUND);
+   };
+
+   let notion_title = match (&state.notion_picker, &report.notion_page_id) {
+      (Some(picker), Some(page)) => picker
+         .page_title(page)
+         .await
+         .inspect_err(|e| warn!(tag_id = %id, "Failed to fetch Notion page title for report: {}", e))
+         .ok()
+         .filter(|title| !title.is_empty()),
+      _ => None,
+   };
+
+   let page = TagReportTemplate {
+      branding: &state.settings.load().branding,
+      report: &report,
+      notion_title,
+      shared_until,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(
+      ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+   ))
+}
+
+async fn tag_report_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   render_tag_report(&state, &id, None).await
+}
+
+#[derive(Deserialize)]
+struct ShareReportForm {
+   #[serde(default = "default_share_days")]
+   days: u32,
+}
+
+fn default_share_days() -> u32 { report::DEFAULT_SHARE_DAYS }
+
+/// Signs a link to the tag's report that works without admin access until it expires. Links can't
+/// be revoked one by one; rotating `TWAG_COOKIE_SECRET` revokes them all.
+async fn share_tag_report(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   headers: HeaderMap,
+   extract::Form(form): extract::Form<ShareReportForm>,
+) -> Result<Response, StatusCode> {
+   let Some(key) = &state.cookie_key else {
+      return Ok((
+         StatusCode::SERVICE_UNAVAILABLE,
+         "Sharing reports needs TWAG_COOKIE_SECRET set.\n",
+      )
+         .into_response());
+   };
+   let exists = sqlx::query_scalar!(r#"SELECT true AS "exists!" FROM twag_tags WHERE id = $1"#, id as TagUid)
+      .fetch_optional(&state.reads.for_tag(&id).get())
+      .await
+      .map_err(|e| {
+         warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+   if exists.is_none() {
+      return Err(StatusCode::NOT_FOUND);
+   }
+
+   let expires = report::share_expires(chrono::Utc::now().timestamp(), form.days);
+   let token = report::share_token(key, &id, expires);
+   let share_url = format!(
+      "{}/tag/{}/report/shared?t={}",
+      public_origin(&state, &headers),
+      id,
+      token
+   );
+   info!(tag_id = %id, expires, "Report shared");
+   let page = TagReportShareTemplate {
+      branding: &state.settings.load().branding,
+      id: &id.to_string(),
+      share_url: &share_url,
+      expires: &report_time(expires),
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+#[derive(Deserialize)]
+struct SharedReportQuery {
+   t: String,
+}
+
+async fn shared_tag_report(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(param): extract::Path<String>,
+   extract::Query(query): extract::Query<SharedReportQuery>,
+) -> Result<Response, StatusCode> {
+   let (Some(key), Ok(id)) = (&state.cookie_key, param.parse::<TagUid>()) else {
+      return Err(StatusCode::NOT_FOUND);
+   };
+   match report::check_share(key, &id, &query.t, chrono::Utc::now().timestamp()) {
+      Ok(expires) => render_tag_report(&state, &id, Some(report_time(expires))).await,
+      Err(report::Refused::Expired) => Ok((StatusCode::GONE, "This link has expired.\n").into_response()),
+      Err(refused) => {
+         info!(tag_id = %id, "Shared report refused: {}", refused);
+         Err(StatusCode::NOT_FOUND)
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "tag_stats_share.html")]
+struct TagStatsShareTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a str,
+   share_url: &'a str,
+   expires: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "tag_stats_shared.html")]
+struct TagStatsSharedTemplate<'a> {
+   branding: &'a Branding,
+   label: Option<String>,
+   access_count: i32,
+   days: &'a [DailyStats],
+   /// For scaling the histogram's bars.
+   most_taps: i32,
+   shared_until: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ShareStatsForm {
+   days: Option<u32>,
+}
+
+/// Signs a link to the tag's stats, for `/share/{token}`. Unlike report links, these can be
+/// revoked, all of a tag's at once, by `/tag/{slug}/stats/share/revoke`.
+async fn share_tag_stats(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   headers: HeaderMap,
+   extract::Form(form): extract::Form<ShareStatsForm>,
+) -> Result<Response, StatusCode> {
+   let Some(key) = &state.cookie_key else {
+      return Ok((
+         StatusCode::SERVICE_UNAVAILABLE,
+         "Sharing stats needs TWAG_COOKIE_SECRET set.\n",
+      )
+         .into_response());
+   };
+   // From the primary, so a link made just after a revocation isn't signed for the revoked generation
+   let generation = sqlx::query_scalar!("SELECT share_generation FROM twag_tags WHERE id = $1", id as TagUid)
+      .fetch_optional(&state.pool.get())
+      .await
+      .map_err(|e| {
+         warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?
+      .ok_or(StatusCode::NOT_FOUND)?;
+
+   let days = form.days.unwrap_or(state.settings.load().stats_share_days);
+   let grant = share::Grant {
+      id,
+      scope: share::Scope::Stats,
+      generation,
+      expires: share::expires(chrono::Utc::now().timestamp(), days),
+   };
+   let share_url = format!("{}/share/{}", public_origin(&state, &headers), grant.token(key));
+   info!(tag_id = %id, expires = grant.expires, "Stats shared");
+   let page = TagStatsShareTemplate {
+      branding: &state.settings.load().branding,
+      id: &id.to_string(),
+      share_url: &share_url,
+      expires: &report_time(grant.expires),
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn revoke_tag_stats_shares(
+   extract::State(db): extract::State<DbState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let result = sqlx::query!(
+      "UPDATE twag_tags SET share_generation = share_generation + 1 WHERE id = $1",
+      id as TagUid
+   )
+   .execute(&db.pool.get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to revoke share links for '{id}' in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   if result.rows_affected() == 0 {
+      return Err(StatusCode::NOT_FOUND);
+   }
+   db.reads.wrote(&id);
+   info!(tag_id = %id, "Stats share links revoked");
+   Ok(axum::response::Redirect::to(&format!("/tag/{}/stats", id)).into_response())
+}
+
+/// `GET /share/{token}`; see `share`. The token is checked before anything is read, and the tag's
+/// generation after.
+async fn shared_tag_stats(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(token): extract::Path<String>,
+) -> Result<Response, StatusCode> {
+   let Some(key) = &state.cookie_key else {
+      return Err(StatusCode::NOT_FOUND);
+   };
+   let refused = |reason: share::Refused| match reason {
+      share::Refused::Expired => Ok((StatusCode::GONE, "This link has expired.\n").into_response()),
+      share::Refused::Revoked => Ok((StatusCode::GONE, "This link has been revoked.\n").into_response()),
+      share::Refused::Invalid => {
+         info!("Shared stats refused: {}", reason);
+         Err(StatusCode::NOT_FOUND)
+      }
+   };
+   let grant = match share::Grant::check(key, &token, chrono::Utc::now().timestamp()) {
+      Ok(grant) => grant,
+      Err(e) => return refused(e),
+   };
+   let id = grant.id;
+
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch shared stats for '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = state.reads.for_tag(&id).acquire().await.map_err(failed)?;
+   let tag = sqlx::query!(
+      r#"SELECT label, access_count, share_generation FROM twag_tags WHERE id = $1 AND deleted_at IS NULL"#,
+      id as TagUid
+   )
+   .fetch_optional(&mut *conn)
+   .await
+   .map_err(failed)?
+   .ok_or(StatusCode::NOT_FOUND)?;
+   if let Err(e) = grant.current(tag.share_generation) {
+      return refused(e);
+   }
+   let days = fetch_daily(&mut conn, &id).await.map_err(failed)?;
+
+   let page = TagStatsSharedTemplate {
+      branding: &state.settings.load().branding,
+      label: tag.label,
+      access_count: tag.access_count.unwrap_or(0),
+      most_taps: days.iter().map(|day| day.taps).max().unwrap_or(0),
+      days: &days,
+      shared_until: &report_time(grant.expires),
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(
+      ([(header::CACHE_CONTROL, "no-store")], response).into_response(),
+   ))
+}
+
+/// One row of `twag_tap_events`.
+struct LoggedTap {
+   tapped_at: String,
+   tap_count: Option<i32>,
+   lang: Option<String>,
+   counted: bool,
+   resolution_path: String,
+   /// `None` for the tag's base target, or when nothing was redirected to.
+   served_target_url: Option<String>,
+   response_status: i16,
+   country: Option<String>,
+   channel: Option<String>,
+}
+
+impl LoggedTap {
+   fn redirected(&self) -> bool { (300..400).contains(&self.response_status) }
+}
+
+/// Most recent first; `None` if the tag doesn't exist.
+async fn fetch_tap_events(state: &AppState, id: &TagUid, limit: i64) -> Result<Option<Vec<LoggedTap>>, StatusCode> {
+   let Ok(mut conn) = state.reads.for_tag(id).acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   };
+   let exists = sqlx::query_scalar!(
+      r#"SELECT EXISTS (SELECT 1 FROM twag_tags WHERE id = $1) AS "exists!""#,
+      id as &TagUid
+   )
+   .fetch_one(&mut *conn)
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch tag '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   if !exists {
+      return Ok(None);
+   }
+
+   let taps = sqlx::query!(
+      r#"SELECT to_char(tapped_at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS "tapped_at!",
+            tap_count, lang, counted, resolution_path, served_target_url, response_status, country, channel
+         FROM twag_tap_events WHERE tag_id = $1 ORDER BY tapped_at DESC, id DESC LIMIT $2"#,
+      id as &TagUid,
+      limit,
+   )
+   .fetch_all(&mut *conn)
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch taps for '{id}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   .into_iter()
+   .map(|row| LoggedTap {
+      tapped_at: row.tapped_at,
+      tap_count: row.tap_count,
+      lang: row.lang,
+      counted: row.counted,
+      resolution_path: row.resolution_path,
+      served_target_url: row.served_target_url,
+      response_status: row.response_status,
+      country: row.country,
+      channel: row.channel,
+   })
+   .collect();
+   Ok(Some(taps))
+}
+
+#[derive(Template)]
+#[template(path = "tag_taps.html")]
+struct TagTapsTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a str,
+   taps: &'a [LoggedTap],
+}
+
+async fn tag_taps_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let taps = fetch_tap_events(&state, &id, 200).await?.ok_or(StatusCode::NOT_FOUND)?;
+   let page = TagTapsTemplate {
+      branding: &state.settings.load().branding,
+      id: &id.to_string(),
+      taps: &taps,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+/// Every retained tap. An empty `served_target_url` means the tag's base target (or no redirect,
+/// per `response_status`), as stored.
+async fn tag_taps_csv(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let taps = fetch_tap_events(&state, &id, i64::MAX)
+      .await?
+      .ok_or(StatusCode::NOT_FOUND)?;
+   let mut csv = String::from(
+      "tapped_at,tap_count,lang,counted,resolution_path,served_target_url,response_status,country,channel\r\n",
+   );
+   for tap in &taps {
+      let fields = [
+         tap.tapped_at.clone(),
+         tap.tap_count.map(|c| c.to_string()).unwrap_or_default(),
+         tap.lang.clone().unwrap_or_default(),
+         tap.counted.to_string(),
+         tap.resolution_path.clone(),
+         tap.served_target_url.clone().unwrap_or_default(),
+         tap.response_status.to_string(),
+         tap.country.clone().unwrap_or_default(),
+         tap.channel.clone().unwrap_or_default(),
+      ];
+      let fields: Vec<String> = fields.iter().map(|field| taps::csv_field(field)).collect();
+      csv.push_str(&fields.join(","));
+      csv.push_str("\r\n");
+   }
+   let disposition = format!("attachment; filename=\"{}-taps.csv\"", id);
+   Ok((
+      [
+         (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+         (header::CONTENT_DISPOSITION, disposition),
+      ],
+      csv,
+   )
+      .into_response())
+}
+
+fn public_origin(state: &AppState, headers: &HeaderMap) -> String {
+   let host = state
+      .settings
+      .load()
+      .canonical_host
+      .as_ref()
+      .map(|c| c.host.clone())
+      .or_else(|| {
+         headers
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+      })
+      .unwrap_or_else(|| "localhost".to_string());
+   format!("https://{host}")
+}
+
+async fn fetch_count_token(state: &AppState, id: &TagUid) -> Result<Option<String>, StatusCode> {
+   let count_token = sqlx::query_scalar!("SELECT count_token FROM twag_tags WHERE id = $1", id as &TagUid)
+      .fetch_optional(&state.reads.for_tag(id).get())
+      .await
+      .map_err(|e| {
+         warn!("Failed to fetch count token for tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+   Ok(count_token.flatten())
+}
+
+/// The tag's model; unknown for a tag that doesn't exist, as the count token is absent for one.
+async fn fetch_tag_model(state: &AppState, id: &TagUid) -> Result<TagModel, StatusCode> {
+   let tag_model = sqlx::query_scalar!("SELECT tag_model FROM twag_tags WHERE id = $1", id as &TagUid)
+      .fetch_optional(&state.reads.for_tag(id).get())
+      .await
+      .map_err(|e| {
+         warn!("Failed to fetch model of tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+   // The column's CHECK keeps it to known models
+   Ok(tag_model.and_then(|m| m.parse().ok()).unwrap_or_default())
+}
+
+/// What a printed code for the tag should point at: its scan URL, carrying the count token if set.
+fn scan_uri(origin: &str, id: &str, count_token: Option<&str>) -> String {
+   count_token::with_token(&format!("{origin}/tag/{id}"), count_token)
+}
+
+async fn tag_qr_matrix(state: &AppState, headers: &HeaderMap, id: &TagUid) -> Result<qr::Matrix, StatusCode> {
+   let count_token = fetch_count_token(state, id).await?;
+   let uri = scan_uri(&public_origin(state, headers), &id.to_string(), count_token.as_deref());
+   qr::Matrix::encode(&uri).map_err(|e| {
+      warn!("Failed to encode QR code for tag '{id}': {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })
+}
+
+async fn tag_qr_svg(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+   let svg = tag_qr_matrix(&state, &headers, &id).await?.to_svg();
+   Ok(([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response())
+}
+
+async fn tag_qr_png(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+   let png = tag_qr_matrix(&state, &headers, &id).await?.to_png().map_err(|e| {
+      warn!("Failed to render QR code for tag '{id}': {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+fn to_hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{:02X}", b)).collect() }
+
+#[derive(Serialize)]
+struct NdefRecordJson {
+   tnf: u8,
+   record_type: &'static str,
+   prefix_code: u8,
+   payload_hex: String,
+}
+
+#[derive(Serialize)]
+struct NdefCapacityJson {
+   tag_type: &'static str,
+   user_bytes: usize,
+   fits: bool,
+}
+
+#[derive(Serialize)]
+struct NdefJson {
+   uri: String,
+   record: NdefRecordJson,
+   message_hex: String,
+   tlv_bytes: usize,
+   capacity: Vec<NdefCapacityJson>,
+   /// Checked against the URL with the counter mirror appended, which is what the tag serves, on
+   /// the tag's model when it's known.
+   capacity_warnings: Vec<String>,
+   tag_model: &'static str,
+   /// False for a printed code, which has nothing to write over NFC.
+   writable: bool,
+}
+
+async fn tag_ndef_json(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+   headers: HeaderMap,
+) -> Result<axum::Json<NdefJson>, StatusCode> {
+   let count_token = fetch_count_token(&state, &id).await?;
+   let tag_model = fetch_tag_model(&state, &id).await?;
+   let origin = public_origin(&state, &headers);
+   let uri = scan_uri(&origin, &id.to_string(), count_token.as_deref());
+   let record = ndef::UriRecord::new(&uri);
+   let message = record.to_message();
+   let tlv_bytes = ndef::to_tlv(&message).len();
+
+   Ok(axum::Json(NdefJson {
+      record: NdefRecordJson {
+         tnf: 0x01,
+         record_type: "U",
+         prefix_code: record.prefix_code,
+         payload_hex: to_hex(&record.payload()),
+      },
+      message_hex: to_hex(&message),
+      tlv_bytes,
+      capacity: ndef::NTAG_CAPACITIES
+         .iter()
+         .map(|&(tag_type, user_bytes)| NdefCapacityJson {
+            tag_type,
+            user_bytes,
+            fits: tlv_bytes <= user_bytes,
+         })
+         .collect(),
+      capacity_warnings: tag_model.capacity_warnings(&count_token::with_token(
+         &ndef::programmed_uri(&origin, &id),
+         count_token.as_deref(),
+      )),
+      tag_model: tag_model.as_str(),
+      writable: tag_model.capabilities().nfc,
+      uri,
+   }))
+}
+
+/// Badges and counts are embedded on third-party pages, so they're readable cross-origin and
+/// cacheable briefly; fetching them never counts as a tap.
+fn embeddable(mut response: Response) -> Response {
+   let headers = response.headers_mut();
+   headers.insert(
+      header::ACCESS_CONTROL_ALLOW_ORIGIN,
+      header::HeaderValue::from_static("*"),
+   );
+   headers.insert(
+      header::CACHE_CONTROL,
+      header::HeaderValue::from_static("public, max-age=60"),
+   );
+   response
+}
+
+async fn fetch_access_count(state: &AppState, id: &TagUid, timings: &mut Timings) -> Result<i32, StatusCode> {
+   let Ok(mut conn) = timings.time("pool", state.reads.for_tag(id).acquire()).await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   };
+   let query =
+      sqlx::query_scalar!("SELECT access_count FROM twag_tags WHERE id = $1", id as &TagUid).fetch_optional(&mut *conn);
+   timings
+      .time("db", query)
+      .await
+      .map_err(|e| {
+         warn!("Failed to fetch access count for tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?
+      .map(|count| count.unwrap_or(0))
+      .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn tag_badge_svg(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let mut timings = Timings::new();
+   let count = fetch_access_count(&state, &id, &mut timings).await?;
+   let svg = badge::badge_svg(
+      "scans",
+      &count.to_string(),
+      &state.settings.load().branding.accent_color,
+   );
+   let response = ([(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8")], svg).into_response();
+   Ok(with_server_timing(&state.settings, &timings, embeddable(response)))
+}
+
+#[derive(Serialize)]
+struct CountJson {
+   count: i32,
+}
+
+async fn tag_count_json(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let mut timings = Timings::new();
+   let count = fetch_access_count(&state, &id, &mut timings).await?;
+   let response = embeddable(axum::Json(CountJson { count }).into_response());
+   Ok(with_server_timing(&state.settings, &timings, response))
+}
+
+#[derive(Template)]
+#[template(path = "tag_write.html")]
+struct TagWriteTemplate<'a> {
+   branding: &'a Branding,
+   id: &'a str,
+   tag_model: TagModel,
+}
+
+async fn tag_write_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let tag_model = fetch_tag_model(&state, &id).await?;
+   let page = TagWriteTemplate {
+      branding: &state.settings.load().branding,
+      id: &id.to_string(),
+      tag_model,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn tag_write_confirm(
+   extract::State(db): extract::State<DbState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   let Ok(mut conn) = db.pool.acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return StatusCode::INTERNAL_SERVER_ERROR;
+   };
+
+   match sqlx::query!(
+      "UPDATE twag_tags SET programmed_at = current_timestamp WHERE id = $1",
+      id as TagUid
+   )
+   .execute(&mut *conn)
+   .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         db.reads.wrote(&id);
+         info!(tag_id = %id, "Tag programmed");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to stamp programmed_at for tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+/// Issues a fresh count token, after which only scans carrying it are counted. Existing counts
+/// are kept; the tag must be reprogrammed with the new URL from `ndef.json`.
+async fn rotate_count_token(
+   extract::State(db): extract::State<DbState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> Result<Response, StatusCode> {
+   let token = sqlx::query_scalar!(
+      r#"UPDATE twag_tags SET count_token = left(replace(gen_random_uuid()::text, '-', ''), 16)
+         WHERE id = $1 RETURNING count_token AS "count_token!""#,
+      id as TagUid
+   )
+   .fetch_optional(&db.pool.get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to rotate count token for tag '{id}': {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   .ok_or(StatusCode::NOT_FOUND)?;
+   db.reads.wrote(&id);
+   info!(tag_id = %id, "Count token rotated");
+   Ok(token.into_response())
+}
+
+/// Goes back to counting every hit.
+async fn clear_count_token(
+   extract::State(db): extract::State<DbState>,
+   extract::Path(id): extract::Path<TagUid>,
+) -> StatusCode {
+   match sqlx::query!("UPDATE twag_tags SET count_token = NULL WHERE id = $1", id as TagUid)
+      .execute(&db.pool.get())
+      .await
+   {
+      Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+      Ok(_) => {
+         db.reads.wrote(&id);
+         info!(tag_id = %id, "Count token cleared");
+         StatusCode::NO_CONTENT
+      }
+      Err(e) => {
+         warn!("Failed to clear count token for tag '{id}': {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      }
+   }
+}
+
+struct OutboxEntry {
+   id: i64,
+   kind: String,
+   payload: String,
+   attempts: i32,
+   last_error: Option<String>,
+   dead: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin_outbox.html")]
+struct AdminOutboxTemplate<'a> {
+   branding: &'a Branding,
+   banner: &'a maintenance::Banner,
+   entries: &'a [OutboxEntry],
+}
+
+async fn admin_outbox_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   if state.client.is_none() {
+      return Ok((StatusCode::NOT_FOUND, "Notion integration is disabled").into_response());
+   }
+
+   let Ok(mut conn) = state.reads.any().acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   };
+
+   let entries: Vec<OutboxEntry> = sqlx::query!(
+      r#"SELECT id, kind, payload::text AS "payload!", attempts, last_error, dead_at IS NOT NULL AS "dead!"
+         FROM twag_outbox WHERE done_at IS NULL ORDER BY id LIMIT 200"#
+   )
+   .fetch_all(&mut *conn)
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch outbox from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   .into_iter()
+   .map(|row| OutboxEntry {
+      id: row.id,
+      kind: row.kind,
+      payload: row.payload,
+      attempts: row.attempts,
+      last_error: row.last_error,
+      dead: row.dead,
+   })
+   .collect();
+
+   let page = AdminOutboxTemplate {
+      branding: &state.settings.load().branding,
+      banner: &maintenance_banner(&state).await,
+      entries: &entries,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+struct LoggedDelivery {
+   id: i64,
+   created_at: String,
+   webhook_url: String,
+   event: String,
+   payload: String,
+   attempts: i32,
+   last_status: Option<i16>,
+   last_error: Option<String>,
+   delivered: bool,
+   failed: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin_webhooks.html")]
+struct AdminWebhooksTemplate<'a> {
+   branding: &'a Branding,
+   banner: &'a maintenance::Banner,
+   deliveries: &'a [LoggedDelivery],
+}
+
+async fn admin_webhooks_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   if state.webhook.is_none() {
+      return Ok((StatusCode::NOT_FOUND, "Webhooks are disabled").into_response());
+   }
+
+   let deliveries: Vec<LoggedDelivery> = sqlx::query!(
+      r#"SELECT id, to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS "created_at!",
+            webhook_url, event, payload::text AS "payload!", attempts, last_status, last_error,
+            delivered_at IS NOT NULL AS "delivered!", failed_at IS NOT NULL AS "failed!"
+         FROM twag_webhook_deliveries ORDER BY id DESC LIMIT 200"#
+   )
+   .fetch_all(&state.reads.any().get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch webhook deliveries from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   .into_iter()
+   .map(|row| LoggedDelivery {
+      id: row.id,
+      created_at: row.created_at,
+      webhook_url: row.webhook_url,
+      event: row.event,
+      payload: row.payload,
+      attempts: row.attempts,
+      last_status: row.last_status,
+      last_error: row.last_error,
+      delivered: row.delivered,
+      failed: row.failed,
+   })
+   .collect();
+
+   let page = AdminWebhooksTemplate {
+      branding: &state.settings.load().branding,
+      banner: &maintenance_banner(&state).await,
+      deliveries: &deliveries,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn admin_redeliver_webhook(
+   extract::State(db): extract::State<DbState>,
+   extract::Path(id): extract::Path<i64>,
+) -> Result<Response, StatusCode> {
+   match webhook::redeliver(&db.pool, id).await {
+      Ok(true) => {
+         info!(delivery_id = id, "Webhook delivery queued for redelivery");
+         Ok(axum::response::Redirect::to("/admin/webhooks").into_response())
+      }
+      Ok(false) => Err(StatusCode::NOT_FOUND),
+      Err(e) => {
+         warn!(delivery_id = id, "Failed to queue webhook redelivery: {:?}", e);
+         Err(StatusCode::INTERNAL_SERVER_ERROR)
+      }
+   }
+}
+
+#[derive(Template)]
+#[template(path = "help.html")]
+struct HelpTemplate<'a> {
+   branding: &'a Branding,
+   routes: &'a [RouteDoc],
+}
+
+async fn help_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   let page = HelpTemplate {
+      branding: &state.settings.load().branding,
+      routes: &state.route_docs,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn help_json(extract::State(state): extract::State<AppState>) -> Response {
+   axum::Json(state.route_docs.as_slice()).into_response()
+}
+
+#[derive(Template)]
+#[template(path = "admin_db_report.html")]
+struct AdminDbReportTemplate<'a> {
+   branding: &'a Branding,
+   banner: &'a maintenance::Banner,
+   report: &'a db_report::Report,
+   large_table_rows: i64,
+}
+
+/// Explains the catalogued queries against the primary, whose statistics decide what's large or
+/// unused. Applying a suggestion is left to the operator.
+async fn admin_db_report(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   let mut catalogue = planned_queries();
+   catalogue.extend(listing::planned());
+   catalogue.extend(retention::planned());
+   let report = db_report::run(&state.pool, &catalogue).await.map_err(|e| {
+      warn!("Failed to build the database report: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+
+   let page = AdminDbReportTemplate {
+      branding: &state.settings.load().branding,
+      banner: &maintenance_banner(&state).await,
+      report: &report,
+      large_table_rows: db_report::LARGE_TABLE_ROWS,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn admin_audit_ids(extract::State(db): extract::State<DbState>) -> Result<Response, StatusCode> {
+   let audit = audit::load_audit(&db.pool).await.map_err(|e| {
+      warn!("Failed to audit tag ids: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(audit.to_string().into_response())
+}
+
+async fn admin_fix_ids(extract::State(db): extract::State<DbState>) -> Result<Response, StatusCode> {
+   let audit = audit::load_audit(&db.pool).await.map_err(|e| {
+      warn!("Failed to audit tag ids: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   audit::apply_fixes(&db.pool, &audit).await.map_err(|e| {
+      warn!("Failed to normalize tag ids: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(format!("{}Normalized {} tag id(s).\n", audit, audit.fixable.len()).into_response())
+}
+
+async fn admin_preload_cache(
+   extract::State(cache): extract::State<CacheState>,
+   extract::State(db): extract::State<DbState>,
+) -> Result<Response, StatusCode> {
+   if cache.preload.tags == 0 {
+      return Ok((StatusCode::CONFLICT, "Preloading is off; TWAG_PRELOAD_TAGS is 0.\n").into_response());
+   }
+   let preloaded = cache
+      .failover
+      .preload(&db.pool.get(), cache.preload)
+      .await
+      .map_err(|e| {
+         warn!("Failed to preload the redirect cache: {}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+   info!(
+      warmed = preloaded.warmed,
+      elapsed_ms = preloaded.elapsed.as_millis() as u64,
+      "Preloaded the redirect cache"
+   );
+   Ok(format!("{preloaded}.\n").into_response())
+}
+
+#[derive(Deserialize)]
+struct LogLevelForm {
+   filter: String,
+   revert_secs: Option<u64>,
+}
+
+async fn admin_set_log_level(
+   extract::State(state): extract::State<AppState>,
+   extract::Form(form): extract::Form<LogLevelForm>,
+) -> Result<Response, StatusCode> {
+   let revert_after = form
+      .revert_secs
+      .filter(|secs| *secs > 0)
+      .map(std::time::Duration::from_secs);
+   match state.telemetry.set_filter(&form.filter, revert_after) {
+      Ok(()) => {}
+      Err(e @ telemetry::FilterError::Invalid(_)) => {
+         return Ok((StatusCode::UNPROCESSABLE_ENTITY, format!("{e}\n")).into_response());
+      }
+      Err(e) => {
+         warn!("Failed to change the log filter: {}", e);
+         return Err(StatusCode::INTERNAL_SERVER_ERROR);
+      }
+   }
+   warn!(filter = %form.filter, revert_secs = form.revert_secs, "Log filter changed");
+   Ok(match revert_after {
+      Some(after) => format!(
+         "Logging by '{}' for {} s, then by '{}' again.\n",
+         form.filter,
+         after.as_secs(),
+         state.telemetry.config().directives
+      ),
+      None => format!("Logging by '{}' until the next restart.\n", form.filter),
+   }
+   .into_response())
+}
+
+#[derive(Template)]
+#[template(path = "admin_provisioning.html")]
+struct AdminProvisioningTemplate<'a> {
+   branding: &'a Branding,
+   /// Whether `TWAG_PROVISIONING_SECRET` is set.
+   enabled: bool,
+   ids: &'a str,
+   days: u32,
+   /// Each id with its creation link.
+   links: &'a [(String, String)],
+   /// Lines that weren't ids, with why.
+   rejected: &'a [(String, String)],
+   expires: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProvisioningForm {
+   #[serde(default)]
+   ids: String,
+   #[serde(default = "default_provisioning_days")]
+   days: u32,
+}
+
+fn default_provisioning_days() -> u32 { provision::DEFAULT_DAYS }
+
+async fn admin_provisioning_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   let page = AdminProvisioningTemplate {
+      branding: &state.settings.load().branding,
+      enabled: state.provisioning_key.is_some(),
+      ids: "",
+      days: provision::DEFAULT_DAYS,
+      links: &[],
+      rejected: &[],
+      expires: None,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+/// Signs a creation link for each id, for a vendor to program onto the tags. Nothing is stored
+/// until a link is used; rotating `TWAG_PROVISIONING_SECRET` revokes every unused one.
+async fn admin_mint_provisioning(
+   extract::State(state): extract::State<AppState>,
+   headers: HeaderMap,
+   extract::Form(form): extract::Form<ProvisioningForm>,
+) -> Result<Response, StatusCode> {
+   let Some(key) = &state.provisioning_key else {
+      return Ok((
+         StatusCode::SERVICE_UNAVAILABLE,
+         "Creation links need TWAG_PROVISIONING_SECRET set.\n",
+      )
+         .into_response());
+   };
+   let (ids, rejected) = provision::parse_ids(&form.ids, &own_hosts(&state, &headers));
+   let now = chrono::Utc::now().timestamp();
+   let origin = public_origin(&state, &headers);
+   let minted: Vec<(TagUid, provision::Link)> = ids
+      .into_iter()
+      .map(|id| (id, provision::Link::for_days(key, &id, now, form.days)))
+      .collect();
+   let links: Vec<(String, String)> = minted
+      .iter()
+      .map(|(id, link)| (id.to_string(), link.create_url(&origin, id)))
+      .collect();
+   info!(count = links.len(), days = form.days, "Creation links minted");
+   let page = AdminProvisioningTemplate {
+      branding: &state.settings.load().branding,
+      enabled: true,
+      ids: &form.ids,
+      days: form.days.clamp(1, provision::MAX_DAYS),
+      expires: minted.first().map(|(_, link)| report_time(link.exp)),
+      links: &links,
+      rejected: &rejected,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+#[derive(Template)]
+#[template(path = "admin_upcoming.html")]
+struct AdminUpcomingTemplate<'a> {
+   branding: &'a Branding,
+   banner: &'a maintenance::Banner,
+   days: u32,
+   transitions: &'a [upcoming::Transition],
+   /// Whether trash purges are left out, the retention run being a dry run.
+   dry_run: bool,
+}
+
+#[derive(Deserialize)]
+struct UpcomingQuery {
+   #[serde(default = "default_upcoming_days")]
+   days: u32,
+}
+
+fn default_upcoming_days() -> u32 { upcoming::DEFAULT_DAYS }
+
+/// The transitions of the next `days` days, with whether the retention run is a dry run.
+async fn upcoming_transitions(state: &AppState, days: u32) -> Result<(Vec<upcoming::Transition>, bool), StatusCode> {
+   let now = chrono::Utc::now();
+   let until = now + chrono::TimeDelta::days(days.clamp(1, upcoming::MAX_DAYS).into());
+   let Ok(mut conn) = state.reads.any().acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   };
+   let tags = upcoming::load(&mut conn, until).await.map_err(|e| {
+      warn!("Failed to fetch time-dependent tags from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   let settings = state.settings.load();
+   let mut policy = resolve::Policy::default();
+   policy.maintenance_target_url = settings.maintenance_target_url.as_deref();
+   let dry_run = RetentionPolicy::from_env().dry_run;
+   Ok((upcoming::transitions(&tags, policy, !dry_run, now, until), dry_run))
+}
+
+async fn admin_upcoming_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(query): extract::Query<UpcomingQuery>,
+) -> Result<Response, StatusCode> {
+   let (transitions, dry_run) = upcoming_transitions(&state, query.days).await?;
+   let page = AdminUpcomingTemplate {
+      branding: &state.settings.load().branding,
+      banner: &maintenance_banner(&state).await,
+      days: query.days.clamp(1, upcoming::MAX_DAYS),
+      transitions: &transitions,
+      dry_run,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn admin_upcoming_ics(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(query): extract::Query<UpcomingQuery>,
+   headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+   let (transitions, _) = upcoming_transitions(&state, query.days).await?;
+   let ical = upcoming::to_ical(&transitions, &public_origin(&state, &headers), chrono::Utc::now());
+   Ok(([(header::CONTENT_TYPE, "text/calendar; charset=utf-8")], ical).into_response())
+}
+
+#[derive(Deserialize)]
+struct SeedQuery {
+   #[serde(default)]
+   danger: bool,
+   #[serde(default)]
+   force: bool,
+   seed: Option<u64>,
+   tags: Option<u32>,
+   days: Option<u32>,
+}
+
+async fn admin_seed(
+   extract::State(db): extract::State<DbState>,
+   extract::Query(query): extract::Query<SeedQuery>,
+) -> Result<Response, StatusCode> {
+   if !query.danger {
+      return Ok((
+         StatusCode::BAD_REQUEST,
+         "Seeding writes demo data into this database; add danger=true to go ahead.\n",
+      )
+         .into_response());
+   }
+   let spec = fixtures::Spec {
+      seed: query.seed.unwrap_or(fixtures::DEFAULT_SEED),
+      tags: query.tags.unwrap_or(fixtures::DEFAULT_TAGS),
+      days: query.days.unwrap_or(fixtures::DEFAULT_DAYS),
+   }
+   .validated();
+   let spec = match spec {
+      Ok(spec) => spec,
+      Err(e) => return Ok((StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response()),
+   };
+   match fixtures::seed(&db.pool.get(), &spec, chrono::Utc::now().date_naive(), query.force).await {
+      Ok(summary) => {
+         info!(
+            seed = spec.seed,
+            tags = summary.tags,
+            taps = summary.taps,
+            "Seeded demo data"
+         );
+         Ok(summary.to_string().into_response())
+      }
+      Err(e @ fixtures::SeedError::NotEmpty(_)) => Ok((StatusCode::CONFLICT, format!("{}\n", e)).into_response()),
+      Err(fixtures::SeedError::Database(e)) => {
+         warn!("Failed to seed demo data: {:?}", e);
+         Err(StatusCode::INTERNAL_SERVER_ERROR)
+      }
+   }
+}
+
+struct LoggedSubmission {
+   received_at: String,
+   tag_id: Option<String>,
+   method: String,
+   content_type: Option<String>,
+   user_agent: Option<String>,
+   query: String,
+   body: String,
+   truncated: bool,
+   status: i16,
+   /// The target URL as `create_tag` would have read it from this submission.
+   submitted_target_url: Option<String>,
+   stored_target_url: Option<String>,
+}
+
+impl LoggedSubmission {
+   fn target_differs(&self) -> bool {
+      self.submitted_target_url.is_some() && self.submitted_target_url != self.stored_target_url
+   }
+}
+
+#[derive(Deserialize)]
+struct AdminRequestsQuery {
+   tag: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_requests.html")]
+struct AdminRequestsTemplate<'a> {
+   branding: &'a Branding,
+   banner: &'a maintenance::Banner,
+   tag: Option<&'a str>,
+   entries: &'a [LoggedSubmission],
+}
+
+async fn admin_requests_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(query): extract::Query<AdminRequestsQuery>,
+) -> Result<Response, StatusCode> {
+   let Ok(mut conn) = state.reads.any().acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR);
+   };
+
+   let tag = query.tag.filter(|t| !t.trim().is_empty());
+   let entries: Vec<LoggedSubmission> = sqlx::query!(
+      r#"SELECT r.received_at::text AS "received_at!", r.tag_id, r.method, r.content_type, r.user_agent,
+            r.query, r.body, r.truncated, r.status, t.target_url AS "stored_target_url?"
+         FROM twag_request_log r
+         LEFT JOIN twag_tags t ON t.id::text = upper(r.tag_id)
+         WHERE $1::text IS NULL OR upper(r.tag_id) = upper($1)
+         ORDER BY r.received_at DESC
+         LIMIT 200"#,
+      tag,
+   )
+   .fetch_all(&mut *conn)
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch request log from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   .into_iter()
+   .map(|row| LoggedSubmission {
+      submitted_target_url: request_log::field(&row.body, "target_url")
+         .or(request_log::field(&row.query, "target_url"))
+         .map(str::to_string),
+      received_at: row.received_at,
+      tag_id: row.tag_id,
+      method: row.method,
+      content_type: row.content_type,
+      user_agent: row.user_agent,
+      query: row.query,
+      body: row.body,
+      truncated: row.truncated,
+      status: row.status,
+      stored_target_url: row.stored_target_url,
+   })
+   .collect();
+
+   let page = AdminRequestsTemplate {
+      branding: &state.settings.load().branding,
+      banner: &maintenance_banner(&state).await,
+      tag: tag.as_deref(),
+      entries: &entries,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+#[derive(Template)]
+#[template(path = "tags.html")]
+struct TagListTemplate<'a> {
+   branding: &'a Branding,
+   banner: &'a maintenance::Banner,
+   filter: &'a TagFilter,
+   /// The rows go where `streaming::ROWS` is, rendered by `TagRowsTemplate`.
+   empty: bool,
+   /// Whether there's another page.
+   more: bool,
+}
+
+#[derive(Template)]
+#[template(path = "tags_rows.html")]
+struct TagRowsTemplate<'a> {
+   /// Each tag with its destination's host, for the favicon; `None` for non-http(s) targets.
+   tags: &'a [(TagRow, Option<String>)],
+}
+
+impl TagRowsTemplate<'_> {
+   fn render_rows(tags: Vec<TagRow>) -> askama::Result<String> {
+      let tags: Vec<(TagRow, Option<String>)> = tags
+         .into_iter()
+         .map(|tag| {
+            let host = favicon::host_of(&tag.target_url);
+            (tag, host)
+         })
+         .collect();
+      TagRowsTemplate { tags: &tags }.render()
+   }
+}
+
+async fn fetch_tags(state: &AppState, filter: &TagFilter) -> Result<Vec<TagRow>, StatusCode> {
+   let tags = filter
+      .query()
+      .build_query_as::<TwagTag>()
+      .fetch_all(&state.reads.any().get())
+      .await
+      .map_err(|e| {
+         warn!("Failed to fetch tags from Postgres: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+   let now = chrono::Utc::now();
+   Ok(tags.into_iter().map(|tag| TagRow::new(tag, now)).collect())
+}
+
+/// Streamed; see `streaming`. The rows on every page are counted first, as only the first batch is
+/// read before the links to other pages render.
+async fn tags_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(filter): extract::Query<TagFilter>,
+) -> Result<Response, StatusCode> {
+   let filter = TagFilter {
+      default_order: Some((Sort::Updated, Direction::Desc)),
+      ..filter
+   };
+   let pool = state.reads.any().get();
+   let mut batches = streaming::read_batches::<TwagTag>(pool.clone(), filter.query(), streaming::BATCH);
+   let mut count = filter.count();
+   let (first, total) = tokio::join!(
+      streaming::first_batch(&mut batches),
+      count.build_query_scalar::<i64>().fetch_one(&pool),
+   );
+   let (first, total) = first.and_then(|first| total.map(|total| (first, total))).map_err(|e| {
+      warn!("Failed to fetch tags from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+
+   let settings = state.settings.load();
+   let empty = first.is_empty();
+   let shell = render_shell(
+      &TagListTemplate {
+         branding: &settings.branding,
+         banner: &maintenance_banner(&state).await,
+         filter: &filter,
+         empty,
+         more: filter.page() * filter.limit() < total,
+      },
+      empty,
+   )?;
+   let now = chrono::Utc::now();
+   let rows = streaming::render_batches(first, batches, move |tags: Vec<TwagTag>| {
+      TagRowsTemplate::render_rows(tags.into_iter().map(|tag| TagRow::new(tag, now)).collect())
+   });
+   Ok(as_html(Response::new(shell.stream(rows, settings.minify_html))))
+}
+
+async fn tags_json(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(filter): extract::Query<TagFilter>,
+) -> Result<Response, StatusCode> {
+   Ok(axum::Json(fetch_tags(&state, &filter).await?).into_response())
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+   #[serde(default)]
+   format: export::Format,
+}
+
+/// `GET /tags/export`: streamed from the rows as they're read; see `export`. The first batch is
+/// read before answering, so a failure to read any is still a 500.
+async fn export_all_tags(
+   extract::State(db): extract::State<DbState>,
+   extract::Query(query): extract::Query<ExportQuery>,
+) -> Result<Response, StatusCode> {
+   let mut batches = streaming::read_batches(db.reads.any().get(), export::query(), streaming::BATCH);
+   let first = streaming::first_batch(&mut batches).await.map_err(|e| {
+      warn!("Failed to export tags from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   let filename = query.format.filename(chrono::Utc::now().date_naive());
+   info!(format = ?query.format, "Exporting every tag");
+   Ok((
+      [
+         (header::CONTENT_TYPE, query.format.content_type().to_string()),
+         (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+         ),
+      ],
+      axum::body::Body::from_stream(export::body(query.format, first, batches)),
+   )
+      .into_response())
+}
+
+#[derive(Template)]
+#[template(path = "stats.html")]
+struct StatsTemplate<'a> {
+   branding: &'a Branding,
+   /// `None` for every tag.
+   kit: Option<&'a str>,
+   dashboard: &'a dashboard::Dashboard,
+   /// Against the quotas; only shown for every tag.
+   usage: Option<&'a quota::Report>,
+}
+
+#[derive(Deserialize)]
+struct PeriodQuery {
+   from: Option<String>,
+   to: Option<String>,
+}
+
+async fn load_dashboard(
+   state: &AppState,
+   scope: &dashboard::Scope,
+   query: &PeriodQuery,
+) -> Result<dashboard::Dashboard, Response> {
+   let today = chrono::Utc::now().date_naive();
+   let period = dashboard::Period::parse(query.from.as_deref(), query.to.as_deref(), today)
+      .map_err(|e| (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response())?;
+   let Ok(mut conn) = state.reads.any().acquire().await else {
+      warn!("Failed to acquire Postgres connection");
+      return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+   };
+   match dashboard::load(&mut conn, scope, &period).await {
+      Ok(Some(dashboard)) => Ok(dashboard),
+      Ok(None) => Err(StatusCode::NOT_FOUND.into_response()),
+      Err(e) => {
+         warn!("Failed to fetch stats from Postgres: {:?}", e);
+         Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+      }
+   }
+}
+
+fn render_stats(
+   state: &AppState,
+   kit: Option<&str>,
+   dashboard: &dashboard::Dashboard,
+   usage: Option<&quota::Report>,
+) -> Result<Response, Response> {
+   let page = StatsTemplate {
+      branding: &state.settings.load().branding,
+      kit,
+      dashboard,
+      usage,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+/// `GET /stats`: every tag's taps over a period, against the period before; see `dashboard`.
+async fn stats_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(query): extract::Query<PeriodQuery>,
+) -> Result<Response, Response> {
+   let dashboard = load_dashboard(&state, &dashboard::Scope::All, &query).await?;
+   let usage = load_usage(&state).await.map_err(IntoResponse::into_response)?;
+   render_stats(&state, None, &dashboard, Some(&usage))
+}
+
+async fn load_usage(state: &AppState) -> Result<quota::Report, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch usage from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = state.reads.any().acquire().await.map_err(failed)?;
+   quota::report(&mut conn, &state.settings.load().quotas)
+      .await
+      .map_err(failed)
+}
+
+/// `GET /usage`: how many tags and aliases there are, each with its limit, or `null` for none.
+async fn usage_json(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   Ok(axum::Json(load_usage(&state).await?).into_response())
+}
+
+async fn stats_json(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(query): extract::Query<PeriodQuery>,
+) -> Result<Response, Response> {
+   let dashboard = load_dashboard(&state, &dashboard::Scope::All, &query).await?;
+   Ok(axum::Json(dashboard).into_response())
+}
+
+/// `GET /kits/{kit}/stats`: as `/stats`, for one kit's tags.
+async fn kit_stats_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(kit): extract::Path<String>,
+   extract::Query(query): extract::Query<PeriodQuery>,
+) -> Result<Response, Response> {
+   let dashboard = load_dashboard(&state, &dashboard::Scope::Kit(kit.clone()), &query).await?;
+   render_stats(&state, Some(&kit), &dashboard, None)
+}
+
+async fn kit_stats_json(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(kit): extract::Path<String>,
+   extract::Query(query): extract::Query<PeriodQuery>,
+) -> Result<Response, Response> {
+   let dashboard = load_dashboard(&state, &dashboard::Scope::Kit(kit), &query).await?;
+   Ok(axum::Json(dashboard).into_response())
+}
+
+/// One of a kit's windows, as its schedule page lists it.
+struct KitWindowRow {
+   id: i64,
+   window: schedule::Window,
+   open: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct KitWindowForm {
+   starts_at: String,
+   ends_at: String,
+   target_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "kit_schedule.html")]
+struct KitScheduleTemplate<'a> {
+   branding: &'a Branding,
+   kit: &'a str,
+   windows: &'a [KitWindowRow],
+   /// Tags in the kit, not counting deleted ones.
+   members: i64,
+   /// Members that keep to their own targets.
+   opted_out: &'a [TagUid],
+   error: Option<&'a str>,
+   /// What was submitted, when it's shown again with an error.
+   form: &'a KitWindowForm,
+}
+
+/// `GET /kits/{kit}/schedule`: the kit's windows that haven't closed, with a form for another.
+async fn kit_schedule_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(kit): extract::Path<String>,
+) -> Result<Response, StatusCode> {
+   render_kit_schedule(&state, StatusCode::OK, &kit, None, &KitWindowForm::default()).await
+}
+
+async fn render_kit_schedule(
+   state: &AppState,
+   status: StatusCode,
+   kit: &str,
+   error: Option<&str>,
+   form: &KitWindowForm,
+) -> Result<Response, StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch the schedule of kit '{kit}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = state.reads.any().acquire().await.map_err(failed)?;
+   let now = chrono::Utc::now();
+   let windows: Vec<KitWindowRow> = sqlx::query!(
+      r#"SELECT id, extract(epoch FROM starts_at)::bigint AS "starts_at!", extract(epoch FROM ends_at)::bigint AS "ends_at!",
+            target_url
+         FROM twag_kit_schedules WHERE kit = $1 AND ends_at > now() ORDER BY starts_at, id"#,
+      kit,
+   )
+   .fetch_all(&mut *conn)
+   .await
+   .map_err(failed)?
+   .into_iter()
+   .filter_map(|row| {
+      let window = schedule::from_columns(&[row.starts_at], &[row.ends_at], &[row.target_url]).pop()?;
+      Some(KitWindowRow {
+         id: row.id,
+         open: window.is_open(now),
+         window,
+      })
+   })
+   .collect();
+   let members = sqlx::query_scalar!(
+      r#"SELECT count(*) AS "members!" FROM twag_tags WHERE kit = $1 AND deleted_at IS NULL"#,
+      kit,
+   )
+   .fetch_one(&mut *conn)
+   .await
+   .map_err(failed)?;
+   let opted_out = sqlx::query_scalar!(
+      r#"SELECT id AS "id: TagUid" FROM twag_tags
+         WHERE kit = $1 AND deleted_at IS NULL AND ignore_kit_schedule ORDER BY id"#,
+      kit,
+   )
+   .fetch_all(&mut *conn)
+   .await
+   .map_err(failed)?;
+   let page = KitScheduleTemplate {
+      branding: &state.settings.load().branding,
+      kit,
+      windows: &windows,
+      members,
+      opted_out: &opted_out,
+      error,
+      form,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok((status, as_html(response.into_response())).into_response())
+}
+
+/// `/kits/{kit}/schedule`, with `kit` escaped as a path segment.
+fn kit_schedule_path(kit: &str) -> String {
+   let mut url = url::Url::parse("http://twag/kits").unwrap();
+   url.path_segments_mut().unwrap().push(kit).push("schedule");
+   url.path().to_string()
+}
+
+/// Every tag of `kit` answers differently once its schedule changes, so none may be answered from
+/// before the change.
+async fn forget_kit(state: &AppState, kit: &str) -> Result<usize, sqlx::Error> {
+   let ids = sqlx::query_scalar!(r#"SELECT id AS "id: TagUid" FROM twag_tags WHERE kit = $1"#, kit)
+      .fetch_all(&state.pool.get())
+      .await?;
+   for id in &ids {
+      state.reads.wrote(id);
+      state.failover.forget(id);
+   }
+   Ok(ids.len())
+}
+
+/// `POST /kits/{kit}/schedule`: adds a window, or shows the form again saying what's wrong with it.
+async fn add_kit_window(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(kit): extract::Path<String>,
+   extract::Form(form): extract::Form<KitWindowForm>,
+) -> Result<Response, StatusCode> {
+   let now = chrono::Utc::now();
+   let window = match (
+      schedule::parse_time(&form.starts_at),
+      schedule::parse_time(&form.ends_at),
+   ) {
+      (None, _) | (_, None) => Err("Times look like 2026-10-20T18:00, in UTC.".to_string()),
+      (Some(starts_at), Some(ends_at)) if ends_at <= starts_at => {
+         Err("A window has to end after it starts.".to_string())
+      }
+      (Some(_), Some(ends_at)) if ends_at <= now => Err("That window has already ended.".to_string()),
+      (Some(starts_at), Some(ends_at)) => {
+         kit::validate_target_url(form.target_url.trim(), state.settings.load().strict_idn).map(|target_url| {
+            schedule::Window {
+               starts_at,
+               ends_at,
+               target_url,
+            }
+         })
+      }
+   };
+   let window = match window {
+      Ok(window) => window,
+      Err(error) => {
+         return render_kit_schedule(&state, StatusCode::UNPROCESSABLE_ENTITY, &kit, Some(&error), &form).await;
+      }
+   };
+   let id = sqlx::query_scalar!(
+      "INSERT INTO twag_kit_schedules (kit, starts_at, ends_at, target_url)
+         VALUES ($1, $2::text::timestamptz, $3::text::timestamptz, $4) RETURNING id",
+      kit,
+      window.starts_at.to_rfc3339(),
+      window.ends_at.to_rfc3339(),
+      window.target_url,
+   )
+   .fetch_one(&state.pool.get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to add a window to kit '{kit}' in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   let members = forget_kit(&state, &kit).await.map_err(|e| {
+      warn!("Failed to fetch the tags of kit '{kit}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   info!(kit = %kit, window_id = id, members, starts_at = %window.starts_at, ends_at = %window.ends_at, "Kit window added");
+   Ok(axum::response::Redirect::to(&kit_schedule_path(&kit)).into_response())
+}
+
+/// `POST /kits/{kit}/schedule/{id}/remove`: ends a window now, or before it ever opens.
+async fn remove_kit_window(
+   extract::State(state): extract::State<AppState>,
+   extract::Path((kit, id)): extract::Path<(String, i64)>,
+) -> Result<Response, StatusCode> {
+   let removed = sqlx::query!("DELETE FROM twag_kit_schedules WHERE id = $1 AND kit = $2", id, kit)
+      .execute(&state.pool.get())
+      .await
+      .map_err(|e| {
+         warn!("Failed to remove window {id} of kit '{kit}' in Postgres: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+   if removed.rows_affected() == 0 {
+      return Err(StatusCode::NOT_FOUND);
+   }
+   let members = forget_kit(&state, &kit).await.map_err(|e| {
+      warn!("Failed to fetch the tags of kit '{kit}' from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   info!(kit = %kit, window_id = id, members, "Kit window removed");
+   Ok(axum::response::Redirect::to(&kit_schedule_path(&kit)).into_response())
+}
+
+#[derive(Template)]
+#[template(path = "tags_bulk_confirm.html")]
+struct TagsBulkConfirmTemplate<'a> {
+   branding: &'a Branding,
+   action: &'a Action,
+   ids: &'a [TagUid],
+}
+
+#[derive(Template)]
+#[template(path = "tags_bulk_result.html")]
+struct TagsBulkResultTemplate<'a> {
+   branding: &'a Branding,
+   /// `None` if the request didn't make it as far as a valid action.
+   action: Option<&'a Action>,
+   error: Option<String>,
+   results: &'a [RowResult],
+}
+
+/// Applies the plan to every tag in one transaction, or to none of them. Missing and deleted tags
+/// are reported per id, with the rest as not attempted.
+async fn apply_bulk(state: &AppState, plan: &bulk::Plan) -> Result<Result<(), Vec<RowResult>>, sqlx::Error> {
+   let mut tx = state.pool.get().begin().await?;
+
+   let ids: Vec<String> = plan.ids.iter().map(TagUid::to_string).collect();
+   let found: Vec<bulk::Found> = sqlx::query!(
+      r#"SELECT id AS "id!: TagUid", deleted_at IS NOT NULL AS "deleted!", target_url, kit,
+            to_char(expires_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI') AS expires_at
+         FROM twag_tags WHERE id::text = ANY($1) FOR UPDATE"#,
+      &ids,
+   )
+   .fetch_all(&mut *tx)
+   .await?
+   .into_iter()
+   .map(|row| bulk::Found {
+      id: row.id,
+      deleted: row.deleted,
+      before: match plan.action {
+         Action::Retarget(_) => Some(row.target_url),
+         Action::SetKit(_) => row.kit,
+         Action::SetExpiry(_) => row.expires_at,
+         Action::Delete | Action::Export => None,
+      },
+   })
+   .collect();
+   if let Err(results) = bulk::check_found(&plan.ids, &found) {
+      return Ok(Err(results));
+   }
+
+   match &plan.action {
+      Action::Retarget(target_url) => {
+         let target_id = targets::find_or_insert(&mut tx, target_url).await?;
+         sqlx::query!(
+            "UPDATE twag_tags SET target_url = $2, target_id = $3 WHERE id::text = ANY($1)",
+            &ids,
+            target_url,
+            target_id,
+         )
+         .execute(&mut *tx)
+         .await?
+      }
+      Action::SetKit(kit) => {
+         sqlx::query!(
+            "UPDATE twag_tags SET kit = $2 WHERE id::text = ANY($1)",
+            &ids,
+            kit.as_deref(),
+         )
+         .execute(&mut *tx)
+         .await?
+      }
+      Action::SetExpiry(expires_on) => {
+         sqlx::query!(
+            "UPDATE twag_tags SET expires_at = $2::text::date::timestamp AT TIME ZONE 'UTC' WHERE id::text = ANY($1)",
+            &ids,
+            expires_on.map(|date| date.to_string()),
+         )
+         .execute(&mut *tx)
+         .await?
+      }
+      Action::Delete => {
+         sqlx::query!(
+            "UPDATE twag_tags SET deleted_at = current_timestamp,
+               purge_after = current_timestamp + make_interval(days => $2)
+            WHERE id::text = ANY($1)",
+            &ids,
+            state.settings.load().trash_days as i32,
+         )
+         .execute(&mut *tx)
+         .await?
+      }
+      Action::Export => unreachable!("exports don't change anything"),
+   };
+
+   // Neither targets, kits nor dates are ever empty, so '' stands in for NULL
+   let (audited, before): (Vec<String>, Vec<String>) = found
+      .into_iter()
+      .map(|found| (found.id.to_string(), found.before.unwrap_or_default()))
+      .unzip();
+   sqlx::query!(
+      r#"INSERT INTO twag_tag_audit (tag_id, action, before, after)
+         SELECT unnest($1::text[])::tag_uid, $2, NULLIF(unnest($3::text[]), ''), $4"#,
+      &audited,
+      plan.action.name(),
+      &before,
+      plan.action.after(),
+   )
+   .execute(&mut *tx)
+   .await?;
+
+   tx.commit().await?;
+   Ok(Ok(()))
+}
+
+/// Locks and changes the planned tags, with one result per id either way.
+async fn run_bulk(state: &AppState, plan: &bulk::Plan) -> Result<Result<Vec<RowResult>, Vec<RowResult>>, Response> {
+   let _lock = state
+      .tag_locks
+      .acquire(&plan.ids, tag_lock::TIMEOUT)
+      .await
+      .map_err(IntoResponse::into_response)?;
+   match apply_bulk(state, plan).await {
+      Ok(Ok(())) => {
+         for id in &plan.ids {
+            state.reads.wrote(id);
+            state.failover.forget(id);
+         }
+         info!(
+            action = plan.action.name(),
+            count = plan.ids.len(),
+            "Applied bulk action"
+         );
+         Ok(Ok(plan.ids.iter().map(RowResult::updated).collect()))
+      }
+      Ok(Err(results)) => Ok(Err(results)),
+      Err(e) => {
+         warn!("Failed to apply bulk action in Postgres: {:?}", e);
+         Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+      }
+   }
+}
+
+async fn export_tags(state: &AppState, ids: &[TagUid]) -> Result<Response, StatusCode> {
+   let filter = TagFilter {
+      ids: Some(ids.iter().map(TagUid::to_string).collect()),
+      ..Default::default()
+   };
+   let tags = fetch_tags(state, &filter).await?;
+   Ok((
+      [
+         (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+         (header::CONTENT_DISPOSITION, "attachment; filename=\"tags.csv\""),
+      ],
+      bulk::csv(&tags),
+   )
+      .into_response())
+}
+
+fn render_bulk_result(
+   state: &AppState,
+   status: StatusCode,
+   action: Option<&Action>,
+   error: Option<String>,
+   results: &[RowResult],
+) -> Result<Response, StatusCode> {
+   let page = TagsBulkResultTemplate {
+      branding: &state.settings.load().branding,
+      action,
+      error,
+      results,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html((status, response).into_response()))
+}
+
+/// `POST /tags/bulk`, from the listing's checkboxes. Retargeting and deleting first render a page
+/// asking for confirmation, which resubmits the same selection with `confirm=yes`.
+async fn bulk_tags(
+   extract::State(state): extract::State<AppState>,
+   body: axum::body::Bytes,
+) -> Result<Response, StatusCode> {
+   let pairs: Vec<(String, String)> = url::form_urlencoded::parse(&body).into_owned().collect();
+   let request = BulkRequest::from_pairs(&pairs);
+   let strict_idn = state.settings.load().strict_idn;
+   let (plan, confirmed) = match request.and_then(|request| Ok((bulk::plan(&request, strict_idn)?, request.confirm))) {
+      Ok(planned) => planned,
+      Err(rejected) => {
+         let results = match &rejected {
+            Rejected::Ids(results) => results.clone(),
+            _ => Vec::new(),
+         };
+         return render_bulk_result(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            None,
+            Some(rejected.to_string()),
+            &results,
+         );
+      }
+   };
+
+   if plan.action == Action::Export {
+      return export_tags(&state, &plan.ids).await;
+   }
+   if plan.action.needs_confirmation() && !confirmed {
+      let page = TagsBulkConfirmTemplate {
+         branding: &state.settings.load().branding,
+         action: &plan.action,
+         ids: &plan.ids,
+      };
+      let response = page.render().map_err(|e| {
+         warn!("Failed to render template: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      return Ok(as_html(response.into_response()));
+   }
+
+   match run_bulk(&state, &plan).await {
+      Ok(Ok(results)) => render_bulk_result(&state, StatusCode::OK, Some(&plan.action), None, &results),
+      Ok(Err(results)) => render_bulk_result(
+         &state,
+         StatusCode::UNPROCESSABLE_ENTITY,
+         Some(&plan.action),
+         Some("Nothing was changed.".to_string()),
+         &results,
+      ),
+      Err(response) => Ok(response),
+   }
+}
+
+/// `POST /api/tags/bulk`: the same request as a JSON object, answered with a 207 of one result
+/// per id. Retargeting and deleting need `"confirm": true` up front.
+async fn bulk_tags_json(
+   extract::State(state): extract::State<AppState>,
+   body: axum::body::Bytes,
+) -> Result<Response, StatusCode> {
+   let request: BulkRequest = serde_json::from_slice(&body).map_err(|e| {
+      info!("Rejecting malformed bulk JSON: {e}");
+      StatusCode::BAD_REQUEST
+   })?;
+   let plan = match bulk::plan(&request, state.settings.load().strict_idn) {
+      Ok(plan) => plan,
+      Err(Rejected::Ids(results)) => return Ok((StatusCode::MULTI_STATUS, axum::Json(results)).into_response()),
+      Err(rejected) => return Ok((StatusCode::BAD_REQUEST, format!("{}\n", rejected)).into_response()),
+   };
+
+   if plan.action == Action::Export {
+      return export_tags(&state, &plan.ids).await;
+   }
+   if plan.action.needs_confirmation() && !request.confirm {
+      let message = format!(
+         "{} can't be undone; resend with \"confirm\": true\n",
+         plan.action.name()
+      );
+      return Ok((StatusCode::PRECONDITION_REQUIRED, message).into_response());
+   }
+
+   let results = match run_bulk(&state, &plan).await {
+      Ok(Ok(results)) | Ok(Err(results)) => results,
+      Err(response) => return Ok(response),
+   };
+   Ok((StatusCode::MULTI_STATUS, axum::Json(results)).into_response())
+}
+
+#[derive(Template)]
+#[template(path = "tags_trash.html")]
+struct TagsTrashTemplate<'a> {
+   branding: &'a Branding,
+   grace_days: u32,
+   now: chrono::DateTime<chrono::Utc>,
+   tags: &'a [trash::Trashed],
+}
+
+#[derive(Template)]
+#[template(path = "tags_trash_confirm.html")]
+struct TagsTrashConfirmTemplate<'a> {
+   branding: &'a Branding,
+   ids: &'a [TagUid],
+}
+
+#[derive(Serialize)]
+struct TrashedJson {
+   id: String,
+   label: Option<String>,
+   target_url: String,
+   deleted_at: String,
+   purge_after: String,
+   days_left: i64,
+}
+
+async fn fetch_trash(state: &AppState) -> Result<Vec<trash::Trashed>, StatusCode> {
+   let result = match state.pool.get().acquire().await {
+      Ok(mut conn) => trash::list(&mut conn).await,
+      Err(e) => Err(e),
+   };
+   result.map_err(|e| {
+      warn!("Failed to list the trash from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })
+}
+
+/// `GET /tags/trash`: deleted tags awaiting their purge, each with a restore button.
+async fn tags_trash_page(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   let tags = fetch_trash(&state).await?;
+   let settings = state.settings.load();
+   let page = TagsTrashTemplate {
+      branding: &settings.branding,
+      grace_days: settings.trash_days,
+      now: chrono::Utc::now(),
+      tags: &tags,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn tags_trash_json(extract::State(state): extract::State<AppState>) -> Result<Response, StatusCode> {
+   let now = chrono::Utc::now();
+   let tags: Vec<TrashedJson> = fetch_trash(&state)
+      .await?
+      .into_iter()
+      .map(|tag| TrashedJson {
+         id: tag.id.to_string(),
+         days_left: tag.days_left(now),
+         label: tag.label,
+         target_url: tag.target_url,
+         deleted_at: tag.deleted_at.to_rfc3339(),
+         purge_after: tag.purge_after.to_rfc3339(),
+      })
+      .collect();
+   Ok(axum::Json(tags).into_response())
+}
+
+/// Restores or purges the tags, with one result per id; see `trash` for how the two race.
+async fn run_trash(state: &AppState, ids: &[TagUid], purge: bool) -> Result<Vec<RowResult>, Response> {
+   let _lock = state
+      .tag_locks
+      .acquire(ids, tag_lock::TIMEOUT)
+      .await
+      .map_err(IntoResponse::into_response)?;
+   let result = match state.pool.get().acquire().await {
+      Ok(mut conn) if purge => trash::purge_now(&mut conn, ids).await,
+      Ok(mut conn) => trash::restore(&mut conn, ids).await,
+      Err(e) => Err(e),
+   };
+   let done = result.map_err(|e| {
+      warn!("Failed to empty the trash in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+   })?;
+   for id in &done {
+      state.reads.wrote(id);
+      state.failover.forget(id);
+   }
+   info!(purge, count = done.len(), "Emptied tags from the trash");
+   Ok(trash::results(ids, &done))
+}
+
+/// `POST /tags/trash/restore` and `/tags/trash/purge`, from the trash page's buttons. Back to the
+/// trash once done, or a page of results if any tag had already left it.
+async fn trash_form(state: &AppState, body: &[u8], purge: bool) -> Result<Response, StatusCode> {
+   let pairs: Vec<(String, String)> = url::form_urlencoded::parse(body).into_owned().collect();
+   let submitted: Vec<String> = pairs
+      .iter()
+      .filter(|(key, _)| key == "ids")
+      .map(|(_, id)| id.clone())
+      .collect();
+   let ids = match bulk::parse_ids(&submitted) {
+      Ok(ids) => ids,
+      Err(rejected) => {
+         let results = match &rejected {
+            Rejected::Ids(results) => results.clone(),
+            _ => Vec::new(),
+         };
+         return render_bulk_result(
+            state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            None,
+            Some(rejected.to_string()),
+            &results,
+         );
+      }
+   };
+
+   let confirmed = pairs.iter().any(|(key, value)| key == "confirm" && value == "yes");
+   if purge && !confirmed {
+      let page = TagsTrashConfirmTemplate {
+         branding: &state.settings.load().branding,
+         ids: &ids,
+      };
+      let response = page.render().map_err(|e| {
+         warn!("Failed to render template: {:?}", e);
+         StatusCode::INTERNAL_SERVER_ERROR
+      })?;
+      return Ok(as_html(response.into_response()));
+   }
+
+   let results = match run_trash(state, &ids, purge).await {
+      Ok(results) => results,
+      Err(response) => return Ok(response),
+   };
+   if results.iter().all(|result| result.error.is_none()) {
+      return Ok(axum::response::Redirect::to("/tags/trash").into_response());
+   }
+   render_bulk_result(
+      state,
+      StatusCode::OK,
+      None,
+      Some("Some tags were no longer in the trash.".to_string()),
+      &results,
+   )
+}
+
+async fn restore_trashed(
+   extract::State(state): extract::State<AppState>,
+   body: axum::body::Bytes,
+) -> Result<Response, StatusCode> {
+   trash_form(&state, &body, false).await
+}
+
+async fn purge_trashed(
+   extract::State(state): extract::State<AppState>,
+   body: axum::body::Bytes,
+) -> Result<Response, StatusCode> {
+   trash_form(&state, &body, true).await
+}
+
+/// `POST /api/tags/trash/restore` and `/api/tags/trash/purge`, as a JSON object of `ids` (and
+/// `"confirm": true` to purge), answered with a 207 of one result per id.
+#[derive(Deserialize)]
+struct TrashRequest {
+   ids: Vec<String>,
+   #[serde(default)]
+   confirm: bool,
+}
+
+async fn trash_json(state: &AppState, body: &[u8], purge: bool) -> Result<Response, StatusCode> {
+   let request: TrashRequest = serde_json::from_slice(body).map_err(|e| {
+      info!("Rejecting malformed trash JSON: {e}");
+      StatusCode::BAD_REQUEST
+   })?;
+   let ids = match bulk::parse_ids(&request.ids) {
+      Ok(ids) => ids,
+      Err(Rejected::Ids(results)) => return Ok((StatusCode::MULTI_STATUS, axum::Json(results)).into_response()),
+      Err(rejected) => return Ok((StatusCode::BAD_REQUEST, format!("{}\n", rejected)).into_response()),
+   };
+   if purge && !request.confirm {
+      let message = "purging can't be undone; resend with \"confirm\": true\n";
+      return Ok((StatusCode::PRECONDITION_REQUIRED, message).into_response());
+   }
+   match run_trash(state, &ids, purge).await {
+      Ok(results) => Ok((StatusCode::MULTI_STATUS, axum::Json(results)).into_response()),
+      Err(response) => Ok(response),
+   }
+}
+
+async fn restore_trashed_json(
+   extract::State(state): extract::State<AppState>,
+   body: axum::body::Bytes,
+) -> Result<Response, StatusCode> {
+   trash_json(&state, &body, false).await
+}
+
+async fn purge_trashed_json(
+   extract::State(state): extract::State<AppState>,
+   body: axum::body::Bytes,
+) -> Result<Response, StatusCode> {
+   trash_json(&state, &body, true).await
+}
+
+#[derive(Template)]
+#[template(path = "targets.html")]
+struct TargetsTemplate<'a> {
+   branding: &'a Branding,
+   targets: &'a [targets::Target],
+}
+
+#[derive(Template)]
+#[template(path = "target.html")]
+struct TargetTemplate<'a> {
+   branding: &'a Branding,
+   target: &'a targets::Target,
+   tags: &'a [TagUid],
+   error: Option<String>,
+   /// A new URL awaiting confirmation.
+   pending: Option<&'a str>,
+}
+
+/// `GET /targets`: the listing grouped by destination.
+async fn targets_page(
+   extract::State(db): extract::State<DbState>,
+   extract::State(SettingsState(settings)): extract::State<SettingsState>,
+) -> Result<Response, StatusCode> {
+   let result = match db.pool.get().acquire().await {
+      Ok(mut conn) => targets::list(&mut conn).await,
+      Err(e) => Err(e),
+   };
+   let targets = result.map_err(|e| {
+      warn!("Failed to list targets from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   let page = TargetsTemplate {
+      branding: &settings.load().branding,
+      targets: &targets,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok(as_html(response.into_response()))
+}
+
+async fn fetch_target(state: &AppState, id: i64) -> Result<(targets::Target, Vec<TagUid>), StatusCode> {
+   let failed = |e: sqlx::Error| {
+      warn!("Failed to fetch target {id} from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   };
+   let mut conn = state.pool.get().acquire().await.map_err(failed)?;
+   let target = targets::get(&mut conn, id)
+      .await
+      .map_err(failed)?
+      .ok_or(StatusCode::NOT_FOUND)?;
+   let tags = targets::tags(&mut conn, id).await.map_err(failed)?;
+   Ok((target, tags))
+}
+
+fn render_target(
+   state: &AppState,
+   status: StatusCode,
+   target: &targets::Target,
+   tags: &[TagUid],
+   error: Option<String>,
+   pending: Option<&str>,
+) -> Result<Response, StatusCode> {
+   let page = TargetTemplate {
+      branding: &state.settings.load().branding,
+      target,
+      tags,
+      error,
+      pending,
+   };
+   let response = page.render().map_err(|e| {
+      warn!("Failed to render template: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   Ok((status, as_html(response.into_response())).into_response())
+}
+
+/// `GET /targets/{id}`: one destination's tags, with a form to move them all.
+async fn target_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<i64>,
+) -> Result<Response, StatusCode> {
+   let (target, tags) = fetch_target(&state, id).await?;
+   render_target(&state, StatusCode::OK, &target, &tags, None, None)
+}
+
+#[derive(Deserialize)]
+struct TargetForm {
+   url: String,
+   confirm: Option<String>,
+}
+
+/// `POST /targets/{id}`: moves every tag using the target to a new URL, once confirmed on a page
+/// saying how many tags that is. A URL that's already another target's merges the two.
+async fn edit_target(
+   extract::State(state): extract::State<AppState>,
+   extract::Path(id): extract::Path<i64>,
+   extract::Form(form): extract::Form<TargetForm>,
+) -> Result<Response, StatusCode> {
+   let (target, tags) = fetch_target(&state, id).await?;
+   let url = match kit::validate_target_url(form.url.trim(), state.settings.load().strict_idn) {
+      Ok(url) => url,
+      Err(error) => {
+         return render_target(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &target,
+            &tags,
+            Some(error),
+            None,
+         )
+      }
+   };
+   if url == target.url {
+      return Ok(axum::response::Redirect::to(&format!("/targets/{}", id)).into_response());
+   }
+   if form.confirm.as_deref() != Some("yes") {
+      return render_target(&state, StatusCode::OK, &target, &tags, None, Some(&url));
+   }
+
+   let _lock = match state.tag_locks.acquire(&tags, tag_lock::TIMEOUT).await {
+      Ok(lock) => lock,
+      Err(locked) => return Ok(locked.into_response()),
+   };
+   let result = match state.pool.get().acquire().await {
+      Ok(mut conn) => targets::move_target(&mut conn, id, &url).await,
+      Err(e) => Err(e),
+   };
+   let moved = match result {
+      Ok(Some(moved)) => moved,
+      Ok(None) => return Err(StatusCode::NOT_FOUND),
+      Err(e) => {
+         warn!("Failed to move target {id} in Postgres: {:?}", e);
+         return Err(StatusCode::INTERNAL_SERVER_ERROR);
+      }
+   };
+   // Tags that joined the target since the locks were taken moved too
+   for tag in &moved.tags {
+      state.reads.wrote(tag);
+      state.failover.forget(tag);
+   }
+   info!(
+      target_id = id,
+      merged_into = moved.target_id,
+      count = moved.tags.len(),
+      "Moved target"
+   );
+   Ok(axum::response::Redirect::to(&format!("/targets/{}", moved.target_id)).into_response())
+}
+
+#[derive(Deserialize)]
+struct PickerQuery {
+   #[serde(default)]
+   q: String,
+}
+
+/// `GET /notion/things` and `/notion/containers`: pages whose title contains `q`, or the most
+/// recently edited with none, for the create page's picker.
+async fn search_notion(notion: &NotionState, database: picker::Database, q: &str) -> Result<Response, StatusCode> {
+   let Some(picker) = &notion.picker else {
+      return Ok((StatusCode::NOT_FOUND, "Notion integration is disabled\n").into_response());
+   };
+   match picker.search(database, q).await {
+      Ok(pages) => Ok(axum::Json(pages).into_response()),
+      Err(PickError::Busy) => Ok((
+         StatusCode::TOO_MANY_REQUESTS,
+         [(header::RETRY_AFTER, "1")],
+         format!("{}\n", PickError::Busy),
+      )
+         .into_response()),
+      Err(PickError::Notion(e)) => {
+         warn!("{}", e);
+         Err(StatusCode::BAD_GATEWAY)
+      }
+   }
+}
+
+async fn search_notion_things(
+   extract::State(notion): extract::State<NotionState>,
+   extract::Query(query): extract::Query<PickerQuery>,
+) -> Result<Response, StatusCode> {
+   search_notion(&notion, picker::Database::Things, &query.q).await
+}
+
+async fn search_notion_containers(
+   extract::State(notion): extract::State<NotionState>,
+   extract::Query(query): extract::Query<PickerQuery>,
+) -> Result<Response, StatusCode> {
+   search_notion(&notion, picker::Database::Containers, &query.q).await
+}
+
+/// Columns on the printed sheet when none, or an unreasonable number, is asked for.
+const DEFAULT_PRINT_COLS: usize = 4;
+const MAX_PRINT_COLS: usize = 8;
+/// Rows of codes rendered, and sent, at a time; a batch is always whole rows.
+const PRINT_ROWS_PER_BATCH: usize = 10;
+
+#[derive(Deserialize)]
+struct PrintQuery {
+   /// Comma-separated tag ids.
+   ids: Option<String>,
+   #[serde(alias = "group")]
+   kit: Option<String>,
+   q: Option<String>,
+   cols: Option<usize>,
+   /// Print labels under the codes; a checkbox, so "on" when set.
+   label: Option<String>,
+}
+
+struct PrintedTag {
+   id: String,
+   label: Option<String>,
+   uri: String,
+}
+
+#[derive(Template)]
+#[template(path = "tags_print.html")]
+struct TagsPrintTemplate<'a> {
+   branding: &'a Branding,
+   cols: usize,
+   /// The codes go where `streaming::ROWS` is, rendered by `TagsPrintRowsTemplate`.
+   empty: bool,
+}
+
+#[derive(Template)]
+#[template(path = "tags_print_rows.html")]
+struct TagsPrintRowsTemplate<'a> {
+   show_labels: bool,
+   /// Chunked into rows of `cols`, so a row is never split across pages.
+   rows: &'a [&'a [PrintedTag]],
+}
+
+impl TagsPrintRowsTemplate<'_> {
+   fn render_rows(tags: &[PrintedTag], cols: usize, show_labels: bool) -> askama::Result<String> {
+      let rows: Vec<&[PrintedTag]> = tags.chunks(cols).collect();
+      TagsPrintRowsTemplate {
+         show_labels,
+         rows: &rows,
+      }
+      .render()
+   }
+}
+
+async fn tags_print_page(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(query): extract::Query<PrintQuery>,
+   headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+   let ids = query
+      .ids
+      .as_deref()
+      .map(|ids| {
+         ids.split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse::<TagUid>().map(|id| id.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+      })
+      .transpose()
+      .map_err(|_| StatusCode::BAD_REQUEST)?;
+   let filter = TagFilter {
+      ids,
+      kit: query.kit,
+      q: query.q,
+      ..Default::default()
+   };
+   // Printing every tag is never what was meant
+   if filter.ids.is_none()
+      && [&filter.kit, &filter.q]
+         .iter()
+         .all(|s| s.as_deref().is_none_or(|s| s.trim().is_empty()))
+   {
+      return Err(StatusCode::BAD_REQUEST);
+   }
+
+   let cols = query
+      .cols
+      .filter(|cols| (1..=MAX_PRINT_COLS).contains(cols))
+      .unwrap_or(DEFAULT_PRINT_COLS);
+   let show_labels = query.label.is_some_and(|label| label == "on");
+   let mut batches =
+      streaming::read_batches::<TwagTag>(state.reads.any().get(), filter.query(), cols * PRINT_ROWS_PER_BATCH);
+   let first = streaming::first_batch(&mut batches).await.map_err(|e| {
+      warn!("Failed to fetch tags from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+
+   let settings = state.settings.load();
+   let empty = first.is_empty();
+   let shell = render_shell(
+      &TagsPrintTemplate {
+         branding: &settings.branding,
+         cols,
+         empty,
+      },
+      empty,
+   )?;
+   let origin = public_origin(&state, &headers);
+   let rows = streaming::render_batches(first, batches, move |tags| {
+      let tags: Vec<PrintedTag> = tags
+         .into_iter()
+         .map(|tag| PrintedTag {
+            uri: scan_uri(&origin, &tag.id.to_string(), tag.count_token.as_deref()),
+            id: tag.id.to_string(),
+            label: tag.label,
+         })
+         .collect();
+      TagsPrintRowsTemplate::render_rows(&tags, cols, show_labels)
+   });
+   Ok(as_html(Response::new(shell.stream(rows, settings.minify_html))))
+}
+
+#[derive(Deserialize)]
+struct FaviconQuery {
+   host: String,
+}
+
+/// Serves a tag destination's favicon as a PNG, fetching it at most once per TTL. Only hosts some
+/// tag points at are fetched, so this can't be used to probe arbitrary hosts.
+async fn favicon_proxy(
+   extract::State(state): extract::State<AppState>,
+   extract::Query(query): extract::Query<FaviconQuery>,
+) -> Result<Response, StatusCode> {
+   let host = favicon::parse_host(&query.host).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+   let known = sqlx::query_scalar!(
+      r#"SELECT EXISTS (
+            SELECT 1 FROM twag_tags
+            WHERE lower(substring(target_url FROM '^https?://(?:[^@/]*@)?([^/:?#]+)')) = $1
+         ) AS "known!""#,
+      host,
+   )
+   .fetch_one(&state.reads.any().get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to look up favicon host in Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?;
+   if !known {
+      return Err(StatusCode::NOT_FOUND);
+   }
+
+   let cached = sqlx::query!(
+      r#"SELECT png, extract(epoch FROM fetched_at)::bigint AS "fetched_at!" FROM twag_favicons
+         WHERE host = $1"#,
+      host,
+   )
+   .fetch_optional(&state.reads.any().get())
+   .await
+   .map_err(|e| {
+      warn!("Failed to fetch cached favicon from Postgres: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+   })?
+   .map(|row| favicon::CachedIcon {
+      png: row.png,
+      fetched_at: row.fetched_at,
+   });
+
+   let png = match favicon::lookup(cached, chrono::Utc::now().timestamp()) {
+      favicon::Lookup::Serve(png) => Some(png),
+      favicon::Lookup::Missing => None,
+      favicon::Lookup::Refetch => {
+         let policy = state.settings.load().fetch_policy.clone();
+         let (png, error) = match favicon::fetch_icon(&host, &policy).await {
+            Ok(png) => (Some(png), None),
+            Err(e) => {
+               info!(%host, "Failed to fetch favicon: {}", e);
+               (None, Some(e.to_string()))
+            }
+         };
+         // The icon is still served if it can't be cached
+         if let Err(e) = sqlx::query!(
+            r#"INSERT INTO twag_favicons (host, png, error) VALUES ($1, $2, $3)
+               ON CONFLICT (host) DO UPDATE
+               SET png = EXCLUDED.png, error = EXCLUDED.error, fetched_at = current_timestamp"#,
+            host,
+            png.as_deref(),
+            error,
+         )
+         .execute(&state.pool.get())
+         .await
+         {
+            warn!("Failed to cache favicon in Postgres: {:?}", e);
+         }
+         png
+      }
+   };
+
+   match png {
+      Some(png) => Ok((
+         [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, favicon::CACHE_CONTROL),
+         ],
+         png,
+      )
+         .into_response()),
+      None => Ok((
+         StatusCode::NOT_FOUND,
+         [(header::CACHE_CONTROL, favicon::MISSING_CACHE_CONTROL)],
+      )
+         .into_response()),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use branding::HexColor;
+
+   /// Settings with nothing optional configured.
+   fn test_settings() -> SharedSettings {
+      SharedSettings::new(Settings {
+         branding: Branding::default(),
+         canonical_host: None,
+         fetch_policy: net::FetchPolicy::default(),
+         lookup_normalize_retry: false,
+         flush_stale_redirects: false,
+         server_timing: false,
+         minify_html: false,
+         maintenance_target_url: None,
+         expired_target_url: None,
+         max_location_len: target_url::DEFAULT_MAX_LOCATION_LEN,
+         age_gate_days: age_gate::DEFAULT_DAYS,
+         age_gate_decline_url: age_gate::DEFAULT_DECLINE_URL.to_string(),
+         reprogram: reprogram::Thresholds::default(),
+         trash_days: trash::DEFAULT_GRACE_DAYS,
+         strict_idn: false,
+         status_page: false,
+         stats_share_days: report::DEFAULT_SHARE_DAYS,
+         privacy_signals: privacy::Signals::Off,
+         quotas: quota::Quotas::default(),
+      })
+   }
+
+   /// An `AppState` with nothing optional configured, over `pool`.
+   fn test_state(pool: ScalingPool, route_docs: Vec<RouteDoc>) -> AppState {
+      AppState {
+         pool: pool.clone(),
+         client: None,
+         notion_picker: None,
+         visitor_hasher: None,
+         settings: test_settings(),
+         notion_outbox: false,
+         retention: Arc::default(),
+         mqtt: None,
+         webhook: None,
+         failover: Failover::default(),
+         preload: Preload { tags: 0 },
+         lookup_limiter: Arc::new(RateLimiter::new(LOOKUPS_PER_WINDOW, LOOKUP_WINDOW)),
+         negative_cache: NegativeCache::default(),
+         prober: fallback::SharedProber::default(),
+         app_links: Arc::default(),
+         tag_locks: Arc::default(),
+         geoip: None,
+         redaction: None,
+         telemetry: Telemetry::new(TelemetryConfig::from_vars(|_| None), std::io::sink).1,
+         cookie_key: None,
+         provisioning_key: None,
+         health_token: None,
+         unready: Arc::default(),
+         reads: ReadPools::new(pool, None),
+         route_docs: Arc::new(route_docs),
+      }
+   }
+
+   #[tokio::test]
+   async fn test_router_serves_exactly_the_documented_routes() {
+      use std::collections::{BTreeMap, BTreeSet};
+      use tower::Service;
+
+      let pool = ScalingPool::lazy();
+      let (router, route_docs) = build_router(&pool).into_parts();
+      let state = test_state(pool, route_docs.clone());
+      let mut app = router.with_state(state);
+
+      let mut documented: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+      for route in &route_docs {
+         let methods = documented.entry(route.path).or_default();
+         assert!(
+            methods.insert(route.method.as_str()),
+            "{} {} documented twice",
+            route.method.as_str(),
+            route.path
+         );
+         if route.method == Method::Get {
+            methods.insert("HEAD");
+         }
+      }
+
+      // Nothing answers TRACE, so every route reports what it does answer without running a handler.
+      for (path, methods) in documented {
+         let uri = path
+            .replace("{slug}", "055B88A23C1250")
+            .replace("{id}", "1")
+            .replace("{kit}", "Kitchen")
+            .replace("{token}", "x");
+         let request = axum::http::Request::builder()
+            .method("TRACE")
+            .uri(&uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+         let response = app.call(request).await.unwrap();
+         assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", path);
+         let allowed: BTreeSet<&str> = response.headers()[header::ALLOW].to_str().unwrap().split(',').collect();
+         assert_eq!(allowed, methods, "{}", path);
+      }
+
+      let request = axum::http::Request::builder()
+         .method("TRACE")
+         .uri("/undocumented")
+         .body(axum::body::Body::empty())
+         .unwrap();
+      assert_eq!(app.call(request).await.unwrap().status(), StatusCode::NOT_FOUND);
+   }
+
+   /// `id` created through the router, as the create form submits it.
+   fn create_request(id: &str) -> axum::http::Request<axum::body::Body> {
+      axum::http::Request::builder()
+         .method("POST")
+         .uri(format!("/tag/create?id={id}"))
+         .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+         .body(axum::body::Body::from("target_url=https%3A%2F%2Fexample.com%2F"))
+         .unwrap()
+   }
+
+   #[sqlx::test(migrations = "./migrations")]
+   #[ignore = "needs a Postgres at DATABASE_URL"]
+   async fn test_creating_an_existing_id_conflicts(pool: sqlx::PgPool) {
+      use tower::ServiceExt;
+
+      let pool = ScalingPool::from_pool(pool);
+      let (router, route_docs) = build_router(&pool).into_parts();
+      let app = router.with_state(test_state(pool, route_docs));
+
+      let created = app.clone().oneshot(create_request("055B88A23C1250")).await.unwrap();
+      assert_eq!(created.status(), StatusCode::OK);
+      let again = app.oneshot(create_request("055B88A23C1250")).await.unwrap();
+      assert_eq!(again.status(), StatusCode::CONFLICT);
+   }
+
+   /// An archived tag's row is moved out of `twag_tags` and back, and scans follow it.
+   #[sqlx::test(migrations = "./migrations")]
+   #[ignore = "needs a Postgres at DATABASE_URL"]
+   async fn test_archived_tags_are_retired_until_restored(pool: sqlx::PgPool) {
+      use tower::ServiceExt;
+
+      let pool = ScalingPool::from_pool(pool);
+      let (router, route_docs) = build_router(&pool).into_parts();
+      let app = router.with_state(test_state(pool.clone(), route_docs));
+      let post = |uri: &str| {
+         axum::http::Request::builder()
+            .method("POST")
+            .uri(uri)
+            .body(axum::body::Body::empty())
+            .unwrap()
+      };
+      let scan = || {
+         axum::http::Request::builder()
+            .uri("/tag/055B88A23C1250")
+            .extension(extract::ConnectInfo::<SocketAddr>("127.0.0.1:4000".parse().unwrap()))
+            .body(axum::body::Body::empty())
+            .unwrap()
+      };
+
+      let created = app.clone().oneshot(create_request("055B88A23C1250")).await.unwrap();
+      assert_eq!(created.status(), StatusCode::OK);
+
+      let archived = app.clone().oneshot(post("/tag/055B88A23C1250/archive")).await.unwrap();
+      assert_eq!(archived.status(), StatusCode::NO_CONTENT);
+      let tags: i64 = sqlx::query_scalar("SELECT count(*) FROM twag_tags")
+         .fetch_one(&pool.get())
+         .await
+         .unwrap();
+      assert_eq!(tags, 0);
+      assert_eq!(app.clone().oneshot(scan()).await.unwrap().status(), StatusCode::GONE);
+      let again = app.clone().oneshot(create_request("055B88A23C1250")).await.unwrap();
+      assert_eq!(again.status(), StatusCode::CONFLICT);
+
+      let restored = app.clone().oneshot(post("/tag/055B88A23C1250/restore")).await.unwrap();
+      assert_eq!(restored.status(), StatusCode::NO_CONTENT);
+      let scanned = app.clone().oneshot(scan()).await.unwrap();
+      assert!(scanned.status().is_redirection(), "{}", scanned.status());
+      assert_eq!(scanned.headers()[header::LOCATION], "https://example.com/");
+      let archive: i64 = sqlx::query_scalar("SELECT count(*) FROM twag_tags_archive")
+         .fetch_one(&pool.get())
+         .await
+         .unwrap();
+      assert_eq!(archive, 0);
+      let missing = app.oneshot(post("/tag/055B88A23C1250/restore")).await.unwrap();
+      assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+   }
+
+   /// A retried submission racing the first, as two instances would see them: each has its own
+   /// tag locks, so only the insert decides.
+   #[sqlx::test(migrations = "./migrations")]
+   #[ignore = "needs a Postgres at DATABASE_URL"]
+   async fn test_simultaneous_creations_make_one_tag(pool: sqlx::PgPool) {
+      use tower::ServiceExt;
+
+      let instance = || {
+         let pool = ScalingPool::from_pool(pool.clone());
+         let (router, route_docs) = build_router(&pool).into_parts();
+         let mut state = test_state(pool, route_docs);
+         state.notion_outbox = true;
+         router.with_state(state)
+      };
+      let (first, second) = tokio::join!(
+         instance().oneshot(create_request("055B88A23C1250")),
+         instance().oneshot(create_request("055B88A23C1250")),
+      );
+      let mut statuses = [first.unwrap().status(), second.unwrap().status()];
+      statuses.sort();
+      assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+
+      let tags: i64 = sqlx::query_scalar("SELECT count(*) FROM twag_tags")
+         .fetch_one(&pool)
+         .await
+         .unwrap();
+      assert_eq!(tags, 1);
+      let enqueued: i64 = sqlx::query_scalar("SELECT count(*) FROM twag_outbox")
+         .fetch_one(&pool)
+         .await
+         .unwrap();
+      assert_eq!(enqueued, 1);
+   }
+
+   /// Handlers taking substates run with only those built; no `AppState`, and no Postgres.
+   #[tokio::test]
+   async fn test_handlers_run_on_their_substates() {
+      let notion = NotionState { picker: None };
+      let query = PickerQuery { q: "drill".to_string() };
+      let response = search_notion_things(extract::State(notion), extract::Query(query))
+         .await
+         .unwrap();
+      assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+      let pool = ScalingPool::lazy();
+      let db = DbState {
+         reads: ReadPools::new(pool.clone(), None),
+         pool,
+      };
+      let cache = CacheState {
+         failover: Failover::default(),
+         preload: Preload { tags: 0 },
+         negative_cache: NegativeCache::default(),
+      };
+      let response = admin_preload_cache(extract::State(cache.clone()), extract::State(db.clone()))
+         .await
+         .unwrap();
+      assert_eq!(response.status(), StatusCode::CONFLICT);
+
+      let health = HealthState {
+         health_token: None,
+         unready: Arc::default(),
+         retention: Arc::default(),
+         mqtt: None,
+         cookie_key: None,
+         provisioning_key: None,
+      };
+      let query = HealthQuery {
+         deep: true,
+         token: None,
+      };
+      let (status, _) = health_check(
+         extract::State(db.clone()),
+         extract::State(cache.clone()),
+         extract::State(SettingsState(test_settings())),
+         extract::State(NotionState { picker: None }),
+         extract::State(health),
+         extract::Query(query),
+         HeaderMap::new(),
+      )
+      .await;
+      assert_eq!(status, StatusCode::FORBIDDEN);
+
+      // Known missing, so answered without a lookup
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      let now = std::time::Instant::now();
+      assert!(cache.negative_cache.record_miss(id, cache.negative_cache.ticket(), now));
+      let scan = ScanState {
+         settings: test_settings(),
+         visitor_hasher: None,
+         cookie_key: None,
+         geoip: None,
+         redaction: None,
+         prober: fallback::SharedProber::default(),
+         tag_locks: Arc::default(),
+         notion_outbox: false,
+         mqtt: None,
+         webhook: None,
+      };
+      let query = TagTapQuery { lang: None, ct: None };
+      let response = get_tag_by_id(
+         extract::State(db),
+         extract::State(cache),
+         extract::State(scan),
+         extract::ConnectInfo("127.0.0.1:4000".parse().unwrap()),
+         HeaderMap::new(),
+         extract::Path(id.to_string()),
+         extract::Query(query),
+      )
+      .await
+      .unwrap();
+      assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+      assert_eq!(response.headers()[header::LOCATION], "/tag/create?id=055B88A23C1250");
+   }
+
+   #[test]
+   fn test_create_page_renders_branding() {
+      let branding = Branding {
+         site_name: "The Cable House".to_string(),
+         accent_color: HexColor::new("#AA3300").unwrap(),
+         logo_url: Some("https://example.com/logo.png".to_string()),
+         footer_text: Some("Please put things back".to_string()),
+      };
+      let html = TagCreateTemplate {
+         branding: &branding,
+         action: "/tag/create?id=055B88A23C1250",
+         id: "055B88A23C1250",
+         tap_count: &None,
+         target_url: &None,
+         notion_page: &None,
+         tag_model: TagModel::Unknown,
+         redirect_kind: RedirectKind::Temporary,
+         times: &FormTimes::default(),
+         fallback: &FormFallback::default(),
+         notion_enabled: true,
+         error: None,
+         capacity_warnings: &[],
+      }
+      .render()
+      .unwrap();
+
+      assert!(html.contains("The Cable House"));
+      assert!(html.contains("--accent-color: #aa3300;"));
+      assert!(html.contains(r#"src="https://example.com/logo.png""#));
+      assert!(html.contains("<footer>Please put things back</footer>"));
+      assert!(html.contains("<h1>Creating 055B88A23C1250 · XQ ...</h1>"));
+   }
+
+   #[test]
+   fn test_create_page_renders_without_optional_branding() {
+      let branding = Branding::default();
+      let html = TagCreateTemplate {
+         branding: &branding,
+         action: "/tag/create?id=055B88A23C1250",
+         id: "055B88A23C1250",
+         tap_count: &None,
+         target_url: &None,
+         notion_page: &None,
+         tag_model: TagModel::Unknown,
+         redirect_kind: RedirectKind::Temporary,
+         times: &FormTimes::default(),
+         fallback: &FormFallback::default(),
+         notion_enabled: true,
+         error: None,
+         capacity_warnings: &[],
+      }
+      .render()
+      .unwrap();
+
+      assert!(html.contains("<strong>twag</strong>"));
+      assert!(!html.contains("<img"));
+      assert!(!html.contains("<footer>"));
+   }
+
+   #[test]
+   fn test_create_page_hides_notion_field_when_disabled() {
+      let branding = Branding::default();
+      let render = |notion_enabled| {
+         TagCreateTemplate {
+            branding: &branding,
+            action: "/tag/create?id=055B88A23C1250",
+            id: "055B88A23C1250",
+            tap_count: &None,
+            target_url: &None,
+            notion_page: &None,
+            tag_model: TagModel::Unknown,
+            redirect_kind: RedirectKind::Temporary,
+            times: &FormTimes::default(),
+            fallback: &FormFallback::default(),
+            notion_enabled,
+            error: None,
+            capacity_warnings: &[],
+         }
+         .render()
+         .unwrap()
+      };
+
+      assert!(render(true).contains(r#"name="notion_page""#));
+      assert!(!render(false).contains(r#"name="notion_page""#));
+   }
+
+   #[test]
+   fn test_create_page_keeps_the_chosen_model() {
+      let branding = Branding::default();
+      let html = TagCreateTemplate {
+         branding: &branding,
+         action: "/tag/create?id=055B88A23C1250",
+         id: "055B88A23C1250",
+         tap_count: &None,
+         target_url: &None,
+         notion_page: &None,
+         tag_model: TagModel::Ntag215,
+         redirect_kind: RedirectKind::SeeOther,
+         times: &FormTimes {
+            activates_at: String::new(),
+            expires_at: "2026-10-20T18:00".to_string(),
+         },
+         fallback: &FormFallback {
+            url: "https://backup.example.com/".to_string(),
+            check_target: true,
+         },
+         notion_enabled: false,
+         error: Some("Target URL is not valid".to_string()),
+         capacity_warnings: &[],
+      }
+      .render()
+      .unwrap();
+
+      assert!(html.contains(r#"<option value="ntag215" selected>NTAG215</option>"#));
+      assert!(html.contains(r#"<option value="qr_only">QR code only</option>"#));
+      assert!(html.contains(r#"<option value="see_other" selected>See other (303)</option>"#));
+      assert!(html.contains(r#"value="2026-10-20T18:00""#));
+      assert!(html.contains(r#"value="https://backup.example.com/""#));
+      assert!(html.contains(r#"<option value="true" selected>"#));
+   }
+
+   #[test]
+   fn test_form_times_are_utc_and_blank_is_none() {
+      assert_eq!(
+         parse_form_time("2026-10-20T18:00", "The expiry"),
+         Ok(Some("2026-10-20T18:00:00Z".parse().unwrap()))
+      );
+      assert_eq!(parse_form_time(" ", "The expiry"), Ok(None));
+      assert_eq!(
+         parse_form_time("2026-10-20", "The activation time"),
+         Err("The activation time must be a date and time in UTC, like 2026-10-20T18:00.".to_string())
+      );
+   }
+
+   #[test]
+   fn test_create_page_shows_capacity_warnings() {
+      let branding = Branding::default();
+      let id: TagUid = "055B88A23C1250AA01BB".parse().unwrap();
+      let capacity_warnings =
+         ndef::capacity_warnings(&ndef::programmed_uri(&format!("https://{}", "h".repeat(120)), &id));
+      let html = TagCreateTemplate {
+         branding: &branding,
+         action: &create_action(&id.to_string(), None, None),
+         id: &id.to_string(),
+         tap_count: &None,
+         target_url: &None,
+         notion_page: &None,
+         tag_model: TagModel::Unknown,
+         redirect_kind: RedirectKind::Temporary,
+         times: &FormTimes::default(),
+         fallback: &FormFallback::default(),
+         notion_enabled: true,
+         error: None,
+         capacity_warnings: &capacity_warnings,
+      }
+      .render()
+      .unwrap();
+
+      assert!(html.contains("needs 160 bytes, but an NTAG213 only holds 144"));
+      assert!(html.contains(r#"<button type="submit">"#));
+   }
+
+   #[test]
+   fn test_create_form_keeps_its_creation_link() {
+      assert_eq!(
+         create_action("055B88A23C1250", Some("00000F"), None),
+         "/tag/create?id=055B88A23C1250&tap_count=00000F"
+      );
+      let link = provision::Link {
+         exp: 1_794_744_000,
+         sig: "ab12".to_string(),
+      };
+      assert_eq!(
+         create_action("055B88A23C1250", None, Some(&link)),
+         "/tag/create?id=055B88A23C1250&exp=1794744000&sig=ab12"
+      );
+      // Whatever was typed as the id stays in the query string
+      assert_eq!(create_action("a&sig=x", None, None), "/tag/create?id=a%26sig%3Dx");
+   }
+
+   #[test]
+   fn test_duplicate_creates_point_at_the_existing_tag() {
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      let page = |trashed| {
+         TagExistsTemplate {
+            branding: &Branding::default(),
+            id: &id,
+            target_url: "https://example.com/menu",
+            trashed,
+         }
+         .render()
+         .unwrap()
+      };
+      let live = page(false);
+      assert!(live.contains("example.com/menu"), "{live}");
+      assert!(live.contains(r#"href="/tag/055B88A23C1250/edit""#), "{live}");
+      let trashed = page(true);
+      assert!(trashed.contains(r#"href="/tags/trash""#), "{trashed}");
+      assert!(!trashed.contains("/edit"), "{trashed}");
+   }
+
+   #[test]
+   fn test_edit_urls_take_scan_slugs() {
+      let slug = admin_slug("055B88A23C1250x00000F").unwrap();
+      assert_eq!(slug.id.to_string(), "055B88A23C1250");
+      assert_eq!(slug.tap_count, Some(15));
+      assert_eq!(admin_slug("055B88A23C1250.vcf").unwrap_err(), StatusCode::NOT_FOUND);
+      assert_eq!(admin_slug("055b88a23c1250").unwrap_err(), StatusCode::NOT_FOUND);
+
+      let location = |slug| create_instead(&admin_slug(slug).unwrap()).headers()[header::LOCATION].clone();
+      assert_eq!(
+         location("055B88A23C1250x00000F"),
+         "/tag/create?id=055B88A23C1250&tap_count=00000F"
+      );
+      assert_eq!(location("055B88A23C1250"), "/tag/create?id=055B88A23C1250");
+
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      assert_eq!(edit_path(&id, Some(15)), "/tag/055B88A23C1250/edit?tap_count=00000F");
+      assert_eq!(edit_path(&id, None), "/tag/055B88A23C1250/edit");
+   }
+
+   fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+      let pairs: Vec<(String, String)> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+      move |name| pairs.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+   }
+
+   const FULL_NOTION_VARS: &[(&str, &str)] = &[
+      ("NOTION_TOKEN", "secret_abc"),
+      ("NOTION_THINGS_DB", "a1b2c3d4e5f67890abcdef1234567890"),
+      ("NOTION_THINGS_COLUMN_NAME", "Container"),
+      ("NOTION_THINGS_DS", "ds-things"),
+      ("NOTION_CONTAINERS_DB", "0a1b2c3d4e5f67890abcdef123456789"),
+      ("NOTION_CONTAINERS_COLUMN_NAME", "Contents"),
+      ("NOTION_CONTAINERS_DS", "ds-containers"),
+   ];
+
+   #[test]
+   fn test_notion_config_absent_without_any_notion_vars() {
+      assert_eq!(NotionConfig::from_vars(vars(&[])).unwrap(), None);
+      assert_eq!(NotionConfig::from_vars(vars(&[("NOTION_TOKEN", "")])).unwrap(), None);
+   }
+
+   #[test]
+   fn test_notion_config_explicitly_disabled() {
+      let mut pairs = FULL_NOTION_VARS.to_vec();
+      pairs.push(("TWAG_NOTION_ENABLED", "false"));
+      assert_eq!(NotionConfig::from_vars(vars(&pairs)).unwrap(), None);
+   }
+
+   #[test]
+   fn test_notion_config_demands_everything_once_enabled() {
+      let config = NotionConfig::from_vars(vars(FULL_NOTION_VARS)).unwrap().unwrap();
+      assert_eq!(&*config.things_column, "Container");
+      assert_eq!(config.containers_db.as_str(), "0a1b2c3d-4e5f-6789-0abc-def123456789");
+
+      let partial = vars(&[("NOTION_TOKEN", "secret_abc")]);
+      assert_eq!(
+         NotionConfig::from_vars(partial).unwrap_err(),
+         "NOTION_THINGS_DB must be set"
+      );
+   }
+
+   const HOSTILE: &str = "\"><script>alert(1)</script>";
+   const SPOOFED: &str = "https://example.com/\u{202E}gpj.exe";
+
+   /// The markup a hostile value could only produce by escaping its context.
+   fn assert_inert(html: &str) {
+      assert!(!html.contains("<script>alert"), "{html}");
+      assert!(!html.contains("\"><"), "{html}");
+      let link = lazy_regex::regex!(r#"(?:(?:href|src|action)="|url=)([^"]*)"#);
+      for link in link.captures_iter(html) {
+         let url = link[1].trim().to_ascii_lowercase();
+         assert!(!url.starts_with("javascript:"), "script URL in {:?}", &link[0]);
+         assert!(!url.contains('\u{202E}'), "direction override in {:?}", &link[0]);
+      }
+   }
+
+   #[test]
+   fn test_strict_import_refusals_report_every_row() {
+      let records = import::parse("id,target_url\n055B88A23C1250,https://example.com\nnot a tag,\n").unwrap();
+      let rows = import::refusal(&records, false, false).unwrap();
+      let html = TagsImportRefusedTemplate {
+         branding: &Branding::default(),
+         rows: &rows,
+         failed: 1,
+      }
+      .render()
+      .unwrap();
+
+      assert!(html.contains("1 of 2 row(s) failed"), "{html}");
+      assert!(html.contains("<td>2</td>\n      <td><code>055B88A23C1250</code></td>\n      <td>not imported</td>"));
+      assert!(html.contains("<td>3</td>\n      <td><code>not a tag</code></td>\n      <td>failed</td>"));
+   }
+
+   #[test]
+   fn test_status_page_shows_only_labels_and_times() {
+      let now = chrono::Utc::now();
+      let row = public_status::Row {
+         label: Some("Laundry machine".to_string()),
+         last_accessed: Some(now - chrono::TimeDelta::hours(2)),
+         access_count: Some(41),
+         public_show_count: false,
+      };
+      let page = PublicStatusTemplate {
+         branding: &Branding::default(),
+         entries: &[public_status::Entry::new(row, now)],
+         refresh_secs: public_status::MAX_AGE_SECS,
+      }
+      .render()
+      .unwrap();
+      assert!(page.contains("Laundry machine"));
+      assert!(page.contains("2 hours ago"));
+      assert!(page.contains(r#"<meta http-equiv="refresh" content="30" />"#));
+      assert!(!page.contains("41"));
+   }
+
+   #[test]
+   fn test_critical_pages_render() {
+      assert_eq!(render_critical_pages(&Branding::default()), Ok("7 pages".to_string()));
+   }
+
+   #[test]
+   fn test_hostile_target_urls_render_inert() {
+      let branding = Branding::default();
+      for target_url in [HOSTILE, "javascript:alert(1)", " JAVASCRIPT:alert(1)", SPOOFED] {
+         let flush = TagFlushTemplate {
+            branding: &branding,
+            target_url,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&flush);
+
+         let gate = TagAgeGateTemplate {
+            branding: &branding,
+            text: HOSTILE,
+            action: &format!("/tag/055B88A23C1250/go?ct={}", HOSTILE),
+         }
+         .render()
+         .unwrap();
+         assert_inert(&gate);
+
+         let arrival = TagArrivalTemplate {
+            branding: &branding,
+            target_url,
+            beacon_url: &format!("/tag/055B88A23C1250/arrived?t={}", HOSTILE),
+         }
+         .render()
+         .unwrap();
+         assert_inert(&arrival);
+
+         let report = report::Report {
+            id: "055B88A23C1250".parse().unwrap(),
+            label: Some(HOSTILE.to_string()),
+            kit: Some(HOSTILE.to_string()),
+            target_url: target_url.to_string(),
+            notion_page_id: None,
+            created_on: None,
+            programmed_on: None,
+            expires_at: None,
+            deleted: false,
+            audit: vec![report::AuditEntry {
+               at: "2026-10-16 12:00".to_string(),
+               action: "retarget".to_string(),
+               before: Some(HOSTILE.to_string()),
+               after: Some(target_url.to_string()),
+               actor: HOSTILE.to_string(),
+            }],
+            months: Vec::new(),
+            epochs: Vec::new(),
+         };
+         let report = TagReportTemplate {
+            branding: &branding,
+            report: &report,
+            notion_title: Some(HOSTILE.to_string()),
+            shared_until: None,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&report);
+
+         let share = TagReportShareTemplate {
+            branding: &branding,
+            id: "055B88A23C1250",
+            share_url: target_url,
+            expires: HOSTILE,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&share);
+
+         let link = TagLinkTemplate {
+            branding: &branding,
+            target_url,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&link);
+
+         let stats = TagStatsTemplate {
+            branding: &branding,
+            id: "055B88A23C1250",
+            target_url,
+            notion_url: Some(target_url.to_string()),
+            short_code: Some(HOSTILE.to_string()),
+            days: &[DailyStats {
+               day: HOSTILE.to_string(),
+               taps: 1,
+               uncounted: 0,
+               approx_unique: None,
+            }],
+            review_state: ReviewState::NeedsReview,
+            review_tap_count: Some(3),
+            last_seen_tap_count: Some(4000),
+            arrival: Some(arrival::Rate {
+               confirmed: 9,
+               settled: 10,
+            }),
+            share_days: 7,
+            privacy_limited: 2,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&stats);
+         assert!(stats.contains("2 tap(s) came from browsers asking not to be tracked"));
+         assert!(stats.contains(r#"<form method="post" action="/tag/055B88A23C1250/clone">"#));
+         assert!(
+            stats.contains(r#"<script type="application/json" id="daily-stats">[{"day":"\"\u003e\u003cscript\u003e"#)
+         );
+
+         let shared_stats = TagStatsSharedTemplate {
+            branding: &branding,
+            label: Some(HOSTILE.to_string()),
+            access_count: 1,
+            days: &[DailyStats {
+               day: HOSTILE.to_string(),
+               taps: 1,
+               uncounted: 0,
+               approx_unique: None,
+            }],
+            most_taps: 1,
+            shared_until: HOSTILE,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&shared_stats);
+         assert!(!shared_stats.contains(target_url));
+
+         let creation = provision::Link {
+            exp: 1,
+            sig: HOSTILE.to_string(),
+         };
+         let create = TagCreateTemplate {
+            branding: &branding,
+            action: &create_action("055B88A23C1250", Some(HOSTILE), Some(&creation)),
+            id: "055B88A23C1250",
+            tap_count: &Some("00000F".to_string()),
+            target_url: &Some(target_url.to_string()),
+            notion_page: &Some(HOSTILE.to_string()),
+            tag_model: TagModel::Unknown,
+            redirect_kind: RedirectKind::Temporary,
+            times: &FormTimes {
+               activates_at: HOSTILE.to_string(),
+               expires_at: HOSTILE.to_string(),
+            },
+            fallback: &FormFallback {
+               url: HOSTILE.to_string(),
+               check_target: true,
+            },
+            notion_enabled: true,
+            error: Some(HOSTILE.to_string()),
+            capacity_warnings: &[],
+         }
+         .render()
+         .unwrap();
+         assert_inert(&create);
+
+         let edit = TagEditTemplate {
+            branding: &branding,
+            id: "055B88A23C1250".parse().unwrap(),
+            target_url: HOSTILE,
+            access_count: HOSTILE,
+            enabled: false,
+            redirect_kind: RedirectKind::Permanent,
+            times: &FormTimes {
+               activates_at: HOSTILE.to_string(),
+               expires_at: HOSTILE.to_string(),
+            },
+            fallback: &FormFallback {
+               url: HOSTILE.to_string(),
+               check_target: true,
+            },
+            error: Some(HOSTILE),
+         }
+         .render()
+         .unwrap();
+         assert_inert(&edit);
+
+         let delete = TagDeleteTemplate {
+            branding: &branding,
+            id: "055B88A23C1250".parse().unwrap(),
+            target_url: HOSTILE,
+            trash_days: 30,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&delete);
+
+         let exists = TagExistsTemplate {
+            branding: &branding,
+            id: &"055B88A23C1250".parse().unwrap(),
+            target_url: HOSTILE,
+            trashed: false,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&exists);
+
+         let provisioning = AdminProvisioningTemplate {
+            branding: &branding,
+            enabled: true,
+            ids: HOSTILE,
+            days: 30,
+            links: &[(HOSTILE.to_string(), target_url.to_string())],
+            rejected: &[(HOSTILE.to_string(), HOSTILE.to_string())],
+            expires: Some(HOSTILE.to_string()),
+         }
+         .render()
+         .unwrap();
+         assert_inert(&provisioning);
+
+         let upcoming = AdminUpcomingTemplate {
+            branding: &branding,
+            banner: &maintenance::Banner::default(),
+            days: 7,
+            transitions: &[upcoming::Transition {
+               id: "055B88A23C1250".parse().unwrap(),
+               label: Some(HOSTILE.to_string()),
+               at: chrono::Utc::now(),
+               cause: upcoming::Cause::Expiry,
+               from: target_url.to_string(),
+               to: HOSTILE.to_string(),
+            }],
+            dry_run: true,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&upcoming);
+
+         let period = dashboard::Period::parse(None, None, chrono::Utc::now().date_naive()).unwrap();
+         let rows = [dashboard::TagDay {
+            id: "055B88A23C1250".parse().unwrap(),
+            label: Some(HOSTILE.to_string()),
+            day: period.to,
+            taps: 1,
+         }];
+         let stats = StatsTemplate {
+            branding: &branding,
+            kit: Some(HOSTILE),
+            dashboard: &dashboard::summarize(&period, &rows, 1, 0, Vec::new()),
+            usage: None,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&stats);
+
+         let status = PublicStatusTemplate {
+            branding: &branding,
+            entries: &[public_status::Entry {
+               label: HOSTILE.to_string(),
+               scanned: "2 hours ago".to_string(),
+               freshness: public_status::Freshness::Recent,
+               count: None,
+            }],
+            refresh_secs: public_status::MAX_AGE_SECS,
+         }
+         .render()
+         .unwrap();
+         assert_inert(&status);
+
+         let listing = render_shell(
+            &TagListTemplate {
+               branding: &branding,
+               banner: &maintenance::Banner::default(),
+               filter: &TagFilter {
+                  q: Some(HOSTILE.to_string()),
+                  kit: Some(HOSTILE.to_string()),
+                  ..Default::default()
+               },
+               empty: false,
+               more: true,
+            },
+            false,
+         )
+         .unwrap();
+         let rows = TagRowsTemplate {
+            tags: &[(
+               TagRow {
+                  id: "055B88A23C1250".to_string(),
+                  label: Some(HOSTILE.to_string()),
+                  target_url: target_url.to_string(),
+                  kit: Some(HOSTILE.to_string()),
+                  access_count: Some(1),
+                  maintenance: false,
+                  stateful: false,
+                  enabled: false,
+                  expires_at: None,
+                  activates_at: Some(HOSTILE.to_string()),
+                  last_accessed: Some(HOSTILE.to_string()),
+                  count_token: None,
+                  review_state: "needs_review".to_string(),
+               },
+               Some(HOSTILE.to_string()),
+            )],
+         }
+         .render()
+         .unwrap();
+         assert_inert(&listing.join(&rows));
+
+         let ids = ["055B88A23C1250".parse().unwrap()];
+         for action in [
+            Action::Retarget(target_url.to_string()),
+            Action::SetKit(Some(HOSTILE.to_string())),
+         ] {
+            let confirm = TagsBulkConfirmTemplate {
+               branding: &branding,
+               action: &action,
+               ids: &ids,
+            }
+            .render()
+            .unwrap();
+            assert_inert(&confirm);
+
+            let result = TagsBulkResultTemplate {
+               branding: &branding,
+               action: Some(&action),
+               error: Some(HOSTILE.to_string()),
+               results: &[RowResult::rejected(HOSTILE, HOSTILE.to_string())],
+            }
+            .render()
+            .unwrap();
+            assert_inert(&result);
+         }
+      }
+   }
+
+   #[test]
+   fn test_hostile_contacts_render_inert() {
+      let branding = Branding::default();
+      let contact = Contact {
+         name: HOSTILE.to_string(),
+         org: Some(HOSTILE.to_string()),
+         phone: Some(HOSTILE.to_string()),
+         email: Some(format!("{HOSTILE}@example.com")),
+         url: Some("javascript:alert(1)".to_string()),
+         note: Some(SPOOFED.to_string()),
+      };
+      let html = TagVcardTemplate {
+         branding: &branding,
+         id: "055B88A23C1250",
+         contact: &contact,
+      }
+      .render()
+      .unwrap();
+      assert_inert(&html);
+      assert!(html.contains(r#"<a href="#"><bdi>javascript:alert(1)</bdi></a>"#));
+   }
+
+   #[test]
+   fn test_hostile_branding_renders_inert() {
+      let branding = Branding {
+         site_name: HOSTILE.to_string(),
+         logo_url: Some("javascript:alert(1)".to_string()),
+         footer_text: Some(HOSTILE.to_string()),
+         ..Branding::default()
+      };
+      let html = TagWriteTemplate {
+         branding: &branding,
+         id: "055B88A23C1250",
+         tag_model: TagModel::Unknown,
+      }
+      .render()
+      .unwrap();
+      assert_inert(&html);
+      assert!(html.contains(r#"const id = "055B88A23C1250";"#));
+   }
+
+   #[test]
+   fn test_write_page_has_nothing_to_write_on_a_printed_code() {
+      let branding = Branding::default();
+      let render = |tag_model| {
+         TagWriteTemplate {
+            branding: &branding,
+            id: "055B88A23C1250",
+            tag_model,
+         }
+         .render()
+         .unwrap()
+      };
+      let printed = render(TagModel::QrOnly);
+      assert!(printed.contains("nothing to write"));
+      assert!(!printed.contains("NDEFReader"));
+      assert!(render(TagModel::Ntag215).contains("NDEFReader"));
+   }
+
+   #[test]
+   fn test_lookup_box_echoes_rejected_input_inert() {
+      let branding = Branding::default();
+      let error = models::parse_tag_input(HOSTILE, &[]).unwrap_err().to_string();
+      let html = IndexTemplate {
+         branding: &branding,
+         q: HOSTILE,
+         error: Some(&error),
+      }
+      .render()
+      .unwrap();
+      assert_inert(&html);
+      assert!(html.contains(r#"action="/lookup""#));
+
+      let html = TagNotFoundTemplate {
+         branding: &branding,
+         id: &"055B88A23C1250".parse().unwrap(),
+      }
+      .render()
+      .unwrap();
+      assert!(html.contains("There's no tag 055B88A23C1250 · XQ here."));
+   }
+
+   #[test]
+   fn test_print_sheet_inlines_svg_and_escapes_labels() {
+      let tags = [PrintedTag {
+         id: "055B88A23C1250".to_string(),
+         label: Some(HOSTILE.to_string()),
+         uri: format!("https://xz.ws/tag/055B88A23C1250?ct={HOSTILE}"),
+      }];
+      let shell = render_shell(
+         &TagsPrintTemplate {
+            branding: &Branding::default(),
+            cols: 4,
+            empty: false,
+         },
+         false,
+      )
+      .unwrap();
+      let html = shell.join(&TagsPrintRowsTemplate::render_rows(&tags, 4, true).unwrap());
+      // The SVG's own markup trips `assert_inert`, so check the label directly
+      assert!(!html.contains(HOSTILE));
+      assert!(!html.contains("<script>alert"));
+      assert_eq!(html.matches("<svg xmlns=").count(), 1);
+      assert!(html.contains("055B88A23C1250 · XQ"));
+      assert!(html.contains("--cols: 4;"));
+   }
+
+   fn tag_row(n: usize) -> TagRow {
+      TagRow {
+         id: format!("04{n:012X}"),
+         label: Some(format!("Tag {n}")),
+         target_url: format!("https://example.com/{n}"),
+         kit: (n % 2 == 0).then(|| "Kitchen".to_string()),
+         access_count: Some(n as i32),
+         maintenance: false,
+         stateful: false,
+         enabled: true,
+         expires_at: None,
+         activates_at: None,
+         last_accessed: None,
+         count_token: None,
+         review_state: "ok".to_string(),
+      }
+   }
+
+   fn printed_tag(n: usize) -> PrintedTag {
+      PrintedTag {
+         id: format!("04{n:012X}"),
+         label: Some(format!("Tag {n}")),
+         uri: format!("https://xz.ws/tag/04{n:012X}"),
+      }
+   }
+
+   /// Sends `tags` through a streamed page in batches of `size`, as the handlers do, and collects
+   /// the body.
+   async fn streamed<T: Send + 'static>(
+      shell: streaming::Shell,
+      mut tags: Vec<T>,
+      size: usize,
+      minified: bool,
+      render: impl Fn(Vec<T>) -> askama::Result<String> + Send + 'static,
+   ) -> String {
+      let (sender, mut batches) = tokio::sync::mpsc::channel(tags.len() / size + 1);
+      while !tags.is_empty() {
+         let rest = tags.split_off(size.min(tags.len()));
+         sender.send(Ok(std::mem::replace(&mut tags, rest))).await.unwrap();
+      }
+      drop(sender);
+      let first = streaming::first_batch(&mut batches).await.unwrap();
+      let body = shell.stream(streaming::render_batches(first, batches, render), minified);
+      String::from_utf8(axum::body::to_bytes(body, usize::MAX).await.unwrap().to_vec()).unwrap()
+   }
+
+   fn listing_shell(empty: bool) -> streaming::Shell {
+      let page = TagListTemplate {
+         branding: &Branding::default(),
+         banner: &maintenance::Banner::default(),
+         filter: &TagFilter::default(),
+         empty,
+         more: false,
+      };
+      render_shell(&page, empty).unwrap()
+   }
+
+   #[tokio::test]
+   async fn test_streamed_listing_matches_buffered() {
+      let tags: Vec<TagRow> = (0..450).map(tag_row).collect();
+      let buffered = listing_shell(false).join(&TagRowsTemplate::render_rows(tags.clone()).unwrap());
+      assert_eq!(buffered.matches("name=\"ids\"").count(), 450);
+      let streamed_page = streamed(
+         listing_shell(false),
+         tags.clone(),
+         streaming::BATCH,
+         false,
+         TagRowsTemplate::render_rows,
+      );
+      assert_eq!(streamed_page.await, buffered);
+      let minified = streamed(
+         listing_shell(false),
+         tags,
+         streaming::BATCH,
+         true,
+         TagRowsTemplate::render_rows,
+      );
+      assert_eq!(minified.await, streaming::minify(&buffered));
+
+      let empty = streamed(
+         listing_shell(true),
+         Vec::new(),
+         streaming::BATCH,
+         false,
+         TagRowsTemplate::render_rows,
+      );
+      assert!(empty.await.contains("No tags found."));
+   }
+
+   #[tokio::test]
+   async fn test_streamed_print_sheet_matches_buffered() {
+      let shell = || {
+         let page = TagsPrintTemplate {
+            branding: &Branding::default(),
+            cols: 3,
+            empty: false,
+         };
+         render_shell(&page, false).unwrap()
+      };
+      let tags: Vec<PrintedTag> = (0..100).map(printed_tag).collect();
+      let buffered = shell().join(&TagsPrintRowsTemplate::render_rows(&tags, 3, true).unwrap());
+      // 34 rows, the last of one code
+      assert_eq!(buffered.matches("<div class=\"row\">").count(), 34);
+      for minified in [false, true] {
+         let tags = (0..100).map(printed_tag).collect();
+         let page = streamed(shell(), tags, 3 * PRINT_ROWS_PER_BATCH, minified, |tags| {
+            TagsPrintRowsTemplate::render_rows(&tags, 3, true)
+         });
+         let expected = match minified {
+            true => streaming::minify(&buffered),
+            false => buffered.clone(),
+         };
+         assert_eq!(page.await, expected);
+      }
+   }
+
+   /// Time to the first byte and to the last, buffered and streamed, for listings of 1k to 50k
+   /// rows; Postgres is left out, so this is rendering alone. Run with
+   /// `cargo test --release bench_listing -- --ignored --nocapture`.
+   #[tokio::test]
+   #[ignore]
+   async fn bench_listing() {
+      use futures_util::StreamExt;
+      use std::time::Instant;
+
+      for rows in [1_000, 10_000, 50_000] {
+         let tags: Vec<TagRow> = (0..rows).map(tag_row).collect();
+
+         let started = Instant::now();
+         let buffered = listing_shell(false).join(&TagRowsTemplate::render_rows(tags.clone()).unwrap());
+         let buffered_time = started.elapsed();
+
+         let started = Instant::now();
+         let (sender, mut batches) = tokio::sync::mpsc::channel(1);
+         let mut rest = tags;
+         tokio::spawn(async move {
+            while !rest.is_empty() {
+               let tail = rest.split_off(streaming::BATCH.min(rest.len()));
+               if sender.send(Ok(std::mem::replace(&mut rest, tail))).await.is_err() {
+                  return;
+               }
+            }
+         });
+         let first = streaming::first_batch(&mut batches).await.unwrap();
+         let rows_stream = streaming::render_batches(first, batches, TagRowsTemplate::render_rows);
+         let mut body = listing_shell(false).stream(rows_stream, false).into_data_stream();
+         let mut len = body.next().await.unwrap().unwrap().len();
+         let first_byte = started.elapsed();
+         while let Some(chunk) = body.next().await {
+            len += chunk.unwrap().len();
+         }
+         let streamed_time = started.elapsed();
+         assert_eq!(len, buffered.len());
+
+         println!(
+            "{rows} rows: buffered {buffered_time:?} to the first byte and the last; \
+             streamed {first_byte:?} to the first, {streamed_time:?} to the last"
+         );
+      }
+   }
+
+   /// A lint over the template sources: every URL attribute goes through `safe_href`, everything
+   /// interpolated into a script goes through `script_json`, and nothing is marked `safe`.
+   #[test]
+   fn test_templates_escape_by_context() {
+      let url_attr = lazy_regex::regex!(r#"(?:(?:href|src|action)="|url=)([^"{]*)(\{\{.*?\}\})?"#);
+      let interpolation = lazy_regex::regex!(r"\{\{([^}]*)\}\}");
+      let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/templates");
+      for entry in std::fs::read_dir(dir).unwrap() {
+         let path = entry.unwrap().path();
+         let source = std::fs::read_to_string(&path).unwrap();
+         let name = path.file_name().unwrap().to_string_lossy();
+
+         for attr in url_attr.captures_iter(&source) {
+            if let Some(expr) = attr.get(2) {
+               assert!(
+                  attr[1].is_empty() && expr.as_str().trim_end_matches("}}").trim_end().ends_with("|safe_href"),
+                  "{name}: URL not built with safe_href: {:?}",
+                  &attr[0]
+               );
+            }
+         }
+         for script in source.split("<script").skip(1) {
+            let body = script.split("</script>").next().unwrap();
+            for expr in interpolation.captures_iter(body) {
+               assert!(
+                  expr[1].trim_end().ends_with("|script_json"),
+                  "{name}: script data not escaped with script_json: {:?}",
+                  &expr[0]
+               );
+            }
+         }
+         assert!(
+            !lazy_regex::regex_is_match!(r"\|\s*safe\b", &source),
+            "{name}: `safe` bypasses escaping"
+         );
+      }
+   }
 }
@@ -0,0 +1,56 @@
+/// What a scan resolves to while maintenance is active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Maintenance<'a> {
+   Redirect(&'a str),
+   /// The built-in maintenance page, for a tag in maintenance with no global URL to send it to.
+   Page,
+}
+
+/// The global URL puts every tag into maintenance, and takes precedence over the per-tag flag.
+pub fn resolve(global_url: Option<&str>, tag_in_maintenance: bool) -> Option<Maintenance<'_>> {
+   match (global_url, tag_in_maintenance) {
+      (Some(url), _) => Some(Maintenance::Redirect(url)),
+      (None, true) => Some(Maintenance::Page),
+      (None, false) => None,
+   }
+}
+
+/// Shown atop admin pages while anything is in maintenance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Banner {
+   pub global_url: Option<String>,
+   /// Tags individually in maintenance.
+   pub tags: i64,
+}
+
+impl Banner {
+   pub fn is_active(&self) -> bool { self.global_url.is_some() || self.tags > 0 }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_global_url_wins() {
+      let url = "https://status.example.com/";
+      assert_eq!(resolve(Some(url), false), Some(Maintenance::Redirect(url)));
+      assert_eq!(resolve(Some(url), true), Some(Maintenance::Redirect(url)));
+   }
+
+   #[test]
+   fn test_tag_flag_without_global_url_falls_back_to_page() {
+      assert_eq!(resolve(None, true), Some(Maintenance::Page));
+   }
+
+   #[test]
+   fn test_inactive() {
+      assert_eq!(resolve(None, false), None);
+      assert!(!Banner::default().is_active());
+      assert!(Banner {
+         global_url: None,
+         tags: 1,
+      }
+      .is_active());
+   }
+}
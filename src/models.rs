@@ -1,101 +1,237 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::hash::{Hash, Hasher};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgRow, PgTypeInfo, PgValueRef, Postgres};
+use sqlx::Row;
 use std::ops::Deref;
 use std::str::FromStr;
 use url::{Host, Url};
 use uuid::Uuid;
 
-/// A type representing a fixed-length, 14-character hexadecimal string.
-#[derive(sqlx::Type, Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
-#[sqlx(type_name = "hex_14", transparent)]
-pub struct Hex14(String);
+/// A tag's NFC UID, as read from either a 7-byte or a 10-byte tag; rendered as uppercase hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TagUid {
+   Seven([u8; 7]),
+   Ten([u8; 10]),
+}
 
 #[derive(Debug, thiserror::Error)]
-pub enum Hex14Error {
-   #[error("Invalid length: expected 14 characters, got {0}")]
+pub enum TagUidError {
+   #[error("Invalid length: expected 14 or 20 characters, got {0}")]
    InvalidLength(usize),
    #[error("Invalid character: expected hex digit, found '{0}'")]
    InvalidCharacter(char),
 }
 
-impl Hex14 {
-   pub fn new(s: impl Into<String>) -> Result<Self, Hex14Error> {
-      let s = s.into();
-      if s.len() != 14 {
-         return Err(Hex14Error::InvalidLength(s.len()));
+fn decode_hex<const N: usize>(s: &str) -> [u8; N] {
+   let mut bytes = [0u8; N];
+   for (i, byte) in bytes.iter_mut().enumerate() {
+      *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+   }
+   bytes
+}
+
+impl TagUid {
+   pub fn new(s: impl AsRef<str>) -> Result<Self, TagUidError> {
+      let s = s.as_ref();
+      if s.len() != 14 && s.len() != 20 {
+         return Err(TagUidError::InvalidLength(s.len()));
       }
-      if s.chars().any(|c| !c.is_ascii_hexdigit()) {
-         return Err(Hex14Error::InvalidCharacter(
-            s.chars().find(|&c| !c.is_ascii_hexdigit()).unwrap(),
-         ));
+      if let Some(c) = s.chars().find(|c| !c.is_ascii_hexdigit()) {
+         return Err(TagUidError::InvalidCharacter(c));
       }
-      Ok(Hex14(s.to_uppercase()))
+      Ok(match s.len() {
+         14 => TagUid::Seven(decode_hex(s)),
+         _ => TagUid::Ten(decode_hex(s)),
+      })
    }
 
-   pub fn as_str(&self) -> &str { &self.0 }
+   pub fn as_bytes(&self) -> &[u8] {
+      match self {
+         TagUid::Seven(bytes) => bytes,
+         TagUid::Ten(bytes) => bytes,
+      }
+   }
+
+   /// Two letters derived from the id, shown beside it (`055B88A23C1250 · XQ`) and accepted after
+   /// it (`055B88A23C1250-XQ`), so an id misread over the phone is caught rather than creating an
+   /// orphan tag. It's a CRC-8 of the id's bytes, which changes with any one mistyped digit and any
+   /// two swapped neighbours. Never stored, nor part of a scan URL.
+   pub fn checksum(&self) -> String {
+      let crc = crc8(self.as_bytes());
+      [crc >> 4, crc & 0xF]
+         .iter()
+         .map(|nibble| CHECKSUM_ALPHABET[*nibble as usize] as char)
+         .collect()
+   }
 }
 
-impl Deref for Hex14 {
-   type Target = str;
+/// Consonants only, so no checksum spells a word, and without B, L, S or Z, which read as digits.
+const CHECKSUM_ALPHABET: &[u8; 16] = b"CDFGHJKMNPQRTVWX";
 
-   fn deref(&self) -> &Self::Target { &self.0 }
+/// CRC-8/SMBUS: polynomial 0x07, starting from 0.
+fn crc8(bytes: &[u8]) -> u8 {
+   bytes.iter().fold(0, |crc, byte| {
+      (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+         0 => crc << 1,
+         _ => (crc << 1) ^ 0x07,
+      })
+   })
 }
 
-impl FromStr for Hex14 {
-   type Err = Hex14Error;
+impl FromStr for TagUid {
+   type Err = TagUidError;
 
-   fn from_str(s: &str) -> Result<Self, Self::Err> { Hex14::new(s) }
+   fn from_str(s: &str) -> Result<Self, Self::Err> { TagUid::new(s) }
 }
 
-impl From<Hex14> for String {
-   fn from(hex: Hex14) -> Self { hex.0 }
+impl<'a> TryFrom<&'a str> for TagUid {
+   type Error = TagUidError;
+
+   fn try_from(s: &'a str) -> Result<Self, Self::Error> { Self::new(s) }
 }
 
-impl AsRef<str> for Hex14 {
-   fn as_ref(&self) -> &str { &self.0 }
+impl TryFrom<String> for TagUid {
+   type Error = TagUidError;
+
+   fn try_from(s: String) -> Result<Self, Self::Error> { Self::new(s) }
 }
 
-impl<'a> TryFrom<&'a str> for Hex14 {
-   type Error = Hex14Error;
+impl From<TagUid> for String {
+   fn from(uid: TagUid) -> Self { uid.to_string() }
+}
 
-   fn try_from(s: &'a str) -> Result<Self, Self::Error> { Self::new(s) }
+impl std::fmt::Display for TagUid {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      self.as_bytes().iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+   }
 }
 
-impl std::fmt::Display for Hex14 {
-   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "{}", self.0) }
+impl PartialEq<&str> for TagUid {
+   fn eq(&self, other: &&str) -> bool { self.to_string() == *other }
 }
 
-impl Hash for Hex14 {
-   fn hash<H: Hasher>(&self, state: &mut H) { self.0.hash(state); }
+impl PartialEq<String> for TagUid {
+   fn eq(&self, other: &String) -> bool { self.to_string() == *other }
 }
 
-impl PartialEq<String> for Hex14 {
-   fn eq(&self, other: &String) -> bool { self.0 == *other }
+impl sqlx::Type<Postgres> for TagUid {
+   fn type_info() -> PgTypeInfo { PgTypeInfo::with_name("tag_uid") }
+
+   fn compatible(ty: &PgTypeInfo) -> bool { <&str as sqlx::Type<Postgres>>::compatible(ty) }
 }
 
-impl PartialEq<&str> for Hex14 {
-   fn eq(&self, other: &&str) -> bool { self.0 == *other }
+impl sqlx::Encode<'_, Postgres> for TagUid {
+   fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+      <String as sqlx::Encode<Postgres>>::encode_by_ref(&self.to_string(), buf)
+   }
 }
 
-impl PartialEq<Hex14> for String {
-   fn eq(&self, other: &Hex14) -> bool { *self == other.0 }
+impl<'r> sqlx::Decode<'r, Postgres> for TagUid {
+   fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+      Ok(TagUid::new(<&str as sqlx::Decode<Postgres>>::decode(value)?)?)
+   }
 }
 
-impl PartialEq<Hex14> for &str {
-   fn eq(&self, other: &Hex14) -> bool { *self == other.0 }
+/// The last path segment of a scan URL: a tag id, optionally followed by the mirrored tap counter
+/// (`x00000F`) and a `.vcf` suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagSlug {
+   pub id: TagUid,
+   pub tap_count: Option<u32>,
+   pub vcf: bool,
 }
 
-impl PartialEq<Hex14> for str {
-   fn eq(&self, other: &Hex14) -> bool { self == other.0 }
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error(
+   "Invalid tag slug '{0}': expected a 14 or 20 digit uppercase hex id, optionally followed by x and a 6 digit counter"
+)]
+pub struct TagSlugError(String);
+
+impl FromStr for TagSlug {
+   type Err = TagSlugError;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      let captures = lazy_regex::regex_captures!(r"^([0-9A-F]{14}|[0-9A-F]{20})(?:x([0-9A-F]{6}))?(\.vcf)?$", s);
+      let Some((_, id, tap_count, vcf)) = captures else {
+         return Err(TagSlugError(s.to_string()));
+      };
+      Ok(TagSlug {
+         id: TagUid::new(id).map_err(|_| TagSlugError(s.to_string()))?,
+         tap_count: (!tap_count.is_empty()).then(|| u32::from_str_radix(tap_count, 16).unwrap()),
+         vcf: !vcf.is_empty(),
+      })
+   }
 }
 
-impl std::borrow::Borrow<str> for Hex14 {
-   fn borrow(&self) -> &str { &self.0 }
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum TagInputError {
+   #[error("Invalid tag id: {0}")]
+   InvalidId(String),
+   #[error("{0} isn't a tag URL for this service")]
+   ForeignHost(String),
+   #[error("{0} isn't a tag URL; expected one like https://xz.ws/tag/055B88A23C1250")]
+   NotATagUrl(String),
+   #[error("Checksum doesn't match — double-check the ID")]
+   ChecksumMismatch,
+}
+
+/// Reads a create or lookup form's id field, which may hold a bare id (in either case, with or
+/// without colons between bytes, and with or without its checksum) or a whole scan URL pasted from
+/// a phone. A URL must point at one of `hosts` (this instance) and its path is read with `TagSlug`,
+/// so the counter comes along; any query string is ignored.
+pub fn parse_tag_input(raw: &str, hosts: &[String]) -> Result<TagSlug, TagInputError> {
+   let raw = raw.trim();
+   if !raw.contains("://") {
+      // `055B88A23C1250-XQ` as typed, or `055B88A23C1250 · XQ` as copied from a page
+      let Some((id, checksum)) = raw.rsplit_once(['-', '·']) else {
+         return parse_bare_id(raw);
+      };
+      let slug = parse_bare_id(id.trim())?;
+      if !slug.id.checksum().eq_ignore_ascii_case(checksum.trim()) {
+         return Err(TagInputError::ChecksumMismatch);
+      }
+      return Ok(slug);
+   }
+
+   let url = Url::parse(raw).map_err(|_| TagInputError::NotATagUrl(raw.to_string()))?;
+   let host = url.host_str().unwrap_or_default();
+   if !hosts.iter().any(|own| own.eq_ignore_ascii_case(host)) {
+      return Err(TagInputError::ForeignHost(raw.to_string()));
+   }
+   match url
+      .path_segments()
+      .map(|segments| segments.collect::<Vec<_>>())
+      .as_deref()
+   {
+      Some(["tag", slug]) => slug
+         .parse::<TagSlug>()
+         .ok()
+         .filter(|slug| !slug.vcf)
+         .ok_or_else(|| TagInputError::NotATagUrl(raw.to_string())),
+      _ => Err(TagInputError::NotATagUrl(raw.to_string())),
+   }
+}
+
+fn parse_bare_id(raw: &str) -> Result<TagSlug, TagInputError> {
+   if let Ok(slug) = raw.parse::<TagSlug>() {
+      return Ok(slug);
+   }
+   // As NFC reader apps display UIDs, e.g. `05:5B:88:A2:3C:12:50`
+   let compact: String = raw.chars().filter(|c| *c != ':').collect();
+   let id = TagUid::new(&compact).map_err(|e| TagInputError::InvalidId(e.to_string()))?;
+   Ok(TagSlug {
+      id,
+      tap_count: None,
+      vcf: false,
+   })
 }
 
 /// A type representing a Notion page/Database ID with validation and parsing from URLs.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(sqlx::Type, Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[sqlx(type_name = "notion_page_id", transparent)]
 pub struct NotionPageId(String);
 
 #[derive(Debug, thiserror::Error)]
@@ -209,6 +345,8 @@ impl NotionPageId {
    pub fn as_str(&self) -> &str { &self.0 }
 
    pub fn as_raw(&self) -> String { self.0.replace('-', "") }
+
+   pub fn notion_url(&self) -> String { format!("https://www.notion.so/{}", self.as_raw()) }
 }
 
 impl Deref for NotionPageId {
@@ -259,56 +397,343 @@ impl AsRef<str> for NotionPageId {
    fn as_ref(&self) -> &str { &self.0 }
 }
 
-#[allow(dead_code)]
-#[derive(sqlx::FromRow)]
+/// A BCP 47 language tag such as `de` or `pt-BR`, normalized to conventional casing.
+#[derive(sqlx::Type, Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[sqlx(type_name = "lang_tag", transparent)]
+pub struct LanguageTag(String);
+
+#[derive(Debug, thiserror::Error)]
+pub enum LanguageTagError {
+   #[error("Invalid language tag '{0}': expected a tag like 'de' or 'pt-BR'")]
+   InvalidFormat(String),
+}
+
+impl LanguageTag {
+   /// As long as the `lang_tag` domain allows.
+   pub const MAX_LEN: usize = 35;
+
+   pub fn new(s: impl AsRef<str>) -> Result<Self, LanguageTagError> {
+      let s = s.as_ref().trim();
+      let err = || LanguageTagError::InvalidFormat(s.to_string());
+      if s.len() > Self::MAX_LEN {
+         return Err(err());
+      }
+      let mut subtags = s.split('-');
+      let primary = subtags.next().ok_or_else(err)?;
+      if !matches!(primary.len(), 2 | 3) || !primary.chars().all(|c| c.is_ascii_alphabetic()) {
+         return Err(err());
+      }
+
+      let mut normalized = primary.to_ascii_lowercase();
+      for subtag in subtags {
+         if !(1..=8).contains(&subtag.len()) || !subtag.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(err());
+         }
+         normalized.push('-');
+         let alphabetic = subtag.chars().all(|c| c.is_ascii_alphabetic());
+         match subtag.len() {
+            2 if alphabetic => normalized.push_str(&subtag.to_ascii_uppercase()),
+            4 if alphabetic => {
+               normalized.push_str(&subtag[..1].to_ascii_uppercase());
+               normalized.push_str(&subtag[1..].to_ascii_lowercase());
+            }
+            _ => normalized.push_str(&subtag.to_ascii_lowercase()),
+         }
+      }
+      Ok(LanguageTag(normalized))
+   }
+
+   pub fn as_str(&self) -> &str { &self.0 }
+
+   pub fn primary(&self) -> &str { self.0.split('-').next().unwrap_or_default() }
+}
+
+impl FromStr for LanguageTag {
+   type Err = LanguageTagError;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> { LanguageTag::new(s) }
+}
+
+impl std::fmt::Display for LanguageTag {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "{}", self.0) }
+}
+
+/// A row of `twag_tags`, as the tag listings read it. Times are selected as Unix seconds, and the
+/// id and Notion page as text, as sqlx only decodes them that way; see `listing::TagFilter::query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TwagTag {
-   pub id: Hex14,
+   pub id: TagUid,
    pub target_url: String,
-   pub created_at: DateTime<Utc>,
-   pub updated_at: DateTime<Utc>,
+   pub created_at: Option<DateTime<Utc>>,
+   pub updated_at: Option<DateTime<Utc>>,
    pub last_accessed: Option<DateTime<Utc>>,
-   pub access_count: i32,
+   pub access_count: Option<i32>,
    pub last_seen_tap_count: Option<i32>,
+   pub notion_page_id: Option<NotionPageId>,
+   pub label: Option<String>,
+   pub kit: Option<String>,
+   pub maintenance: bool,
+   pub stateful: bool,
+   pub enabled: bool,
+   pub expires_at: Option<DateTime<Utc>>,
+   pub activates_at: Option<DateTime<Utc>>,
+   pub count_token: Option<String>,
+   pub review_state: String,
+}
+
+impl<'r> sqlx::FromRow<'r, PgRow> for TwagTag {
+   fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+      let time = |column: &str| -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+         Ok(row
+            .try_get::<Option<i64>, _>(column)?
+            .and_then(|secs| DateTime::from_timestamp(secs, 0)))
+      };
+      let notion_page_id = row
+         .try_get::<Option<String>, _>("notion_page_id")?
+         .map(NotionPageId::new)
+         .transpose()
+         .map_err(|e| sqlx::Error::ColumnDecode {
+            index: "notion_page_id".to_string(),
+            source: Box::new(e),
+         })?;
+      Ok(TwagTag {
+         id: row.try_get("id")?,
+         target_url: row.try_get("target_url")?,
+         created_at: time("created_at")?,
+         updated_at: time("updated_at")?,
+         last_accessed: time("last_accessed")?,
+         access_count: row.try_get("access_count")?,
+         last_seen_tap_count: row.try_get("last_seen_tap_count")?,
+         notion_page_id,
+         label: row.try_get("label")?,
+         kit: row.try_get("kit")?,
+         maintenance: row.try_get("maintenance")?,
+         stateful: row.try_get("stateful")?,
+         enabled: row.try_get("enabled")?,
+         expires_at: time("expires_at")?,
+         activates_at: time("activates_at")?,
+         count_token: row.try_get("count_token")?,
+         review_state: row.try_get("review_state")?,
+      })
+   }
 }
 
 #[cfg(test)]
 mod tests {
    use super::*;
 
-   mod hex14_tests {
+   mod tag_slug_tests {
+      use super::*;
+
+      fn hosts() -> Vec<String> { vec!["xz.ws".to_string()] }
+
+      #[test]
+      fn test_slug_parsing() {
+         let slug: TagSlug = "055B88A23C1250x00000F".parse().unwrap();
+         assert_eq!(slug.id, "055B88A23C1250");
+         assert_eq!(slug.tap_count, Some(15));
+         assert!(!slug.vcf);
+         assert!("055B88A23C1250AA01BB.vcf".parse::<TagSlug>().unwrap().vcf);
+         assert!("055b88a23c1250".parse::<TagSlug>().is_err());
+         assert!("055B88A23C1250x0F".parse::<TagSlug>().is_err());
+      }
+
+      #[test]
+      fn test_input_bare_id() {
+         let slug = parse_tag_input(" 055b88a23c1250 ", &hosts()).unwrap();
+         assert_eq!(slug.id, "055B88A23C1250");
+         assert_eq!(slug.tap_count, None);
+         assert!(matches!(
+            parse_tag_input("055B88A23C12", &hosts()),
+            Err(TagInputError::InvalidId(_))
+         ));
+      }
+
+      #[test]
+      fn test_input_id_with_colons() {
+         let slug = parse_tag_input("05:5b:88:A2:3C:12:50", &hosts()).unwrap();
+         assert_eq!(slug.id, "055B88A23C1250");
+         let slug = parse_tag_input("05:5B:88:A2:3C:12:50:AA:01:BB", &hosts()).unwrap();
+         assert_eq!(slug.id, "055B88A23C1250AA01BB");
+         assert!(matches!(
+            parse_tag_input("05:5B:88:A2:3C:12", &hosts()),
+            Err(TagInputError::InvalidId(_))
+         ));
+      }
+
+      #[test]
+      fn test_input_id_with_checksum() {
+         assert_eq!(
+            parse_tag_input("055B88A23C1250-XQ", &hosts()).unwrap().id,
+            "055B88A23C1250"
+         );
+         assert_eq!(
+            parse_tag_input("055b88a23c1250-xq", &hosts()).unwrap().id,
+            "055B88A23C1250"
+         );
+         assert_eq!(
+            parse_tag_input("055B88A23C1250 · XQ", &hosts()).unwrap().id,
+            "055B88A23C1250"
+         );
+         let slug = parse_tag_input("05:5B:88:A2:3C:12:50:AA:01:BB-DP", &hosts()).unwrap();
+         assert_eq!(slug.id, "055B88A23C1250AA01BB");
+
+         // One digit misheard
+         let misread = parse_tag_input("055B88A23C1251-XQ", &hosts()).unwrap_err();
+         assert_eq!(misread, TagInputError::ChecksumMismatch);
+         assert_eq!(misread.to_string(), "Checksum doesn't match — double-check the ID");
+         assert!(matches!(
+            parse_tag_input("055B88A23C12-XQ", &hosts()),
+            Err(TagInputError::InvalidId(_))
+         ));
+      }
+
+      #[test]
+      fn test_input_full_url_with_counter() {
+         let slug = parse_tag_input("https://xz.ws/tag/055B88A23C1250x00000F", &hosts()).unwrap();
+         assert_eq!(slug.id, "055B88A23C1250");
+         assert_eq!(slug.tap_count, Some(15));
+      }
+
+      #[test]
+      fn test_input_full_url_with_query() {
+         let slug = parse_tag_input("https://XZ.WS/tag/055B88A23C1250AA01BBx000010?ct=abc&lang=de", &hosts()).unwrap();
+         assert_eq!(slug.id, "055B88A23C1250AA01BB");
+         assert_eq!(slug.tap_count, Some(16));
+      }
+
+      #[test]
+      fn test_input_rejects_foreign_and_non_tag_urls() {
+         assert_eq!(
+            parse_tag_input("https://evil.example/tag/055B88A23C1250", &hosts()),
+            Err(TagInputError::ForeignHost(
+               "https://evil.example/tag/055B88A23C1250".to_string()
+            ))
+         );
+         assert_eq!(
+            parse_tag_input("https://evil.example/tag/055B88A23C1250", &hosts())
+               .unwrap_err()
+               .to_string(),
+            "https://evil.example/tag/055B88A23C1250 isn't a tag URL for this service"
+         );
+         assert!(matches!(
+            parse_tag_input("https://xz.ws/tag/055B88A23C1250/stats", &hosts()),
+            Err(TagInputError::NotATagUrl(_))
+         ));
+         assert!(matches!(
+            parse_tag_input("https://xz.ws/tag/055B88A23C1250.vcf", &hosts()),
+            Err(TagInputError::NotATagUrl(_))
+         ));
+      }
+   }
+
+   mod tag_uid_tests {
       use super::*;
 
       #[test]
-      fn test_hex14_validation_and_conversion() {
-         // Valid creation and case conversion
-         let hex = Hex14::new("a1b2c3d4e5f678").unwrap();
-         assert_eq!(hex.as_str(), "A1B2C3D4E5F678");
+      fn test_checksum_vectors() {
+         assert_eq!(crc8(b"123456789"), 0xF4);
+         for (id, checksum) in [
+            ("055B88A23C1250", "XQ"),
+            ("055B88A23C1251", "XV"),
+            ("04A1B2C3D4E5F6", "JH"),
+            ("055B88A23C1250AA01BB", "DP"),
+            ("00000000000000", "CC"),
+         ] {
+            assert_eq!(TagUid::new(id).unwrap().checksum(), checksum, "{id}");
+         }
+      }
+
+      #[test]
+      fn test_checksum_catches_one_digit_and_swaps() {
+         let id = "055B88A23C1250";
+         let checksum = TagUid::new(id).unwrap().checksum();
+         let digits: Vec<char> = id.chars().collect();
+         let changed = |digits: &[char]| {
+            let other = TagUid::new(digits.iter().collect::<String>()).unwrap();
+            other.to_string() == id || other.checksum() != checksum
+         };
+         for at in 0..digits.len() {
+            for digit in "0123456789ABCDEF".chars() {
+               let mut typo = digits.clone();
+               typo[at] = digit;
+               assert!(changed(&typo), "{at} {digit}");
+            }
+            if at + 1 < digits.len() {
+               let mut swapped = digits.clone();
+               swapped.swap(at, at + 1);
+               assert!(changed(&swapped), "{at}");
+            }
+         }
+      }
 
-         // Length validation
-         assert!(matches!(Hex14::new("A1B2C3"), Err(Hex14Error::InvalidLength(_))));
+      #[test]
+      fn test_tag_uid_validation_and_conversion() {
+         // Valid creation and case conversion, for both UID lengths
+         let uid = TagUid::new("a1b2c3d4e5f678").unwrap();
+         assert_eq!(uid.to_string(), "A1B2C3D4E5F678");
+         assert!(matches!(uid, TagUid::Seven(_)));
+
+         let uid = TagUid::new("a1b2c3d4e5f6789abcde").unwrap();
+         assert_eq!(uid.to_string(), "A1B2C3D4E5F6789ABCDE");
+         assert!(matches!(uid, TagUid::Ten(_)));
+
+         // Length validation, including lengths between the two valid ones
+         assert!(matches!(TagUid::new("A1B2C3"), Err(TagUidError::InvalidLength(6))));
+         assert!(matches!(
+            TagUid::new("A1B2C3D4E5F67890"),
+            Err(TagUidError::InvalidLength(16))
+         ));
          assert!(matches!(
-            Hex14::new("A1B2C3D4E5F67890"),
-            Err(Hex14Error::InvalidLength(_))
+            TagUid::new("A1B2C3D4E5F6789ABC"),
+            Err(TagUidError::InvalidLength(18))
+         ));
+         assert!(matches!(
+            TagUid::new("A1B2C3D4E5F6789ABCDEF0"),
+            Err(TagUidError::InvalidLength(22))
          ));
 
          // Character validation
          assert!(matches!(
-            Hex14::new("G1B2C3D4E5F678"),
-            Err(Hex14Error::InvalidCharacter(_))
+            TagUid::new("G1B2C3D4E5F678"),
+            Err(TagUidError::InvalidCharacter('G'))
+         ));
+         assert!(matches!(
+            TagUid::new("A1B2C3D4E5F6789ABCDZ"),
+            Err(TagUidError::InvalidCharacter('Z'))
          ));
       }
 
       #[test]
-      fn test_hex14_string_traits() {
-         let hex: Hex14 = "A1B2C3D4E5F678".parse().unwrap();
+      fn test_tag_uid_bytes() {
+         let uid = TagUid::new("0455B88A23C125").unwrap();
+         assert_eq!(uid.as_bytes(), &[0x04, 0x55, 0xB8, 0x8A, 0x23, 0xC1, 0x25]);
+
+         let uid = TagUid::new("0455B88A23C1250A0B0C").unwrap();
+         assert_eq!(uid.as_bytes().len(), 10);
+         assert_eq!(
+            uid,
+            TagUid::Ten([0x04, 0x55, 0xB8, 0x8A, 0x23, 0xC1, 0x25, 0x0A, 0x0B, 0x0C])
+         );
+      }
+
+      #[test]
+      fn test_tag_uid_string_traits() {
+         let uid: TagUid = "A1B2C3D4E5F678".parse().unwrap();
 
          // Conversion traits
-         let s: String = hex.clone().into();
+         let s: String = uid.into();
          assert_eq!(s, "A1B2C3D4E5F678");
 
          // Equality with strings
-         assert_eq!(hex, "A1B2C3D4E5F678");
-         assert_eq!(hex, "A1B2C3D4E5F678".to_string());
+         assert_eq!(uid, "A1B2C3D4E5F678");
+         assert_eq!(uid, "A1B2C3D4E5F678".to_string());
+
+         // Round-trips through strings don't conflate the two lengths
+         let seven: TagUid = "00000000000000".parse().unwrap();
+         let ten: TagUid = "00000000000000000000".parse().unwrap();
+         assert_ne!(seven, ten);
+         assert_eq!(ten.to_string().parse::<TagUid>().unwrap(), ten);
       }
    }
 
@@ -393,6 +818,17 @@ mod tests {
          assert_eq!(id.as_raw(), "a1b2c3d4e5f67890abcdef1234567890");
       }
 
+      #[test]
+      fn test_notion_page_id_notion_url() {
+         let id =
+            NotionPageId::new("https://www.notion.so/workspace/page-a1b2c3d4e5f67890abcdef1234567890?v=abc").unwrap();
+         assert_eq!(
+            id.notion_url(),
+            "https://www.notion.so/a1b2c3d4e5f67890abcdef1234567890"
+         );
+         assert_eq!(NotionPageId::new(id.notion_url()).unwrap(), id);
+      }
+
       #[test]
       fn test_notion_page_id_hash_and_clone() {
          use std::collections::HashMap;
@@ -445,4 +881,36 @@ mod tests {
          ));
       }
    }
+   mod language_tag_tests {
+      use super::*;
+
+      #[test]
+      fn test_language_tag_normalization() {
+         assert_eq!(LanguageTag::new("de").unwrap().as_str(), "de");
+         assert_eq!(LanguageTag::new("pt-br").unwrap().as_str(), "pt-BR");
+         assert_eq!(LanguageTag::new("ZH-hant-tw").unwrap().as_str(), "zh-Hant-TW");
+         assert_eq!(LanguageTag::new("es-419").unwrap().as_str(), "es-419");
+         assert_eq!(LanguageTag::new(" en-GB ").unwrap().primary(), "en");
+      }
+
+      #[test]
+      fn test_language_tag_validation() {
+         assert!(LanguageTag::new("").is_err());
+         assert!(LanguageTag::new("*").is_err());
+         assert!(LanguageTag::new("e").is_err());
+         assert!(LanguageTag::new("english").is_err());
+         assert!(LanguageTag::new("de-").is_err());
+         assert!(LanguageTag::new("de--AT").is_err());
+         assert!(LanguageTag::new("de_AT").is_err());
+         assert!(LanguageTag::new("de-toolongsubtag").is_err());
+      }
+
+      #[test]
+      fn test_language_tag_fits_the_domain() {
+         let longest = "de-abcdefgh-abcdefgh-abcdefgh-abcde";
+         assert_eq!(longest.len(), LanguageTag::MAX_LEN);
+         assert!(LanguageTag::new(longest).is_ok());
+         assert!(LanguageTag::new(format!("{longest}f")).is_err());
+      }
+   }
 }
@@ -0,0 +1,242 @@
+use crate::models::TagUid;
+
+/// URI identifier codes from the NFC Forum URI Record Type Definition, indexed by code.
+const URI_PREFIXES: [&str; 36] = [
+   "",
+   "http://www.",
+   "https://www.",
+   "http://",
+   "https://",
+   "tel:",
+   "mailto:",
+   "ftp://anonymous:anonymous@",
+   "ftp://ftp.",
+   "ftps://",
+   "sftp://",
+   "smb://",
+   "nfs://",
+   "ftp://",
+   "dav://",
+   "news:",
+   "telnet://",
+   "imap:",
+   "rtsp://",
+   "urn:",
+   "pop:",
+   "sip:",
+   "sips:",
+   "tftp:",
+   "btspp://",
+   "btl2cap://",
+   "btgoep://",
+   "tcpobex://",
+   "irdaobex://",
+   "file://",
+   "urn:epc:id:",
+   "urn:epc:tag:",
+   "urn:epc:pat:",
+   "urn:epc:raw:",
+   "urn:epc:",
+   "urn:nfc:",
+];
+
+const TNF_WELL_KNOWN: u8 = 0x01;
+const FLAG_MB: u8 = 0x80;
+const FLAG_ME: u8 = 0x40;
+const FLAG_SR: u8 = 0x10;
+const URI_RECORD_TYPE: u8 = b'U';
+
+/// User-memory bytes available for an NDEF message on common NXP NTAG parts.
+pub const NTAG_CAPACITIES: [(&str, usize); 3] = [("NTAG213", 144), ("NTAG215", 504), ("NTAG216", 888)];
+
+/// Parts worth warning about at create time; anything larger than an NTAG215 is rare enough in
+/// practice not to nag.
+const WARN_FOR: [&str; 2] = ["NTAG213", "NTAG215"];
+
+/// What the tag's counter mirror writes after the UID: `x` plus six hex digits.
+const COUNTER_MIRROR: &str = "x000000";
+
+/// A single NDEF URI record, with the URI split into its abbreviated prefix code and remainder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UriRecord {
+   pub prefix_code: u8,
+   pub remainder: String,
+}
+
+impl UriRecord {
+   pub fn new(uri: &str) -> Self {
+      let (code, prefix) = URI_PREFIXES
+         .iter()
+         .enumerate()
+         .skip(1)
+         .filter(|(_, prefix)| uri.starts_with(*prefix))
+         .max_by_key(|(_, prefix)| prefix.len())
+         .unwrap_or((0, &""));
+      UriRecord {
+         prefix_code: code as u8,
+         remainder: uri[prefix.len()..].to_string(),
+      }
+   }
+
+   pub fn uri(&self) -> String { format!("{}{}", URI_PREFIXES[self.prefix_code as usize], self.remainder) }
+
+   pub fn payload(&self) -> Vec<u8> {
+      let mut payload = Vec::with_capacity(1 + self.remainder.len());
+      payload.push(self.prefix_code);
+      payload.extend_from_slice(self.remainder.as_bytes());
+      payload
+   }
+
+   /// Encodes this as the sole record of an NDEF message.
+   pub fn to_message(&self) -> Vec<u8> {
+      let payload = self.payload();
+      let mut message = Vec::with_capacity(payload.len() + 7);
+      if payload.len() <= u8::MAX as usize {
+         message.push(FLAG_MB | FLAG_ME | FLAG_SR | TNF_WELL_KNOWN);
+         message.push(1);
+         message.push(payload.len() as u8);
+      } else {
+         message.push(FLAG_MB | FLAG_ME | TNF_WELL_KNOWN);
+         message.push(1);
+         message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+      }
+      message.push(URI_RECORD_TYPE);
+      message.extend_from_slice(&payload);
+      message
+   }
+}
+
+/// Wraps an NDEF message in the Type 2 Tag TLV framing (NDEF TLV plus terminator) actually
+/// stored in tag memory.
+pub fn to_tlv(message: &[u8]) -> Vec<u8> {
+   let mut tlv = Vec::with_capacity(message.len() + 5);
+   tlv.push(0x03);
+   if message.len() < 0xFF {
+      tlv.push(message.len() as u8);
+   } else {
+      tlv.push(0xFF);
+      tlv.extend_from_slice(&(message.len() as u16).to_be_bytes());
+   }
+   tlv.extend_from_slice(message);
+   tlv.push(0xFE);
+   tlv
+}
+
+/// The URL a tag is programmed with, including space for the counter mirror.
+pub fn programmed_uri(origin: &str, id: &TagUid) -> String { format!("{origin}/tag/{id}{COUNTER_MIRROR}") }
+
+/// The bytes `uri` occupies in tag memory, including NDEF record and TLV overhead.
+pub fn tlv_len(uri: &str) -> usize { to_tlv(&UriRecord::new(uri).to_message()).len() }
+
+/// Warnings for each part in `WARN_FOR` that `uri` won't fit on, with the numbers.
+pub fn capacity_warnings(uri: &str) -> Vec<String> {
+   NTAG_CAPACITIES
+      .iter()
+      .filter(|(tag_type, _)| WARN_FOR.contains(tag_type))
+      .filter_map(|&(tag_type, user_bytes)| capacity_warning(uri, tag_type, user_bytes))
+      .collect()
+}
+
+/// A warning if `uri` won't fit in `user_bytes`, with the numbers.
+pub fn capacity_warning(uri: &str, tag_type: &str, user_bytes: usize) -> Option<String> {
+   let needed = tlv_len(uri);
+   (needed > user_bytes).then(|| {
+      format!(
+         "{} needs {} bytes, but an {} only holds {}",
+         uri, needed, tag_type, user_bytes
+      )
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_uri_record_prefix_selection() {
+      let record = UriRecord::new("https://xz.ws/tag/055B88A23C1250");
+      assert_eq!(record.prefix_code, 0x04);
+      assert_eq!(record.remainder, "xz.ws/tag/055B88A23C1250");
+
+      // The longest matching prefix wins
+      assert_eq!(UriRecord::new("https://www.example.com/").prefix_code, 0x02);
+      assert_eq!(UriRecord::new("urn:epc:id:sgtin:1").prefix_code, 0x1E);
+      assert_eq!(UriRecord::new("urn:nfc:sn:1").prefix_code, 0x23);
+
+      let record = UriRecord::new("gopher://example.com/");
+      assert_eq!(record.prefix_code, 0x00);
+      assert_eq!(record.remainder, "gopher://example.com/");
+   }
+
+   #[test]
+   fn test_uri_record_round_trip() {
+      for uri in [
+         "https://xz.ws/tag/055B88A23C1250",
+         "mailto:a@example.com",
+         "weird:thing",
+      ] {
+         assert_eq!(UriRecord::new(uri).uri(), uri);
+      }
+   }
+
+   #[test]
+   fn test_short_record_bytes() {
+      let message = UriRecord::new("https://xz.ws/t").to_message();
+      assert_eq!(message, [&[0xD1, 0x01, 0x08, b'U', 0x04][..], b"xz.ws/t"].concat());
+
+      let tlv = to_tlv(&message);
+      assert_eq!(tlv[0], 0x03);
+      assert_eq!(tlv[1] as usize, message.len());
+      assert_eq!(&tlv[2..tlv.len() - 1], &message[..]);
+      assert_eq!(tlv[tlv.len() - 1], 0xFE);
+   }
+
+   #[test]
+   fn test_long_record_bytes() {
+      let uri = format!("https://xz.ws/{}", "a".repeat(300));
+      let message = UriRecord::new(&uri).to_message();
+      assert_eq!(message[0], 0xC1);
+      assert_eq!(&message[2..6], &(307u32).to_be_bytes());
+      assert_eq!(message[6], b'U');
+      assert_eq!(message.len(), 7 + 307);
+
+      let tlv = to_tlv(&message);
+      assert_eq!(&tlv[..4], &[0x03, 0xFF, 0x01, 0x3A]);
+      assert_eq!(tlv.len(), 4 + message.len() + 1);
+   }
+
+   #[test]
+   fn test_programmed_uri_byte_counts() {
+      let seven: TagUid = "055B88A23C1250".parse().unwrap();
+      let ten: TagUid = "055B88A23C1250AA01BB".parse().unwrap();
+
+      // 1 prefix code + 31 remainder, 4 record header, 2 TLV header and 1 terminator
+      let uri = programmed_uri("https://xz.ws", &seven);
+      assert_eq!(uri, "https://xz.ws/tag/055B88A23C1250x000000");
+      assert_eq!(tlv_len(&uri), 39);
+      assert_eq!(tlv_len(&programmed_uri("https://xz.ws", &ten)), 45);
+      assert!(capacity_warnings(&uri).is_empty());
+   }
+
+   #[test]
+   fn test_capacity_thresholds() {
+      let id: TagUid = "055B88A23C1250AA01BB".parse().unwrap();
+
+      // 45 bytes for a 10-byte UID on a 5-character host; each extra host character costs one
+      let exactly_full = programmed_uri(&format!("https://{}", "h".repeat(5 + 144 - 45)), &id);
+      assert_eq!(tlv_len(&exactly_full), 144);
+      assert!(capacity_warnings(&exactly_full).is_empty());
+
+      let one_over = programmed_uri(&format!("https://{}", "h".repeat(5 + 145 - 45)), &id);
+      assert_eq!(tlv_len(&one_over), 145);
+      assert_eq!(
+         capacity_warnings(&one_over),
+         [format!("{} needs 145 bytes, but an NTAG213 only holds 144", one_over)]
+      );
+
+      // Past 255 bytes the record and TLV both switch to long lengths, costing 5 more
+      let long = programmed_uri(&format!("https://{}", "h".repeat(500)), &id);
+      assert_eq!(tlv_len(&long), 500 - 5 + 45 + 5);
+      assert_eq!(capacity_warnings(&long).len(), 2);
+   }
+}
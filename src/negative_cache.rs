@@ -0,0 +1,253 @@
+//! Tag ids recently looked up and not found, so a bot walking through made-up ids costs a hash
+//! lookup per scan rather than a Postgres query. Entries expire after `TWAG_NEGATIVE_CACHE_SECS`,
+//! and the oldest are dropped past `TWAG_NEGATIVE_CACHE_SIZE`.
+//!
+//! A tag that's just been created must never be refused by a stale entry. Creating tags calls
+//! `invalidate` as soon as the insert commits, and a miss is only recorded with the `Ticket` taken
+//! before its lookup started, which is refused if any tag was created since; so a lookup that raced
+//! a create can't cache the id after it. Other instances only see the new tag once their entry
+//! expires, which is why the default TTL is short.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::TagUid;
+
+const DEFAULT_CAPACITY: usize = 10_000;
+const DEFAULT_TTL: Duration = Duration::from_secs(10);
+/// Longer would leave new tags unreachable on other instances for too long.
+const MAX_TTL: Duration = Duration::from_secs(300);
+
+/// Taken before a lookup, so a miss found by it can be refused if tags were created meanwhile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ticket(u64);
+
+#[derive(Default)]
+struct Entries {
+   expires: HashMap<TagUid, Instant>,
+   /// Insertion order, oldest first. Entries replaced or removed since are left here, and skipped
+   /// when they come up for eviction.
+   order: VecDeque<(TagUid, Instant)>,
+   /// Bumped by every invalidation.
+   generation: u64,
+}
+
+impl Entries {
+   fn evict(&mut self, capacity: usize, now: Instant) {
+      while let Some(&(id, expires)) = self.order.front() {
+         if self.order.len() <= capacity && expires > now {
+            break;
+         }
+         self.order.pop_front();
+         if self.expires.get(&id) == Some(&expires) {
+            self.expires.remove(&id);
+         }
+      }
+   }
+}
+
+/// Counts since startup, for `/healthz`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NegativeStats {
+   pub cached: usize,
+   /// Scans answered without a lookup.
+   pub hits: u64,
+}
+
+impl fmt::Display for NegativeStats {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "unknown ids: {} cached, {} hit(s)", self.cached, self.hits)
+   }
+}
+
+/// Shared by every request; clones share the entries and counts.
+#[derive(Clone)]
+pub struct NegativeCache {
+   capacity: usize,
+   ttl: Duration,
+   entries: Arc<Mutex<Entries>>,
+   hits: Arc<AtomicU64>,
+}
+
+impl Default for NegativeCache {
+   fn default() -> Self { NegativeCache::new(DEFAULT_CAPACITY, DEFAULT_TTL) }
+}
+
+impl NegativeCache {
+   /// A `capacity` of 0 caches nothing.
+   pub fn new(capacity: usize, ttl: Duration) -> Self {
+      NegativeCache {
+         capacity,
+         ttl,
+         entries: Arc::default(),
+         hits: Arc::default(),
+      }
+   }
+
+   /// From `TWAG_NEGATIVE_CACHE_SIZE` (0 turns the cache off) and `TWAG_NEGATIVE_CACHE_SECS`.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+      let capacity = match var("TWAG_NEGATIVE_CACHE_SIZE") {
+         Some(size) => size
+            .trim()
+            .parse()
+            .map_err(|_| format!("TWAG_NEGATIVE_CACHE_SIZE must be a number of ids, not '{size}'"))?,
+         None => DEFAULT_CAPACITY,
+      };
+      let ttl = match var("TWAG_NEGATIVE_CACHE_SECS") {
+         Some(secs) => secs
+            .trim()
+            .parse()
+            .ok()
+            .map(Duration::from_secs)
+            .filter(|ttl| !ttl.is_zero() && *ttl <= MAX_TTL)
+            .ok_or_else(|| {
+               format!(
+                  "TWAG_NEGATIVE_CACHE_SECS must be between 1 and {}, not '{secs}'",
+                  MAX_TTL.as_secs()
+               )
+            })?,
+         None => DEFAULT_TTL,
+      };
+      Ok(NegativeCache::new(capacity, ttl))
+   }
+
+   /// Whether `id` was recently found not to exist.
+   pub fn is_missing(&self, id: &TagUid, now: Instant) -> bool {
+      let missing = self
+         .entries
+         .lock()
+         .unwrap()
+         .expires
+         .get(id)
+         .is_some_and(|expires| *expires > now);
+      if missing {
+         self.hits.fetch_add(1, Ordering::Relaxed);
+      }
+      missing
+   }
+
+   pub fn ticket(&self) -> Ticket { Ticket(self.entries.lock().unwrap().generation) }
+
+   /// Caches that `id` wasn't found by a lookup started at `ticket`, unless a tag has been created
+   /// since. Returns whether it was cached.
+   pub fn record_miss(&self, id: TagUid, ticket: Ticket, now: Instant) -> bool {
+      let mut entries = self.entries.lock().unwrap();
+      if self.capacity == 0 || entries.generation != ticket.0 {
+         return false;
+      }
+      let expires = now + self.ttl;
+      entries.expires.insert(id, expires);
+      entries.order.push_back((id, expires));
+      entries.evict(self.capacity, now);
+      true
+   }
+
+   /// Forgets `ids`, and refuses misses from lookups already under way. Called once tags are
+   /// committed, before anything could have told a client they exist.
+   pub fn invalidate<'a>(&self, ids: impl IntoIterator<Item = &'a TagUid>) {
+      let mut entries = self.entries.lock().unwrap();
+      entries.generation += 1;
+      for id in ids {
+         entries.expires.remove(id);
+      }
+   }
+
+   pub fn stats(&self) -> NegativeStats {
+      NegativeStats {
+         cached: self.entries.lock().unwrap().expires.len(),
+         hits: self.hits.load(Ordering::Relaxed),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn id(n: u32) -> TagUid { format!("04{n:012X}").parse().unwrap() }
+
+   fn secs(n: u64) -> Duration { Duration::from_secs(n) }
+
+   #[test]
+   fn test_misses_are_cached_until_they_expire() {
+      let cache = NegativeCache::new(10, secs(10));
+      let start = Instant::now();
+      assert!(!cache.is_missing(&id(1), start));
+      assert!(cache.record_miss(id(1), cache.ticket(), start));
+      assert!(cache.is_missing(&id(1), start + secs(9)));
+      assert!(!cache.is_missing(&id(1), start + secs(10)));
+      assert!(!cache.is_missing(&id(2), start));
+      assert_eq!(cache.stats().to_string(), "unknown ids: 1 cached, 1 hit(s)");
+   }
+
+   #[test]
+   fn test_creating_a_tag_forgets_its_miss() {
+      let cache = NegativeCache::new(10, secs(10));
+      let start = Instant::now();
+      cache.record_miss(id(1), cache.ticket(), start);
+      cache.record_miss(id(2), cache.ticket(), start);
+      cache.invalidate([&id(1)]);
+      assert!(!cache.is_missing(&id(1), start));
+      assert!(cache.is_missing(&id(2), start));
+   }
+
+   #[test]
+   fn test_a_lookup_racing_a_create_cant_cache_the_new_tag() {
+      let cache = NegativeCache::new(10, secs(10));
+      let start = Instant::now();
+      // A scan looks the id up, and finds nothing...
+      let ticket = cache.ticket();
+      // ...while the tag is created and committed...
+      cache.invalidate([&id(1)]);
+      // ...so by the time the scan records its miss, it's out of date
+      assert!(!cache.record_miss(id(1), ticket, start));
+      assert!(!cache.is_missing(&id(1), start));
+      // Lookups started after the create can cache again
+      assert!(cache.record_miss(id(2), cache.ticket(), start));
+   }
+
+   #[test]
+   fn test_oldest_misses_are_evicted() {
+      let cache = NegativeCache::new(2, secs(10));
+      let start = Instant::now();
+      for n in 1..=3 {
+         cache.record_miss(id(n), cache.ticket(), start);
+      }
+      assert!(!cache.is_missing(&id(1), start));
+      assert!(cache.is_missing(&id(2), start));
+      assert!(cache.is_missing(&id(3), start));
+
+      // Expired entries go first, and re-recording one doesn't evict it by its old place
+      let later = start + secs(10);
+      cache.record_miss(id(2), cache.ticket(), later);
+      assert_eq!(cache.stats().cached, 1);
+      cache.record_miss(id(4), cache.ticket(), later);
+      assert!(cache.is_missing(&id(2), later));
+      assert!(cache.is_missing(&id(4), later));
+   }
+
+   #[test]
+   fn test_settings() {
+      fn vars(size: Option<&'static str>, secs: Option<&'static str>) -> impl Fn(&str) -> Option<String> {
+         move |name| match name {
+            "TWAG_NEGATIVE_CACHE_SIZE" => size.map(str::to_string),
+            "TWAG_NEGATIVE_CACHE_SECS" => secs.map(str::to_string),
+            _ => None,
+         }
+      }
+      let cache = NegativeCache::from_vars(vars(None, None)).unwrap();
+      assert_eq!((cache.capacity, cache.ttl), (DEFAULT_CAPACITY, DEFAULT_TTL));
+      let cache = NegativeCache::from_vars(vars(Some("500"), Some("30"))).unwrap();
+      assert_eq!((cache.capacity, cache.ttl), (500, secs(30)));
+
+      let off = NegativeCache::from_vars(vars(Some("0"), None)).unwrap();
+      assert!(!off.record_miss(id(1), off.ticket(), Instant::now()));
+
+      assert!(NegativeCache::from_vars(vars(Some("lots"), None)).is_err());
+      assert!(NegativeCache::from_vars(vars(None, Some("0"))).is_err());
+      assert!(NegativeCache::from_vars(vars(None, Some("3600"))).is_err());
+   }
+}
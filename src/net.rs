@@ -0,0 +1,396 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use reqwest::{redirect, StatusCode};
+use tracing::debug;
+use url::{Host, Url};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REDIRECTS: usize = 5;
+const MAX_BODY_BYTES: usize = 512 * 1024;
+
+/// An IP network in CIDR notation, e.g. `192.0.2.0/24` or `2001:db8::/32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+   network: IpAddr,
+   prefix: u8,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid IP range '{0}': expected CIDR notation")]
+pub struct IpRangeError(String);
+
+impl FromStr for IpRange {
+   type Err = IpRangeError;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      let err = || IpRangeError(s.to_string());
+      let (network, prefix) = s.trim().split_once('/').ok_or_else(err)?;
+      let network: IpAddr = network.parse().map_err(|_| err())?;
+      let prefix: u8 = prefix.parse().map_err(|_| err())?;
+      let max = if network.is_ipv4() { 32 } else { 128 };
+      if prefix > max {
+         return Err(err());
+      }
+      Ok(IpRange { network, prefix })
+   }
+}
+
+impl IpRange {
+   pub fn contains(&self, ip: IpAddr) -> bool {
+      match (self.network, ip) {
+         (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+            u32::from(net) & mask == u32::from(ip) & mask
+         }
+         (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+            u128::from(net) & mask == u128::from(ip) & mask
+         }
+         _ => false,
+      }
+   }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FetchPolicy {
+   pub extra_blocklist: Vec<IpRange>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+   #[error("Invalid URL: {0}")]
+   InvalidUrl(#[from] url::ParseError),
+   #[error("Unsupported URL scheme '{0}'")]
+   UnsupportedScheme(String),
+   #[error("URL has no host")]
+   MissingHost,
+   #[error("Refusing to fetch from blocked address {0}")]
+   Blocked(IpAddr),
+   #[error("Failed to resolve host: {0}")]
+   Resolve(#[from] std::io::Error),
+   #[error("Host resolved to no addresses")]
+   NoAddresses,
+   #[error("Redirect without a usable Location header")]
+   BadRedirect,
+   #[error("Too many redirects")]
+   TooManyRedirects,
+   #[error("Response body too large")]
+   TooLarge,
+   #[error("HTTP request failed: {0}")]
+   Http(#[from] reqwest::Error),
+}
+
+#[derive(Debug)]
+pub struct FetchedResponse {
+   pub final_url: Url,
+   pub status: StatusCode,
+   pub content_type: Option<String>,
+   pub body: Vec<u8>,
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+   let [a, b, ..] = ip.octets();
+   ip.is_loopback()
+      || ip.is_private()
+      || ip.is_link_local()
+      || ip.is_unspecified()
+      || ip.is_broadcast()
+      || ip.is_multicast()
+      || ip.is_documentation()
+      // Carrier-grade NAT, 100.64.0.0/10
+      || (a == 100 && (b & 0xC0) == 64)
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+   let first = ip.segments()[0];
+   ip.is_loopback()
+      || ip.is_unspecified()
+      || ip.is_multicast()
+      // Unique local, fc00::/7
+      || (first & 0xFE00) == 0xFC00
+      // Link-local, fe80::/10
+      || (first & 0xFFC0) == 0xFE80
+      || ip.to_ipv4_mapped().is_some_and(is_internal_v4)
+}
+
+pub fn is_blocked(ip: IpAddr, policy: &FetchPolicy) -> bool {
+   let internal = match ip {
+      IpAddr::V4(v4) => is_internal_v4(v4),
+      IpAddr::V6(v6) => is_internal_v6(v6),
+   };
+   internal || policy.extra_blocklist.iter().any(|range| range.contains(ip))
+}
+
+/// Chooses the address to connect to. Every resolved address must be allowed, so a hostile resolver
+/// can't sneak an internal address in alongside a public one; the chosen address is then pinned
+/// for the connection so a second lookup can't rebind it.
+fn pin_address(resolved: &[SocketAddr], policy: &FetchPolicy) -> Result<SocketAddr, FetchError> {
+   if let Some(blocked) = resolved.iter().find(|addr| is_blocked(addr.ip(), policy)) {
+      return Err(FetchError::Blocked(blocked.ip()));
+   }
+   resolved.first().copied().ok_or(FetchError::NoAddresses)
+}
+
+async fn resolve(url: &Url, policy: &FetchPolicy) -> Result<(String, SocketAddr), FetchError> {
+   match url.scheme() {
+      "http" | "https" => {}
+      scheme => return Err(FetchError::UnsupportedScheme(scheme.to_string())),
+   }
+   let port = url.port_or_known_default().ok_or(FetchError::MissingHost)?;
+   let host = url.host().ok_or(FetchError::MissingHost)?;
+
+   let resolved: Vec<SocketAddr> = match host {
+      Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+      Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+      Host::Domain(domain) => tokio::net::lookup_host((domain, port)).await?.collect(),
+   };
+   let pinned = pin_address(&resolved, policy)?;
+   Ok((host.to_string(), pinned))
+}
+
+fn redirect_target(current: &Url, location: Option<&str>) -> Result<Url, FetchError> {
+   let location = location.ok_or(FetchError::BadRedirect)?;
+   Ok(current.join(location)?)
+}
+
+/// Fetches a user-supplied URL without letting it reach internal network addresses.
+///
+/// Redirects are followed manually so each hop is re-resolved and re-checked.
+pub async fn safe_fetch(url: &str, policy: &FetchPolicy) -> Result<FetchedResponse, FetchError> {
+   let mut url = Url::parse(url)?;
+
+   for _ in 0..=MAX_REDIRECTS {
+      let (host, pinned) = resolve(&url, policy).await?;
+      debug!(%url, %pinned, "Fetching external URL");
+
+      let client = reqwest::Client::builder()
+         .redirect(redirect::Policy::none())
+         .timeout(FETCH_TIMEOUT)
+         .resolve(&host, pinned)
+         .build()?;
+      let mut response = client.get(url.clone()).send().await?;
+
+      if response.status().is_redirection() {
+         let location = response.headers().get(reqwest::header::LOCATION);
+         url = redirect_target(&url, location.and_then(|v| v.to_str().ok()))?;
+         continue;
+      }
+
+      if response
+         .content_length()
+         .is_some_and(|len| len as usize > MAX_BODY_BYTES)
+      {
+         return Err(FetchError::TooLarge);
+      }
+      let status = response.status();
+      let content_type = response
+         .headers()
+         .get(reqwest::header::CONTENT_TYPE)
+         .and_then(|v| v.to_str().ok())
+         .map(str::to_string);
+      let mut body = Vec::new();
+      while let Some(chunk) = response.chunk().await? {
+         if body.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(FetchError::TooLarge);
+         }
+         body.extend_from_slice(&chunk);
+      }
+
+      return Ok(FetchedResponse {
+         final_url: url,
+         status,
+         content_type,
+         body,
+      });
+   }
+
+   Err(FetchError::TooManyRedirects)
+}
+
+/// Checks every address a lookup made through the client returns, as `pin_address` does, for
+/// `Prober`, whose one client can't be pinned to an address per request.
+struct CheckedResolver(FetchPolicy);
+
+impl reqwest::dns::Resolve for CheckedResolver {
+   fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+      let policy = self.0.clone();
+      let name = name.as_str().to_string();
+      Box::pin(async move {
+         // The connector fills in the port
+         let resolved: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+         pin_address(&resolved, &policy)?;
+         Ok(Box::new(resolved.into_iter()) as reqwest::dns::Addrs)
+      })
+   }
+}
+
+/// The `FetchError` a lookup through `CheckedResolver` failed with, from under the request's error.
+fn refused(e: &reqwest::Error) -> Option<FetchError> {
+   let mut source = std::error::Error::source(e);
+   while let Some(error) = source {
+      match error.downcast_ref::<FetchError>() {
+         Some(FetchError::Blocked(ip)) => return Some(FetchError::Blocked(*ip)),
+         Some(FetchError::NoAddresses) => return Some(FetchError::NoAddresses),
+         _ => source = error.source(),
+      }
+   }
+   None
+}
+
+/// HEAD requests to user-supplied URLs, over one client kept for as long as the policy is, so
+/// probing a target again reuses its connection. Addresses are checked as `safe_fetch` checks them:
+/// an IP in the URL before the request, and every address a lookup returns before connecting.
+/// Redirects aren't followed; their own status is returned.
+#[derive(Clone)]
+pub struct Prober {
+   client: reqwest::Client,
+   policy: FetchPolicy,
+}
+
+impl Prober {
+   pub fn new(policy: FetchPolicy) -> Result<Self, FetchError> {
+      let client = reqwest::Client::builder()
+         .redirect(redirect::Policy::none())
+         .timeout(FETCH_TIMEOUT)
+         .dns_resolver(CheckedResolver(policy.clone()))
+         .build()?;
+      Ok(Prober { client, policy })
+   }
+
+   pub fn policy(&self) -> &FetchPolicy { &self.policy }
+
+   pub async fn probe(&self, url: &str) -> Result<StatusCode, FetchError> {
+      let url = Url::parse(url)?;
+      match url.scheme() {
+         "http" | "https" => {}
+         scheme => return Err(FetchError::UnsupportedScheme(scheme.to_string())),
+      }
+      // Addresses in the URL are connected to without a lookup
+      let literal = match url.host().ok_or(FetchError::MissingHost)? {
+         Host::Ipv4(ip) => Some(IpAddr::V4(ip)),
+         Host::Ipv6(ip) => Some(IpAddr::V6(ip)),
+         Host::Domain(_) => None,
+      };
+      if let Some(ip) = literal.filter(|ip| is_blocked(*ip, &self.policy)) {
+         return Err(FetchError::Blocked(ip));
+      }
+      debug!(%url, "Probing external URL");
+      match self.client.head(url).send().await {
+         Ok(response) => Ok(response.status()),
+         Err(e) => Err(refused(&e).unwrap_or(FetchError::Http(e))),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn addr(s: &str) -> SocketAddr { SocketAddr::new(s.parse().unwrap(), 443) }
+
+   #[test]
+   fn test_internal_ranges_are_blocked() {
+      let policy = FetchPolicy::default();
+      for ip in [
+         "127.0.0.1",
+         "10.1.2.3",
+         "172.16.0.1",
+         "192.168.1.1",
+         "169.254.169.254",
+         "100.64.0.1",
+         "0.0.0.0",
+         "::1",
+         "::",
+         "fe80::1",
+         "fc00::1",
+         "fd12:3456:789a::1",
+         "::ffff:127.0.0.1",
+         "::ffff:169.254.169.254",
+      ] {
+         assert!(is_blocked(ip.parse().unwrap(), &policy), "{ip} should be blocked");
+      }
+      for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111", "::ffff:1.1.1.1"] {
+         assert!(!is_blocked(ip.parse().unwrap(), &policy), "{ip} should be allowed");
+      }
+   }
+
+   #[test]
+   fn test_extra_blocklist() {
+      let policy = FetchPolicy {
+         extra_blocklist: vec!["198.18.0.0/15".parse().unwrap(), "2001:db8:1::/48".parse().unwrap()],
+      };
+      assert!(is_blocked("198.19.255.1".parse().unwrap(), &policy));
+      assert!(!is_blocked("198.20.0.1".parse().unwrap(), &policy));
+      assert!(!is_blocked("198.19.255.1".parse().unwrap(), &FetchPolicy::default()));
+      assert!(is_blocked("2001:db8:1:ffff::1".parse().unwrap(), &policy));
+      assert!(!is_blocked("2001:db8:2::1".parse().unwrap(), &policy));
+
+      assert!("203.0.113.0".parse::<IpRange>().is_err());
+      assert!("203.0.113.0/33".parse::<IpRange>().is_err());
+      assert!("::/0"
+         .parse::<IpRange>()
+         .unwrap()
+         .contains("2606:4700::1".parse().unwrap()));
+   }
+
+   #[test]
+   fn test_pin_address_rejects_rebinding_style_answers() {
+      let policy = FetchPolicy::default();
+
+      assert_eq!(
+         pin_address(&[addr("93.184.216.34")], &policy).unwrap(),
+         addr("93.184.216.34")
+      );
+
+      // A resolver mixing a public and an internal answer is refused outright
+      assert!(matches!(
+         pin_address(&[addr("93.184.216.34"), addr("10.0.0.1")], &policy),
+         Err(FetchError::Blocked(_))
+      ));
+      assert!(matches!(
+         pin_address(&[addr("fd00::1")], &policy),
+         Err(FetchError::Blocked(_))
+      ));
+      assert!(matches!(pin_address(&[], &policy), Err(FetchError::NoAddresses)));
+   }
+
+   #[tokio::test]
+   async fn test_literal_internal_hosts_are_refused_before_connecting() {
+      let policy = FetchPolicy::default();
+      for url in [
+         "http://169.254.169.254/latest/meta-data/",
+         "http://127.0.0.1:8080/",
+         "http://[::1]/",
+         "http://[fd00::1]/",
+         "http://[::ffff:192.168.0.1]/",
+      ] {
+         assert!(
+            matches!(safe_fetch(url, &policy).await, Err(FetchError::Blocked(_))),
+            "{url} should be blocked"
+         );
+      }
+      assert!(matches!(
+         safe_fetch("file:///etc/passwd", &policy).await,
+         Err(FetchError::UnsupportedScheme(_))
+      ));
+   }
+
+   #[tokio::test]
+   async fn test_redirect_to_internal_is_refused() {
+      let policy = FetchPolicy::default();
+      let origin = Url::parse("https://example.com/a").unwrap();
+
+      let next = redirect_target(&origin, Some("http://192.168.1.1/admin")).unwrap();
+      assert!(matches!(resolve(&next, &policy).await, Err(FetchError::Blocked(_))));
+
+      let next = redirect_target(&origin, Some("http://[fc00::5]:8443/")).unwrap();
+      assert!(matches!(resolve(&next, &policy).await, Err(FetchError::Blocked(_))));
+
+      let relative = redirect_target(&origin, Some("/b?c=d")).unwrap();
+      assert_eq!(relative.as_str(), "https://example.com/b?c=d");
+
+      assert!(matches!(redirect_target(&origin, None), Err(FetchError::BadRedirect)));
+   }
+}
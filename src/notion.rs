@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use notion_client::{
+   endpoints::{
+      data_sources::query::request::QueryDataSourceRequest,
+      databases::query::request::{Filter, FilterType, PropertyCondition, TextCondition},
+      pages::{create::request::CreateAPageRequest, update::request::UpdatePagePropertiesRequest},
+      Client as Notion,
+   },
+   objects::{
+      page::{PageProperty, SelectPropertyValue},
+      parent::Parent,
+      rich_text::{RichText, Text},
+   },
+};
+use tracing::debug;
+
+use crate::checkout::CheckoutState;
+use crate::models::{NotionPageId, TagUid};
+use limiter::{NotionLimiter, Priority};
+use schema::ThingsDb;
+
+pub mod edits;
+pub mod limiter;
+// Nothing traverses Things↔Containers yet; anything that does must go through `relations`.
+pub mod picker;
+#[allow(dead_code)]
+pub mod relations;
+pub mod schema;
+pub mod search;
+
+/// The Notion operations tag creation depends on, abstracted so the outbox dispatcher can be
+/// exercised against a fake.
+pub(crate) trait TagPages {
+   fn find_page_for_tag(&self, id: &TagUid) -> impl Future<Output = Result<Option<NotionPageId>, String>> + Send;
+   fn create_page_for_tag(&self, id: &TagUid) -> impl Future<Output = Result<NotionPageId, String>> + Send;
+   fn set_checkout_state(
+      &self,
+      page: &NotionPageId,
+      state: CheckoutState,
+   ) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+#[derive(Clone)]
+pub struct NotionTagPages {
+   pub client: Notion,
+   pub things_db: ThingsDb,
+   pub things_ds: String,
+   pub title_property: String,
+   pub tag_property: String,
+   /// A select property mirroring stateful tags' checkout state, if configured.
+   pub state_property: Option<String>,
+   pub limiter: NotionLimiter,
+}
+
+fn plain_text(content: &str) -> Vec<RichText> {
+   vec![RichText::Text {
+      text: Text {
+         content: content.to_string(),
+         link: None,
+      },
+      annotations: None,
+      plain_text: None,
+      href: None,
+   }]
+}
+
+impl TagPages for NotionTagPages {
+   async fn find_page_for_tag(&self, id: &TagUid) -> Result<Option<NotionPageId>, String> {
+      let request = QueryDataSourceRequest {
+         filter: Some(Filter::Value {
+            filter_type: FilterType::Property {
+               property: self.tag_property.clone(),
+               condition: PropertyCondition::RichText(TextCondition::Equals(id.to_string())),
+            },
+         }),
+         page_size: Some(1),
+         ..Default::default()
+      };
+      self.limiter.acquire(Priority::Background).await;
+      let response = self
+         .client
+         .data_sources
+         .query_a_data_source(&self.things_ds, request)
+         .await
+         .map_err(|err| {
+            format!(
+               "Failed to query DataSource {} for tag {}: {:?}",
+               self.things_ds, id, err
+            )
+         })?;
+
+      let found = response
+         .results
+         .first()
+         .map(|page| NotionPageId::new(&page.id))
+         .transpose();
+      found.map_err(|err| format!("Notion returned an unparseable page id: {}", err))
+   }
+
+   async fn create_page_for_tag(&self, id: &TagUid) -> Result<NotionPageId, String> {
+      let properties = BTreeMap::from([
+         (
+            self.title_property.clone(),
+            PageProperty::Title {
+               id: None,
+               title: plain_text(&id.to_string()),
+            },
+         ),
+         (
+            self.tag_property.clone(),
+            PageProperty::RichText {
+               id: None,
+               rich_text: plain_text(&id.to_string()),
+            },
+         ),
+      ]);
+      let request = CreateAPageRequest {
+         parent: Parent::DatabaseId {
+            database_id: self.things_db.to_string(),
+         },
+         properties,
+         ..Default::default()
+      };
+      self.limiter.acquire(Priority::Background).await;
+      let page = self
+         .client
+         .pages
+         .create_a_page(request)
+         .await
+         .map_err(|err| format!("Failed to create Notion page for tag {}: {:?}", id, err))?;
+
+      debug!(tag_id = %id, page_id = page.id, "Created Notion page");
+      NotionPageId::new(&page.id).map_err(|err| format!("Notion returned an unparseable page id: {}", err))
+   }
+
+   async fn set_checkout_state(&self, page: &NotionPageId, state: CheckoutState) -> Result<(), String> {
+      let Some(state_property) = &self.state_property else {
+         debug!(page_id = %page, "No state property configured, not mirroring checkout state");
+         return Ok(());
+      };
+      let properties = BTreeMap::from([(
+         state_property.clone(),
+         Some(PageProperty::Select {
+            id: None,
+            select: Some(SelectPropertyValue {
+               id: None,
+               name: Some(state.label().to_string()),
+               color: None,
+            }),
+         }),
+      )]);
+      let request = UpdatePagePropertiesRequest {
+         properties,
+         ..Default::default()
+      };
+      self.limiter.acquire(Priority::Background).await;
+      self
+         .client
+         .pages
+         .update_page_properties(page, request)
+         .await
+         .map_err(|err| format!("Failed to mirror checkout state to Notion page {}: {:?}", page, err))?;
+      Ok(())
+   }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, SecondsFormat};
+use serde::Deserialize;
+
+use super::limiter::{NotionLimiter, Priority};
+use super::relations::{NOTION_API_BASE, NOTION_API_VERSION};
+use crate::models::NotionPageId;
+
+const EDITED_PAGE_SIZE: usize = 100;
+/// Written to, when the Things database has a rich-text property by this name, with why a page's
+/// URL wasn't synced.
+pub const SYNC_STATUS_PROPERTY: &str = "Sync status";
+
+/// A Things page, reduced to what the URL sync reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditedPage {
+   pub page_id: NotionPageId,
+   /// Unix seconds. Notion rounds these down to the minute.
+   pub last_edited: i64,
+   /// The URL property, unless it's empty.
+   pub url: Option<String>,
+   /// The `SYNC_STATUS_PROPERTY` text; `None` when the page has no such property.
+   pub status: Option<String>,
+}
+
+/// One page of a data-source query for edited pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditedList {
+   pub pages: Vec<EditedPage>,
+   pub next_cursor: Option<String>,
+   /// The ids of the URL and sync status properties, where any page had them; see
+   /// `NotionEdits::property_ids`.
+   pub property_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PageList {
+   results: Vec<PageObject>,
+   next_cursor: Option<String>,
+   has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct PageObject {
+   id: String,
+   last_edited_time: String,
+   #[serde(default)]
+   properties: HashMap<String, serde_json::Value>,
+}
+
+fn plain_text(rich_text: &serde_json::Value) -> String {
+   rich_text
+      .as_array()
+      .into_iter()
+      .flatten()
+      .filter_map(|part| part["plain_text"].as_str())
+      .collect()
+}
+
+pub fn parse_edited_list(body: &str, url_property: &str) -> Result<EditedList, String> {
+   let list: PageList = serde_json::from_str(body).map_err(|err| format!("Malformed page list: {}", err))?;
+   let property_ids = [url_property, SYNC_STATUS_PROPERTY]
+      .into_iter()
+      .filter_map(|name| {
+         list
+            .results
+            .iter()
+            .find_map(|page| page.properties.get(name)?["id"].as_str())
+      })
+      .map(str::to_string)
+      .collect();
+   let pages = list
+      .results
+      .into_iter()
+      .map(|page| {
+         let page_id =
+            NotionPageId::new(&page.id).map_err(|err| format!("Notion returned an unparseable page id: {}", err))?;
+         let last_edited = DateTime::parse_from_rfc3339(&page.last_edited_time)
+            .map_err(|err| format!("Page {} has an unparseable last_edited_time: {}", page_id, err))?
+            .timestamp();
+         let url = page
+            .properties
+            .get(url_property)
+            .and_then(|property| property["url"].as_str())
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(str::to_string);
+         let status = page
+            .properties
+            .get(SYNC_STATUS_PROPERTY)
+            .filter(|property| property["type"] == "rich_text")
+            .map(|property| plain_text(&property["rich_text"]));
+         Ok(EditedPage {
+            page_id,
+            last_edited,
+            url,
+            status,
+         })
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+   Ok(EditedList {
+      pages,
+      next_cursor: list.next_cursor.filter(|_| list.has_more),
+      property_ids,
+   })
+}
+
+/// A data-source query, limited to `property_ids` once they're known. Notion hands out property
+/// ids already URL-encoded, so they go into the query string as they are.
+fn query_url(things_ds: &str, property_ids: Option<&[String]>) -> String {
+   let url = format!("{}/data_sources/{}/query", NOTION_API_BASE, things_ds);
+   match property_ids {
+      Some(ids) if !ids.is_empty() => {
+         let query: Vec<String> = ids.iter().map(|id| format!("filter_properties={}", id)).collect();
+         format!("{}?{}", url, query.join("&"))
+      }
+      _ => url,
+   }
+}
+
+pub(crate) trait EditSource {
+   /// Pages last edited at or after `since`, oldest first.
+   fn fetch_edited(&self, since: i64, cursor: Option<&str>) -> impl Future<Output = Result<EditedList, String>> + Send;
+   fn set_sync_status(&self, page_id: &NotionPageId, status: &str) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// Pages edited at or after `since`, a query page at a time, following the query's cursor; so a
+/// sync can act on, and record, each page of results before asking for the next.
+pub(crate) struct EditedPages<'a, S> {
+   source: &'a S,
+   since: i64,
+   cursor: Option<String>,
+   done: bool,
+}
+
+impl<'a, S: EditSource> EditedPages<'a, S> {
+   pub fn new(source: &'a S, since: i64) -> Self {
+      EditedPages {
+         source,
+         since,
+         cursor: None,
+         done: false,
+      }
+   }
+
+   /// The next page of results; `None` once there are no more.
+   pub async fn next(&mut self) -> Result<Option<Vec<EditedPage>>, String> {
+      if self.done {
+         return Ok(None);
+      }
+      let list = self.source.fetch_edited(self.since, self.cursor.as_deref()).await?;
+      match list.next_cursor {
+         Some(next) if next.is_empty() || self.cursor.as_deref() == Some(next.as_str()) => {
+            return Err("Notion returned a non-advancing cursor for edited pages".to_string());
+         }
+         Some(next) => self.cursor = Some(next),
+         None => self.done = true,
+      }
+      Ok(Some(list.pages))
+   }
+}
+
+/// Every page edited at or after `since`.
+#[cfg(test)]
+pub(crate) async fn collect_edited(source: &impl EditSource, since: i64) -> Result<Vec<EditedPage>, String> {
+   let mut edited = EditedPages::new(source, since);
+   let mut pages = Vec::new();
+   while let Some(batch) = edited.next().await? {
+      pages.extend(batch);
+   }
+   Ok(pages)
+}
+
+/// Queries the Things data source directly over HTTP, like `NotionRelations`, so that what's read
+/// can be checked against recorded page objects.
+pub struct NotionEdits {
+   pub http: reqwest::Client,
+   pub token: String,
+   pub things_ds: String,
+   pub url_property: String,
+   pub limiter: NotionLimiter,
+   /// Learned from the first page of results; later queries ask for only these properties, rather
+   /// than every property of every page. Ids, unlike names, survive the properties being renamed.
+   pub property_ids: OnceLock<Vec<String>>,
+}
+
+impl NotionEdits {
+   async fn send(&self, request: reqwest::RequestBuilder, body: serde_json::Value) -> Result<String, String> {
+      self.limiter.acquire(Priority::Background).await;
+      let response = request
+         .bearer_auth(&self.token)
+         .header("Notion-Version", NOTION_API_VERSION)
+         .header(reqwest::header::CONTENT_TYPE, "application/json")
+         .body(body.to_string())
+         .send()
+         .await
+         .inspect(|response| self.limiter.record(response))
+         .and_then(|response| response.error_for_status())
+         .map_err(|err| format!("{:?}", err))?;
+      response.text().await.map_err(|err| format!("{:?}", err))
+   }
+}
+
+impl EditSource for NotionEdits {
+   async fn fetch_edited(&self, since: i64, cursor: Option<&str>) -> Result<EditedList, String> {
+      let since = DateTime::from_timestamp(since, 0)
+         .ok_or_else(|| format!("Sync cursor {} is out of range", since))?
+         .to_rfc3339_opts(SecondsFormat::Secs, true);
+      let mut body = serde_json::json!({
+         "filter": {"timestamp": "last_edited_time", "last_edited_time": {"on_or_after": since}},
+         "sorts": [{"timestamp": "last_edited_time", "direction": "ascending"}],
+         "page_size": EDITED_PAGE_SIZE,
+      });
+      if let Some(cursor) = cursor {
+         body["start_cursor"] = cursor.into();
+      }
+      let url = query_url(&self.things_ds, self.property_ids.get().map(Vec::as_slice));
+      let body = self.send(self.http.post(url), body).await.map_err(|err| {
+         format!(
+            "Failed to query DataSource {} for edited pages: {}",
+            self.things_ds, err
+         )
+      })?;
+      let list = parse_edited_list(&body, &self.url_property)?;
+      if !list.property_ids.is_empty() {
+         let _ = self.property_ids.set(list.property_ids.clone());
+      }
+      Ok(list)
+   }
+
+   async fn set_sync_status(&self, page_id: &NotionPageId, status: &str) -> Result<(), String> {
+      let body = serde_json::json!({
+         "properties": {
+            SYNC_STATUS_PROPERTY: {"rich_text": [{"type": "text", "text": {"content": status}}]},
+         },
+      });
+      let url = format!("{}/pages/{}", NOTION_API_BASE, page_id);
+      self
+         .send(self.http.patch(url), body)
+         .await
+         .map_err(|err| format!("Failed to set the sync status of page {}: {}", page_id, err))?;
+      Ok(())
+   }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+   use std::sync::Mutex;
+
+   use super::*;
+
+   // Shaped like `POST /v1/data_sources/{id}/query` responses from a Things database with a "Link"
+   // URL property, trimmed to the properties the sync reads.
+   const FIXTURE_EDITED_1: &str = r#"{
+      "object": "list",
+      "results": [
+         {"object": "page", "id": "11111111-1111-4111-8111-111111111111",
+          "created_time": "2026-10-01T09:00:00.000Z", "last_edited_time": "2026-10-16T12:00:00.000Z",
+          "properties": {
+             "Name": {"id": "title", "type": "title", "title": [{"type": "text", "plain_text": "055B88A23C1250"}]},
+             "Link": {"id": "%3AbCd", "type": "url", "url": "https://example.com/new"},
+             "Sync status": {"id": "sTaT", "type": "rich_text", "rich_text": []}
+          }},
+         {"object": "page", "id": "22222222-2222-4222-8222-222222222222",
+          "created_time": "2026-10-01T09:00:00.000Z", "last_edited_time": "2026-10-16T12:05:00.000Z",
+          "properties": {
+             "Link": {"id": "%3AbCd", "type": "url", "url": "javascript:alert(1)"},
+             "Sync status": {"id": "sTaT", "type": "rich_text", "rich_text": []}
+          }}
+      ],
+      "next_cursor": "cursor-edited-2",
+      "has_more": true,
+      "type": "page_or_data_source",
+      "page_or_data_source": {},
+      "request_id": "5c0ad3b1-0000-4000-8000-000000000011"
+   }"#;
+   const FIXTURE_EDITED_2: &str = r#"{
+      "object": "list",
+      "results": [
+         {"object": "page", "id": "33333333333341118111333333333333",
+          "created_time": "2026-10-01T09:00:00.000Z", "last_edited_time": "2026-10-16T12:10:00.000Z",
+          "properties": {
+             "Link": {"id": "%3AbCd", "type": "url", "url": null},
+             "Sync status": {"id": "sTaT", "type": "rich_text",
+                "rich_text": [{"type": "text", "text": {"content": "twag: stale"}, "plain_text": "twag: stale"}]}
+          }}
+      ],
+      "next_cursor": null,
+      "has_more": false,
+      "type": "page_or_data_source",
+      "page_or_data_source": {},
+      "request_id": "5c0ad3b1-0000-4000-8000-000000000012"
+   }"#;
+
+   /// Serves the fixtures, and records the statuses written back.
+   #[derive(Default)]
+   pub(crate) struct FixtureEdits {
+      pub statuses: Mutex<Vec<(NotionPageId, String)>>,
+   }
+
+   impl EditSource for FixtureEdits {
+      async fn fetch_edited(&self, _since: i64, cursor: Option<&str>) -> Result<EditedList, String> {
+         match cursor {
+            None => parse_edited_list(FIXTURE_EDITED_1, "Link"),
+            Some("cursor-edited-2") => parse_edited_list(FIXTURE_EDITED_2, "Link"),
+            Some(other) => Err(format!("Unexpected cursor {}", other)),
+         }
+      }
+
+      async fn set_sync_status(&self, page_id: &NotionPageId, status: &str) -> Result<(), String> {
+         self
+            .statuses
+            .lock()
+            .unwrap()
+            .push((page_id.clone(), status.to_string()));
+         Ok(())
+      }
+   }
+
+   #[test]
+   fn test_parse_edited_list() {
+      let list = parse_edited_list(FIXTURE_EDITED_1, "Link").unwrap();
+      assert_eq!(list.next_cursor.as_deref(), Some("cursor-edited-2"));
+      assert_eq!(
+         list.pages[0],
+         EditedPage {
+            page_id: NotionPageId::new("11111111-1111-4111-8111-111111111111").unwrap(),
+            last_edited: 1_792_152_000,
+            url: Some("https://example.com/new".to_string()),
+            status: Some(String::new()),
+         }
+      );
+
+      let last = parse_edited_list(FIXTURE_EDITED_2, "Link").unwrap();
+      assert_eq!(last.next_cursor, None);
+      assert_eq!(last.pages[0].url, None);
+      assert_eq!(last.pages[0].status.as_deref(), Some("twag: stale"));
+
+      // Another URL property, and a database without a sync status
+      let other = parse_edited_list(&FIXTURE_EDITED_1.replace("Sync status", "Notes"), "Website").unwrap();
+      assert_eq!(
+         (other.pages[0].url.as_ref(), other.pages[0].status.as_ref()),
+         (None, None)
+      );
+
+      assert!(parse_edited_list("{}", "Link").is_err());
+   }
+
+   #[test]
+   fn test_later_queries_ask_for_only_the_synced_properties() {
+      let list = parse_edited_list(FIXTURE_EDITED_1, "Link").unwrap();
+      assert_eq!(list.property_ids, ["%3AbCd", "sTaT"]);
+      assert_eq!(
+         query_url("ds-1", None),
+         format!("{}/data_sources/ds-1/query", NOTION_API_BASE)
+      );
+      assert_eq!(
+         query_url("ds-1", Some(&list.property_ids)),
+         format!(
+            "{}/data_sources/ds-1/query?filter_properties=%3AbCd&filter_properties=sTaT",
+            NOTION_API_BASE
+         )
+      );
+      // Nothing to learn from a database without the properties
+      let other = parse_edited_list(&FIXTURE_EDITED_1.replace("Sync status", "Notes"), "Website").unwrap();
+      assert!(other.property_ids.is_empty());
+   }
+
+   #[tokio::test]
+   async fn test_collect_edited_follows_the_cursor() {
+      let pages = collect_edited(&FixtureEdits::default(), 0).await.unwrap();
+      assert_eq!(pages.len(), 3);
+      assert_eq!(pages[2].page_id.to_string(), "33333333-3333-4111-8111-333333333333");
+   }
+}
@@ -0,0 +1,236 @@
+//! One token bucket for every call twag makes to Notion, which allows an integration about three
+//! requests a second. Interactive calls, like the page picker's, may spend the whole bucket;
+//! background ones, like the URL sync's and the outbox's, leave `RESERVED` tokens for them, so a
+//! long sync never keeps an admin waiting. A 429 empties the bucket until Notion's `Retry-After`
+//! has passed.
+//!
+//! The bucket only ever reads the time it's given, so its tests run on a made-up clock.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tokens added a second.
+const RATE: f64 = 3.0;
+const BURST: f64 = 3.0;
+/// Tokens background calls leave in the bucket.
+const RESERVED: f64 = 1.0;
+/// Waits are never shorter, so rounding never spins a caller.
+const MIN_WAIT: Duration = Duration::from_millis(1);
+/// How long a 429 without a usable `Retry-After` pauses calls.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+   /// Someone is waiting on the answer.
+   Interactive,
+   Background,
+}
+
+#[derive(Debug)]
+struct Bucket {
+   tokens: f64,
+   updated: Instant,
+   paused_until: Option<Instant>,
+}
+
+impl Bucket {
+   fn new(now: Instant) -> Self {
+      Bucket {
+         tokens: BURST,
+         updated: now,
+         paused_until: None,
+      }
+   }
+
+   /// Takes a token for a call of `priority`, or says how long until one could.
+   fn take(&mut self, priority: Priority, now: Instant) -> Result<(), Duration> {
+      if let Some(until) = self.paused_until.take() {
+         if now < until {
+            self.paused_until = Some(until);
+            return Err(until - now);
+         }
+         // Nothing refills during the pause
+         self.updated = until;
+      }
+      let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+      self.tokens = (self.tokens + elapsed * RATE).min(BURST);
+      self.updated = now;
+      let floor = match priority {
+         Priority::Interactive => 0.0,
+         Priority::Background => RESERVED,
+      };
+      if self.tokens - 1.0 >= floor {
+         self.tokens -= 1.0;
+         return Ok(());
+      }
+      Err(Duration::from_secs_f64((floor + 1.0 - self.tokens) / RATE).max(MIN_WAIT))
+   }
+
+   fn throttled(&mut self, retry_after: Duration, now: Instant) {
+      self.tokens = 0.0;
+      self.updated = now;
+      self.paused_until = Some(now + retry_after);
+   }
+}
+
+/// Counts since startup, for `/healthz`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NotionStats {
+   pub requests: u64,
+   /// Responses with status 429.
+   pub throttled: u64,
+}
+
+impl fmt::Display for NotionStats {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(
+         f,
+         "notion: {} request(s), {} rate-limited",
+         self.requests, self.throttled
+      )
+   }
+}
+
+/// Shared by every Notion caller; clones share the bucket and counts.
+#[derive(Clone)]
+pub struct NotionLimiter {
+   bucket: Arc<Mutex<Bucket>>,
+   requests: Arc<AtomicU64>,
+   throttled: Arc<AtomicU64>,
+}
+
+impl Default for NotionLimiter {
+   fn default() -> Self {
+      NotionLimiter {
+         bucket: Arc::new(Mutex::new(Bucket::new(Instant::now()))),
+         requests: Arc::default(),
+         throttled: Arc::default(),
+      }
+   }
+}
+
+impl NotionLimiter {
+   /// Takes a token without waiting, for calls better refused than queued.
+   pub fn try_acquire(&self, priority: Priority) -> bool {
+      let taken = self.bucket.lock().unwrap().take(priority, Instant::now()).is_ok();
+      if taken {
+         self.requests.fetch_add(1, Ordering::Relaxed);
+      }
+      taken
+   }
+
+   /// Waits for a token.
+   pub async fn acquire(&self, priority: Priority) {
+      loop {
+         let taken = self.bucket.lock().unwrap().take(priority, Instant::now());
+         match taken {
+            Ok(()) => {
+               self.requests.fetch_add(1, Ordering::Relaxed);
+               return;
+            }
+            Err(wait) => tokio::time::sleep(wait).await,
+         }
+      }
+   }
+
+   /// Counts a 429, and pauses every caller for as long as it asks.
+   pub fn record(&self, response: &reqwest::Response) {
+      if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+         return;
+      }
+      let retry_after = response
+         .headers()
+         .get(reqwest::header::RETRY_AFTER)
+         .and_then(|v| v.to_str().ok())
+         .and_then(|v| v.trim().parse().ok())
+         .map(Duration::from_secs)
+         .unwrap_or(DEFAULT_RETRY_AFTER);
+      self.throttled.fetch_add(1, Ordering::Relaxed);
+      self.bucket.lock().unwrap().throttled(retry_after, Instant::now());
+   }
+
+   pub fn stats(&self) -> NotionStats {
+      NotionStats {
+         requests: self.requests.load(Ordering::Relaxed),
+         throttled: self.throttled.load(Ordering::Relaxed),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn ms(n: u64) -> Duration { Duration::from_millis(n) }
+
+   #[test]
+   fn test_background_calls_leave_room_for_interactive_ones() {
+      let start = Instant::now();
+      let mut bucket = Bucket::new(start);
+      assert_eq!(bucket.take(Priority::Background, start), Ok(()));
+      assert_eq!(bucket.take(Priority::Background, start), Ok(()));
+      // The last token is kept back
+      assert!(bucket.take(Priority::Background, start).is_err());
+      assert_eq!(bucket.take(Priority::Interactive, start), Ok(()));
+      assert!(bucket.take(Priority::Interactive, start).is_err());
+   }
+
+   #[test]
+   fn test_interactive_calls_go_first_as_the_bucket_refills() {
+      let start = Instant::now();
+      let mut bucket = Bucket::new(start);
+      for _ in 0..3 {
+         bucket.take(Priority::Interactive, start).unwrap();
+      }
+      let interactive = bucket.take(Priority::Interactive, start).unwrap_err();
+      let background = bucket.take(Priority::Background, start).unwrap_err();
+      assert!(interactive < background, "{interactive:?} {background:?}");
+
+      // A third of a second refills one token: enough for an interactive call, not a background one
+      let later = start + ms(340);
+      assert!(bucket.take(Priority::Background, later).is_err());
+      assert_eq!(bucket.take(Priority::Interactive, later), Ok(()));
+      // Background calls wait for two
+      let later = later + ms(680);
+      assert_eq!(bucket.take(Priority::Background, later), Ok(()));
+   }
+
+   #[test]
+   fn test_a_sustained_sync_stays_under_the_rate() {
+      let start = Instant::now();
+      let mut bucket = Bucket::new(start);
+      let mut now = start;
+      let mut calls = 0;
+      while now < start + Duration::from_secs(10) {
+         match bucket.take(Priority::Background, now) {
+            Ok(()) => calls += 1,
+            Err(wait) => now += wait,
+         }
+      }
+      // The burst, less the reserve, then three a second
+      assert!((30..=33).contains(&calls), "{calls}");
+   }
+
+   #[test]
+   fn test_a_429_pauses_everyone() {
+      let start = Instant::now();
+      let mut bucket = Bucket::new(start);
+      bucket.throttled(Duration::from_secs(2), start);
+      assert_eq!(bucket.take(Priority::Interactive, start + ms(500)), Err(ms(1500)));
+      assert!(bucket.take(Priority::Background, start + ms(1999)).is_err());
+      // Refilling only starts once the pause is over
+      assert!(bucket
+         .take(Priority::Background, start + Duration::from_secs(2))
+         .is_err());
+      assert_eq!(bucket.take(Priority::Interactive, start + ms(2340)), Ok(()));
+   }
+
+   #[test]
+   fn test_stats() {
+      let limiter = NotionLimiter::default();
+      assert!(limiter.try_acquire(Priority::Interactive));
+      assert_eq!(limiter.stats().to_string(), "notion: 1 request(s), 0 rate-limited");
+   }
+}
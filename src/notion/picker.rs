@@ -0,0 +1,358 @@
+//! Searches the Things and Containers databases by title, for the page picker on the create page,
+//! and looks up single pages' titles. Like `edits`, this calls Notion directly over HTTP.
+//!
+//! Every keystroke is a request, so results are cached briefly, and calls to Notion are spaced out
+//! to stay under its limit of about three requests a second per integration. They also draw on
+//! the `limiter` every Notion call shares, ahead of background work.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::limiter::{NotionLimiter, Priority};
+use super::relations::{NOTION_API_BASE, NOTION_API_VERSION};
+use crate::models::NotionPageId;
+
+/// Results per search; a picker showing more than this wants a better query.
+pub const RESULT_CAP: usize = 10;
+const CACHE_TTL: Duration = Duration::from_secs(30);
+const MAX_CACHED: usize = 256;
+/// The least time between two calls to Notion.
+const MIN_INTERVAL: Duration = Duration::from_millis(350);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Database {
+   Things,
+   Containers,
+}
+
+impl fmt::Display for Database {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         Database::Things => write!(f, "Things"),
+         Database::Containers => write!(f, "Containers"),
+      }
+   }
+}
+
+/// A page as the picker lists it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PickedPage {
+   pub id: NotionPageId,
+   pub title: String,
+   /// The page's emoji icon; `None` for none, or an uploaded image.
+   pub icon: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum PickError {
+   #[error("Searching Notion too often; try again in a moment")]
+   Busy,
+   #[error("{0}")]
+   Notion(String),
+}
+
+/// The body of a data-source query for pages whose title contains `q`. With no query, the most
+/// recently edited pages instead, which is usually what's being linked.
+pub fn query_body(q: &str, title_property: &str) -> serde_json::Value {
+   let mut body = serde_json::json!({
+      "sorts": [{"timestamp": "last_edited_time", "direction": "descending"}],
+      "page_size": RESULT_CAP,
+   });
+   let q = q.trim();
+   if !q.is_empty() {
+      body["filter"] = serde_json::json!({"property": title_property, "title": {"contains": q}});
+   }
+   body
+}
+
+/// The plain text of a page's title property, whatever that property is called.
+fn title_of(page: &serde_json::Value) -> String {
+   page["properties"]
+      .as_object()
+      .into_iter()
+      .flat_map(|properties| properties.values())
+      .find(|property| property["type"] == "title")
+      .and_then(|property| property["title"].as_array())
+      .into_iter()
+      .flatten()
+      .filter_map(|part| part["plain_text"].as_str())
+      .collect()
+}
+
+/// The title of a page, as `GET /v1/pages/{id}` returns it.
+pub fn parse_page_title(body: &str) -> Result<String, String> {
+   let page: serde_json::Value = serde_json::from_str(body).map_err(|err| format!("Malformed page: {}", err))?;
+   if page["object"] != "page" {
+      return Err("Malformed page: not a page".to_string());
+   }
+   Ok(title_of(&page))
+}
+
+pub fn parse_pages(body: &str) -> Result<Vec<PickedPage>, String> {
+   let list: serde_json::Value = serde_json::from_str(body).map_err(|err| format!("Malformed page list: {}", err))?;
+   let Some(results) = list["results"].as_array() else {
+      return Err("Malformed page list: no results".to_string());
+   };
+   results
+      .iter()
+      .take(RESULT_CAP)
+      .map(|page| {
+         let id = page["id"].as_str().unwrap_or_default();
+         let id = NotionPageId::new(id).map_err(|err| format!("Notion returned an unparseable page id: {}", err))?;
+         let title = title_of(page);
+         let icon = Some(&page["icon"])
+            .filter(|icon| icon["type"] == "emoji")
+            .and_then(|icon| icon["emoji"].as_str())
+            .map(str::to_string);
+         Ok(PickedPage { id, title, icon })
+      })
+      .collect()
+}
+
+/// A data source to search, and its title property.
+pub struct Searchable {
+   pub ds: String,
+   pub title_property: String,
+}
+
+pub struct NotionPicker {
+   pub http: reqwest::Client,
+   pub token: String,
+   pub things: Searchable,
+   pub containers: Searchable,
+   pub limiter: NotionLimiter,
+   cache: Mutex<HashMap<(Database, String), (Instant, Vec<PickedPage>)>>,
+   last_call: Mutex<Option<Instant>>,
+}
+
+impl NotionPicker {
+   pub fn new(token: String, things: Searchable, containers: Searchable, limiter: NotionLimiter) -> Self {
+      NotionPicker {
+         http: reqwest::Client::new(),
+         token,
+         things,
+         containers,
+         limiter,
+         cache: Mutex::new(HashMap::new()),
+         last_call: Mutex::new(None),
+      }
+   }
+
+   fn cached(&self, key: &(Database, String), now: Instant) -> Option<Vec<PickedPage>> {
+      let mut cache = self.cache.lock().unwrap();
+      cache.retain(|_, (fetched_at, _)| now.duration_since(*fetched_at) < CACHE_TTL);
+      cache.get(key).map(|(_, pages)| pages.clone())
+   }
+
+   fn remember(&self, key: (Database, String), pages: Vec<PickedPage>, now: Instant) {
+      let mut cache = self.cache.lock().unwrap();
+      if cache.len() >= MAX_CACHED {
+         cache.clear();
+      }
+      cache.insert(key, (now, pages));
+   }
+
+   /// Claims the next call to Notion, unless the last was too recent.
+   fn claim_call(&self, now: Instant) -> bool {
+      let mut last_call = self.last_call.lock().unwrap();
+      if last_call.is_some_and(|last| now.duration_since(last) < MIN_INTERVAL) {
+         return false;
+      }
+      *last_call = Some(now);
+      true
+   }
+
+   pub async fn search(&self, database: Database, q: &str) -> Result<Vec<PickedPage>, PickError> {
+      let key = (database, q.trim().to_lowercase());
+      if let Some(pages) = self.cached(&key, Instant::now()) {
+         return Ok(pages);
+      }
+      if !self.claim_call(Instant::now()) || !self.limiter.try_acquire(Priority::Interactive) {
+         return Err(PickError::Busy);
+      }
+
+      let source = match database {
+         Database::Things => &self.things,
+         Database::Containers => &self.containers,
+      };
+      let url = format!("{}/data_sources/{}/query", NOTION_API_BASE, source.ds);
+      let response = self
+         .http
+         .post(url)
+         .bearer_auth(&self.token)
+         .header("Notion-Version", NOTION_API_VERSION)
+         .header(reqwest::header::CONTENT_TYPE, "application/json")
+         .body(query_body(q, &source.title_property).to_string())
+         .send()
+         .await
+         .inspect(|response| self.limiter.record(response))
+         .and_then(|response| response.error_for_status());
+      let response = match response {
+         Ok(response) => response,
+         Err(err) if err.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) => return Err(PickError::Busy),
+         Err(err) => return Err(PickError::Notion(format!("Failed to search {}: {:?}", database, err))),
+      };
+      let body = response
+         .text()
+         .await
+         .map_err(|err| PickError::Notion(format!("Failed to read {} search results: {:?}", database, err)))?;
+      let pages = parse_pages(&body).map_err(PickError::Notion)?;
+      self.remember(key, pages.clone(), Instant::now());
+      Ok(pages)
+   }
+
+   /// Not cached or spaced out like searches: it's only for pages an admin opens one at a time. It
+   /// still waits its turn with the limiter.
+   pub async fn page_title(&self, page: &NotionPageId) -> Result<String, String> {
+      self.limiter.acquire(Priority::Interactive).await;
+      let url = format!("{}/pages/{}", NOTION_API_BASE, page);
+      let body = self
+         .http
+         .get(url)
+         .bearer_auth(&self.token)
+         .header("Notion-Version", NOTION_API_VERSION)
+         .send()
+         .await
+         .inspect(|response| self.limiter.record(response))
+         .and_then(|response| response.error_for_status())
+         .map_err(|err| format!("Failed to fetch page {}: {:?}", page, err))?
+         .text()
+         .await
+         .map_err(|err| format!("Failed to read page {}: {:?}", page, err))?;
+      parse_page_title(&body)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Shaped like `POST /v1/data_sources/{id}/query` from a Containers database whose title
+   // property is "Box", trimmed to the fields the picker reads.
+   const FIXTURE_CONTAINERS: &str = r#"{
+      "object": "list",
+      "results": [
+         {"object": "page", "id": "11111111-1111-4111-8111-111111111111",
+          "icon": {"type": "emoji", "emoji": "📦"},
+          "properties": {
+             "Things": {"id": "%3EzXp", "type": "relation", "relation": [], "has_more": false},
+             "Box": {"id": "title", "type": "title", "title": [
+                {"type": "text", "plain_text": "Camera "}, {"type": "text", "plain_text": "bag"}
+             ]}
+          }},
+         {"object": "page", "id": "22222222222242228222222222222222",
+          "icon": {"type": "external", "external": {"url": "https://example.com/icon.png"}},
+          "properties": {
+             "Box": {"id": "title", "type": "title", "title": []}
+          }}
+      ],
+      "next_cursor": null,
+      "has_more": false,
+      "type": "page_or_data_source",
+      "page_or_data_source": {},
+      "request_id": "5c0ad3b1-0000-4000-8000-000000000021"
+   }"#;
+
+   fn picker() -> NotionPicker {
+      NotionPicker::new(
+         "secret_x".to_string(),
+         Searchable {
+            ds: "things".to_string(),
+            title_property: "Name".to_string(),
+         },
+         Searchable {
+            ds: "containers".to_string(),
+            title_property: "Box".to_string(),
+         },
+         NotionLimiter::default(),
+      )
+   }
+
+   #[test]
+   fn test_query_body_filters_on_the_title() {
+      assert_eq!(
+         query_body(" camera ", "Box"),
+         serde_json::json!({
+            "filter": {"property": "Box", "title": {"contains": "camera"}},
+            "sorts": [{"timestamp": "last_edited_time", "direction": "descending"}],
+            "page_size": RESULT_CAP,
+         })
+      );
+   }
+
+   #[test]
+   fn test_empty_query_lists_recent_pages() {
+      for q in ["", "   "] {
+         let body = query_body(q, "Name");
+         assert!(body.get("filter").is_none(), "{q:?}");
+         assert_eq!(body["sorts"][0]["direction"], "descending");
+         assert_eq!(body["page_size"], RESULT_CAP);
+      }
+   }
+
+   #[test]
+   fn test_parse_pages() {
+      let pages = parse_pages(FIXTURE_CONTAINERS).unwrap();
+      assert_eq!(
+         pages,
+         [
+            PickedPage {
+               id: NotionPageId::new("11111111-1111-4111-8111-111111111111").unwrap(),
+               title: "Camera bag".to_string(),
+               icon: Some("📦".to_string()),
+            },
+            PickedPage {
+               id: NotionPageId::new("22222222222242228222222222222222").unwrap(),
+               title: String::new(),
+               icon: None,
+            },
+         ]
+      );
+   }
+
+   #[test]
+   fn test_parse_page_title() {
+      let body = r#"{"object": "page", "id": "11111111-1111-4111-8111-111111111111",
+         "properties": {"Name": {"id": "title", "type": "title", "title": [
+            {"type": "text", "plain_text": "Grandma's "}, {"type": "text", "plain_text": "clock"}
+         ]}}}"#;
+      assert_eq!(parse_page_title(body), Ok("Grandma's clock".to_string()));
+      assert!(parse_page_title(r#"{"object": "error", "status": 404}"#).is_err());
+      assert!(parse_page_title("").is_err());
+   }
+
+   #[test]
+   fn test_parse_pages_rejects_a_bad_id() {
+      let body = r#"{"results": [{"id": "not-a-page", "properties": {}}]}"#;
+      assert!(parse_pages(body).is_err());
+   }
+
+   #[test]
+   fn test_calls_to_notion_are_spaced_out() {
+      let picker = picker();
+      let now = Instant::now();
+      assert!(picker.claim_call(now));
+      assert!(!picker.claim_call(now + Duration::from_millis(100)));
+      assert!(picker.claim_call(now + MIN_INTERVAL));
+   }
+
+   #[test]
+   fn test_results_are_cached_briefly() {
+      let picker = picker();
+      let now = Instant::now();
+      let key = (Database::Containers, "camera".to_string());
+      picker.remember(key.clone(), parse_pages(FIXTURE_CONTAINERS).unwrap(), now);
+      assert_eq!(
+         picker
+            .cached(&key, now + Duration::from_secs(1))
+            .map(|pages| pages.len()),
+         Some(2)
+      );
+      assert_eq!(picker.cached(&(Database::Things, "camera".to_string()), now), None);
+      assert_eq!(picker.cached(&key, now + CACHE_TTL), None);
+   }
+}
@@ -0,0 +1,713 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::models::NotionPageId;
+
+pub(crate) const NOTION_API_BASE: &str = "https://api.notion.com/v1";
+pub(crate) const NOTION_API_VERSION: &str = "2025-09-03";
+const RELATION_PAGE_SIZE: usize = 100;
+const DEFAULT_RELATION_CAP: usize = 200;
+const RELATION_CACHE_TTL: Duration = Duration::from_secs(30);
+/// The Containers property pointing at the container each one is inside.
+const DEFAULT_PARENT_PROPERTY: &str = "Inside";
+const DEFAULT_CONTAINER_DEPTH: usize = 5;
+/// Containers are rarely moved, so their paths are kept far longer than relations.
+const BREADCRUMB_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// One page of a relation property's items, as returned by the page-property-item endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelationPage {
+   pub ids: Vec<NotionPageId>,
+   pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PropertyItemList {
+   results: Vec<PropertyItem>,
+   next_cursor: Option<String>,
+   has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct PropertyItem {
+   relation: Option<RelationReference>,
+}
+
+#[derive(Deserialize)]
+struct RelationReference {
+   id: String,
+}
+
+pub fn parse_relation_page(body: &str) -> Result<RelationPage, String> {
+   let list: PropertyItemList =
+      serde_json::from_str(body).map_err(|err| format!("Malformed property item list: {}", err))?;
+   let ids = list
+      .results
+      .into_iter()
+      .filter_map(|item| item.relation)
+      .map(|relation| NotionPageId::new(&relation.id))
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|err| format!("Notion returned an unparseable page id: {}", err))?;
+   Ok(RelationPage {
+      ids,
+      next_cursor: list.next_cursor.filter(|_| list.has_more),
+   })
+}
+
+/// The related pages of one relation property, capped so a huge container can't stall a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelationIds {
+   pub ids: Vec<NotionPageId>,
+   /// Relations fetched but cut off by the cap.
+   pub omitted: usize,
+   /// Whether Notion had further pages that were never fetched, so `omitted` is a lower bound.
+   pub has_more: bool,
+}
+
+impl RelationIds {
+   pub fn more_label(&self) -> Option<String> {
+      match (self.omitted, self.has_more) {
+         (0, false) => None,
+         (0, true) => Some("and more".to_string()),
+         (omitted, false) => Some(format!("and {} more", omitted)),
+         (omitted, true) => Some(format!("and {}+ more", omitted)),
+      }
+   }
+}
+
+pub(crate) trait RelationSource {
+   fn fetch_relation_page(
+      &self,
+      page_id: &NotionPageId,
+      property_id: &str,
+      cursor: Option<&str>,
+   ) -> impl Future<Output = Result<RelationPage, String>> + Send;
+}
+
+/// Walks the property-item cursor until `cap` relations are collected. Relation properties embedded
+/// in a page object are silently truncated at 25 entries, so this is the only complete source.
+pub(crate) async fn collect_relation(
+   source: &impl RelationSource,
+   page_id: &NotionPageId,
+   property_id: &str,
+   cap: usize,
+) -> Result<RelationIds, String> {
+   let mut ids = Vec::new();
+   let mut cursor: Option<String> = None;
+   loop {
+      let page = source
+         .fetch_relation_page(page_id, property_id, cursor.as_deref())
+         .await?;
+      let room = cap - ids.len();
+      if page.ids.len() > room {
+         let omitted = page.ids.len() - room;
+         ids.extend(page.ids.into_iter().take(room));
+         return Ok(RelationIds {
+            ids,
+            omitted,
+            has_more: page.next_cursor.is_some(),
+         });
+      }
+      ids.extend(page.ids);
+
+      match page.next_cursor {
+         Some(next) if next.is_empty() || cursor.as_deref() == Some(next.as_str()) => {
+            return Err(format!("Notion returned a non-advancing cursor for page {}", page_id));
+         }
+         Some(_) if ids.len() >= cap => {
+            return Ok(RelationIds {
+               ids,
+               omitted: 0,
+               has_more: true,
+            });
+         }
+         Some(next) => cursor = Some(next),
+         None => {
+            return Ok(RelationIds {
+               ids,
+               omitted: 0,
+               has_more: false,
+            });
+         }
+      }
+   }
+}
+
+/// Short-lived memo of relation lookups, keyed by page and property.
+pub struct RelationCache {
+   ttl: Duration,
+   entries: Mutex<HashMap<(NotionPageId, String), (Instant, RelationIds)>>,
+}
+
+impl RelationCache {
+   pub fn new(ttl: Duration) -> Self {
+      RelationCache {
+         ttl,
+         entries: Mutex::new(HashMap::new()),
+      }
+   }
+
+   pub fn get(&self, page_id: &NotionPageId, property_id: &str, now: Instant) -> Option<RelationIds> {
+      let mut entries = self.entries.lock().unwrap();
+      entries.retain(|_, (fetched_at, _)| now.duration_since(*fetched_at) < self.ttl);
+      entries
+         .get(&(page_id.clone(), property_id.to_string()))
+         .map(|(_, relation)| relation.clone())
+   }
+
+   pub fn insert(&self, page_id: &NotionPageId, property_id: &str, relation: RelationIds, now: Instant) {
+      let mut entries = self.entries.lock().unwrap();
+      entries.insert((page_id.clone(), property_id.to_string()), (now, relation));
+   }
+}
+
+/// A Containers page, as far as its breadcrumb needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+   pub title: String,
+   /// The first page its parent property points at, if any.
+   pub parent: Option<NotionPageId>,
+}
+
+#[derive(Deserialize)]
+struct PageObject {
+   properties: HashMap<String, PageProperty>,
+}
+
+#[derive(Deserialize)]
+struct PageProperty {
+   title: Option<Vec<PlainText>>,
+   relation: Option<Vec<RelationReference>>,
+}
+
+#[derive(Deserialize)]
+struct PlainText {
+   plain_text: String,
+}
+
+/// Reads a container's title and parent from a page object. The relation embedded in a page is
+/// truncated at 25 entries, which doesn't matter for the one parent read here.
+pub fn parse_container(body: &str, parent_property: &str) -> Result<Container, String> {
+   let page: PageObject = serde_json::from_str(body).map_err(|err| format!("Malformed page: {}", err))?;
+   let title = page
+      .properties
+      .values()
+      .find_map(|property| property.title.as_ref())
+      .ok_or("Page has no title property")?
+      .iter()
+      .map(|text| text.plain_text.as_str())
+      .collect();
+   let parent = page
+      .properties
+      .get(parent_property)
+      .and_then(|property| property.relation.as_ref())
+      .and_then(|relation| relation.first())
+      .map(|parent| NotionPageId::new(&parent.id))
+      .transpose()
+      .map_err(|err| format!("Notion returned an unparseable page id: {}", err))?;
+   Ok(Container { title, parent })
+}
+
+pub(crate) trait ContainerSource {
+   fn fetch_container(&self, page_id: &NotionPageId) -> impl Future<Output = Result<Container, String>> + Send;
+}
+
+/// Why a breadcrumb stops short of the outermost container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cut {
+   /// The next container up was already on the path.
+   Cycle,
+   /// There were more containers up than the depth allows.
+   Depth,
+}
+
+/// Where a container is, outermost first: `Garage › Shelf B › Blue box`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breadcrumb {
+   pub titles: Vec<String>,
+   pub cut: Option<Cut>,
+}
+
+impl fmt::Display for Breadcrumb {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      if self.cut == Some(Cut::Depth) {
+         f.write_str("… › ")?;
+      }
+      f.write_str(&self.titles.join(" › "))
+   }
+}
+
+/// Follows `start`'s parent property up through at most `max_depth` containers, `start` included.
+/// A container that turns out to be inside one already on the path ends the walk where it is, with
+/// a warning, rather than failing the lookup.
+pub(crate) async fn breadcrumb(
+   source: &impl ContainerSource,
+   start: &NotionPageId,
+   max_depth: usize,
+) -> Result<Breadcrumb, String> {
+   let mut titles = Vec::new();
+   let mut seen = HashSet::new();
+   let mut next = Some(start.clone());
+   let mut cut = None;
+   while let Some(page_id) = next.take() {
+      if titles.len() == max_depth {
+         cut = Some(Cut::Depth);
+         break;
+      }
+      if !seen.insert(page_id.clone()) {
+         warn!(container = %start, %page_id, "Containers are inside each other; showing the path up to the loop");
+         cut = Some(Cut::Cycle);
+         break;
+      }
+      let container = source.fetch_container(&page_id).await?;
+      titles.push(container.title);
+      next = container.parent;
+   }
+   titles.reverse();
+   Ok(Breadcrumb { titles, cut })
+}
+
+/// Breadcrumbs by the container they lead to, for `BREADCRUMB_CACHE_TTL`.
+pub struct BreadcrumbCache {
+   ttl: Duration,
+   entries: Mutex<HashMap<NotionPageId, (Instant, Breadcrumb)>>,
+}
+
+impl BreadcrumbCache {
+   pub fn new(ttl: Duration) -> Self {
+      BreadcrumbCache {
+         ttl,
+         entries: Mutex::new(HashMap::new()),
+      }
+   }
+
+   pub fn get(&self, container: &NotionPageId, now: Instant) -> Option<Breadcrumb> {
+      let mut entries = self.entries.lock().unwrap();
+      entries.retain(|_, (fetched_at, _)| now.duration_since(*fetched_at) < self.ttl);
+      entries.get(container).map(|(_, breadcrumb)| breadcrumb.clone())
+   }
+
+   pub fn insert(&self, container: &NotionPageId, breadcrumb: Breadcrumb, now: Instant) {
+      self
+         .entries
+         .lock()
+         .unwrap()
+         .insert(container.clone(), (now, breadcrumb));
+   }
+}
+
+/// Fetches relation property items directly over HTTP, since the typed client doesn't expose the
+/// endpoint's pagination cursor.
+pub struct NotionRelations {
+   pub http: reqwest::Client,
+   pub token: String,
+   pub cap: usize,
+   pub cache: RelationCache,
+   /// The Containers property a container's own container is in.
+   pub parent_property: String,
+   pub max_depth: usize,
+   pub breadcrumbs: BreadcrumbCache,
+}
+
+impl RelationSource for NotionRelations {
+   async fn fetch_relation_page(
+      &self,
+      page_id: &NotionPageId,
+      property_id: &str,
+      cursor: Option<&str>,
+   ) -> Result<RelationPage, String> {
+      let mut url = reqwest::Url::parse(&format!("{}/pages/{}/properties/", NOTION_API_BASE, page_id))
+         .and_then(|base| base.join(property_id))
+         .map_err(|err| format!("Failed to build property item URL: {}", err))?;
+      url.query_pairs_mut()
+         .append_pair("page_size", &RELATION_PAGE_SIZE.to_string());
+      if let Some(cursor) = cursor {
+         url.query_pairs_mut().append_pair("start_cursor", cursor);
+      }
+
+      let response = self
+         .http
+         .get(url)
+         .bearer_auth(&self.token)
+         .header("Notion-Version", NOTION_API_VERSION)
+         .send()
+         .await
+         .and_then(|response| response.error_for_status())
+         .map_err(|err| {
+            format!(
+               "Failed to retrieve property {} of page {}: {:?}",
+               property_id, page_id, err
+            )
+         })?;
+      let body = response
+         .text()
+         .await
+         .map_err(|err| format!("Failed to read property {} of page {}: {:?}", property_id, page_id, err))?;
+      parse_relation_page(&body)
+   }
+}
+
+impl ContainerSource for NotionRelations {
+   async fn fetch_container(&self, page_id: &NotionPageId) -> Result<Container, String> {
+      let response = self
+         .http
+         .get(format!("{}/pages/{}", NOTION_API_BASE, page_id))
+         .bearer_auth(&self.token)
+         .header("Notion-Version", NOTION_API_VERSION)
+         .send()
+         .await
+         .and_then(|response| response.error_for_status())
+         .map_err(|err| format!("Failed to retrieve container {}: {:?}", page_id, err))?;
+      let body = response
+         .text()
+         .await
+         .map_err(|err| format!("Failed to read container {}: {:?}", page_id, err))?;
+      parse_container(&body, &self.parent_property)
+   }
+}
+
+impl NotionRelations {
+   pub fn from_env(token: String) -> Self {
+      NotionRelations {
+         http: reqwest::Client::new(),
+         token,
+         cap: dotenvy::var("NOTION_RELATION_LIMIT")
+            .ok()
+            .map(|s| s.parse::<usize>().expect("Invalid NOTION_RELATION_LIMIT"))
+            .unwrap_or(DEFAULT_RELATION_CAP),
+         cache: RelationCache::new(RELATION_CACHE_TTL),
+         parent_property: dotenvy::var("NOTION_CONTAINERS_PARENT_COLUMN_NAME")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| DEFAULT_PARENT_PROPERTY.to_string()),
+         max_depth: dotenvy::var("NOTION_CONTAINER_DEPTH")
+            .ok()
+            .map(|s| s.parse::<usize>().expect("Invalid NOTION_CONTAINER_DEPTH"))
+            .filter(|depth| *depth > 0)
+            .unwrap_or(DEFAULT_CONTAINER_DEPTH),
+         breadcrumbs: BreadcrumbCache::new(BREADCRUMB_CACHE_TTL),
+      }
+   }
+
+   /// The path to `container`, from the cache while it's fresh.
+   pub async fn breadcrumb(&self, container: &NotionPageId) -> Result<Breadcrumb, String> {
+      if let Some(cached) = self.breadcrumbs.get(container, Instant::now()) {
+         return Ok(cached);
+      }
+      let breadcrumb = breadcrumb(self, container, self.max_depth).await?;
+      debug!(%container, depth = breadcrumb.titles.len(), cut = ?breadcrumb.cut, "Fetched container path");
+      self.breadcrumbs.insert(container, breadcrumb.clone(), Instant::now());
+      Ok(breadcrumb)
+   }
+
+   pub async fn relation_ids(&self, page_id: &NotionPageId, property_id: &str) -> Result<RelationIds, String> {
+      if let Some(cached) = self.cache.get(page_id, property_id, Instant::now()) {
+         return Ok(cached);
+      }
+      let relation = collect_relation(self, page_id, property_id, self.cap).await?;
+      debug!(%page_id, property_id, count = relation.ids.len(), omitted = relation.omitted, "Fetched relation");
+      self
+         .cache
+         .insert(page_id, property_id, relation.clone(), Instant::now());
+      Ok(relation)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Recorded from `GET /v1/pages/{id}/properties/{property_id}` against a container with 5 things,
+   // trimmed to two items per page.
+   const FIXTURE_PAGE_1: &str = r#"{
+      "object": "list",
+      "results": [
+         {"object": "property_item", "id": "%3EzXp", "type": "relation",
+          "relation": {"id": "11111111-1111-4111-8111-111111111111"}},
+         {"object": "property_item", "id": "%3EzXp", "type": "relation",
+          "relation": {"id": "22222222-2222-4222-8222-222222222222"}}
+      ],
+      "next_cursor": "cursor-page-2",
+      "has_more": true,
+      "type": "property_item",
+      "property_item": {"id": "%3EzXp", "next_url": "https://api.notion.com/v1/pages/x/properties/%3EzXp?start_cursor=cursor-page-2", "type": "relation", "relation": {}},
+      "request_id": "5c0ad3b1-0000-4000-8000-000000000001"
+   }"#;
+   const FIXTURE_PAGE_2: &str = r#"{
+      "object": "list",
+      "results": [
+         {"object": "property_item", "id": "%3EzXp", "type": "relation",
+          "relation": {"id": "33333333333341118111333333333333"}},
+         {"object": "property_item", "id": "%3EzXp", "type": "relation",
+          "relation": {"id": "44444444-4444-4444-8444-444444444444"}}
+      ],
+      "next_cursor": "cursor-page-3",
+      "has_more": true,
+      "type": "property_item",
+      "property_item": {"id": "%3EzXp", "next_url": "https://api.notion.com/v1/pages/x/properties/%3EzXp?start_cursor=cursor-page-3", "type": "relation", "relation": {}},
+      "request_id": "5c0ad3b1-0000-4000-8000-000000000002"
+   }"#;
+   const FIXTURE_PAGE_3: &str = r#"{
+      "object": "list",
+      "results": [
+         {"object": "property_item", "id": "%3EzXp", "type": "relation",
+          "relation": {"id": "55555555-5555-4555-8555-555555555555"}}
+      ],
+      "next_cursor": null,
+      "has_more": false,
+      "type": "property_item",
+      "property_item": {"id": "%3EzXp", "next_url": null, "type": "relation", "relation": {}},
+      "request_id": "5c0ad3b1-0000-4000-8000-000000000003"
+   }"#;
+
+   struct FixtureSource {
+      requests: Mutex<Vec<Option<String>>>,
+   }
+
+   impl FixtureSource {
+      fn new() -> Self {
+         FixtureSource {
+            requests: Mutex::new(Vec::new()),
+         }
+      }
+   }
+
+   impl RelationSource for FixtureSource {
+      async fn fetch_relation_page(
+         &self,
+         _page_id: &NotionPageId,
+         _property_id: &str,
+         cursor: Option<&str>,
+      ) -> Result<RelationPage, String> {
+         self.requests.lock().unwrap().push(cursor.map(str::to_string));
+         match cursor {
+            None => parse_relation_page(FIXTURE_PAGE_1),
+            Some("cursor-page-2") => parse_relation_page(FIXTURE_PAGE_2),
+            Some("cursor-page-3") => parse_relation_page(FIXTURE_PAGE_3),
+            Some(other) => Err(format!("Unexpected cursor {}", other)),
+         }
+      }
+   }
+
+   fn container() -> NotionPageId { NotionPageId::new("99999999-9999-4999-8999-999999999999").unwrap() }
+
+   #[test]
+   fn test_parse_relation_page() {
+      let page = parse_relation_page(FIXTURE_PAGE_2).unwrap();
+      assert_eq!(page.ids.len(), 2);
+      assert_eq!(page.ids[0].to_string(), "33333333-3333-4111-8111-333333333333");
+      assert_eq!(page.next_cursor.as_deref(), Some("cursor-page-3"));
+
+      let last = parse_relation_page(FIXTURE_PAGE_3).unwrap();
+      assert_eq!(last.next_cursor, None);
+
+      assert!(parse_relation_page("{}").is_err());
+   }
+
+   #[tokio::test]
+   async fn test_collect_relation_follows_every_cursor() {
+      let source = FixtureSource::new();
+      let relation = collect_relation(&source, &container(), "%3EzXp", 100).await.unwrap();
+
+      assert_eq!(relation.ids.len(), 5);
+      assert_eq!(relation.ids[4].to_string(), "55555555-5555-4555-8555-555555555555");
+      assert_eq!(relation.omitted, 0);
+      assert!(!relation.has_more);
+      assert_eq!(relation.more_label(), None);
+      assert_eq!(
+         *source.requests.lock().unwrap(),
+         [
+            None,
+            Some("cursor-page-2".to_string()),
+            Some("cursor-page-3".to_string())
+         ]
+      );
+   }
+
+   #[tokio::test]
+   async fn test_collect_relation_stops_at_cap() {
+      let source = FixtureSource::new();
+      let relation = collect_relation(&source, &container(), "%3EzXp", 3).await.unwrap();
+      assert_eq!(relation.ids.len(), 3);
+      assert_eq!(relation.omitted, 1);
+      assert!(relation.has_more);
+      assert_eq!(relation.more_label().as_deref(), Some("and 1+ more"));
+      assert_eq!(source.requests.lock().unwrap().len(), 2);
+
+      let source = FixtureSource::new();
+      let relation = collect_relation(&source, &container(), "%3EzXp", 4).await.unwrap();
+      assert_eq!(relation.ids.len(), 4);
+      assert_eq!(relation.omitted, 0);
+      assert_eq!(relation.more_label().as_deref(), Some("and more"));
+      assert_eq!(source.requests.lock().unwrap().len(), 2);
+   }
+
+   #[tokio::test]
+   async fn test_collect_relation_rejects_non_advancing_cursor() {
+      struct StuckSource;
+      impl RelationSource for StuckSource {
+         async fn fetch_relation_page(
+            &self,
+            _page_id: &NotionPageId,
+            _property_id: &str,
+            _cursor: Option<&str>,
+         ) -> Result<RelationPage, String> {
+            Ok(RelationPage {
+               ids: vec![],
+               next_cursor: Some("same".to_string()),
+            })
+         }
+      }
+      assert!(collect_relation(&StuckSource, &container(), "%3EzXp", 100)
+         .await
+         .is_err());
+   }
+
+   #[test]
+   fn test_relation_cache_expires() {
+      let cache = RelationCache::new(Duration::from_secs(30));
+      let start = Instant::now();
+      let relation = RelationIds {
+         ids: vec![container()],
+         omitted: 0,
+         has_more: false,
+      };
+      cache.insert(&container(), "%3EzXp", relation.clone(), start);
+
+      assert_eq!(
+         cache.get(&container(), "%3EzXp", start + Duration::from_secs(29)),
+         Some(relation)
+      );
+      assert_eq!(cache.get(&container(), "other", start), None);
+      assert_eq!(cache.get(&container(), "%3EzXp", start + Duration::from_secs(30)), None);
+   }
+
+   // Trimmed from `GET /v1/pages/{id}` on a container inside another.
+   const FIXTURE_CONTAINER: &str = r#"{
+      "object": "page",
+      "id": "33333333-3333-4333-8333-333333333333",
+      "properties": {
+         "Inside": {"id": "bN%5Bq", "type": "relation", "has_more": false,
+                    "relation": [{"id": "22222222-2222-4222-8222-222222222222"}]},
+         "Things": {"id": "%3EzXp", "type": "relation", "has_more": true, "relation": []},
+         "Name": {"id": "title", "type": "title",
+                  "title": [{"type": "text", "plain_text": "Blue "}, {"type": "text", "plain_text": "box"}]}
+      }
+   }"#;
+
+   /// A container graph: each page's title, and the page it's inside.
+   struct FixtureGraph(HashMap<NotionPageId, (&'static str, Option<NotionPageId>)>);
+
+   impl FixtureGraph {
+      fn new(edges: &[(u8, &'static str, Option<u8>)]) -> Self {
+         FixtureGraph(
+            edges
+               .iter()
+               .map(|(page, title, parent)| (page_id(*page), (*title, parent.map(page_id))))
+               .collect(),
+         )
+      }
+   }
+
+   impl ContainerSource for FixtureGraph {
+      async fn fetch_container(&self, page_id: &NotionPageId) -> Result<Container, String> {
+         let (title, parent) = self.0.get(page_id).ok_or(format!("No page {}", page_id))?;
+         Ok(Container {
+            title: title.to_string(),
+            parent: parent.clone(),
+         })
+      }
+   }
+
+   fn page_id(n: u8) -> NotionPageId { NotionPageId::new(format!("{:032x}", n)).unwrap() }
+
+   #[test]
+   fn test_parse_container() {
+      let container = parse_container(FIXTURE_CONTAINER, "Inside").unwrap();
+      assert_eq!(container.title, "Blue box");
+      assert_eq!(
+         container.parent.unwrap().to_string(),
+         "22222222-2222-4222-8222-222222222222"
+      );
+
+      assert_eq!(parse_container(FIXTURE_CONTAINER, "Parent").unwrap().parent, None);
+      assert!(parse_container(r#"{"properties": {}}"#, "Inside").is_err());
+   }
+
+   #[tokio::test]
+   async fn test_breadcrumb_walks_up_to_the_outermost_container() {
+      let graph = FixtureGraph::new(&[(1, "Garage", None), (2, "Shelf B", Some(1)), (3, "Blue box", Some(2))]);
+      let path = breadcrumb(&graph, &page_id(3), 5).await.unwrap();
+      assert_eq!(path.cut, None);
+      assert_eq!(path.to_string(), "Garage › Shelf B › Blue box");
+
+      let outermost = breadcrumb(&graph, &page_id(1), 5).await.unwrap();
+      assert_eq!(outermost.to_string(), "Garage");
+   }
+
+   #[tokio::test]
+   async fn test_breadcrumb_stops_at_the_depth_limit() {
+      let graph = FixtureGraph::new(&[
+         (1, "House", None),
+         (2, "Garage", Some(1)),
+         (3, "Shelf B", Some(2)),
+         (4, "Blue box", Some(3)),
+      ]);
+      let path = breadcrumb(&graph, &page_id(4), 3).await.unwrap();
+      assert_eq!(path.cut, Some(Cut::Depth));
+      assert_eq!(path.to_string(), "… › Garage › Shelf B › Blue box");
+
+      // Exactly as deep as allowed isn't cut
+      assert_eq!(breadcrumb(&graph, &page_id(4), 4).await.unwrap().cut, None);
+   }
+
+   #[tokio::test]
+   async fn test_breadcrumb_cycles_degrade_to_the_partial_path() {
+      let graph = FixtureGraph::new(&[
+         (1, "Shelf A", Some(3)),
+         (2, "Shelf B", Some(1)),
+         (3, "Blue box", Some(2)),
+      ]);
+      let path = breadcrumb(&graph, &page_id(3), 5).await.unwrap();
+      assert_eq!(path.cut, Some(Cut::Cycle));
+      assert_eq!(path.to_string(), "Shelf A › Shelf B › Blue box");
+
+      let inside_itself = FixtureGraph::new(&[(1, "Box", Some(1))]);
+      assert_eq!(
+         breadcrumb(&inside_itself, &page_id(1), 5).await.unwrap(),
+         Breadcrumb {
+            titles: vec!["Box".to_string()],
+            cut: Some(Cut::Cycle),
+         }
+      );
+   }
+
+   #[tokio::test]
+   async fn test_breadcrumb_fails_on_a_missing_container() {
+      let graph = FixtureGraph::new(&[(2, "Shelf B", Some(1))]);
+      assert!(breadcrumb(&graph, &page_id(2), 5).await.is_err());
+   }
+
+   #[test]
+   fn test_breadcrumb_cache_expires() {
+      let cache = BreadcrumbCache::new(BREADCRUMB_CACHE_TTL);
+      let start = Instant::now();
+      let breadcrumb = Breadcrumb {
+         titles: vec!["Garage".to_string()],
+         cut: None,
+      };
+      cache.insert(&container(), breadcrumb.clone(), start);
+      assert_eq!(
+         cache.get(&container(), start + Duration::from_secs(599)),
+         Some(breadcrumb)
+      );
+      assert_eq!(cache.get(&page_id(1), start), None);
+      assert_eq!(cache.get(&container(), start + BREADCRUMB_CACHE_TTL), None);
+   }
+}
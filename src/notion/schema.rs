@@ -0,0 +1,511 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::ops::Deref;
+
+use notion_client::{
+   endpoints::Client as Notion,
+   objects::{data_source::DataSource, database::DatabaseProperty},
+   NotionClientError,
+};
+use tracing::debug;
+
+use crate::models::NotionPageId;
+
+/// The shape of a data-source property, reduced to what twag validates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyKind {
+   Title,
+   RichText,
+   Select,
+   Url,
+   Relation { database_id: Option<String> },
+   Other(String),
+}
+
+impl fmt::Display for PropertyKind {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         PropertyKind::Title => write!(f, "title"),
+         PropertyKind::RichText => write!(f, "rich_text"),
+         PropertyKind::Select => write!(f, "select"),
+         PropertyKind::Url => write!(f, "url"),
+         PropertyKind::Relation { .. } => write!(f, "relation"),
+         PropertyKind::Other(kind) => write!(f, "{}", kind),
+      }
+   }
+}
+
+/// A data source's properties by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema(pub BTreeMap<String, PropertyKind>);
+
+impl From<&DataSource> for Schema {
+   fn from(data_source: &DataSource) -> Self {
+      let kinds = data_source.properties.iter().map(|(name, property)| {
+         let kind = match property {
+            DatabaseProperty::Title { .. } => PropertyKind::Title,
+            DatabaseProperty::RichText { .. } => PropertyKind::RichText,
+            DatabaseProperty::Select { .. } => PropertyKind::Select,
+            DatabaseProperty::Url { .. } => PropertyKind::Url,
+            DatabaseProperty::Relation { relation, .. } => PropertyKind::Relation {
+               database_id: relation.database_id.clone(),
+            },
+            other => PropertyKind::Other(format!("{:?}", other)),
+         };
+         (name.clone(), kind)
+      });
+      Schema(kinds.collect())
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationError {
+   #[error("{database}: Notion rejected the integration token ({message})")]
+   AuthFailed { database: String, message: String },
+   #[error("{database}: failed to retrieve DataSource: {message}")]
+   Api { database: String, message: String },
+   #[error("{database}: missing required property '{property}'")]
+   MissingProperty { database: String, property: String },
+   #[error("{database}: '{property}' must be a {expected} property, found {found}")]
+   WrongType {
+      database: String,
+      property: String,
+      expected: &'static str,
+      found: String,
+   },
+   #[error("{database}: '{property}' relation points to {}, expected {expected}", actual.as_deref().unwrap_or("no Database"))]
+   WrongTarget {
+      database: String,
+      property: String,
+      expected: String,
+      actual: Option<String>,
+   },
+   #[error("{database}: no title property")]
+   NoTitleProperty { database: String },
+}
+
+impl ValidationError {
+   /// Whether fixing this needs a new token or sharing the database with the integration, rather
+   /// than a change to the database's schema.
+   pub fn is_auth(&self) -> bool { matches!(self, ValidationError::AuthFailed { .. }) }
+
+   pub fn is_schema(&self) -> bool { !matches!(self, ValidationError::AuthFailed { .. } | ValidationError::Api { .. }) }
+}
+
+/// Every problem found across both databases.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+   pub problems: Vec<ValidationError>,
+}
+
+impl ValidationReport {
+   pub fn is_ok(&self) -> bool { self.problems.is_empty() }
+
+   pub fn into_result(self) -> Result<(), ValidationReport> {
+      if self.is_ok() {
+         Ok(())
+      } else {
+         Err(self)
+      }
+   }
+}
+
+impl fmt::Display for ValidationReport {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "{} Notion validation problem(s):", self.problems.len())?;
+      for problem in &self.problems {
+         write!(f, "\n - {}", problem)?;
+      }
+      Ok(())
+   }
+}
+
+/// The Things database. It and `ContainersDb`, and likewise the two relation columns, are
+/// distinct types so that one can't be passed where the other is expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThingsDb(pub NotionPageId);
+
+/// The Containers database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainersDb(pub NotionPageId);
+
+/// The Things property relating each thing to its container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThingsRelationColumn(pub String);
+
+/// The Containers property relating each container to its contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainersRelationColumn(pub String);
+
+impl Deref for ThingsDb {
+   type Target = NotionPageId;
+
+   fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl Deref for ContainersDb {
+   type Target = NotionPageId;
+
+   fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl Deref for ThingsRelationColumn {
+   type Target = str;
+
+   fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl Deref for ContainersRelationColumn {
+   type Target = str;
+
+   fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl fmt::Display for ThingsDb {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.0.fmt(f) }
+}
+
+impl fmt::Display for ContainersDb {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.0.fmt(f) }
+}
+
+impl fmt::Display for ThingsRelationColumn {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.0.fmt(f) }
+}
+
+impl fmt::Display for ContainersRelationColumn {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.0.fmt(f) }
+}
+
+/// One side of the Things↔Containers relation.
+#[derive(Debug, Clone)]
+pub struct DatabaseRef<'a, Db, Column> {
+   pub name: &'static str,
+   pub db: &'a Db,
+   pub ds: &'a str,
+   /// The relation property pointing at the other database.
+   pub column: &'a Column,
+}
+
+pub fn validate_relation(
+   schema: &Schema,
+   database: &str,
+   property: &str,
+   expected_target_db: &NotionPageId,
+) -> Result<(), ValidationError> {
+   match schema.0.get(property) {
+      Some(PropertyKind::Relation { database_id }) if database_id.as_deref() == Some(&**expected_target_db) => Ok(()),
+      Some(PropertyKind::Relation { database_id }) => Err(ValidationError::WrongTarget {
+         database: database.to_string(),
+         property: property.to_string(),
+         expected: expected_target_db.to_string(),
+         actual: database_id.clone(),
+      }),
+      Some(kind) => Err(ValidationError::WrongType {
+         database: database.to_string(),
+         property: property.to_string(),
+         expected: "relation",
+         found: kind.to_string(),
+      }),
+      None => Err(ValidationError::MissingProperty {
+         database: database.to_string(),
+         property: property.to_string(),
+      }),
+   }
+}
+
+pub fn validate_rich_text(schema: &Schema, database: &str, property: &str) -> Result<(), ValidationError> {
+   match schema.0.get(property) {
+      Some(PropertyKind::RichText) => Ok(()),
+      Some(kind) => Err(ValidationError::WrongType {
+         database: database.to_string(),
+         property: property.to_string(),
+         expected: "rich_text",
+         found: kind.to_string(),
+      }),
+      None => Err(ValidationError::MissingProperty {
+         database: database.to_string(),
+         property: property.to_string(),
+      }),
+   }
+}
+
+pub fn validate_select(schema: &Schema, database: &str, property: &str) -> Result<(), ValidationError> {
+   match schema.0.get(property) {
+      Some(PropertyKind::Select) => Ok(()),
+      Some(kind) => Err(ValidationError::WrongType {
+         database: database.to_string(),
+         property: property.to_string(),
+         expected: "select",
+         found: kind.to_string(),
+      }),
+      None => Err(ValidationError::MissingProperty {
+         database: database.to_string(),
+         property: property.to_string(),
+      }),
+   }
+}
+
+pub fn validate_url(schema: &Schema, database: &str, property: &str) -> Result<(), ValidationError> {
+   match schema.0.get(property) {
+      Some(PropertyKind::Url) => Ok(()),
+      Some(kind) => Err(ValidationError::WrongType {
+         database: database.to_string(),
+         property: property.to_string(),
+         expected: "url",
+         found: kind.to_string(),
+      }),
+      None => Err(ValidationError::MissingProperty {
+         database: database.to_string(),
+         property: property.to_string(),
+      }),
+   }
+}
+
+pub fn find_title_property(schema: &Schema, database: &str) -> Result<String, ValidationError> {
+   schema
+      .0
+      .iter()
+      .find(|(_, kind)| **kind == PropertyKind::Title)
+      .map(|(name, _)| name.clone())
+      .ok_or_else(|| ValidationError::NoTitleProperty {
+         database: database.to_string(),
+      })
+}
+
+/// Retrieve the primary data-source for a Notion Database.
+///
+/// Since API version 2025-09-03, Database properties live on data-sources
+/// rather than on the Database object itself.
+pub async fn retrieve_schema(
+   client: &Notion,
+   name: &str,
+   database_id: &NotionPageId,
+   data_source_id: &str,
+) -> Result<Schema, ValidationError> {
+   // FIXME: once `notion-client` fixes Database deserialization for API >=
+   //    2025-09-03, discover the data_source_id from the Database object
+   //    instead of requiring it as a parameter.
+   let ds = client
+      .data_sources
+      .retrieve_a_data_source(data_source_id)
+      .await
+      .map_err(|err| {
+         let message = format!("DataSource {} for Database {}: {:?}", data_source_id, database_id, err);
+         match err {
+            NotionClientError::InvalidStatusCode { error } if matches!(error.status, 401 | 403) => {
+               ValidationError::AuthFailed {
+                  database: name.to_string(),
+                  message,
+               }
+            }
+            _ => ValidationError::Api {
+               database: name.to_string(),
+               message,
+            },
+         }
+      })?;
+
+   debug!(?ds, "Retrieved DataSource for Database {}", database_id);
+   Ok(Schema::from(&ds))
+}
+
+/// Retrieves both schemas concurrently and checks that each side's relation points at the other,
+/// reporting every problem rather than stopping at the first.
+pub async fn validate_databases<F, Fut>(
+   retrieve: F,
+   things: DatabaseRef<'_, ThingsDb, ThingsRelationColumn>,
+   containers: DatabaseRef<'_, ContainersDb, ContainersRelationColumn>,
+) -> ValidationReport
+where
+   F: Fn(&'static str, NotionPageId, String) -> Fut,
+   Fut: Future<Output = Result<Schema, ValidationError>>,
+{
+   let (things_schema, containers_schema) = tokio::join!(
+      retrieve(things.name, things.db.0.clone(), things.ds.to_string()),
+      retrieve(containers.name, containers.db.0.clone(), containers.ds.to_string()),
+   );
+
+   let mut report = ValidationReport::default();
+   for (name, column, other_db, schema) in [
+      (things.name, &**things.column, &containers.db.0, things_schema),
+      (containers.name, &**containers.column, &things.db.0, containers_schema),
+   ] {
+      let checked = schema.and_then(|schema| validate_relation(&schema, name, column, other_db));
+      if let Err(problem) = checked {
+         report.problems.push(problem);
+      }
+   }
+   report
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const THINGS_DB: &str = "a1b2c3d4e5f67890abcdef1234567890";
+   const CONTAINERS_DB: &str = "0a1b2c3d4e5f67890abcdef123456789";
+
+   fn schema(properties: &[(&str, PropertyKind)]) -> Schema {
+      Schema(
+         properties
+            .iter()
+            .map(|(name, kind)| (name.to_string(), kind.clone()))
+            .collect(),
+      )
+   }
+
+   fn relation_to(db: &str) -> PropertyKind {
+      PropertyKind::Relation {
+         database_id: Some(NotionPageId::new(db).unwrap().to_string()),
+      }
+   }
+
+   async fn validate(
+      things: Result<Schema, ValidationError>,
+      containers: Result<Schema, ValidationError>,
+   ) -> ValidationReport {
+      let things_db = ThingsDb(NotionPageId::new(THINGS_DB).unwrap());
+      let containers_db = ContainersDb(NotionPageId::new(CONTAINERS_DB).unwrap());
+      validate_databases(
+         |name, _, _| {
+            let canned = if name == "Things" {
+               things.clone()
+            } else {
+               containers.clone()
+            };
+            async move { canned }
+         },
+         DatabaseRef {
+            name: "Things",
+            db: &things_db,
+            ds: "ds-things",
+            column: &ThingsRelationColumn("Container".to_string()),
+         },
+         DatabaseRef {
+            name: "Containers",
+            db: &containers_db,
+            ds: "ds-containers",
+            column: &ContainersRelationColumn("Contents".to_string()),
+         },
+      )
+      .await
+   }
+
+   #[tokio::test]
+   async fn test_valid_databases() {
+      let report = validate(
+         Ok(schema(&[
+            ("Name", PropertyKind::Title),
+            ("Container", relation_to(CONTAINERS_DB)),
+         ])),
+         Ok(schema(&[("Contents", relation_to(THINGS_DB))])),
+      )
+      .await;
+      assert!(report.is_ok(), "{report}");
+   }
+
+   #[tokio::test]
+   async fn test_reports_problems_in_both_databases() {
+      let report = validate(
+         Ok(schema(&[("Container", PropertyKind::RichText)])),
+         Ok(schema(&[("Contents", relation_to(CONTAINERS_DB))])),
+      )
+      .await;
+      assert_eq!(report.problems.len(), 2);
+      assert!(matches!(
+         &report.problems[0],
+         ValidationError::WrongType { database, found, .. } if database == "Things" && found == "rich_text"
+      ));
+      assert!(matches!(
+         &report.problems[1],
+         ValidationError::WrongTarget { database, .. } if database == "Containers"
+      ));
+      assert!(report.problems.iter().all(ValidationError::is_schema));
+   }
+
+   #[tokio::test]
+   async fn test_reports_missing_properties_and_api_errors_together() {
+      let report = validate(
+         Ok(schema(&[("Name", PropertyKind::Title)])),
+         Err(ValidationError::AuthFailed {
+            database: "Containers".to_string(),
+            message: "401".to_string(),
+         }),
+      )
+      .await;
+      assert_eq!(
+         report.problems,
+         [
+            ValidationError::MissingProperty {
+               database: "Things".to_string(),
+               property: "Container".to_string(),
+            },
+            ValidationError::AuthFailed {
+               database: "Containers".to_string(),
+               message: "401".to_string(),
+            },
+         ]
+      );
+      assert!(report.problems[1].is_auth());
+      assert_eq!(
+         report.to_string(),
+         "2 Notion validation problem(s):\n \
+          - Things: missing required property 'Container'\n \
+          - Containers: Notion rejected the integration token (401)"
+      );
+   }
+
+   #[test]
+   fn test_relation_without_target() {
+      let schema = schema(&[("Container", PropertyKind::Relation { database_id: None })]);
+      let err = validate_relation(
+         &schema,
+         "Things",
+         "Container",
+         &NotionPageId::new(CONTAINERS_DB).unwrap(),
+      )
+      .unwrap_err();
+      assert_eq!(
+         err.to_string(),
+         "Things: 'Container' relation points to no Database, expected 0a1b2c3d-4e5f-6789-0abc-def123456789"
+      );
+   }
+
+   #[test]
+   fn test_handles_read_as_their_contents() {
+      let things = ThingsDb(NotionPageId::new(THINGS_DB).unwrap());
+      assert_eq!(things.to_string(), "a1b2c3d4-e5f6-7890-abcd-ef1234567890");
+      assert_eq!(things.as_raw(), THINGS_DB);
+      assert_eq!(*things, NotionPageId::new(THINGS_DB).unwrap());
+
+      let column = ContainersRelationColumn("Contents".to_string());
+      assert_eq!(column.to_string(), "Contents");
+      assert_eq!(&*column, "Contents");
+   }
+
+   #[test]
+   fn test_title_and_rich_text_properties() {
+      let schema = schema(&[
+         ("Name", PropertyKind::Title),
+         ("Tag", PropertyKind::RichText),
+         ("State", PropertyKind::Select),
+      ]);
+      assert_eq!(find_title_property(&schema, "Things").unwrap(), "Name");
+      assert!(validate_rich_text(&schema, "Things", "Tag").is_ok());
+      assert!(validate_select(&schema, "Things", "State").is_ok());
+      assert!(validate_select(&schema, "Things", "Tag").is_err());
+      assert!(matches!(
+         validate_rich_text(&schema, "Things", "Name"),
+         Err(ValidationError::WrongType {
+            expected: "rich_text",
+            ..
+         })
+      ));
+      assert!(matches!(
+         find_title_property(&Schema::default(), "Things"),
+         Err(ValidationError::NoTitleProperty { .. })
+      ));
+   }
+}
@@ -0,0 +1,166 @@
+//! Lists the data sources an integration can see, for picking databases in `setup` rather than
+//! pasting ids. Like `relations`, this goes over HTTP directly: the typed client's search predates
+//! data sources.
+
+use serde::Deserialize;
+use serde_json::json;
+
+use super::relations::{NOTION_API_BASE, NOTION_API_VERSION};
+use crate::models::NotionPageId;
+
+const SEARCH_PAGE_SIZE: usize = 100;
+
+/// A data source and the database it belongs to; twag's config needs both ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSourceListing {
+   pub database_id: NotionPageId,
+   pub data_source_id: String,
+   pub title: String,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SearchError {
+   #[error("Notion rejected the integration token")]
+   Unauthorized,
+   #[error("{0}")]
+   Other(String),
+}
+
+#[derive(Deserialize)]
+struct SearchResults {
+   results: Vec<SearchResult>,
+   next_cursor: Option<String>,
+   has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+   id: String,
+   #[serde(default)]
+   title: Vec<PlainText>,
+   parent: SearchParent,
+}
+
+#[derive(Deserialize)]
+struct PlainText {
+   plain_text: String,
+}
+
+#[derive(Deserialize)]
+struct SearchParent {
+   database_id: Option<String>,
+}
+
+/// One page of `POST /v1/search` results, filtered to data sources. Results without a parent
+/// database are skipped.
+pub fn parse_search_page(body: &str) -> Result<(Vec<DataSourceListing>, Option<String>), String> {
+   let page: SearchResults = serde_json::from_str(body).map_err(|err| format!("Malformed search results: {}", err))?;
+   let mut listings = Vec::new();
+   for result in page.results {
+      let Some(database_id) = result.parent.database_id else {
+         continue;
+      };
+      listings.push(DataSourceListing {
+         database_id: NotionPageId::new(database_id)
+            .map_err(|err| format!("Notion returned an unparseable database id: {}", err))?,
+         data_source_id: result.id,
+         title: result.title.into_iter().map(|text| text.plain_text).collect(),
+      });
+   }
+   Ok((listings, page.next_cursor.filter(|_| page.has_more)))
+}
+
+/// Every data source shared with the integration.
+pub async fn data_sources(http: &reqwest::Client, token: &str) -> Result<Vec<DataSourceListing>, SearchError> {
+   let mut listings = Vec::new();
+   let mut cursor: Option<String> = None;
+   loop {
+      let mut body = json!({
+         "filter": {"property": "object", "value": "data_source"},
+         "page_size": SEARCH_PAGE_SIZE,
+      });
+      if let Some(cursor) = &cursor {
+         body["start_cursor"] = json!(cursor);
+      }
+      let response = http
+         .post(format!("{}/search", NOTION_API_BASE))
+         .bearer_auth(token)
+         .header("Notion-Version", NOTION_API_VERSION)
+         .header(reqwest::header::CONTENT_TYPE, "application/json")
+         .body(body.to_string())
+         .send()
+         .await
+         .map_err(|err| SearchError::Other(format!("Failed to search Notion: {:?}", err)))?;
+      if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+         return Err(SearchError::Unauthorized);
+      }
+      let response = response
+         .error_for_status()
+         .map_err(|err| SearchError::Other(format!("Failed to search Notion: {:?}", err)))?;
+      let text = response
+         .text()
+         .await
+         .map_err(|err| SearchError::Other(format!("Failed to read Notion search results: {:?}", err)))?;
+
+      let (page, next) = parse_search_page(&text).map_err(SearchError::Other)?;
+      listings.extend(page);
+      match next {
+         Some(next) => cursor = Some(next),
+         None => return Ok(listings),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Recorded from `POST /v1/search` with an object filter of `data_source`, trimmed.
+   const FIXTURE: &str = r#"{
+      "object": "list",
+      "results": [
+         {"object": "data_source", "id": "248104cd-477e-80af-bc30-000bd28de8f9",
+          "title": [{"type": "text", "text": {"content": "Things"}, "plain_text": "Things"}],
+          "parent": {"type": "database_id", "database_id": "a1b2c3d4-e5f6-7890-abcd-ef1234567890"},
+          "properties": {}},
+         {"object": "data_source", "id": "248104cd-477e-80af-bc30-000bd28de8fa",
+          "title": [{"plain_text": "Con"}, {"plain_text": "tainers"}],
+          "parent": {"type": "database_id", "database_id": "0a1b2c3d4e5f67890abcdef123456789"},
+          "properties": {}},
+         {"object": "data_source", "id": "248104cd-477e-80af-bc30-000bd28de8fb",
+          "title": [],
+          "parent": {"type": "workspace", "workspace": true}}
+      ],
+      "next_cursor": "cursor-page-2",
+      "has_more": true,
+      "type": "page_or_data_source",
+      "request_id": "5c0ad3b1-0000-4000-8000-000000000004"
+   }"#;
+
+   #[test]
+   fn test_parse_search_page() {
+      let (listings, next) = parse_search_page(FIXTURE).unwrap();
+      assert_eq!(next.as_deref(), Some("cursor-page-2"));
+      assert_eq!(
+         listings,
+         [
+            DataSourceListing {
+               database_id: NotionPageId::new("a1b2c3d4e5f67890abcdef1234567890").unwrap(),
+               data_source_id: "248104cd-477e-80af-bc30-000bd28de8f9".to_string(),
+               title: "Things".to_string(),
+            },
+            DataSourceListing {
+               database_id: NotionPageId::new("0a1b2c3d4e5f67890abcdef123456789").unwrap(),
+               data_source_id: "248104cd-477e-80af-bc30-000bd28de8fa".to_string(),
+               title: "Containers".to_string(),
+            },
+         ]
+      );
+   }
+
+   #[test]
+   fn test_last_page_has_no_cursor() {
+      let body = r#"{"object": "list", "results": [], "next_cursor": null, "has_more": false}"#;
+      assert_eq!(parse_search_page(body).unwrap(), (Vec::new(), None));
+   }
+}
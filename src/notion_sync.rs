@@ -0,0 +1,487 @@
+//! Pulls target URLs edited in Notion back into twag. Whichever side was edited last wins: a
+//! linked page's URL property replaces the tag's target only if the page changed after the tag
+//! was last retargeted.
+//!
+//! Each pass asks only for pages edited since the cursor in `twag_sync_cursors`, a hundred at a
+//! time, and moves the cursor on after each hundred; a pass cut short picks up where it stopped.
+//! Passes are spread out with jitter, and hold an advisory lock, so replicas take turns.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use crate::failover::Failover;
+use crate::kit::validate_target_url;
+use crate::models::{NotionPageId, TagUid};
+use crate::notion::edits::{EditSource, EditedPage, EditedPages};
+use crate::pool::ScalingPool;
+use crate::replica::ReadPools;
+use crate::settings::SharedSettings;
+use crate::tag_lock::{self, TagLocks};
+use crate::target_url;
+use crate::targets;
+
+/// Recorded as the `actor` of the audit entries this writes.
+pub const ACTOR: &str = "notion-sync";
+/// This sync's row in `twag_sync_cursors`.
+const CURSOR: &str = "notion_target_url";
+const INTERVAL: Duration = Duration::from_secs(60);
+/// At most this is added to each `INTERVAL`.
+const JITTER: Duration = Duration::from_secs(20);
+/// Held by whichever instance is syncing, for the length of its pass.
+const LOCK_KEY: i64 = 0x7477_6167_5359_4e43;
+/// Marks sync statuses written by twag, so only those are ever cleared.
+const STATUS_PREFIX: &str = "twag: ";
+
+/// A tag linked to an edited page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linked {
+   pub id: TagUid,
+   pub target_url: String,
+   /// Unix seconds of the tag's creation or latest retarget, whichever is later.
+   pub edited_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+   /// The page's URL is empty, or already the target.
+   Unchanged,
+   /// The tag was retargeted after the page was last edited.
+   TwagNewer,
+   Invalid(String),
+   Retarget(String),
+}
+
+pub fn decide(page: &EditedPage, tag: &Linked, strict_idn: bool) -> Decision {
+   let Some(url) = &page.url else {
+      return Decision::Unchanged;
+   };
+   // Stored URLs are normalized, and Notion shows hosts in Unicode, so a page still holding the
+   // URL as typed would otherwise look edited on every pass
+   if target_url::normalize(url) == tag.target_url.as_str() {
+      return Decision::Unchanged;
+   }
+   // Notion's edit times are rounded down to the minute, so an edit in the same minute as the
+   // retarget is assumed to be the later one
+   if page.last_edited < tag.edited_at - tag.edited_at.rem_euclid(60) {
+      return Decision::TwagNewer;
+   }
+   match validate_target_url(url, strict_idn) {
+      Ok(url) => Decision::Retarget(url),
+      Err(e) => Decision::Invalid(e),
+   }
+}
+
+/// A retarget to apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retarget {
+   pub id: TagUid,
+   pub before: String,
+   pub after: String,
+   pub last_edited: i64,
+}
+
+/// Decides each linked page, and reports invalid URLs on the page itself when it has a sync
+/// status property. Statuses are only written when they'd change, since writing one is itself an
+/// edit the next sync will see.
+pub(crate) async fn review(
+   source: &impl EditSource,
+   pages: &[EditedPage],
+   linked: &HashMap<NotionPageId, Linked>,
+   strict_idn: bool,
+) -> Vec<Retarget> {
+   let mut retargets = Vec::new();
+   for page in pages {
+      let Some(tag) = linked.get(&page.page_id) else {
+         continue;
+      };
+      let decision = decide(page, tag, strict_idn);
+      let status = match &decision {
+         Decision::Invalid(e) => {
+            let url = page.url.as_deref().unwrap_or_default();
+            Some(format!("{}didn't sync '{}': {}", STATUS_PREFIX, url, e))
+         }
+         Decision::TwagNewer => {
+            debug!(tag_id = %tag.id, page_id = %page.page_id, "Tag retargeted since the page was edited, keeping it");
+            None
+         }
+         Decision::Retarget(url) => {
+            retargets.push(Retarget {
+               id: tag.id,
+               before: tag.target_url.clone(),
+               after: url.clone(),
+               last_edited: page.last_edited,
+            });
+            Some(String::new())
+         }
+         Decision::Unchanged => Some(String::new()),
+      };
+      let Some(status) = status else {
+         continue;
+      };
+      match &page.status {
+         Some(current) if *current == status => {}
+         // Someone else's note; leave it be
+         Some(current) if status.is_empty() && !current.starts_with(STATUS_PREFIX) => {}
+         Some(_) => {
+            if let Err(e) = source.set_sync_status(&page.page_id, &status).await {
+               warn!(tag_id = %tag.id, "Failed to write the sync status: {}", e);
+            }
+         }
+         None if !status.is_empty() => warn!(tag_id = %tag.id, page_id = %page.page_id, "{}", status),
+         None => {}
+      }
+   }
+   retargets
+}
+
+/// Where the next sync picks up: the latest edit seen, or else the earliest one that couldn't be
+/// applied, so that it's tried again.
+pub fn next_cursor(previous: i64, pages: &[EditedPage], failed: &[i64]) -> i64 {
+   match failed.iter().min() {
+      Some(earliest) => *earliest,
+      None => pages.iter().map(|page| page.last_edited).fold(previous, i64::max),
+   }
+}
+
+async fn load_cursor(pool: &ScalingPool) -> Result<Option<i64>, sqlx::Error> {
+   sqlx::query_scalar!(
+      r#"SELECT extract(epoch FROM "cursor")::bigint AS "cursor!" FROM twag_sync_cursors WHERE name = $1"#,
+      CURSOR
+   )
+   .fetch_optional(&pool.get())
+   .await
+}
+
+async fn save_cursor(pool: &ScalingPool, cursor: i64) -> Result<(), sqlx::Error> {
+   sqlx::query!(
+      r#"INSERT INTO twag_sync_cursors (name, "cursor") VALUES ($1, to_timestamp($2))
+         ON CONFLICT (name) DO UPDATE SET "cursor" = excluded."cursor""#,
+      CURSOR,
+      cursor as f64,
+   )
+   .execute(&pool.get())
+   .await?;
+   Ok(())
+}
+
+async fn load_linked(pool: &ScalingPool, pages: &[EditedPage]) -> Result<HashMap<NotionPageId, Linked>, sqlx::Error> {
+   let page_ids: Vec<String> = pages.iter().map(|page| page.page_id.to_string()).collect();
+   let rows = sqlx::query!(
+      r#"SELECT t.id AS "id!: TagUid", t.target_url, t.notion_page_id AS "notion_page_id!: NotionPageId",
+            coalesce(extract(epoch FROM greatest(t.created_at, (
+               SELECT max(a.at) FROM twag_tag_audit a WHERE a.tag_id = t.id AND a.action = 'retarget'
+            )))::bigint, 0) AS "edited_at!"
+         FROM twag_tags t
+         WHERE t.deleted_at IS NULL AND t.notion_page_id::text = ANY($1)"#,
+      &page_ids,
+   )
+   .fetch_all(&pool.get())
+   .await?;
+   Ok(rows
+      .into_iter()
+      .map(|row| {
+         let linked = Linked {
+            id: row.id,
+            target_url: row.target_url,
+            edited_at: row.edited_at,
+         };
+         (row.notion_page_id, linked)
+      })
+      .collect())
+}
+
+/// `INTERVAL` plus up to `JITTER`, from `seed`; so that replicas started together drift apart.
+pub fn jittered(seed: u64) -> Duration { INTERVAL + JITTER.mul_f64((seed % 1000) as f64 / 1000.0) }
+
+/// Only changes the target if it's still the one the decision was made against.
+async fn apply(pool: &ScalingPool, retarget: &Retarget) -> Result<bool, sqlx::Error> {
+   let mut tx = pool.get().begin().await?;
+   let target_id = targets::find_or_insert(&mut tx, &retarget.after).await?;
+   let updated = sqlx::query!(
+      "UPDATE twag_tags SET target_url = $3, target_id = $4 WHERE id = $1 AND target_url = $2",
+      retarget.id as TagUid,
+      retarget.before,
+      retarget.after,
+      target_id,
+   )
+   .execute(&mut *tx)
+   .await?
+   .rows_affected();
+   if updated == 0 {
+      return Ok(false);
+   }
+   sqlx::query!(
+      r#"INSERT INTO twag_tag_audit (tag_id, action, before, after, actor) VALUES ($1, 'retarget', $2, $3, $4)"#,
+      retarget.id as TagUid,
+      retarget.before,
+      retarget.after,
+      ACTOR,
+   )
+   .execute(&mut *tx)
+   .await?;
+   tx.commit().await?;
+   Ok(true)
+}
+
+pub(crate) struct UrlSync<S> {
+   pub pool: ScalingPool,
+   pub source: S,
+   pub failover: Failover,
+   pub reads: ReadPools,
+   pub tag_locks: Arc<TagLocks>,
+   pub settings: SharedSettings,
+}
+
+impl<S: EditSource + Send + Sync> UrlSync<S> {
+   /// One pass; returns how many tags were retargeted, or `None` when another instance is already
+   /// syncing. The very first pass only records where to start, so edits made before the sync was
+   /// configured are left alone.
+   pub async fn run_once(&self) -> Result<Option<usize>, String> {
+      // Released with the transaction, or the connection, however the pass ends
+      let mut lock = self
+         .pool
+         .get()
+         .begin()
+         .await
+         .map_err(|e| format!("Failed to begin the sync lock: {:?}", e))?;
+      let locked = sqlx::query_scalar!(r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#, LOCK_KEY)
+         .fetch_one(&mut *lock)
+         .await
+         .map_err(|e| format!("Failed to take the sync lock: {:?}", e))?;
+      if !locked {
+         debug!("Another instance is syncing from Notion");
+         return Ok(None);
+      }
+
+      let cursor = load_cursor(&self.pool)
+         .await
+         .map_err(|e| format!("Failed to load the sync cursor: {:?}", e))?;
+      let Some(mut cursor) = cursor else {
+         let now = chrono::Utc::now().timestamp();
+         save_cursor(&self.pool, now)
+            .await
+            .map_err(|e| format!("Failed to save the sync cursor: {:?}", e))?;
+         info!("Syncing target URLs from Notion edits made from now on");
+         return Ok(Some(0));
+      };
+
+      let mut edited = EditedPages::new(&self.source, cursor);
+      let mut applied = 0;
+      while let Some(pages) = edited.next().await? {
+         let failed = self.sync_pages(&pages, &mut applied).await?;
+         cursor = next_cursor(cursor, &pages, &failed);
+         save_cursor(&self.pool, cursor)
+            .await
+            .map_err(|e| format!("Failed to save the sync cursor: {:?}", e))?;
+         // The cursor can't move past a failure; the next pass starts again from it
+         if !failed.is_empty() {
+            break;
+         }
+      }
+      Ok(Some(applied))
+   }
+
+   /// Applies what one page of results calls for, counting retargets into `applied`; returns the
+   /// edit times of those that failed.
+   async fn sync_pages(&self, pages: &[EditedPage], applied: &mut usize) -> Result<Vec<i64>, String> {
+      let linked = load_linked(&self.pool, pages)
+         .await
+         .map_err(|e| format!("Failed to load linked tags: {:?}", e))?;
+      let retargets = review(&self.source, pages, &linked, self.settings.load().strict_idn).await;
+
+      let mut failed = Vec::new();
+      for retarget in &retargets {
+         let Ok(_lock) = self.tag_locks.acquire(&[retarget.id], tag_lock::TIMEOUT).await else {
+            failed.push(retarget.last_edited);
+            continue;
+         };
+         match apply(&self.pool, retarget).await {
+            Ok(true) => {
+               self.reads.wrote(&retarget.id);
+               self.failover.forget(&retarget.id);
+               info!(tag_id = %retarget.id, target_url = %retarget.after, "Retargeted from Notion");
+               *applied += 1;
+            }
+            Ok(false) => debug!(tag_id = %retarget.id, "Target changed during the sync, skipping"),
+            Err(e) => {
+               warn!(tag_id = %retarget.id, "Failed to apply a target from Notion: {:?}", e);
+               failed.push(retarget.last_edited);
+            }
+         }
+      }
+      Ok(failed)
+   }
+}
+
+pub(crate) fn spawn<S: EditSource + Send + Sync + 'static>(sync: UrlSync<S>) {
+   tokio::spawn(async move {
+      let random = RandomState::new();
+      let mut pass = 0u64;
+      loop {
+         tokio::time::sleep(jittered(random.hash_one(pass))).await;
+         pass += 1;
+         if let Err(e) = sync.run_once().await {
+            warn!("Notion URL sync failed: {}", e);
+         }
+      }
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::notion::edits::collect_edited;
+   use crate::notion::edits::tests::FixtureEdits;
+
+   fn page(id: &str) -> NotionPageId { NotionPageId::new(id).unwrap() }
+
+   fn linked(id: &str, target_url: &str, edited_at: i64) -> Linked {
+      Linked {
+         id: id.parse().unwrap(),
+         target_url: target_url.to_string(),
+         edited_at,
+      }
+   }
+
+   /// The tags linked to the fixture pages, each last retargeted well before the edits.
+   fn fixture_tags() -> HashMap<NotionPageId, Linked> {
+      HashMap::from([
+         (
+            page("11111111-1111-4111-8111-111111111111"),
+            linked("055B88A23C1250", "https://example.com/old", 1_792_000_000),
+         ),
+         (
+            page("22222222-2222-4222-8222-222222222222"),
+            linked("04A1B2C3D4E5F6", "https://example.com/kept", 1_792_000_000),
+         ),
+         (
+            page("33333333-3333-4111-8111-333333333333"),
+            linked("04000000000001", "https://example.com/empty", 1_792_000_000),
+         ),
+      ])
+   }
+
+   #[tokio::test]
+   async fn test_review_retargets_valid_edits_and_reports_invalid_ones() {
+      let source = FixtureEdits::default();
+      let pages = collect_edited(&source, 0).await.unwrap();
+      let retargets = review(&source, &pages, &fixture_tags(), false).await;
+
+      assert_eq!(
+         retargets,
+         [Retarget {
+            id: "055B88A23C1250".parse().unwrap(),
+            before: "https://example.com/old".to_string(),
+            after: "https://example.com/new".to_string(),
+            last_edited: 1_792_152_000,
+         }]
+      );
+
+      let statuses = source.statuses.lock().unwrap();
+      // The invalid URL is reported on its page, and the stale warning on the emptied page cleared
+      assert_eq!(statuses.len(), 2);
+      assert_eq!(statuses[0].0, page("22222222-2222-4222-8222-222222222222"));
+      assert!(statuses[0].1.starts_with("twag: didn't sync 'javascript:alert(1)': "));
+      assert_eq!(
+         statuses[1],
+         (page("33333333-3333-4111-8111-333333333333"), String::new())
+      );
+   }
+
+   #[tokio::test]
+   async fn test_review_writes_nothing_already_written() {
+      let source = FixtureEdits::default();
+      let mut pages = collect_edited(&source, 0).await.unwrap();
+      let invalid = &pages[1];
+      let tags = fixture_tags();
+      let Decision::Invalid(e) = decide(invalid, &tags[&invalid.page_id], false) else {
+         panic!("expected the fixture URL to be invalid");
+      };
+      pages[1].status = Some(format!("twag: didn't sync 'javascript:alert(1)': {}", e));
+      pages[2].status = Some("Checked by hand".to_string());
+
+      review(&source, &pages, &tags, false).await;
+      assert!(source.statuses.lock().unwrap().is_empty());
+   }
+
+   #[tokio::test]
+   async fn test_unlinked_pages_are_ignored() {
+      let source = FixtureEdits::default();
+      let pages = collect_edited(&source, 0).await.unwrap();
+      assert!(review(&source, &pages, &HashMap::new(), false).await.is_empty());
+      assert!(source.statuses.lock().unwrap().is_empty());
+   }
+
+   #[test]
+   fn test_last_write_wins() {
+      let tag = linked("055B88A23C1250", "https://example.com/old", 1_792_152_030);
+      let edited_at = |last_edited| EditedPage {
+         page_id: page("11111111-1111-4111-8111-111111111111"),
+         last_edited,
+         url: Some("https://example.com/new".to_string()),
+         status: None,
+      };
+      assert_eq!(decide(&edited_at(1_792_151_940), &tag, false), Decision::TwagNewer);
+      // Same minute as the retarget
+      assert_eq!(
+         decide(&edited_at(1_792_152_000), &tag, false),
+         Decision::Retarget("https://example.com/new".to_string())
+      );
+      assert_eq!(
+         decide(&edited_at(1_792_152_060), &tag, false),
+         Decision::Retarget("https://example.com/new".to_string())
+      );
+
+      let unchanged = EditedPage {
+         url: Some("https://example.com/old".to_string()),
+         ..edited_at(1_792_152_060)
+      };
+      assert_eq!(decide(&unchanged, &tag, false), Decision::Unchanged);
+   }
+
+   #[test]
+   fn test_unicode_spelling_of_the_stored_url_is_unchanged() {
+      let tag = linked("055B88A23C1250", "https://xn--mnchen-3ya.example/", 1_792_000_000);
+      let edited = EditedPage {
+         page_id: page("11111111-1111-4111-8111-111111111111"),
+         last_edited: 1_792_152_000,
+         url: Some("https://münchen.example/".to_string()),
+         status: None,
+      };
+      assert_eq!(decide(&edited, &tag, false), Decision::Unchanged);
+   }
+
+   #[test]
+   fn test_next_cursor() {
+      let pages = [
+         EditedPage {
+            page_id: page("11111111-1111-4111-8111-111111111111"),
+            last_edited: 200,
+            url: None,
+            status: None,
+         },
+         EditedPage {
+            page_id: page("22222222-2222-4222-8222-222222222222"),
+            last_edited: 300,
+            url: None,
+            status: None,
+         },
+      ];
+      assert_eq!(next_cursor(100, &pages, &[]), 300);
+      assert_eq!(next_cursor(100, &[], &[]), 100);
+      assert_eq!(next_cursor(100, &pages, &[300, 200]), 200);
+   }
+
+   #[test]
+   fn test_passes_are_jittered() {
+      assert_eq!(jittered(0), INTERVAL);
+      assert_eq!(jittered(1500), INTERVAL + JITTER / 2);
+      assert!((0..2000)
+         .map(jittered)
+         .all(|wait| INTERVAL <= wait && wait < INTERVAL + JITTER));
+   }
+}
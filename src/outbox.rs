@@ -0,0 +1,387 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::checkout::CheckoutState;
+use crate::models::{NotionPageId, TagUid};
+use crate::notion::TagPages;
+use crate::pool::ScalingPool;
+
+pub const KIND_CREATE_NOTION_PAGE: &str = "create_notion_page";
+pub const KIND_MIRROR_STATE: &str = "mirror_state";
+pub const MAX_ATTEMPTS: i32 = 8;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BASE_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+const BACKFILL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const BACKFILL_BATCH: i64 = 20;
+/// A backfill claim older than this is presumed to belong to a crashed worker and may be retaken.
+const CLAIM_TTL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateNotionPage {
+   pub tag_id: TagUid,
+}
+
+/// Copies a stateful tag's checkout state to its Notion page. The state itself is read at dispatch
+/// time, so a retried entry can't overwrite a newer state with an older one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MirrorState {
+   pub tag_id: TagUid,
+}
+
+pub fn backoff(attempts: i32) -> Duration {
+   let exponent = attempts.clamp(0, 16) as u32;
+   BASE_BACKOFF
+      .saturating_mul(2u32.saturating_pow(exponent))
+      .min(MAX_BACKOFF)
+}
+
+/// Everything creating a tag sets off, enqueued in the transaction that inserted it, and only once
+/// the insert did: a tag that already existed sets off nothing.
+pub(crate) async fn enqueue_created(
+   conn: &mut sqlx::PgConnection,
+   notion_outbox: bool,
+   id: &TagUid,
+   notion_page_id: Option<&NotionPageId>,
+) -> Result<(), sqlx::Error> {
+   if notion_outbox && notion_page_id.is_none() {
+      let payload = serde_json::to_string(&CreateNotionPage { tag_id: *id }).unwrap();
+      sqlx::query!(
+         "INSERT INTO twag_outbox (kind, payload) VALUES ($1, $2::text::jsonb)",
+         KIND_CREATE_NOTION_PAGE,
+         payload,
+      )
+      .execute(conn)
+      .await?;
+   }
+   Ok(())
+}
+
+/// Finds-or-creates the tag's Notion page. Searching first is what makes a retry after a crash
+/// (page created, outbox entry not yet marked done) idempotent.
+pub(crate) async fn ensure_page(pages: &impl TagPages, tag_id: &TagUid) -> Result<NotionPageId, String> {
+   if let Some(existing) = pages.find_page_for_tag(tag_id).await? {
+      debug!(%tag_id, page_id = %existing, "Found existing Notion page");
+      return Ok(existing);
+   }
+   pages.create_page_for_tag(tag_id).await
+}
+
+/// Claims and processes one due outbox entry. Returns `false` once nothing is due.
+async fn dispatch_one(pool: &ScalingPool, pages: &impl TagPages) -> Result<bool, sqlx::Error> {
+   let mut tx = pool.get().begin().await?;
+
+   let Some(entry) = sqlx::query!(
+      r#"SELECT id, kind, payload::text AS "payload!", attempts FROM twag_outbox
+         WHERE done_at IS NULL AND dead_at IS NULL AND next_attempt_at <= current_timestamp
+         ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED"#
+   )
+   .fetch_optional(&mut *tx)
+   .await?
+   else {
+      return Ok(false);
+   };
+
+   // `Some` when a page was found or created, to be linked to its tag
+   let result = match entry.kind.as_str() {
+      KIND_CREATE_NOTION_PAGE => match serde_json::from_str::<CreateNotionPage>(&entry.payload) {
+         Ok(payload) => ensure_page(pages, &payload.tag_id)
+            .await
+            .map(|page_id| Some((payload.tag_id, page_id))),
+         Err(e) => Err(format!("Malformed payload: {}", e)),
+      },
+      KIND_MIRROR_STATE => match serde_json::from_str::<MirrorState>(&entry.payload) {
+         Ok(payload) => {
+            let tag = sqlx::query!(
+               r#"SELECT notion_page_id AS "notion_page_id: NotionPageId", current_state FROM twag_tags
+                  WHERE id = $1"#,
+               payload.tag_id as TagUid,
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            let state = tag
+               .as_ref()
+               .and_then(|tag| tag.current_state.as_deref())
+               .and_then(|s| s.parse::<CheckoutState>().ok());
+            match (tag.and_then(|tag| tag.notion_page_id), state) {
+               (Some(page_id), Some(state)) => pages.set_checkout_state(&page_id, state).await.map(|()| None),
+               // Unlinked or deleted since; nothing to mirror
+               _ => Ok(None),
+            }
+         }
+         Err(e) => Err(format!("Malformed payload: {}", e)),
+      },
+      kind => Err(format!("Unknown outbox kind '{}'", kind)),
+   };
+
+   match result {
+      Ok(linked) => {
+         if let Some((tag_id, page_id)) = &linked {
+            link_page(&mut tx, tag_id, page_id).await?;
+         }
+         sqlx::query!(
+            "UPDATE twag_outbox SET done_at = current_timestamp, attempts = attempts + 1 WHERE id = $1",
+            entry.id
+         )
+         .execute(&mut *tx)
+         .await?;
+         info!(outbox_id = entry.id, kind = %entry.kind, "Outbox entry done");
+      }
+      Err(error) => {
+         let attempts = entry.attempts + 1;
+         let dead = attempts >= MAX_ATTEMPTS;
+         sqlx::query!(
+            r#"UPDATE twag_outbox SET attempts = $2, last_error = $3,
+                  next_attempt_at = current_timestamp + make_interval(secs => $4),
+                  dead_at = CASE WHEN $5 THEN current_timestamp END
+               WHERE id = $1"#,
+            entry.id,
+            attempts,
+            error,
+            backoff(attempts).as_secs_f64(),
+            dead,
+         )
+         .execute(&mut *tx)
+         .await?;
+         warn!(outbox_id = entry.id, attempts, dead, "Outbox entry failed: {}", error);
+      }
+   }
+
+   tx.commit().await?;
+   Ok(true)
+}
+
+/// Links a tag to its page unless it's already linked, or the page already belongs to another tag.
+async fn link_page(conn: &mut sqlx::PgConnection, tag_id: &TagUid, page_id: &NotionPageId) -> Result<(), sqlx::Error> {
+   let linked = sqlx::query!(
+      r#"UPDATE twag_tags SET notion_page_id = $2::notion_page_id, notion_sync_claimed_at = NULL
+         WHERE id = $1 AND notion_page_id IS NULL
+            AND NOT EXISTS (SELECT 1 FROM twag_tags WHERE notion_page_id = $2::notion_page_id)"#,
+      tag_id as &TagUid,
+      page_id as &NotionPageId,
+   )
+   .execute(conn)
+   .await?
+   .rows_affected();
+   match linked {
+      0 => warn!(%tag_id, %page_id, "Tag already linked, or page linked to another tag; not linking"),
+      _ => debug!(%tag_id, %page_id, "Linked tag to Notion page"),
+   }
+   Ok(())
+}
+
+/// Claims up to a batch of unlinked tags for this worker. Tags with a pending outbox entry are left
+/// to the dispatcher; ones whose entry died are picked up here.
+async fn claim_unlinked(pool: &ScalingPool) -> Result<Vec<TagUid>, sqlx::Error> {
+   sqlx::query_scalar!(
+      r#"UPDATE twag_tags SET notion_sync_claimed_at = current_timestamp
+         WHERE id IN (
+            SELECT id FROM twag_tags
+            WHERE notion_page_id IS NULL
+               AND (notion_sync_claimed_at IS NULL
+                  OR notion_sync_claimed_at < current_timestamp - make_interval(secs => $1))
+               AND NOT EXISTS (
+                  SELECT 1 FROM twag_outbox
+                  WHERE kind = $2 AND done_at IS NULL AND dead_at IS NULL
+                     AND payload->>'tag_id' = twag_tags.id
+               )
+            ORDER BY id LIMIT $3 FOR UPDATE SKIP LOCKED
+         )
+         RETURNING id AS "id: TagUid""#,
+      CLAIM_TTL.as_secs_f64(),
+      KIND_CREATE_NOTION_PAGE,
+      BACKFILL_BATCH,
+   )
+   .fetch_all(&pool.get())
+   .await
+}
+
+/// Finds-or-creates pages for one batch of claimed tags. Returns how many were claimed, so the
+/// caller can stop once nothing is left. A tag that fails keeps its claim, and is retried once the
+/// claim expires.
+pub(crate) async fn backfill_batch(pool: &ScalingPool, pages: &impl TagPages) -> Result<usize, sqlx::Error> {
+   let claimed = claim_unlinked(pool).await?;
+   for tag_id in &claimed {
+      match ensure_page(pages, tag_id).await {
+         Ok(page_id) => {
+            let mut conn = pool.get().acquire().await?;
+            link_page(&mut conn, tag_id, &page_id).await?;
+         }
+         Err(error) => warn!(%tag_id, "Notion backfill failed: {}", error),
+      }
+   }
+   Ok(claimed.len())
+}
+
+pub(crate) fn spawn_backfill(pool: ScalingPool, pages: impl TagPages + Send + Sync + 'static) {
+   tokio::spawn(async move {
+      let mut interval = tokio::time::interval(BACKFILL_INTERVAL);
+      loop {
+         interval.tick().await;
+         loop {
+            match backfill_batch(&pool, &pages).await {
+               Ok(0) => break,
+               Ok(claimed) => info!(claimed, "Backfilled a batch of Notion pages"),
+               Err(e) => {
+                  warn!("Notion backfill failed: {:?}", e);
+                  break;
+               }
+            }
+         }
+      }
+   });
+}
+
+pub(crate) fn spawn_dispatcher(pool: ScalingPool, pages: impl TagPages + Send + Sync + 'static) {
+   tokio::spawn(async move {
+      let mut interval = tokio::time::interval(POLL_INTERVAL);
+      loop {
+         interval.tick().await;
+         loop {
+            match dispatch_one(&pool, &pages).await {
+               Ok(true) => continue,
+               Ok(false) => break,
+               Err(e) => {
+                  warn!("Outbox dispatch failed: {:?}", e);
+                  break;
+               }
+            }
+         }
+      }
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use std::sync::Mutex;
+
+   #[derive(Default)]
+   struct FakePages {
+      pages: Mutex<Vec<(TagUid, NotionPageId)>>,
+      failures_remaining: Mutex<u32>,
+      creates: Mutex<u32>,
+   }
+
+   impl TagPages for FakePages {
+      async fn find_page_for_tag(&self, id: &TagUid) -> Result<Option<NotionPageId>, String> {
+         let pages = self.pages.lock().unwrap();
+         Ok(pages.iter().find(|(tag, _)| tag == id).map(|(_, page)| page.clone()))
+      }
+
+      async fn set_checkout_state(&self, _: &NotionPageId, _: CheckoutState) -> Result<(), String> { Ok(()) }
+
+      async fn create_page_for_tag(&self, id: &TagUid) -> Result<NotionPageId, String> {
+         let mut failures = self.failures_remaining.lock().unwrap();
+         if *failures > 0 {
+            *failures -= 1;
+            return Err("Notion is down".to_string());
+         }
+         let mut creates = self.creates.lock().unwrap();
+         *creates += 1;
+         let page = NotionPageId::new(format!("{:032x}", *creates)).unwrap();
+         self.pages.lock().unwrap().push((*id, page.clone()));
+         Ok(page)
+      }
+   }
+
+   #[test]
+   fn test_backoff_is_exponential_and_capped() {
+      assert_eq!(backoff(0), Duration::from_secs(10));
+      assert_eq!(backoff(1), Duration::from_secs(20));
+      assert_eq!(backoff(3), Duration::from_secs(80));
+      assert_eq!(backoff(12), MAX_BACKOFF);
+      assert_eq!(backoff(i32::MAX), MAX_BACKOFF);
+   }
+
+   #[tokio::test]
+   async fn test_ensure_page_retries_through_failures_and_creates_once() {
+      let tag: TagUid = "055B88A23C1250".parse().unwrap();
+      let pages = FakePages {
+         failures_remaining: Mutex::new(2),
+         ..Default::default()
+      };
+
+      assert!(ensure_page(&pages, &tag).await.is_err());
+      assert!(ensure_page(&pages, &tag).await.is_err());
+      let page = ensure_page(&pages, &tag).await.unwrap();
+
+      assert_eq!(*pages.creates.lock().unwrap(), 1);
+      assert_eq!(pages.find_page_for_tag(&tag).await.unwrap(), Some(page));
+   }
+
+   #[tokio::test]
+   async fn test_ensure_page_after_restart_reuses_existing_page() {
+      let tag: TagUid = "055B88A23C1250".parse().unwrap();
+      let pages = FakePages::default();
+
+      // The first run creates the page but "crashes" before the outbox entry is marked done, so
+      // the entry is dispatched again after restart.
+      let first = ensure_page(&pages, &tag).await.unwrap();
+      let second = ensure_page(&pages, &tag).await.unwrap();
+
+      assert_eq!(first, second);
+      assert_eq!(*pages.creates.lock().unwrap(), 1);
+   }
+
+   #[tokio::test]
+   async fn test_ensure_page_adopts_page_created_elsewhere() {
+      let tag: TagUid = "055B88A23C1250".parse().unwrap();
+      let existing = NotionPageId::new("a1b2c3d4e5f67890abcdef1234567890").unwrap();
+      // Another replica's backfill created the page, but hasn't linked it yet
+      let pages = FakePages {
+         pages: Mutex::new(vec![(tag, existing.clone())]),
+         ..Default::default()
+      };
+
+      assert_eq!(ensure_page(&pages, &tag).await.unwrap(), existing);
+      assert_eq!(*pages.creates.lock().unwrap(), 0);
+   }
+
+   #[cfg(feature = "chaos")]
+   #[tokio::test]
+   async fn test_injected_notion_failures_are_retried() {
+      use crate::chaos::{self, Faults, Flaky};
+
+      let tag: TagUid = "055B88A23C1250".parse().unwrap();
+      let pages = Flaky(FakePages::default());
+      chaos::inject(Faults {
+         notion_fail_percent: 100,
+         ..Faults::default()
+      });
+      assert_eq!(
+         ensure_page(&pages, &tag).await,
+         Err("Injected Notion failure".to_string())
+      );
+      assert_eq!(*pages.0.creates.lock().unwrap(), 0);
+
+      chaos::inject(Faults::default());
+      let page = ensure_page(&pages, &tag).await.unwrap();
+      assert_eq!(ensure_page(&pages, &tag).await.unwrap(), page);
+      assert_eq!(*pages.0.creates.lock().unwrap(), 1);
+   }
+
+   #[test]
+   fn test_mirror_state_payload_carries_no_state() {
+      let payload = MirrorState {
+         tag_id: "055B88A23C1250".parse().unwrap(),
+      };
+      assert_eq!(
+         serde_json::to_string(&payload).unwrap(),
+         r#"{"tag_id":"055B88A23C1250"}"#
+      );
+   }
+
+   #[test]
+   fn test_payload_round_trip() {
+      let payload = CreateNotionPage {
+         tag_id: "055B88A23C1250".parse().unwrap(),
+      };
+      let json = serde_json::to_string(&payload).unwrap();
+      assert_eq!(json, r#"{"tag_id":"055B88A23C1250"}"#);
+      let parsed: CreateNotionPage = serde_json::from_str(&json).unwrap();
+      assert_eq!(parsed.tag_id, payload.tag_id);
+   }
+}
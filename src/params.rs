@@ -0,0 +1,269 @@
+//! Query and form extractors that catch misspelled parameter names. A typo like `target-url`
+//! would otherwise be dropped without a word, and serde's `deny_unknown_fields` can't help: it
+//! rejects every extra parameter, including harmless ones like UTM tags. These only flag names
+//! close to an expected one; anything else is logged and ignored.
+
+use std::collections::BTreeSet;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Form, FromRequest, FromRequestParts, Query, Request};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+/// Larger than any create form, and well under axum's default body limit.
+const MAX_FORM_BYTES: usize = 64 * 1024;
+
+/// The parameter names a query or form struct reads.
+pub trait KnownParams {
+   const NAMES: &'static [&'static str];
+}
+
+/// Lowercased, with `-` and spaces read as `_`, so `Target-URL` is plainly `target_url`.
+fn normalize(name: &str) -> String {
+   name
+      .trim()
+      .chars()
+      .map(|c| match c {
+         '-' | ' ' => '_',
+         c => c.to_ascii_lowercase(),
+      })
+      .collect()
+}
+
+/// Optimal string alignment distance: Levenshtein, plus transposing two adjacent characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+   let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+   let width = b.len() + 1;
+   let at = |i: usize, j: usize| i * width + j;
+   let mut d: Vec<usize> = (0..(a.len() + 1) * width)
+      .map(|n| if n < width { n } else { 0 })
+      .collect();
+   for i in 1..=a.len() {
+      d[at(i, 0)] = i;
+      for j in 1..=b.len() {
+         let cost = usize::from(a[i - 1] != b[j - 1]);
+         let mut best = (d[at(i - 1, j)] + 1)
+            .min(d[at(i, j - 1)] + 1)
+            .min(d[at(i - 1, j - 1)] + cost);
+         if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+            best = best.min(d[at(i - 2, j - 2)] + 1);
+         }
+         d[at(i, j)] = best;
+      }
+   }
+   d[at(a.len(), b.len())]
+}
+
+/// The known name `given` is most likely a typo of, if any. Short names tolerate a single edit,
+/// so that unrelated short parameters aren't mistaken for `id`.
+pub fn suggest(given: &str, known: &[&'static str]) -> Option<&'static str> {
+   let given = normalize(given);
+   known
+      .iter()
+      .map(|&name| (edit_distance(&given, name), name))
+      .filter(|&(distance, name)| distance <= (name.len() / 4).clamp(1, 2))
+      .min_by_key(|&(distance, _)| distance)
+      .map(|(_, name)| name)
+}
+
+/// Parameter names that weren't expected.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Unexpected {
+   /// Near misses, each with the name it was probably meant to be.
+   pub misspelled: Vec<(String, &'static str)>,
+   /// Names that aren't close to anything expected.
+   pub unknown: Vec<String>,
+}
+
+impl Unexpected {
+   pub fn check<'a>(names: impl IntoIterator<Item = &'a str>, known: &[&'static str]) -> Self {
+      let names: BTreeSet<&str> = names.into_iter().filter(|name| !known.contains(name)).collect();
+      let mut unexpected = Unexpected::default();
+      for name in names {
+         match suggest(name, known) {
+            Some(meant) => unexpected.misspelled.push((name.to_string(), meant)),
+            None => unexpected.unknown.push(name.to_string()),
+         }
+      }
+      unexpected
+   }
+
+   /// Parameter names from a query string or urlencoded body.
+   pub fn check_urlencoded(raw: &[u8], known: &[&'static str]) -> Self {
+      let names: Vec<String> = url::form_urlencoded::parse(raw)
+         .map(|(name, _)| name.into_owned())
+         .collect();
+      Self::check(names.iter().map(String::as_str), known)
+   }
+
+   pub fn merge(mut self, other: Unexpected) -> Self {
+      self.misspelled.extend(other.misspelled);
+      self.unknown.extend(other.unknown);
+      self
+   }
+
+   /// The validation error for any misspellings.
+   pub fn error(&self) -> Option<String> {
+      let hints: Vec<String> = self
+         .misspelled
+         .iter()
+         .map(|(given, meant)| format!("Unknown parameter '{}'; did you mean {}?", given, meant))
+         .collect();
+      (!hints.is_empty()).then(|| hints.join(" "))
+   }
+
+   /// Genuinely unknown names are ignored, but noted in case a caller wonders where they went.
+   pub fn log_unknown(&self) {
+      if !self.unknown.is_empty() {
+         debug!(params = ?self.unknown, "Ignoring unknown parameters");
+      }
+   }
+}
+
+fn reject(status: StatusCode, body_text: String, unexpected: &Unexpected) -> Response {
+   let message = match unexpected.error() {
+      Some(hint) => format!("{} ({})\n", body_text, hint),
+      None => format!("{}\n", body_text),
+   };
+   (status, message).into_response()
+}
+
+/// Like `Query`, also reporting unexpected parameter names.
+pub struct CheckedQuery<T>(pub T, pub Unexpected);
+
+impl<T, S> FromRequestParts<S> for CheckedQuery<T>
+where
+   T: DeserializeOwned + KnownParams,
+   S: Send + Sync,
+{
+   type Rejection = Response;
+
+   async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+      let raw = parts.uri.query().unwrap_or_default();
+      let unexpected = Unexpected::check_urlencoded(raw.as_bytes(), T::NAMES);
+      match Query::<T>::try_from_uri(&parts.uri) {
+         Ok(Query(value)) => Ok(CheckedQuery(value, unexpected)),
+         Err(rejection) => Err(reject(rejection.status(), rejection.body_text(), &unexpected)),
+      }
+   }
+}
+
+/// Like `Form`, also reporting unexpected parameter names.
+pub struct CheckedForm<T>(pub T, pub Unexpected);
+
+impl<T, S> FromRequest<S> for CheckedForm<T>
+where
+   T: DeserializeOwned + KnownParams,
+   S: Send + Sync,
+{
+   type Rejection = Response;
+
+   async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+      let (parts, body) = req.into_parts();
+      let bytes: Bytes = axum::body::to_bytes(body, MAX_FORM_BYTES)
+         .await
+         .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+      let unexpected = Unexpected::check_urlencoded(&bytes, T::NAMES);
+      let req = Request::from_parts(parts, Body::from(bytes));
+      match Form::<T>::from_request(req, state).await {
+         Ok(Form(value)) => Ok(CheckedForm(value, unexpected)),
+         Err(rejection) => Err(reject(rejection.status(), rejection.body_text(), &unexpected)),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const CREATE: &[&str] = &["id", "tap_count", "target_url", "notion_page"];
+
+   #[test]
+   fn test_edit_distance() {
+      assert_eq!(edit_distance("target_url", "target_url"), 0);
+      assert_eq!(edit_distance("taget_url", "target_url"), 1);
+      assert_eq!(edit_distance("traget_url", "target_url"), 1);
+      assert_eq!(edit_distance("", "id"), 2);
+      assert_eq!(edit_distance("kitten", "sitting"), 3);
+   }
+
+   #[test]
+   fn test_typos_get_a_suggestion() {
+      for typo in [
+         "target-url",
+         "Target_URL",
+         "targeturl",
+         "taget_url",
+         "tap-count",
+         "notoin_page",
+         "ID",
+      ] {
+         assert!(suggest(typo, CREATE).is_some(), "{typo}");
+      }
+      assert_eq!(suggest("target-url", CREATE), Some("target_url"));
+      assert_eq!(suggest("tapcount", CREATE), Some("tap_count"));
+      assert_eq!(suggest("ids", CREATE), Some("id"));
+   }
+
+   #[test]
+   fn test_genuine_unknowns_get_none() {
+      for unknown in ["utm_source", "url", "q", "lang", "kit", "target"] {
+         assert_eq!(suggest(unknown, CREATE), None, "{unknown}");
+      }
+   }
+
+   #[test]
+   fn test_check_sorts_names_into_misspelled_and_unknown() {
+      let unexpected = Unexpected::check_urlencoded(
+         b"id=055B88A23C1250&target-url=https%3A%2F%2Fexample.com&utm_source=mail&utm_source=again",
+         CREATE,
+      );
+      assert_eq!(unexpected.misspelled, [("target-url".to_string(), "target_url")]);
+      assert_eq!(unexpected.unknown, ["utm_source"]);
+      assert_eq!(
+         unexpected.error().as_deref(),
+         Some("Unknown parameter 'target-url'; did you mean target_url?")
+      );
+
+      let clean = Unexpected::check_urlencoded(b"id=055B88A23C1250&utm_source=mail", CREATE);
+      assert_eq!(clean.error(), None);
+   }
+
+   #[tokio::test]
+   async fn test_query_rejection_carries_the_hint() {
+      #[derive(serde::Deserialize)]
+      struct Create {
+         id: String,
+      }
+      impl KnownParams for Create {
+         const NAMES: &'static [&'static str] = &["id"];
+      }
+
+      let (mut parts, _) = axum::http::Request::builder()
+         .uri("/tag/create?Id=055B88A23C1250")
+         .body(())
+         .unwrap()
+         .into_parts();
+      let Err(response) = CheckedQuery::<Create>::from_request_parts(&mut parts, &()).await else {
+         panic!("expected a rejection");
+      };
+      assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+      let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+      assert!(String::from_utf8_lossy(&body).ends_with("(Unknown parameter 'Id'; did you mean id?)\n"));
+
+      let (mut parts, _) = axum::http::Request::builder()
+         .uri("/tag/create?id=055B88A23C1250&utm_source=mail")
+         .body(())
+         .unwrap()
+         .into_parts();
+      let Ok(CheckedQuery(create, unexpected)) = CheckedQuery::<Create>::from_request_parts(&mut parts, &()).await
+      else {
+         panic!("expected the query to parse");
+      };
+      assert_eq!(create.id, "055B88A23C1250");
+      assert_eq!(unexpected.unknown, ["utm_source"]);
+   }
+}
@@ -0,0 +1,341 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use sqlx::{
+   pool::PoolConnection,
+   postgres::{PgConnectOptions, PgPoolOptions},
+   PgPool, Postgres,
+};
+use tracing::{debug, info, warn};
+
+const WAIT_WINDOW: Duration = Duration::from_secs(30);
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+const P95_WAIT_THRESHOLD: Duration = Duration::from_millis(100);
+const SUSTAINED_FOR: Duration = Duration::from_secs(60);
+const QUIET_FOR: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoolSizing {
+   pub max_connections: u32,
+   pub hard_cap: u32,
+   pub autoscale: bool,
+}
+
+/// A Postgres pool that records acquire latency and, when enabled, swaps itself for a larger (or
+/// smaller) pool in response to sustained saturation.
+#[derive(Clone)]
+pub struct ScalingPool {
+   options: PgConnectOptions,
+   sizing: PoolSizing,
+   current: Arc<RwLock<(PgPool, u32)>>,
+   waits: Arc<Mutex<VecDeque<(Instant, Duration)>>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+   Steady,
+   Saturated,
+   ScaleUp(u32),
+   ScaleDown(u32),
+}
+
+#[derive(Debug, Default)]
+struct Pressure {
+   elevated_since: Option<Instant>,
+   quiet_since: Option<Instant>,
+}
+
+async fn build_pool(options: &PgConnectOptions, max_connections: u32) -> Result<PgPool, sqlx::Error> {
+   PgPoolOptions::new()
+      .min_connections(1)
+      .max_connections(max_connections)
+      .idle_timeout(Duration::from_secs(300))
+      .connect_with(options.clone())
+      .await
+}
+
+impl ScalingPool {
+   pub async fn connect(options: PgConnectOptions, sizing: PoolSizing) -> Result<Self, sqlx::Error> {
+      let pool = build_pool(&options, sizing.max_connections).await?;
+      Ok(ScalingPool {
+         options,
+         sizing,
+         current: Arc::new(RwLock::new((pool, sizing.max_connections))),
+         waits: Arc::new(Mutex::new(VecDeque::new())),
+      })
+   }
+
+   /// A pool whose every acquire fails at once, for tests that only need to build a router.
+   #[cfg(test)]
+   pub fn lazy() -> Self {
+      let options = PgConnectOptions::new().host("127.0.0.1").port(1);
+      let sizing = PoolSizing {
+         max_connections: 1,
+         hard_cap: 1,
+         autoscale: false,
+      };
+      let pool = PgPoolOptions::new()
+         .max_connections(1)
+         .connect_lazy_with(options.clone());
+      ScalingPool {
+         options,
+         sizing,
+         current: Arc::new(RwLock::new((pool, 1))),
+         waits: Arc::new(Mutex::new(VecDeque::new())),
+      }
+   }
+
+   /// Wraps a pool made elsewhere, such as the one `#[sqlx::test]` hands a test, without scaling
+   /// it.
+   #[cfg(test)]
+   pub fn from_pool(pool: PgPool) -> Self {
+      let max_connections = pool.options().get_max_connections();
+      let sizing = PoolSizing {
+         max_connections,
+         hard_cap: max_connections,
+         autoscale: false,
+      };
+      ScalingPool {
+         options: (*pool.connect_options()).clone(),
+         sizing,
+         current: Arc::new(RwLock::new((pool, max_connections))),
+         waits: Arc::new(Mutex::new(VecDeque::new())),
+      }
+   }
+
+   pub fn get(&self) -> PgPool { self.current.read().unwrap().0.clone() }
+
+   pub fn max_connections(&self) -> u32 { self.current.read().unwrap().1 }
+
+   pub async fn acquire(&self) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+      let pool = self.get();
+      let started = Instant::now();
+      #[cfg(feature = "chaos")]
+      let conn = match crate::chaos::before_acquire().await {
+         Ok(()) => pool.acquire().await,
+         Err(e) => Err(e),
+      };
+      #[cfg(not(feature = "chaos"))]
+      let conn = pool.acquire().await;
+      self.record_wait(started, started.elapsed());
+      conn
+   }
+
+   pub async fn close(&self) { self.get().close().await }
+
+   fn record_wait(&self, at: Instant, wait: Duration) {
+      let mut waits = self.waits.lock().unwrap();
+      while waits.front().is_some_and(|(t, _)| at.duration_since(*t) > WAIT_WINDOW) {
+         waits.pop_front();
+      }
+      waits.push_back((at, wait));
+   }
+
+   fn p95_wait(&self, now: Instant) -> Option<Duration> {
+      let mut waits = self.waits.lock().unwrap();
+      while waits.front().is_some_and(|(t, _)| now.duration_since(*t) > WAIT_WINDOW) {
+         waits.pop_front();
+      }
+      let mut samples: Vec<Duration> = waits.iter().map(|(_, w)| *w).collect();
+      drop(waits);
+      percentile_95(&mut samples)
+   }
+
+   pub fn spawn_monitor(&self) {
+      let pool = self.clone();
+      tokio::spawn(async move {
+         let mut pressure = Pressure::default();
+         let mut interval = tokio::time::interval(MONITOR_INTERVAL);
+         loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let p95 = pool.p95_wait(now);
+            let current_max = pool.max_connections();
+            let p95_wait_ms = p95.map(|d| d.as_millis() as u64);
+            debug!(
+               p95_wait_ms,
+               pool_max = current_max,
+               "Sampled Postgres pool acquire latency"
+            );
+
+            match decide(p95, now, &mut pressure, current_max, &pool.sizing) {
+               Verdict::Steady => {}
+               Verdict::Saturated => warn!(
+                  phase = "pool_saturated",
+                  p95_wait_ms,
+                  pool_max = current_max,
+                  autoscale = pool.sizing.autoscale,
+                  "Postgres pool acquire latency has been elevated for a sustained period"
+               ),
+               Verdict::ScaleUp(new_max) | Verdict::ScaleDown(new_max) => {
+                  if let Err(e) = pool.resize(new_max).await {
+                     warn!(
+                        phase = "pool_resize_failed",
+                        pool_max = current_max,
+                        new_max,
+                        "Failed to resize Postgres pool: {:?}",
+                        e
+                     );
+                  }
+               }
+            }
+         }
+      });
+   }
+
+   async fn resize(&self, new_max: u32) -> Result<(), sqlx::Error> {
+      let replacement = build_pool(&self.options, new_max).await?;
+      let (old, old_max) = {
+         let mut current = self.current.write().unwrap();
+         std::mem::replace(&mut *current, (replacement, new_max))
+      };
+      let phase = if new_max > old_max {
+         "pool_scale_up"
+      } else {
+         "pool_scale_down"
+      };
+      info!(phase, old_max, new_max, "Swapped Postgres pool");
+      // Connections already checked out of the old pool are returned to it; `close` waits for them.
+      tokio::spawn(async move { old.close().await });
+      Ok(())
+   }
+}
+
+fn percentile_95(samples: &mut [Duration]) -> Option<Duration> {
+   if samples.is_empty() {
+      return None;
+   }
+   samples.sort_unstable();
+   let rank = (samples.len() * 95).div_ceil(100);
+   Some(samples[rank.saturating_sub(1)])
+}
+
+fn decide(
+   p95: Option<Duration>,
+   now: Instant,
+   pressure: &mut Pressure,
+   current_max: u32,
+   sizing: &PoolSizing,
+) -> Verdict {
+   if p95.is_some_and(|p95| p95 > P95_WAIT_THRESHOLD) {
+      pressure.quiet_since = None;
+      let since = *pressure.elevated_since.get_or_insert(now);
+      if now.duration_since(since) < SUSTAINED_FOR {
+         return Verdict::Steady;
+      }
+      pressure.elevated_since = None;
+      if sizing.autoscale && current_max < sizing.hard_cap {
+         Verdict::ScaleUp((current_max * 2).min(sizing.hard_cap))
+      } else {
+         Verdict::Saturated
+      }
+   } else {
+      pressure.elevated_since = None;
+      let since = *pressure.quiet_since.get_or_insert(now);
+      if now.duration_since(since) < QUIET_FOR || current_max <= sizing.max_connections {
+         return Verdict::Steady;
+      }
+      pressure.quiet_since = None;
+      Verdict::ScaleDown((current_max / 2).max(sizing.max_connections))
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const SIZING: PoolSizing = PoolSizing {
+      max_connections: 5,
+      hard_cap: 20,
+      autoscale: true,
+   };
+
+   #[test]
+   fn test_percentile_95() {
+      assert_eq!(percentile_95(&mut []), None);
+
+      let mut samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+      assert_eq!(percentile_95(&mut samples), Some(Duration::from_millis(95)));
+
+      let mut samples = vec![Duration::from_millis(7)];
+      assert_eq!(percentile_95(&mut samples), Some(Duration::from_millis(7)));
+   }
+
+   #[test]
+   fn test_decide_scales_up_only_after_sustained_pressure() {
+      let slow = Some(Duration::from_millis(500));
+      let start = Instant::now();
+      let mut pressure = Pressure::default();
+
+      assert_eq!(decide(slow, start, &mut pressure, 5, &SIZING), Verdict::Steady);
+      assert_eq!(
+         decide(slow, start + Duration::from_secs(30), &mut pressure, 5, &SIZING),
+         Verdict::Steady
+      );
+      assert_eq!(
+         decide(slow, start + SUSTAINED_FOR, &mut pressure, 5, &SIZING),
+         Verdict::ScaleUp(10)
+      );
+
+      // A single fast sample resets the clock
+      let mut pressure = Pressure::default();
+      decide(slow, start, &mut pressure, 5, &SIZING);
+      decide(None, start + Duration::from_secs(30), &mut pressure, 5, &SIZING);
+      assert_eq!(
+         decide(slow, start + SUSTAINED_FOR, &mut pressure, 5, &SIZING),
+         Verdict::Steady
+      );
+   }
+
+   #[test]
+   fn test_decide_respects_hard_cap_and_autoscale_flag() {
+      let slow = Some(Duration::from_millis(500));
+      let start = Instant::now();
+
+      let mut pressure = Pressure::default();
+      decide(slow, start, &mut pressure, 16, &SIZING);
+      assert_eq!(
+         decide(slow, start + SUSTAINED_FOR, &mut pressure, 16, &SIZING),
+         Verdict::ScaleUp(20)
+      );
+
+      let mut pressure = Pressure::default();
+      decide(slow, start, &mut pressure, 20, &SIZING);
+      assert_eq!(
+         decide(slow, start + SUSTAINED_FOR, &mut pressure, 20, &SIZING),
+         Verdict::Saturated
+      );
+
+      let disabled = PoolSizing {
+         autoscale: false,
+         ..SIZING
+      };
+      let mut pressure = Pressure::default();
+      decide(slow, start, &mut pressure, 5, &disabled);
+      assert_eq!(
+         decide(slow, start + SUSTAINED_FOR, &mut pressure, 5, &disabled),
+         Verdict::Saturated
+      );
+   }
+
+   #[test]
+   fn test_decide_scales_down_after_quiet_period() {
+      let fast = Some(Duration::from_millis(1));
+      let start = Instant::now();
+      let mut pressure = Pressure::default();
+
+      assert_eq!(decide(fast, start, &mut pressure, 20, &SIZING), Verdict::Steady);
+      assert_eq!(
+         decide(fast, start + QUIET_FOR, &mut pressure, 20, &SIZING),
+         Verdict::ScaleDown(10)
+      );
+
+      let mut pressure = Pressure::default();
+      decide(fast, start, &mut pressure, 5, &SIZING);
+      assert_eq!(
+         decide(fast, start + QUIET_FOR, &mut pressure, 5, &SIZING),
+         Verdict::Steady
+      );
+   }
+}
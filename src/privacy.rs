@@ -0,0 +1,147 @@
+//! Honoring a client's request not to be tracked. With `TWAG_PRIVACY_SIGNALS` set, a scan whose
+//! client sends `Sec-GPC: 1` (Global Privacy Control), or `DNT: 1` too if asked, is still answered
+//! and counted in its tag's totals, but its tap event keeps none of `TapDetail`: no visitor
+//! fingerprint, country or channel. The row says `privacy_limited` instead, so stats can say how
+//! many taps they know less about.
+//!
+//! Whether a scan is limited is decided once, as a `Preference`, and the detail only ever reaches
+//! a tap through `Preference::keep`; anything added to `TapDetail` later is dropped the same way.
+
+use std::str::FromStr;
+
+use axum::http::HeaderMap;
+use sqlx::PgConnection;
+
+use crate::models::TagUid;
+
+/// Which signals are honored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Signals {
+   #[default]
+   Off,
+   /// `Sec-GPC` only.
+   Gpc,
+   /// `Sec-GPC` or `DNT`.
+   GpcAndDnt,
+}
+
+impl FromStr for Signals {
+   type Err = String;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      match s.trim() {
+         "off" => Ok(Signals::Off),
+         "respect-gpc" => Ok(Signals::Gpc),
+         "respect-both" => Ok(Signals::GpcAndDnt),
+         other => Err(format!(
+            "TWAG_PRIVACY_SIGNALS must be off, respect-gpc or respect-both, not '{other}'"
+         )),
+      }
+   }
+}
+
+/// Both headers are only ever meant as `1`; anything else says nothing.
+fn signals(headers: &HeaderMap, name: &str) -> bool {
+   headers
+      .get(name)
+      .and_then(|v| v.to_str().ok())
+      .is_some_and(|v| v.trim() == "1")
+}
+
+/// Whether one scan's client asked not to be tracked, as far as the honored signals go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preference {
+   limited: bool,
+}
+
+impl Preference {
+   pub fn of(honored: Signals, headers: &HeaderMap) -> Preference {
+      let gpc = || signals(headers, "sec-gpc");
+      let dnt = || signals(headers, "dnt");
+      Preference {
+         limited: match honored {
+            Signals::Off => false,
+            Signals::Gpc => gpc(),
+            Signals::GpcAndDnt => gpc() || dnt(),
+         },
+      }
+   }
+
+   pub fn limited(&self) -> bool { self.limited }
+
+   /// `detail()`, unless the client asked not to be tracked, in which case it's never called.
+   pub fn keep<T>(&self, detail: impl FnOnce() -> T) -> Option<T> { (!self.limited).then(detail) }
+}
+
+/// How many of the tag's taps, ever, kept no detail.
+pub async fn limited_taps(conn: &mut PgConnection, id: &TagUid) -> Result<i64, sqlx::Error> {
+   sqlx::query_scalar!(
+      r#"SELECT count(*) AS "limited!" FROM twag_tap_events WHERE tag_id = $1 AND privacy_limited"#,
+      id as &TagUid,
+   )
+   .fetch_one(conn)
+   .await
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+      pairs
+         .iter()
+         .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+         .collect()
+   }
+
+   #[test]
+   fn test_settings() {
+      assert_eq!("off".parse(), Ok(Signals::Off));
+      assert_eq!("respect-gpc".parse(), Ok(Signals::Gpc));
+      assert_eq!(" respect-both ".parse(), Ok(Signals::GpcAndDnt));
+      assert!("true".parse::<Signals>().unwrap_err().contains("not 'true'"));
+   }
+
+   #[test]
+   fn test_each_header_combination() {
+      let none = headers(&[]);
+      let gpc = headers(&[("sec-gpc", "1")]);
+      let dnt = headers(&[("dnt", "1")]);
+      let both = headers(&[("sec-gpc", "1"), ("dnt", "1")]);
+      let cases = [
+         (&none, [false, false, false]),
+         (&gpc, [false, true, true]),
+         (&dnt, [false, false, true]),
+         (&both, [false, true, true]),
+      ];
+      for (headers, limited) in cases {
+         for (honored, limited) in [Signals::Off, Signals::Gpc, Signals::GpcAndDnt]
+            .into_iter()
+            .zip(limited)
+         {
+            assert_eq!(
+               Preference::of(honored, headers).limited(),
+               limited,
+               "{honored:?} {headers:?}"
+            );
+         }
+      }
+   }
+
+   #[test]
+   fn test_only_a_1_asks() {
+      for value in ["0", "", "yes", "1, 1"] {
+         let headers = headers(&[("sec-gpc", value), ("dnt", value)]);
+         assert!(!Preference::of(Signals::GpcAndDnt, &headers).limited(), "{value:?}");
+      }
+      assert!(Preference::of(Signals::Gpc, &headers(&[("Sec-GPC", " 1")])).limited());
+   }
+
+   #[test]
+   fn test_detail_is_dropped_when_limited() {
+      let limited = Preference::of(Signals::Gpc, &headers(&[("sec-gpc", "1")]));
+      assert_eq!(limited.keep::<u64>(|| unreachable!("collected anyway")), None);
+      let tracked = Preference::of(Signals::Gpc, &headers(&[]));
+      assert_eq!(tracked.keep(|| 7), Some(7));
+   }
+}
@@ -0,0 +1,278 @@
+//! Pre-signed creation links, for a vendor to write onto tags without holding admin access. A link
+//! is `/tag/create?id=…&exp=…&sig=…`, signed over the id and expiry with
+//! `TWAG_PROVISIONING_SECRET`, and stands in for admin access on the create page and form for that
+//! one id. It creates at most one tag: the first creation through it marks the id consumed, and a
+//! tag that already exists isn't the link's to touch.
+//!
+//! Only twag checks the signature, so the reverse proxy guarding admin routes has to let
+//! `/tag/create` requests carrying `sig` through.
+
+use sqlx::PgConnection;
+
+use crate::models::{self, TagUid};
+use crate::security::{hmac_hex, verify_hex, MIN_SECRET_LEN};
+
+pub const DEFAULT_DAYS: u32 = 30;
+pub const MAX_DAYS: u32 = 365;
+/// Ids per bulk request; a vendor batch bigger than this can be split.
+pub const MAX_IDS: usize = 500;
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Refused {
+   #[error("This creation link is not valid")]
+   Invalid,
+   #[error("This creation link has expired")]
+   Expired,
+   #[error("This creation link has already been used")]
+   Used,
+   #[error("This tag already exists; creation links can't change it")]
+   Exists,
+}
+
+#[derive(Clone)]
+pub struct ProvisioningKey(Vec<u8>);
+
+impl ProvisioningKey {
+   /// Disabled unless `TWAG_PROVISIONING_SECRET` is set.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+      let Some(secret) = var("TWAG_PROVISIONING_SECRET").filter(|s| !s.is_empty()) else {
+         return Ok(None);
+      };
+      if secret.len() < MIN_SECRET_LEN {
+         return Err(format!(
+            "TWAG_PROVISIONING_SECRET must be at least {} bytes",
+            MIN_SECRET_LEN
+         ));
+      }
+      Ok(Some(ProvisioningKey(secret.into_bytes())))
+   }
+
+   fn sign(&self, id: &TagUid, exp: i64) -> String {
+      hmac_hex(&self.0, &[id.to_string().as_bytes(), b".", exp.to_string().as_bytes()])
+   }
+}
+
+/// A link's signature, as carried in its query string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+   /// Unix time the link stops working.
+   pub exp: i64,
+   pub sig: String,
+}
+
+impl Link {
+   pub fn new(key: &ProvisioningKey, id: &TagUid, exp: i64) -> Self {
+      Link {
+         exp,
+         sig: key.sign(id, exp),
+      }
+   }
+
+   /// A link for `id`, good for `days` from `now`; out-of-range choices are clamped.
+   pub fn for_days(key: &ProvisioningKey, id: &TagUid, now: i64, days: u32) -> Self {
+      Link::new(key, id, now + i64::from(days.clamp(1, MAX_DAYS)) * SECS_PER_DAY)
+   }
+
+   /// Whether this link may create tag `id`, before asking whether it's been used.
+   pub fn check(&self, key: &ProvisioningKey, id: &TagUid, now: i64) -> Result<(), Refused> {
+      if !verify_hex(
+         &key.0,
+         &[id.to_string().as_bytes(), b".", self.exp.to_string().as_bytes()],
+         &self.sig,
+      ) {
+         return Err(Refused::Invalid);
+      }
+      if now >= self.exp {
+         return Err(Refused::Expired);
+      }
+      Ok(())
+   }
+
+   /// `/tag/create?id=…&exp=…&sig=…`, under `origin`.
+   pub fn create_url(&self, origin: &str, id: &TagUid) -> String {
+      format!("{}/tag/create?id={}&exp={}&sig={}", origin, id, self.exp, self.sig)
+   }
+}
+
+/// The ids pasted for a bulk request, one per line as bare ids or scan URLs, without repeats; and
+/// the lines that weren't, with why. Past `MAX_IDS`, lines are refused rather than dropped.
+pub fn parse_ids(raw: &str, hosts: &[String]) -> (Vec<TagUid>, Vec<(String, String)>) {
+   let mut ids: Vec<TagUid> = Vec::new();
+   let mut rejected = Vec::new();
+   for line in raw.lines().map(str::trim).filter(|line| !line.is_empty()) {
+      match models::parse_tag_input(line, hosts) {
+         Ok(slug) if ids.contains(&slug.id) => {}
+         Ok(_) if ids.len() >= MAX_IDS => rejected.push((line.to_string(), format!("More than {MAX_IDS} ids"))),
+         Ok(slug) => ids.push(slug.id),
+         Err(e) => rejected.push((line.to_string(), e.to_string())),
+      }
+   }
+   (ids, rejected)
+}
+
+/// Whether tag `id` may be created through a valid link: it mustn't exist, nor have been created
+/// through one before.
+pub async fn usable(conn: &mut PgConnection, id: &TagUid) -> Result<Result<(), Refused>, sqlx::Error> {
+   let row = sqlx::query!(
+      r#"SELECT EXISTS (SELECT 1 FROM twag_tags WHERE id = $1) AS "exists!",
+            EXISTS (SELECT 1 FROM twag_provisioning_consumed WHERE tag_id = $1) AS "used!""#,
+      id as &TagUid,
+   )
+   .fetch_one(conn)
+   .await?;
+   Ok(standing(row.exists, row.used))
+}
+
+/// Whether `link` may create tag `id` now. `Refused::Exists` comes before the signature is looked
+/// at: the request should then go on as if it were unsigned.
+pub async fn admit(
+   conn: &mut PgConnection,
+   key: Option<&ProvisioningKey>,
+   link: &Link,
+   id: &TagUid,
+   now: i64,
+) -> Result<Result<(), Refused>, sqlx::Error> {
+   let standing = usable(conn, id).await?;
+   if standing == Err(Refused::Exists) {
+      return Ok(standing);
+   }
+   let Some(key) = key else {
+      return Ok(Err(Refused::Invalid));
+   };
+   Ok(link.check(key, id, now).and(standing))
+}
+
+/// An existing tag wins over a used link, as it's what the link would be asked to overwrite.
+fn standing(exists: bool, used: bool) -> Result<(), Refused> {
+   match (exists, used) {
+      (true, _) => Err(Refused::Exists),
+      (false, true) => Err(Refused::Used),
+      (false, false) => Ok(()),
+   }
+}
+
+/// Marks the id's link used, returning whether it wasn't already. Meant for the transaction
+/// creating the tag, so that two creations racing through one link can't both commit.
+pub async fn consume(conn: &mut PgConnection, id: &TagUid) -> Result<bool, sqlx::Error> {
+   let result = sqlx::query!(
+      "INSERT INTO twag_provisioning_consumed (tag_id) VALUES ($1::tag_uid) ON CONFLICT DO NOTHING",
+      id as &TagUid,
+   )
+   .execute(conn)
+   .await?;
+   Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const NOW: i64 = 1_792_152_000;
+
+   fn key(secret: &str) -> ProvisioningKey {
+      ProvisioningKey::from_vars(|name| (name == "TWAG_PROVISIONING_SECRET").then(|| secret.to_string()))
+         .unwrap()
+         .unwrap()
+   }
+
+   fn uid(id: &str) -> TagUid { id.parse().unwrap() }
+
+   #[test]
+   fn test_links_work_for_their_id_until_they_expire() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let link = Link::for_days(&key, &id, NOW, 30);
+      assert_eq!(link.exp, NOW + 30 * SECS_PER_DAY);
+      assert_eq!(link.check(&key, &id, NOW), Ok(()));
+      assert_eq!(link.check(&key, &id, link.exp - 1), Ok(()));
+      assert!(link
+         .create_url("https://xz.ws", &id)
+         .starts_with("https://xz.ws/tag/create?id=055B88A23C1250&exp=1794744000&sig="));
+   }
+
+   #[test]
+   fn test_expired_links_are_refused() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let link = Link::for_days(&key, &id, NOW, 1);
+      assert_eq!(link.check(&key, &id, link.exp), Err(Refused::Expired));
+      assert_eq!(link.check(&key, &id, link.exp + 86_400), Err(Refused::Expired));
+   }
+
+   #[test]
+   fn test_tampered_links_are_refused() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let link = Link::for_days(&key, &id, NOW, 1);
+
+      let extended = Link {
+         exp: link.exp + 86_400,
+         ..link.clone()
+      };
+      assert_eq!(extended.check(&key, &id, NOW), Err(Refused::Invalid));
+      assert_eq!(link.check(&key, &uid("04A1B2C3D4E5F6"), NOW), Err(Refused::Invalid));
+      assert_eq!(
+         link.check(&super::key("fedcba9876543210"), &id, NOW),
+         Err(Refused::Invalid)
+      );
+      let mut flipped = link.sig.clone().into_bytes();
+      flipped[0] = if flipped[0] == b'0' { b'1' } else { b'0' };
+      let flipped = Link {
+         sig: String::from_utf8(flipped).unwrap(),
+         ..link.clone()
+      };
+      assert_eq!(flipped.check(&key, &id, NOW), Err(Refused::Invalid));
+      for sig in ["", "zz", &link.sig[1..], &"a".repeat(10_000)] {
+         let garbage = Link {
+            sig: sig.to_string(),
+            ..link.clone()
+         };
+         assert_eq!(garbage.check(&key, &id, NOW), Err(Refused::Invalid), "{sig:?}");
+      }
+   }
+
+   #[test]
+   fn test_links_create_one_tag_only() {
+      assert_eq!(standing(false, false), Ok(()));
+      // Used, then the tag it made was purged
+      assert_eq!(standing(false, true), Err(Refused::Used));
+      assert_eq!(standing(true, true), Err(Refused::Exists));
+      // Created some other way before the link was used
+      assert_eq!(standing(true, false), Err(Refused::Exists));
+   }
+
+   #[test]
+   fn test_days_are_clamped() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      assert_eq!(Link::for_days(&key, &id, NOW, 0).exp, NOW + SECS_PER_DAY);
+      assert_eq!(
+         Link::for_days(&key, &id, NOW, 10_000).exp,
+         NOW + i64::from(MAX_DAYS) * SECS_PER_DAY
+      );
+   }
+
+   #[test]
+   fn test_parse_ids() {
+      let hosts = ["xz.ws".to_string()];
+      let (ids, rejected) = parse_ids(
+         "055B88A23C1250\n\n  https://xz.ws/tag/055B88A23C1250x00000F  \n04:A1:B2:C3:D4:E5:F6\nnope\n",
+         &hosts,
+      );
+      assert_eq!(ids, [uid("055B88A23C1250"), uid("04A1B2C3D4E5F6")]);
+      assert_eq!(rejected.len(), 1);
+      assert_eq!(rejected[0].0, "nope");
+
+      let many: String = (0..MAX_IDS + 2).map(|n| format!("04{:012X}\n", n)).collect();
+      let (ids, rejected) = parse_ids(&many, &hosts);
+      assert_eq!(ids.len(), MAX_IDS);
+      assert_eq!(rejected.len(), 2);
+   }
+
+   #[test]
+   fn test_config() {
+      assert!(ProvisioningKey::from_vars(|_| None).unwrap().is_none());
+      assert!(ProvisioningKey::from_vars(|_| Some("short".to_string())).is_err());
+   }
+}
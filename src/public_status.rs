@@ -0,0 +1,166 @@
+//! `GET /status`: a public page listing the tags marked `public_status`, with how long ago each was
+//! last scanned, e.g. "Laundry machine: 2 hours ago". For shared things whose use is no secret;
+//! tag ids, target URLs and counts stay off the page, counts unless the tag sets
+//! `public_show_count`.
+//!
+//! Off unless `TWAG_STATUS_PAGE=true`. It's one query, never waits on Notion, and may be cached for
+//! `MAX_AGE_SECS`, so a screen left showing it costs next to nothing.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgConnection;
+
+use crate::checkout;
+
+/// How long the page may be cached, and how often it refreshes itself.
+pub const MAX_AGE_SECS: u32 = 30;
+
+/// A listed tag, as stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+   pub label: Option<String>,
+   pub last_accessed: Option<DateTime<Utc>>,
+   pub access_count: Option<i32>,
+   pub public_show_count: bool,
+}
+
+/// Whether a tag is listed; mirrors `load`'s query, for tests.
+pub fn listed(public_status: bool, deleted: bool) -> bool { public_status && !deleted }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+   /// Scanned within the hour.
+   Fresh,
+   /// Within the day.
+   Recent,
+   Stale,
+   Never,
+}
+
+impl Freshness {
+   pub fn of(last_accessed: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Freshness {
+      match last_accessed.map(|at| now - at) {
+         None => Freshness::Never,
+         Some(ago) if ago < TimeDelta::hours(1) => Freshness::Fresh,
+         Some(ago) if ago < TimeDelta::days(1) => Freshness::Recent,
+         Some(_) => Freshness::Stale,
+      }
+   }
+
+   /// The page's CSS class for it.
+   pub fn class(&self) -> &'static str {
+      match self {
+         Freshness::Fresh => "fresh",
+         Freshness::Recent => "recent",
+         Freshness::Stale => "stale",
+         Freshness::Never => "never",
+      }
+   }
+}
+
+/// One line of the page. Holds nothing the page mustn't show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+   pub label: String,
+   /// "2 hours ago", or "never scanned".
+   pub scanned: String,
+   pub freshness: Freshness,
+   pub count: Option<i32>,
+}
+
+impl Entry {
+   pub fn new(row: Row, now: DateTime<Utc>) -> Entry {
+      Entry {
+         label: row.label.unwrap_or_else(|| "Unlabelled".to_string()),
+         scanned: match row.last_accessed {
+            // A tap written with a clock slightly ahead of this one was still just now
+            Some(at) => format!("{} ago", checkout::humanize((now - at).max(TimeDelta::zero()))),
+            None => "never scanned".to_string(),
+         },
+         freshness: Freshness::of(row.last_accessed, now),
+         count: match row.public_show_count {
+            true => Some(row.access_count.unwrap_or(0)),
+            false => None,
+         },
+      }
+   }
+}
+
+pub async fn load(conn: &mut PgConnection) -> Result<Vec<Row>, sqlx::Error> {
+   let rows = sqlx::query!(
+      r#"SELECT label, extract(epoch FROM last_accessed)::bigint AS last_accessed, access_count, public_show_count
+         FROM twag_tags WHERE public_status AND deleted_at IS NULL
+         ORDER BY label NULLS LAST, id"#
+   )
+   .fetch_all(conn)
+   .await?;
+   Ok(rows
+      .into_iter()
+      .map(|row| Row {
+         label: row.label,
+         last_accessed: row.last_accessed.and_then(|secs| DateTime::from_timestamp(secs, 0)),
+         access_count: row.access_count,
+         public_show_count: row.public_show_count,
+      })
+      .collect())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn at(s: &str) -> DateTime<Utc> { DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc) }
+
+   #[test]
+   fn test_only_public_tags_are_listed() {
+      assert!(listed(true, false));
+      assert!(!listed(false, false));
+      // Deleted while public
+      assert!(!listed(true, true));
+      assert!(!listed(false, true));
+   }
+
+   #[test]
+   fn test_freshness() {
+      let now = at("2026-10-16T12:00:00Z");
+      assert_eq!(Freshness::of(Some(at("2026-10-16T11:30:00Z")), now), Freshness::Fresh);
+      assert_eq!(Freshness::of(Some(at("2026-10-16T10:00:00Z")), now), Freshness::Recent);
+      assert_eq!(Freshness::of(Some(at("2026-10-14T12:00:00Z")), now), Freshness::Stale);
+      assert_eq!(Freshness::of(None, now), Freshness::Never);
+   }
+
+   #[test]
+   fn test_counts_only_shown_when_allowed() {
+      let now = at("2026-10-16T12:00:00Z");
+      let row = Row {
+         label: Some("Laundry machine".to_string()),
+         last_accessed: Some(at("2026-10-16T10:00:00Z")),
+         access_count: Some(41),
+         public_show_count: false,
+      };
+      let hidden = Entry::new(row.clone(), now);
+      assert_eq!(hidden.scanned, "2 hours ago");
+      assert_eq!(hidden.count, None);
+      let shown = Entry::new(
+         Row {
+            public_show_count: true,
+            ..row
+         },
+         now,
+      );
+      assert_eq!(shown.count, Some(41));
+
+      let unscanned = Entry::new(
+         Row {
+            label: None,
+            last_accessed: None,
+            access_count: None,
+            public_show_count: true,
+         },
+         now,
+      );
+      assert_eq!(
+         (unscanned.label.as_str(), unscanned.scanned.as_str(), unscanned.count),
+         ("Unlabelled", "never scanned", Some(0))
+      );
+   }
+}
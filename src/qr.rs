@@ -0,0 +1,122 @@
+use std::fmt::Write;
+use std::io::Cursor;
+
+use image::{GrayImage, ImageFormat, Luma};
+use qrcode::{Color, EcLevel, QrCode};
+
+/// Light modules around the code, as the QR spec requires.
+const QUIET_ZONE: usize = 4;
+/// Pixels per module in the PNG rendering.
+const PNG_SCALE: u32 = 8;
+
+/// The dark/light modules of a QR code, shared by the SVG and PNG renderings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matrix {
+   width: usize,
+   dark: Vec<bool>,
+}
+
+impl Matrix {
+   pub fn encode(data: &str) -> Result<Matrix, qrcode::types::QrError> {
+      let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)?;
+      Ok(Matrix {
+         width: code.width(),
+         dark: code.to_colors().into_iter().map(|c| c == Color::Dark).collect(),
+      })
+   }
+
+   fn is_dark(&self, x: usize, y: usize) -> bool { self.dark[y * self.width + x] }
+
+   /// Width in modules, including the quiet zone.
+   fn size(&self) -> usize { self.width + 2 * QUIET_ZONE }
+
+   /// One path of horizontal runs, in module units, so it scales crisply to any print size.
+   pub fn to_svg(&self) -> String {
+      let size = self.size();
+      let mut path = String::new();
+      for y in 0..self.width {
+         let mut x = 0;
+         while x < self.width {
+            if !self.is_dark(x, y) {
+               x += 1;
+               continue;
+            }
+            let start = x;
+            while x < self.width && self.is_dark(x, y) {
+               x += 1;
+            }
+            let run = x - start;
+            write!(path, "M{} {}h{}v1h-{}z", start + QUIET_ZONE, y + QUIET_ZONE, run, run).unwrap();
+         }
+      }
+      format!(
+         r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {size} {size}" shape-rendering="crispEdges"><rect width="{size}" height="{size}" fill="#fff"/><path fill="#000" d="{path}"/></svg>"##
+      )
+   }
+
+   pub fn to_png(&self) -> Result<Vec<u8>, image::ImageError> {
+      let size = (self.size() as u32) * PNG_SCALE;
+      let image = GrayImage::from_fn(size, size, |x, y| {
+         let (x, y) = ((x / PNG_SCALE) as usize, (y / PNG_SCALE) as usize);
+         let inside =
+            (QUIET_ZONE..QUIET_ZONE + self.width).contains(&x) && (QUIET_ZONE..QUIET_ZONE + self.width).contains(&y);
+         match inside && self.is_dark(x - QUIET_ZONE, y - QUIET_ZONE) {
+            true => Luma([0]),
+            false => Luma([255]),
+         }
+      });
+      let mut png = Vec::new();
+      image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+      Ok(png)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   /// Small enough to check by eye:
+   ///
+   /// ```text
+   /// ##.
+   /// .#.
+   /// ###
+   /// ```
+   fn fixed() -> Matrix {
+      Matrix {
+         width: 3,
+         dark: vec![true, true, false, false, true, false, true, true, true],
+      }
+   }
+
+   #[test]
+   fn test_svg_snapshot() {
+      assert_eq!(
+         fixed().to_svg(),
+         r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 11 11" shape-rendering="crispEdges"><rect width="11" height="11" fill="#fff"/><path fill="#000" d="M4 4h2v1h-2zM5 5h1v1h-1zM4 6h3v1h-3z"/></svg>"##
+      );
+   }
+
+   #[test]
+   fn test_png_matches_matrix() {
+      let png = fixed().to_png().unwrap();
+      let image = image::load_from_memory(&png).unwrap().to_luma8();
+      assert_eq!(image.width(), 11 * PNG_SCALE);
+      let module = |x: u32, y: u32| image.get_pixel(x * PNG_SCALE, y * PNG_SCALE)[0];
+      assert_eq!(module(0, 0), 255);
+      assert_eq!(module(4, 4), 0);
+      assert_eq!(module(6, 4), 255);
+      assert_eq!(module(6, 6), 0);
+   }
+
+   #[test]
+   fn test_encode_has_finder_patterns() {
+      let matrix = Matrix::encode("https://xz.ws/tag/055B88A23C1250").unwrap();
+      assert_eq!((matrix.width - 17) % 4, 0);
+      let w = matrix.width;
+      for (x, y) in [(0, 0), (w - 1, 0), (0, w - 1), (6, 6), (w - 7, 6), (6, w - 7)] {
+         assert!(matrix.is_dark(x, y), "({x}, {y}) should be dark");
+      }
+      assert!(!matrix.is_dark(7, 7));
+   }
+}
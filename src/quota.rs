@@ -0,0 +1,194 @@
+//! Soft limits on how many tags and aliases a deployment holds, so a runaway script or import
+//! can't create them without bound. twag has no users of its own, only whoever holds the admin
+//! routes, so the limits are the deployment's: `TWAG_MAX_TAGS` and `TWAG_MAX_ALIASES`, each
+//! unlimited while unset. Deleted tags in the trash don't count.
+//!
+//! A limit is checked by `reserve`, in the transaction that creates, under a transaction-scoped
+//! advisory lock per resource: two creations racing each other, on any instance, take turns, so
+//! they can't both see room for the last one. Lowering a limit below what's already there is
+//! allowed, and only refuses new creations until enough are deleted.
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use sqlx::PgConnection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+   Tags,
+   Aliases,
+}
+
+impl Resource {
+   fn noun(self) -> &'static str {
+      match self {
+         Resource::Tags => "tags",
+         Resource::Aliases => "aliases",
+      }
+   }
+
+   /// Held by `reserve` until its transaction ends.
+   fn lock_key(self) -> i64 {
+      // "twagquot", then the resource
+      const BASE: i64 = 0x7477_6167_7175_6f00;
+      match self {
+         Resource::Tags => BASE,
+         Resource::Aliases => BASE + 1,
+      }
+   }
+}
+
+/// The most of each resource there may be; `None` for no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quotas {
+   pub tags: Option<i64>,
+   pub aliases: Option<i64>,
+}
+
+impl Quotas {
+   pub fn limit(&self, resource: Resource) -> Option<i64> {
+      match resource {
+         Resource::Tags => self.tags,
+         Resource::Aliases => self.aliases,
+      }
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("At most {limit} {} are allowed, and there are {usage} already", resource.noun())]
+pub struct Exceeded {
+   pub resource: Resource,
+   pub limit: i64,
+   pub usage: i64,
+}
+
+impl IntoResponse for Exceeded {
+   fn into_response(self) -> Response { (StatusCode::UNPROCESSABLE_ENTITY, format!("{}.\n", self)).into_response() }
+}
+
+/// Whether `adding` more fit beside `usage` under `limit`. Reaching the limit exactly is allowed.
+pub fn admit(resource: Resource, limit: Option<i64>, usage: i64, adding: i64) -> Result<(), Exceeded> {
+   match limit {
+      Some(limit) if usage + adding > limit => Err(Exceeded { resource, limit, usage }),
+      _ => Ok(()),
+   }
+}
+
+/// How many of `resource` there are now.
+pub async fn usage(conn: &mut PgConnection, resource: Resource) -> Result<i64, sqlx::Error> {
+   match resource {
+      Resource::Tags => {
+         sqlx::query_scalar!(r#"SELECT count(*) AS "usage!" FROM twag_tags WHERE deleted_at IS NULL"#)
+            .fetch_one(conn)
+            .await
+      }
+      Resource::Aliases => {
+         sqlx::query_scalar!(r#"SELECT count(*) AS "usage!" FROM twag_tag_aliases"#)
+            .fetch_one(conn)
+            .await
+      }
+   }
+}
+
+/// Takes `resource`'s lock for the rest of `tx`, and reads its usage then; `None`, without
+/// locking, when it has no limit. `tx` must be the transaction that creates: others creating the
+/// same resource wait until it ends.
+pub async fn hold(tx: &mut PgConnection, quotas: &Quotas, resource: Resource) -> Result<Option<Usage>, sqlx::Error> {
+   let Some(limit) = quotas.limit(resource) else {
+      return Ok(None);
+   };
+   sqlx::query!("SELECT pg_advisory_xact_lock($1)", resource.lock_key())
+      .execute(&mut *tx)
+      .await?;
+   Ok(Some(Usage {
+      used: usage(tx, resource).await?,
+      limit: Some(limit),
+   }))
+}
+
+/// Makes room for `adding` more of `resource` in `tx`, as `hold` does, or says why there isn't
+/// any.
+pub async fn reserve(
+   tx: &mut PgConnection,
+   quotas: &Quotas,
+   resource: Resource,
+   adding: i64,
+) -> Result<Result<(), Exceeded>, sqlx::Error> {
+   Ok(match hold(tx, quotas, resource).await? {
+      Some(usage) => admit(resource, usage.limit, usage.used, adding),
+      None => Ok(()),
+   })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Usage {
+   pub used: i64,
+   pub limit: Option<i64>,
+}
+
+impl Usage {
+   /// Whether nothing more may be created.
+   pub fn full(&self) -> bool { self.limit.is_some_and(|limit| self.used >= limit) }
+}
+
+/// Each resource's usage against its limit, for `GET /usage` and the stats page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Report {
+   pub tags: Usage,
+   pub aliases: Usage,
+}
+
+pub async fn report(conn: &mut PgConnection, quotas: &Quotas) -> Result<Report, sqlx::Error> {
+   Ok(Report {
+      tags: Usage {
+         used: usage(conn, Resource::Tags).await?,
+         limit: quotas.tags,
+      },
+      aliases: Usage {
+         used: usage(conn, Resource::Aliases).await?,
+         limit: quotas.aliases,
+      },
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_the_limit_itself_is_allowed() {
+      assert_eq!(admit(Resource::Tags, Some(10), 9, 1), Ok(()));
+      assert_eq!(
+         admit(Resource::Tags, Some(10), 10, 1),
+         Err(Exceeded {
+            resource: Resource::Tags,
+            limit: 10,
+            usage: 10,
+         })
+      );
+      // A kit of three with room for two is refused whole
+      assert!(admit(Resource::Tags, Some(10), 8, 3).is_err());
+      assert_eq!(admit(Resource::Aliases, None, i64::MAX - 1, 1), Ok(()));
+   }
+
+   #[test]
+   fn test_a_lowered_limit_only_refuses_new_creations() {
+      let usage = Usage {
+         used: 12,
+         limit: Some(10),
+      };
+      assert!(usage.full());
+      assert!(admit(Resource::Aliases, usage.limit, usage.used, 1).is_err());
+      assert_eq!(admit(Resource::Aliases, usage.limit, usage.used, 0), Ok(()));
+   }
+
+   #[test]
+   fn test_refusals_state_the_limit_and_usage() {
+      let exceeded = admit(Resource::Aliases, Some(100), 100, 1).unwrap_err();
+      assert_eq!(
+         exceeded.to_string(),
+         "At most 100 aliases are allowed, and there are 100 already"
+      );
+      assert_eq!(exceeded.into_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+   }
+}
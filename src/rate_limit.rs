@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Clients tracked before expired windows are swept out.
+const MAX_TRACKED: usize = 10_000;
+
+struct Window {
+   started: Instant,
+   hits: u32,
+}
+
+/// A fixed-window limit on hits per client address, held in memory; it resets on restart, and each
+/// instance counts separately.
+pub struct RateLimiter {
+   max_hits: u32,
+   window: Duration,
+   clients: Mutex<HashMap<IpAddr, Window>>,
+}
+
+impl RateLimiter {
+   pub fn new(max_hits: u32, window: Duration) -> Self {
+      RateLimiter {
+         max_hits,
+         window,
+         clients: Mutex::new(HashMap::new()),
+      }
+   }
+
+   /// Counts a hit from `ip`, and says whether it's within the limit.
+   pub fn check(&self, ip: IpAddr, now: Instant) -> bool {
+      let mut clients = self.clients.lock().unwrap();
+      if clients.len() >= MAX_TRACKED {
+         clients.retain(|_, window| now.duration_since(window.started) < self.window);
+      }
+      let window = clients.entry(ip).or_insert(Window { started: now, hits: 0 });
+      if now.duration_since(window.started) >= self.window {
+         *window = Window { started: now, hits: 0 };
+      }
+      window.hits = window.hits.saturating_add(1);
+      window.hits <= self.max_hits
+   }
+
+   pub fn window(&self) -> Duration { self.window }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_limits_each_client_separately() {
+      let limiter = RateLimiter::new(2, Duration::from_secs(60));
+      let (a, b) = ("192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap());
+      let now = Instant::now();
+      assert!(limiter.check(a, now));
+      assert!(limiter.check(a, now));
+      assert!(!limiter.check(a, now));
+      assert!(limiter.check(b, now));
+   }
+
+   #[test]
+   fn test_window_resets() {
+      let limiter = RateLimiter::new(1, Duration::from_secs(60));
+      let ip = "192.0.2.1".parse().unwrap();
+      let now = Instant::now();
+      assert!(limiter.check(ip, now));
+      assert!(!limiter.check(ip, now + Duration::from_secs(59)));
+      assert!(limiter.check(ip, now + Duration::from_secs(60)));
+   }
+}
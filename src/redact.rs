@@ -0,0 +1,190 @@
+//! Keeping tag ids out of logs and analytics exports, for when those leave the machine. With
+//! `TWAG_REDACT_TAG_IDS=true`, every log line is rewritten as it's written, so fields, messages and
+//! request paths are all covered without any log call having to know; and tap events also get a
+//! keyed HMAC of their tag's id, which `twag_tap_events_analytics` exposes in its place.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use lazy_regex::regex;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::models::TagUid;
+use crate::security;
+
+/// Characters of an id kept at each end, enough to tell ids apart at a glance.
+const KEEP: usize = 3;
+
+#[derive(Clone)]
+pub struct Redaction {
+   key: String,
+}
+
+impl Redaction {
+   /// Disabled unless `TWAG_REDACT_TAG_IDS=true`, which then needs `TWAG_REDACT_KEY` for the HMAC.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+      let var = |name: &str| var(name).filter(|s| !s.is_empty());
+      if !var("TWAG_REDACT_TAG_IDS").is_some_and(|s| s == "true") {
+         return Ok(None);
+      }
+      let key = var("TWAG_REDACT_KEY").ok_or("TWAG_REDACT_TAG_IDS is set without TWAG_REDACT_KEY")?;
+      Ok(Some(Redaction { key }))
+   }
+
+   /// Lowercase hex HMAC-SHA256 of the id, the same for every tap of a tag.
+   pub fn tag_key(&self, id: &TagUid) -> String {
+      security::hmac_hex(self.key.as_bytes(), &[id.to_string().as_bytes()])
+   }
+}
+
+/// Shortens every 14 or 20 hex digit run to its ends, e.g. `055B88A23C1250` to `055…250`.
+pub fn redact_tag_ids(text: &str) -> Cow<'_, str> {
+   regex!(r"(?i)\b[0-9a-f]{14}(?:[0-9a-f]{6})?\b").replace_all(text, |id: &lazy_regex::Captures| {
+      let id = &id[0];
+      format!("{}…{}", &id[..KEEP], &id[id.len() - KEEP..])
+   })
+}
+
+/// Wraps the log output, redacting tag ids in each line when `enabled`.
+pub struct Redacting<M> {
+   inner: M,
+   enabled: bool,
+}
+
+impl<M> Redacting<M> {
+   pub fn new(inner: M, enabled: bool) -> Self { Redacting { inner, enabled } }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+   type Writer = RedactingWriter<M::Writer>;
+
+   fn make_writer(&'a self) -> Self::Writer {
+      RedactingWriter {
+         inner: self.inner.make_writer(),
+         enabled: self.enabled,
+      }
+   }
+}
+
+pub struct RedactingWriter<W> {
+   inner: W,
+   enabled: bool,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+   /// Each event arrives whole in one call, so no id can be split across two.
+   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      if !self.enabled {
+         return self.inner.write(buf);
+      }
+      let text = String::from_utf8_lossy(buf);
+      self.inner.write_all(redact_tag_ids(&text).as_bytes())?;
+      Ok(buf.len())
+   }
+
+   fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::sync::{Arc, Mutex};
+
+   use tracing::{info, info_span, warn};
+
+   use super::*;
+
+   #[derive(Clone, Default)]
+   struct Captured(Arc<Mutex<Vec<u8>>>);
+
+   impl Write for Captured {
+      fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+         self.0.lock().unwrap().extend_from_slice(buf);
+         Ok(buf.len())
+      }
+
+      fn flush(&mut self) -> io::Result<()> { Ok(()) }
+   }
+
+   impl Captured {
+      fn text(&self) -> String { String::from_utf8(self.0.lock().unwrap().clone()).unwrap() }
+   }
+
+   fn log_a_tap() {
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      let span = info_span!("request", method = "GET", uri = "/tag/055b88a23c1250-00002A");
+      let _entered = span.enter();
+      info!(tag_id = %id, "Redirecting tag {} to its target", id);
+      warn!("Failed to record tap for 04A1B2C3D4E5F6A7B8C9: pool timed out");
+   }
+
+   fn capture(enabled: bool, json: bool) -> String {
+      let captured = Captured::default();
+      let writer = {
+         let captured = captured.clone();
+         Redacting::new(move || captured.clone(), enabled)
+      };
+      let builder = tracing_subscriber::fmt().with_writer(writer).with_ansi(false);
+      if json {
+         tracing::subscriber::with_default(builder.json().finish(), log_a_tap);
+      } else {
+         tracing::subscriber::with_default(builder.finish(), log_a_tap);
+      }
+      captured.text()
+   }
+
+   #[test]
+   fn test_redact_tag_ids() {
+      assert_eq!(redact_tag_ids("tag 055B88A23C1250 tapped"), "tag 055…250 tapped");
+      assert_eq!(redact_tag_ids("/tag/04a1b2c3d4e5f6a7b8c9"), "/tag/04a…8c9");
+      // Not ids: too short, too long, or part of a longer word
+      for untouched in [
+         "055B88A23C125",
+         "055B88A23C1250FF",
+         "a1b2c3d4e5f67890abcdef1234567890",
+         "x055B88A23C1250",
+      ] {
+         assert_eq!(redact_tag_ids(untouched), untouched);
+      }
+   }
+
+   #[test]
+   fn test_formatted_output_has_no_ids() {
+      for json in [false, true] {
+         let output = capture(true, json);
+         assert!(!output.to_ascii_uppercase().contains("055B88A23C1250"), "{output}");
+         assert!(!output.contains("04A1B2C3D4E5F6A7B8C9"), "{output}");
+         // The message, the field, and the span's path
+         assert!(output.matches("055…250").count() >= 3, "{output}");
+         assert!(output.contains("04A…8C9"), "{output}");
+      }
+   }
+
+   #[test]
+   fn test_disabled_leaves_output_alone() {
+      let output = capture(false, false);
+      assert!(output.contains("tag_id=055B88A23C1250"), "{output}");
+      assert!(output.contains("/tag/055b88a23c1250-00002A"), "{output}");
+   }
+
+   #[test]
+   fn test_config() {
+      let vars = |pairs: &'static [(&'static str, &'static str)]| {
+         move |name: &str| pairs.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string())
+      };
+      assert!(Redaction::from_vars(vars(&[])).unwrap().is_none());
+      assert!(Redaction::from_vars(vars(&[("TWAG_REDACT_TAG_IDS", "true")])).is_err());
+      let redaction = Redaction::from_vars(vars(&[("TWAG_REDACT_TAG_IDS", "true"), ("TWAG_REDACT_KEY", "k")]))
+         .unwrap()
+         .unwrap();
+
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      let key = redaction.tag_key(&id);
+      assert_eq!(key.len(), 64);
+      assert_eq!(key, redaction.tag_key(&id));
+      assert_ne!(key, redaction.tag_key(&"04A1B2C3D4E5F6".parse().unwrap()));
+      let other_key = Redaction {
+         key: "other".to_string(),
+      };
+      assert_ne!(key, other_key.tag_key(&id));
+   }
+}
@@ -0,0 +1,92 @@
+//! Sends reads to an optional Postgres replica (`DATABASE_READ_URL`). Writes always go to the
+//! primary, and so do reads of a tag written in the last `READ_YOUR_WRITES`, so an edit is never
+//! followed by a page that predates it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::TagUid;
+use crate::pool::ScalingPool;
+
+/// Comfortably longer than the replication lag we expect to see.
+pub const READ_YOUR_WRITES: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct ReadPools {
+   primary: ScalingPool,
+   replica: Option<ScalingPool>,
+   written: Arc<Mutex<HashMap<TagUid, Instant>>>,
+}
+
+impl ReadPools {
+   /// Without a replica, every read goes to `primary` and nothing changes.
+   pub fn new(primary: ScalingPool, replica: Option<ScalingPool>) -> Self {
+      ReadPools {
+         primary,
+         replica,
+         written: Arc::default(),
+      }
+   }
+
+   pub fn replica(&self) -> Option<&ScalingPool> { self.replica.as_ref() }
+
+   /// For reads that aren't about any one tag, and can tolerate a little lag.
+   pub fn any(&self) -> &ScalingPool { self.replica.as_ref().unwrap_or(&self.primary) }
+
+   pub fn for_tag(&self, id: &TagUid) -> &ScalingPool {
+      match &self.replica {
+         Some(replica) if !self.recently_written(id, Instant::now()) => replica,
+         _ => &self.primary,
+      }
+   }
+
+   /// Records a write to `id` on the primary; call after every mutation of a tag.
+   pub fn wrote(&self, id: &TagUid) {
+      if self.replica.is_none() {
+         return;
+      }
+      let now = Instant::now();
+      let mut written = self.written.lock().unwrap();
+      written.retain(|_, at| now.duration_since(*at) < READ_YOUR_WRITES);
+      written.insert(*id, now);
+   }
+
+   fn recently_written(&self, id: &TagUid, now: Instant) -> bool {
+      let written = self.written.lock().unwrap();
+      written
+         .get(id)
+         .is_some_and(|at| now.duration_since(*at) < READ_YOUR_WRITES)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn uid(s: &str) -> TagUid { s.parse().unwrap() }
+
+   #[tokio::test]
+   async fn test_without_a_replica_writes_are_not_tracked() {
+      let pools = ReadPools::new(ScalingPool::lazy(), None);
+      pools.wrote(&uid("055B88A23C1250"));
+      assert!(pools.written.lock().unwrap().is_empty());
+   }
+
+   #[tokio::test]
+   async fn test_written_tags_read_the_primary_for_a_while() {
+      let pools = ReadPools::new(ScalingPool::lazy(), Some(ScalingPool::lazy()));
+      let (written, other) = (uid("055B88A23C1250"), uid("04A1B2C3D4E5F6"));
+      let now = Instant::now();
+
+      assert!(!pools.recently_written(&written, now));
+      pools.wrote(&written);
+      assert!(pools.recently_written(&written, now));
+      assert!(!pools.recently_written(&other, now));
+      assert!(!pools.recently_written(&written, now + READ_YOUR_WRITES));
+
+      pools.written.lock().unwrap().insert(other, now - READ_YOUR_WRITES);
+      pools.wrote(&written);
+      assert!(!pools.written.lock().unwrap().contains_key(&other));
+   }
+}
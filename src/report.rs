@@ -0,0 +1,319 @@
+//! A printable history of one tag, for handing over with the thing it's stuck to: its fields,
+//! audit trail and taps by month. An admin can share it through a signed link that stops working
+//! after a few days, so the new owner can read it without access to twag.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use sqlx::PgConnection;
+
+use crate::models::{NotionPageId, TagUid};
+use crate::security::CookieKey;
+
+/// How long a share link works, unless the admin picks otherwise.
+pub const DEFAULT_SHARE_DAYS: u32 = 7;
+pub const MAX_SHARE_DAYS: u32 = 90;
+/// Signed along with the token, so a share token can't be passed off as a cookie or the reverse.
+const TOKEN_PURPOSE: &str = "twag_report";
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+/// The `twag_tag_audit.action` that starts a new life for a tag: it was reprogrammed on purpose.
+const REASSIGNED: &str = "reprogram_accepted";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Refused {
+   #[error("Link is malformed or not signed by this server")]
+   Invalid,
+   #[error("Link was made for another tag")]
+   OtherTag,
+   #[error("Link has expired")]
+   Expired,
+}
+
+/// `{id}.{expires}.{signature}`, for `/tag/{id}/report/shared?t=`.
+pub fn share_token(key: &CookieKey, id: &TagUid, expires: i64) -> String {
+   key.sign(TOKEN_PURPOSE, &format!("{}.{}", id, expires))
+}
+
+/// When a share token for tag `id` stops working.
+pub fn check_share(key: &CookieKey, id: &TagUid, token: &str, now: i64) -> Result<i64, Refused> {
+   let value = key.verify(TOKEN_PURPOSE, token).ok_or(Refused::Invalid)?;
+   let (tag, expires) = value.split_once('.').ok_or(Refused::Invalid)?;
+   let expires: i64 = expires.parse().map_err(|_| Refused::Invalid)?;
+   if tag != id.to_string() {
+      return Err(Refused::OtherTag);
+   }
+   if now >= expires {
+      return Err(Refused::Expired);
+   }
+   Ok(expires)
+}
+
+/// When a link shared for `days` from `now` expires; out-of-range choices are clamped.
+pub fn share_expires(now: i64, days: u32) -> i64 { now + i64::from(days.clamp(1, MAX_SHARE_DAYS)) * SECS_PER_DAY }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+   /// `YYYY-MM-DD HH:MM`, UTC.
+   pub at: String,
+   pub action: String,
+   pub before: Option<String>,
+   pub after: Option<String>,
+   pub actor: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonthlyTaps {
+   /// `YYYY-MM`.
+   pub month: String,
+   pub taps: i64,
+}
+
+/// The stretch of a tag's history between two reprogrammings it was accepted through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Epoch {
+   pub from: Option<NaiveDate>,
+   /// `None` for the current epoch.
+   pub until: Option<NaiveDate>,
+   pub taps: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+   pub id: TagUid,
+   pub label: Option<String>,
+   pub kit: Option<String>,
+   pub target_url: String,
+   pub notion_page_id: Option<NotionPageId>,
+   pub created_on: Option<String>,
+   pub programmed_on: Option<String>,
+   pub expires_at: Option<String>,
+   pub deleted: bool,
+   pub audit: Vec<AuditEntry>,
+   pub months: Vec<MonthlyTaps>,
+   /// Empty unless the tag was ever reassigned.
+   pub epochs: Vec<Epoch>,
+}
+
+pub fn months(days: &[(NaiveDate, i64)]) -> Vec<MonthlyTaps> {
+   let mut by_month: BTreeMap<String, i64> = BTreeMap::new();
+   for (day, taps) in days {
+      *by_month.entry(day.format("%Y-%m").to_string()).or_default() += taps;
+   }
+   by_month
+      .into_iter()
+      .map(|(month, taps)| MonthlyTaps { month, taps })
+      .collect()
+}
+
+/// Splits the daily tap counts at each reassignment. Counts are only kept per day, so taps on the
+/// day of a reassignment are all put in the epoch it starts.
+pub fn epochs(reassigned_on: &[NaiveDate], days: &[(NaiveDate, i64)]) -> Vec<Epoch> {
+   if reassigned_on.is_empty() {
+      return Vec::new();
+   }
+   let mut boundaries = reassigned_on.to_vec();
+   boundaries.sort();
+   boundaries.dedup();
+   let starts = std::iter::once(None).chain(boundaries.iter().copied().map(Some));
+   let ends = boundaries.iter().copied().map(Some).chain(std::iter::once(None));
+   starts
+      .zip(ends)
+      .map(|(from, until)| Epoch {
+         from,
+         until,
+         taps: days
+            .iter()
+            .filter(|(day, _)| from.is_none_or(|from| *day >= from) && until.is_none_or(|until| *day < until))
+            .map(|(_, taps)| taps)
+            .sum(),
+      })
+      .collect()
+}
+
+pub async fn load(conn: &mut PgConnection, id: &TagUid) -> Result<Option<Report>, sqlx::Error> {
+   let Some(tag) = sqlx::query!(
+      r#"SELECT label, kit, target_url, notion_page_id AS "notion_page_id: NotionPageId",
+            to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS created_on,
+            to_char(programmed_at AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS programmed_on,
+            to_char(expires_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI') AS expires_at,
+            deleted_at IS NOT NULL AS "deleted!"
+         FROM twag_tags WHERE id = $1"#,
+      id as &TagUid,
+   )
+   .fetch_optional(&mut *conn)
+   .await?
+   else {
+      return Ok(None);
+   };
+
+   let audit: Vec<AuditEntry> = sqlx::query!(
+      r#"SELECT to_char(at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI') AS "at!", action, before, after, actor
+         FROM twag_tag_audit WHERE tag_id = $1 ORDER BY at, id"#,
+      id as &TagUid,
+   )
+   .fetch_all(&mut *conn)
+   .await?
+   .into_iter()
+   .map(|row| AuditEntry {
+      at: row.at,
+      action: row.action,
+      before: row.before,
+      after: row.after,
+      actor: row.actor,
+   })
+   .collect();
+
+   let days: Vec<(NaiveDate, i64)> = sqlx::query!(
+      r#"SELECT day::text AS "day!", taps FROM twag_tag_daily WHERE tag_id = $1 ORDER BY day"#,
+      id as &TagUid,
+   )
+   .fetch_all(&mut *conn)
+   .await?
+   .into_iter()
+   .filter_map(|row| Some((row.day.parse().ok()?, i64::from(row.taps))))
+   .collect();
+
+   let reassigned_on: Vec<NaiveDate> = audit
+      .iter()
+      .filter(|entry| entry.action == REASSIGNED)
+      .filter_map(|entry| entry.at.get(..10)?.parse().ok())
+      .collect();
+
+   Ok(Some(Report {
+      id: *id,
+      label: tag.label,
+      kit: tag.kit,
+      target_url: tag.target_url,
+      notion_page_id: tag.notion_page_id,
+      created_on: tag.created_on,
+      programmed_on: tag.programmed_on,
+      expires_at: tag.expires_at,
+      deleted: tag.deleted,
+      months: months(&days),
+      epochs: epochs(&reassigned_on, &days),
+      audit,
+   }))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const NOW: i64 = 1_792_152_000;
+
+   fn key(secret: &str) -> CookieKey { CookieKey::from_vars(|_| Some(secret.to_string())).unwrap().unwrap() }
+
+   fn uid(id: &str) -> TagUid { id.parse().unwrap() }
+
+   fn day(s: &str) -> NaiveDate { s.parse().unwrap() }
+
+   #[test]
+   fn test_share_links_work_until_they_expire() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let expires = share_expires(NOW, 7);
+      assert_eq!(expires, NOW + 7 * SECS_PER_DAY);
+      let token = share_token(&key, &id, expires);
+      assert_eq!(check_share(&key, &id, &token, NOW), Ok(expires));
+      assert_eq!(check_share(&key, &id, &token, expires - 1), Ok(expires));
+      assert_eq!(check_share(&key, &id, &token, expires), Err(Refused::Expired));
+      assert_eq!(check_share(&key, &id, &token, expires + 86_400), Err(Refused::Expired));
+   }
+
+   #[test]
+   fn test_share_days_are_clamped() {
+      assert_eq!(share_expires(NOW, 0), NOW + SECS_PER_DAY);
+      assert_eq!(
+         share_expires(NOW, 10_000),
+         NOW + i64::from(MAX_SHARE_DAYS) * SECS_PER_DAY
+      );
+   }
+
+   #[test]
+   fn test_tampered_share_links_are_refused() {
+      let key = key("0123456789abcdef");
+      let id = uid("055B88A23C1250");
+      let expires = share_expires(NOW, 7);
+      let token = share_token(&key, &id, expires);
+
+      let extended = token.replace(&expires.to_string(), &(expires + 86_400).to_string());
+      assert_eq!(check_share(&key, &id, &extended, NOW), Err(Refused::Invalid));
+      assert_eq!(
+         check_share(&super::key("fedcba9876543210"), &id, &token, NOW),
+         Err(Refused::Invalid)
+      );
+      // The same value signed as an arrival token
+      let arrival = key.sign("twag_arrival", &format!("055B88A23C1250.{}", expires));
+      assert_eq!(check_share(&key, &id, &arrival, NOW), Err(Refused::Invalid));
+      for garbage in ["", ".", "..", "055B88A23C1250", &"a".repeat(10_000)] {
+         assert_eq!(
+            check_share(&key, &id, garbage, NOW),
+            Err(Refused::Invalid),
+            "{garbage:?}"
+         );
+      }
+      assert_eq!(
+         check_share(&key, &uid("04A1B2C3D4E5F6"), &token, NOW),
+         Err(Refused::OtherTag)
+      );
+   }
+
+   #[test]
+   fn test_months() {
+      let days = [
+         (day("2026-08-30"), 2),
+         (day("2026-09-01"), 3),
+         (day("2026-09-15"), 4),
+         (day("2026-10-01"), 1),
+      ];
+      assert_eq!(
+         months(&days),
+         [
+            MonthlyTaps {
+               month: "2026-08".to_string(),
+               taps: 2,
+            },
+            MonthlyTaps {
+               month: "2026-09".to_string(),
+               taps: 7,
+            },
+            MonthlyTaps {
+               month: "2026-10".to_string(),
+               taps: 1,
+            },
+         ]
+      );
+      assert_eq!(months(&[]), []);
+   }
+
+   #[test]
+   fn test_epochs_split_at_reassignments() {
+      let days = [
+         (day("2026-08-30"), 2),
+         (day("2026-09-01"), 3),
+         (day("2026-09-15"), 4),
+         (day("2026-10-01"), 1),
+      ];
+      assert_eq!(epochs(&[], &days), []);
+      assert_eq!(
+         epochs(&[day("2026-09-15"), day("2026-09-01"), day("2026-09-15")], &days),
+         [
+            Epoch {
+               from: None,
+               until: Some(day("2026-09-01")),
+               taps: 2,
+            },
+            Epoch {
+               from: Some(day("2026-09-01")),
+               until: Some(day("2026-09-15")),
+               taps: 3,
+            },
+            Epoch {
+               from: Some(day("2026-09-15")),
+               until: None,
+               taps: 5,
+            },
+         ]
+      );
+   }
+}
@@ -0,0 +1,342 @@
+//! Spotting a tag that's been factory reset and rewritten, or cloned: its counter starts over near
+//! zero while the row remembers a much higher one. Revisits of an old URL also arrive with a lower
+//! counter (see `stale_redirect`), so only a drop that's both large and lands near zero counts.
+//!
+//! Such a tag waits in `needs_review` until an admin either accepts the new counter as its
+//! baseline, or marks it suspicious, which quarantines it until accepted after all. Tags can also
+//! be set to be quarantined as soon as they need review.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use sqlx::PgConnection;
+
+use crate::models::TagUid;
+
+/// The `twag_tag_audit.actor` of transitions made by a scan rather than an admin.
+pub const ACTOR_SCAN: &str = "scan";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewState {
+   Ok,
+   NeedsReview,
+   Suspicious,
+}
+
+impl ReviewState {
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         ReviewState::Ok => "ok",
+         ReviewState::NeedsReview => "needs_review",
+         ReviewState::Suspicious => "suspicious",
+      }
+   }
+}
+
+impl fmt::Display for ReviewState {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for ReviewState {
+   type Err = String;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      match s {
+         "ok" => Ok(ReviewState::Ok),
+         "needs_review" => Ok(ReviewState::NeedsReview),
+         "suspicious" => Ok(ReviewState::Suspicious),
+         other => Err(format!("Unknown review state '{}'", other)),
+      }
+   }
+}
+
+/// What makes a backwards jump a reprogram rather than a revisit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thresholds {
+   /// How far below the highest counter seen the new one must be.
+   pub min_drop: i32,
+   /// The highest counter that still counts as having started over.
+   pub max_restart: i32,
+}
+
+impl Default for Thresholds {
+   fn default() -> Self {
+      Thresholds {
+         min_drop: 100,
+         max_restart: 10,
+      }
+   }
+}
+
+impl Thresholds {
+   pub fn is_reprogram(&self, tap_count: i32, last_seen: i32) -> bool {
+      tap_count <= self.max_restart && last_seen.saturating_sub(tap_count) >= self.min_drop
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Review {
+   pub state: ReviewState,
+   /// The highest counter seen since the drop; `None` while `Ok`.
+   pub since_drop: Option<i32>,
+}
+
+impl Review {
+   pub const OK: Review = Review {
+      state: ReviewState::Ok,
+      since_drop: None,
+   };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+   pub review: Review,
+   /// As recorded in `twag_tag_audit.action`, for transitions between states.
+   pub audit: Option<&'static str>,
+   /// Replaces `last_seen_tap_count`, when a new counter is accepted.
+   pub baseline: Option<i32>,
+}
+
+impl Step {
+   fn stay(review: Review) -> Step {
+      Step {
+         review,
+         audit: None,
+         baseline: None,
+      }
+   }
+}
+
+/// A scan carrying `tap_count`, of a tag whose highest counter so far is `last_seen`.
+pub fn scan(review: Review, tap_count: Option<i32>, last_seen: Option<i32>, thresholds: &Thresholds) -> Step {
+   let Some(tap_count) = tap_count else {
+      return Step::stay(review);
+   };
+   match review.state {
+      ReviewState::Ok => match last_seen {
+         Some(last_seen) if thresholds.is_reprogram(tap_count, last_seen) => Step {
+            review: Review {
+               state: ReviewState::NeedsReview,
+               since_drop: Some(tap_count),
+            },
+            audit: Some("reprogram_detected"),
+            baseline: None,
+         },
+         _ => Step::stay(review),
+      },
+      ReviewState::NeedsReview | ReviewState::Suspicious => Step::stay(Review {
+         since_drop: Some(review.since_drop.map_or(tap_count, |seen| seen.max(tap_count))),
+         ..review
+      }),
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+   /// "I reprogrammed it": the counter since the drop becomes the baseline.
+   Accept,
+   /// "Suspicious": quarantined until accepted.
+   Suspicious,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotUnderReview;
+
+/// An admin's decision on a tag under review.
+pub fn decide(review: Review, decision: Decision) -> Result<Step, NotUnderReview> {
+   match (review.state, decision) {
+      (ReviewState::Ok, _) => Err(NotUnderReview),
+      (ReviewState::NeedsReview | ReviewState::Suspicious, Decision::Accept) => Ok(Step {
+         review: Review::OK,
+         audit: Some("reprogram_accepted"),
+         baseline: review.since_drop,
+      }),
+      (ReviewState::NeedsReview, Decision::Suspicious) => Ok(Step {
+         review: Review {
+            state: ReviewState::Suspicious,
+            ..review
+         },
+         audit: Some("reprogram_rejected"),
+         baseline: None,
+      }),
+      (ReviewState::Suspicious, Decision::Suspicious) => Ok(Step::stay(review)),
+   }
+}
+
+/// Whether scans get the quarantine page instead of being answered.
+pub fn quarantined(state: ReviewState, quarantine_on_review: bool) -> bool {
+   match state {
+      ReviewState::Ok => false,
+      ReviewState::NeedsReview => quarantine_on_review,
+      ReviewState::Suspicious => true,
+   }
+}
+
+/// Writes a step taken from `from`, unless another has already moved the tag on. Returns whether
+/// it was written.
+pub async fn record(
+   conn: &mut PgConnection,
+   id: TagUid,
+   from: ReviewState,
+   step: &Step,
+   actor: &str,
+) -> Result<bool, sqlx::Error> {
+   let updated = sqlx::query!(
+      r#"UPDATE twag_tags SET review_state = $3, review_tap_count = $4,
+            last_seen_tap_count = COALESCE($5, last_seen_tap_count)
+         WHERE id = $1 AND review_state = $2"#,
+      id as TagUid,
+      from.as_str(),
+      step.review.state.as_str(),
+      step.review.since_drop,
+      step.baseline,
+   )
+   .execute(&mut *conn)
+   .await?;
+   if updated.rows_affected() == 0 {
+      return Ok(false);
+   }
+   if let Some(action) = step.audit {
+      sqlx::query!(
+         r#"INSERT INTO twag_tag_audit (tag_id, action, before, after, actor) VALUES ($1, $2, $3, $4, $5)"#,
+         id as TagUid,
+         action,
+         from.as_str(),
+         step.review.state.as_str(),
+         actor,
+      )
+      .execute(&mut *conn)
+      .await?;
+   }
+   Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const STATES: [ReviewState; 3] = [ReviewState::Ok, ReviewState::NeedsReview, ReviewState::Suspicious];
+
+   fn thresholds() -> Thresholds { Thresholds::default() }
+
+   fn under(state: ReviewState, since_drop: i32) -> Review {
+      Review {
+         state,
+         since_drop: Some(since_drop),
+      }
+   }
+
+   #[test]
+   fn test_states_round_trip() {
+      for state in STATES {
+         assert_eq!(state.as_str().parse::<ReviewState>(), Ok(state));
+      }
+      assert!("quarantined".parse::<ReviewState>().is_err());
+   }
+
+   #[test]
+   fn test_thresholds() {
+      let t = thresholds();
+      assert!(t.is_reprogram(1, 500));
+      assert!(t.is_reprogram(10, 110));
+      // Not far enough below
+      assert!(!t.is_reprogram(10, 109));
+      // Far below, but not near zero: a revisit of an old URL
+      assert!(!t.is_reprogram(11, 5000));
+      // Forwards
+      assert!(!t.is_reprogram(600, 500));
+      assert!(!Thresholds { min_drop: 1000, ..t }.is_reprogram(1, 500));
+      assert!(Thresholds { max_restart: 50, ..t }.is_reprogram(50, 500));
+   }
+
+   #[test]
+   fn test_scan_detects_a_drop_once() {
+      let detected = scan(Review::OK, Some(3), Some(4000), &thresholds());
+      assert_eq!(
+         detected,
+         Step {
+            review: under(ReviewState::NeedsReview, 3),
+            audit: Some("reprogram_detected"),
+            baseline: None,
+         }
+      );
+
+      // Later scans only raise the counter since the drop, without another audit row
+      let next = scan(detected.review, Some(4), Some(4000), &thresholds());
+      assert_eq!(next, Step::stay(under(ReviewState::NeedsReview, 4)));
+      let older = scan(next.review, Some(2), Some(4000), &thresholds());
+      assert_eq!(older, Step::stay(under(ReviewState::NeedsReview, 4)));
+   }
+
+   #[test]
+   fn test_scan_leaves_ordinary_taps_alone() {
+      for (tap_count, last_seen) in [
+         (Some(4001), Some(4000)),
+         (Some(3990), Some(4000)),
+         (Some(3), None),
+         (None, Some(4000)),
+      ] {
+         assert_eq!(
+            scan(Review::OK, tap_count, last_seen, &thresholds()),
+            Step::stay(Review::OK),
+            "{tap_count:?} after {last_seen:?}"
+         );
+      }
+   }
+
+   #[test]
+   fn test_scan_under_review_never_transitions() {
+      for state in [ReviewState::NeedsReview, ReviewState::Suspicious] {
+         for (tap_count, since_drop) in [(Some(1), 1), (Some(9000), 9000), (None, 5)] {
+            let step = scan(under(state, 5), tap_count, Some(10_000), &thresholds());
+            assert_eq!(step.review, under(state, since_drop.max(5)));
+            assert_eq!((step.audit, step.baseline), (None, None));
+         }
+      }
+   }
+
+   #[test]
+   fn test_decide_every_state_and_decision() {
+      for decision in [Decision::Accept, Decision::Suspicious] {
+         assert_eq!(decide(Review::OK, decision), Err(NotUnderReview));
+      }
+
+      for state in [ReviewState::NeedsReview, ReviewState::Suspicious] {
+         assert_eq!(
+            decide(under(state, 7), Decision::Accept),
+            Ok(Step {
+               review: Review::OK,
+               audit: Some("reprogram_accepted"),
+               baseline: Some(7),
+            })
+         );
+      }
+
+      assert_eq!(
+         decide(under(ReviewState::NeedsReview, 7), Decision::Suspicious),
+         Ok(Step {
+            review: under(ReviewState::Suspicious, 7),
+            audit: Some("reprogram_rejected"),
+            baseline: None,
+         })
+      );
+      assert_eq!(
+         decide(under(ReviewState::Suspicious, 7), Decision::Suspicious),
+         Ok(Step::stay(under(ReviewState::Suspicious, 7)))
+      );
+   }
+
+   #[test]
+   fn test_quarantined() {
+      for quarantine_on_review in [false, true] {
+         assert!(!quarantined(ReviewState::Ok, quarantine_on_review));
+         assert!(quarantined(ReviewState::Suspicious, quarantine_on_review));
+         assert_eq!(
+            quarantined(ReviewState::NeedsReview, quarantine_on_review),
+            quarantine_on_review
+         );
+      }
+   }
+}
@@ -0,0 +1,194 @@
+use axum::{
+   body::{self, Body},
+   extract::{Request, State},
+   http::{header, HeaderMap, StatusCode},
+   middleware::Next,
+   response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::pool::ScalingPool;
+
+/// Mutating requests larger than this are refused outright; the forms that reach these routes
+/// are a few hundred bytes.
+const MAX_BUFFERED_BYTES: usize = 64 * 1024;
+/// Each stored field is capped at this many bytes.
+const MAX_STORED_BYTES: usize = 8 * 1024;
+
+/// The parts of a create request worth keeping for later forensics. Only these fields are ever
+/// read from the request, so credentials in `Authorization`, `Cookie` and the like can't leak
+/// into the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawSubmission {
+   pub method: String,
+   pub path: String,
+   pub content_type: Option<String>,
+   pub user_agent: Option<String>,
+   /// The query string, url-decoded into one `key=value` per line.
+   pub query: String,
+   /// The body as received, url-decoded the same way when it's a form.
+   pub body: String,
+   pub truncated: bool,
+}
+
+/// Truncates to at most `max` bytes without splitting a UTF-8 sequence.
+fn truncate(s: &str, max: usize) -> (&str, bool) {
+   if s.len() <= max {
+      return (s, false);
+   }
+   let mut end = max;
+   while !s.is_char_boundary(end) {
+      end -= 1;
+   }
+   (&s[..end], true)
+}
+
+/// Decodes `application/x-www-form-urlencoded` data into one `key=value` line per pair, keeping
+/// repeated and empty keys exactly as sent.
+pub fn decode_pairs(raw: &str) -> String {
+   url::form_urlencoded::parse(raw.as_bytes())
+      .map(|(key, value)| format!("{}={}", key, value))
+      .collect::<Vec<_>>()
+      .join("\n")
+}
+
+/// The last value of `name` in decoded pairs, matching how the `Form` and `Query` extractors
+/// resolve repeated keys.
+pub fn field<'a>(decoded: &'a str, name: &str) -> Option<&'a str> {
+   decoded
+      .lines()
+      .filter_map(|line| line.split_once('='))
+      .filter(|(key, _)| *key == name)
+      .map(|(_, value)| value)
+      .last()
+}
+
+pub fn capture(method: &str, path: &str, query: Option<&str>, headers: &HeaderMap, body: &[u8]) -> RawSubmission {
+   let header = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+   let content_type = header(header::CONTENT_TYPE);
+   let body = String::from_utf8_lossy(body);
+   let body = match &content_type {
+      Some(ct) if ct.starts_with("application/x-www-form-urlencoded") => decode_pairs(&body),
+      _ => body.into_owned(),
+   };
+   let query = decode_pairs(query.unwrap_or_default());
+
+   let (body, body_truncated) = truncate(&body, MAX_STORED_BYTES);
+   let (query, query_truncated) = truncate(&query, MAX_STORED_BYTES);
+   let user_agent = header(header::USER_AGENT).map(|ua| truncate(&ua, 512).0.to_string());
+   RawSubmission {
+      method: method.to_string(),
+      path: path.to_string(),
+      content_type,
+      user_agent,
+      query: query.to_string(),
+      body: body.to_string(),
+      truncated: body_truncated || query_truncated,
+   }
+}
+
+/// Buffers the body of a mutating request so the raw submission can be logged before the `Form`
+/// extractor consumes it. Logging failures are reported but never fail the request.
+pub async fn log_submission(State(pool): State<ScalingPool>, request: Request, next: Next) -> Response {
+   let (parts, body) = request.into_parts();
+   let bytes = match body::to_bytes(body, MAX_BUFFERED_BYTES).await {
+      Ok(bytes) => bytes,
+      Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+   };
+   let submission = capture(
+      parts.method.as_str(),
+      parts.uri.path(),
+      parts.uri.query(),
+      &parts.headers,
+      &bytes,
+   );
+   let tag_id = field(&submission.query, "id").map(str::to_string);
+
+   let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+
+   let result = sqlx::query!(
+      r#"INSERT INTO twag_request_log
+            (tag_id, method, path, content_type, user_agent, query, body, truncated, status)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+      tag_id,
+      submission.method,
+      submission.path,
+      submission.content_type,
+      submission.user_agent,
+      submission.query,
+      submission.body,
+      submission.truncated,
+      response.status().as_u16() as i16,
+   )
+   .execute(&pool.get())
+   .await;
+   if let Err(e) = result {
+      warn!("Failed to log raw submission: {:?}", e);
+   }
+   response
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_decode_pairs_keeps_what_was_sent() {
+      assert_eq!(
+         decode_pairs("target_url=https%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2&tap_count="),
+         "target_url=https://example.com/?a=1&b=2\ntap_count="
+      );
+      assert_eq!(decode_pairs("a=1&a=2+3"), "a=1\na=2 3");
+      assert_eq!(decode_pairs(""), "");
+   }
+
+   #[test]
+   fn test_field_takes_the_last_value() {
+      let decoded = decode_pairs("target_url=first&id=055B88A23C1250&target_url=second");
+      assert_eq!(field(&decoded, "target_url"), Some("second"));
+      assert_eq!(field(&decoded, "id"), Some("055B88A23C1250"));
+      assert_eq!(field(&decoded, "notion_page"), None);
+   }
+
+   #[test]
+   fn test_capture_never_keeps_credentials() {
+      let mut headers = HeaderMap::new();
+      headers.insert(header::AUTHORIZATION, "Bearer hunter2".parse().unwrap());
+      headers.insert(header::COOKIE, "session=hunter2".parse().unwrap());
+      headers.insert(
+         header::CONTENT_TYPE,
+         "application/x-www-form-urlencoded".parse().unwrap(),
+      );
+      headers.insert(header::USER_AGENT, "provision.sh/1.0".parse().unwrap());
+      let submission = capture(
+         "POST",
+         "/tag/create",
+         Some("id=055B88A23C1250"),
+         &headers,
+         b"target_url=https%3A%2F%2Fexample.com",
+      );
+
+      assert_eq!(submission.query, "id=055B88A23C1250");
+      assert_eq!(submission.body, "target_url=https://example.com");
+      assert_eq!(submission.user_agent.as_deref(), Some("provision.sh/1.0"));
+      assert!(!format!("{:?}", submission).contains("hunter2"));
+   }
+
+   #[test]
+   fn test_capture_caps_stored_size() {
+      let body = format!("target_url={}", "é".repeat(MAX_STORED_BYTES));
+      let submission = capture("POST", "/tag/create", None, &HeaderMap::new(), body.as_bytes());
+      assert!(submission.truncated);
+      assert!(submission.body.len() <= MAX_STORED_BYTES);
+      assert!(submission.body.ends_with('é'));
+   }
+
+   #[test]
+   fn test_capture_keeps_non_form_bodies_verbatim() {
+      let mut headers = HeaderMap::new();
+      headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+      let submission = capture("POST", "/tag/create", None, &headers, br#"{"target_url":"a%20b"}"#);
+      assert_eq!(submission.body, r#"{"target_url":"a%20b"}"#);
+      assert!(!submission.truncated);
+   }
+}
@@ -0,0 +1,940 @@
+//! How a scan of a tag is answered, decided apart from where tags are stored or how the answer is
+//! sent. The server's scan handler and `twag::resolve` both come down to `decide`, so the two
+//! can't drift apart.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+
+use crate::geo::{self, GeoTarget};
+use crate::i18n;
+use crate::maintenance::{self, Maintenance};
+use crate::models::{LanguageTag, TagSlug, TagUid};
+use crate::reprogram::{self, Review, Step, Thresholds};
+use crate::schedule::{self, Window};
+use crate::stale_redirect::{self, StaleRedirect};
+use crate::taps::{self, ResolutionPath, Served};
+
+/// What resolution reads of a stored tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StoredTag {
+   pub target_url: String,
+   pub deleted: bool,
+   /// Off for a decommissioned tag, which answers as gone but keeps its history.
+   pub enabled: bool,
+   /// The first instant the tag no longer resolves. An expiry set as a day is its midnight, UTC.
+   pub expires_at: Option<DateTime<Utc>>,
+   /// Until this instant the tag answers with a page saying when it will, though its scans are
+   /// counted.
+   pub activates_at: Option<DateTime<Utc>>,
+   pub redirect_kind: RedirectKind,
+   /// Unix time of the last permanent redirect served, if any.
+   pub served_permanent_until: Option<i64>,
+   pub last_seen_tap_count: Option<i32>,
+   pub maintenance: bool,
+   pub stateful: bool,
+   /// Whether the tag holds a contact card.
+   pub contact: bool,
+   pub age_gate: bool,
+   pub age_gate_text: Option<String>,
+   pub review: Review,
+   pub quarantine_on_review: bool,
+   pub lang_targets: Vec<(LanguageTag, String)>,
+   /// Per-country targets, first match wins, each with its comma-separated country codes.
+   pub geo_targets: Vec<(String, String)>,
+   /// Its kit's windows that haven't closed yet.
+   pub kit_schedule: Vec<Window>,
+   pub ignore_kit_schedule: bool,
+}
+
+impl StoredTag {
+   /// A tag that only redirects to `target_url`, as newly created ones do.
+   pub fn new(target_url: impl Into<String>) -> Self {
+      StoredTag {
+         target_url: target_url.into(),
+         deleted: false,
+         enabled: true,
+         expires_at: None,
+         activates_at: None,
+         redirect_kind: RedirectKind::Temporary,
+         served_permanent_until: None,
+         last_seen_tap_count: None,
+         maintenance: false,
+         stateful: false,
+         contact: false,
+         age_gate: false,
+         age_gate_text: None,
+         review: Review::OK,
+         quarantine_on_review: false,
+         lang_targets: Vec::new(),
+         geo_targets: Vec::new(),
+         kit_schedule: Vec::new(),
+         ignore_kit_schedule: false,
+      }
+   }
+
+   /// Whether `now` is past its expiry, whether or not it's deleted.
+   pub fn expired(&self, now: DateTime<Utc>) -> bool { self.expires_at.is_some_and(|at| at <= now) }
+
+   /// When it activates, if that's still after `now`.
+   pub fn pending(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> { self.activates_at.filter(|at| *at > now) }
+}
+
+/// Which redirect a tag answers with, as `twag_tags.redirect_kind` stores it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RedirectKind {
+   /// 308, which clients may cache for good; see `stale_redirect` for a tag moved off it.
+   Permanent,
+   /// 307.
+   #[default]
+   Temporary,
+   /// 303; the client follows it with a GET, whatever it sent.
+   SeeOther,
+}
+
+impl RedirectKind {
+   pub const ALL: [RedirectKind; 3] = [RedirectKind::Permanent, RedirectKind::Temporary, RedirectKind::SeeOther];
+
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         RedirectKind::Permanent => "permanent",
+         RedirectKind::Temporary => "temporary",
+         RedirectKind::SeeOther => "see_other",
+      }
+   }
+
+   pub fn name(&self) -> &'static str {
+      match self {
+         RedirectKind::Permanent => "Permanent (308)",
+         RedirectKind::Temporary => "Temporary (307)",
+         RedirectKind::SeeOther => "See other (303)",
+      }
+   }
+
+   pub fn status(&self) -> u16 {
+      match self {
+         RedirectKind::Permanent => 308,
+         RedirectKind::Temporary => 307,
+         RedirectKind::SeeOther => 303,
+      }
+   }
+
+   /// From a form field, where blank means temporary.
+   pub fn from_input(s: &str) -> Result<RedirectKind, String> {
+      match s.trim() {
+         "" => Ok(RedirectKind::Temporary),
+         s => s.parse(),
+      }
+   }
+}
+
+impl fmt::Display for RedirectKind {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for RedirectKind {
+   type Err = String;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      RedirectKind::ALL
+         .into_iter()
+         .find(|kind| kind.as_str() == s)
+         .ok_or_else(|| {
+            let known: Vec<&str> = RedirectKind::ALL.iter().map(RedirectKind::as_str).collect();
+            format!("Unknown redirect kind '{}'; expected one of {}", s, known.join(", "))
+         })
+   }
+}
+
+/// Where `resolve` finds tags.
+pub trait TagStore {
+   fn find(&self, id: &TagUid) -> Option<StoredTag>;
+}
+
+impl TagStore for HashMap<TagUid, StoredTag> {
+   fn find(&self, id: &TagUid) -> Option<StoredTag> { self.get(id).cloned() }
+}
+
+/// The deployment-wide settings resolution depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Policy<'a> {
+   /// Puts every tag into maintenance, redirecting here.
+   pub maintenance_target_url: Option<&'a str>,
+   pub flush_stale_redirects: bool,
+   pub reprogram: Thresholds,
+}
+
+impl Default for Policy<'_> {
+   fn default() -> Self {
+      Policy {
+         maintenance_target_url: None,
+         flush_stale_redirects: false,
+         reprogram: Thresholds::default(),
+      }
+   }
+}
+
+/// What's known about the scan itself.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct ResolveContext<'a> {
+   pub now: DateTime<Utc>,
+   /// An explicit language choice, which wins over `accept_language`.
+   pub lang: Option<&'a str>,
+   /// The client's `Accept-Language`.
+   pub accept_language: Option<&'a str>,
+   /// The client's ISO country code, if known.
+   pub country: Option<&'a str>,
+   /// The client's `Cookie` headers.
+   pub cookies: &'a [&'a str],
+   /// Whether the client has confirmed its age, for age-gated tags.
+   pub age_confirmed: bool,
+   pub policy: Policy<'a>,
+}
+
+impl ResolveContext<'_> {
+   pub fn new(now: DateTime<Utc>) -> Self {
+      ResolveContext {
+         now,
+         lang: None,
+         accept_language: None,
+         country: None,
+         cookies: &[],
+         age_confirmed: false,
+         policy: Policy::default(),
+      }
+   }
+}
+
+/// A page shown before the tag's own answer, if ever.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Gate {
+   Age {
+      text: Option<String>,
+   },
+   /// Held pending review; see `reprogram`.
+   Quarantine,
+}
+
+/// A tag answered with a page of twag's own rather than a redirect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Page {
+   Maintenance,
+   /// Checked out or in; `url` is the target the page links on to.
+   Stateful {
+      url: String,
+   },
+   Contact,
+   /// Clears a permanent redirect the client may have cached, then continues to `url`.
+   Flush {
+      url: String,
+   },
+   /// Not active until `activates_at`; see `StoredTag::activates_at`.
+   Pending {
+      activates_at: DateTime<Utc>,
+   },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ResolveOutcome {
+   /// Not a tag id at all.
+   Invalid,
+   /// A valid id, not yet stored.
+   NotFound {
+      id: TagUid,
+      tap_count: Option<i32>,
+   },
+   /// Deleted, or past its expiry.
+   Expired,
+   /// Decommissioned; see `StoredTag::enabled`.
+   Disabled,
+   /// Asked for a contact card the tag doesn't have.
+   NoContact,
+   Gated {
+      kind: Gate,
+   },
+   Redirect {
+      url: String,
+      status: u16,
+   },
+   Page {
+      page: Page,
+      status: u16,
+   },
+}
+
+/// Everything the server needs to act on a scan besides its outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+   pub outcome: ResolveOutcome,
+   /// The scan's new review state, for found tags.
+   pub review: Option<Step>,
+   /// As recorded; `None` for outcomes that aren't recorded as taps.
+   pub served: Option<Served>,
+   pub lang: Option<LanguageTag>,
+   pub during_maintenance: bool,
+   /// Whether a permanent redirect was sent, which the client may cache.
+   pub served_permanent: bool,
+   /// Whether the failover cache may answer for the tag.
+   pub cacheable: bool,
+   /// Whether the answer depends on `Accept-Language`.
+   pub vary_language: bool,
+}
+
+impl Decision {
+   /// Sends the scan to `url` in place of the target it was redirected to, as when the server finds
+   /// that target down. Always temporarily, so the scan after the target is back goes to it again.
+   pub fn fall_back(&mut self, url: &str) {
+      let status = RedirectKind::Temporary.status();
+      self.outcome = ResolveOutcome::Redirect {
+         url: url.to_string(),
+         status,
+      };
+      self.served_permanent = false;
+      if let Some(served) = &mut self.served {
+         served.path = ResolutionPath::Fallback;
+         served.target_url = Some(url.to_string());
+         served.status = status;
+      }
+   }
+
+   fn unanswered(outcome: ResolveOutcome, review: Option<Step>) -> Decision {
+      Decision {
+         outcome,
+         review,
+         served: None,
+         lang: None,
+         during_maintenance: false,
+         served_permanent: false,
+         cacheable: false,
+         vary_language: false,
+      }
+   }
+}
+
+/// Decides how a scan of `slug` is answered, given its tag as stored.
+pub fn decide(tag: Option<&StoredTag>, slug: &TagSlug, ctx: &ResolveContext) -> Decision {
+   // At most six hex digits, so this always fits
+   let tap_count = slug.tap_count.map(|c| c as i32);
+   let Some(tag) = tag else {
+      return Decision::unanswered(ResolveOutcome::NotFound { id: slug.id, tap_count }, None);
+   };
+   if tag.deleted || tag.expired(ctx.now) {
+      return Decision::unanswered(ResolveOutcome::Expired, None);
+   }
+   if !tag.enabled {
+      return Decision::unanswered(ResolveOutcome::Disabled, None);
+   }
+
+   let step = reprogram::scan(tag.review, tap_count, tag.last_seen_tap_count, &ctx.policy.reprogram);
+   if let Some(activates_at) = tag.pending(ctx.now) {
+      // Counted, so early scans show up in its stats, but never cached, so it activates on time
+      let served = Served {
+         path: ResolutionPath::Pending,
+         target_url: None,
+         status: 200,
+      };
+      return Decision {
+         outcome: ResolveOutcome::Page {
+            page: Page::Pending { activates_at },
+            status: served.status,
+         },
+         review: Some(step),
+         served: Some(served),
+         lang: None,
+         during_maintenance: false,
+         served_permanent: false,
+         cacheable: false,
+         vary_language: false,
+      };
+   }
+   let quarantined = reprogram::quarantined(step.review.state, tag.quarantine_on_review);
+   if tag.age_gate && !quarantined && !ctx.age_confirmed {
+      let gate = Gate::Age {
+         text: tag.age_gate_text.clone(),
+      };
+      return Decision::unanswered(ResolveOutcome::Gated { kind: gate }, Some(step));
+   }
+   if slug.vcf && !tag.contact {
+      return Decision::unanswered(ResolveOutcome::NoContact, Some(step));
+   }
+
+   let available: Vec<LanguageTag> = tag.lang_targets.iter().map(|(lang, _)| lang.clone()).collect();
+   let ranges = match ctx.lang.filter(|l| !l.is_empty()) {
+      Some(lang) => vec![lang.to_ascii_lowercase()],
+      None => ctx.accept_language.map(i18n::parse_accept_language).unwrap_or_default(),
+   };
+   let lang = i18n::negotiate(&ranges, &available).cloned();
+   let language_target = tag
+      .lang_targets
+      .iter()
+      .find(|(l, _)| Some(l) == lang.as_ref())
+      .map(|(_, target_url)| target_url.as_str());
+   let geo_targets: Vec<GeoTarget> = tag
+      .geo_targets
+      .iter()
+      .map(|(countries, target_url)| GeoTarget { countries, target_url })
+      .collect();
+   let geo_target = ctx.country.and_then(|country| geo::target_for(&geo_targets, country));
+   // The kit's schedule wins over the tag's own targets, unless the tag opted out
+   let kit_schedule: &[Window] = if tag.ignore_kit_schedule {
+      &[]
+   } else {
+      &tag.kit_schedule
+   };
+   let scheduled_target = schedule::open(kit_schedule, ctx.now).map(|window| window.target_url.as_str());
+   let target_url = scheduled_target
+      .or(geo_target)
+      .or(language_target)
+      .unwrap_or(&tag.target_url);
+   // A permanent redirect cached before a window opens would skip it, and one cached during it
+   // would outlast it
+   let redirect_kind = match tag.redirect_kind {
+      RedirectKind::Permanent if !kit_schedule.is_empty() => RedirectKind::Temporary,
+      kind => kind,
+   };
+   let permanent = redirect_kind == RedirectKind::Permanent;
+
+   let maintenance = maintenance::resolve(ctx.policy.maintenance_target_url, tag.maintenance);
+   let cookie_name = stale_redirect::cookie_name(&slug.id);
+   let stale = StaleRedirect {
+      permanent_now: permanent,
+      served_permanent_until: tag.served_permanent_until,
+      last_seen_tap_count: tag.last_seen_tap_count,
+      tap_count,
+      flushed_cookie: ctx
+         .cookies
+         .iter()
+         .find_map(|header| stale_redirect::find_cookie(header, &cookie_name)),
+   };
+   let flush = ctx.policy.flush_stale_redirects && stale_redirect::should_flush(&stale);
+   let served = taps::Resolution {
+      quarantined,
+      maintenance,
+      stateful: tag.stateful,
+      contact: tag.contact,
+      flush,
+      base_target: &tag.target_url,
+      language_target,
+      geo_target,
+      scheduled_target,
+      redirect_kind,
+   }
+   .served();
+
+   let page = |page| ResolveOutcome::Page {
+      page,
+      status: served.status,
+   };
+   let outcome = match (served.path, maintenance) {
+      (ResolutionPath::Quarantine, _) => ResolveOutcome::Gated { kind: Gate::Quarantine },
+      (ResolutionPath::Maintenance, Some(Maintenance::Redirect(url))) => ResolveOutcome::Redirect {
+         url: url.to_string(),
+         status: served.status,
+      },
+      (ResolutionPath::Maintenance, _) => page(Page::Maintenance),
+      (ResolutionPath::Stateful, _) => page(Page::Stateful {
+         url: target_url.to_string(),
+      }),
+      (ResolutionPath::Contact, _) => page(Page::Contact),
+      (ResolutionPath::Flush, _) => page(Page::Flush {
+         url: target_url.to_string(),
+      }),
+      // Pending tags were answered above, and fallbacks are only chosen by the server afterwards
+      (
+         ResolutionPath::Direct
+         | ResolutionPath::Language
+         | ResolutionPath::Geo
+         | ResolutionPath::Schedule
+         | ResolutionPath::CacheStale
+         | ResolutionPath::Pending
+         | ResolutionPath::Fallback,
+         _,
+      ) => ResolveOutcome::Redirect {
+         url: target_url.to_string(),
+         status: served.status,
+      },
+   };
+
+   Decision {
+      outcome,
+      review: Some(step),
+      lang,
+      during_maintenance: maintenance.is_some(),
+      served_permanent: !tag.contact && permanent && !tag.stateful && maintenance.is_none(),
+      // A gated tag must never be answered from the cache, which would skip the gate, and the cache
+      // only holds the base target, not a window's
+      cacheable: !tag.contact && !tag.stateful && !tag.age_gate && !quarantined && scheduled_target.is_none(),
+      vary_language: !tag.lang_targets.is_empty(),
+      served: Some(served),
+   }
+}
+
+/// Resolves a scan of `slug`, a tag id as in a scan URL's last path segment.
+pub fn resolve(store: &impl TagStore, slug: &str, ctx: ResolveContext) -> ResolveOutcome {
+   let Ok(slug) = slug.parse::<TagSlug>() else {
+      return ResolveOutcome::Invalid;
+   };
+   decide(store.find(&slug.id).as_ref(), &slug, &ctx).outcome
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::reprogram::ReviewState;
+
+   const ID: &str = "04A1B2C3D4E5F6";
+   const BASE: &str = "https://example.com/";
+
+   fn now() -> DateTime<Utc> { "2026-10-16T12:00:00Z".parse().unwrap() }
+
+   fn store(tag: StoredTag) -> HashMap<TagUid, StoredTag> { HashMap::from([(ID.parse().unwrap(), tag)]) }
+
+   fn redirect(url: &str, status: u16) -> ResolveOutcome {
+      ResolveOutcome::Redirect {
+         url: url.to_string(),
+         status,
+      }
+   }
+
+   #[test]
+   fn test_redirects() {
+      let tags = store(StoredTag::new(BASE));
+      assert_eq!(resolve(&tags, ID, ResolveContext::new(now())), redirect(BASE, 307));
+
+      for (redirect_kind, status) in [
+         (RedirectKind::Permanent, 308),
+         (RedirectKind::Temporary, 307),
+         (RedirectKind::SeeOther, 303),
+      ] {
+         let tags = store(StoredTag {
+            redirect_kind,
+            ..StoredTag::new(BASE)
+         });
+         assert_eq!(
+            resolve(&tags, ID, ResolveContext::new(now())),
+            redirect(BASE, status),
+            "{redirect_kind}"
+         );
+      }
+   }
+
+   #[test]
+   fn test_redirect_kinds() {
+      for kind in RedirectKind::ALL {
+         assert_eq!(kind.as_str().parse::<RedirectKind>(), Ok(kind));
+      }
+      assert_eq!(RedirectKind::from_input(""), Ok(RedirectKind::Temporary));
+      assert_eq!(RedirectKind::from_input(" see_other "), Ok(RedirectKind::SeeOther));
+      assert!(RedirectKind::from_input("found").is_err());
+   }
+
+   #[test]
+   fn test_invalid_and_unknown_slugs() {
+      let tags = store(StoredTag::new(BASE));
+      assert_eq!(
+         resolve(&tags, "not-a-tag", ResolveContext::new(now())),
+         ResolveOutcome::Invalid
+      );
+      assert_eq!(
+         resolve(&tags, "04A1B2C3D4E5F7x00000F", ResolveContext::new(now())),
+         ResolveOutcome::NotFound {
+            id: "04A1B2C3D4E5F7".parse().unwrap(),
+            tap_count: Some(15),
+         }
+      );
+   }
+
+   #[test]
+   fn test_expiry_uses_the_context_time() {
+      let tags = store(StoredTag {
+         expires_at: Some("2026-10-17T00:00:00Z".parse().unwrap()),
+         ..StoredTag::new(BASE)
+      });
+      assert_eq!(resolve(&tags, ID, ResolveContext::new(now())), redirect(BASE, 307));
+      let tomorrow = ResolveContext::new("2026-10-17T00:00:00Z".parse().unwrap());
+      assert_eq!(resolve(&tags, ID, tomorrow), ResolveOutcome::Expired);
+
+      let deleted = store(StoredTag {
+         deleted: true,
+         ..StoredTag::new(BASE)
+      });
+      assert_eq!(
+         resolve(&deleted, ID, ResolveContext::new(now())),
+         ResolveOutcome::Expired
+      );
+   }
+
+   #[test]
+   fn test_expiry_time_is_to_the_second() {
+      let expiring = StoredTag {
+         expires_at: Some("2026-10-16T18:00:00Z".parse().unwrap()),
+         ..StoredTag::new(BASE)
+      };
+      let tags = store(expiring.clone());
+      let before = ResolveContext::new("2026-10-16T17:59:59Z".parse().unwrap());
+      assert_eq!(resolve(&tags, ID, before), redirect(BASE, 307));
+      let at = ResolveContext::new("2026-10-16T18:00:00Z".parse().unwrap());
+      assert_eq!(resolve(&tags, ID, at), ResolveOutcome::Expired);
+
+      // Moving it later brings the tag back
+      let bumped = store(StoredTag {
+         expires_at: Some("2026-10-17T18:00:00Z".parse().unwrap()),
+         ..expiring
+      });
+      assert_eq!(resolve(&bumped, ID, at), redirect(BASE, 307));
+   }
+
+   #[test]
+   fn test_pending_tags_are_counted_until_they_activate() {
+      let pending = StoredTag {
+         activates_at: Some("2026-10-16T18:00:00Z".parse().unwrap()),
+         ..StoredTag::new(BASE)
+      };
+      let slug: TagSlug = ID.parse().unwrap();
+      let decision = decide(Some(&pending), &slug, &ResolveContext::new(now()));
+      assert_eq!(
+         decision.outcome,
+         ResolveOutcome::Page {
+            page: Page::Pending {
+               activates_at: "2026-10-16T18:00:00Z".parse().unwrap(),
+            },
+            status: 200,
+         }
+      );
+      assert_eq!(decision.served.map(|served| served.path), Some(ResolutionPath::Pending));
+      assert!(!decision.cacheable);
+
+      // From the instant itself, and for a tag with none, it's as any other tag
+      let tags = store(pending);
+      let at = ResolveContext::new("2026-10-16T18:00:00Z".parse().unwrap());
+      assert_eq!(resolve(&tags, ID, at), redirect(BASE, 307));
+      assert_eq!(
+         resolve(&store(StoredTag::new(BASE)), ID, ResolveContext::new(now())),
+         redirect(BASE, 307)
+      );
+   }
+
+   #[test]
+   fn test_disabled_tags_answer_as_gone_until_enabled() {
+      let disabled = StoredTag {
+         enabled: false,
+         ..StoredTag::new(BASE)
+      };
+      let slug: TagSlug = format!("{ID}x00000F").parse().unwrap();
+      let decision = decide(Some(&disabled), &slug, &ResolveContext::new(now()));
+      assert_eq!(decision.outcome, ResolveOutcome::Disabled);
+      // Neither counted nor cached, nor reviewed against the counter it reported
+      assert_eq!(
+         (decision.served, decision.review, decision.cacheable),
+         (None, None, false)
+      );
+
+      let enabled = store(StoredTag {
+         enabled: true,
+         ..disabled
+      });
+      assert_eq!(resolve(&enabled, ID, ResolveContext::new(now())), redirect(BASE, 307));
+   }
+
+   #[test]
+   fn test_language_and_country() {
+      let tags = store(StoredTag {
+         lang_targets: vec![("de".parse().unwrap(), "https://example.com/de".to_string())],
+         geo_targets: vec![("DE,AT".to_string(), "https://store.example.com/dach".to_string())],
+         ..StoredTag::new(BASE)
+      });
+      let german = ResolveContext {
+         accept_language: Some("de-DE,de;q=0.9,en;q=0.5"),
+         ..ResolveContext::new(now())
+      };
+      assert_eq!(resolve(&tags, ID, german), redirect("https://example.com/de", 307));
+
+      let explicit = ResolveContext {
+         lang: Some("en"),
+         ..german
+      };
+      assert_eq!(resolve(&tags, ID, explicit), redirect(BASE, 307));
+
+      let in_austria = ResolveContext {
+         country: Some("AT"),
+         ..german
+      };
+      assert_eq!(
+         resolve(&tags, ID, in_austria),
+         redirect("https://store.example.com/dach", 307)
+      );
+   }
+
+   /// Which target a scan goes to: the kit's open window, then the tag's per-country, per-language
+   /// and base targets, in that order.
+   #[test]
+   fn test_target_precedence() {
+      let window = Window {
+         starts_at: "2026-10-16T10:00:00Z".parse().unwrap(),
+         ends_at: "2026-10-16T14:00:00Z".parse().unwrap(),
+         target_url: "https://live.example.com/".to_string(),
+      };
+      let later = Window {
+         starts_at: "2026-10-17T10:00:00Z".parse().unwrap(),
+         ..window.clone()
+      };
+      // (kit window, opted out, country target, language target) => target, path
+      let table = [
+         (None, false, false, false, BASE, ResolutionPath::Direct),
+         (
+            None,
+            false,
+            false,
+            true,
+            "https://example.com/de",
+            ResolutionPath::Language,
+         ),
+         (
+            None,
+            false,
+            true,
+            true,
+            "https://store.example.com/dach",
+            ResolutionPath::Geo,
+         ),
+         (
+            Some(&window),
+            false,
+            false,
+            false,
+            "https://live.example.com/",
+            ResolutionPath::Schedule,
+         ),
+         (
+            Some(&window),
+            false,
+            true,
+            true,
+            "https://live.example.com/",
+            ResolutionPath::Schedule,
+         ),
+         (Some(&window), true, false, false, BASE, ResolutionPath::Direct),
+         (
+            Some(&window),
+            true,
+            true,
+            true,
+            "https://store.example.com/dach",
+            ResolutionPath::Geo,
+         ),
+         (Some(&later), false, false, false, BASE, ResolutionPath::Direct),
+         (
+            Some(&later),
+            false,
+            false,
+            true,
+            "https://example.com/de",
+            ResolutionPath::Language,
+         ),
+      ];
+      for (window, opted_out, geo, lang, target, path) in table {
+         let tag = StoredTag {
+            lang_targets: lang
+               .then(|| ("de".parse().unwrap(), "https://example.com/de".to_string()))
+               .into_iter()
+               .collect(),
+            geo_targets: geo
+               .then(|| ("AT".to_string(), "https://store.example.com/dach".to_string()))
+               .into_iter()
+               .collect(),
+            kit_schedule: window.into_iter().cloned().collect(),
+            ignore_kit_schedule: opted_out,
+            redirect_kind: RedirectKind::Permanent,
+            ..StoredTag::new(BASE)
+         };
+         let ctx = ResolveContext {
+            accept_language: Some("de"),
+            country: Some("AT"),
+            ..ResolveContext::new(now())
+         };
+         let decision = decide(Some(&tag), &ID.parse().unwrap(), &ctx);
+         let case = format!("{window:?} {opted_out} {geo} {lang}");
+         // A tag with a window ahead of it is only ever sent temporarily
+         let status = if window.is_some() && !opted_out { 307 } else { 308 };
+         assert_eq!(decision.outcome, redirect(target, status), "{case}");
+         assert_eq!(decision.served.unwrap().path, path, "{case}");
+         assert_eq!(decision.cacheable, path != ResolutionPath::Schedule, "{case}");
+      }
+   }
+
+   #[test]
+   fn test_age_gate() {
+      let tags = store(StoredTag {
+         age_gate: true,
+         ..StoredTag::new(BASE)
+      });
+      assert_eq!(
+         resolve(&tags, ID, ResolveContext::new(now())),
+         ResolveOutcome::Gated {
+            kind: Gate::Age { text: None },
+         }
+      );
+      let confirmed = ResolveContext {
+         age_confirmed: true,
+         ..ResolveContext::new(now())
+      };
+      assert_eq!(resolve(&tags, ID, confirmed), redirect(BASE, 307));
+   }
+
+   #[test]
+   fn test_quarantine_wins_over_everything_found() {
+      let tags = store(StoredTag {
+         age_gate: true,
+         maintenance: true,
+         review: Review {
+            state: ReviewState::Suspicious,
+            since_drop: Some(3),
+         },
+         ..StoredTag::new(BASE)
+      });
+      assert_eq!(
+         resolve(&tags, ID, ResolveContext::new(now())),
+         ResolveOutcome::Gated { kind: Gate::Quarantine }
+      );
+   }
+
+   #[test]
+   fn test_maintenance() {
+      let tags = store(StoredTag {
+         maintenance: true,
+         ..StoredTag::new(BASE)
+      });
+      assert_eq!(
+         resolve(&tags, ID, ResolveContext::new(now())),
+         ResolveOutcome::Page {
+            page: Page::Maintenance,
+            status: 503,
+         }
+      );
+      let global = ResolveContext {
+         policy: Policy {
+            maintenance_target_url: Some("https://status.example.com/"),
+            ..Policy::default()
+         },
+         ..ResolveContext::new(now())
+      };
+      assert_eq!(
+         resolve(&store(StoredTag::new(BASE)), ID, global),
+         redirect("https://status.example.com/", 302)
+      );
+   }
+
+   #[test]
+   fn test_pages() {
+      let stateful = store(StoredTag {
+         stateful: true,
+         ..StoredTag::new(BASE)
+      });
+      assert_eq!(
+         resolve(&stateful, ID, ResolveContext::new(now())),
+         ResolveOutcome::Page {
+            page: Page::Stateful { url: BASE.to_string() },
+            status: 200,
+         }
+      );
+
+      let contact = store(StoredTag {
+         contact: true,
+         ..StoredTag::new(BASE)
+      });
+      assert_eq!(
+         resolve(&contact, ID, ResolveContext::new(now())),
+         ResolveOutcome::Page {
+            page: Page::Contact,
+            status: 200,
+         }
+      );
+      let vcf = format!("{ID}.vcf");
+      assert_eq!(
+         resolve(&store(StoredTag::new(BASE)), &vcf, ResolveContext::new(now())),
+         ResolveOutcome::NoContact
+      );
+   }
+
+   #[test]
+   fn test_flush() {
+      let tags = store(StoredTag {
+         served_permanent_until: Some(1_700_000_000),
+         last_seen_tap_count: Some(20),
+         ..StoredTag::new(BASE)
+      });
+      let revisit = format!("{ID}x00000A");
+      let flushing = ResolveContext {
+         policy: Policy {
+            flush_stale_redirects: true,
+            ..Policy::default()
+         },
+         ..ResolveContext::new(now())
+      };
+      assert_eq!(
+         resolve(&tags, &revisit, flushing),
+         ResolveOutcome::Page {
+            page: Page::Flush { url: BASE.to_string() },
+            status: 200,
+         }
+      );
+
+      let cookie = "twag_flushed_04A1B2C3D4E5F6=1700000000";
+      let flushed = ResolveContext {
+         cookies: &[cookie],
+         ..flushing
+      };
+      assert_eq!(resolve(&tags, &revisit, flushed), redirect(BASE, 307));
+   }
+
+   #[test]
+   fn test_decision_details() {
+      let slug: TagSlug = format!("{ID}x000003").parse().unwrap();
+      let tag = StoredTag {
+         last_seen_tap_count: Some(4000),
+         redirect_kind: RedirectKind::Permanent,
+         ..StoredTag::new(BASE)
+      };
+      let decision = decide(Some(&tag), &slug, &ResolveContext::new(now()));
+      assert_eq!(decision.review.unwrap().review.state, ReviewState::NeedsReview);
+      assert_eq!(decision.served.unwrap().path, ResolutionPath::Direct);
+      assert!(decision.cacheable);
+      assert!(decision.served_permanent);
+
+      let gated = StoredTag { age_gate: true, ..tag };
+      let decision = decide(Some(&gated), &slug, &ResolveContext::new(now()));
+      assert_eq!(decision.served, None);
+      assert!(!decision.cacheable);
+      // Still noticed behind the gate
+      assert!(decision.review.is_some());
+   }
+
+   #[test]
+   fn test_fallbacks_are_temporary() {
+      let tag = StoredTag {
+         redirect_kind: RedirectKind::Permanent,
+         ..StoredTag::new(BASE)
+      };
+      let mut decision = decide(Some(&tag), &ID.parse().unwrap(), &ResolveContext::new(now()));
+      assert!(decision.served_permanent);
+      decision.fall_back("https://backup.example.com/");
+      assert_eq!(decision.outcome, redirect("https://backup.example.com/", 307));
+      assert!(!decision.served_permanent);
+      assert_eq!(
+         decision.served,
+         Some(Served {
+            path: ResolutionPath::Fallback,
+            target_url: Some("https://backup.example.com/".to_string()),
+            status: 307,
+         })
+      );
+   }
+}
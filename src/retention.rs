@@ -0,0 +1,487 @@
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use tracing::{info, warn};
+
+use crate::db_report::Planned;
+use crate::pool::ScalingPool;
+use crate::trash;
+
+const BATCH_SIZE: i64 = 10_000;
+const BATCH_PAUSE: Duration = Duration::from_millis(200);
+const RUN_AT: NaiveTime = NaiveTime::from_hms_opt(3, 0, 0).unwrap();
+
+/// How long each kind of row is kept. `None` keeps rows forever.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+   pub outbox_done: Option<TimeDelta>,
+   pub daily_rollups: Option<TimeDelta>,
+   pub request_log: Option<TimeDelta>,
+   pub tap_events: Option<TimeDelta>,
+   /// Delivered or failed webhook deliveries, payloads included. Pending ones are never pruned.
+   pub webhook_deliveries: Option<TimeDelta>,
+   /// Finished CSV imports, with their files and reports. Unfinished ones are never pruned.
+   pub import_jobs: Option<TimeDelta>,
+   pub dry_run: bool,
+}
+
+impl RetentionPolicy {
+   pub fn from_env() -> Self {
+      let days = |name: &str| {
+         dotenvy::var(name)
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|s| TimeDelta::days(s.parse::<i64>().unwrap_or_else(|_| panic!("Invalid {}", name))))
+      };
+      RetentionPolicy {
+         outbox_done: days("TWAG_RETAIN_OUTBOX_DAYS").or(Some(TimeDelta::days(30))),
+         daily_rollups: days("TWAG_RETAIN_DAILY_DAYS"),
+         request_log: days("TWAG_RETAIN_REQUEST_LOG_DAYS").or(Some(TimeDelta::days(90))),
+         tap_events: days("TWAG_RETAIN_TAP_EVENTS_DAYS").or(Some(TimeDelta::days(90))),
+         webhook_deliveries: days("TWAG_RETAIN_WEBHOOK_DELIVERIES_DAYS").or(Some(TimeDelta::days(30))),
+         import_jobs: days("TWAG_RETAIN_IMPORT_JOBS_DAYS").or(Some(TimeDelta::days(30))),
+         dry_run: dotenvy::var("TWAG_RETENTION_DRY_RUN").is_ok_and(|s| s == "true"),
+      }
+   }
+}
+
+/// Rows stamped strictly before the cutoff are expired; a row stamped exactly `max_age` ago is
+/// kept for one more run.
+pub fn cutoff(now: DateTime<Utc>, max_age: TimeDelta) -> DateTime<Utc> { now - max_age }
+
+/// Daily rows are expired once their whole day lies before the cutoff.
+pub fn day_cutoff(now: DateTime<Utc>, max_age: TimeDelta) -> NaiveDate { cutoff(now, max_age).date_naive() }
+
+/// The first run strictly after `now`.
+pub fn next_run(now: DateTime<Utc>) -> DateTime<Utc> {
+   let today = now.date_naive().and_time(RUN_AT).and_utc();
+   if today > now {
+      today
+   } else {
+      today + TimeDelta::days(1)
+   }
+}
+
+pub fn until_next_run(now: DateTime<Utc>) -> Duration { (next_run(now) - now).to_std().unwrap_or_default() }
+
+/// Calls `delete_batch` until it removes fewer than a full batch, pausing between batches so
+/// each statement's locks are short-lived.
+pub async fn run_batched<F, Fut>(batch_size: i64, pause: Duration, mut delete_batch: F) -> Result<u64, sqlx::Error>
+where
+   F: FnMut(i64) -> Fut,
+   Fut: Future<Output = Result<u64, sqlx::Error>>,
+{
+   let mut total = 0;
+   loop {
+      let deleted = delete_batch(batch_size).await?;
+      total += deleted;
+      if deleted < batch_size as u64 {
+         return Ok(total);
+      }
+      info!(deleted = total, "Retention batch done, continuing");
+      tokio::time::sleep(pause).await;
+   }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RetentionReport {
+   pub finished_at: Option<DateTime<Utc>>,
+   pub dry_run: bool,
+   /// Rows deleted (or, in a dry run, that would have been) per table.
+   pub removed: Vec<(&'static str, u64)>,
+   pub error: Option<String>,
+}
+
+impl std::fmt::Display for RetentionReport {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      let Some(finished_at) = self.finished_at else {
+         return write!(f, "retention: not run yet");
+      };
+      write!(f, "retention: last run {}", finished_at.to_rfc3339())?;
+      if self.dry_run {
+         write!(f, " (dry run)")?;
+      }
+      for (table, count) in &self.removed {
+         write!(f, ", {} {}", table, count)?;
+      }
+      if let Some(error) = &self.error {
+         write!(f, ", failed: {}", error)?;
+      }
+      Ok(())
+   }
+}
+
+async fn prune_outbox(pool: &ScalingPool, cutoff: DateTime<Utc>, dry_run: bool) -> Result<u64, sqlx::Error> {
+   let cutoff = cutoff.to_rfc3339();
+   if dry_run {
+      let count = sqlx::query_scalar!(
+         r#"SELECT count(*) AS "count!" FROM twag_outbox WHERE done_at < $1::text::timestamptz"#,
+         cutoff
+      )
+      .fetch_one(&pool.get())
+      .await?;
+      return Ok(count as u64);
+   }
+   run_batched(BATCH_SIZE, BATCH_PAUSE, |limit| {
+      let cutoff = cutoff.clone();
+      async move {
+         let result = sqlx::query!(
+            r#"DELETE FROM twag_outbox WHERE id IN (
+                  SELECT id FROM twag_outbox WHERE done_at < $1::text::timestamptz LIMIT $2
+               )"#,
+            cutoff,
+            limit,
+         )
+         .execute(&pool.get())
+         .await?;
+         Ok(result.rows_affected())
+      }
+   })
+   .await
+}
+
+async fn prune_request_log(pool: &ScalingPool, cutoff: DateTime<Utc>, dry_run: bool) -> Result<u64, sqlx::Error> {
+   let cutoff = cutoff.to_rfc3339();
+   if dry_run {
+      let count = sqlx::query_scalar!(
+         r#"SELECT count(*) AS "count!" FROM twag_request_log WHERE received_at < $1::text::timestamptz"#,
+         cutoff
+      )
+      .fetch_one(&pool.get())
+      .await?;
+      return Ok(count as u64);
+   }
+   run_batched(BATCH_SIZE, BATCH_PAUSE, |limit| {
+      let cutoff = cutoff.clone();
+      async move {
+         let result = sqlx::query!(
+            r#"DELETE FROM twag_request_log WHERE id IN (
+                  SELECT id FROM twag_request_log WHERE received_at < $1::text::timestamptz LIMIT $2
+               )"#,
+            cutoff,
+            limit,
+         )
+         .execute(&pool.get())
+         .await?;
+         Ok(result.rows_affected())
+      }
+   })
+   .await
+}
+
+async fn prune_tap_events(pool: &ScalingPool, cutoff: DateTime<Utc>, dry_run: bool) -> Result<u64, sqlx::Error> {
+   let cutoff = cutoff.to_rfc3339();
+   if dry_run {
+      let count = sqlx::query_scalar!(
+         r#"SELECT count(*) AS "count!" FROM twag_tap_events WHERE tapped_at < $1::text::timestamptz"#,
+         cutoff
+      )
+      .fetch_one(&pool.get())
+      .await?;
+      return Ok(count as u64);
+   }
+   run_batched(BATCH_SIZE, BATCH_PAUSE, |limit| {
+      let cutoff = cutoff.clone();
+      async move {
+         let result = sqlx::query!(
+            r#"DELETE FROM twag_tap_events WHERE id IN (
+                  SELECT id FROM twag_tap_events WHERE tapped_at < $1::text::timestamptz LIMIT $2
+               )"#,
+            cutoff,
+            limit,
+         )
+         .execute(&pool.get())
+         .await?;
+         Ok(result.rows_affected())
+      }
+   })
+   .await
+}
+
+async fn prune_webhook_deliveries(
+   pool: &ScalingPool,
+   cutoff: DateTime<Utc>,
+   dry_run: bool,
+) -> Result<u64, sqlx::Error> {
+   let cutoff = cutoff.to_rfc3339();
+   if dry_run {
+      let count = sqlx::query_scalar!(
+         r#"SELECT count(*) AS "count!" FROM twag_webhook_deliveries
+            WHERE coalesce(delivered_at, failed_at) < $1::text::timestamptz"#,
+         cutoff
+      )
+      .fetch_one(&pool.get())
+      .await?;
+      return Ok(count as u64);
+   }
+   run_batched(BATCH_SIZE, BATCH_PAUSE, |limit| {
+      let cutoff = cutoff.clone();
+      async move {
+         let result = sqlx::query!(
+            r#"DELETE FROM twag_webhook_deliveries WHERE id IN (
+                  SELECT id FROM twag_webhook_deliveries
+                  WHERE coalesce(delivered_at, failed_at) < $1::text::timestamptz LIMIT $2
+               )"#,
+            cutoff,
+            limit,
+         )
+         .execute(&pool.get())
+         .await?;
+         Ok(result.rows_affected())
+      }
+   })
+   .await
+}
+
+async fn prune_import_jobs(pool: &ScalingPool, cutoff: DateTime<Utc>, dry_run: bool) -> Result<u64, sqlx::Error> {
+   let cutoff = cutoff.to_rfc3339();
+   if dry_run {
+      let count = sqlx::query_scalar!(
+         r#"SELECT count(*) AS "count!" FROM twag_import_jobs WHERE finished_at < $1::text::timestamptz"#,
+         cutoff
+      )
+      .fetch_one(&pool.get())
+      .await?;
+      return Ok(count as u64);
+   }
+   // Few and large, the file being kept on each; their report rows go with them
+   run_batched(100, BATCH_PAUSE, |limit| {
+      let cutoff = cutoff.clone();
+      async move {
+         let result = sqlx::query!(
+            r#"DELETE FROM twag_import_jobs WHERE id IN (
+                  SELECT id FROM twag_import_jobs WHERE finished_at < $1::text::timestamptz LIMIT $2
+               )"#,
+            cutoff,
+            limit,
+         )
+         .execute(&pool.get())
+         .await?;
+         Ok(result.rows_affected())
+      }
+   })
+   .await
+}
+
+async fn prune_daily(pool: &ScalingPool, cutoff: NaiveDate, dry_run: bool) -> Result<u64, sqlx::Error> {
+   let cutoff = cutoff.to_string();
+   if dry_run {
+      let count = sqlx::query_scalar!(
+         r#"SELECT (SELECT count(*) FROM twag_tag_daily WHERE day < $1::text::date)
+               + (SELECT count(*) FROM twag_tag_daily_lang WHERE day < $1::text::date) AS "count!""#,
+         cutoff
+      )
+      .fetch_one(&pool.get())
+      .await?;
+      return Ok(count as u64);
+   }
+   let daily = run_batched(BATCH_SIZE, BATCH_PAUSE, |limit| {
+      let cutoff = cutoff.clone();
+      async move {
+         let result = sqlx::query!(
+            r#"DELETE FROM twag_tag_daily WHERE ctid IN (
+                  SELECT ctid FROM twag_tag_daily WHERE day < $1::text::date LIMIT $2
+               )"#,
+            cutoff,
+            limit,
+         )
+         .execute(&pool.get())
+         .await?;
+         Ok(result.rows_affected())
+      }
+   })
+   .await?;
+   let daily_lang = run_batched(BATCH_SIZE, BATCH_PAUSE, |limit| {
+      let cutoff = cutoff.clone();
+      async move {
+         let result = sqlx::query!(
+            r#"DELETE FROM twag_tag_daily_lang WHERE ctid IN (
+                  SELECT ctid FROM twag_tag_daily_lang WHERE day < $1::text::date LIMIT $2
+               )"#,
+            cutoff,
+            limit,
+         )
+         .execute(&pool.get())
+         .await?;
+         Ok(result.rows_affected())
+      }
+   })
+   .await?;
+   Ok(daily + daily_lang)
+}
+
+/// The nightly prune of daily rollups, for `/admin/db-report`; see `db_report`.
+pub fn planned() -> Vec<Planned> {
+   vec![Planned::checked(
+      "Rollup pruning",
+      r#"DELETE FROM twag_tag_daily WHERE ctid IN (
+                  SELECT ctid FROM twag_tag_daily WHERE day < $1::text::date LIMIT $2
+               )"#,
+      &["'2026-01-01'", &BATCH_SIZE.to_string()],
+   )]
+}
+
+pub async fn run_once(pool: &ScalingPool, policy: &RetentionPolicy) -> RetentionReport {
+   let now = Utc::now();
+   let mut removed = Vec::new();
+   let result: Result<(), sqlx::Error> = async {
+      if let Some(max_age) = policy.outbox_done {
+         removed.push((
+            "twag_outbox",
+            prune_outbox(pool, cutoff(now, max_age), policy.dry_run).await?,
+         ));
+      }
+      if let Some(max_age) = policy.request_log {
+         removed.push((
+            "twag_request_log",
+            prune_request_log(pool, cutoff(now, max_age), policy.dry_run).await?,
+         ));
+      }
+      if let Some(max_age) = policy.tap_events {
+         removed.push((
+            "twag_tap_events",
+            prune_tap_events(pool, cutoff(now, max_age), policy.dry_run).await?,
+         ));
+      }
+      if let Some(max_age) = policy.webhook_deliveries {
+         removed.push((
+            "twag_webhook_deliveries",
+            prune_webhook_deliveries(pool, cutoff(now, max_age), policy.dry_run).await?,
+         ));
+      }
+      if let Some(max_age) = policy.import_jobs {
+         removed.push((
+            "twag_import_jobs",
+            prune_import_jobs(pool, cutoff(now, max_age), policy.dry_run).await?,
+         ));
+      }
+      removed.push(("twag_tags (trash)", trash::purge_due(pool, now, policy.dry_run).await?));
+      if let Some(max_age) = policy.daily_rollups {
+         removed.push((
+            "twag_tag_daily",
+            prune_daily(pool, day_cutoff(now, max_age), policy.dry_run).await?,
+         ));
+      }
+      Ok(())
+   }
+   .await;
+   if let Err(e) = &result {
+      warn!("Retention run failed: {:?}", e);
+   }
+
+   info!(dry_run = policy.dry_run, ?removed, "Retention run finished");
+   RetentionReport {
+      finished_at: Some(Utc::now()),
+      dry_run: policy.dry_run,
+      removed,
+      error: result.err().map(|e| e.to_string()),
+   }
+}
+
+pub fn spawn_nightly(pool: ScalingPool, policy: RetentionPolicy, last_report: Arc<RwLock<RetentionReport>>) {
+   tokio::spawn(async move {
+      loop {
+         tokio::time::sleep(until_next_run(Utc::now())).await;
+         let report = run_once(&pool, &policy).await;
+         *last_report.write().unwrap() = report;
+      }
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use std::sync::Mutex;
+
+   fn at(s: &str) -> DateTime<Utc> { DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc) }
+
+   #[test]
+   fn test_cutoff_keeps_rows_exactly_at_the_boundary() {
+      let now = at("2026-10-16T12:00:00Z");
+      let cutoff = cutoff(now, TimeDelta::days(30));
+      assert_eq!(cutoff, at("2026-09-16T12:00:00Z"));
+
+      // Deletion matches `done_at < cutoff`
+      let expired = |done_at: &str| at(done_at) < cutoff;
+      assert!(expired("2026-09-16T11:59:59Z"));
+      assert!(!expired("2026-09-16T12:00:00Z"));
+      assert!(!expired("2026-09-16T12:00:01Z"));
+   }
+
+   #[test]
+   fn test_day_cutoff_only_expires_whole_days() {
+      let cutoff = day_cutoff(at("2026-10-16T00:30:00Z"), TimeDelta::days(7));
+      assert_eq!(cutoff, NaiveDate::from_ymd_opt(2026, 10, 9).unwrap());
+
+      // Deletion matches `day < cutoff`, so the partially-expired cutoff day is kept
+      let expired = |day: NaiveDate| day < cutoff;
+      assert!(expired(NaiveDate::from_ymd_opt(2026, 10, 8).unwrap()));
+      assert!(!expired(NaiveDate::from_ymd_opt(2026, 10, 9).unwrap()));
+   }
+
+   #[test]
+   fn test_until_next_run() {
+      assert_eq!(until_next_run(at("2026-10-16T02:00:00Z")), Duration::from_secs(60 * 60));
+      assert_eq!(
+         until_next_run(at("2026-10-16T03:00:00Z")),
+         Duration::from_secs(24 * 60 * 60)
+      );
+      assert_eq!(
+         until_next_run(at("2026-10-16T23:00:00Z")),
+         Duration::from_secs(4 * 60 * 60)
+      );
+   }
+
+   #[tokio::test]
+   async fn test_run_batched_stops_after_a_short_batch() {
+      let remaining = Mutex::new(25_000u64);
+      let calls = Mutex::new(Vec::new());
+      let total = run_batched(10_000, Duration::ZERO, |limit| {
+         let mut remaining = remaining.lock().unwrap();
+         let deleted = (*remaining).min(limit as u64);
+         *remaining -= deleted;
+         calls.lock().unwrap().push(deleted);
+         async move { Ok(deleted) }
+      })
+      .await
+      .unwrap();
+
+      assert_eq!(total, 25_000);
+      assert_eq!(*calls.lock().unwrap(), [10_000, 10_000, 5_000]);
+   }
+
+   #[tokio::test]
+   async fn test_run_batched_exact_multiple_needs_one_empty_batch() {
+      let remaining = Mutex::new(20_000u64);
+      let calls = Mutex::new(0);
+      let total = run_batched(10_000, Duration::ZERO, |limit| {
+         let mut remaining = remaining.lock().unwrap();
+         let deleted = (*remaining).min(limit as u64);
+         *remaining -= deleted;
+         *calls.lock().unwrap() += 1;
+         async move { Ok(deleted) }
+      })
+      .await
+      .unwrap();
+
+      assert_eq!(total, 20_000);
+      assert_eq!(*calls.lock().unwrap(), 3);
+   }
+
+   #[test]
+   fn test_report_display() {
+      assert_eq!(RetentionReport::default().to_string(), "retention: not run yet");
+      let report = RetentionReport {
+         finished_at: Some(at("2026-10-16T03:00:05Z")),
+         dry_run: true,
+         removed: vec![("twag_outbox", 12), ("twag_tag_daily", 0)],
+         error: None,
+      };
+      assert_eq!(
+         report.to_string(),
+         "retention: last run 2026-10-16T03:00:05+00:00 (dry run), twag_outbox 12, twag_tag_daily 0"
+      );
+   }
+}
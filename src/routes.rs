@@ -0,0 +1,236 @@
+//! A registry of every route, filled in as the `Router` is built so that `/help` can't drift from
+//! what's actually served.
+
+use axum::handler::Handler;
+use axum::routing::{self, MethodRouter};
+use axum::Router;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+   Get,
+   Post,
+   Put,
+   Delete,
+}
+
+impl Method {
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         Method::Get => "GET",
+         Method::Post => "POST",
+         Method::Put => "PUT",
+         Method::Delete => "DELETE",
+      }
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+   Public,
+   /// Meant for the operator. Nothing enforces this yet beyond the reverse proxy.
+   Admin,
+}
+
+/// Where a parameter is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum In {
+   Path,
+   Query,
+   Form,
+}
+
+impl In {
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         In::Path => "path",
+         In::Query => "query",
+         In::Form => "form",
+      }
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Param {
+   pub name: &'static str,
+   #[serde(rename = "in")]
+   pub location: In,
+   /// A short type name, e.g. `hex14`, `url`, or `bool`.
+   #[serde(rename = "type")]
+   pub kind: &'static str,
+   pub required: bool,
+   pub description: &'static str,
+}
+
+/// A path parameter; always required.
+pub const fn path(name: &'static str, kind: &'static str, description: &'static str) -> Param {
+   Param {
+      name,
+      location: In::Path,
+      kind,
+      required: true,
+      description,
+   }
+}
+
+/// An optional query-string parameter.
+pub const fn query(name: &'static str, kind: &'static str, description: &'static str) -> Param {
+   Param {
+      name,
+      location: In::Query,
+      kind,
+      required: false,
+      description,
+   }
+}
+
+/// An optional form field.
+pub const fn form(name: &'static str, kind: &'static str, description: &'static str) -> Param {
+   Param {
+      name,
+      location: In::Form,
+      kind,
+      required: false,
+      description,
+   }
+}
+
+impl Param {
+   pub const fn required(self) -> Self { Param { required: true, ..self } }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Doc {
+   pub description: &'static str,
+   pub access: Access,
+   pub params: Vec<Param>,
+}
+
+impl Doc {
+   pub fn public(description: &'static str) -> Self {
+      Doc {
+         description,
+         access: Access::Public,
+         params: Vec::new(),
+      }
+   }
+
+   pub fn admin(description: &'static str) -> Self {
+      Doc {
+         description,
+         access: Access::Admin,
+         params: Vec::new(),
+      }
+   }
+
+   pub fn param(mut self, param: Param) -> Self {
+      self.params.push(param);
+      self
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteDoc {
+   pub method: Method,
+   pub path: &'static str,
+   #[serde(flatten)]
+   pub doc: Doc,
+}
+
+/// Builds a `Router` and its documentation together; routes should only ever be added through
+/// here.
+pub struct Routes<S> {
+   router: Router<S>,
+   docs: Vec<RouteDoc>,
+}
+
+impl<S: Clone + Send + Sync + 'static> Default for Routes<S> {
+   fn default() -> Self {
+      Routes {
+         router: Router::new(),
+         docs: Vec::new(),
+      }
+   }
+}
+
+impl<S: Clone + Send + Sync + 'static> Routes<S> {
+   /// For a `MethodRouter` that needs layers of its own; it must answer exactly `method`.
+   pub fn add(mut self, method: Method, path: &'static str, method_router: MethodRouter<S>, doc: Doc) -> Self {
+      self.router = self.router.route(path, method_router);
+      self.docs.push(RouteDoc { method, path, doc });
+      self
+   }
+
+   pub fn get<H: Handler<T, S>, T: 'static>(self, path: &'static str, handler: H, doc: Doc) -> Self {
+      self.add(Method::Get, path, routing::get(handler), doc)
+   }
+
+   pub fn post<H: Handler<T, S>, T: 'static>(self, path: &'static str, handler: H, doc: Doc) -> Self {
+      self.add(Method::Post, path, routing::post(handler), doc)
+   }
+
+   pub fn put<H: Handler<T, S>, T: 'static>(self, path: &'static str, handler: H, doc: Doc) -> Self {
+      self.add(Method::Put, path, routing::put(handler), doc)
+   }
+
+   pub fn delete<H: Handler<T, S>, T: 'static>(self, path: &'static str, handler: H, doc: Doc) -> Self {
+      self.add(Method::Delete, path, routing::delete(handler), doc)
+   }
+
+   pub fn into_parts(self) -> (Router<S>, Vec<RouteDoc>) { (self.router, self.docs) }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use axum::{body::Body, http::header, http::Request, http::StatusCode};
+   use tower::Service;
+
+   #[tokio::test]
+   async fn test_methods_on_one_path_are_merged() {
+      let (mut router, docs) = Routes::<()>::default()
+         .get("/tag/{slug}/stateful", || async { "" }, Doc::public("State"))
+         .post("/tag/{slug}/stateful", || async { "" }, Doc::admin("Enable"))
+         .delete("/tag/{slug}/stateful", || async { "" }, Doc::admin("Disable"))
+         .into_parts();
+      assert_eq!(
+         docs.iter().map(|doc| doc.method).collect::<Vec<_>>(),
+         [Method::Get, Method::Post, Method::Delete]
+      );
+
+      let request = Request::builder()
+         .method("TRACE")
+         .uri("/tag/055B88A23C1250/stateful")
+         .body(Body::empty())
+         .unwrap();
+      let response = router.call(request).await.unwrap();
+      assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+      let mut allowed: Vec<&str> = response.headers()[header::ALLOW].to_str().unwrap().split(',').collect();
+      allowed.sort();
+      assert_eq!(allowed, ["DELETE", "GET", "HEAD", "POST"]);
+   }
+
+   #[test]
+   fn test_json_shape() {
+      let doc = RouteDoc {
+         method: Method::Get,
+         path: "/tag/{slug}",
+         doc: Doc::public("Redirect").param(path("slug", "hex14", "The tag id")),
+      };
+      assert_eq!(
+         serde_json::to_value(&doc).unwrap(),
+         serde_json::json!({
+            "method": "GET",
+            "path": "/tag/{slug}",
+            "description": "Redirect",
+            "access": "public",
+            "params": [
+               {"name": "slug", "in": "path", "type": "hex14", "required": true, "description": "The tag id"},
+            ],
+         })
+      );
+   }
+}
@@ -0,0 +1,113 @@
+//! Windows of time in which a kit points all of its tags somewhere else, as for an event, then lets
+//! them go back to their own targets. A tag can opt out with `ignore_kit_schedule`.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+   pub starts_at: DateTime<Utc>,
+   /// The first instant the window is closed again.
+   pub ends_at: DateTime<Utc>,
+   pub target_url: String,
+}
+
+impl Window {
+   pub fn is_open(&self, now: DateTime<Utc>) -> bool { self.starts_at <= now && now < self.ends_at }
+}
+
+/// The window open at `now`; of overlapping ones, the one that opened last, so a short window
+/// inside a long one wins while it lasts.
+pub fn open(windows: &[Window], now: DateTime<Utc>) -> Option<&Window> {
+   windows
+      .iter()
+      .filter(|window| window.is_open(now))
+      .max_by_key(|window| window.starts_at)
+}
+
+/// Windows from the parallel arrays of Unix times and targets that lookups select them as.
+pub fn from_columns(starts: &[i64], ends: &[i64], target_urls: &[String]) -> Vec<Window> {
+   starts
+      .iter()
+      .zip(ends)
+      .zip(target_urls)
+      .filter_map(|((starts_at, ends_at), target_url)| {
+         Some(Window {
+            starts_at: DateTime::from_timestamp(*starts_at, 0)?,
+            ends_at: DateTime::from_timestamp(*ends_at, 0)?,
+            target_url: target_url.clone(),
+         })
+      })
+      .collect()
+}
+
+/// A time as typed into the schedule form, in UTC: `2026-10-20T18:00` as a `datetime-local` input
+/// sends it, with or without seconds, or an RFC 3339 time with its own offset.
+pub fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+   let s = s.trim();
+   if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+      return Some(time.with_timezone(&Utc));
+   }
+   ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+      .iter()
+      .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+      .map(|time| time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn at(s: &str) -> DateTime<Utc> { DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc) }
+
+   fn window(starts_at: &str, ends_at: &str, target_url: &str) -> Window {
+      Window {
+         starts_at: at(starts_at),
+         ends_at: at(ends_at),
+         target_url: target_url.to_string(),
+      }
+   }
+
+   #[test]
+   fn test_windows_open_at_their_start_and_close_at_their_end() {
+      let windows = [window(
+         "2026-10-20T18:00:00Z",
+         "2026-10-20T22:00:00Z",
+         "https://live.example.com/",
+      )];
+      assert_eq!(open(&windows, at("2026-10-20T17:59:59Z")), None);
+      assert_eq!(open(&windows, at("2026-10-20T18:00:00Z")), windows.first());
+      assert_eq!(open(&windows, at("2026-10-20T21:59:59Z")), windows.first());
+      assert_eq!(open(&windows, at("2026-10-20T22:00:00Z")), None);
+   }
+
+   #[test]
+   fn test_parse_time() {
+      let expected = Some(at("2026-10-20T18:00:00Z"));
+      assert_eq!(parse_time("2026-10-20T18:00"), expected);
+      assert_eq!(parse_time(" 2026-10-20T18:00:00 "), expected);
+      assert_eq!(parse_time("2026-10-20 18:00"), expected);
+      assert_eq!(parse_time("2026-10-20T20:00:00+02:00"), expected);
+      assert_eq!(parse_time("2026-10-20"), None);
+      assert_eq!(parse_time("tomorrow"), None);
+   }
+
+   #[test]
+   fn test_the_latest_opened_overlapping_window_wins() {
+      let windows = [
+         window(
+            "2026-10-20T00:00:00Z",
+            "2026-10-23T00:00:00Z",
+            "https://festival.example.com/",
+         ),
+         window(
+            "2026-10-21T20:00:00Z",
+            "2026-10-21T21:00:00Z",
+            "https://live.example.com/",
+         ),
+      ];
+      let target = |now| open(&windows, at(now)).map(|window| window.target_url.as_str());
+      assert_eq!(target("2026-10-21T19:00:00Z"), Some("https://festival.example.com/"));
+      assert_eq!(target("2026-10-21T20:30:00Z"), Some("https://live.example.com/"));
+      assert_eq!(target("2026-10-21T21:00:00Z"), Some("https://festival.example.com/"));
+   }
+}
@@ -0,0 +1,125 @@
+//! Keyed signatures over what twag hands out and later has to trust, or lets others check: webhook
+//! bodies, redacted tag ids, and cookies.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+fn mac(key: &[u8], parts: &[&[u8]]) -> Hmac<Sha256> {
+   let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+   for part in parts {
+      mac.update(part);
+   }
+   mac
+}
+
+/// Lowercase hex HMAC-SHA256 of `parts`, concatenated.
+pub fn hmac_hex(key: &[u8], parts: &[&[u8]]) -> String {
+   mac(key, parts)
+      .finalize()
+      .into_bytes()
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+   if hex.len() % 2 != 0 || !hex.is_ascii() {
+      return None;
+   }
+   (0..hex.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+      .collect()
+}
+
+/// Checks a `hmac_hex` signature in constant time.
+pub fn verify_hex(key: &[u8], parts: &[&[u8]], signature: &str) -> bool {
+   from_hex(signature).is_some_and(|bytes| mac(key, parts).verify_slice(&bytes).is_ok())
+}
+
+/// Signs cookie values, so a client can hold on to something twag decided without being able to
+/// forge it.
+#[derive(Clone)]
+pub struct CookieKey(Vec<u8>);
+
+/// Shorter secrets are refused rather than padded out.
+pub const MIN_SECRET_LEN: usize = 16;
+
+impl CookieKey {
+   /// Disabled unless `TWAG_COOKIE_SECRET` is set.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+      let Some(secret) = var("TWAG_COOKIE_SECRET").filter(|s| !s.is_empty()) else {
+         return Ok(None);
+      };
+      if secret.len() < MIN_SECRET_LEN {
+         return Err(format!("TWAG_COOKIE_SECRET must be at least {} bytes", MIN_SECRET_LEN));
+      }
+      Ok(Some(CookieKey(secret.into_bytes())))
+   }
+
+   /// `{value}.{signature}`, signed together with the cookie's name so that it can't be passed off
+   /// as a different cookie.
+   pub fn sign(&self, name: &str, value: &str) -> String {
+      format!(
+         "{}.{}",
+         value,
+         hmac_hex(&self.0, &[name.as_bytes(), b"=", value.as_bytes()])
+      )
+   }
+
+   /// The value of a cookie made by `sign`, unless it's been tampered with.
+   pub fn verify<'a>(&self, name: &str, signed: &'a str) -> Option<&'a str> {
+      let (value, signature) = signed.rsplit_once('.')?;
+      verify_hex(&self.0, &[name.as_bytes(), b"=", value.as_bytes()], signature).then_some(value)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn key(secret: &str) -> CookieKey {
+      CookieKey::from_vars(|name| (name == "TWAG_COOKIE_SECRET").then(|| secret.to_string()))
+         .unwrap()
+         .unwrap()
+   }
+
+   #[test]
+   fn test_hmac_hex_matches_a_known_vector() {
+      // RFC 4231, test case 2
+      assert_eq!(
+         hmac_hex(b"Jefe", &[b"what do ya want ", b"for nothing?"]),
+         "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+      );
+      let signature = hmac_hex(b"Jefe", &[b"what do ya want for nothing?"]);
+      assert!(verify_hex(b"Jefe", &[b"what do ya want ", b"for nothing?"], &signature));
+      assert!(!verify_hex(b"Jeff", &[b"what do ya want for nothing?"], &signature));
+      assert!(!verify_hex(
+         b"Jefe",
+         &[b"what do ya want for nothing?"],
+         &signature[1..]
+      ));
+      assert!(!verify_hex(b"Jefe", &[b"what do ya want for nothing?"], "zz"));
+   }
+
+   #[test]
+   fn test_cookies_round_trip_and_refuse_tampering() {
+      let other = key("fedcba9876543210");
+      let key = key("0123456789abcdef");
+      let signed = key.sign("twag_age_ok", "1792152000");
+      assert_eq!(key.verify("twag_age_ok", &signed), Some("1792152000"));
+
+      assert_eq!(key.verify("twag_age_ok", &signed.replacen("179", "279", 1)), None);
+      assert_eq!(key.verify("twag_other", &signed), None);
+      assert_eq!(key.verify("twag_age_ok", "1792152000"), None);
+      assert_eq!(key.verify("twag_age_ok", "1792152000."), None);
+      assert_eq!(other.verify("twag_age_ok", &signed), None);
+   }
+
+   #[test]
+   fn test_config() {
+      assert!(CookieKey::from_vars(|_| None).unwrap().is_none());
+      assert!(CookieKey::from_vars(|_| Some(String::new())).unwrap().is_none());
+      assert!(CookieKey::from_vars(|_| Some("short".to_string())).is_err());
+   }
+}
@@ -0,0 +1,473 @@
+use std::sync::{Arc, RwLock};
+
+use tracing::{info, warn};
+
+use crate::age_gate;
+use crate::branding::Branding;
+use crate::canonical::CanonicalHost;
+use crate::net::{FetchPolicy, IpRange};
+use crate::privacy;
+use crate::quota;
+use crate::report;
+use crate::reprogram::Thresholds;
+use crate::target_url;
+use crate::trash;
+
+/// Settings that need a restart to change; a reload only warns when these differ.
+const RESTART_REQUIRED_VARS: &[&str] = &[
+   "DATABASE_URL",
+   "DATABASE_READ_URL",
+   "PORT",
+   "TWAG_NOTION_ENABLED",
+   "NOTION_TOKEN",
+   "NOTION_THINGS_DB",
+   "NOTION_THINGS_DS",
+   "NOTION_THINGS_COLUMN_NAME",
+   "NOTION_THINGS_TAG_COLUMN_NAME",
+   "NOTION_THINGS_STATE_COLUMN_NAME",
+   "NOTION_THINGS_URL_COLUMN_NAME",
+   "NOTION_CONTAINERS_DB",
+   "NOTION_CONTAINERS_DS",
+   "NOTION_CONTAINERS_COLUMN_NAME",
+   "TWAG_PG_MAX_CONNECTIONS",
+   "TWAG_PG_MAX_CONNECTIONS_CAP",
+   "TWAG_PG_AUTOSCALE",
+   "TWAG_VISITOR_SALT",
+   "TWAG_MQTT_URL",
+   "TWAG_MQTT_USERNAME",
+   "TWAG_MQTT_PASSWORD",
+   "TWAG_MQTT_CA_FILE",
+   "TWAG_MQTT_CLIENT_ID",
+   "TWAG_MQTT_TOPIC_PREFIX",
+   "TWAG_MQTT_DISCOVERY",
+   "TWAG_WEBHOOK_URL",
+   "TWAG_WEBHOOK_SECRET",
+   "TWAG_APPLE_TEAM_ID",
+   "TWAG_APPLE_BUNDLE_ID",
+   "TWAG_ANDROID_PACKAGE",
+   "TWAG_ANDROID_CERT_FINGERPRINTS",
+   "TWAG_GEOIP_DB",
+   "TWAG_CHAOS",
+   "TWAG_REDACT_TAG_IDS",
+   "TWAG_REDACT_KEY",
+   "TWAG_COOKIE_SECRET",
+   "TWAG_PROVISIONING_SECRET",
+   "TWAG_HEALTH_TOKEN",
+   "TWAG_NEGATIVE_CACHE_SIZE",
+   "TWAG_NEGATIVE_CACHE_SECS",
+   "TWAG_PRELOAD_TAGS",
+];
+
+/// Settings handlers read per request, so they can be swapped on SIGHUP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+   pub branding: Branding,
+   pub canonical_host: Option<CanonicalHost>,
+   pub fetch_policy: FetchPolicy,
+   /// Retry a missed lookup against ids stored in the wrong case; see `audit`.
+   pub lookup_normalize_retry: bool,
+   /// Serve a one-time cache-flushing interstitial to revisits of formerly permanent redirects.
+   pub flush_stale_redirects: bool,
+   /// Expose per-phase timings to clients in a `Server-Timing` header.
+   pub server_timing: bool,
+   /// Strip indentation and blank lines from streamed pages; see `streaming`.
+   pub minify_html: bool,
+   /// Sends every tag here while set; see `maintenance`.
+   pub maintenance_target_url: Option<String>,
+   /// Where scans of expired tags go, rather than to a page saying they've expired.
+   pub expired_target_url: Option<String>,
+   /// Longer target URLs get a link page instead of a redirect; see `target_url`.
+   pub max_location_len: usize,
+   /// How long an age confirmation lasts; see `age_gate`.
+   pub age_gate_days: u32,
+   /// Where declining an age gate goes.
+   pub age_gate_decline_url: String,
+   /// When a counter drop marks a tag for review; see `reprogram`.
+   pub reprogram: Thresholds,
+   /// How long deleted tags stay restorable; see `trash`.
+   pub trash_days: u32,
+   /// Refuse target URLs whose host mixes look-alike scripts; see `target_url::mixed_script_label`.
+   pub strict_idn: bool,
+   /// Serve the public `/status` page; see `public_status`.
+   pub status_page: bool,
+   /// How long a stats share link works unless another length is picked; see `share`.
+   pub stats_share_days: u32,
+   /// Which do-not-track signals keep a tap's detail out of its event; see `privacy`.
+   pub privacy_signals: privacy::Signals,
+   /// How many tags and aliases may be created; see `quota`.
+   pub quotas: quota::Quotas,
+}
+
+impl Settings {
+   /// Reads every hot-reloadable setting, collecting all validation errors rather than stopping
+   /// at the first.
+   pub fn from_env() -> Result<Self, Vec<String>> {
+      let var = |name: &str| dotenvy::var(name).ok().filter(|s| !s.is_empty());
+      let mut errors = Vec::new();
+
+      let branding = Branding::from_env().unwrap_or_else(|e| {
+         errors.push(e.to_string());
+         Branding::default()
+      });
+
+      let canonical_host = var("TWAG_CANONICAL_HOST").map(|host| CanonicalHost {
+         host,
+         allow_writes: var("TWAG_CANONICAL_HOST_ALLOW_WRITES").is_some_and(|s| s == "true"),
+      });
+
+      let extra_blocklist = var("TWAG_FETCH_BLOCKLIST")
+         .unwrap_or_default()
+         .split(',')
+         .filter(|s| !s.trim().is_empty())
+         .filter_map(|s| {
+            s.parse::<IpRange>()
+               .map_err(|e| errors.push(format!("TWAG_FETCH_BLOCKLIST: {}", e)))
+               .ok()
+         })
+         .collect();
+
+      let maintenance_target_url = var("TWAG_MAINTENANCE_TARGET_URL").filter(|raw| match url::Url::parse(raw) {
+         Ok(url) if matches!(url.scheme(), "http" | "https") => true,
+         _ => {
+            errors.push(format!(
+               "TWAG_MAINTENANCE_TARGET_URL must be an http(s) URL, not '{}'",
+               raw
+            ));
+            false
+         }
+      });
+
+      let expired_target_url = var("TWAG_EXPIRED_TARGET_URL").filter(|raw| match url::Url::parse(raw) {
+         Ok(url) if matches!(url.scheme(), "http" | "https") => true,
+         _ => {
+            errors.push(format!("TWAG_EXPIRED_TARGET_URL must be an http(s) URL, not '{}'", raw));
+            false
+         }
+      });
+
+      let max_location_len = match var("TWAG_MAX_LOCATION_LENGTH").map(|raw| raw.parse::<usize>()) {
+         None => target_url::DEFAULT_MAX_LOCATION_LEN,
+         Some(Ok(len)) if len > 0 => len,
+         Some(_) => {
+            errors.push("TWAG_MAX_LOCATION_LENGTH must be a positive number of bytes".to_string());
+            target_url::DEFAULT_MAX_LOCATION_LEN
+         }
+      };
+
+      let age_gate_days = match var("TWAG_AGE_GATE_DAYS").map(|raw| raw.parse::<u32>()) {
+         None => age_gate::DEFAULT_DAYS,
+         Some(Ok(days)) if (1..=400).contains(&days) => days,
+         Some(_) => {
+            errors.push("TWAG_AGE_GATE_DAYS must be a number of days from 1 to 400".to_string());
+            age_gate::DEFAULT_DAYS
+         }
+      };
+
+      // A path on this site, or anywhere over http(s)
+      let age_gate_decline_url = var("TWAG_AGE_GATE_DECLINE_URL")
+         .filter(|raw| {
+            let valid = match url::Url::parse(raw) {
+               Ok(url) => matches!(url.scheme(), "http" | "https"),
+               Err(_) => raw.starts_with('/') && !raw.starts_with("//"),
+            };
+            if !valid {
+               errors.push(format!(
+                  "TWAG_AGE_GATE_DECLINE_URL must be an http(s) URL or a path, not '{}'",
+                  raw
+               ));
+            }
+            valid
+         })
+         .unwrap_or_else(|| age_gate::DEFAULT_DECLINE_URL.to_string());
+
+      let defaults = Thresholds::default();
+      let mut threshold = |name: &str, default: i32| match var(name).map(|raw| raw.parse::<i32>()) {
+         None => default,
+         Some(Ok(n)) if n >= 0 => n,
+         Some(_) => {
+            errors.push(format!("{} must be a counter value, 0 or more", name));
+            default
+         }
+      };
+      let reprogram = Thresholds {
+         min_drop: threshold("TWAG_REPROGRAM_MIN_DROP", defaults.min_drop),
+         max_restart: threshold("TWAG_REPROGRAM_MAX_RESTART", defaults.max_restart),
+      };
+
+      let trash_days = match var("TWAG_TRASH_DAYS").map(|raw| raw.parse::<u32>()) {
+         None => trash::DEFAULT_GRACE_DAYS,
+         Some(Ok(days)) if (1..=3650).contains(&days) => days,
+         Some(_) => {
+            errors.push("TWAG_TRASH_DAYS must be a number of days from 1 to 3650".to_string());
+            trash::DEFAULT_GRACE_DAYS
+         }
+      };
+      let stats_share_days = match var("TWAG_STATS_SHARE_DAYS").map(|raw| raw.parse::<u32>()) {
+         None => report::DEFAULT_SHARE_DAYS,
+         Some(Ok(days)) if (1..=report::MAX_SHARE_DAYS).contains(&days) => days,
+         Some(_) => {
+            errors.push(format!(
+               "TWAG_STATS_SHARE_DAYS must be a number of days from 1 to {}",
+               report::MAX_SHARE_DAYS
+            ));
+            report::DEFAULT_SHARE_DAYS
+         }
+      };
+      let strict_idn = var("TWAG_STRICT_IDN").is_some_and(|s| s == "true");
+      let privacy_signals = var("TWAG_PRIVACY_SIGNALS")
+         .map(|raw| {
+            raw.parse().unwrap_or_else(|e| {
+               errors.push(e);
+               privacy::Signals::default()
+            })
+         })
+         .unwrap_or_default();
+
+      let mut limit = |name: &str| match var(name).map(|raw| raw.parse::<i64>()) {
+         None => None,
+         Some(Ok(n)) if n >= 0 => Some(n),
+         Some(_) => {
+            errors.push(format!("{} must be a number, 0 or more, or unset for no limit", name));
+            None
+         }
+      };
+      let quotas = quota::Quotas {
+         tags: limit("TWAG_MAX_TAGS"),
+         aliases: limit("TWAG_MAX_ALIASES"),
+      };
+
+      if !errors.is_empty() {
+         return Err(errors);
+      }
+      Ok(Settings {
+         branding,
+         canonical_host,
+         fetch_policy: FetchPolicy { extra_blocklist },
+         lookup_normalize_retry: var("TWAG_LOOKUP_NORMALIZE_RETRY").is_some_and(|s| s == "true"),
+         flush_stale_redirects: var("TWAG_FLUSH_STALE_REDIRECTS").is_some_and(|s| s == "true"),
+         server_timing: var("TWAG_SERVER_TIMING").is_some_and(|s| s == "true"),
+         minify_html: var("TWAG_MINIFY_HTML").is_some_and(|s| s == "true"),
+         maintenance_target_url,
+         expired_target_url,
+         max_location_len,
+         age_gate_days,
+         age_gate_decline_url,
+         reprogram,
+         trash_days,
+         strict_idn,
+         status_page: var("TWAG_STATUS_PAGE").is_some_and(|s| s == "true"),
+         stats_share_days,
+         privacy_signals,
+         quotas,
+      })
+   }
+
+   pub fn changed_from(&self, old: &Settings) -> Vec<&'static str> {
+      let mut changed = Vec::new();
+      if self.branding != old.branding {
+         changed.push("branding");
+      }
+      if self.canonical_host != old.canonical_host {
+         changed.push("canonical_host");
+      }
+      if self.fetch_policy != old.fetch_policy {
+         changed.push("fetch_policy");
+      }
+      if self.lookup_normalize_retry != old.lookup_normalize_retry {
+         changed.push("lookup_normalize_retry");
+      }
+      if self.flush_stale_redirects != old.flush_stale_redirects {
+         changed.push("flush_stale_redirects");
+      }
+      if self.server_timing != old.server_timing {
+         changed.push("server_timing");
+      }
+      if self.minify_html != old.minify_html {
+         changed.push("minify_html");
+      }
+      if self.maintenance_target_url != old.maintenance_target_url {
+         changed.push("maintenance_target_url");
+      }
+      if self.expired_target_url != old.expired_target_url {
+         changed.push("expired_target_url");
+      }
+      if self.max_location_len != old.max_location_len {
+         changed.push("max_location_len");
+      }
+      if self.age_gate_days != old.age_gate_days {
+         changed.push("age_gate_days");
+      }
+      if self.age_gate_decline_url != old.age_gate_decline_url {
+         changed.push("age_gate_decline_url");
+      }
+      if self.reprogram != old.reprogram {
+         changed.push("reprogram");
+      }
+      if self.trash_days != old.trash_days {
+         changed.push("trash_days");
+      }
+      if self.strict_idn != old.strict_idn {
+         changed.push("strict_idn");
+      }
+      if self.status_page != old.status_page {
+         changed.push("status_page");
+      }
+      if self.stats_share_days != old.stats_share_days {
+         changed.push("stats_share_days");
+      }
+      if self.privacy_signals != old.privacy_signals {
+         changed.push("privacy_signals");
+      }
+      if self.quotas != old.quotas {
+         changed.push("quotas");
+      }
+      changed
+   }
+}
+
+#[derive(Clone)]
+pub struct SharedSettings(Arc<RwLock<Arc<Settings>>>);
+
+impl SharedSettings {
+   pub fn new(settings: Settings) -> Self { SharedSettings(Arc::new(RwLock::new(Arc::new(settings)))) }
+
+   pub fn load(&self) -> Arc<Settings> { self.0.read().unwrap().clone() }
+
+   /// Swaps in `settings`, returning the names of the settings that changed.
+   pub fn apply(&self, settings: Settings) -> Vec<&'static str> {
+      let mut current = self.0.write().unwrap();
+      let changed = settings.changed_from(&current);
+      *current = Arc::new(settings);
+      changed
+   }
+}
+
+pub fn restart_required_snapshot() -> Vec<(&'static str, Option<String>)> {
+   RESTART_REQUIRED_VARS
+      .iter()
+      .map(|name| (*name, dotenvy::var(name).ok()))
+      .collect()
+}
+
+fn reload(shared: &SharedSettings, startup: &[(&'static str, Option<String>)]) {
+   // Unlike the startup load, values in `.env` must win over the ones loaded last time.
+   if let Err(e) = dotenvy::dotenv_override() {
+      warn!("Failed to re-read .env: {}", e);
+   }
+
+   match Settings::from_env() {
+      Ok(settings) => {
+         let changed = shared.apply(settings);
+         info!(?changed, "Reloaded settings");
+      }
+      Err(errors) => {
+         warn!(?errors, "Rejected new settings, keeping the running ones");
+      }
+   }
+
+   for (name, value) in restart_required_snapshot() {
+      let at_startup = startup.iter().find(|(n, _)| *n == name).and_then(|(_, v)| v.as_ref());
+      if value.as_ref() != at_startup {
+         warn!(setting = name, "Setting changed but requires a restart to take effect");
+      }
+   }
+}
+
+pub fn spawn_reload_on_sighup(shared: SharedSettings) {
+   use tokio::signal::unix::{signal, SignalKind};
+   let startup = restart_required_snapshot();
+   let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+   tokio::spawn(async move {
+      while sighup.recv().await.is_some() {
+         info!("SIGHUP received, reloading settings");
+         reload(&shared, &startup);
+      }
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::canonical::enforce_canonical_host;
+   use axum::{body::Body, http::Request, http::StatusCode, middleware, routing::get, Router};
+   use tower::Service;
+
+   fn settings(site_name: &str, canonical_host: Option<&str>) -> Settings {
+      Settings {
+         branding: Branding {
+            site_name: site_name.to_string(),
+            ..Branding::default()
+         },
+         canonical_host: canonical_host.map(|host| CanonicalHost {
+            host: host.to_string(),
+            allow_writes: false,
+         }),
+         fetch_policy: FetchPolicy::default(),
+         lookup_normalize_retry: false,
+         flush_stale_redirects: false,
+         server_timing: false,
+         minify_html: false,
+         maintenance_target_url: None,
+         expired_target_url: None,
+         max_location_len: target_url::DEFAULT_MAX_LOCATION_LEN,
+         age_gate_days: age_gate::DEFAULT_DAYS,
+         age_gate_decline_url: age_gate::DEFAULT_DECLINE_URL.to_string(),
+         reprogram: Thresholds::default(),
+         trash_days: trash::DEFAULT_GRACE_DAYS,
+         strict_idn: false,
+         status_page: false,
+         stats_share_days: report::DEFAULT_SHARE_DAYS,
+         privacy_signals: privacy::Signals::Off,
+         quotas: quota::Quotas::default(),
+      }
+   }
+
+   #[test]
+   fn test_apply_reports_changed_settings() {
+      let shared = SharedSettings::new(settings("twag", None));
+      assert_eq!(shared.apply(settings("twag", None)), Vec::<&str>::new());
+      assert_eq!(shared.apply(settings("Museum", None)), ["branding"]);
+      assert_eq!(
+         shared.apply(settings("twag", Some("xz.ws"))),
+         ["branding", "canonical_host"]
+      );
+      assert_eq!(shared.load().branding.site_name, "twag");
+   }
+
+   #[tokio::test]
+   async fn test_router_observes_reloaded_settings() {
+      let shared = SharedSettings::new(settings("twag", None));
+      let router = Router::new()
+         .route(
+            "/",
+            get(
+               |axum::extract::State(s): axum::extract::State<SharedSettings>| async move {
+                  s.load().branding.site_name.clone()
+               },
+            ),
+         )
+         .with_state(shared.clone())
+         .layer(middleware::from_fn_with_state(shared.clone(), enforce_canonical_host));
+
+      let request = || {
+         Request::get("/")
+            .header("host", "twag.example")
+            .body(Body::empty())
+            .unwrap()
+      };
+
+      let response = router.clone().call(request()).await.unwrap();
+      assert_eq!(response.status(), StatusCode::OK);
+      let body = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+      assert_eq!(&body[..], b"twag");
+
+      shared.apply(settings("Museum", None));
+      let response = router.clone().call(request()).await.unwrap();
+      let body = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+      assert_eq!(&body[..], b"Museum");
+
+      shared.apply(settings("Museum", Some("xz.ws")));
+      let response = router.clone().call(request()).await.unwrap();
+      assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+   }
+}
@@ -0,0 +1,167 @@
+//! Links to one tag's tap stats that work without admin access: `/share/{token}`, where the token
+//! carries the tag, what it shows and when it expires, signed with `TWAG_COOKIE_SECRET`. Forged and
+//! expired tokens are refused before anything is read from Postgres.
+//!
+//! Each token also signs the tag's `share_generation`, and the shared view only renders while that
+//! still matches the tag's. Bumping it revokes every link made for the tag so far.
+
+use crate::models::TagUid;
+use crate::report;
+use crate::security::CookieKey;
+
+/// Signed along with the token, so a share token can't be passed off as a cookie or the reverse.
+const TOKEN_PURPOSE: &str = "twag_share";
+
+/// What a shared link shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+   /// Counts and the daily histogram; never the target URL or audit trail.
+   Stats,
+}
+
+impl Scope {
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         Scope::Stats => "stats",
+      }
+   }
+
+   fn parse(s: &str) -> Option<Scope> {
+      match s {
+         "stats" => Some(Scope::Stats),
+         _ => None,
+      }
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Refused {
+   #[error("Link is malformed or not signed by this server")]
+   Invalid,
+   #[error("Link has expired")]
+   Expired,
+   #[error("Link was revoked")]
+   Revoked,
+}
+
+/// What a valid token grants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grant {
+   pub id: TagUid,
+   pub scope: Scope,
+   pub generation: i32,
+   pub expires: i64,
+}
+
+impl Grant {
+   /// `{id}.{scope}.{generation}.{expires}.{signature}`, for `/share/{token}`.
+   pub fn token(&self, key: &CookieKey) -> String {
+      key.sign(
+         TOKEN_PURPOSE,
+         &format!(
+            "{}.{}.{}.{}",
+            self.id,
+            self.scope.as_str(),
+            self.generation,
+            self.expires
+         ),
+      )
+   }
+
+   /// The grant a token was signed for, if it hasn't expired.
+   pub fn check(key: &CookieKey, token: &str, now: i64) -> Result<Grant, Refused> {
+      let value = key.verify(TOKEN_PURPOSE, token).ok_or(Refused::Invalid)?;
+      let mut parts = value.split('.');
+      let (Some(id), Some(scope), Some(generation), Some(expires), None) =
+         (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+      else {
+         return Err(Refused::Invalid);
+      };
+      let grant = Grant {
+         id: id.parse().map_err(|_| Refused::Invalid)?,
+         scope: Scope::parse(scope).ok_or(Refused::Invalid)?,
+         generation: generation.parse().map_err(|_| Refused::Invalid)?,
+         expires: expires.parse().map_err(|_| Refused::Invalid)?,
+      };
+      if now >= grant.expires {
+         return Err(Refused::Expired);
+      }
+      Ok(grant)
+   }
+
+   /// Whether the grant still holds, given the tag's `share_generation` now.
+   pub fn current(&self, generation: i32) -> Result<(), Refused> {
+      match self.generation == generation {
+         true => Ok(()),
+         false => Err(Refused::Revoked),
+      }
+   }
+}
+
+/// When a link shared for `days` from `now` expires, clamped as the report's are.
+pub fn expires(now: i64, days: u32) -> i64 { report::share_expires(now, days) }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const NOW: i64 = 1_792_152_000;
+
+   fn key(secret: &str) -> CookieKey { CookieKey::from_vars(|_| Some(secret.to_string())).unwrap().unwrap() }
+
+   fn grant(generation: i32) -> Grant {
+      Grant {
+         id: "055B88A23C1250".parse().unwrap(),
+         scope: Scope::Stats,
+         generation,
+         expires: expires(NOW, 7),
+      }
+   }
+
+   #[test]
+   fn test_links_work_until_they_expire() {
+      let key = key("0123456789abcdef");
+      let token = grant(0).token(&key);
+      assert_eq!(Grant::check(&key, &token, NOW), Ok(grant(0)));
+      assert_eq!(Grant::check(&key, &token, grant(0).expires - 1), Ok(grant(0)));
+      assert_eq!(Grant::check(&key, &token, grant(0).expires), Err(Refused::Expired));
+   }
+
+   #[test]
+   fn test_bumping_the_generation_revokes() {
+      let key = key("0123456789abcdef");
+      let old = Grant::check(&key, &grant(0).token(&key), NOW).unwrap();
+      assert_eq!(old.current(0), Ok(()));
+      assert_eq!(old.current(1), Err(Refused::Revoked));
+      let new = Grant::check(&key, &grant(1).token(&key), NOW).unwrap();
+      assert_eq!(new.current(1), Ok(()));
+   }
+
+   #[test]
+   fn test_tampered_links_are_refused() {
+      let key = key("0123456789abcdef");
+      let token = grant(3).token(&key);
+      let expires = grant(3).expires.to_string();
+
+      let extended = token.replace(&expires, &(grant(3).expires + 86_400).to_string());
+      assert_eq!(Grant::check(&key, &extended, NOW), Err(Refused::Invalid));
+      // Reviving a revoked link by claiming the current generation
+      let bumped = token.replace(".3.", ".4.");
+      assert_eq!(Grant::check(&key, &bumped, NOW), Err(Refused::Invalid));
+      let other_tag = token.replace("055B88A23C1250", "04A1B2C3D4E5F6");
+      assert_eq!(Grant::check(&key, &other_tag, NOW), Err(Refused::Invalid));
+      assert_eq!(
+         Grant::check(&super::key("fedcba9876543210"), &token, NOW),
+         Err(Refused::Invalid)
+      );
+      // A report link for the same tag isn't a stats link
+      let report = report::share_token(&key, &grant(3).id, grant(3).expires);
+      assert_eq!(Grant::check(&key, &report, NOW), Err(Refused::Invalid));
+      // Signed by this server, but not a grant
+      let odd = key.sign(TOKEN_PURPOSE, &format!("055B88A23C1250.audit.3.{expires}"));
+      assert_eq!(Grant::check(&key, &odd, NOW), Err(Refused::Invalid));
+      for garbage in ["", ".", "....", "055B88A23C1250", &"a".repeat(10_000)] {
+         assert_eq!(Grant::check(&key, garbage, NOW), Err(Refused::Invalid), "{garbage:?}");
+      }
+   }
+}
@@ -0,0 +1,115 @@
+//! Short codes for sharing a tag in writing (`/t/x7Qp2`), easier to read aloud than a 14 digit
+//! id. Codes aren't secrets, so they come from a small seeded generator rather than a CSPRNG.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Base58 without `1` as well, so none of 0/O or 1/l/I can be misread as another.
+pub const ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+pub const LEN: usize = 5;
+/// Codes get one character longer after this many collisions in a row.
+const ATTEMPTS_PER_LEN: usize = 3;
+/// Collisions tolerated before giving up; at five or six characters, many more than this means
+/// something other than bad luck.
+pub const MAX_ATTEMPTS: usize = 2 * ATTEMPTS_PER_LEN;
+
+/// SplitMix64: fast, and reproducible from a seed in tests.
+pub struct CodeRng(u64);
+
+impl CodeRng {
+   pub fn seeded(seed: u64) -> Self { CodeRng(seed) }
+
+   /// Seeded from std's per-process hasher keys and the clock.
+   pub fn from_entropy() -> Self {
+      let mut hasher = RandomState::new().build_hasher();
+      let nanos = SystemTime::now()
+         .duration_since(UNIX_EPOCH)
+         .unwrap_or_default()
+         .as_nanos();
+      hasher.write_u128(nanos);
+      CodeRng(hasher.finish())
+   }
+
+   pub fn next_u64(&mut self) -> u64 {
+      self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+      let mut z = self.0;
+      z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+      z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+      z ^ (z >> 31)
+   }
+
+   pub fn code(&mut self, len: usize) -> String {
+      (0..len)
+         .map(|_| ALPHABET[(self.next_u64() % ALPHABET.len() as u64) as usize] as char)
+         .collect()
+   }
+}
+
+/// Codes to try claiming in order, until one isn't taken.
+pub fn candidates(rng: &mut CodeRng) -> impl Iterator<Item = String> + '_ {
+   (0..MAX_ATTEMPTS).map(move |attempt| rng.code(LEN + attempt / ATTEMPTS_PER_LEN))
+}
+
+/// Whether `code` could be an alias at all, before it's looked up.
+pub fn is_plausible(code: &str) -> bool {
+   (1..=32).contains(&code.len()) && code.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+   use std::collections::HashSet;
+
+   use super::*;
+
+   #[test]
+   fn test_alphabet_has_no_lookalikes() {
+      assert_eq!(ALPHABET.len(), 57);
+      for ambiguous in b"0O1lI" {
+         assert!(!ALPHABET.contains(ambiguous), "{}", *ambiguous as char);
+      }
+      assert_eq!(ALPHABET.iter().collect::<HashSet<_>>().len(), ALPHABET.len());
+   }
+
+   #[test]
+   fn test_codes_are_reproducible_from_a_seed() {
+      let a: Vec<String> = candidates(&mut CodeRng::seeded(42)).collect();
+      let b: Vec<String> = candidates(&mut CodeRng::seeded(42)).collect();
+      assert_eq!(a, b);
+      assert!(a.iter().all(|code| code.bytes().all(|c| ALPHABET.contains(&c))));
+      assert_ne!(a, candidates(&mut CodeRng::seeded(43)).collect::<Vec<_>>());
+   }
+
+   #[test]
+   fn test_collisions_retry_then_lengthen() {
+      let lens: Vec<usize> = candidates(&mut CodeRng::seeded(7)).map(|code| code.len()).collect();
+      assert_eq!(lens, [5, 5, 5, 6, 6, 6]);
+
+      // The same seed replays the codes already handed out, so each one collides
+      let mut taken = HashSet::new();
+      let first = candidates(&mut CodeRng::seeded(7)).find(|code| taken.insert(code.clone()));
+      let second = candidates(&mut CodeRng::seeded(7)).find(|code| taken.insert(code.clone()));
+      let third = candidates(&mut CodeRng::seeded(7)).find(|code| taken.insert(code.clone()));
+      let all: Vec<String> = candidates(&mut CodeRng::seeded(7)).collect();
+      assert_eq!(
+         [first, second, third],
+         [Some(all[0].clone()), Some(all[1].clone()), Some(all[2].clone())]
+      );
+
+      for _ in 3..MAX_ATTEMPTS {
+         candidates(&mut CodeRng::seeded(7)).find(|code| taken.insert(code.clone()));
+      }
+      assert_eq!(
+         candidates(&mut CodeRng::seeded(7)).find(|code| taken.insert(code.clone())),
+         None
+      );
+   }
+
+   #[test]
+   fn test_is_plausible() {
+      assert!(is_plausible("x7Qp2"));
+      assert!(!is_plausible(""));
+      assert!(!is_plausible("x7Qp2/../"));
+      assert!(!is_plausible(&"a".repeat(33)));
+   }
+}
@@ -0,0 +1,169 @@
+use crate::models::TagUid;
+
+const COOKIE_MAX_AGE_SECS: u32 = 365 * 24 * 60 * 60;
+
+/// What's known about a tap when deciding whether to flush a permanent redirect the client may
+/// still have cached from before the tag was switched to temporary redirects.
+#[derive(Debug, Clone, Copy)]
+pub struct StaleRedirect<'a> {
+   pub permanent_now: bool,
+   /// Unix time of the last permanent redirect served for this tag; `None` if it has always been
+   /// temporary.
+   pub served_permanent_until: Option<i64>,
+   pub last_seen_tap_count: Option<i32>,
+   pub tap_count: Option<i32>,
+   /// This tag's flush cookie, if the client sent one.
+   pub flushed_cookie: Option<&'a str>,
+}
+
+/// A fresh scan carries a counter past anything seen before and can't have been cached, so only
+/// revisits of an already-seen URL qualify. The cookie records which permanent era was flushed,
+/// so a tag that goes permanent and back again flushes once more.
+pub fn should_flush(tap: &StaleRedirect) -> bool {
+   if tap.permanent_now {
+      return false;
+   }
+   let Some(served_until) = tap.served_permanent_until else {
+      return false;
+   };
+   let revisit = matches!(
+      (tap.tap_count, tap.last_seen_tap_count),
+      (Some(count), Some(last_seen)) if count <= last_seen
+   );
+   let already_flushed = tap
+      .flushed_cookie
+      .and_then(|v| v.parse::<i64>().ok())
+      .is_some_and(|flushed_for| flushed_for >= served_until);
+   revisit && !already_flushed
+}
+
+pub fn cookie_name(id: &TagUid) -> String { format!("twag_flushed_{}", id) }
+
+/// Finds `name` in the value of a `Cookie` header.
+pub fn find_cookie<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+   header
+      .split(';')
+      .filter_map(|pair| pair.trim().split_once('='))
+      .find(|(key, _)| *key == name)
+      .map(|(_, value)| value)
+}
+
+/// Scoped to all of `/tag`: a cookie path of `/tag/{id}` wouldn't match the `/tag/{id}x{count}`
+/// URLs the tag actually produces.
+pub fn set_cookie(id: &TagUid, served_permanent_until: i64) -> String {
+   format!(
+      "{}={}; Path=/tag; Max-Age={}; HttpOnly; SameSite=Lax",
+      cookie_name(id),
+      served_permanent_until,
+      COOKIE_MAX_AGE_SECS
+   )
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn revisit<'a>() -> StaleRedirect<'a> {
+      StaleRedirect {
+         permanent_now: false,
+         served_permanent_until: Some(1_700_000_000),
+         last_seen_tap_count: Some(15),
+         tap_count: Some(12),
+         flushed_cookie: None,
+      }
+   }
+
+   #[test]
+   fn test_flushes_revisit_of_formerly_permanent_tag() {
+      assert!(should_flush(&revisit()));
+      assert!(should_flush(&StaleRedirect {
+         tap_count: Some(15),
+         ..revisit()
+      }));
+   }
+
+   #[test]
+   fn test_never_flushes_always_temporary_tags() {
+      assert!(!should_flush(&StaleRedirect {
+         served_permanent_until: None,
+         ..revisit()
+      }));
+   }
+
+   #[test]
+   fn test_never_flushes_while_still_permanent() {
+      assert!(!should_flush(&StaleRedirect {
+         permanent_now: true,
+         ..revisit()
+      }));
+   }
+
+   #[test]
+   fn test_never_flushes_fresh_scans() {
+      assert!(!should_flush(&StaleRedirect {
+         tap_count: Some(16),
+         ..revisit()
+      }));
+      assert!(!should_flush(&StaleRedirect {
+         tap_count: None,
+         ..revisit()
+      }));
+      assert!(!should_flush(&StaleRedirect {
+         last_seen_tap_count: None,
+         ..revisit()
+      }));
+   }
+
+   #[test]
+   fn test_cookie_marks_client_as_flushed_once_per_permanent_era() {
+      assert!(!should_flush(&StaleRedirect {
+         flushed_cookie: Some("1700000000"),
+         ..revisit()
+      }));
+      assert!(!should_flush(&StaleRedirect {
+         flushed_cookie: Some("1800000000"),
+         ..revisit()
+      }));
+
+      // Flushed for an earlier permanent era; the tag has since served permanent redirects again
+      assert!(should_flush(&StaleRedirect {
+         flushed_cookie: Some("1600000000"),
+         ..revisit()
+      }));
+
+      // A mangled cookie is treated as absent
+      assert!(should_flush(&StaleRedirect {
+         flushed_cookie: Some("yes"),
+         ..revisit()
+      }));
+      assert!(should_flush(&StaleRedirect {
+         flushed_cookie: Some(""),
+         ..revisit()
+      }));
+   }
+
+   #[test]
+   fn test_find_cookie() {
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      let name = cookie_name(&id);
+      let header = "session=abc; twag_flushed_055B88A23C1250=1700000000; twag_flushed_04A1B2C3D4E5F6=1";
+      assert_eq!(find_cookie(header, &name), Some("1700000000"));
+      assert_eq!(find_cookie("session=abc", &name), None);
+      assert_eq!(find_cookie("", &name), None);
+      // Other tags' cookies don't count
+      assert_eq!(find_cookie("twag_flushed_055B88A23C1250AA01BB=1", &name), None);
+   }
+
+   #[test]
+   fn test_set_cookie_round_trips() {
+      let id: TagUid = "055B88A23C1250".parse().unwrap();
+      let set = set_cookie(&id, 1_700_000_000);
+      assert!(set.contains("Path=/tag;"));
+      let pair = set.split(';').next().unwrap();
+      let flushed_cookie = find_cookie(pair, &cookie_name(&id));
+      assert!(!should_flush(&StaleRedirect {
+         flushed_cookie,
+         ..revisit()
+      }));
+   }
+}
@@ -0,0 +1,147 @@
+//! Parts of `AppState` a handler can extract on its own. A handler that needs only the database
+//! takes `State<DbState>` rather than `State<AppState>`, and one that needs two parts takes both;
+//! axum builds each from the router's `AppState` through `FromRef`. A test of such a handler then
+//! builds just the parts it uses, rather than a whole `AppState` with a Notion client, MQTT and
+//! the rest.
+//!
+//! New handlers should extract the narrowest of these that covers what they use, and keep
+//! `State<AppState>` for those that need most of it. A part that several handlers want together
+//! belongs here, with its `FromRef` impl beside it, as `ScanState` is for the scan path's.
+
+use std::sync::{Arc, RwLock};
+
+use axum::extract::FromRef;
+
+use crate::failover::{Failover, Preload};
+use crate::fallback::SharedProber;
+use crate::geo::GeoIp;
+use crate::health::{self, HealthToken};
+use crate::mqtt::MqttPublisher;
+use crate::negative_cache::NegativeCache;
+use crate::notion::picker::NotionPicker;
+use crate::pool::ScalingPool;
+use crate::provision::ProvisioningKey;
+use crate::redact::Redaction;
+use crate::replica::ReadPools;
+use crate::retention::RetentionReport;
+use crate::security::CookieKey;
+use crate::settings::SharedSettings;
+use crate::tag_lock::TagLocks;
+use crate::visitors::VisitorHasher;
+use crate::webhook::WebhookConfig;
+use crate::AppState;
+
+/// The primary, for writes, and where to read from.
+#[derive(Clone)]
+pub struct DbState {
+   pub pool: ScalingPool,
+   pub reads: ReadPools,
+}
+
+impl FromRef<AppState> for DbState {
+   fn from_ref(state: &AppState) -> Self {
+      DbState {
+         pool: state.pool.clone(),
+         reads: state.reads.clone(),
+      }
+   }
+}
+
+/// The settings, as reloaded on SIGHUP; `load` them once per request.
+#[derive(Clone)]
+pub struct SettingsState(pub SharedSettings);
+
+impl FromRef<AppState> for SettingsState {
+   fn from_ref(state: &AppState) -> Self { SettingsState(state.settings.clone()) }
+}
+
+/// The redirects kept for when Postgres can't be reached, see `failover`, and the ids recently
+/// scanned and not found, see `negative_cache`.
+#[derive(Clone)]
+pub struct CacheState {
+   pub failover: Failover,
+   pub preload: Preload,
+   pub negative_cache: NegativeCache,
+}
+
+impl FromRef<AppState> for CacheState {
+   fn from_ref(state: &AppState) -> Self {
+      CacheState {
+         failover: state.failover.clone(),
+         preload: state.preload,
+         negative_cache: state.negative_cache.clone(),
+      }
+   }
+}
+
+/// What a scan uses besides the database and the caches: the settings, and everything a tap is
+/// counted, gated, probed and announced with.
+#[derive(Clone)]
+pub struct ScanState {
+   pub settings: SharedSettings,
+   pub visitor_hasher: Option<VisitorHasher>,
+   pub cookie_key: Option<CookieKey>,
+   pub geoip: Option<Arc<GeoIp>>,
+   pub redaction: Option<Redaction>,
+   pub prober: SharedProber,
+   pub tag_locks: Arc<TagLocks>,
+   pub notion_outbox: bool,
+   pub mqtt: Option<MqttPublisher>,
+   pub webhook: Option<WebhookConfig>,
+}
+
+impl FromRef<AppState> for ScanState {
+   fn from_ref(state: &AppState) -> Self {
+      ScanState {
+         settings: state.settings.clone(),
+         visitor_hasher: state.visitor_hasher.clone(),
+         cookie_key: state.cookie_key.clone(),
+         geoip: state.geoip.clone(),
+         redaction: state.redaction.clone(),
+         prober: state.prober.clone(),
+         tag_locks: state.tag_locks.clone(),
+         notion_outbox: state.notion_outbox,
+         mqtt: state.mqtt.clone(),
+         webhook: state.webhook.clone(),
+      }
+   }
+}
+
+/// What `/healthz` reports on beyond the database and the caches, and the keys its deep check
+/// looks at; see `health`.
+#[derive(Clone)]
+pub struct HealthState {
+   pub health_token: Option<HealthToken>,
+   pub unready: Arc<RwLock<Option<health::Report>>>,
+   pub retention: Arc<RwLock<RetentionReport>>,
+   pub mqtt: Option<MqttPublisher>,
+   pub cookie_key: Option<CookieKey>,
+   pub provisioning_key: Option<ProvisioningKey>,
+}
+
+impl FromRef<AppState> for HealthState {
+   fn from_ref(state: &AppState) -> Self {
+      HealthState {
+         health_token: state.health_token.clone(),
+         unready: state.unready.clone(),
+         retention: state.retention.clone(),
+         mqtt: state.mqtt.clone(),
+         cookie_key: state.cookie_key.clone(),
+         provisioning_key: state.provisioning_key.clone(),
+      }
+   }
+}
+
+/// The page picker, when Notion is enabled.
+#[derive(Clone)]
+pub struct NotionState {
+   pub picker: Option<Arc<NotionPicker>>,
+}
+
+impl FromRef<AppState> for NotionState {
+   fn from_ref(state: &AppState) -> Self {
+      NotionState {
+         picker: state.notion_picker.clone(),
+      }
+   }
+}
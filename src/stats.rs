@@ -0,0 +1,212 @@
+//! The tap aggregates kept on each `twag_tags` row: `access_count`, `last_accessed`,
+//! `last_seen_tap_count` and `served_permanent_until`.
+//!
+//! Taps are recorded from their own tasks, which commit in whatever order they finish, and taps
+//! deferred while Postgres was unreachable are recorded minutes later. So writes arrive out of
+//! order, and the aggregates are kept so that order can't matter:
+//!
+//! - `access_count` is only ever incremented, by the number of counted taps written, never set;
+//! - the other three only move forward, to the greater of what's stored and what's written, as
+//!   Postgres' `GREATEST` does, which skips NULLs.
+//!
+//! Any order and any grouping of the same taps then leaves the same row behind. For the exact
+//! order taps happened in, read `twag_tap_events`, stamped with when each scan was answered.
+//!
+//! `write` is the only place the tap path writes these columns, and `Aggregates::apply` mirrors
+//! it for tests. Accepting a reprogrammed tag's new baseline (`reprogram`) lowers
+//! `last_seen_tap_count` on purpose, and is the one write that may.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+
+use crate::models::TagUid;
+
+/// What's aggregated of one tap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapRecord {
+   /// When the scan was answered, not when it's written.
+   pub at: DateTime<Utc>,
+   pub tap_count: Option<i32>,
+   /// Uncounted hits only move `served_permanent_until`.
+   pub counted: bool,
+   pub served_permanent: bool,
+}
+
+/// Any number of taps of one tag, folded into one write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Batch {
+   pub counted: i32,
+   pub latest: Option<DateTime<Utc>>,
+   pub highest_tap_count: Option<i32>,
+   pub served_permanent: Option<DateTime<Utc>>,
+}
+
+impl Batch {
+   pub fn of(taps: &[TapRecord]) -> Batch {
+      let mut batch = Batch::default();
+      for tap in taps {
+         batch.add(tap);
+      }
+      batch
+   }
+
+   pub fn add(&mut self, tap: &TapRecord) {
+      if tap.counted {
+         self.counted += 1;
+         self.latest = self.latest.max(Some(tap.at));
+         self.highest_tap_count = self.highest_tap_count.max(tap.tap_count);
+      }
+      if tap.served_permanent {
+         self.served_permanent = self.served_permanent.max(Some(tap.at));
+      }
+   }
+
+   pub fn is_empty(&self) -> bool { self.counted == 0 && self.served_permanent.is_none() }
+}
+
+/// The columns `write` updates, as stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Aggregates {
+   pub access_count: i32,
+   pub last_accessed: Option<DateTime<Utc>>,
+   pub last_seen_tap_count: Option<i32>,
+   pub served_permanent_until: Option<DateTime<Utc>>,
+}
+
+impl Aggregates {
+   /// What `write` leaves behind.
+   pub fn apply(&self, batch: &Batch) -> Aggregates {
+      Aggregates {
+         access_count: self.access_count + batch.counted,
+         last_accessed: self.last_accessed.max(batch.latest),
+         last_seen_tap_count: self.last_seen_tap_count.max(batch.highest_tap_count),
+         served_permanent_until: self.served_permanent_until.max(batch.served_permanent),
+      }
+   }
+}
+
+pub async fn write(conn: &mut PgConnection, id: &TagUid, batch: &Batch) -> Result<(), sqlx::Error> {
+   if batch.is_empty() {
+      return Ok(());
+   }
+   sqlx::query!(
+      r#"UPDATE twag_tags SET
+            access_count = COALESCE(access_count, 0) + $2,
+            last_accessed = GREATEST(last_accessed, $3::text::timestamptz),
+            last_seen_tap_count = GREATEST(last_seen_tap_count, $4),
+            served_permanent_until = GREATEST(served_permanent_until, $5::text::timestamptz)
+         WHERE id = $1"#,
+      id as &TagUid,
+      batch.counted,
+      batch.latest.map(|at| at.to_rfc3339()),
+      batch.highest_tap_count,
+      batch.served_permanent.map(|at| at.to_rfc3339()),
+   )
+   .execute(conn)
+   .await?;
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::short_code::CodeRng;
+   use chrono::TimeDelta;
+
+   fn at(s: &str) -> DateTime<Utc> { DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc) }
+
+   fn below(rng: &mut CodeRng, n: u64) -> u64 { rng.next_u64() % n }
+
+   /// A tag's taps in the order they happened: the counter climbs, though not every tap reports it.
+   fn stream(rng: &mut CodeRng, len: usize) -> Vec<TapRecord> {
+      let start = at("2026-10-16T12:00:00Z");
+      (0..len)
+         .map(|n| TapRecord {
+            at: start + TimeDelta::seconds(n as i64 * 7),
+            tap_count: (below(rng, 4) != 0).then_some(n as i32 + 1),
+            counted: below(rng, 5) != 0,
+            served_permanent: below(rng, 2) == 0,
+         })
+         .collect()
+   }
+
+   fn shuffle(rng: &mut CodeRng, taps: &mut [TapRecord]) {
+      for i in (1..taps.len()).rev() {
+         taps.swap(i, below(rng, i as u64 + 1) as usize);
+      }
+   }
+
+   #[test]
+   fn test_any_order_and_grouping_gives_the_same_aggregates() {
+      let before = Aggregates {
+         access_count: 40,
+         last_accessed: Some(at("2026-10-01T00:00:00Z")),
+         last_seen_tap_count: Some(3),
+         served_permanent_until: None,
+      };
+      for seed in 0..200 {
+         let mut rng = CodeRng::seeded(seed);
+         let ordered = stream(&mut rng, 1 + below(&mut rng, 60) as usize);
+         let expected = ordered
+            .iter()
+            .fold(before, |row, tap| row.apply(&Batch::of(std::slice::from_ref(tap))));
+
+         let mut shuffled = ordered.clone();
+         shuffle(&mut rng, &mut shuffled);
+         let mut row = before;
+         let mut rest = shuffled.as_slice();
+         while !rest.is_empty() {
+            let (batch, after) = rest.split_at(1 + below(&mut rng, rest.len() as u64) as usize);
+            row = row.apply(&Batch::of(batch));
+            rest = after;
+         }
+         assert_eq!(row, expected, "seed {seed}");
+      }
+   }
+
+   #[test]
+   fn test_late_taps_never_move_aggregates_back() {
+      let row = Aggregates {
+         access_count: 10,
+         last_accessed: Some(at("2026-10-16T12:00:00Z")),
+         last_seen_tap_count: Some(20),
+         served_permanent_until: Some(at("2026-10-16T12:00:00Z")),
+      };
+      // Deferred during an outage, recorded after later taps
+      let late = Batch::of(&[TapRecord {
+         at: at("2026-10-16T11:00:00Z"),
+         tap_count: Some(12),
+         counted: true,
+         served_permanent: true,
+      }]);
+      assert_eq!(
+         row.apply(&late),
+         Aggregates {
+            access_count: 11,
+            ..row
+         }
+      );
+   }
+
+   #[test]
+   fn test_uncounted_taps_only_move_served_permanent_until() {
+      let tap = TapRecord {
+         at: at("2026-10-16T12:00:00Z"),
+         tap_count: Some(50),
+         counted: false,
+         served_permanent: false,
+      };
+      assert!(Batch::of(&[tap]).is_empty());
+      let permanent = Batch::of(&[TapRecord {
+         served_permanent: true,
+         ..tap
+      }]);
+      assert_eq!(
+         Aggregates::default().apply(&permanent),
+         Aggregates {
+            served_permanent_until: Some(tap.at),
+            ..Aggregates::default()
+         }
+      );
+   }
+}
@@ -0,0 +1,178 @@
+//! Pages with a row per tag, sent while they render: the tag listing and the print sheet. Each page
+//! is rendered once without its rows, with `ROWS` where they go, and split there into a `Shell`.
+//! The rows are read on a task of their own, rendered a batch at a time as Postgres returns them,
+//! and sent between the shell's halves, so the first byte leaves once the first batch is read
+//! however many rows follow.
+//!
+//! `Shell::join` renders the same page in one piece, for tests to compare against. The row
+//! templates end without a newline, so rendering them a batch at a time adds nothing between
+//! batches, and pages are only ever split at the end of a line, which `minify` relies on.
+
+use std::mem;
+
+use axum::body::Body;
+use axum::BoxError;
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use tokio::sync::mpsc;
+
+/// Where a page's rows go.
+pub const ROWS: &str = "<!--rows-->";
+/// Rows rendered, and sent, at a time.
+pub const BATCH: usize = 200;
+
+/// A query's rows, `Vec`s of them at a time, or the error that stopped it.
+pub type Batches<T> = mpsc::Receiver<Result<Vec<T>, sqlx::Error>>;
+
+/// Runs `query` on a task of its own, sending its rows on in batches of `size`. The task stops
+/// early if the receiver is dropped, e.g. when the client goes away.
+pub fn read_batches<T>(pool: PgPool, mut query: QueryBuilder<'static, Postgres>, size: usize) -> Batches<T>
+where
+   T: for<'r> FromRow<'r, PgRow> + Send + Unpin + 'static,
+{
+   let (sender, batches) = mpsc::channel(1);
+   tokio::spawn(async move {
+      let mut rows = query.build_query_as::<T>().fetch(&pool);
+      let mut batch = Vec::with_capacity(size);
+      loop {
+         match rows.try_next().await {
+            Ok(Some(row)) => {
+               batch.push(row);
+               if batch.len() == size && sender.send(Ok(mem::take(&mut batch))).await.is_err() {
+                  return;
+               }
+            }
+            Ok(None) => break,
+            Err(e) => {
+               let _ = sender.send(Err(e)).await;
+               return;
+            }
+         }
+      }
+      if !batch.is_empty() {
+         let _ = sender.send(Ok(batch)).await;
+      }
+   });
+   batches
+}
+
+/// The first batch, read before rendering the shell so it can say when there are no rows at all.
+pub async fn first_batch<T>(batches: &mut Batches<T>) -> Result<Vec<T>, sqlx::Error> {
+   batches.recv().await.unwrap_or(Ok(Vec::new()))
+}
+
+/// `first`, then the rest of `batches`, each rendered by `render` as it's needed.
+pub fn render_batches<T, R>(
+   first: Vec<T>,
+   batches: Batches<T>,
+   render: R,
+) -> impl Stream<Item = Result<String, BoxError>> + Send + 'static
+where
+   T: Send + 'static,
+   R: Fn(Vec<T>) -> Result<String, askama::Error> + Send + 'static,
+{
+   let rest = stream::unfold(batches, |mut batches| async move {
+      let batch = batches.recv().await?;
+      Some((batch, batches))
+   });
+   stream::once(async { Ok(first) })
+      .chain(rest)
+      .map(move |batch| -> Result<String, BoxError> { Ok(render(batch?)?) })
+}
+
+/// A page rendered around `ROWS`.
+#[derive(Debug)]
+pub struct Shell {
+   head: String,
+   tail: String,
+}
+
+impl Shell {
+   /// Splits `page` where its rows go; `None` unless `ROWS` is in it exactly once.
+   pub fn split(page: &str) -> Option<Shell> {
+      let (head, tail) = page.split_once(ROWS)?;
+      (!tail.contains(ROWS)).then(|| Shell {
+         head: head.to_string(),
+         tail: tail.to_string(),
+      })
+   }
+
+   /// A page with nowhere for rows to go, as when there are none.
+   pub fn whole(page: String) -> Shell {
+      Shell {
+         head: page,
+         tail: String::new(),
+      }
+   }
+
+   /// The whole page, with `rows` rendered in one piece.
+   pub fn join(&self, rows: &str) -> String { [self.head.as_str(), rows, self.tail.as_str()].concat() }
+
+   /// The page as a response body: the head, each batch of rows as it's rendered, then the tail.
+   /// An error partway through ends the body early, since the status has already been sent; the
+   /// client sees a truncated response.
+   pub fn stream(self, rows: impl Stream<Item = Result<String, BoxError>> + Send + 'static, minified: bool) -> Body {
+      let Shell { head, tail } = self;
+      let chunks = stream::once(async { Ok(head) })
+         .chain(rows)
+         .chain(stream::once(async { Ok(tail) }))
+         .map_ok(move |chunk| match minified {
+            true => minify(&chunk),
+            false => chunk,
+         });
+      Body::from_stream(chunks)
+   }
+}
+
+/// Drops indentation and blank lines. Only whitespace starting a line goes, so nothing renders
+/// differently on a page without `pre` or `textarea`, as neither streamed page has. Minifying
+/// chunk by chunk gives the same bytes as minifying the whole page, as long as every chunk ends
+/// at the end of a line.
+pub fn minify(html: &str) -> String {
+   let mut minified = String::with_capacity(html.len());
+   for line in html.split_inclusive('\n') {
+      minified.push_str(line.trim_start());
+   }
+   minified
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_split() {
+      let shell = Shell::split("<table>\n<!--rows-->\n</table>\n").unwrap();
+      assert_eq!(shell.join("<tr></tr>\n"), "<table>\n<tr></tr>\n\n</table>\n");
+      assert!(Shell::split("<table></table>").is_none());
+      assert!(Shell::split("<!--rows--><!--rows-->").is_none());
+      assert_eq!(Shell::whole("<p>None</p>".to_string()).join(""), "<p>None</p>");
+   }
+
+   #[test]
+   fn test_minify() {
+      assert_eq!(
+         minify("<table>\n   <tr>\n      <td>a  b</td>\n\n   </tr>\n</table>"),
+         "<table>\n<tr>\n<td>a  b</td>\n</tr>\n</table>"
+      );
+      let page = "<ul>\n   <li>1</li>\n   <li>2</li>\n</ul>\n";
+      let (head, rest) = page.split_at(5);
+      let (rows, tail) = rest.split_at(rest.rfind("</ul>").unwrap());
+      assert_eq!([minify(head), minify(rows), minify(tail)].concat(), minify(page));
+   }
+
+   #[tokio::test]
+   async fn test_batches_render_in_order() {
+      let (sender, batches) = mpsc::channel(4);
+      for batch in [vec![3, 4], vec![5]] {
+         sender.send(Ok(batch)).await.unwrap();
+      }
+      drop(sender);
+      let rendered: Vec<String> = render_batches(vec![1, 2], batches, |batch| Ok(format!("{batch:?}\n")))
+         .try_collect()
+         .await
+         .unwrap();
+      assert_eq!(rendered, ["[1, 2]\n", "[3, 4]\n", "[5]\n"]);
+   }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use tokio::sync::OwnedMutexGuard;
+
+use crate::models::TagUid;
+
+/// How long a mutation waits on another one of the same tag before giving up.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serializes multi-step mutations of a tag within this process, so that contention is reported
+/// as a clean 423 instead of surfacing as a lock timeout in Postgres. Row locks remain what keeps
+/// the data correct, including across instances.
+#[derive(Default)]
+pub struct TagLocks {
+   /// Only the holders and waiters keep a tag's mutex alive, so idle tags take no space.
+   locks: Mutex<HashMap<TagUid, Weak<tokio::sync::Mutex<()>>>>,
+}
+
+/// Held for as long as the mutation runs.
+pub struct TagGuard {
+   _guards: Vec<OwnedMutexGuard<()>>,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("Tag {0} is being changed by another request")]
+pub struct Locked(pub TagUid);
+
+impl IntoResponse for Locked {
+   fn into_response(self) -> Response {
+      let message = format!("{}; try again in a moment.\n", self);
+      (StatusCode::LOCKED, [(header::RETRY_AFTER, "1")], message).into_response()
+   }
+}
+
+impl TagLocks {
+   fn lock_for(&self, id: &TagUid) -> Arc<tokio::sync::Mutex<()>> {
+      let mut locks = self.locks.lock().unwrap();
+      locks.retain(|_, lock| lock.strong_count() > 0);
+      if let Some(lock) = locks.get(id).and_then(Weak::upgrade) {
+         return lock;
+      }
+      let lock = Arc::default();
+      locks.insert(*id, Arc::downgrade(&lock));
+      lock
+   }
+
+   /// Locks every tag in `ids`, always in the same order so that two mutations spanning several
+   /// tags can't deadlock. Fails with the first tag still held elsewhere once `timeout` is up.
+   pub async fn acquire(&self, ids: &[TagUid], timeout: Duration) -> Result<TagGuard, Locked> {
+      let mut ids = ids.to_vec();
+      ids.sort();
+      ids.dedup();
+      let deadline = tokio::time::Instant::now() + timeout;
+      let mut guards = Vec::with_capacity(ids.len());
+      for id in ids {
+         match tokio::time::timeout_at(deadline, self.lock_for(&id).lock_owned()).await {
+            Ok(guard) => guards.push(guard),
+            Err(_) => return Err(Locked(id)),
+         }
+      }
+      Ok(TagGuard { _guards: guards })
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const HOLD: Duration = Duration::from_millis(200);
+   const IMPATIENT: Duration = Duration::from_millis(20);
+
+   fn tag(s: &str) -> TagUid { s.parse().unwrap() }
+
+   /// A stand-in for a multi-step mutation: takes the lock, then holds it across an await.
+   async fn mutate(locks: Arc<TagLocks>, ids: Vec<TagUid>, timeout: Duration) -> Result<(), Locked> {
+      let _guard = locks.acquire(&ids, timeout).await?;
+      tokio::time::sleep(HOLD).await;
+      Ok(())
+   }
+
+   #[tokio::test]
+   async fn test_exactly_one_concurrent_mutation_wins() {
+      let locks = Arc::new(TagLocks::default());
+      let id = tag("055B88A23C1250");
+      let tasks: Vec<_> = (0..8)
+         .map(|_| tokio::spawn(mutate(locks.clone(), vec![id], IMPATIENT)))
+         .collect();
+      let mut results = Vec::new();
+      for task in tasks {
+         results.push(task.await.unwrap());
+      }
+      assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+      assert!(results
+         .iter()
+         .all(|r| matches!(r, Ok(()) | Err(Locked(locked)) if *locked == id)));
+   }
+
+   #[tokio::test]
+   async fn test_patient_mutations_run_in_turn() {
+      let locks = Arc::new(TagLocks::default());
+      let id = tag("055B88A23C1250");
+      let first = tokio::spawn(mutate(locks.clone(), vec![id], IMPATIENT));
+      tokio::time::sleep(IMPATIENT).await;
+      assert_eq!(mutate(locks.clone(), vec![id], HOLD * 4).await, Ok(()));
+      assert_eq!(first.await.unwrap(), Ok(()));
+   }
+
+   #[tokio::test]
+   async fn test_other_tags_are_not_blocked() {
+      let locks = Arc::new(TagLocks::default());
+      let held = tokio::spawn(mutate(locks.clone(), vec![tag("055B88A23C1250")], IMPATIENT));
+      tokio::time::sleep(IMPATIENT).await;
+      let other = vec![tag("04A1B2C3D4E5F6")];
+      assert!(locks.acquire(&other, IMPATIENT).await.is_ok());
+      assert_eq!(
+         locks.acquire(&[other[0], tag("055B88A23C1250")], IMPATIENT).await.err(),
+         Some(Locked(tag("055B88A23C1250")))
+      );
+      held.await.unwrap().unwrap();
+   }
+
+   #[tokio::test]
+   async fn test_idle_locks_are_dropped() {
+      let locks = TagLocks::default();
+      drop(locks.acquire(&[tag("055B88A23C1250")], IMPATIENT).await.unwrap());
+      let _held = locks.acquire(&[tag("04A1B2C3D4E5F6")], IMPATIENT).await.unwrap();
+      let ids: Vec<TagUid> = locks.locks.lock().unwrap().keys().copied().collect();
+      assert_eq!(ids, [tag("04A1B2C3D4E5F6")]);
+   }
+
+   #[test]
+   fn test_locked_response() {
+      let response = Locked(tag("055B88A23C1250")).into_response();
+      assert_eq!(response.status(), StatusCode::LOCKED);
+      assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+   }
+}
@@ -0,0 +1,232 @@
+//! The physical models a tag row can stand for, and what each can do. Everything that depends on
+//! the model (counter warnings, capacity checks, whether there's anything to write over NFC) reads
+//! `Capabilities` from the table here rather than matching on models itself.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ndef;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TagModel {
+   Ntag213,
+   Ntag215,
+   Ntag216,
+   /// NTAG 424 DNA.
+   Ntag424,
+   /// A printed code, with no chip.
+   QrOnly,
+   /// Tags created before models were recorded, or whose model wasn't given.
+   #[default]
+   Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+   /// As `twag_tags.tag_model` stores it, and forms and CSV files give it.
+   pub key: &'static str,
+   pub name: &'static str,
+   /// Bytes available for the NDEF message; `None` without a chip, or for an unknown one.
+   pub ndef_bytes: Option<usize>,
+   /// Can mirror its scan counter into the URL it serves.
+   pub counter: bool,
+   /// Can sign its scans as SUN messages.
+   pub sun: bool,
+   /// Is written over NFC.
+   pub nfc: bool,
+}
+
+/// Every model, in `TagModel::ALL` order.
+const CAPABILITIES: [(TagModel, Capabilities); 6] = [
+   (
+      TagModel::Ntag213,
+      Capabilities {
+         key: "ntag213",
+         name: "NTAG213",
+         ndef_bytes: Some(144),
+         counter: true,
+         sun: false,
+         nfc: true,
+      },
+   ),
+   (
+      TagModel::Ntag215,
+      Capabilities {
+         key: "ntag215",
+         name: "NTAG215",
+         ndef_bytes: Some(504),
+         counter: true,
+         sun: false,
+         nfc: true,
+      },
+   ),
+   (
+      TagModel::Ntag216,
+      Capabilities {
+         key: "ntag216",
+         name: "NTAG216",
+         ndef_bytes: Some(888),
+         counter: true,
+         sun: false,
+         nfc: true,
+      },
+   ),
+   (
+      TagModel::Ntag424,
+      Capabilities {
+         key: "ntag424",
+         name: "NTAG 424 DNA",
+         // Its NDEF file, as shipped
+         ndef_bytes: Some(256),
+         counter: true,
+         sun: true,
+         nfc: true,
+      },
+   ),
+   (
+      TagModel::QrOnly,
+      Capabilities {
+         key: "qr_only",
+         name: "QR code only",
+         ndef_bytes: None,
+         counter: false,
+         sun: false,
+         nfc: false,
+      },
+   ),
+   (
+      TagModel::Unknown,
+      Capabilities {
+         key: "unknown",
+         name: "Unknown",
+         ndef_bytes: None,
+         counter: false,
+         sun: false,
+         nfc: true,
+      },
+   ),
+];
+
+impl TagModel {
+   pub const ALL: [TagModel; 6] = [
+      TagModel::Ntag213,
+      TagModel::Ntag215,
+      TagModel::Ntag216,
+      TagModel::Ntag424,
+      TagModel::QrOnly,
+      TagModel::Unknown,
+   ];
+
+   pub fn capabilities(&self) -> &'static Capabilities { &CAPABILITIES[*self as usize].1 }
+
+   pub fn as_str(&self) -> &'static str { self.capabilities().key }
+
+   pub fn name(&self) -> &'static str { self.capabilities().name }
+
+   /// From a form field or CSV cell, where blank means unknown.
+   pub fn from_input(s: &str) -> Result<TagModel, String> {
+      match s.trim() {
+         "" => Ok(TagModel::Unknown),
+         s => s.to_ascii_lowercase().parse(),
+      }
+   }
+
+   /// Why a tag of this model, created from a scan without a counter, may be misconfigured.
+   pub fn counter_warning(&self, has_counter: bool) -> Option<String> {
+      (self.capabilities().counter && !has_counter).then(|| {
+         format!(
+            "An {} can mirror its scan counter, but none came with this id; taps will go uncounted \
+             until counter mirroring is turned on",
+            self.name()
+         )
+      })
+   }
+
+   /// Warnings for `uri` not fitting this model. An unknown model is checked against the small
+   /// parts most tags are, as before models were recorded; a printed code holds anything a QR
+   /// code can.
+   pub fn capacity_warnings(&self, uri: &str) -> Vec<String> {
+      match (self, self.capabilities().ndef_bytes) {
+         (TagModel::Unknown, _) => ndef::capacity_warnings(uri),
+         (_, Some(user_bytes)) => ndef::capacity_warning(uri, self.name(), user_bytes)
+            .into_iter()
+            .collect(),
+         (_, None) => Vec::new(),
+      }
+   }
+}
+
+impl fmt::Display for TagModel {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for TagModel {
+   type Err = String;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      TagModel::ALL
+         .into_iter()
+         .find(|model| model.as_str() == s)
+         .ok_or_else(|| {
+            let known: Vec<&str> = TagModel::ALL.iter().map(TagModel::as_str).collect();
+            format!("Unknown tag model '{}'; expected one of {}", s, known.join(", "))
+         })
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_table_is_in_order() {
+      for (n, model) in TagModel::ALL.iter().enumerate() {
+         assert_eq!(CAPABILITIES[n].0, *model);
+         assert_eq!(model.as_str().parse::<TagModel>(), Ok(*model));
+      }
+   }
+
+   #[test]
+   fn test_capacities_agree_with_ndef() {
+      for (name, user_bytes) in ndef::NTAG_CAPACITIES {
+         let model = TagModel::ALL.into_iter().find(|model| model.name() == name).unwrap();
+         assert_eq!(model.capabilities().ndef_bytes, Some(user_bytes));
+      }
+   }
+
+   #[test]
+   fn test_input() {
+      assert_eq!(TagModel::from_input(""), Ok(TagModel::Unknown));
+      assert_eq!(TagModel::from_input(" NTAG424 "), Ok(TagModel::Ntag424));
+      assert_eq!(TagModel::from_input("qr_only"), Ok(TagModel::QrOnly));
+      assert!(TagModel::from_input("ntag210")
+         .unwrap_err()
+         .contains("ntag213, ntag215"));
+   }
+
+   #[test]
+   fn test_counter_warnings() {
+      assert!(TagModel::Ntag213.counter_warning(false).is_some());
+      assert!(TagModel::Ntag424.counter_warning(false).is_some());
+      assert_eq!(TagModel::Ntag213.counter_warning(true), None);
+      // Nothing to mirror a counter with, or no telling
+      assert_eq!(TagModel::QrOnly.counter_warning(false), None);
+      assert_eq!(TagModel::Unknown.counter_warning(false), None);
+   }
+
+   #[test]
+   fn test_capacity_by_model() {
+      // 200 bytes of TLV: too big for an NTAG213 only
+      let uri = format!("https://xz.ws/{}", "a".repeat(186));
+      assert_eq!(ndef::tlv_len(&uri), 200);
+      assert_eq!(TagModel::Ntag213.capacity_warnings(&uri).len(), 1);
+      assert!(TagModel::Ntag215.capacity_warnings(&uri).is_empty());
+      assert!(TagModel::Ntag424.capacity_warnings(&uri).is_empty());
+      assert!(TagModel::QrOnly.capacity_warnings(&uri).is_empty());
+      assert_eq!(TagModel::Unknown.capacity_warnings(&uri), ndef::capacity_warnings(&uri));
+
+      let long = format!("https://xz.ws/{}", "a".repeat(300));
+      assert!(TagModel::Ntag424.capacity_warnings(&long)[0].contains("NTAG 424 DNA only holds 256"));
+      assert!(TagModel::Ntag216.capacity_warnings(&long).is_empty());
+   }
+}
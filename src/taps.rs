@@ -0,0 +1,316 @@
+use std::fmt;
+
+use crate::maintenance::Maintenance;
+use crate::resolve::RedirectKind;
+
+/// The `channel` of hits that came through a tag's short link rather than a scan.
+pub const CHANNEL_SHORTLINK: &str = "shortlink";
+
+/// Which branch of the resolver answered a tap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolutionPath {
+   /// Redirected to the tag's base target.
+   Direct,
+   /// Redirected to a per-language target.
+   Language,
+   /// Redirected to a per-country target.
+   Geo,
+   /// Redirected to the target of a window its kit scheduled; see `schedule`.
+   Schedule,
+   Maintenance,
+   /// Redirected from the failover cache while Postgres was unreachable.
+   CacheStale,
+   /// Checked out or in; a confirmation page, no redirect.
+   Stateful,
+   /// A contact card, as a page or a vCard download.
+   Contact,
+   /// The page that flushes a cached permanent redirect before sending the client on.
+   Flush,
+   /// The page held up in place of a tag that looks reprogrammed; see `reprogram`.
+   Quarantine,
+   /// The page shown before the tag activates.
+   Pending,
+   /// Redirected to the tag's fallback, its own target having failed a probe.
+   Fallback,
+}
+
+impl ResolutionPath {
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         ResolutionPath::Direct => "direct",
+         ResolutionPath::Language => "language",
+         ResolutionPath::Geo => "geo",
+         ResolutionPath::Schedule => "schedule",
+         ResolutionPath::Maintenance => "maintenance",
+         ResolutionPath::CacheStale => "cache_stale",
+         ResolutionPath::Stateful => "stateful",
+         ResolutionPath::Contact => "contact",
+         ResolutionPath::Flush => "flush",
+         ResolutionPath::Quarantine => "quarantine",
+         ResolutionPath::Pending => "pending",
+         ResolutionPath::Fallback => "fallback",
+      }
+   }
+}
+
+impl fmt::Display for ResolutionPath {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+/// What a tap was answered with, as recorded in `twag_tap_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Served {
+   pub path: ResolutionPath,
+   /// Where the client was sent. To keep rows small this is `None` when it's the tag's base
+   /// target, and also when nothing was redirected to; `status` tells the two apart.
+   pub target_url: Option<String>,
+   pub status: u16,
+}
+
+/// Everything about a found tag that decides how a tap is answered, in the order the resolver
+/// checks it.
+#[derive(Debug, Clone, Copy)]
+pub struct Resolution<'a> {
+   pub quarantined: bool,
+   pub maintenance: Option<Maintenance<'a>>,
+   pub stateful: bool,
+   pub contact: bool,
+   pub flush: bool,
+   pub base_target: &'a str,
+   /// The negotiated per-language target, if any.
+   pub language_target: Option<&'a str>,
+   /// The per-country target, if any; it wins over the language target.
+   pub geo_target: Option<&'a str>,
+   /// The target of the kit's window open now, if any; it wins over all of the tag's own.
+   pub scheduled_target: Option<&'a str>,
+   pub redirect_kind: RedirectKind,
+}
+
+fn unless_base(url: &str, base: &str) -> Option<String> { Some(url.to_string()).filter(|url| url != base) }
+
+impl Resolution<'_> {
+   pub fn served(&self) -> Served {
+      let redirect_status = self.redirect_kind.status();
+      let target = self
+         .scheduled_target
+         .or(self.geo_target)
+         .or(self.language_target)
+         .unwrap_or(self.base_target);
+      let (path, target_url, status) = match self.maintenance {
+         _ if self.quarantined => (ResolutionPath::Quarantine, None, 200),
+         Some(Maintenance::Redirect(url)) => (ResolutionPath::Maintenance, unless_base(url, self.base_target), 302),
+         Some(Maintenance::Page) => (ResolutionPath::Maintenance, None, 503),
+         None if self.stateful => (ResolutionPath::Stateful, None, 200),
+         None if self.contact => (ResolutionPath::Contact, None, 200),
+         None if self.flush => (ResolutionPath::Flush, unless_base(target, self.base_target), 200),
+         None => match (self.scheduled_target, self.geo_target, self.language_target) {
+            (Some(url), _, _) if url != self.base_target => {
+               (ResolutionPath::Schedule, Some(url.to_string()), redirect_status)
+            }
+            (None, Some(url), _) if url != self.base_target => {
+               (ResolutionPath::Geo, Some(url.to_string()), redirect_status)
+            }
+            (None, None, Some(url)) if url != self.base_target => {
+               (ResolutionPath::Language, Some(url.to_string()), redirect_status)
+            }
+            _ => (ResolutionPath::Direct, None, redirect_status),
+         },
+      };
+      Served {
+         path,
+         target_url,
+         status,
+      }
+   }
+}
+
+/// A tap as served from the failover cache, which only ever holds the base target.
+pub fn served_stale() -> Served {
+   Served {
+      path: ResolutionPath::CacheStale,
+      target_url: None,
+      status: 307,
+   }
+}
+
+/// Quotes a CSV field when it needs it, per RFC 4180.
+pub fn csv_field(value: &str) -> String {
+   if value.contains([',', '"', '\r', '\n']) {
+      format!("\"{}\"", value.replace('"', "\"\""))
+   } else {
+      value.to_string()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const BASE: &str = "https://example.com/";
+
+   fn plain() -> Resolution<'static> {
+      Resolution {
+         quarantined: false,
+         maintenance: None,
+         stateful: false,
+         contact: false,
+         flush: false,
+         base_target: BASE,
+         language_target: None,
+         geo_target: None,
+         scheduled_target: None,
+         redirect_kind: RedirectKind::Permanent,
+      }
+   }
+
+   fn served(path: ResolutionPath, target_url: Option<&str>, status: u16) -> Served {
+      Served {
+         path,
+         target_url: target_url.map(str::to_string),
+         status,
+      }
+   }
+
+   #[test]
+   fn test_direct() {
+      assert_eq!(plain().served(), served(ResolutionPath::Direct, None, 308));
+      let temporary = Resolution {
+         redirect_kind: RedirectKind::Temporary,
+         ..plain()
+      };
+      assert_eq!(temporary.served(), served(ResolutionPath::Direct, None, 307));
+      let see_other = Resolution {
+         redirect_kind: RedirectKind::SeeOther,
+         ..plain()
+      };
+      assert_eq!(see_other.served(), served(ResolutionPath::Direct, None, 303));
+   }
+
+   #[test]
+   fn test_language() {
+      let localized = Resolution {
+         language_target: Some("https://example.com/de"),
+         ..plain()
+      };
+      assert_eq!(
+         localized.served(),
+         served(ResolutionPath::Language, Some("https://example.com/de"), 308)
+      );
+
+      // A language target identical to the base is stored as the base
+      let same = Resolution {
+         language_target: Some(BASE),
+         ..plain()
+      };
+      assert_eq!(same.served(), served(ResolutionPath::Direct, None, 308));
+   }
+
+   #[test]
+   fn test_geo_wins_over_language() {
+      let both = Resolution {
+         language_target: Some("https://example.com/de"),
+         geo_target: Some("https://store.example.com/dach"),
+         ..plain()
+      };
+      assert_eq!(
+         both.served(),
+         served(ResolutionPath::Geo, Some("https://store.example.com/dach"), 308)
+      );
+
+      // A geo target identical to the base is the base, not a reason to fall back to language
+      let same = Resolution {
+         geo_target: Some(BASE),
+         language_target: Some("https://example.com/de"),
+         ..plain()
+      };
+      assert_eq!(same.served(), served(ResolutionPath::Direct, None, 308));
+   }
+
+   #[test]
+   fn test_schedule_wins_over_geo() {
+      let scheduled = Resolution {
+         geo_target: Some("https://store.example.com/dach"),
+         scheduled_target: Some("https://live.example.com/"),
+         redirect_kind: RedirectKind::Temporary,
+         ..plain()
+      };
+      assert_eq!(
+         scheduled.served(),
+         served(ResolutionPath::Schedule, Some("https://live.example.com/"), 307)
+      );
+
+      // A window pointing at the base target still keeps the tag's own targets out of it
+      let same = Resolution {
+         scheduled_target: Some(BASE),
+         ..scheduled
+      };
+      assert_eq!(same.served(), served(ResolutionPath::Direct, None, 307));
+   }
+
+   #[test]
+   fn test_maintenance_wins() {
+      let redirect = Resolution {
+         maintenance: Some(Maintenance::Redirect("https://status.example.com/")),
+         stateful: true,
+         contact: true,
+         ..plain()
+      };
+      assert_eq!(
+         redirect.served(),
+         served(ResolutionPath::Maintenance, Some("https://status.example.com/"), 302)
+      );
+      let page = Resolution {
+         maintenance: Some(Maintenance::Page),
+         ..plain()
+      };
+      assert_eq!(page.served(), served(ResolutionPath::Maintenance, None, 503));
+   }
+
+   #[test]
+   fn test_quarantine_wins_over_maintenance() {
+      let quarantined = Resolution {
+         quarantined: true,
+         maintenance: Some(Maintenance::Redirect("https://status.example.com/")),
+         stateful: true,
+         ..plain()
+      };
+      assert_eq!(quarantined.served(), served(ResolutionPath::Quarantine, None, 200));
+   }
+
+   #[test]
+   fn test_pages() {
+      let stateful = Resolution {
+         stateful: true,
+         contact: true,
+         ..plain()
+      };
+      assert_eq!(stateful.served(), served(ResolutionPath::Stateful, None, 200));
+      let contact = Resolution {
+         contact: true,
+         flush: true,
+         ..plain()
+      };
+      assert_eq!(contact.served(), served(ResolutionPath::Contact, None, 200));
+      let flush = Resolution {
+         flush: true,
+         language_target: Some("https://example.com/fr"),
+         ..plain()
+      };
+      assert_eq!(
+         flush.served(),
+         served(ResolutionPath::Flush, Some("https://example.com/fr"), 200)
+      );
+   }
+
+   #[test]
+   fn test_cache_stale() {
+      assert_eq!(served_stale(), served(ResolutionPath::CacheStale, None, 307));
+   }
+
+   #[test]
+   fn test_csv_field() {
+      assert_eq!(csv_field("https://example.com/"), "https://example.com/");
+      assert_eq!(csv_field("a,b"), "\"a,b\"");
+      assert_eq!(csv_field("say \"hi\"\n"), "\"say \"\"hi\"\"\n\"");
+   }
+}
@@ -0,0 +1,429 @@
+//! Stored target URLs as they go out in a `Location` header. Rows imported from older systems can
+//! hold URLs far longer than browsers and proxies accept, or characters a header can't carry at
+//! all; those get a page with a link instead of a redirect.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use axum::http::HeaderValue;
+use tracing::info;
+
+use crate::pool::ScalingPool;
+
+/// Conservative enough for every browser and for the header limits of common proxies.
+pub const DEFAULT_MAX_LOCATION_LEN: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unsafe {
+   /// Longer than the limit once normalized; holds the normalized length.
+   TooLong(usize),
+   /// Still not a valid header value once normalized. `normalize` should make this unreachable.
+   Unrepresentable,
+}
+
+impl fmt::Display for Unsafe {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         Unsafe::TooLong(len) => write!(f, "{} bytes long", len),
+         Unsafe::Unrepresentable => write!(f, "not representable in a header"),
+      }
+   }
+}
+
+fn is_hex(byte: Option<&u8>) -> bool { byte.is_some_and(u8::is_ascii_hexdigit) }
+
+/// Percent-encodes every byte that can't appear in a header value or a URL as-is (controls,
+/// spaces, non-ASCII), and every `%` that doesn't already start an escape. Existing escapes are
+/// left alone, even ones that don't decode to valid UTF-8; that's for the destination to judge.
+fn encode(raw: &str) -> Cow<'_, str> {
+   let bytes = raw.as_bytes();
+   let needs_encoding = |i: usize| match bytes[i] {
+      b'%' => !(is_hex(bytes.get(i + 1)) && is_hex(bytes.get(i + 2))),
+      byte => !byte.is_ascii_graphic(),
+   };
+   if !(0..bytes.len()).any(needs_encoding) {
+      return Cow::Borrowed(raw);
+   }
+
+   let mut normalized = String::with_capacity(bytes.len() + 16);
+   for (i, &byte) in bytes.iter().enumerate() {
+      if needs_encoding(i) {
+         normalized.push_str(&format!("%{:02X}", byte));
+      } else {
+         normalized.push(byte as char);
+      }
+   }
+   Cow::Owned(normalized)
+}
+
+/// Where the host of an absolute `scheme://` URL lies in `raw`, without any userinfo or port.
+fn host_range(raw: &str) -> Option<Range<usize>> {
+   let scheme_end = raw.find("://")?;
+   let scheme = &raw[..scheme_end];
+   if !scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+      || !scheme
+         .bytes()
+         .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.'))
+   {
+      return None;
+   }
+   let start = scheme_end + 3;
+   let end = raw[start..].find(['/', '?', '#']).map_or(raw.len(), |i| start + i);
+   let host_start = raw[start..end].rfind('@').map_or(start, |i| start + i + 1);
+   let authority = &raw[host_start..end];
+   let host_end = match authority.rfind([':', ']']) {
+      Some(i) if authority.as_bytes()[i] == b':' && authority[i + 1..].bytes().all(|b| b.is_ascii_digit()) => {
+         host_start + i
+      }
+      _ => end,
+   };
+   Some(host_start..host_end)
+}
+
+/// The form stored and sent in `Location`: an internationalized host in punycode, then
+/// everything `encode` escapes percent-encoded. Hosts already in punycode, and existing escapes,
+/// are left as they are, so normalizing twice changes nothing.
+pub fn normalize(raw: &str) -> Cow<'_, str> {
+   let raw = raw.trim();
+   if let Some(host) = host_range(raw).filter(|host| !raw[host.clone()].is_ascii()) {
+      // Empty when the host isn't a valid domain; then it's only escaped, like the rest
+      let ascii = url::quirks::domain_to_ascii(&raw[host.clone()]);
+      if !ascii.is_empty() {
+         let rebuilt = format!("{}{}{}", &raw[..host.start], ascii, &raw[host.end..]);
+         return Cow::Owned(encode(&rebuilt).into_owned());
+      }
+   }
+   encode(raw)
+}
+
+/// Replaces each run of escapes that decodes to non-ASCII UTF-8 with its characters. Escaped
+/// ASCII (`%20`, `%2F`) stays escaped, since decoding it could change how the URL reads.
+fn decode_non_ascii(raw: &str) -> Cow<'_, str> {
+   let bytes = raw.as_bytes();
+   let escaped = |i: usize| -> Option<u8> {
+      let hex = raw.get(i + 1..i + 3).filter(|_| bytes[i] == b'%')?;
+      u8::from_str_radix(hex, 16).ok().filter(|byte| !byte.is_ascii())
+   };
+   if !(0..bytes.len()).any(|i| escaped(i).is_some()) {
+      return Cow::Borrowed(raw);
+   }
+
+   let mut decoded = String::with_capacity(raw.len());
+   let mut i = 0;
+   while i < bytes.len() {
+      let mut run = Vec::new();
+      let mut end = i;
+      while let Some(byte) = escaped(end) {
+         run.push(byte);
+         end += 3;
+      }
+      match std::str::from_utf8(&run) {
+         Ok(text) if !run.is_empty() => {
+            decoded.push_str(text);
+            i = end;
+         }
+         _ if !run.is_empty() => {
+            decoded.push_str(&raw[i..end]);
+            i = end;
+         }
+         _ => {
+            let c = raw[i..].chars().next().unwrap();
+            decoded.push(c);
+            i += c.len_utf8();
+         }
+      }
+   }
+   Cow::Owned(decoded)
+}
+
+/// A stored URL as people would write it, for admin pages: a punycode host shown in Unicode, and
+/// escaped non-ASCII characters decoded. Only for display; links still use the stored form. Like
+/// browsers, hosts mixing look-alike scripts stay in punycode, so they can't pass for another.
+pub fn display_url(raw: &str) -> Cow<'_, str> {
+   let raw = raw.trim();
+   let punycoded = |host: &Range<usize>| {
+      let host = &raw[host.clone()];
+      host
+         .split('.')
+         .any(|label| label.get(..4).is_some_and(|prefix| prefix.eq_ignore_ascii_case("xn--")))
+         && mixed_script_label(host).is_none()
+   };
+   match host_range(raw).filter(punycoded) {
+      Some(host) => {
+         let unicode = url::quirks::domain_to_unicode(&raw[host.clone()]);
+         let shown = format!("{}{}{}", &raw[..host.start], unicode, &raw[host.end..]);
+         Cow::Owned(decode_non_ascii(&shown).into_owned())
+      }
+      None => decode_non_ascii(raw),
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+   Latin,
+   Greek,
+   Cyrillic,
+}
+
+/// Only the scripts whose letters pass for each other; a label mixing Latin with, say, Han is
+/// ordinary.
+fn script(c: char) -> Option<Script> {
+   match c {
+      'a'..='z' | 'A'..='Z' | '\u{c0}'..='\u{24f}' => Some(Script::Latin),
+      '\u{370}'..='\u{3ff}' => Some(Script::Greek),
+      '\u{400}'..='\u{52f}' => Some(Script::Cyrillic),
+      _ => None,
+   }
+}
+
+/// The first label of `host` (punycode or not) that mixes Latin, Greek or Cyrillic letters, like
+/// the Cyrillic `а` in `аpple.com`; such hosts are rejected under `TWAG_STRICT_IDN`.
+pub fn mixed_script_label(host: &str) -> Option<String> {
+   url::quirks::domain_to_unicode(host)
+      .split('.')
+      .find(|label| {
+         let mut scripts = label.chars().filter_map(script);
+         let first = scripts.next();
+         scripts.any(|script| Some(script) != first)
+      })
+      .map(str::to_string)
+}
+
+/// The `Location` header for a stored target URL, or why it can't be one.
+pub fn location(raw: &str, max_len: usize) -> Result<HeaderValue, Unsafe> {
+   let normalized = normalize(raw);
+   if normalized.len() > max_len {
+      return Err(Unsafe::TooLong(normalized.len()));
+   }
+   HeaderValue::from_str(&normalized).map_err(|_| Unsafe::Unrepresentable)
+}
+
+/// A stored target URL that is redirected to only after rewriting, or not at all.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Offender {
+   pub tag_id: String,
+   /// `None` for the tag's base target, otherwise the language of a translated target.
+   pub lang: Option<String>,
+   pub len: usize,
+   /// `None` if the URL only needed percent-encoding.
+   pub problem: Option<Unsafe>,
+}
+
+impl fmt::Display for Offender {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "{}", self.tag_id)?;
+      if let Some(lang) = &self.lang {
+         write!(f, " [{}]", lang)?;
+      }
+      match self.problem {
+         Some(problem) => write!(f, ": served as a link page, {}", problem),
+         None => write!(f, ": {} bytes, percent-encoded on redirect", self.len),
+      }
+   }
+}
+
+pub fn audit_urls(stored: Vec<(String, Option<String>, String)>, max_len: usize) -> Vec<Offender> {
+   stored
+      .into_iter()
+      .filter_map(|(tag_id, lang, url)| {
+         let problem = location(&url, max_len).err();
+         let rewritten = matches!(normalize(&url), Cow::Owned(_));
+         (problem.is_some() || rewritten).then(|| Offender {
+            tag_id,
+            lang,
+            len: url.len(),
+            problem,
+         })
+      })
+      .collect()
+}
+
+pub async fn load_audit(pool: &ScalingPool, max_len: usize) -> Result<Vec<Offender>, sqlx::Error> {
+   let stored = sqlx::query!(
+      r#"SELECT id::text AS "tag_id!", NULL::text AS "lang?", target_url AS "target_url!" FROM twag_tags
+         UNION ALL
+         SELECT tag_id::text, lang::text, target_url FROM twag_tag_targets_i18n
+         ORDER BY 1, 2 NULLS FIRST"#
+   )
+   .fetch_all(&pool.get())
+   .await?
+   .into_iter()
+   .map(|row| (row.tag_id, row.lang, row.target_url))
+   .collect();
+   Ok(audit_urls(stored, max_len))
+}
+
+/// The `TWAG_MODE=audit-urls` entry point: prints every stored target URL that can't be
+/// redirected to as-is.
+pub async fn run(pool: &ScalingPool, max_len: usize) -> Result<(), sqlx::Error> {
+   let offenders = load_audit(pool, max_len).await?;
+   if offenders.is_empty() {
+      println!("All target URLs can be redirected to as stored.");
+   }
+   for offender in &offenders {
+      println!("{}", offender);
+   }
+   info!(count = offenders.len(), "Audited target URLs");
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_ordinary_urls_are_untouched() {
+      let url = "https://example.com/a%20b?q=1&r=%E2%9C%93#frag";
+      assert!(matches!(normalize(url), Cow::Borrowed(_)));
+      assert_eq!(location(url, DEFAULT_MAX_LOCATION_LEN).unwrap(), url);
+   }
+
+   #[test]
+   fn test_raw_non_ascii_and_spaces_are_encoded() {
+      assert_eq!(
+         normalize("https://example.com/Ünïcode path\t?x=✓"),
+         "https://example.com/%C3%9Cn%C3%AFcode%20path%09?x=%E2%9C%93"
+      );
+      assert_eq!(
+         location("https://example.com/\u{7f}\r\n", DEFAULT_MAX_LOCATION_LEN).unwrap(),
+         "https://example.com/%7F"
+      );
+   }
+
+   #[test]
+   fn test_invalid_percent_sequences() {
+      // Escapes that don't decode to UTF-8 are still valid in a URL
+      let invalid_utf8 = "https://example.com/%FF%FE%C3";
+      assert_eq!(normalize(invalid_utf8), invalid_utf8);
+      assert_eq!(
+         normalize("https://example.com/100%?a=%zz&b=%4"),
+         "https://example.com/100%25?a=%25zz&b=%254"
+      );
+   }
+
+   #[test]
+   fn test_idn_hosts_become_punycode() {
+      assert_eq!(
+         normalize("https://münchen.example/straße?q=ä"),
+         "https://xn--mnchen-3ya.example/stra%C3%9Fe?q=%C3%A4"
+      );
+      assert_eq!(
+         normalize("https://user@MÜNCHEN.example:8443"),
+         "https://user@xn--mnchen-3ya.example:8443"
+      );
+      assert_eq!(
+         location("https://münchen.example/", DEFAULT_MAX_LOCATION_LEN).unwrap(),
+         "https://xn--mnchen-3ya.example/"
+      );
+   }
+
+   #[test]
+   fn test_normalizing_twice_changes_nothing() {
+      let urls = [
+         "https://münchen.example/🎉?x=✓",
+         "https://xn--mnchen-3ya.example/%F0%9F%8E%89",
+         "https://example.com/100%?a=%zz",
+      ];
+      for url in urls {
+         let once = normalize(url).into_owned();
+         assert_eq!(normalize(&once), once, "{url}");
+      }
+      assert_eq!(
+         normalize("https://example.com/party/🎉"),
+         "https://example.com/party/%F0%9F%8E%89"
+      );
+      let punycoded = "https://xn--mnchen-3ya.example/%F0%9F%8E%89";
+      assert!(matches!(normalize(punycoded), Cow::Borrowed(_)));
+   }
+
+   #[test]
+   fn test_display_url_round_trips() {
+      let typed = "https://münchen.example/🎉?q=ä%20b";
+      let stored = normalize(typed);
+      assert_eq!(stored, "https://xn--mnchen-3ya.example/%F0%9F%8E%89?q=%C3%A4%20b");
+      assert_eq!(display_url(&stored), typed);
+
+      // Escaped ASCII, and escapes that aren't UTF-8, stay as they are
+      let url = "https://example.com/a%2Fb/%FF%FE";
+      assert_eq!(display_url(url), url);
+
+      let confusable = "https://xn--pple-43d.com/";
+      assert_eq!(display_url(confusable), confusable);
+   }
+
+   #[test]
+   fn test_mixed_script_hosts() {
+      // The first letter is Cyrillic
+      assert_eq!(mixed_script_label("\u{430}pple.com").as_deref(), Some("\u{430}pple"));
+      let punycoded = url::quirks::domain_to_ascii("\u{430}pple.com");
+      assert_eq!(mixed_script_label(&punycoded).as_deref(), Some("\u{430}pple"));
+
+      for host in [
+         "apple.com",
+         "münchen.example",
+         "пример.рф",
+         "例え.テスト",
+         "xn--mnchen-3ya.example",
+      ] {
+         assert_eq!(mixed_script_label(host), None, "{host}");
+      }
+   }
+
+   #[test]
+   fn test_too_long_urls_are_rejected() {
+      let url = format!("https://sharepoint.example.com/?{}", "a".repeat(10 * 1024));
+      assert_eq!(
+         location(&url, DEFAULT_MAX_LOCATION_LEN),
+         Err(Unsafe::TooLong(url.len()))
+      );
+      assert!(location(&url, url.len()).is_ok());
+
+      // The limit applies to the encoded form
+      let url = format!("https://example.com/{}", "é".repeat(700));
+      assert_eq!(
+         location(&url, DEFAULT_MAX_LOCATION_LEN),
+         Err(Unsafe::TooLong(20 + 700 * 6))
+      );
+   }
+
+   #[test]
+   fn test_audit_lists_only_offenders() {
+      let long = format!("https://example.com/{}", "a".repeat(3000));
+      let stored = vec![
+         ("055B88A23C1250".to_string(), None, "https://example.com/".to_string()),
+         (
+            "055B88A23C1250".to_string(),
+            Some("de".to_string()),
+            "https://example.com/ä".to_string(),
+         ),
+         ("04A1B2C3D4E5F6".to_string(), None, long.clone()),
+      ];
+      let offenders = audit_urls(stored, DEFAULT_MAX_LOCATION_LEN);
+      assert_eq!(
+         offenders,
+         [
+            Offender {
+               tag_id: "055B88A23C1250".to_string(),
+               lang: Some("de".to_string()),
+               len: 22,
+               problem: None,
+            },
+            Offender {
+               tag_id: "04A1B2C3D4E5F6".to_string(),
+               lang: None,
+               len: long.len(),
+               problem: Some(Unsafe::TooLong(long.len())),
+            },
+         ]
+      );
+      assert_eq!(
+         offenders[0].to_string(),
+         "055B88A23C1250 [de]: 22 bytes, percent-encoded on redirect"
+      );
+      assert_eq!(
+         offenders[1].to_string(),
+         "04A1B2C3D4E5F6: served as a link page, 3020 bytes long"
+      );
+   }
+}
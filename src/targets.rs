@@ -0,0 +1,191 @@
+//! Tags pointing at the same destination share a row in `twag_targets`, so moving that destination
+//! is one edit at `/targets/{id}` rather than one per tag. Each tag's `target_url` is kept as a
+//! copy of its target's URL by everything here, so scans never need the join.
+
+use sqlx::{Connection, PgConnection};
+
+use crate::models::TagUid;
+
+/// The key two URLs are deduplicated by: what the `url` crate makes of them, which lowercases the
+/// scheme and host, drops default ports, and gives an empty path a `/`. Anything unparseable is
+/// only trimmed. The migration creating `twag_targets` approximates this in SQL.
+pub fn normalize(raw: &str) -> String {
+   let raw = raw.trim();
+   match url::Url::parse(raw) {
+      Ok(url) => url.to_string(),
+      Err(_) => raw.to_string(),
+   }
+}
+
+/// A destination and how many tags, trashed ones included, point at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+   pub id: i64,
+   pub url: String,
+   pub tag_count: i64,
+}
+
+/// The target of each URL, in order, creating those that don't exist yet.
+pub async fn ensure(conn: &mut PgConnection, urls: &[String]) -> Result<Vec<i64>, sqlx::Error> {
+   let normalized: Vec<String> = urls.iter().map(|url| normalize(url)).collect();
+   // Updating a conflicting row to itself makes it come back from RETURNING, and waits for a
+   // concurrent insert of the same URL rather than missing it
+   sqlx::query_scalar!(
+      r#"WITH wanted AS (
+            SELECT * FROM unnest($1::text[], $2::text[]) WITH ORDINALITY AS w (url, normalized, ord)
+         ), targets AS (
+            INSERT INTO twag_targets (url, normalized)
+            SELECT DISTINCT ON (normalized) url, normalized FROM wanted ORDER BY normalized, ord
+            ON CONFLICT (normalized) DO UPDATE SET normalized = EXCLUDED.normalized
+            RETURNING id, normalized
+         )
+         SELECT targets.id AS "id!" FROM wanted JOIN targets USING (normalized) ORDER BY wanted.ord"#,
+      urls,
+      &normalized,
+   )
+   .fetch_all(conn)
+   .await
+}
+
+pub async fn find_or_insert(conn: &mut PgConnection, url: &str) -> Result<i64, sqlx::Error> {
+   let ids = ensure(conn, &[url.to_string()]).await?;
+   Ok(ids[0])
+}
+
+/// Every destination in use, the most shared first.
+pub async fn list(conn: &mut PgConnection) -> Result<Vec<Target>, sqlx::Error> {
+   sqlx::query_as!(
+      Target,
+      r#"SELECT g.id, g.url, count(*) AS "tag_count!"
+         FROM twag_targets g JOIN twag_tags t ON t.target_id = g.id
+         GROUP BY g.id ORDER BY count(*) DESC, g.url"#
+   )
+   .fetch_all(conn)
+   .await
+}
+
+pub async fn get(conn: &mut PgConnection, id: i64) -> Result<Option<Target>, sqlx::Error> {
+   sqlx::query_as!(
+      Target,
+      r#"SELECT g.id, g.url, (SELECT count(*) FROM twag_tags t WHERE t.target_id = g.id) AS "tag_count!"
+         FROM twag_targets g WHERE g.id = $1"#,
+      id,
+   )
+   .fetch_optional(conn)
+   .await
+}
+
+/// The tags pointing at a target.
+pub async fn tags(conn: &mut PgConnection, id: i64) -> Result<Vec<TagUid>, sqlx::Error> {
+   sqlx::query_scalar!(
+      r#"SELECT id AS "id!: TagUid" FROM twag_tags WHERE target_id = $1 ORDER BY id"#,
+      id,
+   )
+   .fetch_all(conn)
+   .await
+}
+
+/// What moving a target changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Moved {
+   /// The target the tags now point at: the same one, unless the new URL already had its own, in
+   /// which case the two are merged.
+   pub target_id: i64,
+   pub tags: Vec<TagUid>,
+}
+
+/// Points every tag using target `id` at `url`, with an audit entry per tag. `None` if there's no
+/// such target.
+pub async fn move_target(conn: &mut PgConnection, id: i64, url: &str) -> Result<Option<Moved>, sqlx::Error> {
+   let mut tx = conn.begin().await?;
+   let Some(before) = sqlx::query_scalar!("SELECT url FROM twag_targets WHERE id = $1 FOR UPDATE", id)
+      .fetch_optional(&mut *tx)
+      .await?
+   else {
+      return Ok(None);
+   };
+
+   let normalized = normalize(url);
+   let merge_into = sqlx::query_scalar!(
+      "SELECT id FROM twag_targets WHERE normalized = $1 AND id <> $2 FOR UPDATE",
+      normalized,
+      id,
+   )
+   .fetch_optional(&mut *tx)
+   .await?;
+   let target_id = match merge_into {
+      Some(other) => other,
+      None => {
+         sqlx::query!(
+            "UPDATE twag_targets SET url = $2, normalized = $3 WHERE id = $1",
+            id,
+            url,
+            normalized,
+         )
+         .execute(&mut *tx)
+         .await?;
+         id
+      }
+   };
+
+   let tags = sqlx::query_scalar!(
+      r#"UPDATE twag_tags SET target_id = $2, target_url = (SELECT url FROM twag_targets WHERE id = $2)
+         WHERE target_id = $1
+         RETURNING id AS "id!: TagUid""#,
+      id,
+      target_id,
+   )
+   .fetch_all(&mut *tx)
+   .await?;
+   if target_id != id {
+      sqlx::query!("DELETE FROM twag_targets WHERE id = $1", id)
+         .execute(&mut *tx)
+         .await?;
+   }
+
+   let audited: Vec<String> = tags.iter().map(TagUid::to_string).collect();
+   sqlx::query!(
+      r#"INSERT INTO twag_tag_audit (tag_id, action, before, after)
+         SELECT unnest($1::text[])::tag_uid, 'retarget', $2, (SELECT url FROM twag_targets WHERE id = $3)"#,
+      &audited,
+      before,
+      target_id,
+   )
+   .execute(&mut *tx)
+   .await?;
+   tx.commit().await?;
+
+   Ok(Some(Moved { target_id, tags }))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_normalize_dedupes_spellings_of_one_url() {
+      let same = [
+         "https://example.com/",
+         "https://example.com",
+         "HTTPS://Example.COM",
+         "https://example.com:443/",
+         " https://example.com/ ",
+      ];
+      for url in same {
+         assert_eq!(normalize(url), "https://example.com/", "{url}");
+      }
+      assert_eq!(normalize("http://example.com:80/a?b#c"), "http://example.com/a?b#c");
+   }
+
+   #[test]
+   fn test_normalize_keeps_paths_and_queries_apart() {
+      assert_ne!(normalize("https://example.com/a"), normalize("https://example.com/A"));
+      assert_ne!(normalize("https://example.com/?q=1"), normalize("https://example.com/"));
+      assert_ne!(normalize("http://example.com/"), normalize("https://example.com/"));
+   }
+
+   #[test]
+   fn test_normalize_leaves_unparseable_urls_alone() {
+      assert_eq!(normalize(" not a url "), "not a url");
+   }
+}
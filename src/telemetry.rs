@@ -0,0 +1,310 @@
+//! Where log lines go, how they look, and which are kept. `Telemetry::new` builds a subscriber from
+//! a `TelemetryConfig` without installing it, so tests can install one of their own for as long as
+//! they need; `Telemetry::install` makes it the global one at startup.
+//!
+//! The filter sits behind a reload layer, so `Telemetry::set_filter` can turn verbosity up on a
+//! live instance, which `PUT /admin/log-level` does. A change can put the configured filter back on
+//! its own after a while, so debug logging isn't left on by whoever forgot to turn it off.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{info, Level, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, ParseError};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+use crate::redact::Redacting;
+
+/// Added to every filter: hyper's client is chatty below info, and its protocol warnings are mostly
+/// clients going away.
+const QUIET: [&str; 2] = ["hyper::client=info", "hyper::proto=warn"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+   #[default]
+   Compact,
+   Pretty,
+   Json,
+}
+
+impl Format {
+   /// `RUST_FMT`; anything but `json` or `pretty` is the compact default.
+   pub fn from_var(value: Option<&str>) -> Format {
+      match value {
+         Some("json") => Format::Json,
+         Some("pretty") => Format::Pretty,
+         _ => Format::Compact,
+      }
+   }
+
+   /// What's logged from targets no directive names.
+   pub fn default_level(self) -> Level {
+      match self {
+         Format::Json => Level::INFO,
+         Format::Pretty => Level::DEBUG,
+         Format::Compact => Level::WARN,
+      }
+   }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelemetryConfig {
+   pub format: Format,
+   /// What's logged from targets no directive names.
+   pub default_level: Level,
+   /// An `EnvFilter` string, as in `RUST_LOG`.
+   pub directives: String,
+   /// Shortens tag ids in every line; see `redact`.
+   pub redact_tag_ids: bool,
+}
+
+impl TelemetryConfig {
+   /// From `RUST_FMT` and `RUST_LOG` (default `info`), without redaction.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+      let format = Format::from_var(var("RUST_FMT").as_deref());
+      TelemetryConfig {
+         format,
+         default_level: format.default_level(),
+         directives: var("RUST_LOG").unwrap_or_else(|| "info".into()),
+         redact_tag_ids: false,
+      }
+   }
+
+   fn quieted(&self, filter: EnvFilter) -> EnvFilter {
+      QUIET.iter().fold(filter, |filter, directive| {
+         filter.add_directive(directive.parse().unwrap())
+      })
+   }
+
+   /// The configured filter. Directives that don't parse are skipped, as `RUST_LOG`'s always were.
+   fn filter(&self) -> EnvFilter {
+      self.quieted(
+         EnvFilter::builder()
+            .with_default_directive(self.default_level.into())
+            .parse_lossy(&self.directives),
+      )
+   }
+
+   /// `directives` in place of the configured ones. Unlike at startup, a typo is refused rather
+   /// than skipped, so nobody is left wondering why nothing changed.
+   fn parse(&self, directives: &str) -> Result<EnvFilter, ParseError> {
+      let filter = EnvFilter::builder()
+         .with_default_directive(self.default_level.into())
+         .parse(directives)?;
+      Ok(self.quieted(filter))
+   }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+   #[error("Invalid filter: {0}")]
+   Invalid(#[from] ParseError),
+   #[error("The subscriber is gone: {0}")]
+   Gone(#[from] reload::Error),
+}
+
+/// Changes the filter of the subscriber it was built with; clones change the same one.
+#[derive(Clone)]
+pub struct Telemetry {
+   config: Arc<TelemetryConfig>,
+   handle: reload::Handle<EnvFilter, Registry>,
+   /// Bumped by every change, so a revert only ever undoes the change that scheduled it.
+   generation: Arc<Mutex<u64>>,
+}
+
+impl Telemetry {
+   /// A subscriber writing `config`'s lines to `writer`, and the handle to its filter. Nothing is
+   /// installed; see `install`, or `tracing::subscriber::set_default` in tests.
+   pub fn new<W>(config: TelemetryConfig, writer: W) -> (impl Subscriber + Send + Sync + 'static, Telemetry)
+   where
+      W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+   {
+      let (filter, handle) = reload::Layer::new(config.filter());
+      let writer = Redacting::new(writer, config.redact_tag_ids);
+      let format = fmt::format().with_timer(fmt::time::ChronoUtc::rfc_3339());
+      let output = match config.format {
+         Format::Json => fmt::layer()
+            .with_writer(writer)
+            .event_format(format.json().with_target(false).with_source_location(true))
+            .boxed(),
+         Format::Pretty => fmt::layer()
+            .with_writer(writer)
+            .event_format(format.pretty().with_source_location(true))
+            .boxed(),
+         Format::Compact => fmt::layer().with_writer(writer).event_format(format).boxed(),
+      };
+      let subscriber = Registry::default().with(filter).with(output);
+      let telemetry = Telemetry {
+         config: Arc::new(config),
+         handle,
+         generation: Arc::default(),
+      };
+      (subscriber, telemetry)
+   }
+
+   /// Logs to stdout for the rest of the process. Panics if a global subscriber is already set.
+   pub fn install(config: TelemetryConfig) -> Telemetry {
+      let (subscriber, telemetry) = Telemetry::new(config, std::io::stdout);
+      subscriber.init();
+      telemetry
+   }
+
+   pub fn config(&self) -> &TelemetryConfig { &self.config }
+
+   /// The filter in effect.
+   pub fn current(&self) -> Result<String, FilterError> { Ok(self.handle.with_current(|f| f.to_string())?) }
+
+   /// Filters by `directives` from now on. With `revert_after`, the configured filter comes back
+   /// then, unless the filter has been changed again since.
+   pub fn set_filter(&self, directives: &str, revert_after: Option<Duration>) -> Result<(), FilterError> {
+      let filter = self.config.parse(directives)?;
+      let generation = {
+         let mut generation = self.generation.lock().unwrap();
+         self.handle.reload(filter)?;
+         *generation += 1;
+         *generation
+      };
+      if let Some(after) = revert_after {
+         let telemetry = self.clone();
+         tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            telemetry.revert(generation);
+         });
+      }
+      Ok(())
+   }
+
+   /// Puts the configured filter back, unless it's been changed since `generation`.
+   fn revert(&self, generation: u64) {
+      let mut current = self.generation.lock().unwrap();
+      if *current != generation {
+         return;
+      }
+      // Only fails once the subscriber is dropped, when nothing's logging anyway
+      if self.handle.reload(self.config.filter()).is_ok() {
+         *current += 1;
+         drop(current);
+         info!(directives = %self.config.directives, "Log filter reverted");
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use std::io;
+   use tracing::{debug, warn};
+
+   #[derive(Clone, Default)]
+   struct Captured(Arc<Mutex<Vec<u8>>>);
+
+   impl io::Write for Captured {
+      fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+         self.0.lock().unwrap().extend_from_slice(buf);
+         Ok(buf.len())
+      }
+
+      fn flush(&mut self) -> io::Result<()> { Ok(()) }
+   }
+
+   impl Captured {
+      /// What's been logged since the last call.
+      fn take(&self) -> String { String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap() }
+   }
+
+   fn config(directives: &str) -> TelemetryConfig {
+      TelemetryConfig {
+         directives: directives.to_string(),
+         ..TelemetryConfig::from_vars(|_| None)
+      }
+   }
+
+   fn capture(config: TelemetryConfig) -> (tracing::subscriber::DefaultGuard, Telemetry, Captured) {
+      let captured = Captured::default();
+      let writer = {
+         let captured = captured.clone();
+         move || captured.clone()
+      };
+      let (subscriber, telemetry) = Telemetry::new(config, writer);
+      (tracing::subscriber::set_default(subscriber), telemetry, captured)
+   }
+
+   #[test]
+   fn test_config_from_vars() {
+      let config = TelemetryConfig::from_vars(|_| None);
+      assert_eq!(config.format, Format::Compact);
+      assert_eq!(config.default_level, Level::WARN);
+      assert_eq!(config.directives, "info");
+      assert!(!config.redact_tag_ids);
+
+      let config = TelemetryConfig::from_vars(|name| match name {
+         "RUST_FMT" => Some("json".to_string()),
+         "RUST_LOG" => Some("twag=debug".to_string()),
+         _ => None,
+      });
+      assert_eq!(config.format, Format::Json);
+      assert_eq!(config.default_level, Level::INFO);
+      assert_eq!(config.directives, "twag=debug");
+      assert_eq!(Format::from_var(Some("pretty")).default_level(), Level::DEBUG);
+      assert_eq!(Format::from_var(Some("logfmt")), Format::Compact);
+   }
+
+   #[test]
+   fn test_set_filter_applies_immediately() {
+      let (_guard, telemetry, captured) = capture(config("warn"));
+      debug!("before");
+      warn!("kept");
+      let logged = captured.take();
+      assert!(!logged.contains("before") && logged.contains("kept"), "{logged}");
+
+      telemetry.set_filter("debug", None).unwrap();
+      debug!("after");
+      assert!(captured.take().contains("after"));
+      assert!(telemetry.current().unwrap().contains("debug"));
+   }
+
+   #[test]
+   fn test_invalid_filters_are_refused() {
+      let (_guard, telemetry, captured) = capture(config("warn"));
+      assert!(matches!(
+         telemetry.set_filter("twag=loud", None),
+         Err(FilterError::Invalid(_))
+      ));
+      debug!("still quiet");
+      assert_eq!(captured.take(), "");
+   }
+
+   #[test]
+   fn test_a_revert_only_undoes_its_own_change() {
+      let (_guard, telemetry, captured) = capture(config("warn"));
+      telemetry.set_filter("debug", None).unwrap();
+      let first = *telemetry.generation.lock().unwrap();
+      telemetry.set_filter("info", None).unwrap();
+
+      // The first change's revert comes due after the second change; it leaves the second alone
+      telemetry.revert(first);
+      info!("second");
+      assert!(captured.take().contains("second"));
+
+      let second = *telemetry.generation.lock().unwrap();
+      telemetry.revert(second);
+      info!("reverted");
+      assert!(!captured.take().contains("reverted"));
+   }
+
+   #[tokio::test]
+   async fn test_changes_revert_after_their_timeout() {
+      let (_guard, telemetry, captured) = capture(config("warn"));
+      telemetry.set_filter("debug", Some(Duration::from_millis(10))).unwrap();
+      debug!("raised");
+      assert!(captured.take().contains("raised"));
+
+      tokio::time::sleep(Duration::from_millis(200)).await;
+      debug!("lowered");
+      let logged = captured.take();
+      assert!(!logged.contains("lowered"), "{logged}");
+   }
+}
@@ -0,0 +1,135 @@
+use std::fmt::Write;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::http::{header::HeaderName, HeaderValue};
+
+pub const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// Per-phase durations for one request, rendered as a `Server-Timing` header.
+#[derive(Debug)]
+pub struct Timings {
+   start: Instant,
+   entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+   Duration(&'static str, Duration),
+   Description(&'static str, String),
+}
+
+impl Default for Timings {
+   fn default() -> Self { Timings::new() }
+}
+
+impl Timings {
+   pub fn new() -> Self {
+      Timings {
+         start: Instant::now(),
+         entries: Vec::new(),
+      }
+   }
+
+   pub fn record(&mut self, phase: &'static str, duration: Duration) {
+      match self
+         .entries
+         .iter_mut()
+         .find(|e| matches!(e, Entry::Duration(p, _) if *p == phase))
+      {
+         // A phase entered twice (say, two queries) reports the sum
+         Some(Entry::Duration(_, total)) => *total += duration,
+         _ => self.entries.push(Entry::Duration(phase, duration)),
+      }
+   }
+
+   /// Records a metric without a duration, such as `cache;desc=miss`.
+   pub fn describe(&mut self, phase: &'static str, description: impl Into<String>) {
+      self.entries.push(Entry::Description(phase, description.into()));
+   }
+
+   pub async fn time<T>(&mut self, phase: &'static str, future: impl Future<Output = T>) -> T {
+      let started = Instant::now();
+      let output = future.await;
+      self.record(phase, started.elapsed());
+      output
+   }
+
+   pub fn phases(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+      self.entries.iter().filter_map(|e| match e {
+         Entry::Duration(phase, duration) => Some((*phase, *duration)),
+         Entry::Description(..) => None,
+      })
+   }
+
+   pub fn header_value(&self, total: Duration) -> String {
+      let mut value = String::new();
+      for entry in &self.entries {
+         match entry {
+            Entry::Duration(phase, duration) => {
+               let _ = write!(value, "{};dur={:.1}, ", phase, millis(*duration));
+            }
+            Entry::Description(phase, description) => {
+               let _ = write!(value, "{};desc=\"{}\", ", phase, description.replace(['"', '\\'], ""));
+            }
+         }
+      }
+      let _ = write!(value, "total;dur={:.1}", millis(total));
+      value
+   }
+
+   /// Finishes timing the request and renders the header, measuring `total` from creation.
+   pub fn finish(&self) -> HeaderValue {
+      HeaderValue::from_str(&self.header_value(self.start.elapsed())).unwrap_or(HeaderValue::from_static(""))
+   }
+}
+
+fn millis(duration: Duration) -> f64 { duration.as_secs_f64() * 1000.0 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_header_format() {
+      let mut timings = Timings::new();
+      timings.record("pool", Duration::from_micros(260));
+      timings.record("db", Duration::from_micros(3_210));
+      timings.describe("cache", "miss");
+      timings.record("notion", Duration::from_micros(120_400));
+      assert_eq!(
+         timings.header_value(Duration::from_micros(130_080)),
+         "pool;dur=0.3, db;dur=3.2, cache;desc=\"miss\", notion;dur=120.4, total;dur=130.1"
+      );
+   }
+
+   #[test]
+   fn test_repeated_phases_are_summed() {
+      let mut timings = Timings::new();
+      timings.record("db", Duration::from_millis(2));
+      timings.record("db", Duration::from_millis(3));
+      assert_eq!(timings.phases().collect::<Vec<_>>(), [("db", Duration::from_millis(5))]);
+   }
+
+   #[test]
+   fn test_descriptions_cannot_break_the_header() {
+      let mut timings = Timings::new();
+      timings.describe("cache", "mi\"ss\\");
+      assert_eq!(
+         timings.header_value(Duration::ZERO),
+         "cache;desc=\"miss\", total;dur=0.0"
+      );
+   }
+
+   #[tokio::test]
+   async fn test_phases_fit_within_total() {
+      let mut timings = Timings::new();
+      timings.time("pool", tokio::time::sleep(Duration::from_millis(5))).await;
+      timings.time("db", tokio::time::sleep(Duration::from_millis(10))).await;
+      let total = timings.start.elapsed();
+
+      let phases: Duration = timings.phases().map(|(_, d)| d).sum();
+      assert!(phases >= Duration::from_millis(15));
+      assert!(phases <= total);
+   }
+}
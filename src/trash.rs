@@ -0,0 +1,241 @@
+//! Deleting a tag only moves it to the trash, where it keeps its aliases, history and settings for
+//! a grace period and can be restored from `/tags/trash`. Once `purge_after` passes, the nightly
+//! retention run deletes it for good, leaving only the audit entry recording the purge.
+//!
+//! A restore and a purge of the same tag can race, from two admins or an admin and the nightly
+//! run. Both only touch rows still in the trash, under a row lock, so exactly one of them wins and
+//! the other reports the tag as no longer in the trash.
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgConnection;
+
+use crate::kit::RowResult;
+use crate::models::TagUid;
+use crate::pool::ScalingPool;
+use crate::retention::run_batched;
+
+/// How long a deleted tag stays restorable, unless `TWAG_TRASH_DAYS` says otherwise.
+pub const DEFAULT_GRACE_DAYS: u32 = 30;
+
+/// Tags purged per transaction. Each takes its tap events with it, so batches are kept small.
+const PURGE_BATCH: i64 = 100;
+const PURGE_PAUSE: Duration = Duration::from_millis(200);
+
+/// The `twag_tag_audit.actor` of purges made by the nightly run rather than an admin.
+pub const ACTOR_RETENTION: &str = "retention";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trashed {
+   pub id: TagUid,
+   pub label: Option<String>,
+   pub target_url: String,
+   pub deleted_at: DateTime<Utc>,
+   pub purge_after: DateTime<Utc>,
+}
+
+impl Trashed {
+   pub fn days_left(&self, now: DateTime<Utc>) -> i64 { days_left(self.purge_after, now) }
+}
+
+/// Whole days until the purge, rounded up, so a tag purged tonight still shows one day left
+/// rather than none.
+pub fn days_left(purge_after: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+   let left = purge_after - now;
+   if left <= TimeDelta::zero() {
+      return 0;
+   }
+   let days = left.num_days();
+   if left > TimeDelta::days(days) {
+      days + 1
+   } else {
+      days
+   }
+}
+
+/// One result per requested id: 200 if it was restored or purged, and 404 if it wasn't in the
+/// trash, whether because it was never deleted or because something else got to it first.
+pub fn results(requested: &[TagUid], done: &[TagUid]) -> Vec<RowResult> {
+   requested
+      .iter()
+      .map(|id| {
+         if done.contains(id) {
+            RowResult::updated(id)
+         } else {
+            RowResult {
+               id: id.to_string(),
+               status: 404,
+               error: Some("Not in the trash".to_string()),
+            }
+         }
+      })
+      .collect()
+}
+
+/// Every tag in the trash, the soonest purged first.
+pub async fn list(conn: &mut PgConnection) -> Result<Vec<Trashed>, sqlx::Error> {
+   let rows = sqlx::query!(
+      r#"SELECT id AS "id!: TagUid", label, target_url,
+            extract(epoch FROM deleted_at)::bigint AS "deleted_at!",
+            extract(epoch FROM purge_after)::bigint AS "purge_after!"
+         FROM twag_tags WHERE deleted_at IS NOT NULL AND purge_after IS NOT NULL
+         ORDER BY purge_after, id"#
+   )
+   .fetch_all(conn)
+   .await?;
+   Ok(rows
+      .into_iter()
+      .map(|row| Trashed {
+         id: row.id,
+         label: row.label,
+         target_url: row.target_url,
+         deleted_at: DateTime::from_timestamp(row.deleted_at, 0).unwrap_or_default(),
+         purge_after: DateTime::from_timestamp(row.purge_after, 0).unwrap_or_default(),
+      })
+      .collect())
+}
+
+/// Takes the tags back out of the trash, returning those that were in it. Their aliases, targets
+/// and history were never removed, so clearing the deletion is all a restore takes.
+pub async fn restore(conn: &mut PgConnection, ids: &[TagUid]) -> Result<Vec<TagUid>, sqlx::Error> {
+   let ids: Vec<String> = ids.iter().map(TagUid::to_string).collect();
+   let restored: Vec<TagUid> = sqlx::query_scalar!(
+      r#"WITH restored AS (
+            UPDATE twag_tags SET deleted_at = NULL, purge_after = NULL
+            WHERE id::text = ANY($1) AND deleted_at IS NOT NULL
+            RETURNING id
+         ), audited AS (
+            INSERT INTO twag_tag_audit (tag_id, action) SELECT id, 'restore' FROM restored
+         )
+         SELECT id AS "id!: TagUid" FROM restored"#,
+      &ids,
+   )
+   .fetch_all(conn)
+   .await?;
+   Ok(restored)
+}
+
+/// Deletes up to `limit` trashed tags for good, in one transaction: those listed in `ids`, and
+/// those due before `due`. Tags locked by a concurrent restore are skipped rather than waited on.
+async fn purge_batch(
+   conn: &mut PgConnection,
+   ids: &[TagUid],
+   due: Option<DateTime<Utc>>,
+   limit: i64,
+   actor: &str,
+) -> Result<Vec<TagUid>, sqlx::Error> {
+   use sqlx::Connection;
+
+   let ids: Vec<String> = ids.iter().map(TagUid::to_string).collect();
+   let mut tx = conn.begin().await?;
+   let doomed = sqlx::query!(
+      r#"SELECT id AS "id!: TagUid", target_url FROM twag_tags
+         WHERE deleted_at IS NOT NULL
+            AND (id::text = ANY($1) OR purge_after < $2::text::timestamptz)
+         ORDER BY purge_after LIMIT $3
+         FOR UPDATE SKIP LOCKED"#,
+      &ids,
+      due.map(|due| due.to_rfc3339()),
+      limit,
+   )
+   .fetch_all(&mut *tx)
+   .await?;
+   if doomed.is_empty() {
+      return Ok(Vec::new());
+   }
+   let (purged, targets): (Vec<String>, Vec<String>) = doomed
+      .iter()
+      .map(|row| (row.id.to_string(), row.target_url.clone()))
+      .unzip();
+
+   // Children first, then the tag; the smaller tables go with it by cascade
+   sqlx::query!("DELETE FROM twag_tap_events WHERE tag_id::text = ANY($1)", &purged)
+      .execute(&mut *tx)
+      .await?;
+   sqlx::query!("DELETE FROM twag_tag_aliases WHERE tag_id::text = ANY($1)", &purged)
+      .execute(&mut *tx)
+      .await?;
+   sqlx::query!("DELETE FROM twag_tags WHERE id::text = ANY($1)", &purged)
+      .execute(&mut *tx)
+      .await?;
+   sqlx::query!(
+      r#"INSERT INTO twag_tag_audit (tag_id, action, before, actor)
+         SELECT unnest($1::text[])::tag_uid, 'purge', unnest($2::text[]), $3"#,
+      &purged,
+      &targets,
+      actor,
+   )
+   .execute(&mut *tx)
+   .await?;
+   tx.commit().await?;
+
+   Ok(doomed.into_iter().map(|row| row.id).collect())
+}
+
+/// `POST /tags/trash/purge`: deletes the given trashed tags now, without waiting out the grace
+/// period. Returns those that were still in the trash.
+pub async fn purge_now(conn: &mut PgConnection, ids: &[TagUid]) -> Result<Vec<TagUid>, sqlx::Error> {
+   purge_batch(conn, ids, None, ids.len() as i64, "operator").await
+}
+
+/// The nightly purge of tags whose grace period is over; see `retention`.
+pub async fn purge_due(pool: &ScalingPool, now: DateTime<Utc>, dry_run: bool) -> Result<u64, sqlx::Error> {
+   if dry_run {
+      let count = sqlx::query_scalar!(
+         r#"SELECT count(*) AS "count!" FROM twag_tags
+            WHERE deleted_at IS NOT NULL AND purge_after < $1::text::timestamptz"#,
+         now.to_rfc3339()
+      )
+      .fetch_one(&pool.get())
+      .await?;
+      return Ok(count as u64);
+   }
+   run_batched(PURGE_BATCH, PURGE_PAUSE, |limit| async move {
+      let mut conn = pool.get().acquire().await?;
+      let purged = purge_batch(&mut conn, &[], Some(now), limit, ACTOR_RETENTION).await?;
+      Ok(purged.len() as u64)
+   })
+   .await
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn at(s: &str) -> DateTime<Utc> { DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc) }
+
+   fn uid(id: &str) -> TagUid { id.parse().unwrap() }
+
+   #[test]
+   fn test_days_left_rounds_up() {
+      let purge_after = at("2026-11-15T12:00:00Z");
+      assert_eq!(days_left(purge_after, at("2026-10-16T12:00:00Z")), 30);
+      assert_eq!(days_left(purge_after, at("2026-10-16T12:00:01Z")), 30);
+      assert_eq!(days_left(purge_after, at("2026-11-14T12:00:00Z")), 1);
+      assert_eq!(days_left(purge_after, at("2026-11-15T11:59:59Z")), 1);
+      assert_eq!(days_left(purge_after, at("2026-11-15T12:00:00Z")), 0);
+      assert_eq!(days_left(purge_after, at("2026-12-01T00:00:00Z")), 0);
+   }
+
+   #[test]
+   fn test_restore_reports_each_id() {
+      let ids = [uid("055B88A23C1250"), uid("04A1B2C3D4E5F6")];
+      let results = results(&ids, &ids[..1]);
+      assert_eq!(results[0], RowResult::updated(&ids[0]));
+      assert_eq!(results[1].status, 404);
+      assert_eq!(results[1].error.as_deref(), Some("Not in the trash"));
+   }
+
+   #[test]
+   fn test_purge_waits_out_the_whole_grace_period() {
+      let now = at("2026-11-15T12:00:00Z");
+
+      // The nightly purge matches `purge_after < now`, so a tag showing no days left goes on the
+      // next run rather than this one
+      let due = |purge_after: &str| at(purge_after) < now;
+      assert!(due("2026-11-15T11:59:59Z"));
+      assert!(!due("2026-11-15T12:00:00Z"));
+      assert_eq!(days_left(at("2026-11-15T12:00:00Z"), now), 0);
+   }
+}
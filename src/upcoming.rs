@@ -0,0 +1,554 @@
+//! What will change about where tags point, for the days ahead. Each tag with time-dependent
+//! settings is only looked at around its own boundaries, the instants something about it changes,
+//! where the resolver is run as for an anonymous scan just before and at the boundary; a boundary
+//! where both answers are the same isn't listed.
+//!
+//! Four things change a tag's answer by themselves: its activation time, its expiry time, the
+//! nightly retention run that purges it once it's been in the trash long enough, and its kit's
+//! windows opening and closing, which are listed for each tag in the kit. twag keeps time in UTC
+//! throughout, so daylight saving changes don't move any of them; calendars show the UTC instants
+//! in local time.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgConnection;
+
+use crate::models::{TagSlug, TagUid};
+use crate::reprogram::{Review, ReviewState};
+use crate::resolve::{self, Gate, Page, Policy, ResolveContext, ResolveOutcome, StoredTag};
+use crate::retention;
+use crate::schedule;
+use crate::vcard::{escape_text, fold};
+
+pub const DEFAULT_DAYS: u32 = 7;
+pub const MAX_DAYS: u32 = 90;
+
+/// A tag with something time-dependent about it.
+#[derive(Debug, Clone)]
+pub struct Timed {
+   pub id: TagUid,
+   pub label: Option<String>,
+   pub tag: StoredTag,
+   /// When it may be purged from the trash, if it's there.
+   pub purge_after: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+   Activation,
+   Expiry,
+   Purge,
+   /// A window of its kit's opens or closes.
+   Schedule,
+}
+
+impl Cause {
+   pub fn describe(self) -> &'static str {
+      match self {
+         Cause::Activation => "activates",
+         Cause::Expiry => "expires",
+         Cause::Purge => "purged from the trash",
+         Cause::Schedule => "kit schedule",
+      }
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Boundary {
+   pub at: DateTime<Utc>,
+   pub cause: Cause,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+   pub id: TagUid,
+   pub label: Option<String>,
+   pub at: DateTime<Utc>,
+   pub cause: Cause,
+   pub from: String,
+   pub to: String,
+}
+
+/// The instants in `(now, until]` at which `tag` may change, in order. Purges are only expected
+/// when the retention run is really deleting, which `purging` says.
+pub fn boundaries(
+   tag: &StoredTag,
+   purge_after: Option<DateTime<Utc>>,
+   purging: bool,
+   now: DateTime<Utc>,
+   until: DateTime<Utc>,
+) -> Vec<Boundary> {
+   let mut found = Vec::new();
+   // Deleted tags already answer as expired
+   if let Some(at) = tag.activates_at.filter(|_| !tag.deleted) {
+      found.push(Boundary {
+         at,
+         cause: Cause::Activation,
+      });
+   }
+   if let Some(at) = tag.expires_at.filter(|_| !tag.deleted) {
+      found.push(Boundary {
+         at,
+         cause: Cause::Expiry,
+      });
+   }
+   if let Some(purge_after) = purge_after.filter(|_| tag.deleted && purging) {
+      // The run purges tags with `purge_after` strictly before it
+      found.push(Boundary {
+         at: retention::next_run(purge_after.max(now)),
+         cause: Cause::Purge,
+      });
+   }
+   if !tag.deleted && !tag.ignore_kit_schedule {
+      for window in &tag.kit_schedule {
+         for at in [window.starts_at, window.ends_at] {
+            found.push(Boundary {
+               at,
+               cause: Cause::Schedule,
+            });
+         }
+      }
+   }
+   found.retain(|boundary| boundary.at > now && boundary.at <= until);
+   found.sort_by_key(|boundary| boundary.at);
+   // Windows back to back share a boundary
+   found.dedup();
+   found
+}
+
+/// Every change in `(now, until]`, soonest first.
+pub fn transitions(
+   tags: &[Timed],
+   policy: Policy,
+   purging: bool,
+   now: DateTime<Utc>,
+   until: DateTime<Utc>,
+) -> Vec<Transition> {
+   let mut found = Vec::new();
+   for timed in tags {
+      let slug = TagSlug {
+         id: timed.id,
+         tap_count: None,
+         vcf: false,
+      };
+      let answer = |at: DateTime<Utc>, purged: bool| {
+         let mut ctx = ResolveContext::new(at);
+         ctx.policy = policy;
+         let tag = Some(&timed.tag).filter(|_| !purged);
+         describe(&resolve::decide(tag, &slug, &ctx).outcome)
+      };
+      let mut purged = false;
+      for boundary in boundaries(&timed.tag, timed.purge_after, purging, now, until) {
+         let from = answer(boundary.at - TimeDelta::seconds(1), purged);
+         purged |= boundary.cause == Cause::Purge;
+         let to = answer(boundary.at, purged);
+         if from != to {
+            found.push(Transition {
+               id: timed.id,
+               label: timed.label.clone(),
+               at: boundary.at,
+               cause: boundary.cause,
+               from,
+               to,
+            });
+         }
+      }
+   }
+   found.sort_by(|a, b| (a.at, a.id).cmp(&(b.at, b.id)));
+   found
+}
+
+/// An outcome as it reads in the listing: the URL for redirects, and what's shown otherwise.
+pub fn describe(outcome: &ResolveOutcome) -> String {
+   match outcome {
+      ResolveOutcome::Redirect { url, .. } => url.clone(),
+      ResolveOutcome::Expired => "410 Gone".to_string(),
+      ResolveOutcome::Disabled => "410 Gone (disabled)".to_string(),
+      ResolveOutcome::NotFound { .. } => "unknown tag (create form)".to_string(),
+      ResolveOutcome::Gated { kind: Gate::Age { .. } } => "age gate".to_string(),
+      ResolveOutcome::Gated { kind: Gate::Quarantine } => "quarantine page".to_string(),
+      ResolveOutcome::Page {
+         page: Page::Maintenance,
+         ..
+      } => "maintenance page".to_string(),
+      ResolveOutcome::Page {
+         page: Page::Stateful { .. },
+         ..
+      } => "check-out page".to_string(),
+      ResolveOutcome::Page {
+         page: Page::Contact, ..
+      } => "contact card".to_string(),
+      ResolveOutcome::Page {
+         page: Page::Pending { .. },
+         ..
+      } => "not yet active page".to_string(),
+      // Not answers an anonymous scan of a stored tag gets
+      _ => format!("{outcome:?}"),
+   }
+}
+
+/// The transitions as an iCalendar feed, one all-but-instant event each. `origin` makes the UIDs
+/// stable across fetches, so a subscribed calendar updates events rather than duplicating them.
+pub fn to_ical(transitions: &[Transition], origin: &str, now: DateTime<Utc>) -> String {
+   const STAMP: &str = "%Y%m%dT%H%M%SZ";
+   let host = origin.split_once("://").map_or(origin, |(_, host)| host);
+   let mut ical = String::new();
+   let mut line = |line: String| {
+      let _ = write!(ical, "{}\r\n", fold(&line));
+   };
+   line("BEGIN:VCALENDAR".to_string());
+   line("VERSION:2.0".to_string());
+   line("PRODID:-//twag//upcoming//EN".to_string());
+   line("X-WR-CALNAME:twag upcoming changes".to_string());
+   for transition in transitions {
+      let name = transition.label.as_deref().unwrap_or_default();
+      let name = if name.is_empty() {
+         transition.id.to_string()
+      } else {
+         format!("{name} ({})", transition.id)
+      };
+      line("BEGIN:VEVENT".to_string());
+      line(format!(
+         "UID:{}-{}-{}@{}",
+         transition.id,
+         match transition.cause {
+            Cause::Expiry => "expiry",
+            Cause::Purge => "purge",
+            Cause::Schedule => "schedule",
+         },
+         transition.at.timestamp(),
+         host
+      ));
+      line(format!("DTSTAMP:{}", now.format(STAMP)));
+      line(format!("DTSTART:{}", transition.at.format(STAMP)));
+      line(format!(
+         "DTEND:{}",
+         (transition.at + TimeDelta::minutes(1)).format(STAMP)
+      ));
+      line(format!(
+         "SUMMARY:{}",
+         escape_text(&format!("{name} {}", transition.cause.describe()))
+      ));
+      line(format!(
+         "DESCRIPTION:{}",
+         escape_text(&format!("From {}\nTo {}", transition.from, transition.to))
+      ));
+      line(format!("URL:{}/tag/{}/stats", origin, transition.id));
+      line("END:VEVENT".to_string());
+   }
+   line("END:VCALENDAR".to_string());
+   ical
+}
+
+/// Tags that might change by `until`; `boundaries` picks out when.
+pub async fn load(conn: &mut PgConnection, until: DateTime<Utc>) -> Result<Vec<Timed>, sqlx::Error> {
+   let rows = sqlx::query!(
+      r#"SELECT t.id AS "id: TagUid", t.label, t.target_url, t.deleted_at IS NOT NULL AS "deleted!",
+            extract(epoch FROM t.expires_at)::bigint AS expires_at,
+            extract(epoch FROM t.activates_at)::bigint AS activates_at,
+            extract(epoch FROM t.purge_after)::bigint AS purge_after,
+            t.redirect_kind, t.maintenance, t.stateful, t.age_gate, t.age_gate_text,
+            t.review_state, t.review_tap_count, t.quarantine_on_review, t.ignore_kit_schedule, t.enabled,
+            EXISTS (SELECT 1 FROM twag_tag_vcards v WHERE v.tag_id = t.id) AS "contact!",
+            ARRAY(SELECT extract(epoch FROM s.starts_at)::bigint FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_starts!",
+            ARRAY(SELECT extract(epoch FROM s.ends_at)::bigint FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_ends!",
+            ARRAY(SELECT s.target_url FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() ORDER BY s.starts_at, s.id) AS "schedule_targets!"
+         FROM twag_tags t
+         WHERE (t.deleted_at IS NULL AND t.expires_at <= $1::text::timestamptz)
+            OR (t.deleted_at IS NULL AND t.activates_at > now() AND t.activates_at <= $1::text::timestamptz)
+            OR (t.deleted_at IS NOT NULL AND t.purge_after < $1::text::timestamptz)
+            OR (t.deleted_at IS NULL AND NOT t.ignore_kit_schedule AND EXISTS (
+               SELECT 1 FROM twag_kit_schedules s
+               WHERE s.kit = t.kit AND s.ends_at > now() AND s.starts_at <= $1::text::timestamptz))
+         ORDER BY t.id"#,
+      until.to_rfc3339(),
+   )
+   .fetch_all(conn)
+   .await?;
+   Ok(rows
+      .into_iter()
+      .map(|row| {
+         let mut tag = StoredTag::new(row.target_url);
+         tag.deleted = row.deleted;
+         tag.expires_at = row.expires_at.and_then(|secs| DateTime::from_timestamp(secs, 0));
+         tag.activates_at = row.activates_at.and_then(|secs| DateTime::from_timestamp(secs, 0));
+         tag.redirect_kind = row.redirect_kind.parse().unwrap_or_default();
+         tag.maintenance = row.maintenance;
+         tag.stateful = row.stateful;
+         tag.contact = row.contact;
+         tag.age_gate = row.age_gate;
+         tag.age_gate_text = row.age_gate_text;
+         tag.review = Review {
+            state: row.review_state.parse().unwrap_or(ReviewState::Ok),
+            since_drop: row.review_tap_count,
+         };
+         tag.quarantine_on_review = row.quarantine_on_review;
+         tag.kit_schedule = schedule::from_columns(&row.schedule_starts, &row.schedule_ends, &row.schedule_targets);
+         tag.ignore_kit_schedule = row.ignore_kit_schedule;
+         tag.enabled = row.enabled;
+         Timed {
+            id: row.id,
+            label: row.label,
+            tag,
+            purge_after: row.purge_after.and_then(|secs| DateTime::from_timestamp(secs, 0)),
+         }
+      })
+      .collect())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use chrono::NaiveDate;
+
+   const BASE: &str = "https://example.com/";
+
+   fn at(s: &str) -> DateTime<Utc> { DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc) }
+
+   fn day(s: &str) -> NaiveDate { s.parse().unwrap() }
+
+   fn expiring(on: &str) -> StoredTag {
+      let mut tag = StoredTag::new(BASE);
+      tag.expires_at = Some(day(on).and_time(chrono::NaiveTime::MIN).and_utc());
+      tag
+   }
+
+   fn trashed() -> StoredTag {
+      let mut tag = StoredTag::new(BASE);
+      tag.deleted = true;
+      tag
+   }
+
+   fn timed(id: &str, tag: StoredTag, purge_after: Option<&str>) -> Timed {
+      Timed {
+         id: id.parse().unwrap(),
+         label: None,
+         tag,
+         purge_after: purge_after.map(at),
+      }
+   }
+
+   #[test]
+   fn test_expiry_is_at_midnight_utc_within_the_window() {
+      let now = at("2026-10-16T12:00:00Z");
+      let until = now + TimeDelta::days(7);
+      assert_eq!(
+         boundaries(&expiring("2026-10-20"), None, true, now, until),
+         [Boundary {
+            at: at("2026-10-20T00:00:00Z"),
+            cause: Cause::Expiry,
+         }]
+      );
+      // Already expired at midnight today
+      assert_eq!(boundaries(&expiring("2026-10-16"), None, true, now, until), []);
+      // The last boundary in the window is included, the first after it isn't
+      assert_eq!(boundaries(&expiring("2026-10-23"), None, true, now, until), []);
+      assert_eq!(
+         boundaries(&expiring("2026-10-23"), None, true, now, at("2026-10-23T00:00:00Z")).len(),
+         1
+      );
+      assert_eq!(boundaries(&StoredTag::new(BASE), None, true, now, until), []);
+   }
+
+   #[test]
+   fn test_expiry_times_are_their_own_boundary() {
+      let now = at("2026-10-16T12:00:00Z");
+      let until = now + TimeDelta::days(7);
+      let mut tag = StoredTag::new(BASE);
+      tag.expires_at = Some(at("2026-10-18T18:30:00Z"));
+      let found = transitions(
+         &[timed("04A1B2C3D4E5F6", tag.clone(), None)],
+         Policy::default(),
+         true,
+         now,
+         until,
+      );
+      assert_eq!(found.len(), 1);
+      assert_eq!(
+         (found[0].at, found[0].cause),
+         (at("2026-10-18T18:30:00Z"), Cause::Expiry)
+      );
+      assert_eq!(found[0].to, "410 Gone");
+
+      tag.deleted = true;
+      assert_eq!(boundaries(&tag, None, true, now, until), []);
+   }
+
+   #[test]
+   fn test_activation_is_listed_as_the_page_giving_way() {
+      let now = at("2026-10-16T12:00:00Z");
+      let until = now + TimeDelta::days(7);
+      let mut tag = StoredTag::new(BASE);
+      tag.activates_at = Some(at("2026-10-20T18:00:00Z"));
+      let found = transitions(
+         &[timed("04A1B2C3D4E5F6", tag, None)],
+         Policy::default(),
+         true,
+         now,
+         until,
+      );
+      assert_eq!(found.len(), 1);
+      assert_eq!(found[0].cause, Cause::Activation);
+      assert_eq!(
+         (found[0].from.as_str(), found[0].to.as_str()),
+         ("not yet active page", BASE)
+      );
+   }
+
+   #[test]
+   fn test_boundaries_ignore_daylight_saving() {
+      // Europe falls back on 2026-10-25 and springs forward on 2027-03-28; the US falls back on
+      // 2026-11-01. Days stay 24 hours long either side.
+      let now = at("2026-10-01T00:00:00Z");
+      let until = now + TimeDelta::days(MAX_DAYS.into());
+      let midnights: Vec<DateTime<Utc>> = ["2026-10-25", "2026-10-26", "2026-11-01", "2026-11-02"]
+         .into_iter()
+         .map(|on| boundaries(&expiring(on), None, true, now, until)[0].at)
+         .collect();
+      assert_eq!(midnights[1] - midnights[0], TimeDelta::hours(24));
+      assert_eq!(midnights[3] - midnights[2], TimeDelta::hours(24));
+
+      let now = at("2027-03-27T12:00:00Z");
+      let until = now + TimeDelta::days(7);
+      assert_eq!(
+         boundaries(&expiring("2027-03-29"), None, true, now, until)[0].at,
+         at("2027-03-29T00:00:00Z")
+      );
+      assert_eq!(
+         boundaries(&trashed(), Some(at("2027-03-28T01:30:00Z")), true, now, until)[0].at,
+         at("2027-03-28T03:00:00Z")
+      );
+   }
+
+   #[test]
+   fn test_purges_happen_at_the_next_retention_run() {
+      let now = at("2026-10-16T12:00:00Z");
+      let until = now + TimeDelta::days(7);
+      let purge = |purge_after: &str, purging| {
+         boundaries(&trashed(), Some(at(purge_after)), purging, now, until)
+            .iter()
+            .map(|boundary| boundary.at)
+            .collect::<Vec<_>>()
+      };
+      assert_eq!(purge("2026-10-18T12:00:00Z", true), [at("2026-10-19T03:00:00Z")]);
+      // The run matches `purge_after < now`, so a tag due exactly at a run waits for the next
+      assert_eq!(purge("2026-10-19T03:00:00Z", true), [at("2026-10-20T03:00:00Z")]);
+      // Overdue tags go at the next run
+      assert_eq!(purge("2026-10-01T00:00:00Z", true), [at("2026-10-17T03:00:00Z")]);
+      assert_eq!(purge("2026-10-30T00:00:00Z", true), []);
+      // A dry run purges nothing
+      assert_eq!(purge("2026-10-18T12:00:00Z", false), []);
+   }
+
+   #[test]
+   fn test_transitions_compare_answers_either_side() {
+      let now = at("2026-10-16T12:00:00Z");
+      let until = now + TimeDelta::days(7);
+      let mut in_maintenance = expiring("2026-10-17");
+      in_maintenance.maintenance = true;
+      let tags = [
+         timed("04A1B2C3D4E5F6", trashed(), Some("2026-10-17T12:00:00Z")),
+         timed("055B88A23C1250", expiring("2026-10-18"), None),
+         timed("04000000000001", in_maintenance, None),
+      ];
+      let found = transitions(&tags, Policy::default(), true, now, until);
+      let summary: Vec<(&str, String, &str, &str)> = found
+         .iter()
+         .map(|t| (t.cause.describe(), t.at.to_rfc3339(), t.from.as_str(), t.to.as_str()))
+         .collect();
+      assert_eq!(
+         summary,
+         [
+            (
+               "expires",
+               "2026-10-17T00:00:00+00:00".to_string(),
+               "maintenance page",
+               "410 Gone"
+            ),
+            ("expires", "2026-10-18T00:00:00+00:00".to_string(), BASE, "410 Gone"),
+            (
+               "purged from the trash",
+               "2026-10-18T03:00:00+00:00".to_string(),
+               "410 Gone",
+               "unknown tag (create form)"
+            ),
+         ]
+      );
+   }
+
+   #[test]
+   fn test_kit_windows_are_listed_for_each_member() {
+      let now = at("2026-10-16T12:00:00Z");
+      let until = now + TimeDelta::days(7);
+      let window = schedule::Window {
+         starts_at: at("2026-10-17T18:00:00Z"),
+         ends_at: at("2026-10-17T22:00:00Z"),
+         target_url: "https://live.example.com/".to_string(),
+      };
+      let member = || {
+         let mut tag = StoredTag::new(BASE);
+         tag.kit_schedule = vec![window.clone()];
+         tag
+      };
+      let mut opted_out = member();
+      opted_out.ignore_kit_schedule = true;
+      let tags = [
+         timed("04000000000001", member(), None),
+         timed("04000000000002", member(), None),
+         timed("04000000000003", opted_out, None),
+      ];
+      let found = transitions(&tags, Policy::default(), true, now, until);
+      let summary: Vec<(String, String, &str, &str)> = found
+         .iter()
+         .map(|t| (t.id.to_string(), t.at.to_rfc3339(), t.from.as_str(), t.to.as_str()))
+         .collect();
+      let opens = "2026-10-17T18:00:00+00:00".to_string();
+      let closes = "2026-10-17T22:00:00+00:00".to_string();
+      assert_eq!(
+         summary,
+         [
+            (
+               "04000000000001".to_string(),
+               opens.clone(),
+               BASE,
+               "https://live.example.com/"
+            ),
+            ("04000000000002".to_string(), opens, BASE, "https://live.example.com/"),
+            (
+               "04000000000001".to_string(),
+               closes.clone(),
+               "https://live.example.com/",
+               BASE
+            ),
+            ("04000000000002".to_string(), closes, "https://live.example.com/", BASE),
+         ]
+      );
+      assert!(found.iter().all(|t| t.cause == Cause::Schedule));
+   }
+
+   #[test]
+   fn test_ical() {
+      let now = at("2026-10-16T12:00:00Z");
+      let transitions = [Transition {
+         id: "055B88A23C1250".parse().unwrap(),
+         label: Some("Camera bag, left".to_string()),
+         at: at("2026-10-18T00:00:00Z"),
+         cause: Cause::Expiry,
+         from: BASE.to_string(),
+         to: "410 Gone".to_string(),
+      }];
+      let ical = to_ical(&transitions, "https://xz.ws", now);
+      assert!(ical.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+      assert!(ical.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+      assert!(ical.contains("UID:055B88A23C1250-expiry-1792281600@xz.ws\r\n"));
+      assert!(ical.contains("DTSTAMP:20261016T120000Z\r\n"));
+      assert!(ical.contains("DTSTART:20261018T000000Z\r\n"));
+      assert!(ical.contains("SUMMARY:Camera bag\\, left (055B88A23C1250) expires\r\n"));
+      assert!(ical.contains("DESCRIPTION:From https://example.com/\\nTo 410 Gone\r\n"));
+      assert_eq!(to_ical(&[], "https://xz.ws", now).matches("BEGIN:").count(), 1);
+   }
+}
@@ -0,0 +1,212 @@
+use std::fmt::Write;
+
+/// Contact details served instead of a redirect for tags in vCard mode.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Contact {
+   pub name: String,
+   pub org: Option<String>,
+   pub phone: Option<String>,
+   pub email: Option<String>,
+   pub url: Option<String>,
+   pub note: Option<String>,
+}
+
+/// Escapes a TEXT value per RFC 6350 §3.4.
+pub fn escape_text(value: &str) -> String {
+   let mut escaped = String::with_capacity(value.len());
+   let mut chars = value.chars().peekable();
+   while let Some(c) = chars.next() {
+      match c {
+         '\\' => escaped.push_str("\\\\"),
+         ',' => escaped.push_str("\\,"),
+         ';' => escaped.push_str("\\;"),
+         '\r' if chars.peek() == Some(&'\n') => {}
+         '\r' | '\n' => escaped.push_str("\\n"),
+         c => escaped.push(c),
+      }
+   }
+   escaped
+}
+
+/// Folds a content line at 75 octets without splitting a UTF-8 sequence, per RFC 6350 §3.2.
+pub fn fold(line: &str) -> String {
+   let mut folded = String::with_capacity(line.len() + line.len() / 74 * 3);
+   let mut width = 0;
+   for c in line.chars() {
+      if width + c.len_utf8() > 75 {
+         folded.push_str("\r\n ");
+         width = 1;
+      }
+      folded.push(c);
+      width += c.len_utf8();
+   }
+   folded
+}
+
+impl Contact {
+   pub fn to_vcard(&self) -> String {
+      let mut lines = vec![
+         "BEGIN:VCARD".to_string(),
+         "VERSION:4.0".to_string(),
+         format!("FN:{}", escape_text(&self.name)),
+      ];
+      if let Some(org) = &self.org {
+         lines.push(format!("ORG:{}", escape_text(org)));
+      }
+      if let Some(phone) = &self.phone {
+         let digits: String = phone.chars().filter(|c| c.is_ascii_digit() || *c == '+').collect();
+         lines.push(format!("TEL;VALUE=uri;TYPE=cell:tel:{}", digits));
+      }
+      if let Some(email) = &self.email {
+         lines.push(format!("EMAIL:{}", escape_text(email)));
+      }
+      if let Some(url) = &self.url {
+         lines.push(format!("URL:{}", url));
+      }
+      if let Some(note) = &self.note {
+         lines.push(format!("NOTE:{}", escape_text(note)));
+      }
+      lines.push("END:VCARD".to_string());
+
+      let mut card = String::new();
+      for line in lines {
+         let _ = write!(card, "{}\r\n", fold(&line));
+      }
+      card
+   }
+
+   /// A `Content-Disposition`-safe file name derived from the contact's name.
+   pub fn file_name(&self) -> String {
+      let stem: String = self
+         .name
+         .split_whitespace()
+         .collect::<Vec<_>>()
+         .join("-")
+         .chars()
+         .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+         .collect();
+      let stem = stem.trim_matches('.');
+      format!("{}.vcf", if stem.is_empty() { "contact" } else { stem })
+   }
+}
+
+fn quality_of(accept: &str, media_type: &str) -> f32 {
+   let (main_type, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+   let mut best: Option<(u8, f32)> = None;
+   for entry in accept.split(',') {
+      let mut parts = entry.split(';');
+      let range = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+      let specificity = if range == media_type {
+         2
+      } else if range.strip_suffix("/*") == Some(main_type) {
+         1
+      } else if range == "*/*" {
+         0
+      } else {
+         continue;
+      };
+      let quality = parts
+         .filter_map(|p| p.trim().strip_prefix("q="))
+         .find_map(|q| q.parse::<f32>().ok())
+         .unwrap_or(1.0);
+      if best.is_none_or(|(s, _)| specificity > s) {
+         best = Some((specificity, quality));
+      }
+   }
+   best.map_or(0.0, |(_, q)| q)
+}
+
+/// Whether the client would rather have an HTML page than the card itself, as desktop browsers
+/// that can't open `.vcf` files do.
+pub fn prefers_html(accept: Option<&str>) -> bool {
+   let Some(accept) = accept else { return false };
+   quality_of(accept, "text/html") > quality_of(accept, "text/vcard")
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_escape_text() {
+      assert_eq!(escape_text("Cable, Elliott"), "Cable\\, Elliott");
+      assert_eq!(escape_text("a;b"), "a\\;b");
+      assert_eq!(escape_text("back\\slash"), "back\\\\slash");
+      assert_eq!(escape_text("line one\nline two"), "line one\\nline two");
+      assert_eq!(escape_text("crlf\r\nhere"), "crlf\\nhere");
+      assert_eq!(escape_text("plain"), "plain");
+   }
+
+   #[test]
+   fn test_vcard_output() {
+      let contact = Contact {
+         name: "Ada Lovelace".to_string(),
+         org: Some("Analytical Engines, Ltd.".to_string()),
+         phone: Some("+44 20 7946 0000".to_string()),
+         email: Some("ada@example.com".to_string()),
+         url: Some("https://example.com/ada".to_string()),
+         note: Some("Met at RustConf;\nask about Bernoulli".to_string()),
+      };
+      assert_eq!(
+         contact.to_vcard(),
+         "BEGIN:VCARD\r\n\
+          VERSION:4.0\r\n\
+          FN:Ada Lovelace\r\n\
+          ORG:Analytical Engines\\, Ltd.\r\n\
+          TEL;VALUE=uri;TYPE=cell:tel:+442079460000\r\n\
+          EMAIL:ada@example.com\r\n\
+          URL:https://example.com/ada\r\n\
+          NOTE:Met at RustConf\\;\\nask about Bernoulli\r\n\
+          END:VCARD\r\n"
+      );
+   }
+
+   #[test]
+   fn test_vcard_omits_missing_fields() {
+      let contact = Contact {
+         name: "Ada".to_string(),
+         ..Default::default()
+      };
+      assert_eq!(
+         contact.to_vcard(),
+         "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:Ada\r\nEND:VCARD\r\n"
+      );
+   }
+
+   #[test]
+   fn test_long_lines_are_folded() {
+      let contact = Contact {
+         name: "Ada".to_string(),
+         note: Some("é".repeat(60)),
+         ..Default::default()
+      };
+      let card = contact.to_vcard();
+      for line in card.split("\r\n") {
+         assert!(line.len() <= 75, "{line:?} is {} octets", line.len());
+      }
+      let unfolded = card.replace("\r\n ", "");
+      assert!(unfolded.contains(&format!("NOTE:{}\r\n", "é".repeat(60))));
+   }
+
+   #[test]
+   fn test_file_name() {
+      let mut contact = Contact {
+         name: "Ada Lovelace".to_string(),
+         ..Default::default()
+      };
+      assert_eq!(contact.file_name(), "Ada-Lovelace.vcf");
+      contact.name = "\"; rm -rf /".to_string();
+      assert_eq!(contact.file_name(), "-rm--rf-.vcf");
+      contact.name = "李".to_string();
+      assert_eq!(contact.file_name(), "contact.vcf");
+   }
+
+   #[test]
+   fn test_prefers_html() {
+      let desktop = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+      assert!(prefers_html(Some(desktop)));
+      assert!(!prefers_html(Some("text/vcard, text/html;q=0.5")));
+      assert!(!prefers_html(Some("*/*")));
+      assert!(!prefers_html(None));
+   }
+}
@@ -0,0 +1,157 @@
+#![allow(deprecated)] // `SipHasher` is the only keyed hash in std; it's deprecated only in favour of `DefaultHasher`.
+
+use std::hash::{Hash, Hasher, SipHasher};
+use std::net::IpAddr;
+
+use chrono::NaiveDate;
+
+const PRECISION: u32 = 10;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// One-way, salted fingerprinting of a scanner. Only the resulting `u64` ever leaves this type.
+#[derive(Clone)]
+pub struct VisitorHasher {
+   k0: u64,
+   k1: u64,
+}
+
+impl VisitorHasher {
+   pub fn new(salt: &str) -> Self {
+      let derive = |domain: u64| {
+         let mut hasher = SipHasher::new_with_keys(domain, !domain);
+         salt.hash(&mut hasher);
+         hasher.finish()
+      };
+      VisitorHasher {
+         k0: derive(0x7477_6167_0000_0000),
+         k1: derive(0x7477_6167_0000_0001),
+      }
+   }
+
+   pub fn fingerprint(&self, ip: IpAddr, user_agent: &str, day: NaiveDate) -> u64 {
+      let mut hasher = SipHasher::new_with_keys(self.k0, self.k1);
+      network_prefix(ip).hash(&mut hasher);
+      user_agent.hash(&mut hasher);
+      day.hash(&mut hasher);
+      hasher.finish()
+   }
+}
+
+/// Collapses an address to its /24 (IPv4) or /48 (IPv6), so that one household or phone hopping
+/// between addresses in the same block still reads as one scanner.
+fn network_prefix(ip: IpAddr) -> IpAddr {
+   match ip {
+      IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & 0xFFFF_FF00).into()),
+      IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & !((1u128 << 80) - 1)).into()),
+   }
+}
+
+/// A HyperLogLog sketch of distinct fingerprints, stored as one byte per register.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sketch(Vec<u8>);
+
+impl Sketch {
+   pub fn new() -> Self { Sketch(vec![0; REGISTERS]) }
+
+   pub fn from_bytes(bytes: Vec<u8>) -> Option<Self> { (bytes.len() == REGISTERS).then_some(Sketch(bytes)) }
+
+   pub fn as_bytes(&self) -> &[u8] { &self.0 }
+
+   pub fn insert(&mut self, fingerprint: u64) {
+      let index = (fingerprint >> (64 - PRECISION)) as usize;
+      let rest = (fingerprint << PRECISION) | (1 << (PRECISION - 1));
+      let rank = rest.leading_zeros() as u8 + 1;
+      self.0[index] = self.0[index].max(rank);
+   }
+
+   pub fn estimate(&self) -> u64 {
+      let m = REGISTERS as f64;
+      let alpha = 0.7213 / (1.0 + 1.079 / m);
+      let sum: f64 = self.0.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+      let raw = alpha * m * m / sum;
+
+      let zeros = self.0.iter().filter(|&&r| r == 0).count();
+      if raw <= 2.5 * m && zeros > 0 {
+         (m * (m / zeros as f64).ln()).round() as u64
+      } else {
+         raw.round() as u64
+      }
+   }
+}
+
+impl Default for Sketch {
+   fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use std::net::{Ipv4Addr, Ipv6Addr};
+
+   fn day() -> NaiveDate { NaiveDate::from_ymd_opt(2025, 6, 1).unwrap() }
+
+   fn synthetic_visitors(hasher: &VisitorHasher, n: u32) -> impl Iterator<Item = u64> + '_ {
+      (0..n).map(move |i| hasher.fingerprint(IpAddr::V4(Ipv4Addr::from(i << 8)), "Mozilla/5.0", day()))
+   }
+
+   #[test]
+   fn test_fingerprint_is_salted_and_prefix_coarsened() {
+      let a = VisitorHasher::new("salt-a");
+      let b = VisitorHasher::new("salt-b");
+      let ip = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+      let neighbour = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 200));
+      let elsewhere = IpAddr::V4(Ipv4Addr::new(203, 0, 114, 7));
+
+      assert_ne!(a.fingerprint(ip, "UA", day()), b.fingerprint(ip, "UA", day()));
+      assert_eq!(a.fingerprint(ip, "UA", day()), a.fingerprint(neighbour, "UA", day()));
+      assert_ne!(a.fingerprint(ip, "UA", day()), a.fingerprint(elsewhere, "UA", day()));
+      assert_ne!(a.fingerprint(ip, "UA", day()), a.fingerprint(ip, "Other UA", day()));
+      assert_ne!(
+         a.fingerprint(ip, "UA", day()),
+         a.fingerprint(ip, "UA", day().succ_opt().unwrap())
+      );
+
+      let v6: Ipv6Addr = "2001:db8:1234:5678::1".parse().unwrap();
+      let v6_neighbour: Ipv6Addr = "2001:db8:1234:ffff::2".parse().unwrap();
+      assert_eq!(
+         a.fingerprint(IpAddr::V6(v6), "UA", day()),
+         a.fingerprint(IpAddr::V6(v6_neighbour), "UA", day())
+      );
+   }
+
+   #[test]
+   fn test_sketch_repeated_scans_count_once() {
+      let hasher = VisitorHasher::new("salt");
+      let mut sketch = Sketch::new();
+      let fingerprint = hasher.fingerprint(IpAddr::V4(Ipv4Addr::LOCALHOST), "UA", day());
+      for _ in 0..500 {
+         sketch.insert(fingerprint);
+      }
+      assert_eq!(sketch.estimate(), 1);
+      assert_eq!(Sketch::new().estimate(), 0);
+   }
+
+   #[test]
+   fn test_sketch_accuracy_against_synthetic_visitors() {
+      let hasher = VisitorHasher::new("salt");
+      for n in [10u32, 100, 1_000, 10_000, 100_000] {
+         let mut sketch = Sketch::new();
+         for fingerprint in synthetic_visitors(&hasher, n) {
+            sketch.insert(fingerprint);
+         }
+         let error = (sketch.estimate() as f64 - n as f64).abs() / n as f64;
+         assert!(error < 0.1, "estimate {} for {} visitors", sketch.estimate(), n);
+      }
+   }
+
+   #[test]
+   fn test_sketch_byte_round_trip() {
+      let hasher = VisitorHasher::new("salt");
+      let mut sketch = Sketch::new();
+      synthetic_visitors(&hasher, 50).for_each(|f| sketch.insert(f));
+
+      let restored = Sketch::from_bytes(sketch.as_bytes().to_vec()).unwrap();
+      assert_eq!(restored, sketch);
+      assert!(Sketch::from_bytes(vec![0; 12]).is_none());
+   }
+}
@@ -0,0 +1,439 @@
+//! Outgoing webhooks, delivered from `twag_webhook_deliveries` with retries.
+//!
+//! Every request is a `POST` of a JSON body, signed so the consumer can tell it came from twag:
+//!
+//! - `X-Twag-Timestamp`: Unix seconds when the attempt was sent.
+//! - `X-Twag-Signature`: `sha256=` and the lowercase hex HMAC-SHA256, keyed with
+//!   `TWAG_WEBHOOK_SECRET`, of the timestamp, a `.`, and the raw request body.
+//! - `X-Twag-Event` and `X-Twag-Delivery`: the event name and the delivery's id, which stays the
+//!   same across retries and redeliveries.
+//!
+//! Consumers should recompute the signature over the body exactly as received, compare it in
+//! constant time, and refuse timestamps more than a few minutes from their own clock, so that a
+//! captured request can't be replayed later.
+
+use std::time::Duration;
+
+use reqwest::{redirect, StatusCode};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::outbox::backoff;
+use crate::pool::ScalingPool;
+use crate::security;
+
+pub const EVENT_TAP: &str = "tap";
+pub const MAX_ATTEMPTS: i32 = 8;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookConfig {
+   pub url: String,
+   pub secret: String,
+}
+
+impl WebhookConfig {
+   /// Disabled unless `TWAG_WEBHOOK_URL` is set; deliveries are always signed, so it also needs
+   /// `TWAG_WEBHOOK_SECRET`.
+   pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+      let var = |name: &str| var(name).filter(|s| !s.is_empty());
+      let Some(raw) = var("TWAG_WEBHOOK_URL") else {
+         return Ok(None);
+      };
+      let url = url::Url::parse(&raw).map_err(|e| format!("Invalid TWAG_WEBHOOK_URL: {}", e))?;
+      if !matches!(url.scheme(), "http" | "https") {
+         return Err(format!(
+            "TWAG_WEBHOOK_URL must be http:// or https://, not {}://",
+            url.scheme()
+         ));
+      }
+      let secret = var("TWAG_WEBHOOK_SECRET").ok_or("TWAG_WEBHOOK_URL is set without TWAG_WEBHOOK_SECRET")?;
+      Ok(Some(WebhookConfig {
+         url: url.to_string(),
+         secret,
+      }))
+   }
+}
+
+/// The `X-Twag-Signature` value for a body sent at `timestamp`.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+   let timestamp = timestamp.to_string();
+   let hex = security::hmac_hex(secret.as_bytes(), &[timestamp.as_bytes(), b".", body.as_bytes()]);
+   format!("sha256={}", hex)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+   pub id: i64,
+   pub event: String,
+   pub payload: String,
+}
+
+/// How one attempt at a delivery went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attempt {
+   Delivered(u16),
+   /// Worth trying again: a 5xx, 408, 429, or no response at all.
+   Retry {
+      status: Option<u16>,
+      error: String,
+   },
+   /// The consumer refused it; sending it again won't help.
+   Rejected {
+      status: u16,
+      error: String,
+   },
+}
+
+impl Attempt {
+   fn status(&self) -> Option<u16> {
+      match self {
+         Attempt::Delivered(status) | Attempt::Rejected { status, .. } => Some(*status),
+         Attempt::Retry { status, .. } => *status,
+      }
+   }
+
+   fn error(&self) -> Option<&str> {
+      match self {
+         Attempt::Delivered(_) => None,
+         Attempt::Retry { error, .. } | Attempt::Rejected { error, .. } => Some(error),
+      }
+   }
+}
+
+fn classify(status: StatusCode) -> Attempt {
+   let error = || format!("Endpoint answered {}", status);
+   match status {
+      s if s.is_success() => Attempt::Delivered(s.as_u16()),
+      s if s.is_server_error() || s == StatusCode::REQUEST_TIMEOUT || s == StatusCode::TOO_MANY_REQUESTS => {
+         Attempt::Retry {
+            status: Some(s.as_u16()),
+            error: error(),
+         }
+      }
+      // Other 4xx, and redirects, which aren't followed
+      s => Attempt::Rejected {
+         status: s.as_u16(),
+         error: error(),
+      },
+   }
+}
+
+/// What becomes of a delivery after its `attempts`th attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Next {
+   Delivered,
+   RetryIn(Duration),
+   Failed,
+}
+
+pub fn next(attempts: i32, attempt: &Attempt) -> Next {
+   match attempt {
+      Attempt::Delivered(_) => Next::Delivered,
+      Attempt::Rejected { .. } => Next::Failed,
+      Attempt::Retry { .. } if attempts >= MAX_ATTEMPTS => Next::Failed,
+      Attempt::Retry { .. } => Next::RetryIn(backoff(attempts)),
+   }
+}
+
+pub fn client() -> reqwest::Client {
+   reqwest::Client::builder()
+      .redirect(redirect::Policy::none())
+      .timeout(TIMEOUT)
+      .build()
+      .expect("Failed to build the webhook HTTP client")
+}
+
+/// Sends one signed attempt at a delivery.
+pub async fn attempt(client: &reqwest::Client, config: &WebhookConfig, delivery: &Delivery, timestamp: i64) -> Attempt {
+   let response = client
+      .post(&config.url)
+      .header(reqwest::header::CONTENT_TYPE, "application/json")
+      .header("X-Twag-Event", &delivery.event)
+      .header("X-Twag-Delivery", delivery.id.to_string())
+      .header("X-Twag-Timestamp", timestamp.to_string())
+      .header(
+         "X-Twag-Signature",
+         signature(&config.secret, timestamp, &delivery.payload),
+      )
+      .body(delivery.payload.clone())
+      .send()
+      .await;
+   match response {
+      Ok(response) => classify(response.status()),
+      Err(e) => Attempt::Retry {
+         status: None,
+         error: e.to_string(),
+      },
+   }
+}
+
+pub async fn enqueue(
+   pool: &ScalingPool,
+   config: &WebhookConfig,
+   event: &str,
+   payload: &impl Serialize,
+) -> Result<(), sqlx::Error> {
+   let payload = serde_json::to_string(payload).expect("Webhook payloads serialize");
+   sqlx::query!(
+      "INSERT INTO twag_webhook_deliveries (webhook_url, event, payload) VALUES ($1, $2, $3::text::jsonb)",
+      config.url,
+      event,
+      payload,
+   )
+   .execute(&pool.get())
+   .await?;
+   Ok(())
+}
+
+/// Makes a delivery due again, however it last went, with a fresh set of attempts. Returns
+/// whether it exists.
+pub async fn redeliver(pool: &ScalingPool, id: i64) -> Result<bool, sqlx::Error> {
+   let updated = sqlx::query!(
+      r#"UPDATE twag_webhook_deliveries
+         SET attempts = 0, next_attempt_at = current_timestamp, delivered_at = NULL, failed_at = NULL
+         WHERE id = $1"#,
+      id
+   )
+   .execute(&pool.get())
+   .await?
+   .rows_affected();
+   Ok(updated > 0)
+}
+
+/// Deliveries queued for an endpoint that's since been reconfigured are left alone, and stay
+/// visible on the admin page.
+async fn dispatch_one(
+   pool: &ScalingPool,
+   client: &reqwest::Client,
+   config: &WebhookConfig,
+) -> Result<bool, sqlx::Error> {
+   let mut tx = pool.get().begin().await?;
+
+   let Some(row) = sqlx::query!(
+      r#"SELECT id, event, payload::text AS "payload!", attempts FROM twag_webhook_deliveries
+         WHERE webhook_url = $1 AND delivered_at IS NULL AND failed_at IS NULL
+            AND next_attempt_at <= current_timestamp
+         ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED"#,
+      config.url,
+   )
+   .fetch_optional(&mut *tx)
+   .await?
+   else {
+      return Ok(false);
+   };
+
+   let delivery = Delivery {
+      id: row.id,
+      event: row.event,
+      payload: row.payload,
+   };
+   let result = attempt(client, config, &delivery, chrono::Utc::now().timestamp()).await;
+   let attempts = row.attempts + 1;
+   let next = next(attempts, &result);
+   sqlx::query!(
+      r#"UPDATE twag_webhook_deliveries SET attempts = $2, last_status = $3, last_error = $4,
+            delivered_at = CASE WHEN $5 THEN current_timestamp END,
+            failed_at = CASE WHEN $6 THEN current_timestamp END,
+            next_attempt_at = current_timestamp + make_interval(secs => $7)
+         WHERE id = $1"#,
+      delivery.id,
+      attempts,
+      result.status().map(|s| s as i16),
+      result.error(),
+      next == Next::Delivered,
+      next == Next::Failed,
+      match next {
+         Next::RetryIn(delay) => delay.as_secs_f64(),
+         Next::Delivered | Next::Failed => 0.0,
+      },
+   )
+   .execute(&mut *tx)
+   .await?;
+   tx.commit().await?;
+
+   match next {
+      Next::Delivered => info!(delivery_id = delivery.id, event = %delivery.event, "Webhook delivered"),
+      next => warn!(
+         delivery_id = delivery.id,
+         attempts,
+         failed = next == Next::Failed,
+         "Webhook delivery failed: {}",
+         result.error().unwrap_or_default()
+      ),
+   }
+   Ok(true)
+}
+
+pub(crate) fn spawn_dispatcher(pool: ScalingPool, config: WebhookConfig) {
+   tokio::spawn(async move {
+      let client = client();
+      let mut interval = tokio::time::interval(POLL_INTERVAL);
+      loop {
+         interval.tick().await;
+         loop {
+            match dispatch_one(&pool, &client, &config).await {
+               Ok(true) => continue,
+               Ok(false) => break,
+               Err(e) => {
+                  warn!("Webhook dispatch failed: {:?}", e);
+                  break;
+               }
+            }
+         }
+      }
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use std::collections::VecDeque;
+   use std::sync::{Arc, Mutex};
+
+   use axum::http::HeaderMap;
+
+   const SECRET: &str = "whsec_test";
+   const NOW: i64 = 1_800_000_000;
+
+   type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+   /// A local endpoint that answers with each of `statuses` in turn, recording what it was sent.
+   async fn endpoint(statuses: &[u16]) -> (WebhookConfig, Received) {
+      let statuses = Arc::new(Mutex::new(statuses.iter().copied().collect::<VecDeque<_>>()));
+      let received = Received::default();
+      let app = axum::Router::new().route(
+         "/hook",
+         axum::routing::post({
+            let received = received.clone();
+            move |headers: HeaderMap, body: String| async move {
+               received.lock().unwrap().push((headers, body));
+               let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+               axum::http::StatusCode::from_u16(status).unwrap()
+            }
+         }),
+      );
+      let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+      let addr = listener.local_addr().unwrap();
+      tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+      let config = WebhookConfig {
+         url: format!("http://{}/hook", addr),
+         secret: SECRET.to_string(),
+      };
+      (config, received)
+   }
+
+   fn delivery() -> Delivery {
+      Delivery {
+         id: 42,
+         event: EVENT_TAP.to_string(),
+         payload: r#"{"tag_id":"055B88A23C1250"}"#.to_string(),
+      }
+   }
+
+   fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str { headers.get(name).unwrap().to_str().unwrap() }
+
+   #[test]
+   fn test_signature_matches_documented_algorithm() {
+      // HMAC-SHA256 of `1800000000.{"tag_id":"055B88A23C1250"}`, as computed independently
+      assert_eq!(
+         signature(SECRET, NOW, &delivery().payload),
+         "sha256=388bbd5c4e5e5fca3cb4a4aff09c0406c037d914248421e0344b82f291d0cac0"
+      );
+      assert_ne!(
+         signature(SECRET, NOW + 1, &delivery().payload),
+         signature(SECRET, NOW, &delivery().payload)
+      );
+   }
+
+   #[tokio::test]
+   async fn test_requests_are_signed() {
+      let (config, received) = endpoint(&[204]).await;
+      assert_eq!(
+         attempt(&client(), &config, &delivery(), NOW).await,
+         Attempt::Delivered(204)
+      );
+
+      let received = received.lock().unwrap();
+      let (headers, body) = &received[0];
+      assert_eq!(body, &delivery().payload);
+      assert_eq!(header(headers, "x-twag-event"), "tap");
+      assert_eq!(header(headers, "x-twag-delivery"), "42");
+      assert_eq!(header(headers, "x-twag-timestamp"), NOW.to_string());
+      let timestamp: i64 = header(headers, "x-twag-timestamp").parse().unwrap();
+      assert_eq!(header(headers, "x-twag-signature"), signature(SECRET, timestamp, body));
+   }
+
+   #[tokio::test]
+   async fn test_server_errors_are_retried() {
+      let (config, received) = endpoint(&[500, 500, 200]).await;
+      let client = client();
+      let mut attempts = 0;
+      let outcome = loop {
+         let result = attempt(&client, &config, &delivery(), NOW + attempts as i64).await;
+         attempts += 1;
+         match next(attempts, &result) {
+            Next::RetryIn(delay) => assert_eq!(delay, backoff(attempts)),
+            done => break done,
+         }
+      };
+      assert_eq!(outcome, Next::Delivered);
+      assert_eq!(attempts, 3);
+      let received = received.lock().unwrap();
+      assert!(received
+         .iter()
+         .all(|(headers, _)| header(headers, "x-twag-delivery") == "42"));
+   }
+
+   #[tokio::test]
+   async fn test_client_errors_are_not_retried() {
+      let (config, received) = endpoint(&[410]).await;
+      let result = attempt(&client(), &config, &delivery(), NOW).await;
+      assert_eq!(
+         result,
+         Attempt::Rejected {
+            status: 410,
+            error: "Endpoint answered 410 Gone".to_string(),
+         }
+      );
+      assert_eq!(next(1, &result), Next::Failed);
+      assert_eq!(received.lock().unwrap().len(), 1);
+   }
+
+   #[test]
+   fn test_retries_end_in_failure() {
+      let retry = classify(StatusCode::TOO_MANY_REQUESTS);
+      assert!(matches!(retry, Attempt::Retry { status: Some(429), .. }));
+      assert_eq!(next(MAX_ATTEMPTS - 1, &retry), Next::RetryIn(backoff(MAX_ATTEMPTS - 1)));
+      assert_eq!(next(MAX_ATTEMPTS, &retry), Next::Failed);
+      assert!(matches!(
+         classify(StatusCode::FOUND),
+         Attempt::Rejected { status: 302, .. }
+      ));
+   }
+
+   #[test]
+   fn test_config() {
+      let vars = |pairs: &'static [(&'static str, &'static str)]| {
+         move |name: &str| pairs.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+      };
+      assert_eq!(WebhookConfig::from_vars(vars(&[])), Ok(None));
+      assert!(WebhookConfig::from_vars(vars(&[("TWAG_WEBHOOK_URL", "https://hooks.example.com/twag")])).is_err());
+      assert!(WebhookConfig::from_vars(vars(&[
+         ("TWAG_WEBHOOK_URL", "ftp://hooks.example.com/"),
+         ("TWAG_WEBHOOK_SECRET", SECRET),
+      ]))
+      .is_err());
+      assert_eq!(
+         WebhookConfig::from_vars(vars(&[
+            ("TWAG_WEBHOOK_URL", "https://hooks.example.com/twag"),
+            ("TWAG_WEBHOOK_SECRET", SECRET),
+         ])),
+         Ok(Some(WebhookConfig {
+            url: "https://hooks.example.com/twag".to_string(),
+            secret: SECRET.to_string(),
+         }))
+      );
+   }
+}